@@ -0,0 +1,112 @@
+use std::{error::Error, sync::Arc};
+
+use clap_blocks::object_store::{make_object_store, ObjectStoreConfig};
+use influxdb3_wal::{
+    encryption::{EncryptionKey, KeyProvider, StaticKeyProvider},
+    inspect::{WalFileSummary, WalInspector},
+    WalFileSequenceNumber,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct Config {
+    /// object store options
+    #[clap(flatten)]
+    object_store_config: ObjectStoreConfig,
+
+    /// The host identifier prefix of the server whose WAL files are inspected
+    #[clap(long = "host-id", env = "INFLUXDB3_HOST_IDENTIFIER_PREFIX")]
+    host_identifier_prefix: String,
+
+    /// The hex encoded key to decrypt the WAL files with, if they are encrypted
+    #[clap(long = "encryption-key", env = "INFLUXDB3_ENCRYPTION_KEY")]
+    encryption_key: Option<String>,
+
+    /// Only inspect the WAL file with this sequence number
+    #[clap(long = "file")]
+    wal_file_number: Option<u64>,
+
+    /// Print the full decoded contents of the WAL files, as JSON, rather than a summary of them
+    #[clap(long = "dump", default_value_t = false)]
+    dump: bool,
+
+    /// Print the summaries of the WAL files as JSON
+    #[clap(long = "json", default_value_t = false, conflicts_with = "dump")]
+    json: bool,
+}
+
+pub(super) async fn command(config: Config) -> Result<(), Box<dyn Error>> {
+    let object_store = make_object_store(&config.object_store_config)?;
+    let key_provider = config
+        .encryption_key
+        .map(|key| -> Result<Arc<dyn KeyProvider>, Box<dyn Error>> {
+            Ok(Arc::new(StaticKeyProvider::new(EncryptionKey::from_hex(
+                &key,
+            )?)))
+        })
+        .transpose()?;
+    let inspector = WalInspector::new(object_store, config.host_identifier_prefix, key_provider);
+
+    let paths = match config.wal_file_number {
+        Some(wal_file_number) => vec![inspector.path(WalFileSequenceNumber::new(wal_file_number))],
+        None => inspector.list().await?,
+    };
+
+    for path in paths {
+        if config.dump {
+            match inspector.load(&path).await {
+                Ok((contents, corrupt_entries)) => {
+                    for corrupt_entry in corrupt_entries {
+                        eprintln!(
+                            "{path}: skipped corrupt entry at offset {}: {}",
+                            corrupt_entry.offset, corrupt_entry.error
+                        );
+                    }
+                    println!("{}", serde_json::to_string_pretty(&contents)?);
+                }
+                Err(e) => eprintln!("{path}: {e}"),
+            }
+            continue;
+        }
+
+        match inspector.summarize(&path).await {
+            Ok(summary) if config.json => println!("{}", serde_json::to_string(&summary)?),
+            Ok(summary) => print_summary(&summary),
+            Err(e) => eprintln!("{path}: {e}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn print_summary(summary: &WalFileSummary) {
+    println!(
+        "{path}: {size_bytes} bytes, {write_ops} write ops, {catalog_ops} catalog ops, \
+         {rows} rows, time range {min}..={max}",
+        path = summary.path,
+        size_bytes = summary.size_bytes,
+        write_ops = summary.write_ops,
+        catalog_ops = summary.catalog_ops,
+        rows = summary.rows,
+        min = summary.min_timestamp_ns,
+        max = summary.max_timestamp_ns,
+    );
+    if let Some(snapshot) = &summary.snapshot {
+        println!("  snapshot: {snapshot:?}");
+    }
+    for (database_name, tables) in &summary.tables {
+        for (table_id, table) in tables {
+            println!(
+                "  {database_name} table {table_id}: {rows} rows, time range {min}..={max}",
+                rows = table.rows,
+                min = table.min_time_ns,
+                max = table.max_time_ns,
+            );
+        }
+    }
+    for corrupt_entry in &summary.corrupt_entries {
+        println!(
+            "  corrupt entry at offset {}: {}",
+            corrupt_entry.offset, corrupt_entry.error
+        );
+    }
+}
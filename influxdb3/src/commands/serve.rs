@@ -8,6 +8,7 @@ use clap_blocks::{
     tokio::TokioDatafusionConfig,
 };
 use datafusion_util::config::register_iox_object_store;
+use influxdb3_catalog::catalog::CatalogLimits;
 use influxdb3_process::{
     build_malloc_conf, setup_metric_registry, INFLUXDB3_GIT_HASH, INFLUXDB3_VERSION, PROCESS_UUID,
 };
@@ -18,13 +19,31 @@ use influxdb3_server::{
     serve, CommonServerState,
 };
 use influxdb3_telemetry::store::TelemetryStore;
-use influxdb3_wal::{Gen1Duration, WalConfig};
+use influxdb3_wal::{
+    encryption::{EncryptionKey, StaticKeyProvider},
+    object_store::ReplayProgress,
+    recovery::RecoveryTarget,
+    Gen1Duration, SnapshotTriggers, WalAckLevel, WalCompression, WalConfig, WalFileSequenceNumber,
+    WalRetention,
+};
 use influxdb3_write::{
     last_cache::LastCacheProvider,
-    parquet_cache::create_cached_obj_store_and_oracle,
-    persister::Persister,
-    write_buffer::{persisted_files::PersistedFiles, WriteBufferImpl},
-    WriteBuffer,
+    parquet_cache::{create_cached_obj_store_and_oracle, CacheAdmissionPolicy, CacheTable},
+    persister::{
+        ParquetCompression, ParquetStatisticsLevel, Persister, PersisterConfig,
+        DEFAULT_CATALOG_CHECKPOINT_INTERVAL, DEFAULT_PARQUET_ZSTD_LEVEL,
+        DEFAULT_PERSIST_CONCURRENCY, ROW_GROUP_WRITE_SIZE,
+    },
+    write_buffer::{
+        background_compaction, background_deleted_table_removal, background_downsampling,
+        background_other_writers_refresh, background_parquet_garbage_collection,
+        background_persisted_files_index, background_retention_enforcement,
+        background_wal_garbage_collection,
+        compactor::{CompactionConfig, DEFAULT_COMPACTION_MIN_FILES},
+        persisted_files::PersistedFiles,
+        WriteBufferImpl, WriteBufferImplArgs,
+    },
+    LastCacheReplay, WriteBuffer,
 };
 use iox_query::exec::{DedicatedExecutor, Executor, ExecutorConfig};
 use iox_time::SystemProvider;
@@ -32,10 +51,16 @@ use object_store::ObjectStore;
 use observability_deps::tracing::*;
 use panic_logging::SendPanicsToTracing;
 use parquet_file::storage::{ParquetStorage, StorageId};
-use std::{collections::HashMap, path::Path, str::FromStr};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 use std::{num::NonZeroUsize, sync::Arc};
 use thiserror::Error;
 use tokio::net::TcpListener;
+use tokio::sync::watch;
 use tokio_util::sync::CancellationToken;
 use trace_exporters::TracingConfig;
 use trace_http::ctx::TraceHeaderParser;
@@ -79,6 +104,9 @@ pub enum Error {
 
     #[error("failed to initialize last cache: {0}")]
     InitializeLastCache(#[source] influxdb3_write::last_cache::Error),
+
+    #[error("invalid encryption key: {0}")]
+    InvalidEncryptionKey(#[from] influxdb3_wal::encryption::Error),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -197,6 +225,370 @@ pub struct Config {
     )]
     pub wal_max_write_buffer_size: usize,
 
+    /// Snapshot the WAL as soon as the oldest write that has not been snapshot was written to
+    /// the WAL this long ago, rather than waiting for `--wal-snapshot-size` WAL files to be
+    /// written, so that the writes of infrequent writers are persisted promptly. By default,
+    /// there is no limit.
+    ///
+    /// Enter as a human-readable time, e.g., "10m", "1h", etc.
+    #[clap(
+        long = "wal-snapshot-max-age",
+        env = "INFLUXDB3_WAL_SNAPSHOT_MAX_AGE",
+        action
+    )]
+    pub wal_snapshot_max_age: Option<humantime::Duration>,
+
+    /// Snapshot the WAL as soon as the writes that have not been snapshot reach this size, in
+    /// bytes, rather than waiting for `--wal-snapshot-size` WAL files to be written, so that heavy
+    /// writers do not buffer too much. By default, there is no limit.
+    #[clap(
+        long = "wal-snapshot-max-bytes",
+        env = "INFLUXDB3_WAL_SNAPSHOT_MAX_BYTES",
+        action
+    )]
+    pub wal_snapshot_max_bytes: Option<usize>,
+
+    /// How the contents of WAL files are compressed before they are written to object storage:
+    /// one of `none`, `zstd`, `lz4`, or `snappy`. WAL files are replayed on startup regardless
+    /// of how they were compressed.
+    #[clap(
+        long = "wal-compression",
+        env = "INFLUXDB3_WAL_COMPRESSION",
+        default_value = "none",
+        action
+    )]
+    pub wal_compression: WalCompression,
+
+    /// The approximate maximum size, in bytes, of the writes in a WAL file. Once this many bytes
+    /// of writes are buffered, they are flushed without waiting for the flush interval, and larger
+    /// bursts of writes are split across multiple WAL files. By default, there is no limit.
+    #[clap(
+        long = "wal-max-file-size-bytes",
+        env = "INFLUXDB3_WAL_MAX_FILE_SIZE_BYTES",
+        action
+    )]
+    pub wal_max_file_size_bytes: Option<usize>,
+
+    /// The number of the most recently snapshot WAL files to keep, rather than deleting them once
+    /// they have been snapshot, e.g., for point-in-time recovery. Retained files are not replayed.
+    #[clap(
+        long = "wal-retention-files",
+        env = "INFLUXDB3_WAL_RETENTION_FILES",
+        default_value = "0",
+        action
+    )]
+    pub wal_retention_files: usize,
+
+    /// How long to keep WAL files after they have been snapshot, e.g., for point-in-time recovery.
+    /// Files are kept while they are within either this period or `--wal-retention-files`.
+    #[clap(
+        long = "wal-retention-period",
+        env = "INFLUXDB3_WAL_RETENTION_PERIOD",
+        default_value = "0s",
+        action
+    )]
+    pub wal_retention_period: humantime::Duration,
+
+    /// Recover the server to how it was after the WAL file with this number was written, on
+    /// startup, discarding the writes after it. The snapshots and WAL files after it are moved
+    /// aside in object storage, rather than being deleted. Remove this once the server has been
+    /// recovered, or the writes since the recovery are discarded when the server is restarted.
+    #[clap(
+        long = "recover-to-wal-file",
+        conflicts_with = "recover_to_time",
+        action
+    )]
+    pub recover_to_wal_file: Option<u64>,
+
+    /// Recover the server to how it was at this time, e.g., `2024-10-01T12:00:00Z`, on startup,
+    /// discarding the writes in the WAL files written after it. Remove this once the server has
+    /// been recovered.
+    #[clap(long = "recover-to-time", action)]
+    pub recover_to_time: Option<humantime::Timestamp>,
+
+    /// A directory on local disk to write WAL files to before they are uploaded to object
+    /// storage. Unless `--wal-ack-level` is set, writes are acknowledged once they are synced to
+    /// the local disk, rather than once they are in object storage. Any files left in the
+    /// directory on startup are uploaded before the WAL is replayed. By default, WAL files are
+    /// written directly to object storage.
+    #[clap(long = "wal-local-dir", env = "INFLUXDB3_WAL_LOCAL_DIR", action)]
+    pub wal_local_dir: Option<PathBuf>,
+
+    /// When writes are acknowledged: `object-store-put`, once the WAL file with them is in object
+    /// storage; `local-durable`, once it is synced to the `--wal-local-dir`; or `buffered`, as
+    /// soon as they are buffered, before the WAL file is written. Defaults to `local-durable` if
+    /// `--wal-local-dir` is set, and `object-store-put` otherwise.
+    #[clap(long = "wal-ack-level", env = "INFLUXDB3_WAL_ACK_LEVEL", action)]
+    pub wal_ack_level: Option<WalAckLevel>,
+
+    /// Queue up to this many bytes of WAL files in memory when they cannot be written to object
+    /// storage, and upload them once it is available again, rather than failing the writes in
+    /// them. Those writes are acknowledged as `buffered`, as they are lost if the server stops
+    /// before the files are uploaded. By default, writes fail when their WAL file cannot be
+    /// written.
+    #[clap(
+        long = "wal-handoff-max-bytes",
+        env = "INFLUXDB3_WAL_HANDOFF_MAX_BYTES",
+        action
+    )]
+    pub wal_handoff_max_bytes: Option<usize>,
+
+    /// The interval on which to check the WAL for files that were snapshot, but left in the WAL,
+    /// e.g., by a snapshot that was interrupted, and remove them, so that they are not replayed
+    /// again. Set to "0s" to disable the check.
+    #[clap(
+        long = "wal-gc-interval",
+        env = "INFLUXDB3_WAL_GC_INTERVAL",
+        default_value = "10m",
+        action
+    )]
+    pub wal_gc_interval: humantime::Duration,
+
+    /// The interval on which to check object storage for parquet files that are not referenced
+    /// by any snapshot, e.g., those of a snapshot that failed, or that were removed by retention,
+    /// and remove them. Set to "0s" to disable the check.
+    #[clap(
+        long = "parquet-gc-interval",
+        env = "INFLUXDB3_PARQUET_GC_INTERVAL",
+        default_value = "1h",
+        action
+    )]
+    pub parquet_gc_interval: humantime::Duration,
+
+    /// How long ago an unreferenced parquet file must have been last modified to be removed by
+    /// the `--parquet-gc-interval` check, so that the files of a snapshot that is still being
+    /// persisted are not removed.
+    #[clap(
+        long = "parquet-gc-min-age",
+        env = "INFLUXDB3_PARQUET_GC_MIN_AGE",
+        default_value = "1h",
+        action
+    )]
+    pub parquet_gc_min_age: humantime::Duration,
+
+    /// Only log the parquet files that are found by the `--parquet-gc-interval` check, rather
+    /// than removing them.
+    #[clap(
+        long = "parquet-gc-dry-run",
+        env = "INFLUXDB3_PARQUET_GC_DRY_RUN",
+        default_value_t = false,
+        action
+    )]
+    pub parquet_gc_dry_run: bool,
+
+    /// The interval on which to compact the persisted parquet files of each table into larger,
+    /// sorted and deduplicated, gen2 files. Set to "0s" to disable compaction.
+    #[clap(
+        long = "compaction-interval",
+        env = "INFLUXDB3_COMPACTION_INTERVAL",
+        default_value = "10m",
+        action
+    )]
+    pub compaction_interval: humantime::Duration,
+
+    /// The duration of the windows of time that the files of a table are compacted in, expressed
+    /// as a human-readable time, e.g., "1h", "1d". It should be a multiple of the gen1 duration.
+    #[clap(
+        long = "gen2-duration",
+        env = "INFLUXDB3_GEN2_DURATION",
+        default_value = "1h",
+        action
+    )]
+    pub gen2_duration: humantime::Duration,
+
+    /// The number of files that a window must have before they are compacted.
+    #[clap(
+        long = "compaction-min-files",
+        env = "INFLUXDB3_COMPACTION_MIN_FILES",
+        default_value_t = DEFAULT_COMPACTION_MIN_FILES,
+        action
+    )]
+    pub compaction_min_files: usize,
+
+    /// The interval at which the downsampling tasks of each database are run over the data that
+    /// was persisted since they last ran, expressed as a human-readable time, e.g., "1m", "10m".
+    /// Set to "0s" to disable downsampling.
+    #[clap(
+        long = "downsampling-interval",
+        env = "INFLUXDB3_DOWNSAMPLING_INTERVAL",
+        default_value = "1m",
+        action
+    )]
+    pub downsampling_interval: humantime::Duration,
+
+    /// The interval at which an index of all of the persisted files is written to object storage,
+    /// which they are loaded from on startup, along with the snapshots taken since, rather than
+    /// from every snapshot, expressed as a human-readable time, e.g., "30m", "1h". Set to "0s" to
+    /// disable the index.
+    #[clap(
+        long = "persisted-files-index-interval",
+        env = "INFLUXDB3_PERSISTED_FILES_INDEX_INTERVAL",
+        default_value = "1h",
+        action
+    )]
+    pub persisted_files_index_interval: humantime::Duration,
+
+    /// The host identifier prefixes of other writers, comma separated, whose persisted parquet
+    /// files are queried along with those of this host, so that it serves a unified view of the
+    /// data written to all of them. Only the writes that they have persisted are queried, and only
+    /// in the tables that this host also has.
+    #[clap(
+        long = "other-writers",
+        env = "INFLUXDB3_OTHER_WRITERS",
+        value_delimiter = ',',
+        action
+    )]
+    pub other_writers: Vec<String>,
+
+    /// The interval at which the files that the `--other-writers` have persisted since they were
+    /// last loaded are loaded, expressed as a human-readable time, e.g., "10s", "1m".
+    #[clap(
+        long = "other-writers-refresh-interval",
+        env = "INFLUXDB3_OTHER_WRITERS_REFRESH_INTERVAL",
+        default_value = "10s",
+        action
+    )]
+    pub other_writers_refresh_interval: humantime::Duration,
+
+    /// If set, how long the lease that this server holds on its host identifier prefix lasts,
+    /// expressed as a human-readable time, e.g., "30s", "1m". The lease is renewed every third of
+    /// this, and a server that starts on a prefix whose lease another server holds waits for it
+    /// to expire, so that only one server writes to the prefix at a time. Writes, and writes to
+    /// object storage, are stopped once the lease is lost. Defaults to "0s", which holds no lease.
+    ///
+    /// The lease needs an object store that supports conditional creates and updates, which the
+    /// local file system does not, nor does S3 unless it is configured to. The server fails to
+    /// start if it cannot hold the lease on the object store.
+    #[clap(
+        long = "lease-duration",
+        env = "INFLUXDB3_LEASE_DURATION",
+        default_value = "0s",
+        action
+    )]
+    pub lease_duration: humantime::Duration,
+
+    /// Only log the WAL files that are found by the `--wal-gc-interval` check, rather than
+    /// removing them.
+    #[clap(
+        long = "wal-gc-dry-run",
+        env = "INFLUXDB3_WAL_GC_DRY_RUN",
+        default_value_t = false,
+        action
+    )]
+    pub wal_gc_dry_run: bool,
+
+    /// A 256-bit key, hex encoded, to encrypt WAL, catalog, and snapshot files with before they
+    /// are written. Files that were written unencrypted can still be read, but once files are
+    /// encrypted, the server cannot start without the key that they were encrypted with.
+    #[clap(long = "encryption-key", env = "INFLUXDB3_ENCRYPTION_KEY", action)]
+    pub encryption_key: Option<String>,
+
+    /// Previous encryption keys, hex encoded and comma separated, that are only used to decrypt
+    /// files that were encrypted with them, e.g., while the `--encryption-key` is being rotated.
+    #[clap(
+        long = "previous-encryption-keys",
+        env = "INFLUXDB3_PREVIOUS_ENCRYPTION_KEYS",
+        requires = "encryption_key",
+        value_delimiter = ',',
+        action
+    )]
+    pub previous_encryption_keys: Vec<String>,
+
+    /// The number of times that changes to the catalog are persisted as deltas, which happens on
+    /// snapshots that follow a change, before the catalog is persisted in full and the deltas are
+    /// compacted into it. Lower values make loading the catalog on startup faster, at the cost of
+    /// persisting large catalogs more often.
+    #[clap(
+        long = "catalog-checkpoint-interval",
+        env = "INFLUXDB3_CATALOG_CHECKPOINT_INTERVAL",
+        default_value_t = DEFAULT_CATALOG_CHECKPOINT_INTERVAL,
+        action
+    )]
+    pub catalog_checkpoint_interval: usize,
+
+    /// The number of parquet files that are persisted at the same time when the buffer is
+    /// snapshot. Higher values make snapshots of many tables finish sooner, at the cost of more
+    /// memory and object storage requests at once.
+    #[clap(
+        long = "persist-concurrency",
+        env = "INFLUXDB3_PERSIST_CONCURRENCY",
+        default_value_t = DEFAULT_PERSIST_CONCURRENCY,
+        action
+    )]
+    pub persist_concurrency: usize,
+
+    /// The number of versions of the catalog persisted in full that are kept in object storage,
+    /// along with the deltas persisted after them, so that the catalog can be loaded as it was at
+    /// an earlier point. All versions are kept if this is not set.
+    #[clap(
+        long = "retained-catalog-versions",
+        env = "INFLUXDB3_RETAINED_CATALOG_VERSIONS",
+        action
+    )]
+    pub retained_catalog_versions: Option<usize>,
+
+    /// The maximum number of rows in each row group of the parquet files that are persisted.
+    /// Smaller row groups can be pruned more finely when they are queried, at the cost of larger
+    /// files.
+    #[clap(
+        long = "parquet-row-group-size",
+        env = "INFLUXDB3_PARQUET_ROW_GROUP_SIZE",
+        default_value_t = ROW_GROUP_WRITE_SIZE,
+        action
+    )]
+    pub parquet_row_group_size: usize,
+
+    /// The best-effort maximum size, in bytes, of each data page in the parquet files that are
+    /// persisted.
+    #[clap(
+        long = "parquet-data-page-size-bytes",
+        env = "INFLUXDB3_PARQUET_DATA_PAGE_SIZE_BYTES",
+        default_value = "1048576",
+        action
+    )]
+    pub parquet_data_page_size_bytes: usize,
+
+    /// How the parquet files that are persisted are compressed: one of `none`, `snappy`, `lz4`,
+    /// or `zstd`.
+    #[clap(
+        long = "parquet-compression",
+        env = "INFLUXDB3_PARQUET_COMPRESSION",
+        default_value = "zstd",
+        action
+    )]
+    pub parquet_compression: ParquetCompression,
+
+    /// The level, from 1 to 22, that the parquet files that are persisted are compressed at when
+    /// they are compressed with zstd. Higher levels give smaller files, but take longer to write.
+    #[clap(
+        long = "parquet-zstd-level",
+        env = "INFLUXDB3_PARQUET_ZSTD_LEVEL",
+        default_value_t = DEFAULT_PARQUET_ZSTD_LEVEL,
+        value_parser = clap::value_parser!(i32).range(1..=22),
+        action
+    )]
+    pub parquet_zstd_level: i32,
+
+    /// Disable dictionary encoding of the columns in the parquet files that are persisted.
+    #[clap(
+        long = "parquet-disable-dictionary",
+        env = "INFLUXDB3_PARQUET_DISABLE_DICTIONARY",
+        default_value_t = false,
+        action
+    )]
+    pub parquet_disable_dictionary: bool,
+
+    /// The statistics that are written to the parquet files that are persisted, which are used to
+    /// prune the data that is read when they are queried: one of `none`, `chunk`, for each column
+    /// in each row group, or `page`, for each data page as well.
+    #[clap(
+        long = "parquet-statistics-level",
+        env = "INFLUXDB3_PARQUET_STATISTICS_LEVEL",
+        default_value = "page",
+        action
+    )]
+    pub parquet_statistics_level: ParquetStatisticsLevel,
+
     // TODO - tune this default:
     /// The size of the query log. Up to this many queries will remain in the log before
     /// old queries are evicted to make room for new ones.
@@ -218,6 +610,17 @@ pub struct Config {
     )]
     pub buffer_mem_limit_mb: usize,
 
+    /// Hold back writes that arrive while the buffered data is over `--buffer-mem-limit-mb`
+    /// until the forced snapshot brings it under the limit, and reject them if that takes longer
+    /// than this, expressed as a human-readable time, e.g., "10s". By default, writes are not
+    /// held back, and the limit only forces a snapshot.
+    #[clap(
+        long = "buffer-full-timeout",
+        env = "INFLUXDB3_BUFFER_FULL_TIMEOUT",
+        action
+    )]
+    pub buffer_full_timeout: Option<humantime::Duration>,
+
     /// The host idendifier used as a prefix in all object store file paths. This should be unique
     /// for any hosts that share the same object store configuration, i.e., the same bucket.
     #[clap(long = "host-id", env = "INFLUXDB3_HOST_IDENTIFIER_PREFIX", action)]
@@ -263,6 +666,57 @@ pub struct Config {
     )]
     pub disable_parquet_mem_cache: bool,
 
+    /// The number of the most recent Parquet files to load into the in-memory Parquet cache on
+    /// startup, so that the first queries do not all have to fetch them from object storage.
+    /// Only as many files as fit in the cache are loaded. By default, none are loaded.
+    #[clap(
+        long = "parquet-mem-cache-prefill-files",
+        env = "INFLUXDB3_PARQUET_MEM_CACHE_PREFILL_FILES",
+        default_value_t = 0,
+        action
+    )]
+    pub parquet_mem_cache_prefill_files: usize,
+
+    /// Parquet files larger than this, in megabytes (MB), are not loaded into the in-memory
+    /// Parquet cache. By default, any file that fits in the cache is loaded.
+    #[clap(
+        long = "parquet-mem-cache-max-file-size-mb",
+        env = "INFLUXDB3_PARQUET_MEM_CACHE_MAX_FILE_SIZE_MB",
+        action
+    )]
+    pub parquet_mem_cache_max_file_size_mb: Option<usize>,
+
+    /// Parquet files that only contain data older than this are not loaded into the in-memory
+    /// Parquet cache, e.g., the files of a backfill of historical data.
+    ///
+    /// Enter as a human-readable time, e.g., "1h", "7d", etc.
+    #[clap(
+        long = "parquet-mem-cache-max-file-age",
+        env = "INFLUXDB3_PARQUET_MEM_CACHE_MAX_FILE_AGE",
+        action
+    )]
+    pub parquet_mem_cache_max_file_age: Option<humantime::Duration>,
+
+    /// Only load the Parquet files of these tables into the in-memory Parquet cache. Tables are
+    /// comma separated, and given as `<db>.<table>`, or `<db>.*` for all tables in a database.
+    #[clap(
+        long = "parquet-mem-cache-allow-tables",
+        env = "INFLUXDB3_PARQUET_MEM_CACHE_ALLOW_TABLES",
+        value_delimiter = ',',
+        action
+    )]
+    pub parquet_mem_cache_allow_tables: Vec<CacheTable>,
+
+    /// Never load the Parquet files of these tables into the in-memory Parquet cache. Tables are
+    /// given in the same way as for `--parquet-mem-cache-allow-tables`.
+    #[clap(
+        long = "parquet-mem-cache-deny-tables",
+        env = "INFLUXDB3_PARQUET_MEM_CACHE_DENY_TABLES",
+        value_delimiter = ',',
+        action
+    )]
+    pub parquet_mem_cache_deny_tables: Vec<CacheTable>,
+
     /// telemetry server endpoint
     #[clap(
         long = "telemetry-endpoint",
@@ -281,6 +735,99 @@ pub struct Config {
         action
     )]
     pub last_cache_eviction_interval: humantime::Duration,
+
+    /// The amount of memory, in megabytes (MB), that the values held in the Last-N-Value caches
+    /// may use. When they use more, the values of the least recently written keys are evicted
+    /// on the eviction interval. By default, there is no limit.
+    #[clap(
+        long = "last-cache-mem-budget-mb",
+        env = "INFLUXDB3_LAST_CACHE_MEM_BUDGET_MB",
+        action
+    )]
+    pub last_cache_mem_budget_mb: Option<usize>,
+
+    /// The interval on which to drop data that is outside of the retention period of its
+    /// database, expressed as a human-readable time, e.g., "20s", "1m", "1h".
+    #[clap(
+        long = "retention-check-interval",
+        env = "INFLUXDB3_RETENTION_CHECK_INTERVAL",
+        default_value = "1m",
+        action
+    )]
+    pub retention_check_interval: humantime::Duration,
+
+    /// How long a deleted table can be undeleted for, before its data is removed for good,
+    /// expressed as a human-readable time, e.g., "1h", "7d". Deleted tables are checked for on
+    /// the `--retention-check-interval`.
+    #[clap(
+        long = "table-delete-grace-period",
+        env = "INFLUXDB3_TABLE_DELETE_GRACE_PERIOD",
+        default_value = "24h",
+        action
+    )]
+    pub table_delete_grace_period: humantime::Duration,
+
+    /// The maximum number of databases. Writes, and other changes to the catalog, that would add
+    /// a database beyond this limit are rejected.
+    #[clap(
+        long = "max-databases",
+        env = "INFLUXDB3_MAX_DATABASES",
+        default_value_t = CatalogLimits::default().max_databases,
+        action
+    )]
+    pub max_databases: usize,
+
+    /// The maximum number of tables, across all databases. Writes, and other changes to the
+    /// catalog, that would add a table beyond this limit are rejected.
+    #[clap(
+        long = "max-tables",
+        env = "INFLUXDB3_MAX_TABLES",
+        default_value_t = CatalogLimits::default().max_tables,
+        action
+    )]
+    pub max_tables: usize,
+
+    /// The maximum number of tables in each database. Writes, and other changes to the catalog,
+    /// that would add a table beyond this limit are rejected. By default, only the global limit
+    /// on the number of tables applies.
+    #[clap(
+        long = "max-tables-per-db",
+        env = "INFLUXDB3_MAX_TABLES_PER_DB",
+        action
+    )]
+    pub max_tables_per_db: Option<usize>,
+
+    /// The maximum number of columns, across all tables. Writes, and other changes to the
+    /// catalog, that would add a column beyond this limit are rejected.
+    #[clap(
+        long = "max-columns",
+        env = "INFLUXDB3_MAX_COLUMNS",
+        default_value_t = CatalogLimits::default().max_columns,
+        action
+    )]
+    pub max_columns: usize,
+
+    /// Record the lines that are dropped from partial writes, along with the reason they were
+    /// rejected, in the `_rejected_writes` table of the database they were written to.
+    #[clap(
+        long = "record-rejected-lines",
+        env = "INFLUXDB3_RECORD_REJECTED_LINES",
+        default_value_t = false,
+        action
+    )]
+    pub record_rejected_lines: bool,
+
+    /// How the last caches are populated with the writes that are replayed from the WAL on
+    /// startup: `write-through` writes each WAL file to them as it is replayed, `parallel` writes
+    /// the replayed data to them once the replay is finished, handling the tables in parallel,
+    /// and `cold` skips it, so they start out with only the values from their last snapshot.
+    #[clap(
+        long = "last-cache-replay",
+        env = "INFLUXDB3_LAST_CACHE_REPLAY",
+        default_value = "write-through",
+        action
+    )]
+    pub last_cache_replay: LastCacheReplay,
 }
 
 /// Specified size of the Parquet cache in megabytes (MB)
@@ -378,6 +925,15 @@ pub async fn command(config: Config) -> Result<()> {
             config.parquet_mem_cache_size.as_num_bytes(),
             config.parquet_mem_cache_prune_percentage.into(),
             config.parquet_mem_cache_prune_interval.into(),
+            CacheAdmissionPolicy {
+                max_file_size_bytes: config
+                    .parquet_mem_cache_max_file_size_mb
+                    .map(|mb| mb * 1_000 * 1_000),
+                max_file_age: config.parquet_mem_cache_max_file_age.map(Into::into),
+                allow_tables: config.parquet_mem_cache_allow_tables,
+                deny_tables: config.parquet_mem_cache_deny_tables,
+            },
+            &metrics,
         );
         (object_store, Some(parquet_cache))
     } else {
@@ -428,15 +984,53 @@ pub async fn command(config: Config) -> Result<()> {
         )
         .with_jaeger_debug_name(config.tracing_config.traces_jaeger_debug_name);
 
-    let persister = Arc::new(Persister::new(
-        Arc::clone(&object_store),
-        config.host_identifier_prefix,
-    ));
+    let mut persister = Persister::new(Arc::clone(&object_store), config.host_identifier_prefix)
+        .with_catalog_checkpoint_interval(config.catalog_checkpoint_interval)
+        .with_persist_concurrency(config.persist_concurrency)
+        .with_metric_registry(&metrics)
+        .with_config(PersisterConfig {
+            row_group_size: config.parquet_row_group_size,
+            data_page_size: config.parquet_data_page_size_bytes,
+            compression: config.parquet_compression,
+            zstd_level: config.parquet_zstd_level,
+            dictionary_enabled: !config.parquet_disable_dictionary,
+            statistics: config.parquet_statistics_level,
+        });
+    if let Some(retained_catalog_versions) = config.retained_catalog_versions {
+        persister = persister.with_retained_catalog_versions(retained_catalog_versions);
+    }
+    if let Some(key) = config.encryption_key {
+        let previous_keys = config
+            .previous_encryption_keys
+            .iter()
+            .map(|key| EncryptionKey::from_hex(key))
+            .collect::<Result<Vec<_>, _>>()?;
+        persister = persister.with_key_provider(Arc::new(StaticKeyProvider::with_previous_keys(
+            EncryptionKey::from_hex(&key)?,
+            previous_keys,
+        )));
+    }
+    let persister = Arc::new(persister);
     let wal_config = WalConfig {
         gen1_duration: config.gen1_duration,
         max_write_buffer_size: config.wal_max_write_buffer_size,
         flush_interval: config.wal_flush_interval.into(),
         snapshot_size: config.wal_snapshot_size,
+        compression: config.wal_compression,
+        max_file_size_bytes: config.wal_max_file_size_bytes,
+        retention: WalRetention {
+            keep_files: config.wal_retention_files,
+            keep_duration: config.wal_retention_period.into(),
+        },
+        ack_level: config.wal_ack_level.unwrap_or(match config.wal_local_dir {
+            Some(_) => WalAckLevel::LocalDurable,
+            None => WalAckLevel::ObjectStorePut,
+        }),
+        snapshot_triggers: SnapshotTriggers {
+            max_age: config.wal_snapshot_max_age.map(Into::into),
+            max_bytes: config.wal_snapshot_max_bytes,
+        },
+        handoff_max_bytes: config.wal_handoff_max_bytes,
     };
 
     let catalog = Arc::new(
@@ -445,16 +1039,50 @@ pub async fn command(config: Config) -> Result<()> {
             .await
             .map_err(Error::InitializePersistedCatalog)?,
     );
+    catalog.set_limits(CatalogLimits {
+        max_databases: config.max_databases,
+        max_tables: config.max_tables,
+        max_tables_per_db: config.max_tables_per_db,
+        max_columns: config.max_columns,
+    });
 
     let last_cache = LastCacheProvider::new_from_catalog_with_background_eviction(
         Arc::clone(&catalog) as _,
         config.last_cache_eviction_interval.into(),
+        config.last_cache_mem_budget_mb.map(|mb| mb * 1_000 * 1_000),
     )
     .map_err(Error::InitializeLastCache)?;
     info!(instance_id = ?catalog.instance_id(), "Catalog initialized with");
 
-    let write_buffer_impl = Arc::new(
-        WriteBufferImpl::new(
+    // report the progress of the WAL replay, at most once a second, until it is done:
+    let (wal_replay_progress, mut replay_progress) = watch::channel(ReplayProgress::default());
+    tokio::spawn(async move {
+        while replay_progress.changed().await.is_ok() {
+            let ReplayProgress { replayed, total } = *replay_progress.borrow_and_update();
+            info!("replaying {replayed}/{total} WAL files");
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+
+    let recovery_target = match (config.recover_to_wal_file, config.recover_to_time) {
+        (Some(wal_file_number), _) => Some(RecoveryTarget::WalFile(WalFileSequenceNumber::new(
+            wal_file_number,
+        ))),
+        (None, Some(time)) => Some(RecoveryTarget::Time(time.into())),
+        (None, None) => None,
+    };
+    let write_buffer_args = WriteBufferImplArgs {
+        wal_local_dir: config.wal_local_dir,
+        wal_replay_progress: Some(wal_replay_progress),
+        parquet_cache_prefill_files: config.parquet_mem_cache_prefill_files,
+        buffer_mem_limit_bytes: Some(config.buffer_mem_limit_mb * 1_000 * 1_000),
+        buffer_full_timeout: config.buffer_full_timeout.map(Into::into),
+        record_rejected_lines: config.record_rejected_lines,
+        last_cache_replay: config.last_cache_replay,
+        other_writers: config.other_writers.clone(),
+        lease_duration: (!config.lease_duration.is_zero()).then(|| config.lease_duration.into()),
+        metric_registry: Arc::clone(&metrics),
+        ..WriteBufferImplArgs::new(
             Arc::clone(&persister),
             Arc::clone(&catalog),
             last_cache,
@@ -463,9 +1091,65 @@ pub async fn command(config: Config) -> Result<()> {
             wal_config,
             parquet_cache,
         )
-        .await
-        .map_err(|e| Error::WriteBufferInit(e.into()))?,
+    };
+    let write_buffer_impl = match recovery_target {
+        Some(target) => WriteBufferImpl::recover_to(write_buffer_args, target).await,
+        None => WriteBufferImpl::new(write_buffer_args).await,
+    };
+    let write_buffer_impl =
+        Arc::new(write_buffer_impl.map_err(|e| Error::WriteBufferInit(e.into()))?);
+    background_retention_enforcement(
+        Arc::clone(&write_buffer_impl),
+        config.retention_check_interval.into(),
+    );
+    background_deleted_table_removal(
+        Arc::clone(&write_buffer_impl),
+        config.retention_check_interval.into(),
+        config.table_delete_grace_period.into(),
     );
+    if !config.compaction_interval.is_zero() {
+        background_compaction(
+            Arc::clone(&write_buffer_impl),
+            config.compaction_interval.into(),
+            CompactionConfig {
+                gen2_duration: config.gen2_duration.into(),
+                min_files: config.compaction_min_files,
+            },
+        );
+    }
+    if !config.downsampling_interval.is_zero() {
+        background_downsampling(
+            Arc::clone(&write_buffer_impl),
+            config.downsampling_interval.into(),
+        );
+    }
+    if !config.persisted_files_index_interval.is_zero() {
+        background_persisted_files_index(
+            Arc::clone(&write_buffer_impl),
+            config.persisted_files_index_interval.into(),
+        );
+    }
+    if !config.other_writers.is_empty() {
+        background_other_writers_refresh(
+            Arc::clone(&write_buffer_impl),
+            config.other_writers_refresh_interval.into(),
+        );
+    }
+    if !config.parquet_gc_interval.is_zero() {
+        background_parquet_garbage_collection(
+            Arc::clone(&write_buffer_impl),
+            config.parquet_gc_interval.into(),
+            config.parquet_gc_min_age.into(),
+            config.parquet_gc_dry_run,
+        );
+    }
+    if !config.wal_gc_interval.is_zero() {
+        background_wal_garbage_collection(
+            Arc::clone(&write_buffer_impl),
+            config.wal_gc_interval.into(),
+            config.wal_gc_dry_run,
+        );
+    }
 
     let telemetry_store = setup_telemetry_store(
         &config.object_store_config,
@@ -476,7 +1160,7 @@ pub async fn command(config: Config) -> Result<()> {
     )
     .await;
 
-    let write_buffer: Arc<dyn WriteBuffer> = write_buffer_impl;
+    let write_buffer: Arc<dyn WriteBuffer> = Arc::<WriteBufferImpl>::clone(&write_buffer_impl);
 
     let common_state = CommonServerState::new(
         Arc::clone(&metrics),
@@ -517,6 +1201,9 @@ pub async fn command(config: Config) -> Result<()> {
     };
     serve(server, frontend_shutdown).await?;
 
+    // persist everything in the buffer, so that the WAL does not need to be replayed on restart:
+    write_buffer_impl.shutdown().await;
+
     Ok(())
 }
 
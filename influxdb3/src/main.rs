@@ -26,6 +26,7 @@ use trogging::{
 
 mod commands {
     pub(crate) mod common;
+    pub mod debug;
     pub mod last_cache;
     pub mod query;
     pub mod serve;
@@ -91,6 +92,9 @@ enum Command {
 
     /// Manage last-n-value caches
     LastCache(commands::last_cache::Config),
+
+    /// Debugging tools, e.g., to inspect the WAL files in object storage
+    Debug(commands::debug::Config),
 }
 
 fn main() -> Result<(), std::io::Error> {
@@ -148,6 +152,12 @@ fn main() -> Result<(), std::io::Error> {
                     std::process::exit(ReturnCode::Failure as _)
                 }
             }
+            Some(Command::Debug(config)) => {
+                if let Err(e) = commands::debug::command(config).await {
+                    eprintln!("Debug command failed: {e}");
+                    std::process::exit(ReturnCode::Failure as _)
+                }
+            }
         }
     });
 
@@ -117,6 +117,7 @@ async fn flight() -> Result<(), influxdb3_client::Error> {
                 "| public       | information_schema | tables        | VIEW       |",
                 "| public       | information_schema | views         | VIEW       |",
                 "| public       | iox                | cpu           | BASE TABLE |",
+                "| public       | system             | audit_log     | BASE TABLE |",
                 "| public       | system             | last_caches   | BASE TABLE |",
                 "| public       | system             | parquet_files | BASE TABLE |",
                 "| public       | system             | queries       | BASE TABLE |",
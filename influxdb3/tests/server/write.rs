@@ -113,6 +113,97 @@ async fn api_v3_write() {
     }
 }
 
+#[tokio::test]
+async fn api_v3_write_tabular() {
+    let server = TestServer::spawn().await;
+    let client = reqwest::Client::new();
+    let url = format!("{base}/api/v3/write_tabular", base = server.client_addr());
+
+    let resp = client
+        .post(&url)
+        .query(&[
+            ("db", "foo"),
+            ("table", "sensors"),
+            ("tags", "site"),
+            ("time", "ts"),
+            ("field_types", "count:integer"),
+            ("ignore", "note"),
+            ("precision", "second"),
+        ])
+        .header("content-type", "text/csv; charset=utf-8")
+        .body(
+            "\
+            site,ts,temp,count,note\n\
+            north,1234,21.5,3,first\n\
+            south,1234,18.0,4,\n\
+            ",
+        )
+        .send()
+        .await
+        .expect("send write request");
+    assert_eq!(StatusCode::OK, resp.status());
+
+    let resp = client
+        .post(&url)
+        .query(&[
+            ("db", "foo"),
+            ("table", "sensors"),
+            ("tags", "site"),
+            ("time", "ts"),
+            ("field_types", "count:integer"),
+            ("precision", "second"),
+        ])
+        .header("content-type", "application/x-ndjson")
+        .body(
+            "\
+            {\"site\": \"east\", \"ts\": 1235, \"temp\": 20, \"count\": 5}\n\
+            {\"site\": \"west\", \"ts\": 1235, \"temp\": [1]}\n\
+            ",
+        )
+        .send()
+        .await
+        .expect("send write request");
+    // the line with a nested value is rejected, and the rest are written:
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+    assert_contains!(
+        resp.text().await.unwrap(),
+        "column temp has a nested value, which cannot be written"
+    );
+
+    let resp = server
+        .api_v3_query_sql(&[
+            ("db", "foo"),
+            ("q", "SELECT * FROM sensors ORDER BY site"),
+            ("format", "pretty"),
+        ])
+        .await
+        .text()
+        .await
+        .expect("get body");
+    assert_eq!(
+        "\
+        +-------+-------+------+---------------------+\n\
+        | count | site  | temp | time                |\n\
+        +-------+-------+------+---------------------+\n\
+        | 5     | east  | 20.0 | 1970-01-01T00:20:35 |\n\
+        | 3     | north | 21.5 | 1970-01-01T00:20:34 |\n\
+        | 4     | south | 18.0 | 1970-01-01T00:20:34 |\n\
+        +-------+-------+------+---------------------+",
+        resp
+    );
+
+    // the format of the body must be known:
+    let resp = client
+        .post(&url)
+        .query(&[("db", "foo"), ("table", "sensors")])
+        .header("content-type", "application/json")
+        .body("{}")
+        .send()
+        .await
+        .expect("send write request");
+    assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, resp.status());
+}
+
 #[tokio::test]
 async fn api_v1_write_request_parsing() {
     let server = TestServer::spawn().await;
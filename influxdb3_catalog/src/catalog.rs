@@ -2,19 +2,25 @@
 
 use crate::catalog::Error::TableNotFound;
 use bimap::BiHashMap;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use indexmap::IndexMap;
 use influxdb3_id::{ColumnId, DbId, SerdeVecMap, TableId};
 use influxdb3_wal::{
-    CatalogBatch, CatalogOp, FieldAdditions, LastCacheDefinition, LastCacheDelete,
+    CardinalityLimits, CatalogBatch, CatalogOp, ColumnDefaultDefinition, CompatibilityMode,
+    DeleteTableDefinition, DistinctCacheDefinition, DistinctCacheDelete, DownsamplingTask,
+    DropColumnDefinition, FieldAdditions, FieldCoercion, FieldData, Gen1Duration, IdentifierPolicy,
+    LastCacheDefinition, LastCacheDelete, LastCacheValueColumnsDef, RenameColumnDefinition,
+    SchemaLimits, SchemaMode, SeriesKeyPolicy, WriteRateLimit,
 };
 use influxdb_line_protocol::FieldValue;
 use observability_deps::tracing::info;
 use parking_lot::RwLock;
 use schema::{InfluxColumnType, InfluxFieldType, Schema, SchemaBuilder};
 use serde::{Deserialize, Serialize, Serializer};
+use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Debug, Error, Clone)]
@@ -28,17 +34,21 @@ pub enum Error {
     )]
     TooManyColumns,
 
-    #[error(
-        "Update to schema would exceed number of tables limit of {} tables",
-        Catalog::NUM_TABLES_LIMIT
-    )]
-    TooManyTables,
+    #[error("Update to schema would exceed number of tables limit of {0} tables")]
+    TooManyTables(usize),
 
     #[error(
-        "Adding a new database would exceed limit of {} databases",
-        Catalog::NUM_DBS_LIMIT
+        "Update to schema would exceed limit of {} tables in database {}",
+        limit,
+        db_name
     )]
-    TooManyDbs,
+    TooManyTablesInDb { db_name: Arc<str>, limit: usize },
+
+    #[error("Update to schema would exceed limit of {0} columns across all tables")]
+    TooManyColumnsInCatalog(usize),
+
+    #[error("Adding a new database would exceed limit of {0} databases")]
+    TooManyDbs(usize),
 
     #[error("Table {} not in DB schema for {}", table_name, db_name)]
     TableNotFound {
@@ -69,6 +79,66 @@ pub enum Error {
         table_name: String,
         existing: String,
     },
+
+    #[error("Database {0} already exists")]
+    DatabaseAlreadyExists(Arc<str>),
+
+    #[error("Table {} already exists in DB schema for {}", table_name, db_name)]
+    TableAlreadyExists {
+        db_name: Arc<str>,
+        table_name: Arc<str>,
+    },
+
+    #[error("Column {} not found in table {}", column_name, table_name)]
+    ColumnNotFound {
+        table_name: Arc<str>,
+        column_name: Arc<str>,
+    },
+
+    #[error(
+        "Column {} in table {} is not a field; only field columns can be dropped, renamed, or \
+         have default values",
+        column_name,
+        table_name
+    )]
+    NotAFieldColumn {
+        table_name: Arc<str>,
+        column_name: Arc<str>,
+    },
+
+    #[error(
+        "Column {} already exists in table {}, or was used by a column that was dropped or renamed",
+        column_name,
+        table_name
+    )]
+    ColumnAlreadyExists {
+        table_name: Arc<str>,
+        column_name: Arc<str>,
+    },
+
+    #[error(
+        "Column {} in table {} is used by the last cache {}",
+        column_name,
+        table_name,
+        cache_name
+    )]
+    ColumnUsedByLastCache {
+        table_name: Arc<str>,
+        column_name: Arc<str>,
+        cache_name: Arc<str>,
+    },
+
+    #[error(
+        "Column {} in table {} is used by the distinct cache {}",
+        column_name,
+        table_name,
+        cache_name
+    )]
+    ColumnUsedByDistinctCache {
+        table_name: Arc<str>,
+        column_name: Arc<str>,
+        cache_name: Arc<str>,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -116,11 +186,11 @@ impl Serialize for Catalog {
 }
 
 impl Catalog {
-    /// Limit for the number of Databases that InfluxDB Edge can have
+    /// Default limit for the number of Databases that InfluxDB Edge can have
     pub(crate) const NUM_DBS_LIMIT: usize = 5;
     /// Limit for the number of columns per table that InfluxDB Edge can have
     pub(crate) const NUM_COLUMNS_PER_TABLE_LIMIT: usize = 500;
-    /// Limit for the number of tables across all DBs that InfluxDB Edge can have
+    /// Default limit for the number of tables across all DBs that InfluxDB Edge can have
     pub(crate) const NUM_TABLES_LIMIT: usize = 2000;
 
     pub fn new(host_id: Arc<str>, instance_id: Arc<str>) -> Self {
@@ -139,14 +209,60 @@ impl Catalog {
         self.inner.write().apply_catalog_batch(catalog_batch)
     }
 
+    /// Apply a `catalog_batch` that was written to the WAL. Batches were within the limits of the
+    /// catalog when they were written to the WAL, so they are not checked against the limits
+    /// again, which may have been lowered since.
+    ///
+    /// The batch is persisted in the next [`CatalogDelta`], whether or not it changed the catalog,
+    /// since it may have been applied before it was written to the WAL.
+    pub fn replay_catalog_batch(&self, catalog_batch: &CatalogBatch) -> Result<()> {
+        let mut inner = self.inner.write();
+        inner.apply(catalog_batch, false)?;
+        inner.unpersisted_batches.push(catalog_batch.clone());
+        Ok(())
+    }
+
+    /// Check that the `catalog_batch` could be applied to the catalog, without applying it
+    pub fn validate_catalog_batch(&self, catalog_batch: &CatalogBatch) -> Result<()> {
+        self.inner
+            .read()
+            .db_schema_from_batch(catalog_batch, true)
+            .map(|_| ())
+    }
+
+    pub fn limits(&self) -> CatalogLimits {
+        self.inner.read().limits
+    }
+
+    /// Set the limits on the size of the catalog, which are enforced on later updates to it
+    pub fn set_limits(&self, limits: CatalogLimits) {
+        self.inner.write().limits = limits;
+    }
+
+    /// The size of the catalog, and the limits on it
+    pub fn usage(&self) -> CatalogUsage {
+        let inner = self.inner.read();
+        CatalogUsage {
+            databases: inner.databases.len(),
+            tables: inner.table_count(),
+            columns: inner.column_count(),
+            tables_per_db: inner
+                .databases
+                .values()
+                .map(|db| (Arc::clone(&db.name), db.tables.len()))
+                .collect(),
+            limits: inner.limits,
+        }
+    }
+
     pub fn db_or_create(&self, db_name: &str) -> Result<Arc<DatabaseSchema>> {
         let db = match self.db_schema(db_name) {
             Some(db) => db,
             None => {
                 let mut inner = self.inner.write();
 
-                if inner.databases.len() >= Self::NUM_DBS_LIMIT {
-                    return Err(Error::TooManyDbs);
+                if inner.databases.len() >= inner.limits.max_databases {
+                    return Err(Error::TooManyDbs(inner.limits.max_databases));
                 }
 
                 info!("return new db {}", db_name);
@@ -252,6 +368,54 @@ impl Catalog {
         inner.updated = true;
     }
 
+    /// Add a distinct cache to a table, replacing the definition of a cache with the same name
+    pub fn add_distinct_cache(
+        &self,
+        db_id: DbId,
+        table_id: TableId,
+        distinct_cache: DistinctCacheDefinition,
+    ) {
+        let mut inner = self.inner.write();
+        let mut db = inner
+            .databases
+            .get(&db_id)
+            .expect("db should exist")
+            .as_ref()
+            .clone();
+        let mut table = db
+            .tables
+            .get(&table_id)
+            .expect("table should exist")
+            .as_ref()
+            .clone();
+        table.add_distinct_cache(distinct_cache);
+        db.tables.insert(table_id, Arc::new(table));
+        inner.databases.insert(db_id, Arc::new(db));
+        inner.sequence = inner.sequence.next();
+        inner.updated = true;
+    }
+
+    pub fn delete_distinct_cache(&self, db_id: DbId, table_id: TableId, name: &str) {
+        let mut inner = self.inner.write();
+        let mut db = inner
+            .databases
+            .get(&db_id)
+            .expect("db should exist")
+            .as_ref()
+            .clone();
+        let mut table = db
+            .tables
+            .get(&table_id)
+            .expect("table should exist")
+            .as_ref()
+            .clone();
+        table.remove_distinct_cache(name);
+        db.tables.insert(table_id, Arc::new(table));
+        inner.databases.insert(db_id, Arc::new(db));
+        inner.sequence = inner.sequence.next();
+        inner.updated = true;
+    }
+
     pub fn instance_id(&self) -> Arc<str> {
         Arc::clone(&self.inner.read().instance_id)
     }
@@ -277,12 +441,21 @@ impl Catalog {
         self.inner.read().updated
     }
 
-    /// After the catalog has been persisted, mark it as not updated, if the sequence number
-    /// matches. If it doesn't then the catalog was updated while persistence was running and
-    /// will need to be persisted on the next snapshot.
-    pub fn set_updated_false_if_sequence_matches(&self, sequence_number: CatalogSequenceNumber) {
+    /// After the changes in `delta` have been persisted, either as the delta itself, or in full
+    /// if `checkpoint` is set, remove its batches from those to persist in the next delta, and
+    /// mark the catalog as not updated, if the sequence number matches. If it doesn't then the
+    /// catalog was updated while persistence was running and will need to be persisted on the
+    /// next snapshot.
+    pub fn set_persisted(&self, delta: &CatalogDelta, checkpoint: bool) {
         let mut inner = self.inner.write();
-        if inner.sequence == sequence_number {
+        let persisted_batches = delta.batches.len().min(inner.unpersisted_batches.len());
+        inner.unpersisted_batches.drain(..persisted_batches);
+        inner.deltas_since_checkpoint = if checkpoint {
+            0
+        } else {
+            inner.deltas_since_checkpoint + 1
+        };
+        if inner.sequence == delta.sequence {
             inner.updated = false;
         }
     }
@@ -307,6 +480,66 @@ pub struct InnerCatalog {
     updated: bool,
     #[serde_as(as = "DbMapAsArray")]
     db_map: BiHashMap<DbId, Arc<str>>,
+    /// The limits on the size of the catalog, which are configured on the server, rather than
+    /// persisted with the catalog
+    #[serde(skip)]
+    limits: CatalogLimits,
+    /// The batches from the WAL that have been applied since the changes to the catalog were last
+    /// persisted, which are persisted as a [`CatalogDelta`], rather than persisting the catalog
+    /// in full each time that it changes
+    #[serde(skip)]
+    unpersisted_batches: Vec<CatalogBatch>,
+    /// The number of deltas that have been persisted since the catalog was persisted in full
+    #[serde(skip)]
+    deltas_since_checkpoint: usize,
+}
+
+/// The changes to the catalog between the catalog being persisted, which are persisted instead of
+/// the catalog in full. The catalog is loaded by applying the deltas that were persisted after it
+/// to it, in order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogDelta {
+    /// The sequence number of the catalog after the changes
+    pub sequence: CatalogSequenceNumber,
+    /// The WAL batches that were applied to the catalog since the changes to it were last
+    /// persisted
+    pub batches: Vec<CatalogBatch>,
+}
+
+/// Limits on the size of the catalog, which are enforced when it is updated, since large
+/// catalogs are slow to persist, and to replay from the WAL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CatalogLimits {
+    /// The maximum number of databases
+    pub max_databases: usize,
+    /// The maximum number of tables, across all databases
+    pub max_tables: usize,
+    /// The maximum number of tables in each database. If not set, only `max_tables` applies.
+    pub max_tables_per_db: Option<usize>,
+    /// The maximum number of columns, across all tables
+    pub max_columns: usize,
+}
+
+impl Default for CatalogLimits {
+    fn default() -> Self {
+        Self {
+            max_databases: Catalog::NUM_DBS_LIMIT,
+            max_tables: Catalog::NUM_TABLES_LIMIT,
+            max_tables_per_db: None,
+            max_columns: Catalog::NUM_TABLES_LIMIT * Catalog::NUM_COLUMNS_PER_TABLE_LIMIT,
+        }
+    }
+}
+
+/// The number of databases, tables, and columns in the catalog, along with the limits on them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CatalogUsage {
+    pub databases: usize,
+    pub tables: usize,
+    pub columns: usize,
+    /// The number of tables in each database, by name
+    pub tables_per_db: BTreeMap<Arc<str>, usize>,
+    pub limits: CatalogLimits,
 }
 
 serde_with::serde_conv!(
@@ -371,6 +604,9 @@ impl InnerCatalog {
             instance_id,
             updated: false,
             db_map: BiHashMap::new(),
+            limits: CatalogLimits::default(),
+            unpersisted_batches: vec![],
+            deltas_since_checkpoint: 0,
         }
     }
 
@@ -378,39 +614,70 @@ impl InnerCatalog {
         self.sequence
     }
 
+    /// Move the catalog to another host, e.g., when it is restored from a backup under the
+    /// prefix of a different host
+    pub fn set_host_id(&mut self, host_id: Arc<str>) {
+        self.host_id = host_id;
+    }
+
     pub fn table_count(&self) -> usize {
         self.databases.values().map(|db| db.tables.len()).sum()
     }
 
-    /// Applies the `CatalogBatch` while validating that all updates are compatible. If updates
-    /// have already been applied, the sequence number and updated tracker are not updated.
-    pub fn apply_catalog_batch(&mut self, catalog_batch: &CatalogBatch) -> Result<()> {
-        let table_count = self.table_count();
+    pub fn column_count(&self) -> usize {
+        self.databases.values().map(|db| db.column_count()).sum()
+    }
 
-        if let Some(db) = self.databases.get(&catalog_batch.database_id) {
-            let existing_table_count = db.tables.len();
+    /// The changes to persist since the changes to the catalog were last persisted
+    pub fn unpersisted_delta(&self) -> CatalogDelta {
+        CatalogDelta {
+            sequence: self.sequence,
+            batches: self.unpersisted_batches.clone(),
+        }
+    }
 
-            if let Some(new_db) = db.new_if_updated_from_batch(catalog_batch)? {
-                let new_table_count = new_db.tables.len() - existing_table_count;
-                if table_count + new_table_count > Catalog::NUM_TABLES_LIMIT {
-                    return Err(Error::TooManyTables);
-                }
-                let new_db = Arc::new(new_db);
-                self.databases.insert(new_db.id, Arc::clone(&new_db));
-                self.sequence = self.sequence.next();
-                self.updated = true;
-                self.db_map.insert(new_db.id, Arc::clone(&new_db.name));
-            }
-        } else {
-            if self.databases.len() >= Catalog::NUM_DBS_LIMIT {
-                return Err(Error::TooManyDbs);
-            }
+    /// The number of deltas that have been persisted since the catalog was persisted in full
+    pub fn deltas_since_checkpoint(&self) -> usize {
+        self.deltas_since_checkpoint
+    }
+
+    /// Apply a delta that was persisted after the catalog, when loading the catalog. Like batches
+    /// replayed from the WAL, the batches in the delta are not checked against the limits.
+    pub fn apply_delta(&mut self, delta: &CatalogDelta) -> Result<()> {
+        for catalog_batch in &delta.batches {
+            self.apply(catalog_batch, false)?;
+        }
+        self.sequence = self.sequence.max(delta.sequence);
+        self.updated = false;
+        self.deltas_since_checkpoint += 1;
+        Ok(())
+    }
 
-            let new_db = DatabaseSchema::new_from_batch(catalog_batch)?;
-            if table_count + new_db.tables.len() > Catalog::NUM_TABLES_LIMIT {
-                return Err(Error::TooManyTables);
+    /// Apply the batches in a delta, as [`InnerCatalog::apply_delta`] does, until the catalog
+    /// reaches the sequence number `until`, to reconstruct the catalog as it was at that point
+    pub fn apply_delta_until(
+        &mut self,
+        delta: &CatalogDelta,
+        until: CatalogSequenceNumber,
+    ) -> Result<()> {
+        for catalog_batch in &delta.batches {
+            if self.sequence >= until {
+                break;
             }
+            self.apply(catalog_batch, false)?;
+        }
+        self.updated = false;
+        Ok(())
+    }
+
+    /// Applies the `CatalogBatch` while validating that all updates are compatible. If updates
+    /// have already been applied, the sequence number and updated tracker are not updated.
+    pub fn apply_catalog_batch(&mut self, catalog_batch: &CatalogBatch) -> Result<()> {
+        self.apply(catalog_batch, true)
+    }
 
+    fn apply(&mut self, catalog_batch: &CatalogBatch, enforce_limits: bool) -> Result<()> {
+        if let Some(new_db) = self.db_schema_from_batch(catalog_batch, enforce_limits)? {
             let new_db = Arc::new(new_db);
             self.databases.insert(new_db.id, Arc::clone(&new_db));
             self.sequence = self.sequence.next();
@@ -421,9 +688,215 @@ impl InnerCatalog {
         Ok(())
     }
 
+    /// Validate the `catalog_batch` against the catalog, and its limits if `enforce_limits` is
+    /// set, returning the updated schema of its database if the batch changes it, or `None`
+    /// otherwise
+    fn db_schema_from_batch(
+        &self,
+        catalog_batch: &CatalogBatch,
+        enforce_limits: bool,
+    ) -> Result<Option<DatabaseSchema>> {
+        let existing_db = self.databases.get(&catalog_batch.database_id);
+        let new_db = if let Some(db) = existing_db {
+            let Some(new_db) = db.new_if_updated_from_batch(catalog_batch)? else {
+                return Ok(None);
+            };
+            new_db
+        } else {
+            if enforce_limits && self.databases.len() >= self.limits.max_databases {
+                return Err(Error::TooManyDbs(self.limits.max_databases));
+            }
+            DatabaseSchema::new_from_batch(catalog_batch)?
+        };
+        if enforce_limits {
+            self.check_limits(existing_db.map(Arc::as_ref), &new_db)?;
+        }
+
+        // a database can only be created, or renamed, with a name that is not in use:
+        if self
+            .db_map
+            .get_by_right(&new_db.name)
+            .is_some_and(|db_id| *db_id != new_db.id)
+        {
+            return Err(Error::DatabaseAlreadyExists(Arc::clone(&new_db.name)));
+        }
+        Ok(Some(new_db))
+    }
+
+    /// Check that replacing the `existing_db`, if there is one, with `new_db` keeps the number of
+    /// tables and columns within the limits. Updates that do not add tables or columns are
+    /// allowed even if the catalog is over its limits, e.g., because they were lowered.
+    fn check_limits(
+        &self,
+        existing_db: Option<&DatabaseSchema>,
+        new_db: &DatabaseSchema,
+    ) -> Result<()> {
+        let limits = &self.limits;
+        let (existing_tables, existing_columns) = existing_db
+            .map(|db| (db.tables.len(), db.column_count()))
+            .unwrap_or_default();
+
+        let new_tables = new_db.tables.len();
+        if new_tables > existing_tables {
+            if self.table_count() - existing_tables + new_tables > limits.max_tables {
+                return Err(Error::TooManyTables(limits.max_tables));
+            }
+            if let Some(limit) = limits.max_tables_per_db.filter(|limit| new_tables > *limit) {
+                return Err(Error::TooManyTablesInDb {
+                    db_name: Arc::clone(&new_db.name),
+                    limit,
+                });
+            }
+        }
+
+        let new_columns = new_db.column_count();
+        if new_columns > existing_columns
+            && self.column_count() - existing_columns + new_columns > limits.max_columns
+        {
+            return Err(Error::TooManyColumnsInCatalog(limits.max_columns));
+        }
+        Ok(())
+    }
+
     pub fn db_exists(&self, db_id: DbId) -> bool {
         self.databases.contains_key(&db_id)
     }
+
+    /// The ids that follow on from the largest database, table, and column ids in the catalog
+    pub fn next_ids(&self) -> NextIds {
+        let mut next_ids = NextIds::new(DbId::from(0), TableId::from(0), ColumnId::from(0));
+        for db in self.databases.values() {
+            next_ids.db_id = next_ids.db_id.max(DbId::from(db.id.as_u32() + 1));
+            for table in db.tables.values() {
+                next_ids.table_id = next_ids
+                    .table_id
+                    .max(TableId::from(table.table_id.as_u32() + 1));
+                if let Some(column_id) = table.columns.keys().max() {
+                    next_ids.column_id = next_ids
+                        .column_id
+                        .max(ColumnId::from(column_id.as_u32() + 1));
+                }
+            }
+        }
+        next_ids
+    }
+
+    /// Import the databases of a catalog that was exported from another host, giving them, and
+    /// their tables and columns, new ids starting from `next_ids`, so that they do not clash with
+    /// the ids used on this host.
+    ///
+    /// Nothing is imported if a database with the same name already exists, or if importing the
+    /// databases would exceed the limits on the number of databases or tables.
+    pub fn import(&mut self, exported: &InnerCatalog, mut next_ids: NextIds) -> Result<()> {
+        let limits = &self.limits;
+        if self.databases.len() + exported.databases.len() > limits.max_databases {
+            return Err(Error::TooManyDbs(limits.max_databases));
+        }
+        if self.table_count() + exported.table_count() > limits.max_tables {
+            return Err(Error::TooManyTables(limits.max_tables));
+        }
+        if let Some(limit) = limits.max_tables_per_db {
+            if let Some(db) = exported
+                .databases
+                .values()
+                .find(|db| db.tables.len() > limit)
+            {
+                return Err(Error::TooManyTablesInDb {
+                    db_name: Arc::clone(&db.name),
+                    limit,
+                });
+            }
+        }
+        if self.column_count() + exported.column_count() > limits.max_columns {
+            return Err(Error::TooManyColumnsInCatalog(limits.max_columns));
+        }
+        if let Some(db) = exported
+            .databases
+            .values()
+            .find(|db| self.db_map.contains_right(&db.name))
+        {
+            return Err(Error::DatabaseAlreadyExists(Arc::clone(&db.name)));
+        }
+
+        for exported_db in exported.databases.values() {
+            let db = Arc::new(exported_db.with_new_ids(&mut next_ids));
+            info!(name = %db.name, exported_id = %exported_db.id, id = %db.id, "imported database");
+            self.db_map.insert(db.id, Arc::clone(&db.name));
+            self.databases.insert(db.id, db);
+        }
+        if !exported.databases.is_empty() {
+            self.sequence = self.sequence.next();
+            self.updated = true;
+        }
+        Ok(())
+    }
+}
+
+/// The ids to give to the next database, table, and column that are created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NextIds {
+    pub db_id: DbId,
+    pub table_id: TableId,
+    pub column_id: ColumnId,
+}
+
+impl NextIds {
+    pub fn new(db_id: DbId, table_id: TableId, column_id: ColumnId) -> Self {
+        Self {
+            db_id,
+            table_id,
+            column_id,
+        }
+    }
+
+    /// The later of the ids in `self` and `other`, for each kind of id
+    pub fn max(self, other: Self) -> Self {
+        Self {
+            db_id: self.db_id.max(other.db_id),
+            table_id: self.table_id.max(other.table_id),
+            column_id: self.column_id.max(other.column_id),
+        }
+    }
+
+    pub(crate) fn take_db_id(&mut self) -> DbId {
+        let id = self.db_id;
+        self.db_id = DbId::from(id.as_u32() + 1);
+        id
+    }
+
+    pub(crate) fn take_table_id(&mut self) -> TableId {
+        let id = self.table_id;
+        self.table_id = TableId::from(id.as_u32() + 1);
+        id
+    }
+
+    pub(crate) fn take_column_id(&mut self) -> ColumnId {
+        let id = self.column_id;
+        self.column_id = ColumnId::from(id.as_u32() + 1);
+        id
+    }
+}
+
+/// The version of the format of [`CatalogExport`] documents written by this version of InfluxDB
+pub const CATALOG_EXPORT_VERSION: u32 = 1;
+
+/// A self-contained copy of the catalog of a host, which can be imported into the catalog of
+/// another host, e.g., to move a dataset between host identifiers
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CatalogExport {
+    /// The version of the format of the document, so that documents exported by other versions
+    /// of InfluxDB can be rejected, or converted, when they are imported
+    pub version: u32,
+    pub catalog: InnerCatalog,
+}
+
+impl CatalogExport {
+    pub fn new(catalog: InnerCatalog) -> Self {
+        Self {
+            version: CATALOG_EXPORT_VERSION,
+            catalog,
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -433,6 +906,39 @@ pub struct DatabaseSchema {
     /// The database is a map of tables
     pub tables: SerdeVecMap<TableId, Arc<TableDefinition>>,
     pub table_map: BiHashMap<TableId, Arc<str>>,
+    /// Data older than the retention period is dropped from the database. If not set, data is
+    /// retained forever.
+    pub retention_period: Option<Duration>,
+    /// Writes with timestamps further than this into the future, relative to the time of the
+    /// write, are rejected. If not set, there is no limit.
+    pub future_write_limit: Option<Duration>,
+    /// Writes with timestamps further than this into the past, relative to the time of the write,
+    /// are rejected. If not set, there is no limit.
+    pub past_write_limit: Option<Duration>,
+    /// Limits on the schema of the tables in the database
+    pub schema_limits: SchemaLimits,
+    /// Limits on the number of tables and series in the database
+    pub cardinality_limits: CardinalityLimits,
+    /// Whether writes may add tables and columns to the database
+    pub schema_mode: SchemaMode,
+    /// How v1 writes of field values that do not match the type of their column are handled
+    pub field_coercion: FieldCoercion,
+    /// How line protocol written to the database is interpreted
+    pub compatibility_mode: CompatibilityMode,
+    /// How v3 writes whose series key does not match that of their table are handled
+    pub series_key_policy: SeriesKeyPolicy,
+    /// How the names of the tables and columns in the database are normalized
+    pub identifier_policy: IdentifierPolicy,
+    /// Limits on the rate at which data can be written to the database
+    pub write_rate_limit: WriteRateLimit,
+    /// The duration of the chunks that writes to the database are buffered in, and persisted as
+    /// parquet files. If not set, the gen1 duration of the server is used.
+    pub gen1_duration: Option<Gen1Duration>,
+    /// Key/value pairs that describe the database, e.g., its owner, which have no effect on the
+    /// server
+    pub metadata: BTreeMap<String, String>,
+    /// The tasks that aggregate the data of tables in the database into other tables, by name
+    pub downsampling_tasks: BTreeMap<Arc<str>, DownsamplingTask>,
 }
 
 impl DatabaseSchema {
@@ -442,6 +948,20 @@ impl DatabaseSchema {
             name,
             tables: Default::default(),
             table_map: BiHashMap::new(),
+            retention_period: None,
+            future_write_limit: None,
+            past_write_limit: None,
+            schema_limits: SchemaLimits::default(),
+            cardinality_limits: CardinalityLimits::default(),
+            schema_mode: SchemaMode::default(),
+            field_coercion: FieldCoercion::default(),
+            compatibility_mode: CompatibilityMode::default(),
+            series_key_policy: SeriesKeyPolicy::default(),
+            identifier_policy: IdentifierPolicy::default(),
+            write_rate_limit: WriteRateLimit::default(),
+            gen1_duration: None,
+            metadata: BTreeMap::new(),
+            downsampling_tasks: BTreeMap::new(),
         }
     }
 
@@ -450,6 +970,22 @@ impl DatabaseSchema {
     /// returned, otherwise a new `DatabaseSchema` will be returned with the updates applied.
     pub fn new_if_updated_from_batch(&self, catalog_batch: &CatalogBatch) -> Result<Option<Self>> {
         let mut updated_or_new_tables = SerdeVecMap::new();
+        let mut hard_deleted_tables = HashSet::new();
+        let mut name = Arc::clone(&self.name);
+        let mut retention_period = self.retention_period;
+        let mut future_write_limit = self.future_write_limit;
+        let mut past_write_limit = self.past_write_limit;
+        let mut schema_limits = self.schema_limits;
+        let mut cardinality_limits = self.cardinality_limits;
+        let mut schema_mode = self.schema_mode;
+        let mut field_coercion = self.field_coercion;
+        let mut compatibility_mode = self.compatibility_mode;
+        let mut series_key_policy = self.series_key_policy;
+        let mut identifier_policy = self.identifier_policy;
+        let mut write_rate_limit = self.write_rate_limit;
+        let mut gen1_duration = self.gen1_duration;
+        let mut metadata = &self.metadata;
+        let mut downsampling_tasks = Cow::Borrowed(&self.downsampling_tasks);
 
         for catalog_op in &catalog_batch.ops {
             match catalog_op {
@@ -517,38 +1053,359 @@ impl DatabaseSchema {
                         updated_or_new_tables.insert(new_table.table_id, Arc::new(new_table));
                     }
                 }
-            }
-        }
+                CatalogOp::UpdateLastCache(last_cache_definition) => {
+                    let new_or_existing_table = updated_or_new_tables
+                        .get(&last_cache_definition.table_id)
+                        .or_else(|| self.tables.get(&last_cache_definition.table_id));
 
-        if updated_or_new_tables.is_empty() {
-            Ok(None)
-        } else {
-            for (table_id, table_def) in &self.tables {
-                if !updated_or_new_tables.contains_key(table_id) {
-                    updated_or_new_tables.insert(*table_id, Arc::clone(table_def));
+                    let table = new_or_existing_table.ok_or(TableNotFound {
+                        db_name: Arc::clone(&self.name),
+                        table_name: Arc::clone(&last_cache_definition.table),
+                    })?;
+
+                    if let Some(new_table) =
+                        table.new_if_last_cache_definition_updates_existing(last_cache_definition)
+                    {
+                        updated_or_new_tables.insert(new_table.table_id, Arc::new(new_table));
+                    }
                 }
-            }
+                CatalogOp::CreateDistinctCache(distinct_cache_definition) => {
+                    let new_or_existing_table = updated_or_new_tables
+                        .get(&distinct_cache_definition.table_id)
+                        .or_else(|| self.tables.get(&distinct_cache_definition.table_id));
 
-            // With the final list of updated/new tables update the current mapping
-            let new_table_maps = updated_or_new_tables
-                .iter()
-                .map(|(table_id, table_def)| (*table_id, Arc::clone(&table_def.table_name)))
-                .collect();
+                    let table = new_or_existing_table.ok_or(TableNotFound {
+                        db_name: Arc::clone(&self.name),
+                        table_name: Arc::clone(&distinct_cache_definition.table),
+                    })?;
 
-            Ok(Some(Self {
-                id: self.id,
-                name: Arc::clone(&self.name),
-                tables: updated_or_new_tables,
-                table_map: new_table_maps,
-            }))
-        }
-    }
+                    if let Some(new_table) =
+                        table.new_if_distinct_cache_definition_is_new(distinct_cache_definition)
+                    {
+                        updated_or_new_tables.insert(new_table.table_id, Arc::new(new_table));
+                    }
+                }
+                CatalogOp::DeleteDistinctCache(distinct_cache_deletion) => {
+                    let new_or_existing_table = updated_or_new_tables
+                        .get(&distinct_cache_deletion.table_id)
+                        .or_else(|| self.tables.get(&distinct_cache_deletion.table_id));
 
-    pub fn new_from_batch(catalog_batch: &CatalogBatch) -> Result<Self> {
-        let db_schema = Self::new(
-            catalog_batch.database_id,
-            Arc::clone(&catalog_batch.database_name),
-        );
+                    let table = new_or_existing_table.ok_or(TableNotFound {
+                        db_name: Arc::clone(&self.name),
+                        table_name: Arc::clone(&distinct_cache_deletion.table_name),
+                    })?;
+
+                    if let Some(new_table) =
+                        table.new_if_distinct_cache_deletes_existing(distinct_cache_deletion)
+                    {
+                        updated_or_new_tables.insert(new_table.table_id, Arc::new(new_table));
+                    }
+                }
+                CatalogOp::SetRetentionPeriod(definition) => {
+                    retention_period = definition.retention_period;
+                }
+                CatalogOp::SetWriteTimeLimits(definition) => {
+                    future_write_limit = definition.future_write_limit;
+                    past_write_limit = definition.past_write_limit;
+                }
+                CatalogOp::SetSchemaLimits(definition) => {
+                    schema_limits = definition.schema_limits;
+                }
+                CatalogOp::SetCardinalityLimits(definition) => {
+                    cardinality_limits = definition.cardinality_limits;
+                }
+                CatalogOp::SetSchemaMode(definition) => {
+                    schema_mode = definition.schema_mode;
+                }
+                CatalogOp::SetFieldCoercion(definition) => {
+                    field_coercion = definition.field_coercion;
+                }
+                CatalogOp::SetCompatibilityMode(definition) => {
+                    compatibility_mode = definition.compatibility_mode;
+                }
+                CatalogOp::SetSeriesKeyPolicy(definition) => {
+                    series_key_policy = definition.series_key_policy;
+                }
+                CatalogOp::SetIdentifierPolicy(definition) => {
+                    identifier_policy = definition.identifier_policy;
+                }
+                CatalogOp::SetWriteRateLimit(definition) => {
+                    write_rate_limit = definition.write_rate_limit;
+                }
+                CatalogOp::SetGen1Duration(definition) => {
+                    gen1_duration = definition.gen1_duration;
+                }
+                CatalogOp::RenameDatabase(definition) => {
+                    name = Arc::clone(&definition.new_database_name);
+                }
+                CatalogOp::RenameTable(definition) => {
+                    let Some(table) = updated_or_new_tables
+                        .get(&definition.table_id)
+                        .or_else(|| self.tables.get(&definition.table_id))
+                    else {
+                        return Err(Error::TableNotFound {
+                            db_name: Arc::clone(&definition.database_name),
+                            table_name: Arc::clone(&definition.table_name),
+                        });
+                    };
+                    if table.table_name == definition.new_table_name {
+                        continue;
+                    }
+                    if self.table_name_in_use(
+                        &updated_or_new_tables,
+                        definition.table_id,
+                        &definition.new_table_name,
+                    ) {
+                        return Err(Error::TableAlreadyExists {
+                            db_name: Arc::clone(&definition.database_name),
+                            table_name: Arc::clone(&definition.new_table_name),
+                        });
+                    }
+                    let new_table = table.new_with_name(Arc::clone(&definition.new_table_name));
+                    updated_or_new_tables.insert(new_table.table_id, Arc::new(new_table));
+                }
+                CatalogOp::DropColumn(definition) => {
+                    let Some(table) = updated_or_new_tables
+                        .get(&definition.table_id)
+                        .or_else(|| self.tables.get(&definition.table_id))
+                    else {
+                        return Err(Error::TableNotFound {
+                            db_name: Arc::clone(&definition.database_name),
+                            table_name: Arc::clone(&definition.table_name),
+                        });
+                    };
+                    if let Some(new_table) = table.new_if_column_dropped(definition)? {
+                        updated_or_new_tables.insert(new_table.table_id, Arc::new(new_table));
+                    }
+                }
+                CatalogOp::RenameColumn(definition) => {
+                    let Some(table) = updated_or_new_tables
+                        .get(&definition.table_id)
+                        .or_else(|| self.tables.get(&definition.table_id))
+                    else {
+                        return Err(Error::TableNotFound {
+                            db_name: Arc::clone(&definition.database_name),
+                            table_name: Arc::clone(&definition.table_name),
+                        });
+                    };
+                    if let Some(new_table) = table.new_if_column_renamed(definition)? {
+                        updated_or_new_tables.insert(new_table.table_id, Arc::new(new_table));
+                    }
+                }
+                CatalogOp::MarkTableDeleted(definition) => {
+                    let Some(table) =
+                        self.updated_or_existing_table(&updated_or_new_tables, definition)
+                    else {
+                        continue;
+                    };
+                    if table.deleted_at_ns.is_none() {
+                        let new_table = TableDefinition {
+                            deleted_at_ns: Some(catalog_batch.time_ns),
+                            ..table.as_ref().clone()
+                        };
+                        updated_or_new_tables.insert(new_table.table_id, Arc::new(new_table));
+                    }
+                }
+                CatalogOp::UndeleteTable(definition) => {
+                    let Some(table) =
+                        self.updated_or_existing_table(&updated_or_new_tables, definition)
+                    else {
+                        continue;
+                    };
+                    if table.deleted_at_ns.is_none() {
+                        continue;
+                    }
+                    // the name of the table may have been taken by a table created since:
+                    if self.table_name_in_use(
+                        &updated_or_new_tables,
+                        definition.table_id,
+                        &table.table_name,
+                    ) {
+                        return Err(Error::TableAlreadyExists {
+                            db_name: Arc::clone(&definition.database_name),
+                            table_name: Arc::clone(&table.table_name),
+                        });
+                    }
+                    let new_table = TableDefinition {
+                        deleted_at_ns: None,
+                        ..table.as_ref().clone()
+                    };
+                    updated_or_new_tables.insert(new_table.table_id, Arc::new(new_table));
+                }
+                CatalogOp::HardDeleteTable(definition) => {
+                    if self.tables.contains_key(&definition.table_id) {
+                        hard_deleted_tables.insert(definition.table_id);
+                    }
+                    updated_or_new_tables.shift_remove(&definition.table_id);
+                    if downsampling_tasks
+                        .values()
+                        .any(|task| task.source_table_id == definition.table_id)
+                    {
+                        downsampling_tasks
+                            .to_mut()
+                            .retain(|_, task| task.source_table_id != definition.table_id);
+                    }
+                }
+                CatalogOp::SetDatabaseMetadata(definition) => {
+                    metadata = &definition.metadata;
+                }
+                CatalogOp::SetTableMetadata(definition) => {
+                    let Some(table) = updated_or_new_tables
+                        .get(&definition.table_id)
+                        .or_else(|| self.tables.get(&definition.table_id))
+                    else {
+                        return Err(Error::TableNotFound {
+                            db_name: Arc::clone(&definition.database_name),
+                            table_name: Arc::clone(&definition.table_name),
+                        });
+                    };
+                    if table.metadata != definition.metadata {
+                        let new_table = TableDefinition {
+                            metadata: definition.metadata.clone(),
+                            ..table.as_ref().clone()
+                        };
+                        updated_or_new_tables.insert(new_table.table_id, Arc::new(new_table));
+                    }
+                }
+                CatalogOp::SetColumnDefault(definition) => {
+                    let Some(table) = updated_or_new_tables
+                        .get(&definition.table_id)
+                        .or_else(|| self.tables.get(&definition.table_id))
+                    else {
+                        return Err(Error::TableNotFound {
+                            db_name: Arc::clone(&definition.database_name),
+                            table_name: Arc::clone(&definition.table_name),
+                        });
+                    };
+                    if let Some(new_table) = table.new_if_column_default_changed(definition)? {
+                        updated_or_new_tables.insert(new_table.table_id, Arc::new(new_table));
+                    }
+                }
+                CatalogOp::CreateDownsamplingTask(definition) => {
+                    let source_table_id = definition.task.source_table_id;
+                    if !updated_or_new_tables.contains_key(&source_table_id)
+                        && !self.tables.contains_key(&source_table_id)
+                    {
+                        return Err(Error::TableNotFound {
+                            db_name: Arc::clone(&definition.database_name),
+                            table_name: Arc::clone(&definition.source_table_name),
+                        });
+                    }
+                    if downsampling_tasks.get(&definition.task.name) != Some(&definition.task) {
+                        downsampling_tasks
+                            .to_mut()
+                            .insert(Arc::clone(&definition.task.name), definition.task.clone());
+                    }
+                }
+                CatalogOp::DeleteDownsamplingTask(definition) => {
+                    if downsampling_tasks.contains_key(&definition.task_name) {
+                        downsampling_tasks.to_mut().remove(&definition.task_name);
+                    }
+                }
+            }
+        }
+
+        if updated_or_new_tables.is_empty()
+            && hard_deleted_tables.is_empty()
+            && name == self.name
+            && retention_period == self.retention_period
+            && future_write_limit == self.future_write_limit
+            && past_write_limit == self.past_write_limit
+            && schema_limits == self.schema_limits
+            && cardinality_limits == self.cardinality_limits
+            && schema_mode == self.schema_mode
+            && field_coercion == self.field_coercion
+            && compatibility_mode == self.compatibility_mode
+            && series_key_policy == self.series_key_policy
+            && identifier_policy == self.identifier_policy
+            && write_rate_limit == self.write_rate_limit
+            && gen1_duration == self.gen1_duration
+            && *metadata == self.metadata
+            && *downsampling_tasks == self.downsampling_tasks
+        {
+            Ok(None)
+        } else {
+            for (table_id, table_def) in &self.tables {
+                if !updated_or_new_tables.contains_key(table_id)
+                    && !hard_deleted_tables.contains(table_id)
+                {
+                    updated_or_new_tables.insert(*table_id, Arc::clone(table_def));
+                }
+            }
+
+            // With the final list of updated/new tables update the current mapping, which leaves
+            // out deleted tables, so that they cannot be found by name
+            let new_table_maps = updated_or_new_tables
+                .iter()
+                .filter(|(_, table_def)| !table_def.is_deleted())
+                .map(|(table_id, table_def)| (*table_id, Arc::clone(&table_def.table_name)))
+                .collect();
+
+            Ok(Some(Self {
+                id: self.id,
+                name,
+                tables: updated_or_new_tables,
+                table_map: new_table_maps,
+                retention_period,
+                future_write_limit,
+                past_write_limit,
+                schema_limits,
+                cardinality_limits,
+                schema_mode,
+                field_coercion,
+                compatibility_mode,
+                series_key_policy,
+                identifier_policy,
+                write_rate_limit,
+                gen1_duration,
+                metadata: metadata.clone(),
+                downsampling_tasks: downsampling_tasks.into_owned(),
+            }))
+        }
+    }
+
+    /// The table that `definition` refers to, as it is updated by the batch being applied. It
+    /// may not exist when the WAL is replayed, if the table was hard deleted since, in which case
+    /// the op has no effect.
+    fn updated_or_existing_table(
+        &self,
+        updated_or_new_tables: &SerdeVecMap<TableId, Arc<TableDefinition>>,
+        definition: &DeleteTableDefinition,
+    ) -> Option<Arc<TableDefinition>> {
+        updated_or_new_tables
+            .get(&definition.table_id)
+            .or_else(|| self.tables.get(&definition.table_id))
+            .cloned()
+    }
+
+    /// Whether a table other than `table_id`, that is not deleted, is named `table_name`, taking
+    /// the tables updated by the batch being applied into account
+    fn table_name_in_use(
+        &self,
+        updated_or_new_tables: &SerdeVecMap<TableId, Arc<TableDefinition>>,
+        table_id: TableId,
+        table_name: &str,
+    ) -> bool {
+        updated_or_new_tables
+            .values()
+            .chain(
+                self.tables
+                    .iter()
+                    .filter(|(table_id, _)| !updated_or_new_tables.contains_key(table_id))
+                    .map(|(_, table_def)| table_def),
+            )
+            .any(|table_def| {
+                table_def.table_id != table_id
+                    && !table_def.is_deleted()
+                    && table_def.table_name.as_ref() == table_name
+            })
+    }
+
+    pub fn new_from_batch(catalog_batch: &CatalogBatch) -> Result<Self> {
+        let db_schema = Self::new(
+            catalog_batch.database_id,
+            Arc::clone(&catalog_batch.database_name),
+        );
         let new_db = db_schema
             .new_if_updated_from_batch(catalog_batch)?
             .expect("database must be new");
@@ -582,8 +1439,7 @@ impl DatabaseSchema {
         &self,
         table_name: impl Into<Arc<str>>,
     ) -> Option<(TableId, Schema)> {
-        self.table_map
-            .get_by_right(&table_name.into())
+        self.lookup_table_id(table_name.into())
             .and_then(|table_id| {
                 self.tables
                     .get(table_id)
@@ -595,8 +1451,7 @@ impl DatabaseSchema {
         &self,
         table_name: impl Into<Arc<str>>,
     ) -> Option<Arc<TableDefinition>> {
-        self.table_map
-            .get_by_right(&table_name.into())
+        self.lookup_table_id(table_name.into())
             .and_then(|table_id| self.tables.get(table_id).cloned())
     }
 
@@ -608,7 +1463,7 @@ impl DatabaseSchema {
         &self,
         table_name: impl Into<Arc<str>>,
     ) -> Option<(TableId, Arc<TableDefinition>)> {
-        let table_id = self.table_map.get_by_right(&table_name.into())?;
+        let table_id = self.lookup_table_id(table_name.into())?;
         self.tables
             .get(table_id)
             .map(|table_def| (*table_id, Arc::clone(table_def)))
@@ -618,13 +1473,28 @@ impl DatabaseSchema {
         self.tables.keys().cloned().collect()
     }
 
+    /// The names of the tables in the database, leaving out those that are deleted
     pub fn table_names(&self) -> Vec<Arc<str>> {
         self.tables
             .values()
+            .filter(|td| !td.is_deleted())
             .map(|td| Arc::clone(&td.table_name))
             .collect()
     }
 
+    /// The tables that are marked as deleted, but have not been hard deleted yet
+    pub fn deleted_tables(&self) -> impl Iterator<Item = Arc<TableDefinition>> + use<'_> {
+        self.tables
+            .values()
+            .filter(|td| td.is_deleted())
+            .map(Arc::clone)
+    }
+
+    /// The number of columns across all of the tables in the database
+    pub fn column_count(&self) -> usize {
+        self.tables.values().map(|table| table.columns.len()).sum()
+    }
+
     pub fn table_exists(&self, table_id: &TableId) -> bool {
         self.tables.contains_key(table_id)
     }
@@ -634,11 +1504,47 @@ impl DatabaseSchema {
     }
 
     pub fn table_name_to_id(&self, table_name: impl Into<Arc<str>>) -> Option<TableId> {
-        self.table_map.get_by_right(&table_name.into()).copied()
+        self.lookup_table_id(table_name.into()).copied()
+    }
+
+    /// The id of the table with the given name, or, if there is none, with the name as it is
+    /// normalized by the identifier policy of the database, so that tables that were created
+    /// before the policy was set can still be found by their exact names
+    fn lookup_table_id(&self, table_name: Arc<str>) -> Option<&TableId> {
+        self.table_map.get_by_right(&table_name).or_else(|| {
+            match self.identifier_policy.normalize(&table_name) {
+                Cow::Borrowed(_) => None,
+                Cow::Owned(normalized) => self.table_map.get_by_right(normalized.as_str()),
+            }
+        })
     }
 
+    /// The name of a table, including those that are deleted
     pub fn table_id_to_name(&self, table_id: &TableId) -> Option<Arc<str>> {
-        self.table_map.get_by_left(table_id).map(Arc::clone)
+        self.tables
+            .get(table_id)
+            .map(|table_def| Arc::clone(&table_def.table_name))
+    }
+
+    /// The time, in nanoseconds, before which data in this database has expired, relative to
+    /// `now_ns`. Returns `None` if the database has no retention period.
+    pub fn retention_cutoff_ns(&self, now_ns: i64) -> Option<i64> {
+        self.retention_period
+            .map(|period| now_ns.saturating_sub(period.as_nanos().try_into().unwrap_or(i64::MAX)))
+    }
+
+    /// The earliest time, in nanoseconds, that can be written to this database relative to
+    /// `now_ns`. Returns `None` if the database has no past write limit.
+    pub fn earliest_write_time_ns(&self, now_ns: i64) -> Option<i64> {
+        self.past_write_limit
+            .map(|limit| now_ns.saturating_sub(limit.as_nanos().try_into().unwrap_or(i64::MAX)))
+    }
+
+    /// The latest time, in nanoseconds, that can be written to this database relative to
+    /// `now_ns`. Returns `None` if the database has no future write limit.
+    pub fn latest_write_time_ns(&self, now_ns: i64) -> Option<i64> {
+        self.future_write_limit
+            .map(|limit| now_ns.saturating_add(limit.as_nanos().try_into().unwrap_or(i64::MAX)))
     }
 }
 
@@ -651,6 +1557,19 @@ pub struct TableDefinition {
     pub column_map: BiHashMap<ColumnId, Arc<str>>,
     pub series_key: Option<Vec<ColumnId>>,
     pub last_caches: HashMap<Arc<str>, LastCacheDefinition>,
+    pub distinct_caches: HashMap<Arc<str>, DistinctCacheDefinition>,
+    /// The time, in nanoseconds, at which the table was marked as deleted. Deleted tables are
+    /// hidden from queries and writes, but keep their data until they are hard deleted.
+    pub deleted_at_ns: Option<i64>,
+    /// The names that columns had before they were renamed, mapped to the id of the column, or
+    /// to `None` if the column was dropped. Files persisted before the change still have columns
+    /// with these names, so they cannot be used for new columns.
+    pub retired_columns: BTreeMap<Arc<str>, Option<ColumnId>>,
+    /// Key/value pairs that describe the table, e.g., its owner, which have no effect on the
+    /// server
+    pub metadata: BTreeMap<String, String>,
+    /// The values that field columns have in rows that are written without them, instead of null
+    pub column_defaults: BTreeMap<ColumnId, FieldData>,
 }
 
 impl TableDefinition {
@@ -705,6 +1624,11 @@ impl TableDefinition {
             column_map,
             series_key,
             last_caches: HashMap::new(),
+            distinct_caches: HashMap::new(),
+            deleted_at_ns: None,
+            retired_columns: BTreeMap::new(),
+            metadata: BTreeMap::new(),
+            column_defaults: BTreeMap::new(),
         })
     }
 
@@ -800,8 +1724,24 @@ impl TableDefinition {
         if new_fields.is_empty() {
             Ok(None)
         } else {
+            let mut new_tags = new_fields
+                .iter()
+                .filter(|(_, _, data_type)| *data_type == InfluxColumnType::Tag)
+                .map(|(id, _, _)| *id)
+                .collect::<Vec<_>>();
+            new_tags.sort();
             let mut new_table = self.clone();
             new_table.add_columns(new_fields)?;
+            // new tags become key columns of the last caches that take them on automatically:
+            for last_cache in new_table.last_caches.values_mut() {
+                if last_cache.auto_key_columns {
+                    for id in &new_tags {
+                        if !last_cache.key_columns.contains(id) {
+                            last_cache.key_columns.push(*id);
+                        }
+                    }
+                }
+            }
             Ok(Some(new_table))
         }
     }
@@ -832,91 +1772,422 @@ impl TableDefinition {
         }
     }
 
-    /// Check if the column exists in the [`TableDefinition`]
-    pub fn column_exists(&self, column: impl Into<Arc<str>>) -> bool {
-        self.column_map.get_by_right(&column.into()).is_some()
-    }
-
-    /// Add the columns to this [`TableDefinition`]
-    ///
-    /// This ensures that the resulting schema has its columns ordered
-    pub fn add_columns(
-        &mut self,
-        columns: Vec<(ColumnId, Arc<str>, InfluxColumnType)>,
-    ) -> Result<()> {
-        // Use BTree to insert existing and new columns, and use that to generate the
-        // resulting schema, to ensure column order is consistent:
-        let mut cols = BTreeMap::new();
-        for (_, col_def) in self.columns.drain(..) {
-            cols.insert(Arc::clone(&col_def.name), col_def);
-        }
-        for (id, name, column_type) in columns {
-            assert!(
-                cols.insert(
-                    Arc::clone(&name),
-                    // any new column added by this function must be nullable:
-                    ColumnDefinition::new(id, name, column_type, true)
-                )
-                .is_none(),
-                "attempted to add existing column"
-            );
+    pub(crate) fn new_if_last_cache_definition_updates_existing(
+        &self,
+        last_cache_definition: &LastCacheDefinition,
+    ) -> Option<Self> {
+        match self.last_caches.get(&last_cache_definition.name) {
+            Some(existing) if existing != last_cache_definition => {
+                let mut new_table = self.clone();
+                new_table.add_last_cache(last_cache_definition.clone());
+                Some(new_table)
+            }
+            _ => None,
         }
+    }
 
-        // ensure we don't go over the column limit
-        if cols.len() > Catalog::NUM_COLUMNS_PER_TABLE_LIMIT {
-            return Err(Error::TooManyColumns);
+    pub(crate) fn new_if_distinct_cache_definition_is_new(
+        &self,
+        distinct_cache_definition: &DistinctCacheDefinition,
+    ) -> Option<Self> {
+        if self
+            .distinct_caches
+            .contains_key(&distinct_cache_definition.name)
+        {
+            None
+        } else {
+            let mut new_table = self.clone();
+            new_table.add_distinct_cache(distinct_cache_definition.clone());
+            Some(new_table)
         }
+    }
 
-        let mut schema_builder = SchemaBuilder::with_capacity(cols.len());
-        // TODO: may need to capture some schema-level metadata, currently, this causes trouble in
-        // tests, so I am omitting this for now:
-        // schema_builder.measurement(&self.name);
-        for (name, col_def) in &cols {
-            schema_builder.influx_column(name.as_ref(), col_def.data_type);
+    pub(crate) fn new_if_distinct_cache_deletes_existing(
+        &self,
+        distinct_cache_delete: &DistinctCacheDelete,
+    ) -> Option<Self> {
+        if self
+            .distinct_caches
+            .contains_key(&distinct_cache_delete.name)
+        {
+            let mut new_table = self.clone();
+            new_table.remove_distinct_cache(&distinct_cache_delete.name);
+            Some(new_table)
+        } else {
+            None
         }
-        let schema = schema_builder.build().expect("schema should be valid");
-        self.schema = schema;
+    }
 
-        self.columns = cols
-            .into_iter()
-            .inspect(|(_, def)| {
-                self.column_map.insert(def.id, Arc::clone(&def.name));
+    /// Create a copy of this [`TableDefinition`] with a new name, which keeps its id and columns,
+    /// so that data already written to the table is still part of it
+    pub(crate) fn new_with_name(&self, table_name: Arc<str>) -> Self {
+        let columns = self
+            .columns
+            .values()
+            .map(|def| (def.id, Arc::clone(&def.name), def.data_type))
+            .collect();
+        let mut new_table = Self::new(
+            self.table_id,
+            Arc::clone(&table_name),
+            columns,
+            self.series_key.clone(),
+        )
+        .expect("renaming a table does not change its columns");
+        new_table.columns = self.columns.clone();
+        new_table.deleted_at_ns = self.deleted_at_ns;
+        new_table.retired_columns = self.retired_columns.clone();
+        new_table.metadata = self.metadata.clone();
+        new_table.column_defaults = self.column_defaults.clone();
+        new_table.last_caches = self
+            .last_caches
+            .iter()
+            .map(|(cache_name, def)| {
+                let def = LastCacheDefinition {
+                    table: Arc::clone(&table_name),
+                    ..def.clone()
+                };
+                (Arc::clone(cache_name), def)
             })
-            .map(|(_, def)| (def.id, def))
             .collect();
-
-        Ok(())
-    }
-
-    pub fn index_column_ids(&self) -> Vec<ColumnId> {
-        self.columns
+        new_table.distinct_caches = self
+            .distinct_caches
             .iter()
-            .filter_map(|(id, def)| match def.data_type {
-                InfluxColumnType::Tag => Some(*id),
-                InfluxColumnType::Field(_) | InfluxColumnType::Timestamp => None,
+            .map(|(cache_name, def)| {
+                let def = DistinctCacheDefinition {
+                    table: Arc::clone(&table_name),
+                    ..def.clone()
+                };
+                (Arc::clone(cache_name), def)
             })
-            .collect()
-    }
-
-    pub fn influx_schema(&self) -> &Schema {
-        &self.schema
+            .collect();
+        new_table
     }
 
-    pub fn num_columns(&self) -> usize {
-        self.influx_schema().len()
-    }
+    /// Validates that the field column can be dropped, and returns a new [`TableDefinition`]
+    /// without it, or `None` if it was already dropped, e.g., when the WAL is replayed.
+    pub(crate) fn new_if_column_dropped(
+        &self,
+        drop_column: &DropColumnDefinition,
+    ) -> Result<Option<Self>> {
+        let Some(column) = self.columns.get(&drop_column.column_id) else {
+            return Ok(None);
+        };
+        self.check_field_column(column)?;
+        let cache_name = self.last_caches.values().find_map(|cache| {
+            let in_value_columns = match &cache.value_columns {
+                LastCacheValueColumnsDef::Explicit { columns } => columns.contains(&column.id),
+                LastCacheValueColumnsDef::AllNonKeyColumns => false,
+            };
+            let in_aggregates = cache
+                .aggregates
+                .iter()
+                .any(|aggregate| aggregate.column_id == column.id);
+            (cache.key_columns.contains(&column.id) || in_value_columns || in_aggregates)
+                .then(|| Arc::clone(&cache.name))
+        });
+        if let Some(cache_name) = cache_name {
+            return Err(Error::ColumnUsedByLastCache {
+                table_name: Arc::clone(&self.table_name),
+                column_name: Arc::clone(&column.name),
+                cache_name,
+            });
+        }
+        if let Some(cache) = self
+            .distinct_caches
+            .values()
+            .find(|cache| cache.column_ids.contains(&column.id))
+        {
+            return Err(Error::ColumnUsedByDistinctCache {
+                table_name: Arc::clone(&self.table_name),
+                column_name: Arc::clone(&column.name),
+                cache_name: Arc::clone(&cache.name),
+            });
+        }
 
-    pub fn field_type_by_name(&self, name: impl Into<Arc<str>>) -> Option<InfluxColumnType> {
-        self.column_name_to_id(name)
-            .and_then(|id| self.columns.get(&id))
-            .map(|def| def.data_type)
+        let columns = self
+            .columns
+            .values()
+            .filter(|def| def.id != column.id)
+            .map(|def| (def.id, Arc::clone(&def.name), def.data_type))
+            .collect();
+        let mut new_table = self.new_with_columns(columns);
+        for column_id in new_table.retired_columns.values_mut() {
+            if *column_id == Some(column.id) {
+                *column_id = None;
+            }
+        }
+        new_table
+            .retired_columns
+            .insert(Arc::clone(&column.name), None);
+        Ok(Some(new_table))
+    }
+
+    /// Validates that the default value can be set for the field column, and returns a new
+    /// [`TableDefinition`] with it, or `None` if the column already has it, or was dropped, e.g.,
+    /// when the WAL is replayed
+    pub(crate) fn new_if_column_default_changed(
+        &self,
+        column_default: &ColumnDefaultDefinition,
+    ) -> Result<Option<Self>> {
+        let Some(column) = self.columns.get(&column_default.column_id) else {
+            return Ok(None);
+        };
+        if self.column_defaults.get(&column.id) == column_default.default.as_ref() {
+            return Ok(None);
+        }
+        self.check_field_column(column)?;
+
+        let mut new_table = self.clone();
+        match &column_default.default {
+            Some(default) => {
+                if default.column_type() != column.data_type {
+                    return Err(Error::FieldTypeMismatch {
+                        table_name: self.table_name.to_string(),
+                        column_name: column.name.to_string(),
+                        existing: column.data_type,
+                        attempted: default.column_type(),
+                    });
+                }
+                new_table.column_defaults.insert(column.id, default.clone());
+            }
+            None => {
+                new_table.column_defaults.remove(&column.id);
+            }
+        }
+        Ok(Some(new_table))
+    }
+
+    /// Validates that the field column can be renamed, and returns a new [`TableDefinition`]
+    /// with its new name, or `None` if it already has it, or was dropped, e.g., when the WAL is
+    /// replayed. A column can be renamed back to a name that it had before.
+    pub(crate) fn new_if_column_renamed(
+        &self,
+        rename_column: &RenameColumnDefinition,
+    ) -> Result<Option<Self>> {
+        let Some(column) = self.columns.get(&rename_column.column_id) else {
+            return Ok(None);
+        };
+        let new_name = &rename_column.new_column_name;
+        if column.name == *new_name {
+            return Ok(None);
+        }
+        self.check_field_column(column)?;
+        let retired_by_other_column = self
+            .retired_columns
+            .get(new_name)
+            .is_some_and(|column_id| *column_id != Some(column.id));
+        if self.column_exists(Arc::clone(new_name))
+            || retired_by_other_column
+            || new_name.as_ref() == TIME_COLUMN_NAME
+        {
+            return Err(Error::ColumnAlreadyExists {
+                table_name: Arc::clone(&self.table_name),
+                column_name: Arc::clone(new_name),
+            });
+        }
+
+        let columns = self
+            .columns
+            .values()
+            .map(|def| {
+                let name = if def.id == column.id {
+                    Arc::clone(new_name)
+                } else {
+                    Arc::clone(&def.name)
+                };
+                (def.id, name, def.data_type)
+            })
+            .collect();
+        let mut new_table = self.new_with_columns(columns);
+        new_table.retired_columns.remove(new_name);
+        new_table
+            .retired_columns
+            .insert(Arc::clone(&column.name), Some(column.id));
+        Ok(Some(new_table))
+    }
+
+    /// Only field columns can be dropped or renamed, since the series key, and the sort key of
+    /// persisted files, are made of the tags and time
+    fn check_field_column(&self, column: &ColumnDefinition) -> Result<()> {
+        match column.data_type {
+            InfluxColumnType::Field(_) => Ok(()),
+            InfluxColumnType::Tag | InfluxColumnType::Timestamp => Err(Error::NotAFieldColumn {
+                table_name: Arc::clone(&self.table_name),
+                column_name: Arc::clone(&column.name),
+            }),
+        }
+    }
+
+    /// Create a copy of this [`TableDefinition`] with the given columns, which keeps the
+    /// nullability of the columns that it already had
+    fn new_with_columns(&self, columns: Vec<(ColumnId, Arc<str>, InfluxColumnType)>) -> Self {
+        let mut new_table = Self::new(
+            self.table_id,
+            Arc::clone(&self.table_name),
+            columns,
+            self.series_key.clone(),
+        )
+        .expect("dropping or renaming a column does not add columns");
+        for def in new_table.columns.values_mut() {
+            if let Some(existing) = self.columns.get(&def.id) {
+                def.nullable = existing.nullable;
+            }
+        }
+        new_table.last_caches = self.last_caches.clone();
+        new_table.distinct_caches = self.distinct_caches.clone();
+        new_table.deleted_at_ns = self.deleted_at_ns;
+        new_table.retired_columns = self.retired_columns.clone();
+        new_table.metadata = self.metadata.clone();
+        new_table.column_defaults = self
+            .column_defaults
+            .iter()
+            .filter(|(column_id, _)| new_table.columns.contains_key(*column_id))
+            .map(|(column_id, default)| (*column_id, default.clone()))
+            .collect();
+        new_table
+    }
+
+    /// Whether `name` was the name of a column that was dropped or renamed
+    pub fn is_retired_column_name(&self, name: &str) -> bool {
+        self.retired_columns.contains_key(name)
+    }
+
+    /// The names that columns had before they were renamed, along with their current names
+    pub fn renamed_columns(&self) -> impl Iterator<Item = (Arc<str>, Arc<str>)> + use<'_> {
+        self.retired_columns
+            .iter()
+            .filter_map(|(old_name, column_id)| {
+                column_id
+                    .and_then(|id| self.column_id_to_name(&id))
+                    .map(|name| (Arc::clone(old_name), name))
+            })
+    }
+
+    /// The schema to read the files persisted for the table with. Files persisted before a
+    /// column was renamed have the column under its old name, so the schema has a column for
+    /// each of the old names as well, of the same type as the column.
+    pub fn parquet_schema(&self) -> Schema {
+        let mut columns = BTreeMap::new();
+        for def in self.columns.values() {
+            columns.insert(Arc::clone(&def.name), def.data_type);
+        }
+        for (old_name, name) in self.renamed_columns() {
+            let data_type = self
+                .field_type_by_name(name)
+                .expect("renamed column exists");
+            columns.insert(old_name, data_type);
+        }
+        if columns.len() == self.columns.len() {
+            return self.schema.clone();
+        }
+
+        let mut schema_builder = SchemaBuilder::with_capacity(columns.len());
+        schema_builder.measurement(self.table_name.as_ref());
+        for (name, column_type) in &columns {
+            schema_builder.influx_column(name.as_ref(), *column_type);
+        }
+        if let Some(sk) = &self.series_key {
+            schema_builder.with_series_key(sk.iter().map(|id| {
+                self.column_map
+                    .get_by_left(id)
+                    .expect("invalid column id in series key definition")
+            }));
+        }
+        schema_builder.build().expect("schema should be valid")
+    }
+
+    /// Check if the column exists in the [`TableDefinition`]
+    pub fn column_exists(&self, column: impl Into<Arc<str>>) -> bool {
+        self.column_map.get_by_right(&column.into()).is_some()
+    }
+
+    /// Add the columns to this [`TableDefinition`]
+    ///
+    /// This ensures that the resulting schema has its columns ordered
+    pub fn add_columns(
+        &mut self,
+        columns: Vec<(ColumnId, Arc<str>, InfluxColumnType)>,
+    ) -> Result<()> {
+        // Use BTree to insert existing and new columns, and use that to generate the
+        // resulting schema, to ensure column order is consistent:
+        let mut cols = BTreeMap::new();
+        for (_, col_def) in self.columns.drain(..) {
+            cols.insert(Arc::clone(&col_def.name), col_def);
+        }
+        for (id, name, column_type) in columns {
+            assert!(
+                cols.insert(
+                    Arc::clone(&name),
+                    // any new column added by this function must be nullable:
+                    ColumnDefinition::new(id, name, column_type, true)
+                )
+                .is_none(),
+                "attempted to add existing column"
+            );
+        }
+
+        // ensure we don't go over the column limit
+        if cols.len() > Catalog::NUM_COLUMNS_PER_TABLE_LIMIT {
+            return Err(Error::TooManyColumns);
+        }
+
+        let mut schema_builder = SchemaBuilder::with_capacity(cols.len());
+        // TODO: may need to capture some schema-level metadata, currently, this causes trouble in
+        // tests, so I am omitting this for now:
+        // schema_builder.measurement(&self.name);
+        for (name, col_def) in &cols {
+            schema_builder.influx_column(name.as_ref(), col_def.data_type);
+        }
+        let schema = schema_builder.build().expect("schema should be valid");
+        self.schema = schema;
+
+        self.columns = cols
+            .into_iter()
+            .inspect(|(_, def)| {
+                self.column_map.insert(def.id, Arc::clone(&def.name));
+            })
+            .map(|(_, def)| (def.id, def))
+            .collect();
+
+        Ok(())
+    }
+
+    pub fn index_column_ids(&self) -> Vec<ColumnId> {
+        self.columns
+            .iter()
+            .filter_map(|(id, def)| match def.data_type {
+                InfluxColumnType::Tag => Some(*id),
+                InfluxColumnType::Field(_) | InfluxColumnType::Timestamp => None,
+            })
+            .collect()
+    }
+
+    pub fn influx_schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.influx_schema().len()
+    }
+
+    pub fn field_type_by_name(&self, name: impl Into<Arc<str>>) -> Option<InfluxColumnType> {
+        self.column_name_to_id(name)
+            .and_then(|id| self.columns.get(&id))
+            .map(|def| def.data_type)
     }
 
     pub fn is_v3(&self) -> bool {
         self.influx_schema().series_key().is_some()
     }
 
+    /// Whether the table is marked as deleted
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at_ns.is_some()
+    }
+
     /// Add a new last cache to this table definition
+    ///
+    /// This replaces the definition of a cache with the same name, if there is one.
     pub fn add_last_cache(&mut self, last_cache: LastCacheDefinition) {
         self.last_caches
             .insert(Arc::clone(&last_cache.name), last_cache);
@@ -933,6 +2204,25 @@ impl TableDefinition {
             .map(|(name, def)| (Arc::clone(name), def))
     }
 
+    /// Add a new distinct cache to this table definition
+    ///
+    /// This replaces the definition of a cache with the same name, if there is one.
+    pub fn add_distinct_cache(&mut self, distinct_cache: DistinctCacheDefinition) {
+        self.distinct_caches
+            .insert(Arc::clone(&distinct_cache.name), distinct_cache);
+    }
+
+    /// Remove a distinct cache from the table definition
+    pub fn remove_distinct_cache(&mut self, name: &str) {
+        self.distinct_caches.remove(name);
+    }
+
+    pub fn distinct_caches(&self) -> impl Iterator<Item = (Arc<str>, &DistinctCacheDefinition)> {
+        self.distinct_caches
+            .iter()
+            .map(|(name, def)| (Arc::clone(name), def))
+    }
+
     pub fn column_name_to_id(&self, name: impl Into<Arc<str>>) -> Option<ColumnId> {
         self.column_map.get_by_right(&name.into()).copied()
     }
@@ -1002,7 +2292,7 @@ pub fn influx_column_type_from_field_value(fv: &FieldValue<'_>) -> InfluxColumnT
 
 #[cfg(test)]
 mod tests {
-    use influxdb3_wal::{create, FieldDataType};
+    use influxdb3_wal::{create, ColumnNamePolicy, DownsamplingAggregate, FieldDataType};
     use pretty_assertions::assert_eq;
     use test_helpers::assert_contains;
 
@@ -1026,6 +2316,20 @@ mod tests {
                 map.insert(TableId::from(2), "test_table_2".into());
                 map
             },
+            retention_period: None,
+            future_write_limit: None,
+            past_write_limit: None,
+            schema_limits: SchemaLimits::default(),
+            cardinality_limits: CardinalityLimits::default(),
+            schema_mode: SchemaMode::default(),
+            field_coercion: FieldCoercion::default(),
+            compatibility_mode: CompatibilityMode::default(),
+            series_key_policy: SeriesKeyPolicy::default(),
+            identifier_policy: IdentifierPolicy::default(),
+            write_rate_limit: WriteRateLimit::default(),
+            gen1_duration: None,
+            metadata: Default::default(),
+            downsampling_tasks: Default::default(),
         };
         use InfluxColumnType::*;
         use InfluxFieldType::*;
@@ -1223,6 +2527,20 @@ mod tests {
             name: "test".into(),
             tables: SerdeVecMap::new(),
             table_map: BiHashMap::new(),
+            retention_period: None,
+            future_write_limit: None,
+            past_write_limit: None,
+            schema_limits: SchemaLimits::default(),
+            cardinality_limits: CardinalityLimits::default(),
+            schema_mode: SchemaMode::default(),
+            field_coercion: FieldCoercion::default(),
+            compatibility_mode: CompatibilityMode::default(),
+            series_key_policy: SeriesKeyPolicy::default(),
+            identifier_policy: IdentifierPolicy::default(),
+            write_rate_limit: WriteRateLimit::default(),
+            gen1_duration: None,
+            metadata: Default::default(),
+            downsampling_tasks: Default::default(),
         };
         database.tables.insert(
             TableId::from(0),
@@ -1279,6 +2597,20 @@ mod tests {
                 map.insert(TableId::from(1), "test_table_1".into());
                 map
             },
+            retention_period: None,
+            future_write_limit: None,
+            past_write_limit: None,
+            schema_limits: SchemaLimits::default(),
+            cardinality_limits: CardinalityLimits::default(),
+            schema_mode: SchemaMode::default(),
+            field_coercion: FieldCoercion::default(),
+            compatibility_mode: CompatibilityMode::default(),
+            series_key_policy: SeriesKeyPolicy::default(),
+            identifier_policy: IdentifierPolicy::default(),
+            write_rate_limit: WriteRateLimit::default(),
+            gen1_duration: None,
+            metadata: Default::default(),
+            downsampling_tasks: Default::default(),
         };
         use InfluxColumnType::*;
         use InfluxFieldType::*;
@@ -1337,6 +2669,20 @@ mod tests {
                 map.insert(TableId::from(0), "test".into());
                 map
             },
+            retention_period: None,
+            future_write_limit: None,
+            past_write_limit: None,
+            schema_limits: SchemaLimits::default(),
+            cardinality_limits: CardinalityLimits::default(),
+            schema_mode: SchemaMode::default(),
+            field_coercion: FieldCoercion::default(),
+            compatibility_mode: CompatibilityMode::default(),
+            series_key_policy: SeriesKeyPolicy::default(),
+            identifier_policy: IdentifierPolicy::default(),
+            write_rate_limit: WriteRateLimit::default(),
+            gen1_duration: None,
+            metadata: Default::default(),
+            downsampling_tasks: Default::default(),
         };
         use InfluxColumnType::*;
         use InfluxFieldType::*;
@@ -1425,4 +2771,868 @@ mod tests {
             .expect_err("should fail to apply AddFields operation for non-existent table");
         assert_contains!(err.to_string(), "Table banana not in DB schema for foo");
     }
+
+    #[test]
+    fn apply_catalog_batch_sets_retention_period() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        catalog.insert_database(DatabaseSchema::new(DbId::new(), Arc::from("foo")));
+        let db_id = catalog.db_name_to_id("foo").unwrap();
+        let sequence = catalog.sequence_number();
+
+        let catalog_batch = create::catalog_batch_op(
+            db_id,
+            "foo",
+            0,
+            [create::set_retention_period_op(
+                db_id,
+                "foo",
+                Some(Duration::from_secs(3600)),
+            )],
+        );
+        catalog
+            .apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+            .unwrap();
+        let db = catalog.db_schema_by_id(&db_id).unwrap();
+        assert_eq!(Some(Duration::from_secs(3600)), db.retention_period);
+        assert_eq!(sequence.next(), catalog.sequence_number());
+
+        // applying the same batch again is a no-op:
+        catalog
+            .apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+            .unwrap();
+        assert_eq!(sequence.next(), catalog.sequence_number());
+
+        // the retention period survives a serialization round trip:
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        assert_eq!(
+            Some(Duration::from_secs(3600)),
+            deserialized
+                .db_schema_by_id(&db_id)
+                .unwrap()
+                .retention_period
+        );
+
+        // clearing the retention period:
+        catalog
+            .apply_catalog_batch(
+                create::catalog_batch_op(
+                    db_id,
+                    "foo",
+                    0,
+                    [create::set_retention_period_op(db_id, "foo", None)],
+                )
+                .as_catalog()
+                .unwrap(),
+            )
+            .unwrap();
+        assert!(catalog
+            .db_schema_by_id(&db_id)
+            .unwrap()
+            .retention_period
+            .is_none());
+    }
+
+    #[test]
+    fn apply_catalog_batch_sets_write_time_limits() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        catalog.insert_database(DatabaseSchema::new(DbId::new(), Arc::from("foo")));
+        let db_id = catalog.db_name_to_id("foo").unwrap();
+
+        let catalog_batch = create::catalog_batch_op(
+            db_id,
+            "foo",
+            0,
+            [create::set_write_time_limits_op(
+                db_id,
+                "foo",
+                Some(Duration::from_secs(60)),
+                Some(Duration::from_secs(3600)),
+            )],
+        );
+        catalog
+            .apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+            .unwrap();
+        let db = catalog.db_schema_by_id(&db_id).unwrap();
+        assert_eq!(Some(60_000_000_000), db.latest_write_time_ns(0));
+        assert_eq!(Some(-3_600_000_000_000), db.earliest_write_time_ns(0));
+
+        // the limits survive a serialization round trip:
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        let db = deserialized.db_schema_by_id(&db_id).unwrap();
+        assert_eq!(Some(Duration::from_secs(60)), db.future_write_limit);
+        assert_eq!(Some(Duration::from_secs(3600)), db.past_write_limit);
+
+        // clearing the limits:
+        catalog
+            .apply_catalog_batch(
+                create::catalog_batch_op(
+                    db_id,
+                    "foo",
+                    0,
+                    [create::set_write_time_limits_op(db_id, "foo", None, None)],
+                )
+                .as_catalog()
+                .unwrap(),
+            )
+            .unwrap();
+        let db = catalog.db_schema_by_id(&db_id).unwrap();
+        assert!(db.latest_write_time_ns(0).is_none());
+        assert!(db.earliest_write_time_ns(0).is_none());
+    }
+
+    #[test]
+    fn apply_catalog_batch_sets_schema_limits() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        catalog.insert_database(DatabaseSchema::new(DbId::new(), Arc::from("foo")));
+        let db_id = catalog.db_name_to_id("foo").unwrap();
+        let schema_limits = SchemaLimits {
+            max_columns_per_table: Some(10),
+            max_column_name_length: Some(32),
+            column_name_policy: ColumnNamePolicy::Printable,
+        };
+
+        let catalog_batch = create::catalog_batch_op(
+            db_id,
+            "foo",
+            0,
+            [create::set_schema_limits_op(db_id, "foo", schema_limits)],
+        );
+        catalog
+            .apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+            .unwrap();
+        assert_eq!(
+            schema_limits,
+            catalog.db_schema_by_id(&db_id).unwrap().schema_limits
+        );
+
+        // the limits survive a serialization round trip:
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        assert_eq!(
+            schema_limits,
+            deserialized.db_schema_by_id(&db_id).unwrap().schema_limits
+        );
+    }
+
+    #[test]
+    fn apply_catalog_batch_sets_cardinality_limits() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        catalog.insert_database(DatabaseSchema::new(DbId::new(), Arc::from("foo")));
+        let db_id = catalog.db_name_to_id("foo").unwrap();
+        let cardinality_limits = CardinalityLimits {
+            max_tables: Some(10),
+            max_series_per_table: Some(1_000),
+        };
+
+        let catalog_batch = create::catalog_batch_op(
+            db_id,
+            "foo",
+            0,
+            [create::set_cardinality_limits_op(
+                db_id,
+                "foo",
+                cardinality_limits,
+            )],
+        );
+        catalog
+            .apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+            .unwrap();
+        assert_eq!(
+            cardinality_limits,
+            catalog.db_schema_by_id(&db_id).unwrap().cardinality_limits
+        );
+
+        // the limits survive a serialization round trip:
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        assert_eq!(
+            cardinality_limits,
+            deserialized
+                .db_schema_by_id(&db_id)
+                .unwrap()
+                .cardinality_limits
+        );
+    }
+
+    #[test]
+    fn apply_catalog_batch_sets_schema_mode() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        catalog.insert_database(DatabaseSchema::new(DbId::new(), Arc::from("foo")));
+        let db_id = catalog.db_name_to_id("foo").unwrap();
+        assert_eq!(
+            SchemaMode::Auto,
+            catalog.db_schema_by_id(&db_id).unwrap().schema_mode
+        );
+
+        let catalog_batch = create::catalog_batch_op(
+            db_id,
+            "foo",
+            0,
+            [create::set_schema_mode_op(
+                db_id,
+                "foo",
+                SchemaMode::TagLocked,
+            )],
+        );
+        catalog
+            .apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+            .unwrap();
+        assert_eq!(
+            SchemaMode::TagLocked,
+            catalog.db_schema_by_id(&db_id).unwrap().schema_mode
+        );
+
+        // the mode survives a serialization round trip:
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        assert_contains!(serialized, "tag-locked");
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        assert_eq!(
+            SchemaMode::TagLocked,
+            deserialized.db_schema_by_id(&db_id).unwrap().schema_mode
+        );
+    }
+
+    #[test]
+    fn apply_catalog_batch_sets_field_coercion() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        catalog.insert_database(DatabaseSchema::new(DbId::new(), Arc::from("foo")));
+        let db_id = catalog.db_name_to_id("foo").unwrap();
+        assert_eq!(
+            FieldCoercion::Off,
+            catalog.db_schema_by_id(&db_id).unwrap().field_coercion
+        );
+
+        let catalog_batch = create::catalog_batch_op(
+            db_id,
+            "foo",
+            0,
+            [create::set_field_coercion_op(
+                db_id,
+                "foo",
+                FieldCoercion::WidenNumeric,
+            )],
+        );
+        catalog
+            .apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+            .unwrap();
+        assert_eq!(
+            FieldCoercion::WidenNumeric,
+            catalog.db_schema_by_id(&db_id).unwrap().field_coercion
+        );
+
+        // the policy survives a serialization round trip:
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        assert_contains!(serialized, "widen-numeric");
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        assert_eq!(
+            FieldCoercion::WidenNumeric,
+            deserialized.db_schema_by_id(&db_id).unwrap().field_coercion
+        );
+    }
+
+    #[test]
+    fn apply_catalog_batch_sets_write_rate_limit() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        catalog.insert_database(DatabaseSchema::new(DbId::new(), Arc::from("foo")));
+        let db_id = catalog.db_name_to_id("foo").unwrap();
+        assert!(catalog
+            .db_schema_by_id(&db_id)
+            .unwrap()
+            .write_rate_limit
+            .is_unlimited());
+
+        let write_rate_limit = WriteRateLimit {
+            lines_per_second: Some(1_000),
+            bytes_per_second: None,
+        };
+        let catalog_batch = create::catalog_batch_op(
+            db_id,
+            "foo",
+            0,
+            [create::set_write_rate_limit_op(
+                db_id,
+                "foo",
+                write_rate_limit,
+            )],
+        );
+        catalog
+            .apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+            .unwrap();
+        assert_eq!(
+            write_rate_limit,
+            catalog.db_schema_by_id(&db_id).unwrap().write_rate_limit
+        );
+
+        // the limit survives a serialization round trip:
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        assert_eq!(
+            write_rate_limit,
+            deserialized
+                .db_schema_by_id(&db_id)
+                .unwrap()
+                .write_rate_limit
+        );
+    }
+
+    #[test]
+    fn apply_catalog_batch_sets_gen1_duration() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        catalog.insert_database(DatabaseSchema::new(DbId::new(), Arc::from("foo")));
+        let db_id = catalog.db_name_to_id("foo").unwrap();
+        assert_eq!(None, catalog.db_schema_by_id(&db_id).unwrap().gen1_duration);
+
+        let gen1_duration = Some(Gen1Duration::new_1m());
+        let catalog_batch = create::catalog_batch_op(
+            db_id,
+            "foo",
+            0,
+            [create::set_gen1_duration_op(db_id, "foo", gen1_duration)],
+        );
+        catalog
+            .apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+            .unwrap();
+        assert_eq!(
+            gen1_duration,
+            catalog.db_schema_by_id(&db_id).unwrap().gen1_duration
+        );
+
+        // the duration survives a serialization round trip:
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        assert_eq!(
+            gen1_duration,
+            deserialized.db_schema_by_id(&db_id).unwrap().gen1_duration
+        );
+    }
+
+    #[test]
+    fn apply_catalog_batch_renames_database_and_table() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        catalog.insert_database(DatabaseSchema::new(DbId::new(), Arc::from("bar")));
+        let db_id = DbId::new();
+        let (cpu_id, mem_id) = (TableId::new(), TableId::new());
+        let fields = || {
+            [create::field_def(
+                ColumnId::new(),
+                "time",
+                FieldDataType::Timestamp,
+            )]
+        };
+        catalog
+            .apply_catalog_batch(
+                create::catalog_batch_op(
+                    db_id,
+                    "foo",
+                    0,
+                    [
+                        create::create_table_op(db_id, "foo", cpu_id, "cpu", fields()),
+                        create::create_table_op(db_id, "foo", mem_id, "mem", fields()),
+                    ],
+                )
+                .as_catalog()
+                .unwrap(),
+            )
+            .unwrap();
+
+        // names that are in use cannot be taken:
+        for op in [
+            create::rename_database_op(db_id, "foo", "bar"),
+            create::rename_table_op(db_id, "foo", cpu_id, "cpu", "mem"),
+        ] {
+            let catalog_batch = create::catalog_batch_op(db_id, "foo", 0, [op]);
+            assert!(catalog
+                .apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+                .is_err());
+        }
+
+        // tables can swap names within a batch, and the ids are kept:
+        let catalog_batch = create::catalog_batch_op(
+            db_id,
+            "foo",
+            0,
+            [
+                create::rename_database_op(db_id, "foo", "baz"),
+                create::rename_table_op(db_id, "foo", cpu_id, "cpu", "tmp"),
+                create::rename_table_op(db_id, "foo", mem_id, "mem", "cpu"),
+                create::rename_table_op(db_id, "foo", cpu_id, "tmp", "mem"),
+            ],
+        );
+        catalog
+            .apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+            .unwrap();
+        assert_eq!(None, catalog.db_name_to_id("foo"));
+        assert_eq!(Some(db_id), catalog.db_name_to_id("baz"));
+        let db_schema = catalog.db_schema("baz").unwrap();
+        assert_eq!(Some(mem_id), db_schema.table_name_to_id("cpu"));
+        assert_eq!(Some(cpu_id), db_schema.table_name_to_id("mem"));
+        assert_eq!(
+            "mem",
+            db_schema
+                .table_definition_by_id(&cpu_id)
+                .unwrap()
+                .table_name
+                .as_ref()
+        );
+
+        // the new names survive a serialization round trip:
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        assert_eq!(Some(db_id), deserialized.db_name_to_id("baz"));
+        assert_eq!(
+            Some(mem_id),
+            deserialized
+                .db_schema("baz")
+                .unwrap()
+                .table_name_to_id("cpu")
+        );
+    }
+
+    #[test]
+    fn apply_catalog_batch_deletes_and_undeletes_tables() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        let db_id = DbId::new();
+        let (old_id, new_id) = (TableId::new(), TableId::new());
+        let apply = |time_ns: i64, op: CatalogOp| {
+            catalog.apply_catalog_batch(
+                create::catalog_batch_op(db_id, "foo", time_ns, [op])
+                    .as_catalog()
+                    .unwrap(),
+            )
+        };
+        let create_table = |table_id: TableId| {
+            create::create_table_op(
+                db_id,
+                "foo",
+                table_id,
+                "cpu",
+                [create::field_def(
+                    ColumnId::new(),
+                    "time",
+                    FieldDataType::Timestamp,
+                )],
+            )
+        };
+        apply(0, create_table(old_id)).unwrap();
+
+        // a deleted table cannot be found by name, so a new table can take its name:
+        apply(
+            10,
+            create::mark_table_deleted_op(db_id, "foo", old_id, "cpu"),
+        )
+        .unwrap();
+        let db_schema = catalog.db_schema("foo").unwrap();
+        assert!(db_schema.table_definition("cpu").is_none());
+        assert!(db_schema.table_names().is_empty());
+        assert_eq!(
+            Some(10),
+            db_schema
+                .table_definition_by_id(&old_id)
+                .unwrap()
+                .deleted_at_ns
+        );
+        apply(20, create_table(new_id)).unwrap();
+        assert!(matches!(
+            apply(30, create::undelete_table_op(db_id, "foo", old_id, "cpu")),
+            Err(Error::TableAlreadyExists { .. })
+        ));
+
+        apply(
+            30,
+            create::mark_table_deleted_op(db_id, "foo", new_id, "cpu"),
+        )
+        .unwrap();
+        apply(40, create::undelete_table_op(db_id, "foo", old_id, "cpu")).unwrap();
+        let db_schema = catalog.db_schema("foo").unwrap();
+        assert_eq!(Some(old_id), db_schema.table_name_to_id("cpu"));
+        assert_eq!(
+            vec![new_id],
+            db_schema
+                .deleted_tables()
+                .map(|table_def| table_def.table_id)
+                .collect::<Vec<_>>()
+        );
+
+        // the deletion survives a serialization round trip:
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        assert_eq!(
+            db_schema.as_ref(),
+            deserialized.db_schema("foo").unwrap().as_ref()
+        );
+
+        apply(
+            50,
+            create::hard_delete_table_op(db_id, "foo", new_id, "cpu"),
+        )
+        .unwrap();
+        let db_schema = catalog.db_schema("foo").unwrap();
+        assert!(db_schema.table_definition_by_id(&new_id).is_none());
+        assert_eq!(Some(old_id), db_schema.table_name_to_id("cpu"));
+    }
+
+    #[test]
+    fn apply_catalog_batch_drops_and_renames_columns() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        let db_id = DbId::new();
+        let table_id = TableId::new();
+        let (host_id, usage_id, idle_id) = (ColumnId::new(), ColumnId::new(), ColumnId::new());
+        let apply = |op: CatalogOp| {
+            let catalog_batch = create::catalog_batch_op(db_id, "foo", 0, [op]);
+            catalog.apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+        };
+        let rename = |column_id: ColumnId, column_name: &str, new_column_name: &str| {
+            create::rename_column_op(
+                db_id,
+                "foo",
+                table_id,
+                "cpu",
+                column_id,
+                column_name,
+                new_column_name,
+            )
+        };
+        apply(create::create_table_op(
+            db_id,
+            "foo",
+            table_id,
+            "cpu",
+            [
+                create::field_def(host_id, "host", FieldDataType::Tag),
+                create::field_def(usage_id, "usage", FieldDataType::Float),
+                create::field_def(idle_id, "idle", FieldDataType::Float),
+                create::field_def(ColumnId::new(), "time", FieldDataType::Timestamp),
+            ],
+        ))
+        .unwrap();
+
+        apply(rename(usage_id, "usage", "cpu_usage")).unwrap();
+        let table_def = catalog
+            .db_schema("foo")
+            .unwrap()
+            .table_definition("cpu")
+            .unwrap();
+        assert_eq!(Some(usage_id), table_def.column_name_to_id("cpu_usage"));
+        assert!(!table_def.column_exists("usage"));
+        assert!(table_def.is_retired_column_name("usage"));
+        assert_eq!(
+            vec![(Arc::from("usage"), Arc::from("cpu_usage"))],
+            table_def.renamed_columns().collect::<Vec<_>>()
+        );
+        // files persisted before the rename are read with the old name as well:
+        let parquet_schema = table_def.parquet_schema().as_arrow();
+        assert!(parquet_schema.index_of("usage").is_ok());
+        assert!(parquet_schema.index_of("cpu_usage").is_ok());
+        assert!(table_def.schema.as_arrow().index_of("usage").is_err());
+
+        // only fields can be renamed, and not to a name that is in use:
+        assert!(matches!(
+            apply(rename(host_id, "host", "hostname")),
+            Err(Error::NotAFieldColumn { .. })
+        ));
+        assert!(matches!(
+            apply(rename(usage_id, "cpu_usage", "idle")),
+            Err(Error::ColumnAlreadyExists { .. })
+        ));
+
+        // the name of a dropped column cannot be taken either:
+        apply(create::drop_column_op(
+            db_id, "foo", table_id, "cpu", idle_id, "idle",
+        ))
+        .unwrap();
+        let table_def = catalog
+            .db_schema("foo")
+            .unwrap()
+            .table_definition("cpu")
+            .unwrap();
+        assert!(!table_def.column_exists("idle"));
+        assert_eq!(Some(&None), table_def.retired_columns.get("idle"));
+        assert!(matches!(
+            apply(rename(usage_id, "cpu_usage", "idle")),
+            Err(Error::ColumnAlreadyExists { .. })
+        ));
+
+        // a column can be renamed back to a name that it had:
+        apply(rename(usage_id, "cpu_usage", "usage")).unwrap();
+        let table_def = catalog
+            .db_schema("foo")
+            .unwrap()
+            .table_definition("cpu")
+            .unwrap();
+        assert_eq!(Some(usage_id), table_def.column_name_to_id("usage"));
+        assert_eq!(
+            vec![(Arc::from("cpu_usage"), Arc::from("usage"))],
+            table_def.renamed_columns().collect::<Vec<_>>()
+        );
+
+        // the retired names survive a serialization round trip:
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        let deserialized_table_def = deserialized
+            .db_schema("foo")
+            .unwrap()
+            .table_definition("cpu")
+            .unwrap();
+        assert_eq!(
+            table_def.retired_columns,
+            deserialized_table_def.retired_columns
+        );
+    }
+
+    #[test]
+    fn apply_catalog_batch_sets_metadata() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        let db_id = DbId::new();
+        let table_id = TableId::new();
+        let apply = |op: CatalogOp| {
+            let catalog_batch = create::catalog_batch_op(db_id, "foo", 0, [op]);
+            catalog.apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+        };
+        apply(create::create_table_op(
+            db_id,
+            "foo",
+            table_id,
+            "cpu",
+            [create::field_def(
+                ColumnId::new(),
+                "time",
+                FieldDataType::Timestamp,
+            )],
+        ))
+        .unwrap();
+
+        apply(create::set_database_metadata_op(
+            db_id,
+            "foo",
+            [("owner", "team-a")],
+        ))
+        .unwrap();
+        apply(create::set_table_metadata_op(
+            db_id,
+            "foo",
+            table_id,
+            "cpu",
+            [("owner", "team-b"), ("sensitivity", "low")],
+        ))
+        .unwrap();
+        let sequence_number = catalog.sequence_number();
+        // setting the same metadata again does not change the catalog:
+        apply(create::set_database_metadata_op(
+            db_id,
+            "foo",
+            [("owner", "team-a")],
+        ))
+        .unwrap();
+        assert_eq!(sequence_number, catalog.sequence_number());
+        assert!(matches!(
+            apply(create::set_table_metadata_op(
+                db_id,
+                "foo",
+                TableId::new(),
+                "mem",
+                [("owner", "team-b")],
+            )),
+            Err(Error::TableNotFound { .. })
+        ));
+
+        // the metadata is kept when the table is changed, and survives a serialization round trip:
+        apply(create::rename_table_op(
+            db_id, "foo", table_id, "cpu", "cpu2",
+        ))
+        .unwrap();
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        for catalog in [&catalog, &deserialized] {
+            let db_schema = catalog.db_schema("foo").unwrap();
+            assert_eq!(
+                BTreeMap::from([("owner".to_string(), "team-a".to_string())]),
+                db_schema.metadata
+            );
+            assert_eq!(
+                BTreeMap::from([
+                    ("owner".to_string(), "team-b".to_string()),
+                    ("sensitivity".to_string(), "low".to_string()),
+                ]),
+                db_schema.table_definition("cpu2").unwrap().metadata
+            );
+        }
+    }
+
+    #[test]
+    fn apply_catalog_batch_creates_downsampling_tasks() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        let db_id = DbId::new();
+        let table_id = TableId::new();
+        let apply = |op: CatalogOp| {
+            let catalog_batch = create::catalog_batch_op(db_id, "foo", 0, [op]);
+            catalog.apply_catalog_batch(catalog_batch.as_catalog().unwrap())
+        };
+        apply(create::create_table_op(
+            db_id,
+            "foo",
+            table_id,
+            "cpu",
+            [create::field_def(
+                ColumnId::new(),
+                "time",
+                FieldDataType::Timestamp,
+            )],
+        ))
+        .unwrap();
+
+        let task = DownsamplingTask {
+            name: "cpu_1m".into(),
+            source_table_id: table_id,
+            target_table_name: "cpu_1m".into(),
+            interval: Duration::from_secs(60),
+            aggregate: DownsamplingAggregate::Mean,
+        };
+        apply(create::create_downsampling_task_op(
+            db_id,
+            "foo",
+            "cpu",
+            task.clone(),
+        ))
+        .unwrap();
+        let sequence_number = catalog.sequence_number();
+        // creating the same task again does not change the catalog:
+        apply(create::create_downsampling_task_op(
+            db_id,
+            "foo",
+            "cpu",
+            task.clone(),
+        ))
+        .unwrap();
+        assert_eq!(sequence_number, catalog.sequence_number());
+        assert!(matches!(
+            apply(create::create_downsampling_task_op(
+                db_id,
+                "foo",
+                "mem",
+                DownsamplingTask {
+                    name: "mem_1m".into(),
+                    source_table_id: TableId::new(),
+                    ..task.clone()
+                },
+            )),
+            Err(Error::TableNotFound { .. })
+        ));
+
+        // the task survives a serialization round trip:
+        let serialized = serde_json::to_string(&catalog).unwrap();
+        let deserialized_inner: InnerCatalog = serde_json::from_str(&serialized).unwrap();
+        let deserialized = Catalog::from_inner(deserialized_inner);
+        for catalog in [&catalog, &deserialized] {
+            assert_eq!(
+                BTreeMap::from([(Arc::from("cpu_1m"), task.clone())]),
+                catalog.db_schema("foo").unwrap().downsampling_tasks
+            );
+        }
+
+        // tasks are deleted, and so are those of a table that is hard deleted:
+        apply(create::delete_downsampling_task_op(db_id, "foo", "cpu_1m")).unwrap();
+        assert!(catalog
+            .db_schema("foo")
+            .unwrap()
+            .downsampling_tasks
+            .is_empty());
+        apply(create::create_downsampling_task_op(
+            db_id, "foo", "cpu", task,
+        ))
+        .unwrap();
+        apply(create::hard_delete_table_op(db_id, "foo", table_id, "cpu")).unwrap();
+        assert!(catalog
+            .db_schema("foo")
+            .unwrap()
+            .downsampling_tasks
+            .is_empty());
+    }
+
+    #[test]
+    fn catalog_limits() {
+        let catalog = Catalog::new(Arc::from("host"), Arc::from("instance"));
+        catalog.set_limits(CatalogLimits {
+            max_databases: 2,
+            max_tables: 3,
+            max_tables_per_db: Some(2),
+            max_columns: 5,
+        });
+        let create_table = |db_name: &str, table_name: &str| {
+            let db_id = catalog
+                .db_name_to_id(db_name)
+                .unwrap_or_else(|| catalog.db_or_create(db_name).unwrap().id);
+            create::catalog_batch_op(
+                db_id,
+                db_name,
+                0,
+                [create::create_table_op(
+                    db_id,
+                    db_name,
+                    TableId::new(),
+                    table_name,
+                    [
+                        create::field_def(ColumnId::new(), "usage", FieldDataType::Float),
+                        create::field_def(ColumnId::new(), "time", FieldDataType::Timestamp),
+                    ],
+                )],
+            )
+        };
+
+        for table_name in ["t1", "t2"] {
+            let op = create_table("a", table_name);
+            catalog
+                .apply_catalog_batch(op.as_catalog().unwrap())
+                .unwrap();
+        }
+        let op = create_table("a", "t3");
+        assert!(matches!(
+            catalog.apply_catalog_batch(op.as_catalog().unwrap()),
+            Err(Error::TooManyTablesInDb { limit: 2, .. })
+        ));
+        let op = create_table("b", "t1");
+        assert!(matches!(
+            catalog.validate_catalog_batch(op.as_catalog().unwrap()),
+            Err(Error::TooManyColumnsInCatalog(5))
+        ));
+        assert!(matches!(
+            catalog.apply_catalog_batch(op.as_catalog().unwrap()),
+            Err(Error::TooManyColumnsInCatalog(5))
+        ));
+        assert!(matches!(
+            catalog.db_or_create("c"),
+            Err(Error::TooManyDbs(2))
+        ));
+
+        assert_eq!(
+            CatalogUsage {
+                databases: 2,
+                tables: 2,
+                columns: 4,
+                tables_per_db: BTreeMap::from([(Arc::from("a"), 2), (Arc::from("b"), 0)]),
+                limits: catalog.limits(),
+            },
+            catalog.usage()
+        );
+
+        // batches replayed from the WAL were within the limits when they were written, which may
+        // have been lowered since:
+        catalog
+            .replay_catalog_batch(op.as_catalog().unwrap())
+            .unwrap();
+        assert_eq!(6, catalog.usage().columns);
+    }
 }
@@ -1,5 +1,6 @@
 use crate::catalog::ColumnDefinition;
 use crate::catalog::DatabaseSchema;
+use crate::catalog::NextIds;
 use crate::catalog::TableDefinition;
 use arrow::datatypes::DataType as ArrowDataType;
 use bimap::BiHashMap;
@@ -7,12 +8,19 @@ use influxdb3_id::ColumnId;
 use influxdb3_id::DbId;
 use influxdb3_id::SerdeVecMap;
 use influxdb3_id::TableId;
-use influxdb3_wal::{LastCacheDefinition, LastCacheValueColumnsDef};
+use influxdb3_wal::{
+    CardinalityLimits, CompatibilityMode, DistinctCacheDefinition, DownsamplingTask, FieldCoercion,
+    FieldData, Gen1Duration, IdentifierPolicy, LastCacheAggregate, LastCacheDefinition,
+    LastCacheValueColumnsDef, SchemaLimits, SchemaMode, SeriesKeyPolicy, WriteRateLimit,
+};
 use schema::InfluxColumnType;
 use schema::InfluxFieldType;
 use schema::TIME_DATA_TIMEZONE;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 impl Serialize for DatabaseSchema {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -33,11 +41,64 @@ impl<'de> Deserialize<'de> for DatabaseSchema {
     }
 }
 
+impl DatabaseSchema {
+    /// A copy of the database with new ids for it, and for its tables and columns, taken from
+    /// `next_ids`, e.g., to import it into the catalog of another host
+    pub(crate) fn with_new_ids(&self, next_ids: &mut NextIds) -> Self {
+        let mut snapshot = DatabaseSnapshot::from(self);
+        snapshot.id = next_ids.take_db_id();
+        let mut table_ids = HashMap::with_capacity(snapshot.tables.len());
+        snapshot.tables = snapshot
+            .tables
+            .into_iter()
+            .map(|(id, table)| {
+                let table = table.with_new_ids(next_ids);
+                table_ids.insert(id, table.table_id);
+                (table.table_id, table)
+            })
+            .collect();
+        for task in snapshot.downsampling_tasks.values_mut() {
+            task.source_table_id = *table_ids
+                .get(&task.source_table_id)
+                .expect("serialized catalog should only refer to tables in the database");
+        }
+        snapshot.into()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct DatabaseSnapshot {
     id: DbId,
     name: Arc<str>,
     tables: SerdeVecMap<TableId, TableSnapshot>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retention_period: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    future_write_limit: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    past_write_limit: Option<Duration>,
+    #[serde(default, skip_serializing_if = "SchemaLimits::is_unlimited")]
+    schema_limits: SchemaLimits,
+    #[serde(default, skip_serializing_if = "CardinalityLimits::is_unlimited")]
+    cardinality_limits: CardinalityLimits,
+    #[serde(default, skip_serializing_if = "SchemaMode::is_auto")]
+    schema_mode: SchemaMode,
+    #[serde(default, skip_serializing_if = "FieldCoercion::is_off")]
+    field_coercion: FieldCoercion,
+    #[serde(default, skip_serializing_if = "CompatibilityMode::is_off")]
+    compatibility_mode: CompatibilityMode,
+    #[serde(default, skip_serializing_if = "SeriesKeyPolicy::is_reject")]
+    series_key_policy: SeriesKeyPolicy,
+    #[serde(default, skip_serializing_if = "IdentifierPolicy::is_preserve")]
+    identifier_policy: IdentifierPolicy,
+    #[serde(default, skip_serializing_if = "WriteRateLimit::is_unlimited")]
+    write_rate_limit: WriteRateLimit,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gen1_duration: Option<Gen1Duration>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    downsampling_tasks: BTreeMap<Arc<str>, DownsamplingTask>,
 }
 
 impl From<&DatabaseSchema> for DatabaseSnapshot {
@@ -50,6 +111,20 @@ impl From<&DatabaseSchema> for DatabaseSnapshot {
                 .iter()
                 .map(|(table_id, table_def)| (*table_id, table_def.as_ref().into()))
                 .collect(),
+            retention_period: db.retention_period,
+            future_write_limit: db.future_write_limit,
+            past_write_limit: db.past_write_limit,
+            schema_limits: db.schema_limits,
+            cardinality_limits: db.cardinality_limits,
+            schema_mode: db.schema_mode,
+            field_coercion: db.field_coercion,
+            compatibility_mode: db.compatibility_mode,
+            series_key_policy: db.series_key_policy,
+            identifier_policy: db.identifier_policy,
+            write_rate_limit: db.write_rate_limit,
+            gen1_duration: db.gen1_duration,
+            metadata: db.metadata.clone(),
+            downsampling_tasks: db.downsampling_tasks.clone(),
         }
     }
 }
@@ -61,7 +136,10 @@ impl From<DatabaseSnapshot> for DatabaseSchema {
             .tables
             .into_iter()
             .map(|(id, table)| {
-                table_map.insert(id, Arc::clone(&table.table_name));
+                // deleted tables cannot be found by name:
+                if table.deleted_at_ns.is_none() {
+                    table_map.insert(id, Arc::clone(&table.table_name));
+                }
                 (id, Arc::new(table.into()))
             })
             .collect();
@@ -70,6 +148,20 @@ impl From<DatabaseSnapshot> for DatabaseSchema {
             name: snap.name,
             tables,
             table_map,
+            retention_period: snap.retention_period,
+            future_write_limit: snap.future_write_limit,
+            past_write_limit: snap.past_write_limit,
+            schema_limits: snap.schema_limits,
+            cardinality_limits: snap.cardinality_limits,
+            schema_mode: snap.schema_mode,
+            field_coercion: snap.field_coercion,
+            compatibility_mode: snap.compatibility_mode,
+            series_key_policy: snap.series_key_policy,
+            identifier_policy: snap.identifier_policy,
+            write_rate_limit: snap.write_rate_limit,
+            gen1_duration: snap.gen1_duration,
+            metadata: snap.metadata,
+            downsampling_tasks: snap.downsampling_tasks,
         }
     }
 }
@@ -108,6 +200,68 @@ struct TableSnapshot {
     cols: SerdeVecMap<ColumnId, ColumnDefinitionSnapshot>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     last_caches: Vec<LastCacheSnapshot>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    distinct_caches: Vec<DistinctCacheSnapshot>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at_ns: Option<i64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    retired_cols: BTreeMap<Arc<str>, Option<ColumnId>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    col_defaults: BTreeMap<ColumnId, FieldData>,
+}
+
+impl TableSnapshot {
+    /// Give the table, and its columns, new ids from `next_ids`, and update the references to
+    /// them from its series key, caches, retired column names, and column defaults
+    fn with_new_ids(mut self, next_ids: &mut NextIds) -> Self {
+        self.table_id = next_ids.take_table_id();
+        let column_ids: HashMap<ColumnId, ColumnId> = self
+            .cols
+            .keys()
+            .map(|id| (*id, next_ids.take_column_id()))
+            .collect();
+        let new_column_id = |id: &ColumnId| {
+            *column_ids
+                .get(id)
+                .expect("serialized catalog should only refer to columns in the table")
+        };
+
+        self.cols = self
+            .cols
+            .into_iter()
+            .map(|(id, mut col)| {
+                col.id = new_column_id(&id);
+                (col.id, col)
+            })
+            .collect();
+        self.key = self.key.map(|key| key.iter().map(new_column_id).collect());
+        for last_cache in &mut self.last_caches {
+            last_cache.table_id = self.table_id;
+            last_cache.keys = last_cache.keys.iter().map(new_column_id).collect();
+            last_cache.vals = last_cache
+                .vals
+                .as_ref()
+                .map(|vals| vals.iter().map(new_column_id).collect());
+            for aggregate in &mut last_cache.aggs {
+                aggregate.column_id = new_column_id(&aggregate.column_id);
+            }
+        }
+        for distinct_cache in &mut self.distinct_caches {
+            distinct_cache.table_id = self.table_id;
+            distinct_cache.cols = distinct_cache.cols.iter().map(new_column_id).collect();
+        }
+        for id in self.retired_cols.values_mut().flatten() {
+            *id = new_column_id(id);
+        }
+        self.col_defaults = self
+            .col_defaults
+            .into_iter()
+            .map(|(id, default)| (new_column_id(&id), default))
+            .collect();
+        self
+    }
 }
 
 /// Representation of Arrow's `DataType` for table snapshots.
@@ -252,6 +406,11 @@ impl From<&TableDefinition> for TableSnapshot {
                 })
                 .collect(),
             last_caches: def.last_caches.values().map(Into::into).collect(),
+            distinct_caches: def.distinct_caches.values().map(Into::into).collect(),
+            deleted_at_ns: def.deleted_at_ns,
+            retired_cols: def.retired_columns.clone(),
+            metadata: def.metadata.clone(),
+            col_defaults: def.column_defaults.clone(),
         }
     }
 }
@@ -336,6 +495,15 @@ impl From<TableSnapshot> for TableDefinition {
                 .into_iter()
                 .map(|lc_snap| (Arc::clone(&lc_snap.name), lc_snap.into()))
                 .collect(),
+            distinct_caches: snap
+                .distinct_caches
+                .into_iter()
+                .map(|dc_snap| (Arc::clone(&dc_snap.name), dc_snap.into()))
+                .collect(),
+            deleted_at_ns: snap.deleted_at_ns,
+            retired_columns: snap.retired_cols,
+            metadata: snap.metadata,
+            column_defaults: snap.col_defaults,
             ..table_def
         }
     }
@@ -382,6 +550,10 @@ struct LastCacheSnapshot {
     vals: Option<Vec<ColumnId>>,
     n: usize,
     ttl: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    aggs: Vec<LastCacheAggregate>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    auto_keys: bool,
 }
 
 impl From<&LastCacheDefinition> for LastCacheSnapshot {
@@ -397,6 +569,8 @@ impl From<&LastCacheDefinition> for LastCacheSnapshot {
             },
             n: lcd.count.into(),
             ttl: lcd.ttl,
+            aggs: lcd.aggregates.clone(),
+            auto_keys: lcd.auto_key_columns,
         }
     }
 }
@@ -417,6 +591,44 @@ impl From<LastCacheSnapshot> for LastCacheDefinition {
                 .try_into()
                 .expect("catalog contains invalid last cache size"),
             ttl: snap.ttl,
+            aggregates: snap.aggs,
+            auto_key_columns: snap.auto_keys,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DistinctCacheSnapshot {
+    table_id: TableId,
+    table: Arc<str>,
+    name: Arc<str>,
+    cols: Vec<ColumnId>,
+    max_cardinality: usize,
+    ttl: u64,
+}
+
+impl From<&DistinctCacheDefinition> for DistinctCacheSnapshot {
+    fn from(dcd: &DistinctCacheDefinition) -> Self {
+        Self {
+            table_id: dcd.table_id,
+            table: Arc::clone(&dcd.table),
+            name: Arc::clone(&dcd.name),
+            cols: dcd.column_ids.to_vec(),
+            max_cardinality: dcd.max_cardinality,
+            ttl: dcd.ttl,
+        }
+    }
+}
+
+impl From<DistinctCacheSnapshot> for DistinctCacheDefinition {
+    fn from(snap: DistinctCacheSnapshot) -> Self {
+        Self {
+            table_id: snap.table_id,
+            table: snap.table,
+            name: snap.name,
+            column_ids: snap.cols,
+            max_cardinality: snap.max_cardinality,
+            ttl: snap.ttl,
         }
     }
 }
@@ -1,5 +1,6 @@
 //! HTTP API service implementations for `server`
 
+use crate::otlp;
 use crate::{query_executor, QueryKind};
 use crate::{CommonServerState, QueryExecutor};
 use arrow::record_batch::RecordBatch;
@@ -13,22 +14,30 @@ use datafusion::execution::memory_pool::UnboundedMemoryPool;
 use datafusion::execution::RecordBatchStream;
 use datafusion::physical_plan::SendableRecordBatchStream;
 use futures::{StreamExt, TryStreamExt};
+use hyper::body::HttpBody;
 use hyper::header::ACCEPT;
 use hyper::header::AUTHORIZATION;
 use hyper::header::CONTENT_ENCODING;
 use hyper::header::CONTENT_TYPE;
+use hyper::header::RETRY_AFTER;
 use hyper::http::HeaderValue;
 use hyper::HeaderMap;
 use hyper::{Body, Method, Request, Response, StatusCode};
 use influxdb3_catalog::catalog::Error as CatalogError;
 use influxdb3_process::{INFLUXDB3_GIT_HASH_SHORT, INFLUXDB3_VERSION};
-use influxdb3_wal::LastCacheDefinition;
+use influxdb3_wal::{CatalogOp, LastCacheDefinition};
 use influxdb3_write::last_cache;
 use influxdb3_write::persister::TrackedMemoryArrowWriter;
+use influxdb3_write::write_buffer::decompress::{
+    decompress, ContentEncoding, Error as DecompressError,
+};
 use influxdb3_write::write_buffer::Error as WriteBufferError;
 use influxdb3_write::BufferedWriteRequest;
+use influxdb3_write::Durability;
 use influxdb3_write::Precision;
 use influxdb3_write::WriteBuffer;
+use influxdb3_write::{ColumnMapping, TabularFormat};
+use influxdb3_write::{WriteLineError, WriteLineErrorCategory};
 use iox_http::write::single_tenant::SingleTenantRequestUnifier;
 use iox_http::write::v1::V1_NAMESPACE_RP_SEPARATOR;
 use iox_http::write::{WriteParseError, WriteRequestUnifier};
@@ -36,17 +45,24 @@ use iox_query_influxql_rewrite as rewrite;
 use iox_query_params::StatementParams;
 use iox_time::TimeProvider;
 use observability_deps::tracing::{debug, error, info};
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use prost::Message;
+use schema::InfluxFieldType;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::convert::Infallible;
 use std::fmt::Debug;
 use std::pin::Pin;
 use std::str::Utf8Error;
 use std::string::FromUtf8Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use thiserror::Error;
+use tokio::io::AsyncBufRead;
+use tokio_util::io::StreamReader;
 use unicode_segmentation::UnicodeSegmentation;
 
 mod v1;
@@ -77,6 +93,20 @@ pub enum Error {
     #[error("unacceptable content-type, expected: {expected}")]
     InvalidContentType { expected: mime::Mime },
 
+    /// The `Content-Type` of a write of tabular data is not one of the formats it can be in.
+    #[error(
+        "unacceptable content-type for a tabular write: {0}, expected one of text/csv or \
+        application/x-ndjson"
+    )]
+    InvalidTabularContentType(String),
+
+    /// A field type in the column mapping of a write of tabular data is not valid.
+    #[error(
+        "invalid field type: {0}, expected a column and one of float, integer, uinteger, \
+        string, or boolean, separated by a colon"
+    )]
+    InvalidFieldType(String),
+
     /// The client disconnected.
     #[error("client disconnected")]
     ClientHangup(hyper::Error),
@@ -85,9 +115,13 @@ pub enum Error {
     #[error("max request size ({0} bytes) exceeded")]
     RequestSizeExceeded(usize),
 
-    /// Decoding a gzip-compressed stream of data failed.
-    #[error("error decoding gzip stream: {0}")]
-    InvalidGzip(std::io::Error),
+    /// The OTLP request could not be decoded.
+    #[error("invalid otlp request: {0}")]
+    InvalidOtlpRequest(prost::DecodeError),
+
+    /// Decoding a compressed stream of data failed.
+    #[error("error decoding compressed request body: {0}")]
+    InvalidCompressedBody(DecompressError),
 
     #[error("invalid mime type ({0})")]
     InvalidMimeType(String),
@@ -198,6 +232,9 @@ pub enum Error {
 
     #[error("v1 query API error: {0}")]
     V1Query(#[from] v1::QueryError),
+
+    #[error("the parquet cache is disabled")]
+    ParquetCacheDisabled,
 }
 
 #[derive(Debug, Error)]
@@ -218,15 +255,26 @@ struct ErrorMessage<T: Serialize> {
     data: Option<T>,
 }
 
+/// The body of the response to a partial write, which summarizes the number of lines rejected in
+/// each error category, in addition to providing the errors for the individual lines
+#[derive(Debug, Serialize)]
+struct PartialWriteErrorMessage {
+    error: String,
+    data: Vec<WriteLineError>,
+    error_counts: BTreeMap<WriteLineErrorCategory, usize>,
+}
+
 impl Error {
     /// Convert this error into an HTTP [`Response`]
     fn into_response(self) -> Response<Body> {
         debug!(error = ?self, "API error");
         match self {
             Self::WriteBuffer(WriteBufferError::CatalogUpdateError(
-                err @ (CatalogError::TooManyDbs
+                err @ (CatalogError::TooManyDbs(_)
                 | CatalogError::TooManyColumns
-                | CatalogError::TooManyTables),
+                | CatalogError::TooManyTables(_)
+                | CatalogError::TooManyTablesInDb { .. }
+                | CatalogError::TooManyColumnsInCatalog(_)),
             )) => {
                 let err: ErrorMessage<()> = ErrorMessage {
                     error: err.to_string(),
@@ -251,7 +299,22 @@ impl Error {
                     .body(body)
                     .unwrap()
             }
-            Self::WriteBuffer(err @ WriteBufferError::ColumnDoesNotExist(_)) => {
+            Self::WriteBuffer(WriteBufferError::CardinalityLimitExceeded(err)) => {
+                let err = ErrorMessage {
+                    error: "cardinality limit exceeded".into(),
+                    data: Some(err),
+                };
+                let serialized = serde_json::to_string(&err).unwrap();
+                let body = Body::from(serialized);
+                Response::builder()
+                    .status(StatusCode::UNPROCESSABLE_ENTITY)
+                    .body(body)
+                    .unwrap()
+            }
+            Self::WriteBuffer(
+                err @ (WriteBufferError::ColumnDoesNotExist(_)
+                | WriteBufferError::ReadLineProtocol(_)),
+            ) => {
                 let err: ErrorMessage<()> = ErrorMessage {
                     error: err.to_string(),
                     data: None,
@@ -263,6 +326,42 @@ impl Error {
                     .body(body)
                     .unwrap()
             }
+            Self::WriteBuffer(
+                err @ (WriteBufferError::BufferFull { .. }
+                | WriteBufferError::NoWriteInReadOnly
+                | WriteBufferError::NotLeader),
+            ) => {
+                let err: ErrorMessage<()> = ErrorMessage {
+                    error: err.to_string(),
+                    data: None,
+                };
+                let serialized = serde_json::to_string(&err).unwrap();
+                let body = Body::from(serialized);
+                Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(body)
+                    .unwrap()
+            }
+            Self::WriteBuffer(
+                ref err @ WriteBufferError::RateLimited {
+                    ref retry_after, ..
+                },
+            ) => {
+                // Retry-After is given in whole seconds, so round up:
+                let retry_after_secs =
+                    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                let err: ErrorMessage<()> = ErrorMessage {
+                    error: err.to_string(),
+                    data: None,
+                };
+                let serialized = serde_json::to_string(&err).unwrap();
+                let body = Body::from(serialized);
+                Response::builder()
+                    .status(StatusCode::TOO_MANY_REQUESTS)
+                    .header(RETRY_AFTER, retry_after_secs.max(1))
+                    .body(body)
+                    .unwrap()
+            }
             Self::WriteBuffer(WriteBufferError::LastCacheError(ref lc_err)) => match lc_err {
                 last_cache::Error::InvalidCacheSize
                 | last_cache::Error::CacheAlreadyExists { .. }
@@ -293,9 +392,10 @@ impl Error {
                     .unwrap()
             }
             Self::PartialLpWrite(data) => {
-                let err = ErrorMessage {
+                let err = PartialWriteErrorMessage {
                     error: "partial write of line protocol occurred".into(),
-                    data: Some(data.invalid_lines),
+                    error_counts: data.invalid_line_counts(),
+                    data: data.invalid_lines,
                 };
                 let serialized = serde_json::to_string(&err).unwrap();
                 let body = Body::from(serialized);
@@ -324,14 +424,28 @@ impl Error {
                 .status(StatusCode::BAD_REQUEST)
                 .body(Body::from(self.to_string()))
                 .unwrap(),
-            Self::InvalidContentType { .. } => Response::builder()
-                .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+            Self::InvalidCompressedBody(_) | Self::InvalidOtlpRequest(_) => Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(self.to_string()))
+                .unwrap(),
+            Self::InvalidFieldType(_) => Response::builder()
+                .status(StatusCode::BAD_REQUEST)
                 .body(Body::from(self.to_string()))
                 .unwrap(),
+            Self::InvalidContentType { .. } | Self::InvalidTabularContentType(_) => {
+                Response::builder()
+                    .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                    .body(Body::from(self.to_string()))
+                    .unwrap()
+            }
             Self::SerdeUrlDecoding(_) => Response::builder()
                 .status(StatusCode::BAD_REQUEST)
                 .body(Body::from(self.to_string()))
                 .unwrap(),
+            Self::ParquetCacheDisabled => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(self.to_string()))
+                .unwrap(),
             _ => {
                 let body = Body::from(self.to_string());
                 Response::builder()
@@ -348,11 +462,11 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 #[derive(Debug)]
 pub(crate) struct HttpApi<Q, T> {
     common_state: CommonServerState,
-    write_buffer: Arc<dyn WriteBuffer>,
-    time_provider: Arc<T>,
+    pub(crate) write_buffer: Arc<dyn WriteBuffer>,
+    pub(crate) time_provider: Arc<T>,
     pub(crate) query_executor: Arc<Q>,
     max_request_bytes: usize,
-    authorizer: Arc<dyn Authorizer>,
+    pub(crate) authorizer: Arc<dyn Authorizer>,
     legacy_write_param_unifier: SingleTenantRequestUnifier,
 }
 
@@ -406,15 +520,15 @@ where
         validate_db_name(&params.db, accept_rp)?;
         info!("write_lp to {}", params.db);
 
-        let body = self.read_body(req).await?;
-        let body = std::str::from_utf8(&body).map_err(Error::NonUtf8Body)?;
-
         let database = NamespaceName::new(params.db)?;
 
         let default_time = self.time_provider.now();
 
-        let result = if use_v3 {
-            self.write_buffer
+        let (result, payload_size) = if use_v3 {
+            let body = self.read_body(req).await?;
+            let body = std::str::from_utf8(&body).map_err(Error::NonUtf8Body)?;
+            let result = self
+                .write_buffer
                 .write_lp_v3(
                     database,
                     body,
@@ -422,21 +536,50 @@ where
                     params.accept_partial,
                     params.precision,
                 )
-                .await?
+                .await?;
+            (result, body.len())
+        } else if params.stream {
+            // the body is validated and buffered in batches as it is read, rather than being
+            // read into memory in its entirety first:
+            let (body, bytes_read) = self.body_reader(req).await?;
+            let result = self
+                .write_buffer
+                .write_lp_stream(
+                    database,
+                    body,
+                    default_time,
+                    params.accept_partial,
+                    params.precision,
+                    params.durability,
+                )
+                .await
+                .map_err(|e| match e {
+                    WriteBufferError::ReadLineProtocol(e)
+                        if e.get_ref().is_some_and(|e| e.is::<BodySizeExceeded>()) =>
+                    {
+                        Error::RequestSizeExceeded(self.max_request_bytes)
+                    }
+                    e => e.into(),
+                })?;
+            (result, bytes_read.load(Ordering::Relaxed))
         } else {
-            self.write_buffer
-                .write_lp(
+            let body = self.read_body(req).await?;
+            let body = std::str::from_utf8(&body).map_err(Error::NonUtf8Body)?;
+            let result = self
+                .write_buffer
+                .write_lp_with_durability(
                     database,
                     body,
                     default_time,
                     params.accept_partial,
                     params.precision,
+                    params.durability,
                 )
-                .await?
+                .await?;
+            (result, body.len())
         };
 
         let num_lines = result.line_count;
-        let payload_size = body.len();
         self.common_state
             .telemetry_store
             .add_write_metrics(num_lines, payload_size);
@@ -448,6 +591,128 @@ where
         }
     }
 
+    /// Write a body of CSV, or JSON lines, to a table, with the columns mapped to the tags, fields,
+    /// and time of its rows by the query parameters. The format is given by the `Content-Type`.
+    async fn write_tabular(&self, req: Request<Body>) -> Result<Response<Body>> {
+        let query = req.uri().query().ok_or(Error::MissingWriteParams)?;
+        let params: WriteTabularParams = serde_urlencoded::from_str(query)?;
+        validate_db_name(&params.db, false)?;
+        info!("write_tabular to {}", params.db);
+
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .map(|v| v.to_str().map_err(Error::NonUtf8ContentTypeHeader))
+            .transpose()?
+            .unwrap_or_default();
+        // parameters of the content type, e.g., the charset, are ignored:
+        let format = match content_type.split(';').next().unwrap_or_default().trim() {
+            "text/csv" => TabularFormat::Csv,
+            "application/x-ndjson" | "application/jsonl" | "application/x-jsonlines" => {
+                TabularFormat::JsonLines
+            }
+            other => return Err(Error::InvalidTabularContentType(other.to_string())),
+        };
+        let mapping = params.column_mapping()?;
+
+        let body = self.read_body(req).await?;
+        let body = std::str::from_utf8(&body).map_err(Error::NonUtf8Body)?;
+
+        let database = NamespaceName::new(params.db)?;
+        let result = self
+            .write_buffer
+            .write_tabular(
+                database,
+                format,
+                body,
+                &mapping,
+                self.time_provider.now(),
+                params.accept_partial,
+                params.precision,
+            )
+            .await?;
+        self.common_state
+            .telemetry_store
+            .add_write_metrics(result.line_count, body.len());
+
+        if result.invalid_lines.is_empty() {
+            Ok(Response::new(Body::empty()))
+        } else {
+            Err(Error::PartialLpWrite(result))
+        }
+    }
+
+    /// Validate the line protocol in the request body against the schema of the database, without
+    /// writing it, and respond with the lines that would be rejected and the schema changes that
+    /// the write would make
+    async fn validate_lp(&self, req: Request<Body>) -> Result<Response<Body>> {
+        let query = req.uri().query().ok_or(Error::MissingWriteParams)?;
+        let params: WriteParams = serde_urlencoded::from_str(query)?;
+        validate_db_name(&params.db, false)?;
+
+        let body = self.read_body(req).await?;
+        let body = std::str::from_utf8(&body).map_err(Error::NonUtf8Body)?;
+
+        let database = NamespaceName::new(params.db)?;
+        let result = self
+            .write_buffer
+            .validate_lp(database, body, params.precision)
+            .await?;
+        let response = ValidateLpResponse {
+            line_count: result.line_count,
+            error_counts: result.invalid_line_counts(),
+            invalid_lines: result.invalid_lines,
+            catalog_ops: result.catalog_ops,
+        };
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(serde_json::to_string(&response).unwrap()))
+            .map_err(Into::into)
+    }
+
+    async fn write_otlp_metrics(&self, req: Request<Body>) -> Result<Response<Body>> {
+        let query = req.uri().query().ok_or(Error::MissingWriteParams)?;
+        let params: OtlpWriteParams = serde_urlencoded::from_str(query)?;
+        validate_db_name(&params.db, false)?;
+        info!("otlp metrics write to {}", params.db);
+
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .map(|v| v.to_str().map_err(Error::NonUtf8ContentTypeHeader))
+            .transpose()?;
+        if content_type != Some(PROTOBUF_CONTENT_TYPE) {
+            return Err(Error::InvalidContentType {
+                expected: PROTOBUF_CONTENT_TYPE.parse().unwrap(),
+            });
+        }
+
+        let body = self.read_body(req).await?;
+        let payload_size = body.len();
+        let request =
+            ExportMetricsServiceRequest::decode(body).map_err(Error::InvalidOtlpRequest)?;
+
+        let database = NamespaceName::new(params.db)?;
+        let (result, response) = otlp::write_metrics(
+            self.write_buffer.as_ref(),
+            database,
+            request,
+            self.time_provider.as_ref(),
+        )
+        .await?;
+        self.common_state
+            .telemetry_store
+            .add_write_metrics(result.line_count, payload_size);
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, PROTOBUF_CONTENT_TYPE)
+            .body(Body::from(response.encode_to_vec()))
+            .unwrap())
+    }
+
     async fn query_sql(&self, req: Request<Body>) -> Result<Response<Body>> {
         let QueryRequest {
             database,
@@ -512,6 +777,71 @@ where
         Ok(Response::new(Body::from(body)))
     }
 
+    /// The range of snapshot WAL files that are retained and pending deletion, or `null` if there
+    /// are none
+    async fn retained_wal_files(&self) -> Result<Response<Body>> {
+        let retained = self.write_buffer.retained_wal_files().await?;
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(serde_json::to_string(&retained).unwrap()))
+            .map_err(Into::into)
+    }
+
+    fn catalog_usage(&self) -> Result<Response<Body>> {
+        let usage = self.write_buffer.catalog().usage();
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(serde_json::to_string(&usage).unwrap()))
+            .map_err(Into::into)
+    }
+
+    fn parquet_cache_usage(&self) -> Result<Response<Body>> {
+        let usage = self
+            .write_buffer
+            .parquet_cache()
+            .ok_or(Error::ParquetCacheDisabled)?
+            .usage();
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(serde_json::to_string(&usage).unwrap()))
+            .map_err(Into::into)
+    }
+
+    /// Remove the files of the given database from the parquet cache, or everything if no
+    /// database is given
+    fn parquet_cache_delete(&self, req: Request<Body>) -> Result<Response<Body>> {
+        let ParquetCacheDeleteRequest { db } = match req.uri().query() {
+            Some(query) => serde_urlencoded::from_str(query)?,
+            None => ParquetCacheDeleteRequest { db: None },
+        };
+        let parquet_cache = self
+            .write_buffer
+            .parquet_cache()
+            .ok_or(Error::ParquetCacheDisabled)?;
+        match db {
+            Some(db) => {
+                let db_id = self
+                    .write_buffer
+                    .catalog()
+                    .db_name_to_id(&db)
+                    .ok_or(WriteBufferError::DbDoesNotExist)?;
+                parquet_cache.invalidate_db(db_id);
+            }
+            None => parquet_cache.clear(),
+        }
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .body(Body::empty())
+            .unwrap())
+    }
+
     fn handle_metrics(&self) -> Result<Response<Body>> {
         let mut body: Vec<u8> = Default::default();
         let mut reporter = metric_exporters::PrometheusTextEncoder::new(&mut body);
@@ -523,16 +853,7 @@ where
     /// Parse the request's body into raw bytes, applying the configured size
     /// limits and decoding any content encoding.
     async fn read_body(&self, req: hyper::Request<Body>) -> Result<Bytes> {
-        let encoding = req
-            .headers()
-            .get(&CONTENT_ENCODING)
-            .map(|v| v.to_str().map_err(Error::NonUtf8ContentEncodingHeader))
-            .transpose()?;
-        let ungzip = match encoding {
-            None | Some("identity") => false,
-            Some("gzip") => true,
-            Some(v) => return Err(Error::InvalidContentEncoding(v.to_string())),
-        };
+        let encoding = content_encoding(req.headers())?;
 
         let mut payload = req.into_body();
 
@@ -545,36 +866,56 @@ where
             }
             body.extend_from_slice(&chunk);
         }
+
+        // Decompress the body, if it was compressed. At most max_request_bytes bytes are
+        // decompressed, to prevent a decompression bomb based DoS. Decompressing is CPU bound,
+        // so it is done on a blocking thread rather than on the runtime:
         let body = body.freeze();
+        let max_request_bytes = self.max_request_bytes;
+        let decompressed = match encoding {
+            ContentEncoding::Identity => decompress(encoding, body, max_request_bytes),
+            _ => tokio::task::spawn_blocking(move || decompress(encoding, body, max_request_bytes))
+                .await
+                .expect("decompressing a request body should not panic"),
+        };
+        decompressed.map_err(|e| match e {
+            DecompressError::SizeExceeded(n) => Error::RequestSizeExceeded(n),
+            e => Error::InvalidCompressedBody(e),
+        })
+    }
 
-        // If the body is not compressed, return early.
-        if !ungzip {
-            return Ok(body);
+    /// Get a reader over the body of the request, which is read from the client as it is read
+    /// from the reader, along with the number of bytes that have been read from it. Reading
+    /// fails with [`BodySizeExceeded`] once more than `max_request_bytes` have been read.
+    ///
+    /// Compressed bodies are read and decompressed in their entirety by [`Self::read_body`]
+    /// first, which limits their size in the same way.
+    async fn body_reader(
+        &self,
+        req: hyper::Request<Body>,
+    ) -> Result<(Box<dyn AsyncBufRead + Send + Unpin>, Arc<AtomicUsize>)> {
+        if content_encoding(req.headers())? != ContentEncoding::Identity {
+            let body = self.read_body(req).await?;
+            let bytes_read = Arc::new(AtomicUsize::new(body.len()));
+            return Ok((Box::new(std::io::Cursor::new(body)), bytes_read));
         }
-
-        // Unzip the gzip-encoded content
-        use std::io::Read;
-        let decoder = flate2::read::GzDecoder::new(&body[..]);
-
-        // Read at most max_request_bytes bytes to prevent a decompression bomb
-        // based DoS.
-        //
-        // In order to detect if the entire stream ahs been read, or truncated,
-        // read an extra byte beyond the limit and check the resulting data
-        // length - see the max_request_size_truncation test.
-        let mut decoder = decoder.take(self.max_request_bytes as u64 + 1);
-        let mut decoded_data = Vec::new();
-        decoder
-            .read_to_end(&mut decoded_data)
-            .map_err(Error::InvalidGzip)?;
-
-        // If the length is max_size+1, the body is at least max_size+1 bytes in
-        // length, and possibly longer, but truncated.
-        if decoded_data.len() > self.max_request_bytes {
+        // fail early if the client says that the body is too large:
+        if req.body().size_hint().lower() > self.max_request_bytes as u64 {
             return Err(Error::RequestSizeExceeded(self.max_request_bytes));
         }
 
-        Ok(decoded_data.into())
+        let max_request_bytes = self.max_request_bytes;
+        let bytes_read = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&bytes_read);
+        let chunks = req.into_body().map(move |chunk| {
+            let chunk = chunk.map_err(std::io::Error::other)?;
+            let read = counter.fetch_add(chunk.len(), Ordering::Relaxed) + chunk.len();
+            if read > max_request_bytes {
+                return Err(std::io::Error::other(BodySizeExceeded));
+            }
+            Ok(chunk)
+        });
+        Ok((Box::new(StreamReader::new(chunks)), bytes_read))
     }
 
     async fn authorize_request(&self, req: &mut Request<Body>) -> Result<(), AuthorizationError> {
@@ -716,6 +1057,11 @@ where
         let (table_id, table_def) = db_schema
             .table_definition_and_id(table.as_str())
             .ok_or_else(|| WriteBufferError::TableDoesNotExist)?;
+        let (key_columns, auto_key_columns) = match key_columns {
+            Some(LastCacheKeyColumns::Auto(_)) => (None, true),
+            Some(LastCacheKeyColumns::Explicit(names)) => (Some(names), false),
+            None => (None, false),
+        };
         let key_columns = key_columns
             .map(|names| {
                 names
@@ -743,19 +1089,31 @@ where
             })
             .transpose()?;
 
-        match self
-            .write_buffer
-            .create_last_cache(
-                db_id,
-                table_id,
-                name.as_deref(),
-                count,
-                ttl.map(Duration::from_secs),
-                key_columns,
-                value_columns,
-            )
-            .await?
-        {
+        let created = if auto_key_columns {
+            self.write_buffer
+                .create_last_cache_with_auto_key_columns(
+                    db_id,
+                    table_id,
+                    name.as_deref(),
+                    count,
+                    ttl.map(Duration::from_secs),
+                    value_columns,
+                )
+                .await?
+        } else {
+            self.write_buffer
+                .create_last_cache(
+                    db_id,
+                    table_id,
+                    name.as_deref(),
+                    count,
+                    ttl.map(Duration::from_secs),
+                    key_columns,
+                    value_columns,
+                )
+                .await?
+        };
+        match created {
             Some(def) => Response::builder()
                 .status(StatusCode::CREATED)
                 .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
@@ -813,6 +1171,26 @@ where
     }
 }
 
+/// Get the encoding of a request body from its `Content-Encoding` header
+fn content_encoding(headers: &HeaderMap) -> Result<ContentEncoding> {
+    Ok(headers
+        .get(&CONTENT_ENCODING)
+        .map(|v| v.to_str().map_err(Error::NonUtf8ContentEncodingHeader))
+        .transpose()?
+        .map(|v| {
+            v.parse::<ContentEncoding>()
+                .map_err(|_| Error::InvalidContentEncoding(v.to_string()))
+        })
+        .transpose()?
+        .unwrap_or_default())
+}
+
+/// The error that reading a streamed request body fails with once it exceeds the maximum
+/// request size
+#[derive(Debug, Error)]
+#[error("max request size exceeded")]
+struct BodySizeExceeded;
+
 /// Check that the content type is application/json
 fn json_content_type(headers: &HeaderMap) -> bool {
     let content_type = if let Some(content_type) = headers.get(CONTENT_TYPE) {
@@ -862,7 +1240,7 @@ fn extract_v1_auth_token(req: &mut Request<Body>) -> Option<Vec<u8>> {
         .map(String::into_bytes)
 }
 
-fn validate_auth_header(header: HeaderValue) -> Result<Vec<u8>, AuthorizationError> {
+pub(crate) fn validate_auth_header(header: HeaderValue) -> Result<Vec<u8>, AuthorizationError> {
     // Split the header value into two parts
     let mut header = header.to_str()?.split(' ');
 
@@ -1069,6 +1447,14 @@ async fn record_batch_stream_to_body(
 const fn true_fn() -> bool {
     true
 }
+/// The content type used for OTLP over HTTP requests and responses
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+#[derive(Debug, Deserialize)]
+struct OtlpWriteParams {
+    db: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct WriteParams {
     pub(crate) db: String,
@@ -1076,6 +1462,77 @@ pub(crate) struct WriteParams {
     pub(crate) accept_partial: bool,
     #[serde(default)]
     pub(crate) precision: Precision,
+    /// When the write is acknowledged; this only applies to writes that use the v1 data model
+    #[serde(default)]
+    pub(crate) durability: Durability,
+    /// Validate and buffer the body in batches as it is read, rather than reading it into memory
+    /// in its entirety first; this only applies to writes that use the v1 data model.
+    ///
+    /// A streamed write is not atomic: each batch is written before the next one is read, so if
+    /// a line is rejected, with `accept_partial` set to `false`, or the body turns out to exceed
+    /// the maximum request size, the error is returned with the batches before it, and the
+    /// catalog changes that they made, already written.
+    #[serde(default)]
+    pub(crate) stream: bool,
+}
+
+/// The parameters of a write of tabular data, which map its columns to the rows of a table. The
+/// lists of columns are separated by commas.
+#[derive(Debug, Deserialize)]
+pub(crate) struct WriteTabularParams {
+    pub(crate) db: String,
+    pub(crate) table: String,
+    #[serde(default)]
+    pub(crate) tags: Option<String>,
+    /// The column with the timestamp of each row
+    #[serde(default)]
+    pub(crate) time: Option<String>,
+    /// The types of fields, as `column:type`, which are otherwise inferred
+    #[serde(default)]
+    pub(crate) field_types: Option<String>,
+    #[serde(default)]
+    pub(crate) ignore: Option<String>,
+    #[serde(default = "true_fn")]
+    pub(crate) accept_partial: bool,
+    #[serde(default)]
+    pub(crate) precision: Precision,
+}
+
+impl WriteTabularParams {
+    fn column_mapping(&self) -> Result<ColumnMapping> {
+        fn columns(list: &Option<String>) -> Vec<String> {
+            list.iter()
+                .flat_map(|list| list.split(','))
+                .map(str::trim)
+                .filter(|column| !column.is_empty())
+                .map(str::to_string)
+                .collect()
+        }
+        let field_types = columns(&self.field_types)
+            .into_iter()
+            .map(|field_type| {
+                let (column, type_name) = field_type
+                    .split_once(':')
+                    .ok_or_else(|| Error::InvalidFieldType(field_type.clone()))?;
+                let influx_type = match type_name.trim() {
+                    "float" => InfluxFieldType::Float,
+                    "integer" => InfluxFieldType::Integer,
+                    "uinteger" => InfluxFieldType::UInteger,
+                    "string" => InfluxFieldType::String,
+                    "boolean" => InfluxFieldType::Boolean,
+                    _ => return Err(Error::InvalidFieldType(field_type.clone())),
+                };
+                Ok((column.trim().to_string(), influx_type))
+            })
+            .collect::<Result<_>>()?;
+        Ok(ColumnMapping {
+            table_name: self.table.clone(),
+            tags: columns(&self.tags),
+            time_column: self.time.clone().filter(|time| !time.is_empty()),
+            field_types,
+            ignore: columns(&self.ignore),
+        })
+    }
 }
 
 impl From<iox_http::write::WriteParams> for WriteParams {
@@ -1085,6 +1542,8 @@ impl From<iox_http::write::WriteParams> for WriteParams {
             // legacy behaviour was to not accept partial:
             accept_partial: false,
             precision: legacy.precision.into(),
+            durability: Durability::default(),
+            stream: false,
         }
     }
 }
@@ -1095,15 +1554,39 @@ struct LastCacheCreateRequest {
     db: String,
     table: String,
     name: Option<String>,
-    key_columns: Option<Vec<String>>,
+    key_columns: Option<LastCacheKeyColumns>,
     value_columns: Option<Vec<String>>,
     count: Option<usize>,
     ttl: Option<u64>,
 }
 
+/// The key columns of a last cache, either `"auto"`, for the tags of the table, including those
+/// that are added to it later, or a list of column names
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LastCacheKeyColumns {
+    Auto(AutoKeyColumns),
+    Explicit(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum AutoKeyColumns {
+    Auto,
+}
+
 #[derive(Debug, Serialize)]
 struct LastCacheCreatedResponse(LastCacheDefinition);
 
+/// Response definition for the `POST /api/v3/validate_lp` API
+#[derive(Debug, Serialize)]
+struct ValidateLpResponse {
+    line_count: usize,
+    invalid_lines: Vec<WriteLineError>,
+    error_counts: BTreeMap<WriteLineErrorCategory, usize>,
+    catalog_ops: Vec<CatalogOp>,
+}
+
 /// Request definition for the `DELETE /api/v3/configure/last_cache` API
 #[derive(Debug, Deserialize)]
 struct LastCacheDeleteRequest {
@@ -1112,6 +1595,12 @@ struct LastCacheDeleteRequest {
     name: String,
 }
 
+/// Request definition for the `DELETE /api/v3/parquet_cache` API
+#[derive(Debug, Deserialize)]
+struct ParquetCacheDeleteRequest {
+    db: Option<String>,
+}
+
 pub(crate) async fn route_request<Q: QueryExecutor, T: TimeProvider>(
     http_server: Arc<HttpApi<Q, T>>,
     mut req: Request<Body>,
@@ -1176,6 +1665,9 @@ where
         }
         (Method::POST, "/api/v3/write") => http_server.write_v3(req).await,
         (Method::POST, "/api/v3/write_lp") => http_server.write_lp(req).await,
+        (Method::POST, "/api/v3/write_tabular") => http_server.write_tabular(req).await,
+        (Method::POST, "/api/v3/validate_lp") => http_server.validate_lp(req).await,
+        (Method::POST, "/v1/metrics") => http_server.write_otlp_metrics(req).await,
         (Method::GET | Method::POST, "/api/v3/query_sql") => http_server.query_sql(req).await,
         (Method::GET | Method::POST, "/api/v3/query_influxql") => {
             http_server.query_influxql(req).await
@@ -1184,6 +1676,10 @@ where
         (Method::GET, "/health" | "/api/v1/health") => http_server.health(),
         (Method::GET | Method::POST, "/ping") => http_server.ping(),
         (Method::GET, "/metrics") => http_server.handle_metrics(),
+        (Method::GET, "/api/v3/wal/retained") => http_server.retained_wal_files().await,
+        (Method::GET, "/api/v3/catalog/usage") => http_server.catalog_usage(),
+        (Method::GET, "/api/v3/parquet_cache/usage") => http_server.parquet_cache_usage(),
+        (Method::DELETE, "/api/v3/parquet_cache") => http_server.parquet_cache_delete(req),
         (Method::POST, "/api/v3/configure/last_cache") => {
             http_server.configure_last_cache_create(req).await
         }
@@ -15,6 +15,7 @@ pub mod auth;
 pub mod builder;
 mod grpc;
 mod http;
+mod otlp;
 pub mod query_executor;
 mod service;
 mod system_tables;
@@ -22,6 +23,7 @@ mod system_tables;
 use crate::grpc::make_flight_server;
 use crate::http::route_request;
 use crate::http::HttpApi;
+use crate::otlp::make_otlp_metrics_server;
 use async_trait::async_trait;
 use authz::Authorizer;
 use datafusion::execution::SendableRecordBatchStream;
@@ -41,6 +43,7 @@ use std::sync::Arc;
 use thiserror::Error;
 use tokio::net::TcpListener;
 use tokio_util::sync::CancellationToken;
+use tonic::transport::server::Routes;
 use tower::Layer;
 use trace::ctx::SpanContext;
 use trace::TraceCollector;
@@ -178,10 +181,16 @@ where
         TRACE_SERVER_NAME,
     );
 
-    let grpc_service = trace_layer.clone().layer(make_flight_server(
+    let grpc_routes = Routes::new(make_flight_server(
         Arc::clone(&server.http.query_executor),
         Some(server.authorizer()),
+    ))
+    .add_service(make_otlp_metrics_server(
+        Arc::clone(&server.http.write_buffer),
+        Arc::clone(&server.http.time_provider),
+        server.authorizer(),
     ));
+    let grpc_service = trace_layer.clone().layer(grpc_routes);
 
     let rest_service = hyper::service::make_service_fn(|_| {
         let http_server = Arc::clone(&server.http);
@@ -387,6 +396,7 @@ mod tests {
                 \"data\":{\
                     \"original_line\":\"cpu,host=a val= 123\",\
                     \"line_number\":1,\
+                    \"error_category\":\"parse\",\
                     \"error_message\":\"No fields were provided\"\
                 }\
             }"
@@ -414,8 +424,10 @@ mod tests {
                 \"data\":[{\
                     \"original_line\":\"cpu,host=a val= 123\",\
                     \"line_number\":2,\
+                    \"error_category\":\"parse\",\
                     \"error_message\":\"No fields were provided\"\
-                }]\
+                }],\
+                \"error_counts\":{\"parse\":1}\
             }"
         );
 
@@ -751,6 +763,79 @@ mod tests {
         shutdown.cancel();
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn write_otlp_metrics_over_http() {
+        use opentelemetry_proto::tonic::{
+            collector::metrics::v1::{ExportMetricsServiceRequest, ExportMetricsServiceResponse},
+            common::v1::{any_value, AnyValue, KeyValue},
+            metrics::v1::{
+                metric, number_data_point, Gauge, Metric, NumberDataPoint, ResourceMetrics,
+                ScopeMetrics,
+            },
+        };
+        use prost::Message;
+
+        let start_time = 0;
+        let (server, shutdown, _) = setup_server(start_time).await;
+
+        let data_point = |host: &str, value: f64, time_unix_nano: u64| NumberDataPoint {
+            attributes: vec![KeyValue {
+                key: "host".to_string(),
+                value: Some(AnyValue {
+                    value: Some(any_value::Value::StringValue(host.to_string())),
+                }),
+            }],
+            time_unix_nano,
+            value: Some(number_data_point::Value::AsDouble(value)),
+            ..Default::default()
+        };
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: vec![Metric {
+                        name: "cpu".to_string(),
+                        data: Some(metric::Data::Gauge(Gauge {
+                            data_points: vec![data_point("a", 0.5, 100), data_point("b", 0.7, 200)],
+                        })),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let client = Client::new();
+        let request = Request::builder()
+            .uri(format!("{server}/v1/metrics?db=foo"))
+            .method("POST")
+            .header(hyper::header::CONTENT_TYPE, "application/x-protobuf")
+            .body(Body::from(request.encode_to_vec()))
+            .unwrap();
+        let resp = client.request(request).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = body::to_bytes(resp.into_body()).await.unwrap();
+        let response = ExportMetricsServiceResponse::decode(body).unwrap();
+        assert!(response.partial_success.is_none());
+
+        let res = query(
+            &server,
+            "foo",
+            "select host, time, gauge from cpu",
+            "csv",
+            None,
+        )
+        .await;
+        let body = body::to_bytes(res.into_body()).await.unwrap();
+        let actual = std::str::from_utf8(body.as_bytes()).unwrap();
+        let expected = "host,time,gauge\n\
+                        a,1970-01-01T00:00:00.000000100,0.5\n\
+                        b,1970-01-01T00:00:00.000000200,0.7\n";
+        assert_eq!(actual, expected);
+
+        shutdown.cancel();
+    }
+
     async fn setup_server(start_time: i64) -> (String, CancellationToken, Arc<dyn WriteBuffer>) {
         let trace_header_parser = trace_http::ctx::TraceHeaderParser::new();
         let metrics = Arc::new(metric::Registry::new());
@@ -778,13 +863,15 @@ mod tests {
         let catalog = Arc::new(Catalog::new(sample_host_id, instance_id));
         let write_buffer_impl = Arc::new(
             influxdb3_write::write_buffer::WriteBufferImpl::new(
-                Arc::clone(&persister),
-                Arc::clone(&catalog),
-                LastCacheProvider::new_from_catalog(catalog as _).unwrap(),
-                Arc::<MockProvider>::clone(&time_provider),
-                Arc::clone(&exec),
-                WalConfig::test_config(),
-                Some(parquet_cache),
+                influxdb3_write::write_buffer::WriteBufferImplArgs::new(
+                    Arc::clone(&persister),
+                    Arc::clone(&catalog),
+                    LastCacheProvider::new_from_catalog(catalog as _).unwrap(),
+                    Arc::<MockProvider>::clone(&time_provider),
+                    Arc::clone(&exec),
+                    WalConfig::test_config(),
+                    Some(parquet_cache),
+                ),
             )
             .await
             .unwrap(),
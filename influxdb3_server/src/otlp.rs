@@ -0,0 +1,492 @@
+//! Ingest of OpenTelemetry metrics sent using the OTLP protocol, over gRPC or HTTP
+//!
+//! Each metric is written to a table named for the metric. Resource attributes and data point
+//! attributes are written as tags, with data point attributes taking precedence when both use the
+//! same key. The values of each data point are written to fields named for the kind of metric:
+//!
+//! * gauges, and non-monotonic sums, use a `gauge` field
+//! * monotonic sums use a `counter` field
+//! * histograms use `count`, `sum`, `min` and `max` fields. In addition, each bucket of an
+//!   explicit bucket histogram is written as its own row, with the bucket's upper bound in an
+//!   `le` tag, and the cumulative count of the bucket in a `bucket` field
+//! * summaries use `count` and `sum` fields, and each quantile is written as its own row with a
+//!   `quantile` tag and a `value` field
+//!
+//! OTLP does not have a notion of a database, so gRPC requests must supply one using the
+//! `database` metadata key, while HTTP requests use the `db` query parameter.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use authz::Authorizer;
+use data_types::NamespaceName;
+use hyper::header::HeaderValue;
+use influxdb3_write::write_buffer::Error as WriteBufferError;
+use influxdb3_write::{BufferedWriteRequest, Precision, WriteBuffer, WriteFieldValue, WriteRow};
+use iox_time::TimeProvider;
+use observability_deps::tracing::{debug, info};
+use opentelemetry_proto::tonic::collector::metrics::v1::{
+    metrics_service_server::{MetricsService, MetricsServiceServer},
+    ExportMetricsPartialSuccess, ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+};
+use opentelemetry_proto::tonic::common::v1::{any_value, KeyValue};
+use opentelemetry_proto::tonic::metrics::v1::{metric, number_data_point, NumberDataPoint};
+use tonic::{Request, Response, Status};
+
+use crate::http::{validate_auth_header, AuthorizationError};
+
+/// The gRPC metadata key used to specify the database to write metrics to
+pub(crate) const DATABASE_METADATA_KEY: &str = "database";
+
+/// Convert the metrics in an OTLP export request into rows that can be written to the
+/// [`WriteBuffer`]
+pub(crate) fn metrics_request_to_rows(request: ExportMetricsServiceRequest) -> Vec<WriteRow> {
+    let mut rows = vec![];
+    for resource_metrics in request.resource_metrics {
+        let resource_tags = resource_metrics
+            .resource
+            .map(|r| attributes_to_tags(r.attributes, BTreeMap::new()))
+            .unwrap_or_default();
+        for metric in resource_metrics
+            .scope_metrics
+            .into_iter()
+            .flat_map(|sm| sm.metrics)
+        {
+            let Some(data) = metric.data else {
+                continue;
+            };
+            let mut converter = MetricConverter {
+                table_name: metric.name,
+                resource_tags: &resource_tags,
+                rows: &mut rows,
+            };
+            converter.convert(data);
+        }
+    }
+    rows
+}
+
+/// Converts the data points of a single metric into rows
+struct MetricConverter<'a> {
+    table_name: String,
+    resource_tags: &'a BTreeMap<String, String>,
+    rows: &'a mut Vec<WriteRow>,
+}
+
+impl MetricConverter<'_> {
+    fn convert(&mut self, data: metric::Data) {
+        match data {
+            metric::Data::Gauge(gauge) => {
+                for dp in gauge.data_points {
+                    self.push_number(dp, "gauge");
+                }
+            }
+            metric::Data::Sum(sum) => {
+                let field_name = if sum.is_monotonic { "counter" } else { "gauge" };
+                for dp in sum.data_points {
+                    self.push_number(dp, field_name);
+                }
+            }
+            metric::Data::Histogram(histogram) => {
+                for dp in histogram.data_points {
+                    let tags = self.tags(dp.attributes);
+                    let timestamp = data_point_timestamp(dp.time_unix_nano);
+                    let mut fields = vec![("count".to_string(), WriteFieldValue::U64(dp.count))];
+                    push_optional_floats(
+                        &mut fields,
+                        [("sum", dp.sum), ("min", dp.min), ("max", dp.max)],
+                    );
+
+                    // bucket counts are cumulative, to match the semantics of the `le` tag:
+                    let mut cumulative_count = 0;
+                    for (i, count) in dp.bucket_counts.iter().enumerate() {
+                        cumulative_count += count;
+                        let le = dp
+                            .explicit_bounds
+                            .get(i)
+                            .map(|b| b.to_string())
+                            .unwrap_or_else(|| "+Inf".to_string());
+                        self.push_row(
+                            with_tag(&tags, "le", le),
+                            vec![("bucket".to_string(), WriteFieldValue::U64(cumulative_count))],
+                            timestamp,
+                        );
+                    }
+                    self.push_row(tags, fields, timestamp);
+                }
+            }
+            metric::Data::ExponentialHistogram(histogram) => {
+                for dp in histogram.data_points {
+                    let tags = self.tags(dp.attributes);
+                    let mut fields = vec![("count".to_string(), WriteFieldValue::U64(dp.count))];
+                    push_optional_floats(
+                        &mut fields,
+                        [("sum", dp.sum), ("min", dp.min), ("max", dp.max)],
+                    );
+                    self.push_row(tags, fields, data_point_timestamp(dp.time_unix_nano));
+                }
+            }
+            metric::Data::Summary(summary) => {
+                for dp in summary.data_points {
+                    let tags = self.tags(dp.attributes);
+                    let timestamp = data_point_timestamp(dp.time_unix_nano);
+                    for q in dp.quantile_values {
+                        self.push_row(
+                            with_tag(&tags, "quantile", q.quantile.to_string()),
+                            vec![("value".to_string(), WriteFieldValue::F64(q.value))],
+                            timestamp,
+                        );
+                    }
+                    self.push_row(
+                        tags,
+                        vec![
+                            ("count".to_string(), WriteFieldValue::U64(dp.count)),
+                            ("sum".to_string(), WriteFieldValue::F64(dp.sum)),
+                        ],
+                        timestamp,
+                    );
+                }
+            }
+        }
+    }
+
+    fn push_number(&mut self, dp: NumberDataPoint, field_name: &str) {
+        let value = match dp.value {
+            Some(number_data_point::Value::AsDouble(v)) => WriteFieldValue::F64(v),
+            Some(number_data_point::Value::AsInt(v)) => WriteFieldValue::I64(v),
+            None => return,
+        };
+        let tags = self.tags(dp.attributes);
+        self.push_row(
+            tags,
+            vec![(field_name.to_string(), value)],
+            data_point_timestamp(dp.time_unix_nano),
+        );
+    }
+
+    fn tags(&self, attributes: Vec<KeyValue>) -> BTreeMap<String, String> {
+        attributes_to_tags(attributes, self.resource_tags.clone())
+    }
+
+    fn push_row(
+        &mut self,
+        tags: BTreeMap<String, String>,
+        fields: Vec<(String, WriteFieldValue)>,
+        timestamp: Option<i64>,
+    ) {
+        self.rows.push(WriteRow {
+            table_name: self.table_name.clone(),
+            tags: tags.into_iter().collect(),
+            fields,
+            timestamp,
+        });
+    }
+}
+
+/// Add the attributes to the set of tags, skipping any with empty or non-scalar values, which
+/// cannot be represented as a tag
+fn attributes_to_tags(
+    attributes: Vec<KeyValue>,
+    mut tags: BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    for KeyValue { key, value } in attributes {
+        let value = match value.and_then(|v| v.value) {
+            Some(any_value::Value::StringValue(s)) => s,
+            Some(any_value::Value::BoolValue(b)) => b.to_string(),
+            Some(any_value::Value::IntValue(i)) => i.to_string(),
+            Some(any_value::Value::DoubleValue(d)) => d.to_string(),
+            Some(any_value::Value::BytesValue(b)) => hex::encode(b),
+            Some(any_value::Value::ArrayValue(_) | any_value::Value::KvlistValue(_)) | None => {
+                continue
+            }
+        };
+        if key.is_empty() || value.is_empty() {
+            continue;
+        }
+        tags.insert(key, value);
+    }
+    tags
+}
+
+fn with_tag(tags: &BTreeMap<String, String>, key: &str, value: String) -> BTreeMap<String, String> {
+    let mut tags = tags.clone();
+    tags.insert(key.to_string(), value);
+    tags
+}
+
+fn push_optional_floats<const N: usize>(
+    fields: &mut Vec<(String, WriteFieldValue)>,
+    values: [(&str, Option<f64>); N],
+) {
+    for (name, value) in values {
+        if let Some(v) = value {
+            fields.push((name.to_string(), WriteFieldValue::F64(v)));
+        }
+    }
+}
+
+/// Data points without a timestamp are given the ingest time
+fn data_point_timestamp(time_unix_nano: u64) -> Option<i64> {
+    (time_unix_nano != 0).then(|| i64::try_from(time_unix_nano).unwrap_or(i64::MAX))
+}
+
+/// Write the metrics in the request to the given database, returning the response to send back
+/// to the client. Invalid data points are rejected, but do not fail the entire request.
+pub(crate) async fn write_metrics(
+    write_buffer: &dyn WriteBuffer,
+    database: NamespaceName<'static>,
+    request: ExportMetricsServiceRequest,
+    time_provider: &dyn TimeProvider,
+) -> Result<(BufferedWriteRequest, ExportMetricsServiceResponse), WriteBufferError> {
+    let rows = metrics_request_to_rows(request);
+    debug!(%database, rows = rows.len(), "writing otlp metrics");
+    let result = write_buffer
+        .write_rows(
+            database,
+            rows,
+            time_provider.now(),
+            true,
+            Precision::Nanosecond,
+        )
+        .await?;
+
+    let partial_success = (!result.invalid_lines.is_empty()).then(|| ExportMetricsPartialSuccess {
+        rejected_data_points: result.invalid_lines.len() as i64,
+        error_message: result
+            .invalid_lines
+            .iter()
+            .map(|e| e.error_message.as_str())
+            .collect::<Vec<_>>()
+            .join("; "),
+    });
+    Ok((result, ExportMetricsServiceResponse { partial_success }))
+}
+
+/// The OTLP metrics gRPC service
+#[derive(Debug)]
+pub(crate) struct OtlpMetricsService<T> {
+    write_buffer: Arc<dyn WriteBuffer>,
+    time_provider: Arc<T>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+pub(crate) fn make_otlp_metrics_server<T: TimeProvider>(
+    write_buffer: Arc<dyn WriteBuffer>,
+    time_provider: Arc<T>,
+    authorizer: Arc<dyn Authorizer>,
+) -> MetricsServiceServer<OtlpMetricsService<T>> {
+    MetricsServiceServer::new(OtlpMetricsService {
+        write_buffer,
+        time_provider,
+        authorizer,
+    })
+}
+
+impl<T: TimeProvider> OtlpMetricsService<T> {
+    async fn authorize<B>(&self, request: &Request<B>) -> Result<(), Status> {
+        let token = request
+            .metadata()
+            .get("authorization")
+            .map(|v| {
+                HeaderValue::from_bytes(v.as_bytes())
+                    .map_err(|_| AuthorizationError::MalformedRequest)
+                    .and_then(validate_auth_header)
+            })
+            .transpose()
+            .map_err(authorization_error_to_status)?;
+        self.authorizer
+            .permissions(token, &[])
+            .await
+            .map_err(|e| authorization_error_to_status(e.into()))?;
+        Ok(())
+    }
+}
+
+fn authorization_error_to_status(error: AuthorizationError) -> Status {
+    match error {
+        AuthorizationError::Unauthorized => Status::unauthenticated(error.to_string()),
+        AuthorizationError::Forbidden => Status::permission_denied(error.to_string()),
+        AuthorizationError::MalformedRequest | AuthorizationError::ToStr(_) => {
+            Status::invalid_argument(error.to_string())
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl<T: TimeProvider> MetricsService for OtlpMetricsService<T> {
+    async fn export(
+        &self,
+        request: Request<ExportMetricsServiceRequest>,
+    ) -> Result<Response<ExportMetricsServiceResponse>, Status> {
+        self.authorize(&request).await?;
+        let database = request
+            .metadata()
+            .get(DATABASE_METADATA_KEY)
+            .ok_or_else(|| {
+                Status::invalid_argument(format!(
+                    "the database to write to must be provided in the '{DATABASE_METADATA_KEY}' \
+                    metadata key"
+                ))
+            })?
+            .to_str()
+            .map_err(|e| Status::invalid_argument(e.to_string()))?
+            .to_string();
+        let database =
+            NamespaceName::new(database).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        info!("otlp metrics export to {}", database);
+
+        let (_, response) = write_metrics(
+            self.write_buffer.as_ref(),
+            database,
+            request.into_inner(),
+            self.time_provider.as_ref(),
+        )
+        .await
+        .map_err(|e| match e {
+            WriteBufferError::BufferFull { .. } => Status::unavailable(e.to_string()),
+            WriteBufferError::RateLimited { .. } => Status::resource_exhausted(e.to_string()),
+            WriteBufferError::NoWriteInReadOnly | WriteBufferError::NotLeader => {
+                Status::failed_precondition(e.to_string())
+            }
+            e => Status::internal(e.to_string()),
+        })?;
+        Ok(Response::new(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry_proto::tonic::{
+        common::v1::AnyValue,
+        metrics::v1::{
+            Gauge, Histogram, HistogramDataPoint, Metric, ResourceMetrics, ScopeMetrics, Sum,
+        },
+        resource::v1::Resource,
+    };
+
+    use super::*;
+
+    fn kv(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue {
+                value: Some(any_value::Value::StringValue(value.to_string())),
+            }),
+        }
+    }
+
+    fn request(metrics: Vec<Metric>) -> ExportMetricsServiceRequest {
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(Resource {
+                    attributes: vec![kv("service.name", "api"), kv("host", "resource")],
+                    ..Default::default()
+                }),
+                scope_metrics: vec![ScopeMetrics {
+                    metrics,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn tags(tags: &[(&str, &str)]) -> Vec<(String, String)> {
+        tags.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn convert_gauge_and_sum() {
+        let rows = metrics_request_to_rows(request(vec![
+            Metric {
+                name: "cpu_usage".to_string(),
+                data: Some(metric::Data::Gauge(Gauge {
+                    data_points: vec![NumberDataPoint {
+                        attributes: vec![kv("host", "a")],
+                        time_unix_nano: 10,
+                        value: Some(number_data_point::Value::AsDouble(0.5)),
+                        ..Default::default()
+                    }],
+                })),
+                ..Default::default()
+            },
+            Metric {
+                name: "requests".to_string(),
+                data: Some(metric::Data::Sum(Sum {
+                    data_points: vec![NumberDataPoint {
+                        time_unix_nano: 0,
+                        value: Some(number_data_point::Value::AsInt(7)),
+                        ..Default::default()
+                    }],
+                    is_monotonic: true,
+                    ..Default::default()
+                })),
+                ..Default::default()
+            },
+        ]));
+
+        assert_eq!(
+            rows,
+            vec![
+                WriteRow {
+                    table_name: "cpu_usage".to_string(),
+                    // the data point attribute overrides the resource attribute:
+                    tags: tags(&[("host", "a"), ("service.name", "api")]),
+                    fields: vec![("gauge".to_string(), WriteFieldValue::F64(0.5))],
+                    timestamp: Some(10),
+                },
+                WriteRow {
+                    table_name: "requests".to_string(),
+                    tags: tags(&[("host", "resource"), ("service.name", "api")]),
+                    fields: vec![("counter".to_string(), WriteFieldValue::I64(7))],
+                    timestamp: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn convert_histogram() {
+        let rows = metrics_request_to_rows(request(vec![Metric {
+            name: "latency".to_string(),
+            data: Some(metric::Data::Histogram(Histogram {
+                data_points: vec![HistogramDataPoint {
+                    time_unix_nano: 10,
+                    count: 6,
+                    sum: Some(12.5),
+                    bucket_counts: vec![1, 2, 3],
+                    explicit_bounds: vec![1.0, 5.0],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })),
+            ..Default::default()
+        }]));
+
+        let bucket = |le: &str, count: u64| WriteRow {
+            table_name: "latency".to_string(),
+            tags: tags(&[("host", "resource"), ("le", le), ("service.name", "api")]),
+            fields: vec![("bucket".to_string(), WriteFieldValue::U64(count))],
+            timestamp: Some(10),
+        };
+        assert_eq!(
+            rows,
+            vec![
+                bucket("1", 1),
+                bucket("5", 3),
+                bucket("+Inf", 6),
+                WriteRow {
+                    table_name: "latency".to_string(),
+                    tags: tags(&[("host", "resource"), ("service.name", "api")]),
+                    fields: vec![
+                        ("count".to_string(), WriteFieldValue::U64(6)),
+                        ("sum".to_string(), WriteFieldValue::F64(12.5)),
+                    ],
+                    timestamp: Some(10),
+                },
+            ]
+        );
+    }
+}
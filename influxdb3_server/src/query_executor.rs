@@ -14,12 +14,16 @@ use datafusion::datasource::{TableProvider, TableType};
 use datafusion::error::DataFusionError;
 use datafusion::execution::SendableRecordBatchStream;
 use datafusion::logical_expr::TableProviderFilterPushDown;
+use datafusion::physical_expr::expressions::{col as physical_col, is_not_null, CaseExpr};
+use datafusion::physical_plan::projection::ProjectionExec;
 use datafusion::physical_plan::ExecutionPlan;
-use datafusion::prelude::Expr;
+use datafusion::prelude::{col, lit, Expr};
+use datafusion::scalar::ScalarValue;
 use datafusion_util::config::DEFAULT_SCHEMA;
 use datafusion_util::MemoryStream;
-use influxdb3_catalog::catalog::{Catalog, DatabaseSchema};
+use influxdb3_catalog::catalog::{Catalog, DatabaseSchema, TableDefinition};
 use influxdb3_telemetry::store::TelemetryStore;
+use influxdb3_write::distinct_cache::DistinctCacheFunction;
 use influxdb3_write::last_cache::LastCacheFunction;
 use influxdb3_write::WriteBuffer;
 use iox_query::exec::{Executor, IOxSessionContext, QueryConfig};
@@ -35,7 +39,7 @@ use iox_query_influxql::frontend::planner::InfluxQLQueryPlanner;
 use iox_query_params::StatementParams;
 use metric::Registry;
 use observability_deps::tracing::{debug, info};
-use schema::Schema;
+use schema::{Schema, TIME_COLUMN_NAME};
 use std::any::Any;
 use std::collections::HashMap;
 use std::fmt::Debug;
@@ -420,7 +424,9 @@ impl QueryNamespace for Database {
             return Ok(vec![]);
         };
 
-        table.chunks(&ctx.inner().state(), projection, filters, None)
+        table
+            .chunks(&ctx.inner().state(), projection, filters, None)
+            .await
     }
 
     fn retention_time_ns(&self) -> Option<i64> {
@@ -469,6 +475,13 @@ impl QueryNamespace for Database {
                 self.write_buffer.last_cache_provider(),
             )),
         );
+        ctx.inner().register_udtf(
+            DISTINCT_CACHE_UDTF_NAME,
+            Arc::new(DistinctCacheFunction::new(
+                self.db_schema.id,
+                self.write_buffer.distinct_cache_provider(),
+            )),
+        );
         ctx
     }
 
@@ -483,6 +496,7 @@ impl QueryNamespace for Database {
 }
 
 const LAST_CACHE_UDTF_NAME: &str = "last_cache";
+const DISTINCT_CACHE_UDTF_NAME: &str = "distinct_cache";
 
 impl CatalogProvider for Database {
     fn as_any(&self) -> &dyn Any {
@@ -539,13 +553,28 @@ pub struct QueryTable {
 }
 
 impl QueryTable {
-    fn chunks(
+    /// The filters of a scan, along with one on the time of the rows, if the database has a
+    /// retention period, since the buffer chunks and files that have rows from before it may
+    /// have newer rows too, and are queried
+    fn filters_with_retention(&self, filters: &[Expr]) -> Vec<Expr> {
+        let mut filters = filters.to_vec();
+        if let Some(cutoff_ns) = self.write_buffer.retention_cutoff_ns(&self.db_schema.name) {
+            filters.push(
+                col(TIME_COLUMN_NAME)
+                    .gt_eq(lit(ScalarValue::TimestampNanosecond(Some(cutoff_ns), None))),
+            );
+        }
+        filters
+    }
+
+    async fn chunks(
         &self,
         ctx: &dyn Session,
         projection: Option<&Vec<usize>>,
         filters: &[Expr],
         _limit: Option<usize>,
     ) -> Result<Vec<Arc<dyn QueryChunk>>, DataFusionError> {
+        self.write_buffer.load_persisted_files(filters).await?;
         self.write_buffer.get_table_chunks(
             &self.db_schema.name,
             &self.table_name,
@@ -554,6 +583,102 @@ impl QueryTable {
             ctx,
         )
     }
+
+    /// Scan a table that has renamed columns. Files persisted before a column was renamed have
+    /// it under an old name, so the old names are scanned as well, and the projection is
+    /// rewritten to take the value of each renamed column from whichever of its names has one.
+    async fn scan_with_renamed_columns(
+        &self,
+        ctx: &dyn Session,
+        table_def: &TableDefinition,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> datafusion::common::Result<Arc<dyn ExecutionPlan>> {
+        let renamed_columns = table_def.renamed_columns().collect::<Vec<_>>();
+        let table_schema = self.schema.as_arrow();
+        let parquet_schema = table_def.parquet_schema();
+        let parquet_arrow_schema = parquet_schema.as_arrow();
+
+        let output_columns = match projection {
+            Some(projection) => projection
+                .iter()
+                .map(|i| table_schema.field(*i).name().clone())
+                .collect::<Vec<_>>(),
+            None => table_schema
+                .fields()
+                .iter()
+                .map(|field| field.name().clone())
+                .collect(),
+        };
+        // each output column is scanned under all of its names, current name first:
+        let column_names = output_columns
+            .iter()
+            .map(|name| {
+                let old_names = renamed_columns
+                    .iter()
+                    .filter(|(_, new_name)| new_name.as_ref() == name.as_str())
+                    .map(|(old_name, _)| old_name.to_string());
+                std::iter::once(name.clone())
+                    .chain(old_names)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let scan_projection = column_names
+            .iter()
+            .flatten()
+            .map(|name| parquet_arrow_schema.index_of(name))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // a renamed column is null in the files that have it under an old name, so filters on
+        // it cannot be pushed down; they are still applied to the output of the scan, since
+        // pushdown is inexact:
+        let filters = filters
+            .iter()
+            .filter(|filter| {
+                !filter.column_refs().iter().any(|column| {
+                    renamed_columns
+                        .iter()
+                        .any(|(_, new_name)| new_name.as_ref() == column.name())
+                })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut builder = ProviderBuilder::new(Arc::clone(&self.table_name), parquet_schema);
+        for chunk in self.chunks(ctx, None, &filters, limit).await? {
+            builder = builder.add_chunk(chunk);
+        }
+        let provider = match builder.build() {
+            Ok(provider) => provider,
+            Err(e) => panic!("unexpected error: {e:?}"),
+        };
+        let scan = provider
+            .scan(ctx, Some(&scan_projection), &filters, limit)
+            .await?;
+
+        let scan_schema = scan.schema();
+        let exprs = output_columns
+            .iter()
+            .zip(&column_names)
+            .map(|(output_column, names)| {
+                let mut names = names.iter().rev();
+                let last = names.next().expect("columns have at least one name");
+                let mut expr = physical_col(last, &scan_schema)?;
+                for name in names {
+                    let column = physical_col(name, &scan_schema)?;
+                    expr = Arc::new(CaseExpr::try_new(
+                        None,
+                        vec![(is_not_null(Arc::clone(&column))?, column)],
+                        Some(expr),
+                    )?);
+                }
+                Ok((expr, output_column.clone()))
+            })
+            .collect::<datafusion::common::Result<Vec<_>>>()?;
+
+        Ok(Arc::new(ProjectionExec::try_new(exprs, scan)?))
+    }
 }
 
 #[async_trait]
@@ -584,16 +709,26 @@ impl TableProvider for QueryTable {
         filters: &[Expr],
         limit: Option<usize>,
     ) -> datafusion::common::Result<Arc<dyn ExecutionPlan>> {
-        let filters = filters.to_vec();
+        let filters = self.filters_with_retention(filters);
         debug!(
             ?projection,
             ?filters,
             ?limit,
             "QueryTable as TableProvider::scan"
         );
+        if let Some(table_def) = self
+            .db_schema
+            .table_definition(Arc::clone(&self.table_name))
+            .filter(|table_def| table_def.renamed_columns().next().is_some())
+        {
+            return self
+                .scan_with_renamed_columns(ctx, &table_def, projection, &filters, limit)
+                .await;
+        }
+
         let mut builder = ProviderBuilder::new(Arc::clone(&self.table_name), self.schema.clone());
 
-        let chunks = self.chunks(ctx, projection, &filters, limit)?;
+        let chunks = self.chunks(ctx, projection, &filters, limit).await?;
         for chunk in chunks {
             builder = builder.add_chunk(chunk);
         }
@@ -608,7 +743,7 @@ impl TableProvider for QueryTable {
 }
 #[cfg(test)]
 mod tests {
-    use std::{num::NonZeroUsize, sync::Arc, time::Duration};
+    use std::{collections::BTreeMap, num::NonZeroUsize, sync::Arc, time::Duration};
 
     use arrow::array::RecordBatch;
     use data_types::NamespaceName;
@@ -621,8 +756,10 @@ mod tests {
         last_cache::LastCacheProvider,
         parquet_cache::test_cached_obj_store_and_oracle,
         persister::Persister,
-        write_buffer::{persisted_files::PersistedFiles, WriteBufferImpl},
-        WriteBuffer,
+        write_buffer::{
+            persisted_files::PersistedFiles, BufferMode, WriteBufferImpl, WriteBufferImplArgs,
+        },
+        Precision, WriteBuffer,
     };
     use iox_query::exec::{DedicatedExecutor, Executor, ExecutorConfig};
     use iox_time::{MockProvider, Time};
@@ -658,7 +795,7 @@ mod tests {
         ))
     }
 
-    async fn setup() -> (Arc<dyn WriteBuffer>, QueryExecutorImpl, Arc<MockProvider>) {
+    async fn setup() -> (Arc<WriteBufferImpl>, QueryExecutorImpl, Arc<MockProvider>) {
         // Set up QueryExecutor
         let object_store: Arc<dyn ObjectStore> =
             Arc::new(LocalFileSystem::new_with_prefix(test_helpers::tmp_dir().unwrap()).unwrap());
@@ -671,7 +808,7 @@ mod tests {
         let instance_id = Arc::from("instance-id");
         let catalog = Arc::new(Catalog::new(host_id, instance_id));
         let write_buffer_impl = Arc::new(
-            WriteBufferImpl::new(
+            WriteBufferImpl::new(WriteBufferImplArgs::new(
                 Arc::clone(&persister),
                 Arc::clone(&catalog),
                 LastCacheProvider::new_from_catalog(catalog as _).unwrap(),
@@ -682,21 +819,27 @@ mod tests {
                     max_write_buffer_size: 100,
                     flush_interval: Duration::from_millis(10),
                     snapshot_size: 1,
+                    compression: Default::default(),
+                    max_file_size_bytes: None,
+                    retention: Default::default(),
+                    ack_level: Default::default(),
+                    snapshot_triggers: Default::default(),
+                    handoff_max_bytes: None,
                 },
                 Some(parquet_cache),
-            )
+            ))
             .await
             .unwrap(),
         );
 
         let persisted_files: Arc<PersistedFiles> = Arc::clone(&write_buffer_impl.persisted_files());
         let telemetry_store = TelemetryStore::new_without_background_runners(persisted_files);
-        let write_buffer: Arc<dyn WriteBuffer> = write_buffer_impl;
+        let write_buffer: Arc<dyn WriteBuffer> = Arc::<WriteBufferImpl>::clone(&write_buffer_impl);
         let metrics = Arc::new(Registry::new());
         let datafusion_config = Arc::new(Default::default());
         let query_executor = QueryExecutorImpl::new(CreateQueryExecutorArgs {
             catalog: write_buffer.catalog(),
-            write_buffer: Arc::clone(&write_buffer),
+            write_buffer,
             exec,
             metrics,
             datafusion_config,
@@ -705,7 +848,7 @@ mod tests {
             telemetry_store,
         });
 
-        (write_buffer, query_executor, time_provider)
+        (write_buffer_impl, query_executor, time_provider)
     }
 
     #[test_log::test(tokio::test)]
@@ -782,6 +925,90 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn system_tables_columns_and_wal_files() {
+        let (write_buffer, query_executor, _) = setup().await;
+        let db_name = "test_db";
+        write_buffer
+            .write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                "\
+                cpu,host=a usage=0.5 1\n\
+                mem,host=a,region=us-east free=2i 1\n\
+                ",
+                Time::from_timestamp_nanos(0),
+                false,
+                influxdb3_write::Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        write_buffer
+            .set_table_metadata(
+                db_name,
+                "cpu",
+                BTreeMap::from([("owner".to_string(), "team-a".to_string())]),
+            )
+            .await
+            .unwrap();
+
+        let query = |query: &'static str| {
+            let query_executor = &query_executor;
+            async move {
+                let batch_stream = query_executor
+                    .query(db_name, query, None, crate::QueryKind::Sql, None, None)
+                    .await
+                    .unwrap();
+                batch_stream
+                    .try_collect::<Vec<RecordBatch>>()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert_batches_sorted_eq!(
+            [
+                "+------------+--------------+------------------+------------+--------------------+",
+                "| table_name | column_count | last_cache_count | deleted_at | metadata           |",
+                "+------------+--------------+------------------+------------+--------------------+",
+                "| cpu        | 3            | 0                |            | {\"owner\":\"team-a\"} |",
+                "| mem        | 4            | 0                |            |                    |",
+                "+------------+--------------+------------------+------------+--------------------+",
+            ],
+            &query(
+                "SELECT table_name, column_count, last_cache_count, deleted_at, metadata \
+                 FROM system.tables"
+            )
+            .await
+        );
+        assert_batches_sorted_eq!(
+            [
+                "+------------+-------------+-------------+",
+                "| table_name | column_name | column_type |",
+                "+------------+-------------+-------------+",
+                "| cpu        | host        | tag         |",
+                "| cpu        | time        | timestamp   |",
+                "| cpu        | usage       | float       |",
+                "| mem        | free        | integer     |",
+                "| mem        | host        | tag         |",
+                "| mem        | region      | tag         |",
+                "| mem        | time        | timestamp   |",
+                "+------------+-------------+-------------+",
+            ],
+            &query("SELECT table_name, column_name, column_type FROM system.columns").await
+        );
+        // the WAL files may have been snapshot already, but none of them are corrupt:
+        assert_batches_sorted_eq!(
+            [
+                "+----------+",
+                "| count(*) |",
+                "+----------+",
+                "| 0        |",
+                "+----------+",
+            ],
+            &query("SELECT count(*) FROM system.wal_files WHERE corrupt_entries > 0").await
+        );
+    }
+
     #[tokio::test]
     async fn system_parquet_files_predicate_error() {
         let (write_buffer, query_executor, time_provider) = setup().await;
@@ -810,4 +1037,192 @@ mod tests {
         let error: DataFusionError = stream.try_collect::<Vec<RecordBatch>>().await.unwrap_err();
         assert_eq!(error.message(), table_name_predicate_error().message());
     }
+
+    #[tokio::test]
+    async fn query_deduplicates_rows_across_buffer_and_files() {
+        let (write_buffer, query_executor, _) = setup().await;
+        let db_name = "test_db";
+        let write = |write_buffer: &WriteBufferImpl, lp: &'static str| {
+            write_buffer.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                lp,
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+        };
+        let query = || {
+            let query_executor = &query_executor;
+            async move {
+                let stream = query_executor
+                    .query(
+                        db_name,
+                        "SELECT * FROM cpu",
+                        None,
+                        crate::QueryKind::Sql,
+                        None,
+                        None,
+                    )
+                    .await
+                    .unwrap();
+                stream.try_collect::<Vec<RecordBatch>>().await.unwrap()
+            }
+        };
+
+        // the same row is persisted to two files, which overlap:
+        for lp in ["cpu,host=a usage=1 1", "cpu,host=a usage=2 1"] {
+            write(&write_buffer, lp).await.unwrap();
+            write_buffer.set_mode(BufferMode::DrainAndPersist).await;
+            write_buffer.set_mode(BufferMode::ReadWrite).await;
+        }
+        assert_batches_sorted_eq!(
+            [
+                "+------+--------------------------------+-------+",
+                "| host | time                           | usage |",
+                "+------+--------------------------------+-------+",
+                "| a    | 1970-01-01T00:00:00.000000001Z | 2.0   |",
+                "+------+--------------------------------+-------+",
+            ],
+            &query().await
+        );
+
+        // and written again to the buffer, which is newer than either file:
+        write(&write_buffer, "cpu,host=a usage=3 1").await.unwrap();
+        assert_batches_sorted_eq!(
+            [
+                "+------+--------------------------------+-------+",
+                "| host | time                           | usage |",
+                "+------+--------------------------------+-------+",
+                "| a    | 1970-01-01T00:00:00.000000001Z | 3.0   |",
+                "+------+--------------------------------+-------+",
+            ],
+            &query().await
+        );
+    }
+
+    #[tokio::test]
+    async fn query_filters_rows_older_than_the_retention_period() {
+        let (write_buffer, query_executor, time_provider) = setup().await;
+        let db_name = "test_db";
+        time_provider.set(Time::from_timestamp(100, 0).unwrap());
+        write_buffer
+            .write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                "cpu,host=a usage=1 65\ncpu,host=b usage=2 95",
+                Time::from_timestamp(100, 0).unwrap(),
+                false,
+                Precision::Second,
+            )
+            .await
+            .unwrap();
+        // both rows are in the same chunk, which has data from inside the retention period:
+        write_buffer
+            .set_retention_period(db_name, Some(Duration::from_secs(30)))
+            .await
+            .unwrap();
+
+        let query = || {
+            let query_executor = &query_executor;
+            async move {
+                let stream = query_executor
+                    .query(
+                        db_name,
+                        "SELECT * FROM cpu",
+                        None,
+                        crate::QueryKind::Sql,
+                        None,
+                        None,
+                    )
+                    .await
+                    .unwrap();
+                stream.try_collect::<Vec<RecordBatch>>().await.unwrap()
+            }
+        };
+        let expected = [
+            "+------+----------------------+-------+",
+            "| host | time                 | usage |",
+            "+------+----------------------+-------+",
+            "| b    | 1970-01-01T00:01:35Z | 2.0   |",
+            "+------+----------------------+-------+",
+        ];
+        assert_batches_sorted_eq!(expected, &query().await);
+
+        // and the same goes for the file that they are persisted to:
+        write_buffer.set_mode(BufferMode::DrainAndPersist).await;
+        assert_batches_sorted_eq!(expected, &query().await);
+    }
+
+    #[tokio::test]
+    async fn query_renamed_and_dropped_columns() {
+        let (write_buffer, query_executor, _) = setup().await;
+        let db_name = "test_db";
+        let write = |write_buffer: &WriteBufferImpl, lp: &'static str| {
+            write_buffer.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                lp,
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+        };
+
+        // persist one row before the columns are changed, and leave another in the buffer:
+        write(&write_buffer, "cpu,host=a usage=1,idle=9 1")
+            .await
+            .unwrap();
+        write_buffer.set_mode(BufferMode::DrainAndPersist).await;
+        write_buffer.set_mode(BufferMode::ReadWrite).await;
+        write(&write_buffer, "cpu,host=b usage=2,idle=8 2")
+            .await
+            .unwrap();
+
+        write_buffer
+            .rename_column(db_name, "cpu", "usage", "cpu_usage")
+            .await
+            .unwrap();
+        write_buffer
+            .drop_column(db_name, "cpu", "idle")
+            .await
+            .unwrap();
+        write(&write_buffer, "cpu,host=c cpu_usage=3 3")
+            .await
+            .unwrap();
+        // the old names cannot be used for new columns:
+        assert!(write(&write_buffer, "cpu,host=d usage=4 4").await.is_err());
+        assert!(write(&write_buffer, "cpu,host=d idle=4 4").await.is_err());
+
+        let query = |query: &'static str| {
+            let query_executor = &query_executor;
+            async move {
+                let stream = query_executor
+                    .query(db_name, query, None, crate::QueryKind::Sql, None, None)
+                    .await
+                    .unwrap();
+                stream.try_collect::<Vec<RecordBatch>>().await.unwrap()
+            }
+        };
+        assert_batches_sorted_eq!(
+            [
+                "+-----------+------+--------------------------------+",
+                "| cpu_usage | host | time                           |",
+                "+-----------+------+--------------------------------+",
+                "| 1.0       | a    | 1970-01-01T00:00:00.000000001Z |",
+                "| 2.0       | b    | 1970-01-01T00:00:00.000000002Z |",
+                "| 3.0       | c    | 1970-01-01T00:00:00.000000003Z |",
+                "+-----------+------+--------------------------------+",
+            ],
+            &query("SELECT * FROM cpu").await
+        );
+        assert_batches_sorted_eq!(
+            [
+                "+------+-----------+",
+                "| host | cpu_usage |",
+                "+------+-----------+",
+                "| b    | 2.0       |",
+                "| c    | 3.0       |",
+                "+------+-----------+",
+            ],
+            &query("SELECT host, cpu_usage FROM cpu WHERE cpu_usage > 1").await
+        );
+    }
 }
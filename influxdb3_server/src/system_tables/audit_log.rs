@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, RecordBatch, StringArray, TimestampNanosecondArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::{error::DataFusionError, logical_expr::Expr};
+use influxdb3_catalog::catalog::DatabaseSchema;
+use influxdb3_write::{write_buffer::audit::AuditLogEntry, WriteBuffer};
+use iox_system_tables::IoxSystemTable;
+
+pub(super) struct AuditLogTable {
+    db_schema: Arc<DatabaseSchema>,
+    schema: SchemaRef,
+    buffer: Arc<dyn WriteBuffer>,
+}
+
+impl AuditLogTable {
+    pub(super) fn new(db_schema: Arc<DatabaseSchema>, buffer: Arc<dyn WriteBuffer>) -> Self {
+        Self {
+            db_schema,
+            schema: audit_log_schema(),
+            buffer,
+        }
+    }
+}
+
+fn audit_log_schema() -> SchemaRef {
+    let columns = vec![
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Nanosecond, None),
+            false,
+        ),
+        Field::new("operation", DataType::Utf8, false),
+        Field::new("source", DataType::Utf8, false),
+        Field::new("details", DataType::Utf8, false),
+    ];
+    Arc::new(Schema::new(columns))
+}
+
+#[async_trait::async_trait]
+impl IoxSystemTable for AuditLogTable {
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    async fn scan(
+        &self,
+        _filters: Option<Vec<Expr>>,
+        _limit: Option<usize>,
+    ) -> Result<RecordBatch, DataFusionError> {
+        let entries = self
+            .buffer
+            .audit_log()
+            .await
+            .map_err(|e| DataFusionError::External(Box::new(e)))?
+            .into_iter()
+            .filter(|entry| entry.database_name == self.db_schema.name)
+            .collect::<Vec<_>>();
+
+        from_audit_log_entries(self.schema(), &entries)
+    }
+}
+
+fn from_audit_log_entries(
+    schema: SchemaRef,
+    entries: &[AuditLogEntry],
+) -> Result<RecordBatch, DataFusionError> {
+    let details = entries
+        .iter()
+        .map(|e| serde_json::to_string(&e.op).map(Some))
+        .collect::<Result<StringArray, _>>()
+        .map_err(|e| DataFusionError::External(Box::new(e)))?;
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            entries
+                .iter()
+                .map(|e| Some(e.time_ns))
+                .collect::<TimestampNanosecondArray>(),
+        ),
+        Arc::new(
+            entries
+                .iter()
+                .map(|e| Some(e.operation()))
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            entries
+                .iter()
+                .map(|e| Some(e.source.as_str()))
+                .collect::<StringArray>(),
+        ),
+        Arc::new(details),
+    ];
+
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, BooleanArray, RecordBatch, StringArray, UInt32Array};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use datafusion::{error::DataFusionError, logical_expr::Expr};
+use influxdb3_catalog::catalog::{ColumnDefinition, DatabaseSchema};
+use iox_system_tables::IoxSystemTable;
+use schema::{InfluxColumnType, InfluxFieldType};
+
+pub(super) struct ColumnsTable {
+    db_schema: Arc<DatabaseSchema>,
+    schema: SchemaRef,
+}
+
+impl ColumnsTable {
+    pub(super) fn new(db_schema: Arc<DatabaseSchema>) -> Self {
+        Self {
+            db_schema,
+            schema: columns_schema(),
+        }
+    }
+}
+
+fn columns_schema() -> SchemaRef {
+    let columns = vec![
+        Field::new("table_name", DataType::Utf8, false),
+        Field::new("column_name", DataType::Utf8, false),
+        Field::new("column_id", DataType::UInt32, false),
+        Field::new("column_type", DataType::Utf8, false),
+        Field::new("nullable", DataType::Boolean, false),
+    ];
+    Arc::new(Schema::new(columns))
+}
+
+#[async_trait::async_trait]
+impl IoxSystemTable for ColumnsTable {
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    async fn scan(
+        &self,
+        _filters: Option<Vec<Expr>>,
+        _limit: Option<usize>,
+    ) -> Result<RecordBatch, DataFusionError> {
+        let mut tables = self.db_schema.tables().collect::<Vec<_>>();
+        tables.sort_unstable_by_key(|table| table.table_id);
+        let columns = tables
+            .iter()
+            .flat_map(|table| {
+                table
+                    .columns
+                    .values()
+                    .map(|column| (Arc::clone(&table.table_name), column))
+            })
+            .collect::<Vec<_>>();
+        from_column_definitions(self.schema(), &columns)
+    }
+}
+
+/// The name of the type of a column, as it is shown in the system table
+fn column_type_name(column_type: InfluxColumnType) -> &'static str {
+    match column_type {
+        InfluxColumnType::Tag => "tag",
+        InfluxColumnType::Timestamp => "timestamp",
+        InfluxColumnType::Field(InfluxFieldType::Float) => "float",
+        InfluxColumnType::Field(InfluxFieldType::Integer) => "integer",
+        InfluxColumnType::Field(InfluxFieldType::UInteger) => "uinteger",
+        InfluxColumnType::Field(InfluxFieldType::String) => "string",
+        InfluxColumnType::Field(InfluxFieldType::Boolean) => "boolean",
+    }
+}
+
+fn from_column_definitions(
+    schema: SchemaRef,
+    column_defs: &[(Arc<str>, &ColumnDefinition)],
+) -> Result<RecordBatch, DataFusionError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            column_defs
+                .iter()
+                .map(|(table_name, _)| Some(table_name.as_ref()))
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            column_defs
+                .iter()
+                .map(|(_, c)| Some(c.name.as_ref()))
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            column_defs
+                .iter()
+                .map(|(_, c)| Some(c.id.as_u32()))
+                .collect::<UInt32Array>(),
+        ),
+        Arc::new(
+            column_defs
+                .iter()
+                .map(|(_, c)| Some(column_type_name(c.data_type)))
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            column_defs
+                .iter()
+                .map(|(_, c)| Some(c.nullable))
+                .collect::<BooleanArray>(),
+        ),
+    ];
+
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
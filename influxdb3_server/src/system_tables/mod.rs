@@ -8,19 +8,30 @@ use iox_system_tables::SystemTableProvider;
 use parquet_files::ParquetFilesTable;
 use tonic::async_trait;
 
-use self::{last_caches::LastCachesTable, queries::QueriesTable};
+use self::{
+    audit_log::AuditLogTable, columns::ColumnsTable, last_caches::LastCachesTable,
+    queries::QueriesTable, tables::TablesTable, wal_files::WalFilesTable,
+};
 
+mod audit_log;
+mod columns;
 mod last_caches;
 mod parquet_files;
 #[cfg(test)]
 pub(crate) use parquet_files::table_name_predicate_error;
 mod queries;
+mod tables;
+mod wal_files;
 
 pub const SYSTEM_SCHEMA_NAME: &str = "system";
 
 const QUERIES_TABLE_NAME: &str = "queries";
 const LAST_CACHES_TABLE_NAME: &str = "last_caches";
 const PARQUET_FILES_TABLE_NAME: &str = "parquet_files";
+const AUDIT_LOG_TABLE_NAME: &str = "audit_log";
+const TABLES_TABLE_NAME: &str = "tables";
+const COLUMNS_TABLE_NAME: &str = "columns";
+const WAL_FILES_TABLE_NAME: &str = "wal_files";
 
 pub(crate) struct SystemSchemaProvider {
     tables: HashMap<&'static str, Arc<dyn TableProvider>>,
@@ -55,9 +66,26 @@ impl SystemSchemaProvider {
         tables.insert(LAST_CACHES_TABLE_NAME, last_caches);
         let parquet_files = Arc::new(SystemTableProvider::new(Arc::new(ParquetFilesTable::new(
             db_schema.id,
-            buffer,
+            Arc::clone(&buffer),
         ))));
         tables.insert(PARQUET_FILES_TABLE_NAME, parquet_files);
+        let audit_log = Arc::new(SystemTableProvider::new(Arc::new(AuditLogTable::new(
+            Arc::clone(&db_schema),
+            Arc::clone(&buffer),
+        ))));
+        tables.insert(AUDIT_LOG_TABLE_NAME, audit_log);
+        let tables_table = Arc::new(SystemTableProvider::new(Arc::new(TablesTable::new(
+            Arc::clone(&db_schema),
+        ))));
+        tables.insert(TABLES_TABLE_NAME, tables_table);
+        let columns = Arc::new(SystemTableProvider::new(Arc::new(ColumnsTable::new(
+            db_schema,
+        ))));
+        tables.insert(COLUMNS_TABLE_NAME, columns);
+        let wal_files = Arc::new(SystemTableProvider::new(Arc::new(WalFilesTable::new(
+            buffer,
+        ))));
+        tables.insert(WAL_FILES_TABLE_NAME, wal_files);
         Self { tables }
     }
 }
@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use arrow::array::{GenericListBuilder, StringBuilder};
+use arrow_array::{
+    ArrayRef, RecordBatch, StringArray, TimestampNanosecondArray, UInt32Array, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::{error::DataFusionError, logical_expr::Expr};
+use influxdb3_catalog::catalog::{DatabaseSchema, TableDefinition};
+use iox_system_tables::IoxSystemTable;
+
+pub(super) struct TablesTable {
+    db_schema: Arc<DatabaseSchema>,
+    schema: SchemaRef,
+}
+
+impl TablesTable {
+    pub(super) fn new(db_schema: Arc<DatabaseSchema>) -> Self {
+        Self {
+            db_schema,
+            schema: tables_schema(),
+        }
+    }
+}
+
+fn tables_schema() -> SchemaRef {
+    let columns = vec![
+        Field::new("table_name", DataType::Utf8, false),
+        Field::new("table_id", DataType::UInt32, false),
+        Field::new("column_count", DataType::UInt64, false),
+        Field::new(
+            "series_key",
+            DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+            true,
+        ),
+        Field::new("last_cache_count", DataType::UInt64, false),
+        Field::new(
+            "deleted_at",
+            DataType::Timestamp(TimeUnit::Nanosecond, None),
+            true,
+        ),
+        Field::new("metadata", DataType::Utf8, true),
+    ];
+    Arc::new(Schema::new(columns))
+}
+
+#[async_trait::async_trait]
+impl IoxSystemTable for TablesTable {
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    async fn scan(
+        &self,
+        _filters: Option<Vec<Expr>>,
+        _limit: Option<usize>,
+    ) -> Result<RecordBatch, DataFusionError> {
+        let mut tables = self.db_schema.tables().collect::<Vec<_>>();
+        tables.sort_unstable_by_key(|table| table.table_id);
+        from_table_definitions(self.schema(), &tables)
+    }
+}
+
+fn from_table_definitions(
+    schema: SchemaRef,
+    tables: &[Arc<TableDefinition>],
+) -> Result<RecordBatch, DataFusionError> {
+    let mut series_key_arr =
+        GenericListBuilder::<i32, StringBuilder>::with_capacity(StringBuilder::new(), tables.len());
+    for table in tables {
+        match &table.series_key {
+            Some(series_key) => {
+                for column_id in series_key {
+                    series_key_arr
+                        .values()
+                        .append_option(table.column_id_to_name(column_id));
+                }
+                series_key_arr.append(true);
+            }
+            None => series_key_arr.append_null(),
+        }
+    }
+
+    // the metadata of a table is shown as a JSON object, if it has any:
+    let metadata = tables
+        .iter()
+        .map(|t| {
+            (!t.metadata.is_empty())
+                .then(|| serde_json::to_string(&t.metadata))
+                .transpose()
+        })
+        .collect::<Result<StringArray, _>>()
+        .map_err(|e| DataFusionError::External(Box::new(e)))?;
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            tables
+                .iter()
+                .map(|t| Some(t.table_name.as_ref()))
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            tables
+                .iter()
+                .map(|t| Some(t.table_id.as_u32()))
+                .collect::<UInt32Array>(),
+        ),
+        Arc::new(
+            tables
+                .iter()
+                .map(|t| Some(t.columns.len() as u64))
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(series_key_arr.finish()),
+        Arc::new(
+            tables
+                .iter()
+                .map(|t| Some(t.last_caches.len() as u64))
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            tables
+                .iter()
+                .map(|t| t.deleted_at_ns)
+                .collect::<TimestampNanosecondArray>(),
+        ),
+        Arc::new(metadata),
+    ];
+
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
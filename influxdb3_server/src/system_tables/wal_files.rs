@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use datafusion::{error::DataFusionError, logical_expr::Expr};
+use influxdb3_wal::inspect::WalFileSummary;
+use influxdb3_write::WriteBuffer;
+use iox_system_tables::IoxSystemTable;
+
+/// The WAL files of the server that have not been snapshot yet, which hold the writes to every
+/// database, not just the one that is queried
+pub(super) struct WalFilesTable {
+    schema: SchemaRef,
+    buffer: Arc<dyn WriteBuffer>,
+}
+
+impl WalFilesTable {
+    pub(super) fn new(buffer: Arc<dyn WriteBuffer>) -> Self {
+        Self {
+            schema: wal_files_schema(),
+            buffer,
+        }
+    }
+}
+
+fn wal_files_schema() -> SchemaRef {
+    let columns = vec![
+        Field::new("wal_file_number", DataType::UInt64, false),
+        Field::new("path", DataType::Utf8, false),
+        Field::new("size_bytes", DataType::UInt64, false),
+        Field::new("min_time", DataType::Int64, false),
+        Field::new("max_time", DataType::Int64, false),
+        Field::new("write_ops", DataType::UInt64, false),
+        Field::new("catalog_ops", DataType::UInt64, false),
+        Field::new("row_count", DataType::UInt64, false),
+        Field::new("snapshot_sequence_number", DataType::UInt64, true),
+        Field::new("corrupt_entries", DataType::UInt64, false),
+    ];
+    Arc::new(Schema::new(columns))
+}
+
+#[async_trait::async_trait]
+impl IoxSystemTable for WalFilesTable {
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    async fn scan(
+        &self,
+        _filters: Option<Vec<Expr>>,
+        _limit: Option<usize>,
+    ) -> Result<RecordBatch, DataFusionError> {
+        let wal_files = self
+            .buffer
+            .wal_files()
+            .await
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        from_wal_file_summaries(self.schema(), &wal_files)
+    }
+}
+
+fn from_wal_file_summaries(
+    schema: SchemaRef,
+    wal_files: &[WalFileSummary],
+) -> Result<RecordBatch, DataFusionError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            wal_files
+                .iter()
+                .map(|f| Some(f.wal_file_number.as_u64()))
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            wal_files
+                .iter()
+                .map(|f| Some(f.path.as_str()))
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            wal_files
+                .iter()
+                .map(|f| Some(f.size_bytes as u64))
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            wal_files
+                .iter()
+                .map(|f| Some(f.min_timestamp_ns))
+                .collect::<Int64Array>(),
+        ),
+        Arc::new(
+            wal_files
+                .iter()
+                .map(|f| Some(f.max_timestamp_ns))
+                .collect::<Int64Array>(),
+        ),
+        Arc::new(
+            wal_files
+                .iter()
+                .map(|f| Some(f.write_ops as u64))
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            wal_files
+                .iter()
+                .map(|f| Some(f.catalog_ops as u64))
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            wal_files
+                .iter()
+                .map(|f| Some(f.rows as u64))
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            wal_files
+                .iter()
+                .map(|f| {
+                    f.snapshot
+                        .map(|snapshot| snapshot.snapshot_sequence_number.as_u64())
+                })
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            wal_files
+                .iter()
+                .map(|f| Some(f.corrupt_entries.len() as u64))
+                .collect::<UInt64Array>(),
+        ),
+    ];
+
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
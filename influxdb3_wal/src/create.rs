@@ -1,6 +1,6 @@
 //! A set of helper methods for creating WAL operations in tests.
 
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 use influxdb3_id::{ColumnId, DbId};
 
@@ -101,6 +101,8 @@ impl CreateLastCacheOpBuilder {
                 .unwrap_or(LastCacheValueColumnsDef::AllNonKeyColumns),
             count: self.count.unwrap_or_else(|| LastCacheSize::new(1).unwrap()),
             ttl: self.ttl.unwrap_or(3600),
+            aggregates: vec![],
+            auto_key_columns: false,
         })
     }
 }
@@ -133,3 +135,358 @@ pub fn delete_last_cache_op(
         name: cache_name.into(),
     })
 }
+
+pub fn create_distinct_cache_op(
+    table_id: TableId,
+    table_name: impl Into<Arc<str>>,
+    cache_name: impl Into<Arc<str>>,
+    column_ids: impl IntoIterator<Item = ColumnId>,
+    max_cardinality: usize,
+    ttl: u64,
+) -> CatalogOp {
+    CatalogOp::CreateDistinctCache(DistinctCacheDefinition {
+        table_id,
+        table: table_name.into(),
+        name: cache_name.into(),
+        column_ids: column_ids.into_iter().collect(),
+        max_cardinality,
+        ttl,
+    })
+}
+
+pub fn delete_distinct_cache_op(
+    table_id: TableId,
+    table_name: impl Into<Arc<str>>,
+    cache_name: impl Into<Arc<str>>,
+) -> CatalogOp {
+    CatalogOp::DeleteDistinctCache(DistinctCacheDelete {
+        table_name: table_name.into(),
+        table_id,
+        name: cache_name.into(),
+    })
+}
+
+pub fn set_retention_period_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    retention_period: Option<Duration>,
+) -> CatalogOp {
+    CatalogOp::SetRetentionPeriod(RetentionPeriodDefinition {
+        database_id,
+        database_name: db_name.into(),
+        retention_period,
+    })
+}
+
+pub fn set_write_time_limits_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    future_write_limit: Option<Duration>,
+    past_write_limit: Option<Duration>,
+) -> CatalogOp {
+    CatalogOp::SetWriteTimeLimits(WriteTimeLimitsDefinition {
+        database_id,
+        database_name: db_name.into(),
+        future_write_limit,
+        past_write_limit,
+    })
+}
+
+pub fn set_schema_limits_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    schema_limits: SchemaLimits,
+) -> CatalogOp {
+    CatalogOp::SetSchemaLimits(SchemaLimitsDefinition {
+        database_id,
+        database_name: db_name.into(),
+        schema_limits,
+    })
+}
+
+pub fn set_cardinality_limits_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    cardinality_limits: CardinalityLimits,
+) -> CatalogOp {
+    CatalogOp::SetCardinalityLimits(CardinalityLimitsDefinition {
+        database_id,
+        database_name: db_name.into(),
+        cardinality_limits,
+    })
+}
+
+pub fn set_schema_mode_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    schema_mode: SchemaMode,
+) -> CatalogOp {
+    CatalogOp::SetSchemaMode(SchemaModeDefinition {
+        database_id,
+        database_name: db_name.into(),
+        schema_mode,
+    })
+}
+
+pub fn set_field_coercion_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    field_coercion: FieldCoercion,
+) -> CatalogOp {
+    CatalogOp::SetFieldCoercion(FieldCoercionDefinition {
+        database_id,
+        database_name: db_name.into(),
+        field_coercion,
+    })
+}
+
+pub fn set_compatibility_mode_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    compatibility_mode: CompatibilityMode,
+) -> CatalogOp {
+    CatalogOp::SetCompatibilityMode(CompatibilityModeDefinition {
+        database_id,
+        database_name: db_name.into(),
+        compatibility_mode,
+    })
+}
+
+pub fn set_series_key_policy_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    series_key_policy: SeriesKeyPolicy,
+) -> CatalogOp {
+    CatalogOp::SetSeriesKeyPolicy(SeriesKeyPolicyDefinition {
+        database_id,
+        database_name: db_name.into(),
+        series_key_policy,
+    })
+}
+
+pub fn set_identifier_policy_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    identifier_policy: IdentifierPolicy,
+) -> CatalogOp {
+    CatalogOp::SetIdentifierPolicy(IdentifierPolicyDefinition {
+        database_id,
+        database_name: db_name.into(),
+        identifier_policy,
+    })
+}
+
+pub fn set_write_rate_limit_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    write_rate_limit: WriteRateLimit,
+) -> CatalogOp {
+    CatalogOp::SetWriteRateLimit(WriteRateLimitDefinition {
+        database_id,
+        database_name: db_name.into(),
+        write_rate_limit,
+    })
+}
+
+pub fn set_gen1_duration_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    gen1_duration: Option<Gen1Duration>,
+) -> CatalogOp {
+    CatalogOp::SetGen1Duration(Gen1DurationDefinition {
+        database_id,
+        database_name: db_name.into(),
+        gen1_duration,
+    })
+}
+
+pub fn rename_database_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    new_db_name: impl Into<Arc<str>>,
+) -> CatalogOp {
+    CatalogOp::RenameDatabase(RenameDatabaseDefinition {
+        database_id,
+        database_name: db_name.into(),
+        new_database_name: new_db_name.into(),
+    })
+}
+
+pub fn rename_table_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    table_id: TableId,
+    table_name: impl Into<Arc<str>>,
+    new_table_name: impl Into<Arc<str>>,
+) -> CatalogOp {
+    CatalogOp::RenameTable(RenameTableDefinition {
+        database_id,
+        database_name: db_name.into(),
+        table_id,
+        table_name: table_name.into(),
+        new_table_name: new_table_name.into(),
+    })
+}
+
+pub fn mark_table_deleted_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    table_id: TableId,
+    table_name: impl Into<Arc<str>>,
+) -> CatalogOp {
+    CatalogOp::MarkTableDeleted(DeleteTableDefinition {
+        database_id,
+        database_name: db_name.into(),
+        table_id,
+        table_name: table_name.into(),
+    })
+}
+
+pub fn undelete_table_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    table_id: TableId,
+    table_name: impl Into<Arc<str>>,
+) -> CatalogOp {
+    CatalogOp::UndeleteTable(DeleteTableDefinition {
+        database_id,
+        database_name: db_name.into(),
+        table_id,
+        table_name: table_name.into(),
+    })
+}
+
+pub fn hard_delete_table_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    table_id: TableId,
+    table_name: impl Into<Arc<str>>,
+) -> CatalogOp {
+    CatalogOp::HardDeleteTable(DeleteTableDefinition {
+        database_id,
+        database_name: db_name.into(),
+        table_id,
+        table_name: table_name.into(),
+    })
+}
+
+pub fn drop_column_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    table_id: TableId,
+    table_name: impl Into<Arc<str>>,
+    column_id: ColumnId,
+    column_name: impl Into<Arc<str>>,
+) -> CatalogOp {
+    CatalogOp::DropColumn(DropColumnDefinition {
+        database_id,
+        database_name: db_name.into(),
+        table_id,
+        table_name: table_name.into(),
+        column_id,
+        column_name: column_name.into(),
+    })
+}
+
+pub fn rename_column_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    table_id: TableId,
+    table_name: impl Into<Arc<str>>,
+    column_id: ColumnId,
+    column_name: impl Into<Arc<str>>,
+    new_column_name: impl Into<Arc<str>>,
+) -> CatalogOp {
+    CatalogOp::RenameColumn(RenameColumnDefinition {
+        database_id,
+        database_name: db_name.into(),
+        table_id,
+        table_name: table_name.into(),
+        column_id,
+        column_name: column_name.into(),
+        new_column_name: new_column_name.into(),
+    })
+}
+
+pub fn set_database_metadata_op<'a>(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    metadata: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> CatalogOp {
+    CatalogOp::SetDatabaseMetadata(DatabaseMetadataDefinition {
+        database_id,
+        database_name: db_name.into(),
+        metadata: metadata_map(metadata),
+    })
+}
+
+pub fn set_table_metadata_op<'a>(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    table_id: TableId,
+    table_name: impl Into<Arc<str>>,
+    metadata: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> CatalogOp {
+    CatalogOp::SetTableMetadata(TableMetadataDefinition {
+        database_id,
+        database_name: db_name.into(),
+        table_id,
+        table_name: table_name.into(),
+        metadata: metadata_map(metadata),
+    })
+}
+
+pub fn set_column_default_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    table_id: TableId,
+    table_name: impl Into<Arc<str>>,
+    column_id: ColumnId,
+    column_name: impl Into<Arc<str>>,
+    default: Option<FieldData>,
+) -> CatalogOp {
+    CatalogOp::SetColumnDefault(ColumnDefaultDefinition {
+        database_id,
+        database_name: db_name.into(),
+        table_id,
+        table_name: table_name.into(),
+        column_id,
+        column_name: column_name.into(),
+        default,
+    })
+}
+
+pub fn create_downsampling_task_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    source_table_name: impl Into<Arc<str>>,
+    task: DownsamplingTask,
+) -> CatalogOp {
+    CatalogOp::CreateDownsamplingTask(DownsamplingTaskDefinition {
+        database_id,
+        database_name: db_name.into(),
+        source_table_name: source_table_name.into(),
+        task,
+    })
+}
+
+pub fn delete_downsampling_task_op(
+    database_id: DbId,
+    db_name: impl Into<Arc<str>>,
+    task_name: impl Into<Arc<str>>,
+) -> CatalogOp {
+    CatalogOp::DeleteDownsamplingTask(DeleteDownsamplingTaskDefinition {
+        database_id,
+        database_name: db_name.into(),
+        task_name: task_name.into(),
+    })
+}
+
+fn metadata_map<'a>(
+    metadata: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> BTreeMap<String, String> {
+    metadata
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
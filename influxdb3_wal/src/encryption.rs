@@ -0,0 +1,290 @@
+//! Encryption at rest of the files written to object storage, i.e., WAL files, and the catalog
+//! and snapshot files written by the persister, using AES-256-GCM.
+//!
+//! Encrypted files start with [`ENCRYPTED_FILE_IDENTIFIER`], followed by the id of the key that
+//! they were encrypted with, the nonce, and then the encrypted data and its authentication tag.
+//! Files that do not start with the identifier are read as they are, so files written before
+//! encryption was enabled can still be read.
+
+use std::{collections::HashMap, fmt::Debug};
+
+use bytes::Bytes;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use thiserror::Error;
+
+/// The first bytes of an encrypted file
+pub const ENCRYPTED_FILE_IDENTIFIER: &[u8] = b"idb3enc1";
+
+/// The length, in bytes, of an AES-256 key
+pub const KEY_LEN: usize = 32;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("file is encrypted, but no encryption key is configured")]
+    NoKeyProvider,
+
+    #[error("encryption key {0} is not known")]
+    UnknownKey(String),
+
+    #[error("invalid encryption key: {0}")]
+    InvalidKey(String),
+
+    #[error("encrypted file is truncated")]
+    Truncated,
+
+    #[error("failed to encrypt data")]
+    Encryption,
+
+    #[error("failed to decrypt data; it may be corrupt")]
+    Decryption,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A key used to encrypt and decrypt files, along with its id, which is stored in the files that
+/// it encrypts so that the key can be found to decrypt them
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: String,
+    key: [u8; KEY_LEN],
+}
+
+impl EncryptionKey {
+    /// Create a key from its bytes, with an id derived from a hash of the key
+    pub fn new(key: [u8; KEY_LEN]) -> Self {
+        let id = hex::encode(&digest(&SHA256, &key).as_ref()[..8]);
+        Self { id, key }
+    }
+
+    /// Parse a key from the hex encoding of its bytes
+    pub fn from_hex(s: &str) -> Result<Self> {
+        let key = hex::decode(s.trim())
+            .map_err(|e| Error::InvalidKey(e.to_string()))?
+            .try_into()
+            .map_err(|_| {
+                Error::InvalidKey(format!(
+                    "key must be {KEY_LEN} bytes, i.e., {} hex digits",
+                    KEY_LEN * 2
+                ))
+            })?;
+        Ok(Self::new(key))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn aead_key(&self) -> LessSafeKey {
+        LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM, &self.key).expect("key has the right length"),
+        )
+    }
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // never log the key itself:
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Provides the keys used to encrypt and decrypt files. Implementations can hold a static key,
+/// read it from the environment, or fetch it from a key management service.
+///
+/// Keys are requested on the write path, so implementations that fetch keys from a remote
+/// service should cache them.
+pub trait KeyProvider: Debug + Send + Sync + 'static {
+    /// The key to encrypt new files with
+    fn encryption_key(&self) -> Result<EncryptionKey>;
+
+    /// The key with the given id, to decrypt a file that was encrypted with it
+    fn decryption_key(&self, key_id: &str) -> Result<EncryptionKey>;
+}
+
+/// A [`KeyProvider`] that encrypts with a single key, and can decrypt files encrypted with it or
+/// any of a set of previous keys, e.g., while the key is being rotated
+#[derive(Debug)]
+pub struct StaticKeyProvider {
+    current: EncryptionKey,
+    keys: HashMap<String, EncryptionKey>,
+}
+
+impl StaticKeyProvider {
+    pub fn new(current: EncryptionKey) -> Self {
+        Self::with_previous_keys(current, [])
+    }
+
+    pub fn with_previous_keys(
+        current: EncryptionKey,
+        previous: impl IntoIterator<Item = EncryptionKey>,
+    ) -> Self {
+        let keys = previous
+            .into_iter()
+            .chain([current.clone()])
+            .map(|key| (key.id.clone(), key))
+            .collect();
+        Self { current, keys }
+    }
+
+    /// Create a provider with the hex encoded key in the environment variable `var`
+    pub fn from_env(var: &str) -> Result<Self> {
+        let key = std::env::var(var)
+            .map_err(|e| Error::InvalidKey(format!("environment variable {var}: {e}")))?;
+        Ok(Self::new(EncryptionKey::from_hex(&key)?))
+    }
+}
+
+impl KeyProvider for StaticKeyProvider {
+    fn encryption_key(&self) -> Result<EncryptionKey> {
+        Ok(self.current.clone())
+    }
+
+    fn decryption_key(&self, key_id: &str) -> Result<EncryptionKey> {
+        self.keys
+            .get(key_id)
+            .cloned()
+            .ok_or_else(|| Error::UnknownKey(key_id.to_string()))
+    }
+}
+
+/// Encrypt the contents of a file with the current key of the provider
+pub fn encrypt(key_provider: &dyn KeyProvider, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let key = key_provider.encryption_key()?;
+    let key_id = key.id().as_bytes();
+    let key_id_len = u8::try_from(key_id.len())
+        .map_err(|_| Error::InvalidKey("key id must be under 256 bytes".to_string()))?;
+
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| Error::Encryption)?;
+
+    let mut data = plaintext.to_vec();
+    key.aead_key()
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+        .map_err(|_| Error::Encryption)?;
+
+    let mut buf = Vec::with_capacity(
+        ENCRYPTED_FILE_IDENTIFIER.len() + 1 + key_id.len() + NONCE_LEN + data.len(),
+    );
+    buf.extend_from_slice(ENCRYPTED_FILE_IDENTIFIER);
+    buf.push(key_id_len);
+    buf.extend_from_slice(key_id);
+    buf.extend_from_slice(&nonce);
+    buf.extend_from_slice(&data);
+    Ok(buf)
+}
+
+/// Encrypt the contents of a file if there is a key provider, or return them as they are
+pub fn maybe_encrypt(key_provider: Option<&dyn KeyProvider>, data: Vec<u8>) -> Result<Vec<u8>> {
+    match key_provider {
+        Some(key_provider) => encrypt(key_provider, &data),
+        None => Ok(data),
+    }
+}
+
+/// Decrypt the contents of a file if it is encrypted, or return them as they are if it is not
+pub fn maybe_decrypt(key_provider: Option<&dyn KeyProvider>, data: Bytes) -> Result<Bytes> {
+    if !data.starts_with(ENCRYPTED_FILE_IDENTIFIER) {
+        return Ok(data);
+    }
+    let rest = &data[ENCRYPTED_FILE_IDENTIFIER.len()..];
+    let key_provider = key_provider.ok_or(Error::NoKeyProvider)?;
+
+    let (&key_id_len, rest) = rest.split_first().ok_or(Error::Truncated)?;
+    let key_id_len = key_id_len as usize;
+    if rest.len() < key_id_len + NONCE_LEN {
+        return Err(Error::Truncated);
+    }
+    let (key_id, rest) = rest.split_at(key_id_len);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let key_id = std::str::from_utf8(key_id).map_err(|_| Error::Decryption)?;
+    let key = key_provider.decryption_key(key_id)?;
+
+    let mut data = ciphertext.to_vec();
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| Error::Truncated)?;
+    let plaintext_len = key
+        .aead_key()
+        .open_in_place(nonce, Aad::empty(), &mut data)
+        .map_err(|_| Error::Decryption)?
+        .len();
+    data.truncate(plaintext_len);
+    Ok(Bytes::from(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> EncryptionKey {
+        EncryptionKey::new([byte; KEY_LEN])
+    }
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let provider = StaticKeyProvider::new(key(1));
+        let encrypted = encrypt(&provider, b"some data").unwrap();
+        assert!(encrypted.starts_with(ENCRYPTED_FILE_IDENTIFIER));
+        assert!(!encrypted.windows(9).any(|w| w == b"some data"));
+        assert_eq!(
+            Bytes::from_static(b"some data"),
+            maybe_decrypt(Some(&provider), Bytes::from(encrypted.clone())).unwrap()
+        );
+
+        // the data cannot be read without the key, or if it has been changed:
+        assert!(matches!(
+            maybe_decrypt(None, Bytes::from(encrypted.clone())),
+            Err(Error::NoKeyProvider)
+        ));
+        assert!(matches!(
+            maybe_decrypt(
+                Some(&StaticKeyProvider::new(key(2))),
+                Bytes::from(encrypted.clone())
+            ),
+            Err(Error::UnknownKey(_))
+        ));
+        let mut corrupted = encrypted;
+        *corrupted.last_mut().unwrap() ^= 0xff;
+        assert!(matches!(
+            maybe_decrypt(Some(&provider), Bytes::from(corrupted)),
+            Err(Error::Decryption)
+        ));
+
+        // unencrypted data is returned as it is:
+        assert_eq!(
+            Bytes::from_static(b"idb3.003"),
+            maybe_decrypt(Some(&provider), Bytes::from_static(b"idb3.003")).unwrap()
+        );
+    }
+
+    #[test]
+    fn decrypt_with_previous_key() {
+        let encrypted = encrypt(&StaticKeyProvider::new(key(1)), b"some data").unwrap();
+        let provider = StaticKeyProvider::with_previous_keys(key(2), [key(1)]);
+        assert_eq!(
+            Bytes::from_static(b"some data"),
+            maybe_decrypt(Some(&provider), Bytes::from(encrypted)).unwrap()
+        );
+        assert_eq!(key(2).id(), provider.encryption_key().unwrap().id());
+    }
+
+    #[test]
+    fn key_from_hex() {
+        let hex_key = "00".repeat(KEY_LEN);
+        assert_eq!(key(0).id(), EncryptionKey::from_hex(&hex_key).unwrap().id());
+        assert!(matches!(
+            EncryptionKey::from_hex("0011"),
+            Err(Error::InvalidKey(_))
+        ));
+        assert!(matches!(
+            EncryptionKey::from_hex("not hex"),
+            Err(Error::InvalidKey(_))
+        ));
+    }
+}
@@ -0,0 +1,523 @@
+//! Replication of the WAL of another host, the leader, to a read-only replica, e.g., to scale out
+//! queries or to fail over to. The replica polls the object store for the WAL files that the
+//! leader has written since the last poll, and sends the contents of each of them to its file
+//! notifier, as the leader's WAL does once it has written the file, so that the replica buffers
+//! the same writes as the leader does.
+//!
+//! A file that triggered a snapshot on the leader is sent to the notifier with the snapshot, which
+//! the notifier must load once the leader has persisted it, rather than persisting one of its own.
+//! The files are applied one at a time, so the next file waits for the snapshot to be loaded.
+//!
+//! The replica lags the leader by up to the poll interval, plus the time it takes to apply the
+//! files, which is recorded in the metrics. A replica that falls so far behind that the leader
+//! removes WAL files before they are applied misses them, which is also recorded. The writes in
+//! them are in the snapshots that the leader took of them, which the notifier loads before the
+//! files after them are applied.
+
+use crate::encryption::KeyProvider;
+use crate::object_store::{decode_wal_file, list_wal_files_from, subscription, AttachedNotifiers};
+use crate::snapshot_tracker::SnapshotInfo;
+use crate::{
+    NotifierId, RetainedWalFiles, SnapshotDetails, SnapshotSequenceNumber, Wal, WalAckLevel,
+    WalContents, WalFileNotifier, WalFileSequenceNumber, WalOp, WalRuntimeConfig, WalSubscription,
+};
+use metric::{DurationGauge, Registry, U64Counter, U64Gauge};
+use object_store::ObjectStore;
+use observability_deps::tracing::{debug, error, info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{broadcast, oneshot, Mutex, OwnedSemaphorePermit};
+
+/// The number of applied WAL files that are buffered for subscribers to the replica's WAL, before
+/// the subscribers that have not received them yet miss them
+const SUBSCRIPTION_BUFFER_FILES: usize = 100;
+
+/// The metrics that a replica records for how far behind the leader it is
+#[derive(Debug)]
+struct FollowerMetrics {
+    /// The number of the last WAL file of the leader that was applied
+    last_wal_file: U64Gauge,
+    /// The number of WAL files of the leader that were found by the last poll, and are not
+    /// applied yet
+    pending_files: U64Gauge,
+    /// How long the last WAL file that was applied had been in object storage for
+    lag: DurationGauge,
+    /// The number of WAL files that the leader removed before they were applied
+    missed_files: U64Counter,
+}
+
+impl FollowerMetrics {
+    fn new(metric_registry: &Registry) -> Self {
+        Self {
+            last_wal_file: metric_registry
+                .register_metric::<U64Gauge>(
+                    "influxdb3_replication_last_wal_file",
+                    "number of the last wal file of the leader that the replica applied",
+                )
+                .recorder(&[]),
+            pending_files: metric_registry
+                .register_metric::<U64Gauge>(
+                    "influxdb3_replication_pending_wal_files",
+                    "number of wal files of the leader that the replica has found, but not applied",
+                )
+                .recorder(&[]),
+            lag: metric_registry
+                .register_metric::<DurationGauge>(
+                    "influxdb3_replication_lag",
+                    "time between the leader writing the last applied wal file and the replica \
+                    applying it",
+                )
+                .recorder(&[]),
+            missed_files: metric_registry
+                .register_metric::<U64Counter>(
+                    "influxdb3_replication_missed_wal_files",
+                    "number of wal files that the leader removed before the replica applied them",
+                )
+                .recorder(&[]),
+        }
+    }
+}
+
+/// How far the replica has got through the leader's WAL
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    last_wal_file: WalFileSequenceNumber,
+    last_snapshot_sequence_number: SnapshotSequenceNumber,
+}
+
+/// The WAL of a read-only replica, which follows the WAL of the leader in object storage. It
+/// cannot be written to, and never snapshots itself.
+#[derive(Debug)]
+pub struct WalFollower {
+    object_store: Arc<dyn ObjectStore>,
+    leader_host_identifier_prefix: String,
+    file_notifier: Arc<dyn WalFileNotifier>,
+    attached_notifiers: AttachedNotifiers,
+    key_provider: Option<Arc<dyn KeyProvider>>,
+    /// Locked while the files found by a poll are applied, so that they are applied in order
+    position: Mutex<Position>,
+    is_shutdown: AtomicBool,
+    /// The contents of every WAL file that is applied are sent to the subscribers to the WAL
+    subscribers: broadcast::Sender<Arc<WalContents>>,
+    metrics: FollowerMetrics,
+}
+
+impl WalFollower {
+    /// Creates a WAL that follows the WAL of the leader, the host with the
+    /// `leader_host_identifier_prefix`, from the WAL file after `last_wal_sequence_number`,
+    /// which is the last WAL file in the snapshots of the leader that the replica has loaded.
+    ///
+    /// The WAL files that the leader has written since are applied to the notifier before this
+    /// returns, and those that it writes later are applied every `poll_interval`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        object_store: Arc<dyn ObjectStore>,
+        leader_host_identifier_prefix: impl Into<String> + Send,
+        file_notifier: Arc<dyn WalFileNotifier>,
+        key_provider: Option<Arc<dyn KeyProvider>>,
+        last_wal_sequence_number: Option<WalFileSequenceNumber>,
+        last_snapshot_sequence_number: Option<SnapshotSequenceNumber>,
+        poll_interval: Duration,
+        metric_registry: &Registry,
+    ) -> crate::Result<Arc<Self>> {
+        let follower = Arc::new(Self {
+            object_store,
+            leader_host_identifier_prefix: leader_host_identifier_prefix.into(),
+            file_notifier,
+            attached_notifiers: AttachedNotifiers::default(),
+            key_provider,
+            position: Mutex::new(Position {
+                last_wal_file: last_wal_sequence_number.unwrap_or_default(),
+                last_snapshot_sequence_number: last_snapshot_sequence_number.unwrap_or_default(),
+            }),
+            is_shutdown: AtomicBool::new(false),
+            subscribers: broadcast::channel(SUBSCRIPTION_BUFFER_FILES).0,
+            metrics: FollowerMetrics::new(metric_registry),
+        });
+
+        let applied = follower.poll().await?;
+        info!(
+            leader = %follower.leader_host_identifier_prefix,
+            applied,
+            "caught up with the wal of the leader"
+        );
+        background_wal_follow(Arc::clone(&follower), poll_interval);
+
+        Ok(follower)
+    }
+
+    /// Apply the WAL files that the leader has written since the last poll to the notifier,
+    /// returning the number of files that were applied
+    pub async fn poll(&self) -> crate::Result<usize> {
+        let mut position = self.position.lock().await;
+        let wal_files = list_wal_files_from(
+            self.object_store.as_ref(),
+            &self.leader_host_identifier_prefix,
+            position.last_wal_file.next(),
+        )
+        .await?;
+        let mut pending = wal_files.len() as u64;
+        self.metrics.pending_files.set(pending);
+
+        let mut applied = 0;
+        for (wal_file_number, meta) in wal_files {
+            // a file that is being retained can be listed both in the WAL and as retained:
+            if wal_file_number <= position.last_wal_file {
+                continue;
+            }
+            let expected = position.last_wal_file.next();
+            if wal_file_number > expected {
+                let missed = wal_file_number.as_u64() - expected.as_u64();
+                warn!(
+                    leader = %self.leader_host_identifier_prefix,
+                    from = %expected,
+                    missed,
+                    "wal files of the leader were removed before they were applied"
+                );
+                self.metrics.missed_files.inc(missed);
+                if let Some(snapshot_sequence_number) =
+                    self.file_notifier.notify_missed(wal_file_number).await
+                {
+                    position.last_snapshot_sequence_number = snapshot_sequence_number;
+                }
+            }
+
+            let file_bytes = match self.object_store.get(&meta.location).await {
+                Ok(result) => result.bytes().await?,
+                // the leader removed the file since it was listed. If it was retained, it is
+                // found again by the next poll:
+                Err(object_store::Error::NotFound { .. }) => break,
+                Err(e) => return Err(e.into()),
+            };
+            let path = meta.location.clone();
+            let key_provider = self.key_provider.clone();
+            let wal_contents = tokio::task::spawn_blocking(move || {
+                decode_wal_file(&path, key_provider.as_deref(), file_bytes)
+            })
+            .await
+            .expect("decoding a WAL file should not panic")?;
+            // unreadable files are logged and skipped, as they are on replay:
+            if let Some(wal_contents) = wal_contents {
+                debug!(%wal_file_number, "applying wal file of the leader");
+                self.apply(wal_contents, &mut position).await;
+            }
+
+            position.last_wal_file = wal_file_number;
+            applied += 1;
+            pending -= 1;
+            self.metrics.last_wal_file.set(wal_file_number.as_u64());
+            self.metrics.pending_files.set(pending);
+            self.metrics.lag.set(
+                SystemTime::from(meta.last_modified)
+                    .elapsed()
+                    .unwrap_or_default(),
+            );
+        }
+
+        Ok(applied)
+    }
+
+    /// Send the contents of a WAL file to the notifiers, waiting for the snapshot that it
+    /// triggered, if any, to be loaded
+    async fn apply(&self, wal_contents: WalContents, position: &mut Position) {
+        if self.subscribers.receiver_count() > 0 {
+            let _ = self.subscribers.send(Arc::new(wal_contents.clone()));
+        }
+        self.attached_notifiers.notify(&wal_contents);
+
+        match wal_contents.snapshot {
+            None => self.file_notifier.notify(wal_contents),
+            Some(snapshot_details) => {
+                info!(?snapshot_details, "loading snapshot of the leader");
+                let snapshot_loaded = self
+                    .file_notifier
+                    .notify_and_snapshot(wal_contents, snapshot_details)
+                    .await;
+                if snapshot_loaded.await.is_err() {
+                    error!(
+                        ?snapshot_details,
+                        "notifier dropped the snapshot of the leader without loading it"
+                    );
+                }
+                position.last_snapshot_sequence_number = snapshot_details.snapshot_sequence_number;
+            }
+        }
+    }
+}
+
+/// Periodically apply the WAL files that the leader has written, until the follower is shut down
+fn background_wal_follow(
+    follower: Arc<WalFollower>,
+    poll_interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(poll_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            interval.tick().await;
+            if follower.is_shutdown.load(Ordering::Acquire) {
+                break;
+            }
+            if let Err(e) = follower.poll().await {
+                error!(
+                    %e,
+                    leader = %follower.leader_host_identifier_prefix,
+                    "error following the wal of the leader"
+                );
+            }
+        }
+    })
+}
+
+#[async_trait::async_trait]
+impl Wal for WalFollower {
+    async fn buffer_op_unconfirmed(&self, _op: WalOp) -> crate::Result<(), crate::Error> {
+        Err(crate::Error::ReadOnlyReplica)
+    }
+
+    async fn write_ops(&self, _ops: Vec<WalOp>) -> crate::Result<WalAckLevel, crate::Error> {
+        Err(crate::Error::ReadOnlyReplica)
+    }
+
+    async fn flush_buffer(
+        &self,
+    ) -> Option<(
+        oneshot::Receiver<SnapshotDetails>,
+        SnapshotInfo,
+        OwnedSemaphorePermit,
+    )> {
+        None
+    }
+
+    async fn cleanup_snapshot(
+        &self,
+        _snapshot_details: SnapshotInfo,
+        _snapshot_permit: OwnedSemaphorePermit,
+    ) {
+        // the replica never snapshots, so has no files of its own to remove
+    }
+
+    async fn last_wal_sequence_number(&self) -> WalFileSequenceNumber {
+        self.position.lock().await.last_wal_file
+    }
+
+    async fn subscribe(
+        &self,
+        from: Option<WalFileSequenceNumber>,
+    ) -> crate::Result<WalSubscription, crate::Error> {
+        subscription(
+            self.subscribers.subscribe(),
+            Arc::clone(&self.object_store),
+            &self.leader_host_identifier_prefix,
+            self.key_provider.clone(),
+            from,
+        )
+        .await
+    }
+
+    fn attach_notifier(&self, notifier: Arc<dyn WalFileNotifier>) -> NotifierId {
+        self.attached_notifiers.attach(notifier)
+    }
+
+    fn detach_notifier(&self, id: NotifierId) -> bool {
+        self.attached_notifiers.detach(id)
+    }
+
+    async fn retained_wal_files(&self) -> crate::Result<Option<RetainedWalFiles>, crate::Error> {
+        Ok(None)
+    }
+
+    async fn collect_garbage(
+        &self,
+        _snapshot_wal_file: WalFileSequenceNumber,
+        _dry_run: bool,
+    ) -> crate::Result<Vec<WalFileSequenceNumber>, crate::Error> {
+        // the WAL files of the leader are only ever removed by the leader
+        Ok(vec![])
+    }
+
+    async fn last_snapshot_sequence_number(&self) -> SnapshotSequenceNumber {
+        self.position.lock().await.last_snapshot_sequence_number
+    }
+
+    async fn reserve_snapshot_sequence_number(
+        &self,
+    ) -> (SnapshotSequenceNumber, WalFileSequenceNumber) {
+        // the replica does not persist snapshots, so the number is not taken from the leader:
+        let position = self.position.lock().await;
+        (
+            position.last_snapshot_sequence_number.next(),
+            position.last_wal_file,
+        )
+    }
+
+    async fn shutdown(&self) {
+        self.is_shutdown.store(true, Ordering::Release);
+        // wait for the files of a poll that is running to be applied:
+        let _position = self.position.lock().await;
+    }
+
+    async fn force_snapshot(&self) {
+        // the leader snapshots the writes, which the replica loads
+    }
+
+    async fn force_full_snapshot(&self) {
+        // the leader snapshots the writes, which the replica loads
+    }
+
+    async fn flush_and_snapshot_all(&self) {
+        // the leader snapshots the writes, which the replica loads
+    }
+
+    async fn update_config(&self, _config: WalRuntimeConfig) -> crate::Result<()> {
+        Err(crate::Error::ReadOnlyReplica)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::{wal_path, WalObjectStore};
+    use crate::{CatalogBatch, WalConfig};
+    use async_trait::async_trait;
+    use influxdb3_id::DbId;
+    use metric::{Attributes, Metric};
+    use object_store::memory::InMemory;
+    use std::any::Any;
+
+    #[derive(Debug, Default)]
+    struct TestNotifier {
+        notified_files: parking_lot::Mutex<Vec<WalFileSequenceNumber>>,
+        missed_before: parking_lot::Mutex<Vec<WalFileSequenceNumber>>,
+    }
+
+    #[async_trait]
+    impl WalFileNotifier for TestNotifier {
+        fn notify(&self, write: WalContents) {
+            self.notified_files.lock().push(write.wal_file_number);
+        }
+
+        async fn notify_and_snapshot(
+            &self,
+            write: WalContents,
+            snapshot_details: SnapshotDetails,
+        ) -> oneshot::Receiver<SnapshotDetails> {
+            self.notified_files.lock().push(write.wal_file_number);
+            let (sender, receiver) = oneshot::channel();
+            sender.send(snapshot_details).unwrap();
+            receiver
+        }
+
+        async fn notify_missed(
+            &self,
+            wal_file_number: WalFileSequenceNumber,
+        ) -> Option<SnapshotSequenceNumber> {
+            self.missed_before.lock().push(wal_file_number);
+            Some(SnapshotSequenceNumber::new(7))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[tokio::test]
+    async fn follower_applies_the_wal_files_of_the_leader() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let leader = WalObjectStore::new(
+            Arc::clone(&object_store),
+            "leader",
+            Arc::new(TestNotifier::default()),
+            WalConfig::test_config(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            &Registry::new(),
+        )
+        .await
+        .unwrap();
+        let write = || async {
+            Wal::write_ops(
+                leader.as_ref(),
+                vec![WalOp::Catalog(CatalogBatch {
+                    database_id: DbId::from(0),
+                    database_name: "db1".into(),
+                    time_ns: 0,
+                    ops: vec![],
+                })],
+            )
+            .await
+            .unwrap();
+        };
+        write().await;
+        write().await;
+
+        // the files that were written before the follower started are applied when it starts:
+        let notifier = Arc::new(TestNotifier::default());
+        let metric_registry = Registry::new();
+        let follower = WalFollower::new(
+            Arc::clone(&object_store),
+            "leader",
+            Arc::clone(&notifier) as _,
+            None,
+            None,
+            None,
+            Duration::from_secs(3600),
+            &metric_registry,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            vec![WalFileSequenceNumber::new(1), WalFileSequenceNumber::new(2)],
+            *notifier.notified_files.lock()
+        );
+
+        // a file that is removed before it is applied is missed, and the notifier loads the
+        // snapshots of it before the file after it is applied:
+        write().await;
+        write().await;
+        object_store
+            .delete(&wal_path("leader", WalFileSequenceNumber::new(3)))
+            .await
+            .unwrap();
+        assert_eq!(1, follower.poll().await.unwrap());
+        assert_eq!(0, follower.poll().await.unwrap());
+        assert_eq!(
+            WalFileSequenceNumber::new(4),
+            *notifier.notified_files.lock().last().unwrap()
+        );
+        assert_eq!(
+            WalFileSequenceNumber::new(4),
+            follower.last_wal_sequence_number().await
+        );
+        assert_eq!(
+            vec![WalFileSequenceNumber::new(4)],
+            *notifier.missed_before.lock()
+        );
+        assert_eq!(
+            SnapshotSequenceNumber::new(7),
+            follower.last_snapshot_sequence_number().await
+        );
+
+        let missed_files = metric_registry
+            .get_instrument::<Metric<U64Counter>>("influxdb3_replication_missed_wal_files")
+            .unwrap()
+            .get_observer(&Attributes::from(&[]))
+            .unwrap()
+            .fetch();
+        assert_eq!(1, missed_files);
+
+        // and the replica cannot be written to:
+        assert!(matches!(
+            follower.write_ops(vec![]).await,
+            Err(crate::Error::ReadOnlyReplica)
+        ));
+        leader.shutdown().await;
+        follower.shutdown().await;
+    }
+}
@@ -0,0 +1,267 @@
+//! The handoff queue of the WAL, which holds the WAL files that could not be written to object
+//! storage, so that the writes in them are acknowledged while object storage is unavailable,
+//! rather than failed. The queued files are uploaded in the background, in order, once object
+//! storage is available again, and the files that are flushed while there are any in the queue
+//! are queued behind them, so that the WAL stays in order.
+//!
+//! The queue is held in memory, and bounded by the size of the files in it; once it is full,
+//! writes fail as they do without it. The writes in the queue are lost if the server stops before
+//! they are uploaded, so they are acknowledged at the [`WalAckLevel::Buffered`] level.
+//!
+//! [`WalAckLevel::Buffered`]: crate::WalAckLevel::Buffered
+
+use crate::{write_fence_is_open, WalFileSequenceNumber, WriteFence};
+use bytes::Bytes;
+use metric::{Registry, U64Gauge};
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload};
+use observability_deps::tracing::{error, info, warn};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// How long the background upload waits before it retries the queued files after an upload fails
+const UPLOAD_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub(crate) struct HandoffQueue {
+    /// The maximum total size of the files in the queue
+    max_bytes: usize,
+    queue: parking_lot::Mutex<Queue>,
+    /// Held while the front file of the queue is uploaded, so that it is not discarded from the
+    /// queue partway through, and uploaded after the WAL files have been removed
+    uploading: tokio::sync::Mutex<()>,
+    /// Notified when a file is queued, to wake the background upload
+    queued: Notify,
+    metrics: HandoffMetrics,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    files: VecDeque<QueuedFile>,
+    size_bytes: usize,
+}
+
+#[derive(Debug)]
+struct QueuedFile {
+    wal_file_number: WalFileSequenceNumber,
+    path: Path,
+    data: Bytes,
+}
+
+#[derive(Debug)]
+struct HandoffMetrics {
+    files: U64Gauge,
+    bytes: U64Gauge,
+}
+
+impl HandoffQueue {
+    /// Create a queue that holds up to `max_bytes` of WAL files, recording how many are queued in
+    /// `metric_registry`
+    pub(crate) fn new(max_bytes: usize, metric_registry: &Registry) -> Self {
+        let files = metric_registry
+            .register_metric::<U64Gauge>(
+                "influxdb3_wal_handoff_files",
+                "number of wal files that are queued to be written to object storage",
+            )
+            .recorder(&[]);
+        let bytes = metric_registry
+            .register_metric::<U64Gauge>(
+                "influxdb3_wal_handoff_bytes",
+                "size of the wal files that are queued to be written to object storage",
+            )
+            .recorder(&[]);
+        Self {
+            max_bytes,
+            queue: Default::default(),
+            uploading: Default::default(),
+            queued: Notify::new(),
+            metrics: HandoffMetrics { files, bytes },
+        }
+    }
+
+    /// Whether there are no files in the queue
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queue.lock().files.is_empty()
+    }
+
+    /// The number of files in the queue
+    pub(crate) fn len(&self) -> usize {
+        self.queue.lock().files.len()
+    }
+
+    /// Queue a WAL file to be uploaded to `path`, behind those already in the queue, returning
+    /// false if it does not fit in the queue
+    pub(crate) fn push(
+        &self,
+        wal_file_number: WalFileSequenceNumber,
+        path: Path,
+        data: Bytes,
+    ) -> bool {
+        let mut queue = self.queue.lock();
+        if queue.size_bytes + data.len() > self.max_bytes {
+            return false;
+        }
+        queue.size_bytes += data.len();
+        queue.files.push_back(QueuedFile {
+            wal_file_number,
+            path,
+            data,
+        });
+        self.record_metrics(&queue);
+        self.queued.notify_one();
+        true
+    }
+
+    /// Discard the files up to and including `wal_file_number` from the queue, without uploading
+    /// them, as the writes in them have been persisted by a snapshot. Returns the number of files
+    /// that were discarded.
+    pub(crate) async fn discard_through(&self, wal_file_number: WalFileSequenceNumber) -> usize {
+        let _uploading = self.uploading.lock().await;
+        let mut queue = self.queue.lock();
+        let mut discarded = 0;
+        while queue
+            .files
+            .front()
+            .is_some_and(|file| file.wal_file_number <= wal_file_number)
+        {
+            let file = queue.files.pop_front().expect("queue has a front file");
+            queue.size_bytes -= file.data.len();
+            discarded += 1;
+        }
+        self.record_metrics(&queue);
+        discarded
+    }
+
+    /// Upload the files in the queue to `object_store`, in order, until it is empty, an upload
+    /// fails, or the `write_fence` is closed. Returns the number of files that were uploaded.
+    pub(crate) async fn upload(
+        &self,
+        object_store: &dyn ObjectStore,
+        write_fence: Option<&Arc<dyn WriteFence>>,
+    ) -> Result<usize, object_store::Error> {
+        let _uploading = self.uploading.lock().await;
+        let mut uploaded = 0;
+        loop {
+            if !write_fence_is_open(write_fence) {
+                return Ok(uploaded);
+            }
+            let Some((path, data)) = self
+                .queue
+                .lock()
+                .files
+                .front()
+                .map(|file| (file.path.clone(), file.data.clone()))
+            else {
+                return Ok(uploaded);
+            };
+            object_store
+                .put(&path, PutPayload::from_bytes(data))
+                .await?;
+
+            let mut queue = self.queue.lock();
+            let file = queue.files.pop_front().expect("queue has a front file");
+            queue.size_bytes -= file.data.len();
+            self.record_metrics(&queue);
+            uploaded += 1;
+        }
+    }
+
+    fn record_metrics(&self, queue: &Queue) {
+        self.metrics.files.set(queue.files.len() as u64);
+        self.metrics.bytes.set(queue.size_bytes as u64);
+    }
+}
+
+/// Upload the files that are queued in the `queue` to `object_store` in the background, retrying
+/// until object storage is available again, and stopping once the `write_fence` is closed
+pub(crate) fn background_handoff_upload(
+    queue: Arc<HandoffQueue>,
+    object_store: Arc<dyn ObjectStore>,
+    write_fence: Option<Arc<dyn WriteFence>>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            if queue.is_empty() {
+                queue.queued.notified().await;
+            }
+            if !write_fence_is_open(write_fence.as_ref()) {
+                error!(
+                    n_files = queue.len(),
+                    "not uploading the wal files in the handoff queue, as the host identifier \
+                    prefix may have been taken over, the writes in them are lost"
+                );
+                return;
+            }
+            match queue
+                .upload(object_store.as_ref(), write_fence.as_ref())
+                .await
+            {
+                Ok(0) => (),
+                Ok(n_files) => info!(n_files, "uploaded the wal files in the handoff queue"),
+                Err(e) => {
+                    warn!(
+                        %e,
+                        n_files = queue.len(),
+                        "error uploading the wal files in the handoff queue, retrying"
+                    );
+                    tokio::time::sleep(UPLOAD_RETRY_INTERVAL).await;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn queue_is_bounded_and_uploaded_in_order() {
+        let queue = HandoffQueue::new(10, &Registry::new());
+        let file = |n: u64| {
+            (
+                WalFileSequenceNumber::new(n),
+                Path::from(format!("host/wal/{n}.wal")),
+                Bytes::from(vec![n as u8; 4]),
+            )
+        };
+        for n in 1..=2 {
+            let (wal_file_number, path, data) = file(n);
+            assert!(queue.push(wal_file_number, path, data));
+        }
+        // a third file would take the queue over its size:
+        let (wal_file_number, path, data) = file(3);
+        assert!(!queue.push(wal_file_number, path, data));
+        assert_eq!(2, queue.len());
+
+        // the files that a snapshot persisted are not uploaded:
+        assert_eq!(
+            1,
+            queue.discard_through(WalFileSequenceNumber::new(1)).await
+        );
+        let (wal_file_number, path, data) = file(3);
+        assert!(queue.push(wal_file_number, path, data));
+
+        let object_store = InMemory::new();
+        assert_eq!(2, queue.upload(&object_store, None).await.unwrap());
+        assert!(queue.is_empty());
+        assert!(object_store
+            .head(&Path::from("host/wal/1.wal"))
+            .await
+            .is_err());
+        for n in 2..=3 {
+            let path = Path::from(format!("host/wal/{n}.wal"));
+            let bytes = object_store
+                .get(&path)
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap();
+            assert_eq!(vec![n as u8; 4], bytes.to_vec());
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Inspection of the WAL files in object store, to debug problems with replaying them without
+//! having to write one-off programs to decode them
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use bytes::Bytes;
+use influxdb3_id::TableId;
+use object_store::{path::Path, ObjectStore};
+use serde::Serialize;
+
+use crate::{
+    encryption::{maybe_decrypt, KeyProvider},
+    object_store::list_wal_file_paths,
+    serialize::{deserialize_skipping_corrupt_entries, CorruptEntry},
+    SnapshotDetails, WalContents, WalFileSequenceNumber, WalOp,
+};
+
+/// A summary of the contents of a WAL file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WalFileSummary {
+    pub path: String,
+    pub wal_file_number: WalFileSequenceNumber,
+    pub size_bytes: usize,
+    pub min_timestamp_ns: i64,
+    pub max_timestamp_ns: i64,
+    /// The snapshot that was started when the file was written, if there was one
+    pub snapshot: Option<SnapshotDetails>,
+    pub write_ops: usize,
+    pub catalog_ops: usize,
+    pub rows: usize,
+    /// The tables that were written to in the file, by database
+    pub tables: BTreeMap<Arc<str>, BTreeMap<TableId, TableSummary>>,
+    /// The entries in the file that are corrupt, and are skipped when the WAL is replayed
+    pub corrupt_entries: Vec<CorruptEntrySummary>,
+}
+
+/// A summary of the writes to a table in a WAL file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TableSummary {
+    pub rows: usize,
+    pub min_time_ns: i64,
+    pub max_time_ns: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CorruptEntrySummary {
+    pub offset: usize,
+    pub error: String,
+}
+
+impl From<&CorruptEntry> for CorruptEntrySummary {
+    fn from(corrupt_entry: &CorruptEntry) -> Self {
+        Self {
+            offset: corrupt_entry.offset,
+            error: corrupt_entry.error.to_string(),
+        }
+    }
+}
+
+/// Lists, decodes, and summarizes the WAL files of a host
+#[derive(Debug)]
+pub struct WalInspector {
+    object_store: Arc<dyn ObjectStore>,
+    host_identifier_prefix: String,
+    key_provider: Option<Arc<dyn KeyProvider>>,
+}
+
+impl WalInspector {
+    /// Create an inspector of the WAL files of the host, which decrypts them with the keys from
+    /// `key_provider` if they are encrypted
+    pub fn new(
+        object_store: Arc<dyn ObjectStore>,
+        host_identifier_prefix: impl Into<String>,
+        key_provider: Option<Arc<dyn KeyProvider>>,
+    ) -> Self {
+        Self {
+            object_store,
+            host_identifier_prefix: host_identifier_prefix.into(),
+            key_provider,
+        }
+    }
+
+    /// The paths of the WAL files of the host, in the order they were written
+    pub async fn list(&self) -> crate::Result<Vec<Path>> {
+        list_wal_file_paths(self.object_store.as_ref(), &self.host_identifier_prefix).await
+    }
+
+    /// The path of the WAL file with the given number
+    pub fn path(&self, wal_file_number: WalFileSequenceNumber) -> Path {
+        crate::object_store::wal_path(&self.host_identifier_prefix, wal_file_number)
+    }
+
+    /// Load and decode a WAL file, along with the entries in it that are corrupt, which are
+    /// skipped. Fails if the file cannot be read at all.
+    pub async fn load(&self, path: &Path) -> crate::Result<(WalContents, Vec<CorruptEntry>)> {
+        let file_bytes = self.get(path).await?;
+        self.decode(path, file_bytes)
+    }
+
+    /// Load and summarize a WAL file
+    pub async fn summarize(&self, path: &Path) -> crate::Result<WalFileSummary> {
+        let file_bytes = self.get(path).await?;
+        let size_bytes = file_bytes.len();
+        let (contents, corrupt_entries) = self.decode(path, file_bytes)?;
+        Ok(summarize(path, size_bytes, &contents, &corrupt_entries))
+    }
+
+    async fn get(&self, path: &Path) -> crate::Result<Bytes> {
+        Ok(self.object_store.get(path).await?.bytes().await?)
+    }
+
+    fn decode(
+        &self,
+        path: &Path,
+        file_bytes: Bytes,
+    ) -> crate::Result<(WalContents, Vec<CorruptEntry>)> {
+        let file_bytes = maybe_decrypt(self.key_provider.as_deref(), file_bytes)?;
+        deserialize_skipping_corrupt_entries(file_bytes).map_err(
+            |CorruptEntry { offset, error }| crate::Error::WalCorruption {
+                path: path.to_string(),
+                offset,
+                source: error,
+            },
+        )
+    }
+}
+
+/// Summarize the decoded contents of the WAL file at `path`, which is `size_bytes` in size
+pub fn summarize(
+    path: &Path,
+    size_bytes: usize,
+    contents: &WalContents,
+    corrupt_entries: &[CorruptEntry],
+) -> WalFileSummary {
+    let mut summary = WalFileSummary {
+        path: path.to_string(),
+        wal_file_number: contents.wal_file_number,
+        size_bytes,
+        min_timestamp_ns: contents.min_timestamp_ns,
+        max_timestamp_ns: contents.max_timestamp_ns,
+        snapshot: contents.snapshot,
+        write_ops: 0,
+        catalog_ops: 0,
+        rows: 0,
+        tables: BTreeMap::new(),
+        corrupt_entries: corrupt_entries.iter().map(Into::into).collect(),
+    };
+
+    for op in &contents.ops {
+        match op {
+            WalOp::Write(write_batch) => {
+                summary.write_ops += 1;
+                let tables = summary
+                    .tables
+                    .entry(Arc::clone(&write_batch.database_name))
+                    .or_default();
+                for (table_id, table_chunks) in write_batch.table_chunks.iter() {
+                    let rows = table_chunks
+                        .chunk_time_to_chunk
+                        .values()
+                        .map(|chunk| chunk.rows.len())
+                        .sum::<usize>();
+                    summary.rows += rows;
+                    let table = tables.entry(*table_id).or_insert(TableSummary {
+                        rows: 0,
+                        min_time_ns: i64::MAX,
+                        max_time_ns: i64::MIN,
+                    });
+                    table.rows += rows;
+                    table.min_time_ns = table.min_time_ns.min(table_chunks.min_time);
+                    table.max_time_ns = table.max_time_ns.max(table_chunks.max_time);
+                }
+            }
+            WalOp::Catalog(catalog_batch) => summary.catalog_ops += catalog_batch.ops.len(),
+        }
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        serialize::serialize_to_file_bytes, CatalogBatch, CatalogOp, DatabaseDefinition, Field,
+        FieldData, Row, TableChunk, TableChunks, WalCompression, WriteBatch,
+    };
+    use hashbrown::HashMap;
+    use indexmap::IndexMap;
+    use influxdb3_id::{ColumnId, DbId};
+    use object_store::{memory::InMemory, PutPayload};
+
+    #[tokio::test]
+    async fn summarize_wal_files() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let inspector = WalInspector::new(Arc::clone(&object_store), "my_host", None);
+        let row = |time: i64| Row {
+            time,
+            fields: vec![Field {
+                id: ColumnId::from(0),
+                value: FieldData::Integer(time),
+            }],
+        };
+        let contents = WalContents {
+            min_timestamp_ns: 1,
+            max_timestamp_ns: 3,
+            wal_file_number: WalFileSequenceNumber::new(1),
+            ops: vec![
+                WalOp::Catalog(CatalogBatch {
+                    database_id: DbId::from(0),
+                    database_name: "db1".into(),
+                    time_ns: 1,
+                    ops: vec![CatalogOp::CreateDatabase(DatabaseDefinition {
+                        database_id: DbId::from(0),
+                        database_name: "db1".into(),
+                    })],
+                }),
+                WalOp::Write(WriteBatch {
+                    database_id: DbId::from(0),
+                    database_name: "db1".into(),
+                    table_chunks: IndexMap::from([(
+                        TableId::from(0),
+                        TableChunks {
+                            min_time: 2,
+                            max_time: 3,
+                            chunk_time_to_chunk: HashMap::from([(
+                                0,
+                                TableChunk {
+                                    rows: vec![row(2), row(3)],
+                                },
+                            )]),
+                        },
+                    )])
+                    .into(),
+                    min_time_ns: 2,
+                    max_time_ns: 3,
+                }),
+            ],
+            snapshot: None,
+        };
+        let path = inspector.path(contents.wal_file_number);
+        let file_bytes = serialize_to_file_bytes(&contents, WalCompression::Zstd).unwrap();
+        let size_bytes = file_bytes.len();
+        object_store
+            .put(&path, PutPayload::from(file_bytes))
+            .await
+            .unwrap();
+        object_store
+            .put(
+                &inspector.path(WalFileSequenceNumber::new(2)),
+                PutPayload::from_static(b"not a wal file"),
+            )
+            .await
+            .unwrap();
+
+        let paths = inspector.list().await.unwrap();
+        assert_eq!(2, paths.len());
+        assert_eq!(contents, inspector.load(&paths[0]).await.unwrap().0);
+        assert_eq!(
+            WalFileSummary {
+                path: "my_host/wal/00000000001.wal".to_string(),
+                wal_file_number: WalFileSequenceNumber::new(1),
+                size_bytes,
+                min_timestamp_ns: 1,
+                max_timestamp_ns: 3,
+                snapshot: None,
+                write_ops: 1,
+                catalog_ops: 1,
+                rows: 2,
+                tables: BTreeMap::from([(
+                    "db1".into(),
+                    BTreeMap::from([(
+                        TableId::from(0),
+                        TableSummary {
+                            rows: 2,
+                            min_time_ns: 2,
+                            max_time_ns: 3,
+                        },
+                    )]),
+                )]),
+                corrupt_entries: vec![],
+            },
+            inspector.summarize(&paths[0]).await.unwrap()
+        );
+        assert!(matches!(
+            inspector.summarize(&paths[1]).await,
+            Err(crate::Error::WalCorruption { .. })
+        ));
+    }
+}
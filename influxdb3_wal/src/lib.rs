@@ -4,13 +4,20 @@
 //! index files in object storage.
 
 pub mod create;
+pub mod encryption;
+pub mod follower;
+mod handoff;
+pub mod inspect;
+mod local_tier;
 pub mod object_store;
+pub mod recovery;
 pub mod serialize;
 mod snapshot_tracker;
 
 use crate::snapshot_tracker::SnapshotInfo;
 use async_trait::async_trait;
 use data_types::Timestamp;
+use futures_util::stream::BoxStream;
 use hashbrown::HashMap;
 use indexmap::IndexMap;
 use influxdb3_id::{ColumnId, DbId, SerdeVecMap, TableId};
@@ -21,13 +28,16 @@ use observability_deps::tracing::error;
 use schema::{InfluxColumnType, InfluxFieldType};
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{any::Any, num::ParseIntError};
 use thiserror::Error;
-use tokio::sync::{oneshot, OwnedSemaphorePermit};
+use tokio::sync::{oneshot, watch, Notify, OwnedSemaphorePermit};
+use unicode_normalization::UnicodeNormalization;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -49,11 +59,63 @@ pub enum Error {
     #[error("invalid gen1 duration {0}. Must be one of 1m, 5m, 10m")]
     InvalidGen1Duration(String),
 
+    #[error("invalid WAL compression {0}. Must be one of none, zstd, lz4, snappy")]
+    InvalidWalCompression(String),
+
+    #[error("invalid WAL ack level {0}. Must be one of object-store-put, local-durable, buffered")]
+    InvalidWalAckLevel(String),
+
+    #[error("invalid WAL config: {0}")]
+    InvalidWalConfig(String),
+
     #[error("last cache size must be from 1 to 10")]
     InvalidLastCacheSize,
 
+    #[error("invalid downsampling aggregate {0}. Must be one of mean, min, max, sum, count")]
+    InvalidDownsamplingAggregate(String),
+
     #[error("invalid WAL file path")]
     InvalidWalFilePath,
+
+    #[error("the WAL handoff queue is full, with {0} WAL files not in object storage yet")]
+    HandoffQueueFull(usize),
+
+    #[error("local WAL file error: {0}")]
+    LocalWalFile(#[from] std::io::Error),
+
+    #[error("corrupt WAL file {path} at offset {offset}: {source}")]
+    WalCorruption {
+        path: String,
+        offset: usize,
+        source: crate::serialize::Error,
+    },
+
+    #[error("WAL encryption error: {0}")]
+    Encryption(#[from] crate::encryption::Error),
+
+    #[error("no WAL files were written at or before the recovery target")]
+    RecoveryTargetNotFound,
+
+    #[error("WAL file {0} is needed for the recovery, but is neither in the WAL nor retained")]
+    RecoveryWalFileMissing(WalFileSequenceNumber),
+
+    #[error("the snapshot triggered by WAL file {0} cannot be read from the file")]
+    RecoverySnapshotUnreadable(WalFileSequenceNumber),
+
+    #[error("WAL file {0} triggered a snapshot, but is neither in the WAL nor retained")]
+    SnapshotWalFileMissing(WalFileSequenceNumber),
+
+    #[error("WAL subscriber fell behind, and missed {0} WAL files")]
+    SubscriptionLagged(u64),
+
+    #[error("the WAL of a replica is read-only, as it follows the WAL of another host")]
+    ReadOnlyReplica,
+
+    #[error(
+        "the WAL cannot write to its host identifier prefix, which another server may have taken \
+        over"
+    )]
+    WriteFenced,
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -63,10 +125,11 @@ pub trait Wal: Debug + Send + Sync + 'static {
     /// Buffer into a single larger operation in memory. Returns before the operation is persisted.
     async fn buffer_op_unconfirmed(&self, op: WalOp) -> Result<(), Error>;
 
-    /// Writes the ops into the buffer and waits until the WAL file is persisted. When this returns
-    /// the operations are durable in the configured object store and the file notifier has been
-    /// called, which puts it into the queryable memory buffer.
-    async fn write_ops(&self, ops: Vec<WalOp>) -> Result<(), Error>;
+    /// Writes the ops into the buffer and waits until they are acknowledged at the configured
+    /// [`WalAckLevel`], returning the level that was achieved. Unless that is
+    /// [`WalAckLevel::Buffered`], the operations are durable when this returns, and the file
+    /// notifier has been called, which puts them into the queryable memory buffer.
+    async fn write_ops(&self, ops: Vec<WalOp>) -> Result<WalAckLevel, Error>;
 
     /// Flushes all buffered writes to a single WAL file and calls the file notifier with the contents.
     /// If it is time for a snapshot, it will tell the notifier to start the snapshot and return
@@ -90,13 +153,89 @@ pub trait Wal: Debug + Send + Sync + 'static {
     /// Returns the last persisted wal file sequence number
     async fn last_wal_sequence_number(&self) -> WalFileSequenceNumber;
 
+    /// Subscribe to the contents of every wal file that is flushed from now on. If `from` is set,
+    /// the wal files from that sequence number that are still in the wal, or retained, are
+    /// replayed to the subscriber first, so it can catch up from where it left off.
+    async fn subscribe(
+        &self,
+        from: Option<WalFileSequenceNumber>,
+    ) -> Result<WalSubscription, Error>;
+
+    /// Attach a notifier that is sent the contents of every wal file that is flushed from now on,
+    /// alongside the file notifier that the wal was created with, until it is detached. These
+    /// notifiers are never asked to snapshot; the snapshot details are in the contents of a file
+    /// that triggers a snapshot.
+    fn attach_notifier(&self, notifier: Arc<dyn WalFileNotifier>) -> NotifierId;
+
+    /// Detach a notifier that was attached with [`Wal::attach_notifier`]. Returns false if it was
+    /// not attached.
+    fn detach_notifier(&self, id: NotifierId) -> bool;
+
+    /// Returns the range of snapshot wal files that are retained and pending deletion, if any
+    async fn retained_wal_files(&self) -> Result<Option<RetainedWalFiles>, Error>;
+
+    /// Deletes the wal files that were snapshot by the snapshot that `snapshot_wal_file` triggered,
+    /// but were left in the wal, e.g., because the server stopped before they were removed. They
+    /// are retained, rather than deleted, if retention is configured. Returns the files that were
+    /// found, which are only logged if `dry_run` is set.
+    ///
+    /// The files of a snapshot are removed once it has been persisted, so this must only be called
+    /// with snapshots that have been persisted, and whose files have been removed.
+    async fn collect_garbage(
+        &self,
+        snapshot_wal_file: WalFileSequenceNumber,
+        dry_run: bool,
+    ) -> Result<Vec<WalFileSequenceNumber>, Error>;
+
     /// Returns the last persisted wal file sequence number
     async fn last_snapshot_sequence_number(&self) -> SnapshotSequenceNumber;
 
+    /// Take the next snapshot sequence number for a snapshot that is persisted without going
+    /// through the WAL, e.g., of imported parquet files, along with the last WAL file that was
+    /// written when it was taken, which the snapshot is of. The snapshots of the WAL that follow
+    /// it are numbered after it, and are of the same WAL file, or a later one.
+    async fn reserve_snapshot_sequence_number(
+        &self,
+    ) -> (SnapshotSequenceNumber, WalFileSequenceNumber);
+
     /// Stop all writes to the WAL and flush the buffer to a WAL file.
     async fn shutdown(&self);
+
+    /// Request that the next flush of the buffer snapshots all data it can, regardless of the
+    /// configured snapshot size. This will flush a WAL file even if no ops have been buffered.
+    async fn force_snapshot(&self);
+
+    /// Request that the next flush of the buffer snapshots every WAL period, including the one
+    /// that it flushes, so that the snapshot does not wait for another WAL file to be written,
+    /// e.g., while writes are held back until the buffer is persisted.
+    async fn force_full_snapshot(&self);
+
+    /// Flush any buffered writes and snapshot all of the data that has been written to the WAL,
+    /// returning once the snapshot has been persisted. Ops that are buffered while this runs may
+    /// not be included in the snapshot.
+    async fn flush_and_snapshot_all(&self);
+
+    /// Change the flush interval, snapshot size, and gen1 duration of the running WAL. The new
+    /// snapshot size and gen1 duration apply from the next snapshot.
+    async fn update_config(&self, config: WalRuntimeConfig) -> Result<(), Error>;
+}
+
+/// Identifies a notifier that was attached to the WAL with [`Wal::attach_notifier`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NotifierId(u64);
+
+impl NotifierId {
+    pub fn new(id: u64) -> Self {
+        Self(id)
+    }
 }
 
+/// The contents of the WAL files, in the order that they were written, for a subscriber to the
+/// WAL. A subscriber that falls too far behind gets a [`Error::SubscriptionLagged`] error, then
+/// the files after those it missed; it can subscribe again from the last file that it received
+/// to catch up on those that it missed.
+pub type WalSubscription = BoxStream<'static, Result<Arc<WalContents>, Error>>;
+
 /// When the WAL persists a file with buffered ops, the contents are sent to this
 /// notifier so that the data can be loaded into the in memory buffer and caches.
 #[async_trait]
@@ -112,9 +251,33 @@ pub trait WalFileNotifier: Debug + Send + Sync + 'static {
         snapshot_details: SnapshotDetails,
     ) -> oneshot::Receiver<SnapshotDetails>;
 
+    /// Notify the handler that the WAL files before `wal_file_number` were removed before they
+    /// were sent to it, which only happens to a replica that falls behind its leader, so that it
+    /// can load the snapshots that the leader took of them instead. Returns the sequence number of
+    /// the last snapshot that was loaded, if any were.
+    async fn notify_missed(
+        &self,
+        _wal_file_number: WalFileSequenceNumber,
+    ) -> Option<SnapshotSequenceNumber> {
+        None
+    }
+
     fn as_any(&self) -> &dyn Any;
 }
 
+/// Checked before a host writes to, or deletes from, its host identifier prefix in object storage,
+/// so that it stops once another server may have taken the prefix over, e.g., because the host
+/// lost its lease on the prefix
+pub trait WriteFence: Debug + Send + Sync + 'static {
+    /// Whether the host may still write to its host identifier prefix
+    fn is_open(&self) -> bool;
+}
+
+/// Whether `write_fence` is open, which it is if there is none
+pub fn write_fence_is_open(write_fence: Option<&Arc<dyn WriteFence>>) -> bool {
+    write_fence.map_or(true, |write_fence| write_fence.is_open())
+}
+
 /// The configuration for the WAL
 #[derive(Debug, Clone, Copy)]
 pub struct WalConfig {
@@ -126,15 +289,57 @@ pub struct WalConfig {
     pub flush_interval: Duration,
     /// The number of wal files to snapshot at a time
     pub snapshot_size: usize,
+    /// How the contents of wal files are compressed. Files are replayed regardless of how they
+    /// were compressed, so this can be changed between restarts.
+    pub compression: WalCompression,
+    /// The approximate maximum size, in bytes, of the writes in a wal file. Once the buffered
+    /// writes reach this size, the buffer is flushed without waiting for the flush interval, and
+    /// a buffer that is larger than this is split across multiple wal files.
+    pub max_file_size_bytes: Option<usize>,
+    /// How long wal files are kept after the data in them has been snapshot
+    pub retention: WalRetention,
+    /// When writes to the wal are acknowledged
+    pub ack_level: WalAckLevel,
+    /// When to snapshot before `snapshot_size` wal files have been written
+    pub snapshot_triggers: SnapshotTriggers,
+    /// If set, the wal files that cannot be written to object storage are queued in memory, up to
+    /// this many bytes of them, and uploaded once it is available again, rather than failing the
+    /// writes in them, which are acknowledged as buffered instead
+    pub handoff_max_bytes: Option<usize>,
 }
 
 impl WalConfig {
+    /// The settings that can be changed while the WAL is running
+    pub fn runtime_config(&self) -> WalRuntimeConfig {
+        WalRuntimeConfig {
+            gen1_duration: self.gen1_duration,
+            flush_interval: self.flush_interval,
+            snapshot_size: self.snapshot_size,
+        }
+    }
+
+    /// This config with the settings that can be changed while the WAL is running replaced
+    pub fn with_runtime_config(self, runtime_config: WalRuntimeConfig) -> Self {
+        Self {
+            gen1_duration: runtime_config.gen1_duration,
+            flush_interval: runtime_config.flush_interval,
+            snapshot_size: runtime_config.snapshot_size,
+            ..self
+        }
+    }
+
     pub fn test_config() -> Self {
         Self {
             gen1_duration: Gen1Duration::new_5m(),
             max_write_buffer_size: 1000,
             flush_interval: Duration::from_millis(10),
             snapshot_size: 100,
+            compression: WalCompression::None,
+            max_file_size_bytes: None,
+            retention: WalRetention::default(),
+            ack_level: WalAckLevel::default(),
+            snapshot_triggers: SnapshotTriggers::default(),
+            handoff_max_bytes: None,
         }
     }
 }
@@ -146,12 +351,157 @@ impl Default for WalConfig {
             max_write_buffer_size: 100_000,
             flush_interval: Duration::from_secs(1),
             snapshot_size: 600,
+            compression: WalCompression::None,
+            max_file_size_bytes: None,
+            retention: WalRetention::default(),
+            ack_level: WalAckLevel::default(),
+            snapshot_triggers: SnapshotTriggers::default(),
+            handoff_max_bytes: None,
+        }
+    }
+}
+
+/// The settings of the [`WalConfig`] that can be changed while the WAL is running, with
+/// [`Wal::update_config`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalRuntimeConfig {
+    pub gen1_duration: Gen1Duration,
+    pub flush_interval: Duration,
+    pub snapshot_size: usize,
+}
+
+impl WalRuntimeConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.flush_interval.is_zero() {
+            return Err(Error::InvalidWalConfig(
+                "flush interval must be greater than zero".to_string(),
+            ));
+        }
+        if self.snapshot_size == 0 {
+            return Err(Error::InvalidWalConfig(
+                "snapshot size must be greater than zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How many WAL files are kept after the data in them has been snapshot, e.g., so that there is a
+/// window of WAL files for point-in-time recovery. Retained files are moved out of the WAL, so they
+/// are not replayed, and are deleted by a background task once they are outside of the window.
+///
+/// A file is kept while it is one of the `keep_files` most recently snapshot files, or it was
+/// snapshot less than `keep_duration` ago. By default, files are deleted once they are snapshot.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct WalRetention {
+    pub keep_files: usize,
+    pub keep_duration: Duration,
+}
+
+impl WalRetention {
+    /// Whether snapshot WAL files are retained at all
+    pub fn is_enabled(&self) -> bool {
+        self.keep_files > 0 || !self.keep_duration.is_zero()
+    }
+}
+
+/// Limits on the writes in the WAL files that have not been snapshot, which trigger a snapshot of
+/// all of them as soon as they are reached, rather than waiting for `snapshot_size` WAL files to
+/// be written. This bounds how long the writes of infrequent writers are buffered before they are
+/// persisted, and how much is buffered for heavy writers. By default, there are no limits.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct SnapshotTriggers {
+    /// Snapshot once the oldest WAL file with writes that have not been snapshot was written
+    /// this long ago
+    pub max_age: Option<Duration>,
+    /// Snapshot once the writes that have not been snapshot reach this size, in bytes
+    pub max_bytes: Option<usize>,
+}
+
+/// The range of WAL files that have been snapshot, but are retained and pending deletion
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+pub struct RetainedWalFiles {
+    pub first: WalFileSequenceNumber,
+    pub last: WalFileSequenceNumber,
+    pub count: usize,
+}
+
+/// How the contents of WAL files are compressed before they are written to object storage
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum WalCompression {
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+    Snappy,
+}
+
+impl FromStr for WalCompression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "zstd" => Ok(Self::Zstd),
+            "lz4" => Ok(Self::Lz4),
+            "snappy" => Ok(Self::Snappy),
+            _ => Err(Error::InvalidWalCompression(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for WalCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Zstd => write!(f, "zstd"),
+            Self::Lz4 => write!(f, "lz4"),
+            Self::Snappy => write!(f, "snappy"),
+        }
+    }
+}
+
+/// When writes to the WAL are acknowledged, i.e., when [`Wal::write_ops`] returns
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum WalAckLevel {
+    /// Once the WAL file with the writes has been put to object storage. If WAL files are written
+    /// to a local directory first, this waits for the file to be uploaded.
+    #[default]
+    ObjectStorePut,
+    /// Once the WAL file with the writes has been synced to the local disk, if WAL files are
+    /// written to a local directory first. Otherwise, files are put to object storage directly,
+    /// which is waited for instead.
+    LocalDurable,
+    /// As soon as the writes have been buffered, before the WAL file with them is written. Writes
+    /// are lost if the server stops before the buffer is next flushed.
+    Buffered,
+}
+
+impl FromStr for WalAckLevel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "object-store-put" => Ok(Self::ObjectStorePut),
+            "local-durable" => Ok(Self::LocalDurable),
+            "buffered" => Ok(Self::Buffered),
+            _ => Err(Error::InvalidWalAckLevel(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for WalAckLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ObjectStorePut => write!(f, "object-store-put"),
+            Self::LocalDurable => write!(f, "local-durable"),
+            Self::Buffered => write!(f, "buffered"),
         }
     }
 }
 
 /// The duration of data timestamps, grouped into files persisted into object storage.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Gen1Duration(Duration);
 
 impl Gen1Duration {
@@ -244,6 +594,34 @@ pub enum CatalogOp {
     AddFields(FieldAdditions),
     CreateLastCache(LastCacheDefinition),
     DeleteLastCache(LastCacheDelete),
+    /// Changes the count, TTL, or value columns of an existing last cache, to those in the
+    /// definition, keeping the values in it
+    UpdateLastCache(LastCacheDefinition),
+    CreateDistinctCache(DistinctCacheDefinition),
+    DeleteDistinctCache(DistinctCacheDelete),
+    SetRetentionPeriod(RetentionPeriodDefinition),
+    SetWriteTimeLimits(WriteTimeLimitsDefinition),
+    SetSchemaLimits(SchemaLimitsDefinition),
+    SetCardinalityLimits(CardinalityLimitsDefinition),
+    SetSchemaMode(SchemaModeDefinition),
+    SetFieldCoercion(FieldCoercionDefinition),
+    SetCompatibilityMode(CompatibilityModeDefinition),
+    SetSeriesKeyPolicy(SeriesKeyPolicyDefinition),
+    SetIdentifierPolicy(IdentifierPolicyDefinition),
+    SetWriteRateLimit(WriteRateLimitDefinition),
+    SetGen1Duration(Gen1DurationDefinition),
+    RenameDatabase(RenameDatabaseDefinition),
+    RenameTable(RenameTableDefinition),
+    MarkTableDeleted(DeleteTableDefinition),
+    UndeleteTable(DeleteTableDefinition),
+    HardDeleteTable(DeleteTableDefinition),
+    DropColumn(DropColumnDefinition),
+    RenameColumn(RenameColumnDefinition),
+    SetDatabaseMetadata(DatabaseMetadataDefinition),
+    SetTableMetadata(TableMetadataDefinition),
+    SetColumnDefault(ColumnDefaultDefinition),
+    CreateDownsamplingTask(DownsamplingTaskDefinition),
+    DeleteDownsamplingTask(DeleteDownsamplingTaskDefinition),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -252,6 +630,530 @@ pub struct DatabaseDefinition {
     pub database_name: Arc<str>,
 }
 
+/// Sets, or clears, the retention period of a database
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RetentionPeriodDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    /// Data older than this duration is dropped from the database. `None` keeps data forever.
+    pub retention_period: Option<Duration>,
+}
+
+/// Sets, or clears, the limits on how far into the future or past the timestamps of writes to a
+/// database may be, relative to the time of the write
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WriteTimeLimitsDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    /// Writes further than this into the future are rejected. `None` removes the limit.
+    pub future_write_limit: Option<Duration>,
+    /// Writes further than this into the past are rejected. `None` removes the limit.
+    pub past_write_limit: Option<Duration>,
+}
+
+/// Sets the limits on the schema of the tables in a database
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SchemaLimitsDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub schema_limits: SchemaLimits,
+}
+
+/// Sets the limits on the number of tables and series in a database
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CardinalityLimitsDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub cardinality_limits: CardinalityLimits,
+}
+
+/// Sets the schema mode of a database
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SchemaModeDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub schema_mode: SchemaMode,
+}
+
+/// Controls whether writes to a database may change its schema
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SchemaMode {
+    /// Tables and columns are created as needed by writes
+    #[default]
+    Auto,
+    /// Lines that would add a table or column are rejected
+    Strict,
+    /// New fields may be added, but lines that would add a tag are rejected
+    TagLocked,
+}
+
+impl SchemaMode {
+    pub fn is_auto(&self) -> bool {
+        *self == Self::Auto
+    }
+}
+
+impl std::fmt::Display for SchemaMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Strict => write!(f, "strict"),
+            Self::TagLocked => write!(f, "tag-locked"),
+        }
+    }
+}
+
+/// Sets the field coercion policy of a database
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FieldCoercionDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub field_coercion: FieldCoercion,
+}
+
+/// Controls how v1 writes of a field value whose type does not match the existing type of its
+/// column are handled
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FieldCoercion {
+    /// The line is rejected
+    #[default]
+    Off,
+    /// Integer values are converted when written to a float column, and unsigned integer values
+    /// when written to an integer column, as long as the value is unchanged by the conversion;
+    /// other mismatches are rejected
+    WidenNumeric,
+    /// As [`FieldCoercion::WidenNumeric`], and any value written to a string column is stored as
+    /// its string representation
+    Stringify,
+}
+
+impl FieldCoercion {
+    pub fn is_off(&self) -> bool {
+        *self == Self::Off
+    }
+}
+
+impl std::fmt::Display for FieldCoercion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::WidenNumeric => write!(f, "widen-numeric"),
+            Self::Stringify => write!(f, "stringify"),
+        }
+    }
+}
+
+/// Sets the compatibility mode of a database
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CompatibilityModeDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub compatibility_mode: CompatibilityMode,
+}
+
+/// Controls how line protocol written to a database is interpreted
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompatibilityMode {
+    /// Line protocol is parsed as is
+    #[default]
+    Off,
+    /// Field values are interpreted in the same way as InfluxDB 1.x, e.g., shorthand booleans and
+    /// floats in scientific notation are accepted
+    #[serde(rename = "influxdb1")]
+    InfluxDb1,
+}
+
+impl CompatibilityMode {
+    pub fn is_off(&self) -> bool {
+        *self == Self::Off
+    }
+}
+
+impl std::fmt::Display for CompatibilityMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::InfluxDb1 => write!(f, "influxdb1"),
+        }
+    }
+}
+
+/// Sets the series key policy of a database
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SeriesKeyPolicyDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub series_key_policy: SeriesKeyPolicy,
+}
+
+/// Controls how v3 writes whose series key does not match that of the table they are written to
+/// are handled
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SeriesKeyPolicy {
+    /// Lines must have exactly the tags in the series key of the table, in the same order
+    #[default]
+    Reject,
+    /// Lines may leave out tags in the series key of the table, which are filled with an empty
+    /// value, since series key columns are not nullable. Lines with tags that are not in the
+    /// series key are still rejected.
+    Fill,
+}
+
+impl SeriesKeyPolicy {
+    pub fn is_reject(&self) -> bool {
+        *self == Self::Reject
+    }
+}
+
+impl std::fmt::Display for SeriesKeyPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reject => write!(f, "reject"),
+            Self::Fill => write!(f, "fill"),
+        }
+    }
+}
+
+/// Sets the identifier policy of a database
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct IdentifierPolicyDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub identifier_policy: IdentifierPolicy,
+}
+
+/// Controls how the names of the tables and columns in a database are normalized, when they are
+/// written to, and looked up. Changing the policy does not rename existing tables and columns.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdentifierPolicy {
+    /// Names are used as they are, so names that differ only in case are different
+    #[default]
+    Preserve,
+    /// Names are lowercased
+    Lowercase,
+    /// Names are lowercased, and normalized to Unicode NFKC form, so that names that are written
+    /// with different, but equivalent, characters are the same
+    Normalize,
+}
+
+impl IdentifierPolicy {
+    pub fn is_preserve(&self) -> bool {
+        *self == Self::Preserve
+    }
+
+    /// Normalize a table or column name according to this policy
+    pub fn normalize<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Preserve => Cow::Borrowed(name),
+            Self::Lowercase if !name.chars().any(char::is_uppercase) => Cow::Borrowed(name),
+            Self::Lowercase => Cow::Owned(name.to_lowercase()),
+            // ASCII text is always in NFKC form:
+            Self::Normalize if name.is_ascii() => Self::Lowercase.normalize(name),
+            Self::Normalize => Cow::Owned(name.nfkc().collect::<String>().to_lowercase()),
+        }
+    }
+}
+
+impl std::fmt::Display for IdentifierPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Preserve => write!(f, "preserve"),
+            Self::Lowercase => write!(f, "lowercase"),
+            Self::Normalize => write!(f, "normalize"),
+        }
+    }
+}
+
+/// Sets the write rate limit of a database
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WriteRateLimitDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub write_rate_limit: WriteRateLimit,
+}
+
+/// Sets, or clears, the gen1 duration of a database
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Gen1DurationDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    /// The duration of the chunks that writes to the database are buffered in. `None` uses the
+    /// gen1 duration of the server.
+    pub gen1_duration: Option<Gen1Duration>,
+}
+
+/// Renames a database. The id of the database is unchanged, so data that has already been
+/// persisted remains part of it.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RenameDatabaseDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub new_database_name: Arc<str>,
+}
+
+/// Renames a table. The id of the table is unchanged, so data that has already been persisted
+/// remains part of it.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RenameTableDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub table_id: TableId,
+    pub table_name: Arc<str>,
+    pub new_table_name: Arc<str>,
+}
+
+/// Identifies a table that is being deleted, undeleted, or removed. A table is first marked as
+/// deleted, at the time of its [`CatalogBatch`], which hides it, but keeps its data, so that it
+/// can be undeleted, until it is removed for good by a hard delete.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DeleteTableDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub table_id: TableId,
+    pub table_name: Arc<str>,
+}
+
+/// Drops a field column from a table. Its data is left in the files that were already persisted,
+/// but is no longer part of the table, and its name cannot be used for a new column.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DropColumnDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub table_id: TableId,
+    pub table_name: Arc<str>,
+    pub column_id: ColumnId,
+    pub column_name: Arc<str>,
+}
+
+/// Renames a field column of a table. Files that were persisted before the rename have the
+/// column under its old name, which is mapped to the new one when they are queried.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RenameColumnDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub table_id: TableId,
+    pub table_name: Arc<str>,
+    pub column_id: ColumnId,
+    pub column_name: Arc<str>,
+    pub new_column_name: Arc<str>,
+}
+
+/// Replaces the metadata of a database, i.e., the key/value pairs that describe it, such as its
+/// owner, which are kept in the catalog but have no effect on the server
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DatabaseMetadataDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Replaces the metadata of a table, as [`DatabaseMetadataDefinition`] does for a database
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TableMetadataDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub table_id: TableId,
+    pub table_name: Arc<str>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Sets, or clears, the value that a field column of a table has in rows that are written
+/// without it, instead of null. The default is filled in when rows are buffered, so rows that
+/// were already buffered are not changed, but those replayed from the WAL are given the default
+/// that the column has when they are replayed.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ColumnDefaultDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub table_id: TableId,
+    pub table_name: Arc<str>,
+    pub column_id: ColumnId,
+    pub column_name: Arc<str>,
+    /// The default value, which must have the type of the column. `None` removes the default.
+    pub default: Option<FieldData>,
+}
+
+/// Creates a downsampling task in a database, or replaces the task with the same name
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DownsamplingTaskDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    /// The name of the source table of the task, when it was created
+    pub source_table_name: Arc<str>,
+    pub task: DownsamplingTask,
+}
+
+/// Deletes a downsampling task from a database. Data that it has already written to its target
+/// table is kept.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DeleteDownsamplingTaskDefinition {
+    pub database_id: DbId,
+    pub database_name: Arc<str>,
+    pub task_name: Arc<str>,
+}
+
+/// A task that aggregates the data persisted for a table into windows of time, and writes the
+/// results to another table, so that queries over long ranges of time can read far fewer rows
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DownsamplingTask {
+    pub name: Arc<str>,
+    /// The table whose data is aggregated
+    pub source_table_id: TableId,
+    /// The table that the results are written to, which is created by the first write to it, as
+    /// for any other write
+    pub target_table_name: Arc<str>,
+    /// The duration of the windows that the data is aggregated in. Each result row has the time
+    /// of the start of its window.
+    pub interval: Duration,
+    /// How the values of each field in a window of a series are aggregated
+    pub aggregate: DownsamplingAggregate,
+}
+
+/// The aggregate that a [`DownsamplingTask`] computes for each field. The mean, minimum, maximum,
+/// and sum are only computed for numeric fields, and the other fields are left out of the target
+/// table.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownsamplingAggregate {
+    Mean,
+    Min,
+    Max,
+    Sum,
+    /// The number of non-null values
+    Count,
+}
+
+impl std::fmt::Display for DownsamplingAggregate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mean => write!(f, "mean"),
+            Self::Min => write!(f, "min"),
+            Self::Max => write!(f, "max"),
+            Self::Sum => write!(f, "sum"),
+            Self::Count => write!(f, "count"),
+        }
+    }
+}
+
+impl FromStr for DownsamplingAggregate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Self::Mean),
+            "min" => Ok(Self::Min),
+            "max" => Ok(Self::Max),
+            "sum" => Ok(Self::Sum),
+            "count" => Ok(Self::Count),
+            _ => Err(Error::InvalidDownsamplingAggregate(s.to_string())),
+        }
+    }
+}
+
+/// Limits on the rate at which data can be written to a database. The default is to have no
+/// limit.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WriteRateLimit {
+    /// The maximum number of lines that can be written per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines_per_second: Option<u64>,
+    /// The maximum number of bytes of line protocol that can be written per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_per_second: Option<u64>,
+}
+
+impl WriteRateLimit {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Limits on the schema of the tables in a database, which are enforced when new columns are
+/// added by writes. The default is to have no limits beyond those of the catalog.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SchemaLimits {
+    /// The maximum number of columns in a table, including the time column
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_columns_per_table: Option<usize>,
+    /// The maximum length of a column name, in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_column_name_length: Option<usize>,
+    /// The characters that are allowed in column names
+    #[serde(default)]
+    pub column_name_policy: ColumnNamePolicy,
+}
+
+impl SchemaLimits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Limits on the cardinality of the data in a database, which are enforced when writes add
+/// tables or series to it. The default is to have no limits beyond those of the catalog.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CardinalityLimits {
+    /// The maximum number of tables in the database
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tables: Option<usize>,
+    /// The maximum number of distinct series, i.e., unique sets of tag values, in each table of
+    /// the database
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_series_per_table: Option<usize>,
+}
+
+impl CardinalityLimits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The characters that are allowed in the names of new columns
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnNamePolicy {
+    /// Any characters are allowed
+    #[default]
+    Any,
+    /// Any characters other than control characters are allowed
+    Printable,
+    /// Only ASCII letters, digits, and underscores are allowed, and the name cannot start with a
+    /// digit
+    Identifier,
+}
+
+impl ColumnNamePolicy {
+    /// Whether `name` is allowed as a column name by this policy
+    pub fn allows(&self, name: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Printable => !name.chars().any(char::is_control),
+            Self::Identifier => {
+                let mut chars = name.chars();
+                chars
+                    .next()
+                    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ColumnNamePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => write!(f, "any"),
+            Self::Printable => write!(f, "printable"),
+            Self::Identifier => write!(f, "identifier"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct TableDefinition {
     pub database_id: DbId,
@@ -351,6 +1253,13 @@ pub struct LastCacheDefinition {
     pub count: LastCacheSize,
     /// The time-to-live (TTL) in seconds for entries in the cache
     pub ttl: u64,
+    /// Aggregates computed over the last values of each key, which are produced as extra columns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aggregates: Vec<LastCacheAggregate>,
+    /// Whether tag columns that are added to the table after the cache was created become key
+    /// columns of the cache
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto_key_columns: bool,
 }
 
 impl LastCacheDefinition {
@@ -377,6 +1286,8 @@ impl LastCacheDefinition {
             },
             count: count.try_into()?,
             ttl,
+            aggregates: vec![],
+            auto_key_columns: false,
         })
     }
 
@@ -399,6 +1310,8 @@ impl LastCacheDefinition {
             value_columns: LastCacheValueColumnsDef::AllNonKeyColumns,
             count: count.try_into()?,
             ttl,
+            aggregates: vec![],
+            auto_key_columns: false,
         })
     }
 }
@@ -414,6 +1327,36 @@ pub enum LastCacheValueColumnsDef {
     AllNonKeyColumns,
 }
 
+/// An aggregate of the values of a column that a last cache holds for each key
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+pub struct LastCacheAggregate {
+    pub column_id: ColumnId,
+    pub function: LastCacheAggregateFunction,
+}
+
+/// The aggregate functions that a last cache can compute over the last values of a column. The
+/// minimum, maximum, and sum can only be computed for numeric fields.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum LastCacheAggregateFunction {
+    Min,
+    Max,
+    Sum,
+    /// The number of non-null values
+    Count,
+}
+
+impl std::fmt::Display for LastCacheAggregateFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Min => write!(f, "min"),
+            Self::Max => write!(f, "max"),
+            Self::Sum => write!(f, "sum"),
+            Self::Count => write!(f, "count"),
+        }
+    }
+}
+
 /// The maximum allowed size for a last cache
 pub const LAST_CACHE_MAX_SIZE: usize = 10;
 
@@ -475,6 +1418,31 @@ pub struct LastCacheDelete {
     pub name: Arc<str>,
 }
 
+/// Defines a distinct value cache in a given table and database, which holds the distinct
+/// combinations of values written to a set of tag or string field columns
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct DistinctCacheDefinition {
+    /// The table id the cache is associated with
+    pub table_id: TableId,
+    /// The table name the cache is associated with
+    pub table: Arc<str>,
+    /// Given name of the cache
+    pub name: Arc<str>,
+    /// Columns whose distinct values are held in the cache, in the order of the cache hierarchy
+    pub column_ids: Vec<ColumnId>,
+    /// The maximum number of distinct combinations of values to hold in the cache
+    pub max_cardinality: usize,
+    /// The time-to-live (TTL) in seconds for values in the cache that are not written again
+    pub ttl: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DistinctCacheDelete {
+    pub table_name: Arc<str>,
+    pub table_id: TableId,
+    pub name: Arc<str>,
+}
+
 #[serde_as]
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct WriteBatch {
@@ -532,6 +1500,60 @@ impl WriteBatch {
             }
         }
     }
+
+    /// The approximate size of the rows in the batch, in bytes
+    pub fn size_bytes(&self) -> usize {
+        self.table_chunks
+            .values()
+            .flat_map(|chunks| chunks.chunk_time_to_chunk.values())
+            .flat_map(|chunk| &chunk.rows)
+            .map(Row::size_bytes)
+            .sum()
+    }
+
+    /// Split the batch into batches whose rows are at most `max_size_bytes` in size, unless a
+    /// single row is larger than that, in which case it is in a batch of its own
+    pub fn split(self, max_size_bytes: usize) -> Vec<Self> {
+        let new_batch = || Self {
+            database_id: self.database_id,
+            database_name: Arc::clone(&self.database_name),
+            table_chunks: Default::default(),
+            min_time_ns: i64::MAX,
+            max_time_ns: i64::MIN,
+        };
+        let mut batches = vec![];
+        let mut batch = new_batch();
+        let mut batch_size = 0;
+
+        for (table_id, chunks) in &self.table_chunks {
+            for (chunk_time, chunk) in &chunks.chunk_time_to_chunk {
+                for row in &chunk.rows {
+                    let row_size = row.size_bytes();
+                    if batch_size > 0 && batch_size + row_size > max_size_bytes {
+                        batches.push(std::mem::replace(&mut batch, new_batch()));
+                        batch_size = 0;
+                    }
+                    batch_size += row_size;
+                    batch.min_time_ns = batch.min_time_ns.min(row.time);
+                    batch.max_time_ns = batch.max_time_ns.max(row.time);
+                    let table_chunks = batch.table_chunks.entry(*table_id).or_default();
+                    table_chunks.min_time = table_chunks.min_time.min(row.time);
+                    table_chunks.max_time = table_chunks.max_time.max(row.time);
+                    table_chunks
+                        .chunk_time_to_chunk
+                        .entry(*chunk_time)
+                        .or_default()
+                        .rows
+                        .push(row.clone());
+                }
+            }
+        }
+        if batch_size > 0 {
+            batches.push(batch);
+        }
+
+        batches
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -593,11 +1615,30 @@ pub struct Row {
     pub fields: Vec<Field>,
 }
 
+impl Row {
+    /// The approximate size of the row, in bytes
+    pub fn size_bytes(&self) -> usize {
+        std::mem::size_of::<i64>()
+            + self
+                .fields
+                .iter()
+                .map(|field| {
+                    std::mem::size_of::<ColumnId>()
+                        + match &field.value {
+                            FieldData::Key(s) | FieldData::Tag(s) => s.len(),
+                            FieldData::String(s) => s.len(),
+                            _ => std::mem::size_of::<u64>(),
+                        }
+                })
+                .sum::<usize>()
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum FieldData {
     Timestamp(i64),
-    Key(String),
-    Tag(String),
+    Key(Arc<str>),
+    Tag(Arc<str>),
     String(String),
     Integer(i64),
     UInteger(u64),
@@ -623,10 +1664,25 @@ impl PartialEq for FieldData {
 
 impl Eq for FieldData {}
 
+impl FieldData {
+    /// The type of the column that the value is written to
+    pub fn column_type(&self) -> InfluxColumnType {
+        match self {
+            Self::Timestamp(_) => InfluxColumnType::Timestamp,
+            Self::Key(_) | Self::Tag(_) => InfluxColumnType::Tag,
+            Self::String(_) => InfluxColumnType::Field(InfluxFieldType::String),
+            Self::Integer(_) => InfluxColumnType::Field(InfluxFieldType::Integer),
+            Self::UInteger(_) => InfluxColumnType::Field(InfluxFieldType::UInteger),
+            Self::Float(_) => InfluxColumnType::Field(InfluxFieldType::Float),
+            Self::Boolean(_) => InfluxColumnType::Field(InfluxFieldType::Boolean),
+        }
+    }
+}
+
 impl<'a> From<&SeriesValue<'a>> for FieldData {
     fn from(sk: &SeriesValue<'a>) -> Self {
         match sk {
-            SeriesValue::String(s) => Self::Key(s.to_string()),
+            SeriesValue::String(s) => Self::Key(Arc::from(s.as_str())),
         }
     }
 }
@@ -742,16 +1798,31 @@ pub struct SnapshotDetails {
     pub last_wal_sequence_number: WalFileSequenceNumber,
 }
 
+/// Flushes the WAL buffer on the flush interval, which is restarted whenever a new interval is
+/// sent on `flush_interval`
 pub fn background_wal_flush<W: Wal>(
     wal: Arc<W>,
-    flush_interval: Duration,
+    mut flush_interval: watch::Receiver<Duration>,
+    flush_requested: Arc<Notify>,
 ) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
-        let mut interval = tokio::time::interval(flush_interval);
-        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let new_interval = |period| {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            interval
+        };
+        let mut interval = new_interval(*flush_interval.borrow_and_update());
 
         loop {
-            interval.tick().await;
+            // flush on the interval, or as soon as the buffer is full enough to be flushed:
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = flush_requested.notified() => {}
+                Ok(()) = flush_interval.changed() => {
+                    interval = new_interval(*flush_interval.borrow_and_update());
+                    continue;
+                }
+            }
 
             let cleanup_after_snapshot = wal.flush_buffer().await;
 
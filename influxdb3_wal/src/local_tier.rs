@@ -0,0 +1,217 @@
+//! A tier of the WAL on local disk, in front of object storage. WAL files are written to, and
+//! synced on, the local disk before the writes in them are acknowledged, and are then uploaded to
+//! object storage in the background, so that writes do not have to wait on object storage.
+//!
+//! Local WAL files are removed once they have been uploaded. Any that are left on the disk when
+//! the server stops are uploaded when it is started again, before the WAL is replayed.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path as FsPath, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::Bytes;
+use object_store::{path::Path, ObjectStore, PutPayload};
+use observability_deps::tracing::{error, info};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{mpsc, watch},
+};
+
+use crate::{object_store::wal_path, write_fence_is_open, WalFileSequenceNumber, WriteFence};
+
+/// The extension of the WAL files in the local directory, which matches those in object storage
+const LOCAL_WAL_FILE_EXTENSION: &str = "wal";
+
+/// The extension of WAL files that are being written; they are renamed once they are complete
+const TEMP_FILE_EXTENSION: &str = "tmp";
+
+#[derive(Debug)]
+pub(crate) struct LocalWalTier {
+    dir: PathBuf,
+    /// The WAL files that have been written to the local disk, but not yet uploaded
+    pending_uploads: Arc<watch::Sender<BTreeSet<WalFileSequenceNumber>>>,
+    upload_tx: mpsc::UnboundedSender<Upload>,
+}
+
+#[derive(Debug)]
+struct Upload {
+    wal_file_number: WalFileSequenceNumber,
+    object_path: Path,
+    local_path: PathBuf,
+    data: Bytes,
+}
+
+impl LocalWalTier {
+    /// Create the local tier of the WAL in `dir`, starting the background task that uploads the
+    /// files written to it to `object_store`, until the `write_fence` is closed
+    pub(crate) fn new(
+        dir: PathBuf,
+        object_store: Arc<dyn ObjectStore>,
+        write_fence: Option<Arc<dyn WriteFence>>,
+    ) -> Self {
+        let (pending_uploads, _) = watch::channel(BTreeSet::new());
+        let pending_uploads = Arc::new(pending_uploads);
+        let (upload_tx, upload_rx) = mpsc::unbounded_channel();
+        tokio::spawn(upload_wal_files(
+            object_store,
+            upload_rx,
+            Arc::clone(&pending_uploads),
+            write_fence,
+        ));
+        Self {
+            dir,
+            pending_uploads,
+            upload_tx,
+        }
+    }
+
+    /// Upload any WAL files that were left in the local directory when the server stopped, so
+    /// that they are replayed along with the rest of the WAL in object storage
+    pub(crate) async fn reconcile(
+        &self,
+        object_store: &Arc<dyn ObjectStore>,
+        host_identifier_prefix: &str,
+    ) -> crate::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        let mut wal_files = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                // the server stopped before the file was complete, so its writes were never
+                // acknowledged:
+                Some(TEMP_FILE_EXTENSION) => tokio::fs::remove_file(&path).await?,
+                Some(LOCAL_WAL_FILE_EXTENSION) => {
+                    let wal_file_number = path
+                        .file_stem()
+                        .and_then(|s| s.to_str())
+                        .and_then(|s| s.parse::<u64>().ok())
+                        .map(WalFileSequenceNumber::new)
+                        .ok_or(crate::Error::InvalidWalFilePath)?;
+                    wal_files.push((wal_file_number, path));
+                }
+                _ => (),
+            }
+        }
+        wal_files.sort();
+
+        for (wal_file_number, local_path) in wal_files {
+            info!(?local_path, "uploading WAL file left on local disk");
+            let data = tokio::fs::read(&local_path).await?;
+            object_store
+                .put(
+                    &wal_path(host_identifier_prefix, wal_file_number),
+                    PutPayload::from(data),
+                )
+                .await?;
+            tokio::fs::remove_file(&local_path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a WAL file to the local disk, returning once it is durable there, and queue it to be
+    /// uploaded to `object_path`
+    pub(crate) async fn write(
+        &self,
+        wal_file_number: WalFileSequenceNumber,
+        object_path: Path,
+        data: Bytes,
+    ) -> std::io::Result<()> {
+        let local_path = self
+            .dir
+            .join(format!("{:011}", wal_file_number.as_u64()))
+            .with_extension(LOCAL_WAL_FILE_EXTENSION);
+        let temp_path = local_path.with_extension(TEMP_FILE_EXTENSION);
+
+        // write to a temporary file that is renamed once it is synced, so that a partially
+        // written file is never uploaded:
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        file.write_all(&data).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temp_path, &local_path).await?;
+        sync_dir(&self.dir).await?;
+
+        self.pending_uploads.send_modify(|pending| {
+            pending.insert(wal_file_number);
+        });
+        self.upload_tx
+            .send(Upload {
+                wal_file_number,
+                object_path,
+                local_path,
+                data,
+            })
+            .expect("WAL upload task should be running");
+
+        Ok(())
+    }
+
+    /// Wait until every WAL file up to and including `wal_file_number` has been uploaded
+    pub(crate) async fn wait_for_uploads(&self, wal_file_number: WalFileSequenceNumber) {
+        let mut pending_uploads = self.pending_uploads.subscribe();
+        let _ = pending_uploads
+            .wait_for(|pending| pending.first().map_or(true, |n| *n > wal_file_number))
+            .await;
+    }
+
+    /// Wait until every WAL file written so far has been uploaded
+    pub(crate) async fn wait_for_all_uploads(&self) {
+        let mut pending_uploads = self.pending_uploads.subscribe();
+        let _ = pending_uploads.wait_for(|pending| pending.is_empty()).await;
+    }
+}
+
+/// Sync the directory, so that the files that were renamed in it are durable
+async fn sync_dir(dir: &FsPath) -> std::io::Result<()> {
+    tokio::fs::File::open(dir).await?.sync_all().await
+}
+
+/// Upload the WAL files, in the order they were written, retrying until each succeeds, and
+/// remove them from the local disk once they are in object storage. Once the `write_fence` is
+/// closed, the files are left on the local disk instead, as another server may have written files
+/// with the same numbers since.
+async fn upload_wal_files(
+    object_store: Arc<dyn ObjectStore>,
+    mut upload_rx: mpsc::UnboundedReceiver<Upload>,
+    pending_uploads: Arc<watch::Sender<BTreeSet<WalFileSequenceNumber>>>,
+    write_fence: Option<Arc<dyn WriteFence>>,
+) {
+    while let Some(upload) = upload_rx.recv().await {
+        let mut uploaded = false;
+        while write_fence_is_open(write_fence.as_ref()) {
+            match object_store
+                .put(
+                    &upload.object_path,
+                    PutPayload::from_bytes(upload.data.clone()),
+                )
+                .await
+            {
+                Ok(_) => {
+                    uploaded = true;
+                    break;
+                }
+                Err(e) => {
+                    error!(%e, "error uploading wal file to object store");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+        if !uploaded {
+            error!(
+                local_path = ?upload.local_path,
+                "not uploading wal file, as the host identifier prefix may have been taken over"
+            );
+        } else if let Err(e) = tokio::fs::remove_file(&upload.local_path).await {
+            // the file will be uploaded again on restart, which is harmless
+            error!(%e, local_path = ?upload.local_path, "error removing uploaded wal file");
+        }
+        pending_uploads.send_modify(|pending| {
+            pending.remove(&upload.wal_file_number);
+        });
+    }
+}
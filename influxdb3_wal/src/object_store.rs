@@ -1,143 +1,393 @@
-use crate::serialize::verify_file_type_and_deserialize;
+use crate::encryption::{self, maybe_decrypt, maybe_encrypt, KeyProvider};
+use crate::handoff::{background_handoff_upload, HandoffQueue};
+use crate::local_tier::LocalWalTier;
+use crate::serialize::{
+    deserialize_skipping_corrupt_entries, serialize_entries_to_file_bytes, CorruptEntry,
+    EncodedEntry, SerializationBuffers,
+};
 use crate::snapshot_tracker::{SnapshotInfo, SnapshotTracker, WalPeriod};
 use crate::{
-    background_wal_flush, CatalogBatch, SnapshotDetails, SnapshotSequenceNumber, Wal, WalConfig,
-    WalContents, WalFileNotifier, WalFileSequenceNumber, WalOp, WriteBatch,
+    background_wal_flush, write_fence_is_open, NotifierId, RetainedWalFiles, SnapshotDetails,
+    SnapshotSequenceNumber, Wal, WalAckLevel, WalCompression, WalConfig, WalContents,
+    WalFileNotifier, WalFileSequenceNumber, WalOp, WalRetention, WalRuntimeConfig, WalSubscription,
+    WriteBatch, WriteFence,
 };
 use bytes::Bytes;
 use data_types::Timestamp;
-use futures_util::stream::StreamExt;
-use hashbrown::HashMap;
+use futures_util::future;
+use futures_util::stream::{self, StreamExt};
+use indexmap::{map::Entry, IndexMap};
+use metric::{DurationGauge, DurationHistogram, Registry, U64Histogram, U64HistogramOptions};
 use object_store::path::{Path, PathPart};
-use object_store::{ObjectStore, PutPayload};
-use observability_deps::tracing::{debug, error, info};
+use object_store::{ObjectMeta, ObjectStore, PutPayload};
+use observability_deps::tracing::{debug, error, info, warn};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::Mutex;
-use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
+use tokio::sync::{broadcast, oneshot, watch, Notify, OwnedSemaphorePermit, Semaphore};
+
+/// The number of times that writing a WAL file to object store is retried before it fails
+const WAL_FILE_PUT_RETRIES: usize = 100;
+
+/// The number of times that writing a WAL file to object store is retried before it is queued in
+/// the handoff queue, if there is one
+const WAL_FILE_PUT_RETRIES_BEFORE_HANDOFF: usize = 10;
+
+/// The number of WAL files that are downloaded and decoded concurrently when the WAL is replayed
+const REPLAY_CONCURRENCY: usize = 10;
+
+/// How often the retained WAL files are checked for files that are outside of the retention
+/// window, and can be deleted
+const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The number of flushed WAL files that are buffered for subscribers to the WAL, before the
+/// subscribers that have not received them yet miss them
+const SUBSCRIPTION_BUFFER_FILES: usize = 100;
+
+/// The progress of replaying the WAL files on startup
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ReplayProgress {
+    /// The number of WAL files that have been replayed so far
+    pub replayed: usize,
+    /// The number of WAL files to replay
+    pub total: usize,
+}
+
+/// The metrics that the WAL records in the metric registry
+#[derive(Debug)]
+struct WalMetrics {
+    /// How long it takes to flush the buffer into WAL files and write them
+    flush_duration: DurationHistogram,
+    /// The size of the WAL files that are written
+    file_size_bytes: U64Histogram,
+    /// How long it took to replay the WAL on startup
+    replay_duration: DurationGauge,
+}
+
+impl WalMetrics {
+    fn new(metric_registry: &Registry) -> Self {
+        let flush_duration = metric_registry
+            .register_metric::<DurationHistogram>(
+                "influxdb3_wal_flush_duration",
+                "time taken to flush the wal buffer into wal files and write them",
+            )
+            .recorder(&[]);
+        let file_size_bytes = metric_registry
+            .register_metric_with_options::<U64Histogram, _>(
+                "influxdb3_wal_file_size_bytes",
+                "size of the wal files that are written",
+                || {
+                    U64HistogramOptions::new([
+                        1_024,
+                        16 * 1_024,
+                        256 * 1_024,
+                        1_024 * 1_024,
+                        16 * 1_024 * 1_024,
+                        256 * 1_024 * 1_024,
+                        u64::MAX,
+                    ])
+                },
+            )
+            .recorder(&[]);
+        let replay_duration = metric_registry
+            .register_metric::<DurationGauge>(
+                "influxdb3_wal_replay_duration",
+                "time taken to replay the wal on startup",
+            )
+            .recorder(&[]);
+        Self {
+            flush_duration,
+            file_size_bytes,
+            replay_duration,
+        }
+    }
+}
+
+impl Default for WalMetrics {
+    fn default() -> Self {
+        Self::new(&Registry::new())
+    }
+}
 
 #[derive(Debug)]
 pub struct WalObjectStore {
     object_store: Arc<dyn ObjectStore>,
     host_identifier_prefix: String,
     file_notifier: Arc<dyn WalFileNotifier>,
+    /// Notifiers attached at runtime, which are sent the contents of each file alongside the
+    /// file notifier, but never snapshot
+    attached_notifiers: AttachedNotifiers,
+    compression: WalCompression,
+    /// Writes are split into ops of up to this size when they are buffered, so that they can be
+    /// grouped into WAL files of up to this size when the buffer is flushed
+    max_file_size_bytes: Option<usize>,
+    /// The buffers that ops are serialized with when they are buffered
+    serialization_buffers: SerializationBuffers,
+    /// If set, WAL files are written to local disk, and uploaded to object storage in the
+    /// background, rather than being written directly to object storage
+    local_tier: Option<LocalWalTier>,
+    /// If set, WAL files are encrypted with the keys that it provides
+    key_provider: Option<Arc<dyn KeyProvider>>,
+    /// If set, the WAL files that cannot be written to object store are queued here, and
+    /// uploaded in the background once it is available again
+    handoff: Option<Arc<HandoffQueue>>,
+    /// How long WAL files are retained after they have been snapshot
+    retention: WalRetention,
+    /// When the writes passed to [`Wal::write_ops`] are acknowledged
+    ack_level: WalAckLevel,
     /// Buffered wal ops go in here along with the state to track when to snapshot
     flush_buffer: Mutex<FlushBuffer>,
+    /// Notified when the buffer has reached the maximum wal file size, so that the background
+    /// flush does not wait for the flush interval
+    flush_requested: Arc<Notify>,
+    /// The interval that the buffer is flushed on, which the background flush is restarted with
+    /// when it is changed
+    flush_interval: watch::Sender<Duration>,
+    /// The contents of every WAL file that is flushed are sent to the subscribers to the WAL
+    subscribers: broadcast::Sender<Arc<WalContents>>,
+    /// The metrics that are recorded for flushes, written files, and replay
+    metrics: WalMetrics,
+    /// If set, no WAL files are written or deleted once it is closed
+    write_fence: Option<Arc<dyn WriteFence>>,
 }
 
 impl WalObjectStore {
     /// Creates a new WAL. This will replay files into the notifier and trigger any snapshots that
     /// exist in the WAL files that haven't been cleaned up yet.
+    ///
+    /// If `local_dir` is set, WAL files are written to that directory on local disk before they
+    /// are uploaded to object storage, so writes are acknowledged once they are durable on the
+    /// local disk. Any files left in the directory are uploaded before the WAL is replayed.
+    ///
+    /// If `key_provider` is set, WAL files are encrypted before they are written, and encrypted
+    /// files are decrypted when they are replayed.
+    ///
+    /// If `replay_progress` is set, the number of WAL files that have been replayed is sent on it
+    /// as the replay progresses.
+    ///
+    /// If `write_fence` is set, the WAL stops writing and deleting files once it is closed, and
+    /// the writes that are not in object storage yet fail.
+    ///
+    /// The flush latency, file sizes, and replay duration are recorded in `metric_registry`.
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         object_store: Arc<dyn ObjectStore>,
         host_identifier_prefix: impl Into<String> + Send,
         file_notifier: Arc<dyn WalFileNotifier>,
         config: WalConfig,
+        local_dir: Option<PathBuf>,
+        key_provider: Option<Arc<dyn KeyProvider>>,
         last_wal_sequence_number: Option<WalFileSequenceNumber>,
         last_snapshot_sequence_number: Option<SnapshotSequenceNumber>,
+        replay_progress: Option<watch::Sender<ReplayProgress>>,
+        write_fence: Option<Arc<dyn WriteFence>>,
+        metric_registry: &Registry,
     ) -> Result<Arc<Self>, crate::Error> {
-        let flush_interval = config.flush_interval;
-        let wal = Self::new_without_replay(
+        let mut wal = Self::new_without_replay(
             object_store,
             host_identifier_prefix,
             file_notifier,
             config,
+            local_dir,
+            key_provider,
             last_wal_sequence_number,
             last_snapshot_sequence_number,
+            write_fence,
         );
-
-        wal.replay().await?;
+        wal.metrics = WalMetrics::new(metric_registry);
+        wal.handoff = config
+            .handoff_max_bytes
+            .map(|max_bytes| Arc::new(HandoffQueue::new(max_bytes, metric_registry)));
+
+        let replay_start = Instant::now();
+        if let Some(local_tier) = &wal.local_tier {
+            local_tier
+                .reconcile(&wal.object_store, &wal.host_identifier_prefix)
+                .await?;
+        }
+        wal.replay_with_progress(replay_progress.as_ref()).await?;
+        wal.metrics.replay_duration.set(replay_start.elapsed());
         let wal = Arc::new(wal);
-        background_wal_flush(Arc::clone(&wal), flush_interval);
+        background_wal_flush(
+            Arc::clone(&wal),
+            wal.flush_interval.subscribe(),
+            Arc::clone(&wal.flush_requested),
+        );
+        background_wal_retention(Arc::clone(&wal));
+        if let Some(handoff) = &wal.handoff {
+            background_handoff_upload(
+                Arc::clone(handoff),
+                Arc::clone(&wal.object_store),
+                wal.write_fence.clone(),
+            );
+        }
 
         Ok(wal)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn new_without_replay(
         object_store: Arc<dyn ObjectStore>,
         host_identifier_prefix: impl Into<String>,
         file_notifier: Arc<dyn WalFileNotifier>,
         config: WalConfig,
+        local_dir: Option<PathBuf>,
+        key_provider: Option<Arc<dyn KeyProvider>>,
         last_wal_sequence_number: Option<WalFileSequenceNumber>,
         last_snapshot_sequence_number: Option<SnapshotSequenceNumber>,
+        write_fence: Option<Arc<dyn WriteFence>>,
     ) -> Self {
         let wal_file_sequence_number = last_wal_sequence_number.unwrap_or_default().next();
         Self {
+            local_tier: local_dir
+                .map(|dir| LocalWalTier::new(dir, Arc::clone(&object_store), write_fence.clone())),
             object_store,
             host_identifier_prefix: host_identifier_prefix.into(),
             file_notifier,
+            attached_notifiers: AttachedNotifiers::default(),
+            compression: config.compression,
+            max_file_size_bytes: config.max_file_size_bytes,
+            serialization_buffers: SerializationBuffers::default(),
+            key_provider,
+            handoff: config
+                .handoff_max_bytes
+                .map(|max_bytes| Arc::new(HandoffQueue::new(max_bytes, &Registry::new()))),
+            retention: config.retention,
+            ack_level: config.ack_level,
             flush_buffer: Mutex::new(FlushBuffer::new(
                 WalBuffer {
                     is_shutdown: false,
                     wal_file_sequence_number,
                     op_limit: config.max_write_buffer_size,
                     op_count: 0,
-                    database_to_write_batch: Default::default(),
-                    catalog_batches: vec![],
+                    size_bytes: 0,
+                    max_file_size_bytes: config.max_file_size_bytes,
+                    catalog_ops: vec![],
+                    write_ops: vec![],
                     write_op_responses: vec![],
                 },
                 SnapshotTracker::new(
                     config.snapshot_size,
                     config.gen1_duration,
                     last_snapshot_sequence_number,
-                ),
+                )
+                .with_triggers(config.snapshot_triggers),
             )),
+            flush_requested: Arc::new(Notify::new()),
+            flush_interval: watch::channel(config.flush_interval).0,
+            subscribers: broadcast::channel(SUBSCRIPTION_BUFFER_FILES).0,
+            metrics: WalMetrics::default(),
+            write_fence,
+        }
+    }
+
+    /// Check that the WAL may still write to its host identifier prefix, returning
+    /// [`crate::Error::WriteFenced`] if it may not
+    fn check_write_fence(&self) -> crate::Result<()> {
+        if write_fence_is_open(self.write_fence.as_ref()) {
+            Ok(())
+        } else {
+            Err(crate::Error::WriteFenced)
         }
     }
 
     /// Loads the WAL files in order from object store, calling the file notifier on each one and
-    /// populating the snapshot tracker with the WAL periods.
+    /// populating the snapshot tracker with the WAL periods. Files are downloaded and decoded
+    /// concurrently, but are applied to the notifier in order.
+    ///
+    /// Corrupt entries, and the truncated ends of files, are skipped, as are files that cannot be
+    /// read at all, so that the rest of the WAL can still be replayed; each is logged as a
+    /// [`crate::Error::WalCorruption`]. A file that is encrypted with a key that is not available
+    /// fails the replay, however, since the rest of the WAL cannot be read either.
     pub async fn replay(&self) -> crate::Result<()> {
+        self.replay_with_progress(None).await
+    }
+
+    async fn replay_with_progress(
+        &self,
+        progress: Option<&watch::Sender<ReplayProgress>>,
+    ) -> crate::Result<()> {
         let paths = self.load_existing_wal_file_paths().await?;
+        let total = paths.len();
+        let report_progress = |replayed| {
+            if let Some(progress) = progress {
+                progress.send_replace(ReplayProgress { replayed, total });
+            }
+        };
+        report_progress(0);
+
+        let mut wal_files = futures_util::stream::iter(paths)
+            .map(|path| self.load_wal_file(path))
+            .buffered(REPLAY_CONCURRENCY);
+        let mut replayed = 0;
+        while let Some(wal_contents) = wal_files.next().await {
+            if let Some(wal_contents) = wal_contents? {
+                self.replay_wal_file(wal_contents).await;
+            }
+            replayed += 1;
+            report_progress(replayed);
+        }
 
-        for path in paths {
-            let file_bytes = self.object_store.get(&path).await?.bytes().await?;
-            let wal_contents = verify_file_type_and_deserialize(file_bytes)?;
+        Ok(())
+    }
 
-            // add this to the snapshot tracker, so we know what to clear out later if the replay
-            // was a wal file that had a snapshot
-            self.flush_buffer
-                .lock()
-                .await
-                .replay_wal_period(WalPeriod::new(
-                    wal_contents.wal_file_number,
-                    Timestamp::new(wal_contents.min_timestamp_ns),
-                    Timestamp::new(wal_contents.max_timestamp_ns),
-                ));
+    /// Downloads and decodes a WAL file, returning `None` if it cannot be read
+    async fn load_wal_file(&self, path: Path) -> crate::Result<Option<WalContents>> {
+        let file_bytes = self.object_store.get(&path).await?.bytes().await?;
+        let key_provider = self.key_provider.clone();
+        tokio::task::spawn_blocking(move || {
+            decode_wal_file(&path, key_provider.as_deref(), file_bytes)
+        })
+        .await
+        .expect("decoding a WAL file should not panic")
+    }
 
-            match wal_contents.snapshot {
-                None => self.file_notifier.notify(wal_contents),
-                Some(snapshot_details) => {
-                    let snapshot_info = {
-                        let mut buffer = self.flush_buffer.lock().await;
+    /// Applies a replayed WAL file to the notifier, and snapshots if the file was written with a
+    /// snapshot
+    async fn replay_wal_file(&self, wal_contents: WalContents) {
+        // add this to the snapshot tracker, so we know what to clear out later if the replay
+        // was a wal file that had a snapshot
+        self.flush_buffer.lock().await.replay_wal_period(
+            WalPeriod::new(
+                wal_contents.wal_file_number,
+                Timestamp::new(wal_contents.min_timestamp_ns),
+                Timestamp::new(wal_contents.max_timestamp_ns),
+            )
+            .with_size_bytes(write_size_bytes(&wal_contents)),
+        );
+
+        match wal_contents.snapshot {
+            None => self.file_notifier.notify(wal_contents),
+            Some(snapshot_details) => {
+                let snapshot_info = {
+                    let mut buffer = self.flush_buffer.lock().await;
 
-                        match buffer.snapshot_tracker.snapshot() {
-                            None => None,
-                            Some(info) => {
-                                let semaphore = Arc::clone(&buffer.snapshot_semaphore);
-                                let permit = semaphore.acquire_owned().await.unwrap();
+                    match buffer.snapshot_tracker.snapshot() {
+                        None => None,
+                        Some(info) => {
+                            let semaphore = Arc::clone(&buffer.snapshot_semaphore);
+                            let permit = semaphore.acquire_owned().await.unwrap();
 
-                                Some((info, permit))
-                            }
+                            Some((info, permit))
                         }
-                    };
+                    }
+                };
 
-                    let snapshot_done = self
-                        .file_notifier
-                        .notify_and_snapshot(wal_contents, snapshot_details)
-                        .await;
-                    let details = snapshot_done.await.unwrap();
-                    assert_eq!(snapshot_details, details);
+                let snapshot_done = self
+                    .file_notifier
+                    .notify_and_snapshot(wal_contents, snapshot_details)
+                    .await;
+                let details = snapshot_done.await.unwrap();
+                assert_eq!(snapshot_details, details);
 
-                    // if the info is there, we have wal files to delete
-                    if let Some((snapshot_info, snapshot_permit)) = snapshot_info {
-                        self.cleanup_snapshot(snapshot_info, snapshot_permit).await;
-                    }
+                // if the info is there, we have wal files to delete
+                if let Some((snapshot_info, snapshot_permit)) = snapshot_info {
+                    self.cleanup_snapshot(snapshot_info, snapshot_permit).await;
                 }
             }
         }
-
-        Ok(())
     }
 
     /// Stop accepting write operations, flush of buffered writes to a WAL file and return when done.
@@ -146,6 +396,72 @@ impl WalObjectStore {
         self.flush_buffer.lock().await.wal_buffer.is_shutdown = true;
 
         // do the flush and wait for the snapshot if that's running
+        self.flush_and_wait_for_snapshot().await;
+
+        // and for the WAL files on local disk to be uploaded
+        if let Some(local_tier) = &self.local_tier {
+            local_tier.wait_for_all_uploads().await;
+        }
+
+        // the files in the handoff queue get one more attempt, as they are lost otherwise:
+        if let Some(handoff) = &self.handoff {
+            if let Err(e) = handoff
+                .upload(self.object_store.as_ref(), self.write_fence.as_ref())
+                .await
+            {
+                error!(
+                    %e,
+                    n_files = handoff.len(),
+                    "error uploading the wal files in the handoff queue on shutdown, the writes \
+                    in them are lost"
+                );
+            }
+        }
+    }
+
+    /// Flush any buffered writes and snapshot everything that has been written to the WAL,
+    /// returning once the snapshot has been persisted by the notifier and the snapshotted WAL
+    /// files have been removed.
+    pub async fn flush_and_snapshot_all(&self) {
+        // flush the buffered ops into a WAL file, so that they can be snapshot:
+        self.flush_and_wait_for_snapshot().await;
+
+        // then flush an empty WAL file with a forced snapshot, which takes every WAL period
+        // except the empty file, i.e., everything that was written before this was called:
+        self.flush_buffer
+            .lock()
+            .await
+            .snapshot_tracker
+            .request_forced_snapshot();
+        self.flush_and_wait_for_snapshot().await;
+
+        // the background flush may have taken the forced snapshot before we could, in which
+        // case it holds the snapshot permit until the snapshot is done:
+        let snapshot_semaphore = Arc::clone(&self.flush_buffer.lock().await.snapshot_semaphore);
+        let _permit = snapshot_semaphore
+            .acquire()
+            .await
+            .expect("snapshot semaphore permit");
+    }
+
+    /// Change the flush interval, snapshot size, and gen1 duration of the running WAL. A new
+    /// flush interval restarts the background flush, and the snapshot size and gen1 duration
+    /// apply from the next snapshot.
+    pub async fn update_config(&self, config: WalRuntimeConfig) -> crate::Result<()> {
+        config.validate()?;
+        self.flush_buffer
+            .lock()
+            .await
+            .snapshot_tracker
+            .update_config(config.snapshot_size, config.gen1_duration);
+        self.flush_interval.send_replace(config.flush_interval);
+        info!(?config, "updated wal config");
+        Ok(())
+    }
+
+    /// Flush the buffer and, if that kicks off a snapshot, wait for it to complete and remove the
+    /// snapshotted WAL files
+    async fn flush_and_wait_for_snapshot(&self) {
         if let Some((snapshot_done, snapshot_info, snapshot_permit)) = self.flush_buffer().await {
             let snapshot_details = snapshot_done.await.expect("snapshot should complete");
             assert_eq!(snapshot_info.snapshot_details, snapshot_details);
@@ -154,27 +470,63 @@ impl WalObjectStore {
         }
     }
 
-    /// Buffer into a single larger operation in memory. Returns before the operation is persisted.
+    /// Encode the ops into the entries that they are written to a WAL file as, splitting writes
+    /// that are larger than the maximum WAL file size. This is done before the ops are buffered,
+    /// so that they are serialized once, and outside of the buffer lock.
+    fn encode_ops(&self, ops: impl IntoIterator<Item = WalOp>) -> crate::Result<Vec<BufferedOp>> {
+        let mut encoded = vec![];
+        for op in ops {
+            let ops = match (op, self.max_file_size_bytes) {
+                (WalOp::Write(write_batch), Some(max_file_size_bytes)) => write_batch
+                    .split(max_file_size_bytes)
+                    .into_iter()
+                    .map(WalOp::Write)
+                    .collect(),
+                (op, _) => vec![op],
+            };
+            for op in ops {
+                let entry = self.serialization_buffers.encode(&op, self.compression)?;
+                encoded.push(BufferedOp::new(op, entry));
+            }
+        }
+        Ok(encoded)
+    }
+
+    /// Buffer the op in memory. Returns before the operation is persisted.
     async fn buffer_op_unconfirmed(&self, op: WalOp) -> crate::Result<(), crate::Error> {
-        self.flush_buffer
-            .lock()
-            .await
-            .wal_buffer
-            .buffer_op_unconfirmed(op)
+        let ops = self.encode_ops([op])?;
+        self.buffer_encoded_ops(ops).await
+    }
+
+    async fn buffer_encoded_ops(&self, ops: Vec<BufferedOp>) -> crate::Result<(), crate::Error> {
+        let mut flush_buffer = self.flush_buffer.lock().await;
+        flush_buffer.wal_buffer.buffer_ops(ops)?;
+        if flush_buffer.wal_buffer.is_full() {
+            self.flush_requested.notify_one();
+        }
+        Ok(())
     }
 
-    /// Writes the op into the buffer and waits until the WAL file is persisted. When this returns
-    /// the operation is durable in the configured object store.
-    async fn write_ops(&self, ops: Vec<WalOp>) -> crate::Result<(), crate::Error> {
+    /// Writes the op into the buffer and waits until it is acknowledged at the configured
+    /// [`WalAckLevel`], returning the level that was achieved.
+    async fn write_ops(&self, ops: Vec<WalOp>) -> crate::Result<WalAckLevel, crate::Error> {
+        let ops = self.encode_ops(ops)?;
+        if self.ack_level == WalAckLevel::Buffered {
+            self.buffer_encoded_ops(ops).await?;
+            return Ok(WalAckLevel::Buffered);
+        }
+
         let (tx, rx) = oneshot::channel();
-        self.flush_buffer
-            .lock()
-            .await
-            .wal_buffer
-            .buffer_ops_with_response(ops, tx)?;
+        {
+            let mut flush_buffer = self.flush_buffer.lock().await;
+            flush_buffer.wal_buffer.buffer_ops_with_response(ops, tx)?;
+            if flush_buffer.wal_buffer.is_full() {
+                self.flush_requested.notify_one();
+            }
+        }
 
         match rx.await {
-            Ok(WriteResult::Success(())) => Ok(()),
+            Ok(WriteResult::Success(ack_level)) => Ok(ack_level),
             Ok(WriteResult::Error(e)) => Err(crate::Error::WriteError(e)),
             Err(_) => Err(crate::Error::WriteError(
                 "oneshot channel closed".to_string(),
@@ -189,121 +541,237 @@ impl WalObjectStore {
         SnapshotInfo,
         OwnedSemaphorePermit,
     )> {
-        let (wal_contents, responses, snapshot) = {
+        let flush_start = Instant::now();
+        let (wal_files, responses, mut snapshot) = {
             let mut flush_buffer = self.flush_buffer.lock().await;
-            if flush_buffer.wal_buffer.is_empty() {
+            // an empty wal file is flushed for a forced or triggered snapshot, as the last period
+            // is never snapshot by either, unless the forced snapshot is a full one:
+            if flush_buffer.wal_buffer.is_empty()
+                && !flush_buffer.snapshot_tracker.forced_snapshot_pending()
+                && !flush_buffer.snapshot_tracker.triggered_snapshot_pending()
+            {
                 return None;
             }
             flush_buffer
                 .flush_buffer_into_contents_and_responses()
                 .await
         };
-        info!(
-            n_ops = %wal_contents.ops.len(),
-            min_timestamp_ns = %wal_contents.min_timestamp_ns,
-            max_timestamp_ns = %wal_contents.max_timestamp_ns,
-            wal_file_number = %wal_contents.wal_file_number,
-            snapshot_details = ?wal_contents.snapshot,
-            "flushing WAL buffer to object store"
-        );
 
+        let mut snapshot_response = None;
+        let mut ack_level = WalAckLevel::ObjectStorePut;
+        let mut last_wal_file_number = None;
+        for WalFile {
+            contents: wal_contents,
+            entries,
+        } in wal_files
+        {
+            info!(
+                n_ops = %wal_contents.ops.len(),
+                min_timestamp_ns = %wal_contents.min_timestamp_ns,
+                max_timestamp_ns = %wal_contents.max_timestamp_ns,
+                wal_file_number = %wal_contents.wal_file_number,
+                snapshot_details = ?wal_contents.snapshot,
+                "flushing WAL buffer to object store"
+            );
+
+            let file_ack_level = match self.write_wal_file(&wal_contents, entries).await {
+                Ok(file_ack_level) => file_ack_level,
+                Err(e) => {
+                    // the object store must be down, so drop all these responses and any in the new
+                    // buffer
+                    for response in responses {
+                        let _ = response.send(WriteResult::Error(e.to_string()));
+                    }
+
+                    self.flush_buffer
+                        .lock()
+                        .await
+                        .flush_buffer_with_failure(WriteResult::Error(e.to_string()))
+                        .await;
+
+                    return None;
+                }
+            };
+            match file_ack_level {
+                WalAckLevel::LocalDurable if ack_level == WalAckLevel::ObjectStorePut => {
+                    ack_level = WalAckLevel::LocalDurable;
+                }
+                WalAckLevel::Buffered => ack_level = WalAckLevel::Buffered,
+                _ => (),
+            }
+            last_wal_file_number = Some(wal_contents.wal_file_number);
+
+            if self.subscribers.receiver_count() > 0 {
+                let _ = self.subscribers.send(Arc::new(wal_contents.clone()));
+            }
+            self.attached_notifiers.notify(&wal_contents);
+
+            // now that we've persisted this latest notify and start the snapshot, if set
+            match wal_contents.snapshot {
+                Some(snapshot_details) => {
+                    info!(?snapshot_details, "snapshotting wal");
+                    let snapshot_done = self
+                        .file_notifier
+                        .notify_and_snapshot(wal_contents, snapshot_details)
+                        .await;
+                    let (snapshot_info, snapshot_permit) = snapshot
+                        .take()
+                        .expect("snapshot should be set when snapshot details are set");
+                    snapshot_response = Some((snapshot_done, snapshot_info, snapshot_permit));
+                }
+                None => {
+                    debug!(
+                        "notify sent to buffer for wal file {}",
+                        wal_contents.wal_file_number.as_u64()
+                    );
+                    self.file_notifier.notify(wal_contents);
+                }
+            }
+        }
+
+        // files on the local disk must be uploaded before writes that wait for object storage are
+        // acknowledged:
+        if let (Some(local_tier), Some(last_wal_file_number)) =
+            (&self.local_tier, last_wal_file_number)
+        {
+            if ack_level == WalAckLevel::LocalDurable
+                && self.ack_level == WalAckLevel::ObjectStorePut
+                && !responses.is_empty()
+            {
+                local_tier.wait_for_uploads(last_wal_file_number).await;
+                ack_level = WalAckLevel::ObjectStorePut;
+            }
+        }
+
+        // send all the responses back to clients
+        for response in responses {
+            let _ = response.send(WriteResult::Success(ack_level));
+        }
+        self.metrics.flush_duration.record(flush_start.elapsed());
+
+        snapshot_response
+    }
+
+    /// Write a WAL file from the entries that its ops were encoded into, encoding those that were
+    /// coalesced, to the local tier if there is one, or else to object store, retrying until it
+    /// succeeds or the retries are exhausted, and then queueing it in the handoff queue, if there
+    /// is one. Returns the level of durability that the file was written with.
+    async fn write_wal_file(
+        &self,
+        wal_contents: &WalContents,
+        entries: Vec<Option<EncodedEntry>>,
+    ) -> crate::Result<WalAckLevel> {
+        self.check_write_fence()?;
         let wal_path = wal_path(&self.host_identifier_prefix, wal_contents.wal_file_number);
-        let data = crate::serialize::serialize_to_file_bytes(&wal_contents)
-            .expect("unable to serialize wal contents into bytes for file");
+        let entries = wal_contents
+            .ops
+            .iter()
+            .zip(entries)
+            .map(|(op, entry)| match entry {
+                Some(entry) => entry,
+                None => self
+                    .serialization_buffers
+                    .encode(op, self.compression)
+                    .expect("unable to serialize coalesced wal op"),
+            })
+            .collect::<Vec<_>>();
+        let data = serialize_entries_to_file_bytes(
+            wal_contents,
+            &entries,
+            self.compression,
+            &self.serialization_buffers,
+        )
+        .expect("unable to serialize wal contents into bytes for file");
+        let data =
+            maybe_encrypt(self.key_provider.as_deref(), data).expect("unable to encrypt wal file");
         let data = Bytes::from(data);
+        self.metrics.file_size_bytes.record(data.len() as u64);
+
+        // if there is a local tier, the file only needs to be durable on the local disk before
+        // the writes in it are acknowledged; if that fails, fall back to writing to object store:
+        if let Some(local_tier) = &self.local_tier {
+            match local_tier
+                .write(wal_contents.wal_file_number, wal_path.clone(), data.clone())
+                .await
+            {
+                Ok(()) => return Ok(WalAckLevel::LocalDurable),
+                Err(e) => {
+                    error!(%e, "error writing wal file to local disk, writing to object store");
+                }
+            }
+        }
+
+        // while there are files in the handoff queue, the files after them are queued behind
+        // them, so that they are written to object store in order:
+        if let Some(handoff) = &self.handoff {
+            if !handoff.is_empty() {
+                return self.hand_off_wal_file(
+                    handoff,
+                    wal_contents.wal_file_number,
+                    wal_path,
+                    data,
+                );
+            }
+        }
 
+        let max_retries = match self.handoff {
+            Some(_) => WAL_FILE_PUT_RETRIES_BEFORE_HANDOFF,
+            None => WAL_FILE_PUT_RETRIES,
+        };
         let mut retry_count = 0;
 
         // keep trying to write this to object store forever
         loop {
+            self.check_write_fence()?;
             match self
                 .object_store
                 .put(&wal_path, PutPayload::from_bytes(data.clone()))
                 .await
             {
-                Ok(_) => {
-                    break;
-                }
+                Ok(_) => return Ok(WalAckLevel::ObjectStorePut),
                 Err(e) => {
                     error!(%e, "error writing wal file to object store");
                     retry_count += 1;
-                    if retry_count > 100 {
-                        // we're over max retries, the object store must be down, so drop
-                        // all these responses and any in the new buffer
-                        for response in responses {
-                            let _ = response.send(WriteResult::Error(e.to_string()));
-                        }
-
-                        self.flush_buffer
-                            .lock()
-                            .await
-                            .flush_buffer_with_failure(WriteResult::Error(e.to_string()))
-                            .await;
-
-                        return None;
+                    if retry_count > max_retries {
+                        return match &self.handoff {
+                            Some(handoff) => self.hand_off_wal_file(
+                                handoff,
+                                wal_contents.wal_file_number,
+                                wal_path,
+                                data,
+                            ),
+                            None => Err(e.into()),
+                        };
                     }
 
                     tokio::time::sleep(Duration::from_millis(100)).await;
                 }
             }
         }
+    }
 
-        // now that we've persisted this latest notify and start the snapshot, if set
-        let snapshot_response = match wal_contents.snapshot {
-            Some(snapshot_details) => {
-                info!(?snapshot_details, "snapshotting wal");
-                let snapshot_done = self
-                    .file_notifier
-                    .notify_and_snapshot(wal_contents, snapshot_details)
-                    .await;
-                let (snapshot_info, snapshot_permit) =
-                    snapshot.expect("snapshot should be set when snapshot details are set");
-                Some((snapshot_done, snapshot_info, snapshot_permit))
-            }
-            None => {
-                debug!(
-                    "notify sent to buffer for wal file {}",
-                    wal_contents.wal_file_number.as_u64()
-                );
-                self.file_notifier.notify(wal_contents);
-                None
-            }
-        };
-
-        // send all the responses back to clients
-        for response in responses {
-            let _ = response.send(WriteResult::Success(()));
+    /// Queue a WAL file in the handoff queue, failing if the queue is full
+    fn hand_off_wal_file(
+        &self,
+        handoff: &HandoffQueue,
+        wal_file_number: WalFileSequenceNumber,
+        wal_path: Path,
+        data: Bytes,
+    ) -> crate::Result<WalAckLevel> {
+        if handoff.push(wal_file_number, wal_path, data) {
+            warn!(
+                %wal_file_number,
+                n_files = handoff.len(),
+                "queued wal file in the handoff queue until object store is available"
+            );
+            Ok(WalAckLevel::Buffered)
+        } else {
+            Err(crate::Error::HandoffQueueFull(handoff.len()))
         }
-
-        snapshot_response
     }
 
     async fn load_existing_wal_file_paths(&self) -> crate::Result<Vec<Path>> {
-        let mut paths = Vec::new();
-        let mut offset: Option<Path> = None;
-        let path = Path::from(format!("{host}/wal", host = self.host_identifier_prefix));
-        loop {
-            let mut listing = if let Some(offset) = offset {
-                self.object_store.list_with_offset(Some(&path), &offset)
-            } else {
-                self.object_store.list(Some(&path))
-            };
-            let path_count = paths.len();
-
-            while let Some(item) = listing.next().await {
-                paths.push(item?.location);
-            }
-
-            if path_count == paths.len() {
-                break;
-            }
-
-            paths.sort();
-            offset = Some(paths.last().unwrap().clone())
-        }
-        paths.sort();
-
-        Ok(paths)
+        list_wal_file_paths(self.object_store.as_ref(), &self.host_identifier_prefix).await
     }
 
     async fn remove_snapshot_wal_files(
@@ -311,11 +779,45 @@ impl WalObjectStore {
         snapshot_info: SnapshotInfo,
         snapshot_permit: OwnedSemaphorePermit,
     ) {
+        if let Err(e) = self.check_write_fence() {
+            error!(%e, "not removing the snapshot wal files");
+            return;
+        }
+        // the files must be uploaded before they are deleted, or they would be uploaded again
+        // after they have been deleted, and replayed on restart:
+        let last_wal_file_number = snapshot_info
+            .wal_periods
+            .iter()
+            .map(|period| period.wal_file_number)
+            .max();
+        if let (Some(local_tier), Some(last_wal_file_number)) =
+            (&self.local_tier, last_wal_file_number)
+        {
+            local_tier.wait_for_uploads(last_wal_file_number).await;
+        }
+        // those that are still in the handoff queue need not be uploaded at all, as the snapshot
+        // has persisted the writes in them:
+        if let (Some(handoff), Some(last_wal_file_number)) = (&self.handoff, last_wal_file_number) {
+            let discarded = handoff.discard_through(last_wal_file_number).await;
+            if discarded > 0 {
+                info!(
+                    n_files = discarded,
+                    "discarded snapshot wal files from the handoff queue"
+                );
+            }
+        }
+
         for period in snapshot_info.wal_periods {
             let path = wal_path(&self.host_identifier_prefix, period.wal_file_number);
 
             loop {
-                match self.object_store.delete(&path).await {
+                // retained files are moved out of the WAL, so they are not replayed:
+                let result = if self.retention.is_enabled() {
+                    self.retain_wal_file(&path, period.wal_file_number).await
+                } else {
+                    self.object_store.delete(&path).await
+                };
+                match result {
                     Ok(_) => break,
                     Err(object_store::Error::Generic { store, source }) => {
                         error!(%store, %source, "error deleting wal file");
@@ -335,6 +837,169 @@ impl WalObjectStore {
         // release the permit so the next snapshot can be run when the time comes
         drop(snapshot_permit);
     }
+
+    /// Move a snapshot WAL file to the retained files. It is copied, rather than renamed, so that
+    /// its last modified time is when it was retained, from which its retention period starts.
+    async fn retain_wal_file(
+        &self,
+        path: &Path,
+        wal_file_number: WalFileSequenceNumber,
+    ) -> Result<(), object_store::Error> {
+        let retained_path = retained_wal_path(&self.host_identifier_prefix, wal_file_number);
+        self.object_store.copy(path, &retained_path).await?;
+        self.object_store.delete(path).await
+    }
+
+    async fn load_retained_wal_files(&self) -> crate::Result<Vec<ObjectMeta>> {
+        let dir = Path::from(format!("{}/wal_retained", self.host_identifier_prefix));
+        list_objects(self.object_store.as_ref(), &dir).await
+    }
+
+    /// Subscribe to the contents of the WAL files that are flushed, catching up from the files in
+    /// object store from `from`, if it is set. See [`Wal::subscribe`].
+    pub async fn subscribe(
+        &self,
+        from: Option<WalFileSequenceNumber>,
+    ) -> crate::Result<WalSubscription> {
+        // subscribe before listing the files to catch up on, so that none are missed in between:
+        let receiver = self.subscribers.subscribe();
+
+        if from.is_some() {
+            // files are sent to subscribers once they are on the local disk, so they must be
+            // uploaded before they can be caught up on from object store:
+            if let Some(local_tier) = &self.local_tier {
+                let last_wal_file_number = self
+                    .flush_buffer
+                    .lock()
+                    .await
+                    .snapshot_tracker
+                    .last_wal_sequence_number();
+                local_tier.wait_for_uploads(last_wal_file_number).await;
+            }
+        }
+
+        subscription(
+            receiver,
+            Arc::clone(&self.object_store),
+            &self.host_identifier_prefix,
+            self.key_provider.clone(),
+            from,
+        )
+        .await
+    }
+
+    /// Returns the range of the retained WAL files, which are pending deletion
+    pub async fn retained_wal_files(&self) -> crate::Result<Option<RetainedWalFiles>> {
+        let retained = self.load_retained_wal_files().await?;
+        let (Some(first), Some(last)) = (retained.first(), retained.last()) else {
+            return Ok(None);
+        };
+        Ok(Some(RetainedWalFiles {
+            first: WalFileSequenceNumber::try_from(&first.location)?,
+            last: WalFileSequenceNumber::try_from(&last.location)?,
+            count: retained.len(),
+        }))
+    }
+
+    /// Deletes the retained WAL files that are neither among the most recently snapshot files
+    /// nor within the retention period. Files retained under an earlier configuration are deleted
+    /// once they are outside of the current one.
+    async fn enforce_retention(&self) -> crate::Result<()> {
+        self.check_write_fence()?;
+        let retained = self.load_retained_wal_files().await?;
+        let now = SystemTime::now();
+        let deletable = retained.len().saturating_sub(self.retention.keep_files);
+
+        for meta in &retained[..deletable] {
+            if SystemTime::from(meta.last_modified) + self.retention.keep_duration > now {
+                continue;
+            }
+            debug!(path = %meta.location, "deleting retained wal file");
+            // any file that fails to delete is tried again on the next check
+            if let Err(e) = self.object_store.delete(&meta.location).await {
+                error!(%e, path = %meta.location, "error deleting retained wal file");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes, or retains, the WAL files that were left in the WAL after the snapshot that
+    /// `snapshot_wal_file` triggered. See [`Wal::collect_garbage`].
+    pub async fn collect_garbage(
+        &self,
+        snapshot_wal_file: WalFileSequenceNumber,
+        dry_run: bool,
+    ) -> crate::Result<Vec<WalFileSequenceNumber>> {
+        if !dry_run {
+            self.check_write_fence()?;
+        }
+        let last_snapshot_wal_file = self.last_snapshot_wal_file(snapshot_wal_file).await?;
+
+        let mut orphaned = vec![];
+        for path in self.load_existing_wal_file_paths().await? {
+            let wal_file_number = WalFileSequenceNumber::try_from(&path)?;
+            if wal_file_number > last_snapshot_wal_file {
+                break;
+            }
+            orphaned.push(wal_file_number);
+            if dry_run {
+                warn!(%path, "found orphaned wal file that has already been snapshot");
+                continue;
+            }
+
+            warn!(%path, "removing orphaned wal file that has already been snapshot");
+            let result = if self.retention.is_enabled() {
+                self.retain_wal_file(&path, wal_file_number).await
+            } else {
+                self.object_store.delete(&path).await
+            };
+            // any file that fails to be removed is found again on the next collection
+            if let Err(e) = result {
+                error!(%e, %path, "error removing orphaned wal file");
+            }
+        }
+
+        Ok(orphaned)
+    }
+
+    /// The last WAL file that was snapshot by the snapshot that `snapshot_wal_file` triggered,
+    /// which is read from the snapshot details in the file
+    async fn last_snapshot_wal_file(
+        &self,
+        snapshot_wal_file: WalFileSequenceNumber,
+    ) -> crate::Result<WalFileSequenceNumber> {
+        for path in [
+            wal_path(&self.host_identifier_prefix, snapshot_wal_file),
+            retained_wal_path(&self.host_identifier_prefix, snapshot_wal_file),
+        ] {
+            let file_bytes = match self.object_store.get(&path).await {
+                Ok(result) => result.bytes().await?,
+                Err(object_store::Error::NotFound { .. }) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            return decode_wal_file(&path, self.key_provider.as_deref(), file_bytes)?
+                .and_then(|contents| contents.snapshot)
+                .map(|snapshot| snapshot.last_wal_sequence_number)
+                .ok_or(crate::Error::RecoverySnapshotUnreadable(snapshot_wal_file));
+        }
+        Err(crate::Error::SnapshotWalFileMissing(snapshot_wal_file))
+    }
+}
+
+/// Periodically delete the retained WAL files that are outside of the retention window
+fn background_wal_retention(wal: Arc<WalObjectStore>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RETENTION_CHECK_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = wal.enforce_retention().await {
+                error!(%e, "error enforcing wal retention");
+            }
+        }
+    })
 }
 
 #[async_trait::async_trait]
@@ -343,7 +1008,7 @@ impl Wal for WalObjectStore {
         self.buffer_op_unconfirmed(op).await
     }
 
-    async fn write_ops(&self, ops: Vec<WalOp>) -> crate::Result<(), crate::Error> {
+    async fn write_ops(&self, ops: Vec<WalOp>) -> crate::Result<WalAckLevel, crate::Error> {
         self.write_ops(ops).await
     }
 
@@ -374,16 +1039,108 @@ impl Wal for WalObjectStore {
             .last_wal_sequence_number()
     }
 
-    async fn last_snapshot_sequence_number(&self) -> SnapshotSequenceNumber {
-        self.flush_buffer
-            .lock()
-            .await
-            .snapshot_tracker
-            .last_snapshot_sequence_number()
+    async fn subscribe(
+        &self,
+        from: Option<WalFileSequenceNumber>,
+    ) -> crate::Result<WalSubscription, crate::Error> {
+        self.subscribe(from).await
     }
 
-    async fn shutdown(&self) {
-        self.shutdown().await
+    fn attach_notifier(&self, notifier: Arc<dyn WalFileNotifier>) -> NotifierId {
+        self.attached_notifiers.attach(notifier)
+    }
+
+    fn detach_notifier(&self, id: NotifierId) -> bool {
+        self.attached_notifiers.detach(id)
+    }
+
+    async fn retained_wal_files(&self) -> crate::Result<Option<RetainedWalFiles>, crate::Error> {
+        self.retained_wal_files().await
+    }
+
+    async fn collect_garbage(
+        &self,
+        snapshot_wal_file: WalFileSequenceNumber,
+        dry_run: bool,
+    ) -> crate::Result<Vec<WalFileSequenceNumber>, crate::Error> {
+        self.collect_garbage(snapshot_wal_file, dry_run).await
+    }
+
+    async fn last_snapshot_sequence_number(&self) -> SnapshotSequenceNumber {
+        self.flush_buffer
+            .lock()
+            .await
+            .snapshot_tracker
+            .last_snapshot_sequence_number()
+    }
+
+    async fn reserve_snapshot_sequence_number(
+        &self,
+    ) -> (SnapshotSequenceNumber, WalFileSequenceNumber) {
+        // the snapshots of the WAL that are in flight, with earlier numbers, are of the files that
+        // were written before this is taken, as both are taken with the buffer locked:
+        let mut buffer = self.flush_buffer.lock().await;
+        let snapshot_sequence_number = buffer.snapshot_tracker.reserve_snapshot_sequence_number();
+        (
+            snapshot_sequence_number,
+            buffer.snapshot_tracker.last_wal_sequence_number(),
+        )
+    }
+
+    async fn shutdown(&self) {
+        self.shutdown().await
+    }
+
+    async fn force_snapshot(&self) {
+        self.flush_buffer
+            .lock()
+            .await
+            .snapshot_tracker
+            .request_forced_snapshot();
+    }
+
+    async fn force_full_snapshot(&self) {
+        self.flush_buffer
+            .lock()
+            .await
+            .snapshot_tracker
+            .request_full_snapshot();
+    }
+
+    async fn flush_and_snapshot_all(&self) {
+        self.flush_and_snapshot_all().await
+    }
+
+    async fn update_config(&self, config: WalRuntimeConfig) -> crate::Result<()> {
+        self.update_config(config).await
+    }
+}
+
+/// The notifiers attached to the WAL at runtime
+#[derive(Debug, Default)]
+pub(crate) struct AttachedNotifiers {
+    next_id: AtomicU64,
+    notifiers: parking_lot::RwLock<Vec<(NotifierId, Arc<dyn WalFileNotifier>)>>,
+}
+
+impl AttachedNotifiers {
+    pub(crate) fn attach(&self, notifier: Arc<dyn WalFileNotifier>) -> NotifierId {
+        let id = NotifierId::new(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.notifiers.write().push((id, notifier));
+        id
+    }
+
+    pub(crate) fn detach(&self, id: NotifierId) -> bool {
+        let mut notifiers = self.notifiers.write();
+        let count = notifiers.len();
+        notifiers.retain(|(notifier_id, _)| *notifier_id != id);
+        notifiers.len() < count
+    }
+
+    pub(crate) fn notify(&self, wal_contents: &WalContents) {
+        for (_, notifier) in self.notifiers.read().iter() {
+            notifier.notify(wal_contents.clone());
+        }
     }
 }
 
@@ -408,33 +1165,40 @@ impl FlushBuffer {
         self.snapshot_tracker.add_wal_period(wal_period);
     }
 
-    /// Converts the wal_buffer into contents and resets it. Returns the channels waiting for
-    /// responses. If a snapshot should occur with this flush, a semaphore permit is also returned.
+    /// Converts the wal_buffer into the contents of one or more wal files and resets it. Returns
+    /// the channels waiting for responses. If a snapshot should occur with this flush, it is set
+    /// on the last wal file, and a semaphore permit is also returned.
     async fn flush_buffer_into_contents_and_responses(
         &mut self,
     ) -> (
-        WalContents,
+        Vec<WalFile>,
         Vec<oneshot::Sender<WriteResult>>,
         Option<(SnapshotInfo, OwnedSemaphorePermit)>,
     ) {
         // convert into wal contents and resopnses and capture if a snapshot should be taken
-        let (mut wal_contents, responses) = self.flush_buffer_with_responses();
-        self.snapshot_tracker.add_wal_period(WalPeriod {
-            wal_file_number: wal_contents.wal_file_number,
-            min_time: Timestamp::new(wal_contents.min_timestamp_ns),
-            max_time: Timestamp::new(wal_contents.max_timestamp_ns),
-        });
+        let (mut wal_files, responses) = self.flush_buffer_with_responses();
+        for WalFile { contents, .. } in &wal_files {
+            self.snapshot_tracker.add_wal_period(
+                WalPeriod::new(
+                    contents.wal_file_number,
+                    Timestamp::new(contents.min_timestamp_ns),
+                    Timestamp::new(contents.max_timestamp_ns),
+                )
+                .with_size_bytes(write_size_bytes(contents)),
+            );
+        }
 
         let snapshot = match self.snapshot_tracker.snapshot() {
             Some(snapshot_info) => {
-                wal_contents.snapshot = Some(snapshot_info.snapshot_details);
+                let last_wal_file = wal_files.last_mut().expect("there is always a wal file");
+                last_wal_file.contents.snapshot = Some(snapshot_info.snapshot_details);
 
                 Some((snapshot_info, self.acquire_snapshot_permit().await))
             }
             None => None,
         };
 
-        (wal_contents, responses, snapshot)
+        (wal_files, responses, snapshot)
     }
 
     async fn acquire_snapshot_permit(&self) -> OwnedSemaphorePermit {
@@ -445,20 +1209,30 @@ impl FlushBuffer {
             .expect("snapshot semaphore permit")
     }
 
-    fn flush_buffer_with_responses(&mut self) -> (WalContents, Vec<oneshot::Sender<WriteResult>>) {
+    fn flush_buffer_with_responses(&mut self) -> (Vec<WalFile>, Vec<oneshot::Sender<WriteResult>>) {
         // swap out the filled buffer with a new one
         let mut new_buffer = WalBuffer {
             is_shutdown: false,
-            wal_file_sequence_number: self.wal_buffer.wal_file_sequence_number.next(),
+            wal_file_sequence_number: self.wal_buffer.wal_file_sequence_number,
             op_limit: self.wal_buffer.op_limit,
             op_count: 0,
-            database_to_write_batch: Default::default(),
+            size_bytes: 0,
+            max_file_size_bytes: self.wal_buffer.max_file_size_bytes,
+            catalog_ops: vec![],
+            write_ops: vec![],
             write_op_responses: vec![],
-            catalog_batches: vec![],
         };
         std::mem::swap(&mut self.wal_buffer, &mut new_buffer);
 
-        new_buffer.into_wal_contents_and_responses()
+        let (wal_files, responses) = new_buffer.into_wal_contents_and_responses();
+        self.wal_buffer.wal_file_sequence_number = wal_files
+            .last()
+            .expect("there is always a wal file")
+            .contents
+            .wal_file_number
+            .next();
+
+        (wal_files, responses)
     }
 
     async fn flush_buffer_with_failure(&mut self, error: WriteResult) {
@@ -475,14 +1249,26 @@ struct WalBuffer {
     wal_file_sequence_number: WalFileSequenceNumber,
     op_limit: usize,
     op_count: usize,
-    database_to_write_batch: HashMap<Arc<str>, WriteBatch>,
-    catalog_batches: Vec<CatalogBatch>,
+    /// The approximate size of the buffered writes, in bytes
+    size_bytes: usize,
+    max_file_size_bytes: Option<usize>,
+    /// The buffered catalog ops, which come before the writes in the wal files
+    catalog_ops: Vec<BufferedOp>,
+    /// The buffered writes, in the order that they were buffered
+    write_ops: Vec<BufferedOp>,
     write_op_responses: Vec<oneshot::Sender<WriteResult>>,
 }
 
 impl WalBuffer {
     fn is_empty(&self) -> bool {
-        self.database_to_write_batch.is_empty() && self.catalog_batches.is_empty()
+        self.write_ops.is_empty() && self.catalog_ops.is_empty()
+    }
+
+    /// Whether the buffered writes have reached the maximum size of a wal file, so should be
+    /// flushed without waiting for the flush interval
+    fn is_full(&self) -> bool {
+        self.max_file_size_bytes
+            .is_some_and(|max_file_size_bytes| self.size_bytes >= max_file_size_bytes)
     }
 }
 
@@ -491,39 +1277,22 @@ impl WalBuffer {
 // passes, we can use this to pass the object store error back to the client.
 #[derive(Debug, Clone)]
 pub enum WriteResult {
-    Success(()),
+    /// The write succeeded, with the level of durability that was achieved
+    Success(WalAckLevel),
     Error(String),
 }
 
 impl WalBuffer {
-    fn buffer_op_unconfirmed(&mut self, op: WalOp) -> crate::Result<(), crate::Error> {
+    fn buffer_ops(&mut self, ops: Vec<BufferedOp>) -> crate::Result<(), crate::Error> {
         if self.op_count >= self.op_limit {
             return Err(crate::Error::BufferFull(self.op_count));
         }
 
-        match op {
-            WalOp::Write(new_write_batch) => {
-                let db_name = Arc::clone(&new_write_batch.database_name);
-
-                // insert the database write batch or add to existing
-                let write_batch =
-                    self.database_to_write_batch
-                        .entry(db_name)
-                        .or_insert_with(|| WriteBatch {
-                            database_id: new_write_batch.database_id,
-                            database_name: new_write_batch.database_name,
-                            table_chunks: Default::default(),
-                            min_time_ns: i64::MAX,
-                            max_time_ns: i64::MIN,
-                        });
-                write_batch.add_write_batch(
-                    new_write_batch.table_chunks,
-                    new_write_batch.min_time_ns,
-                    new_write_batch.max_time_ns,
-                );
-            }
-            WalOp::Catalog(catalog_batch) => {
-                self.catalog_batches.push(catalog_batch);
+        for op in ops {
+            self.size_bytes += op.size_bytes;
+            match op.op {
+                WalOp::Write(_) => self.write_ops.push(op),
+                WalOp::Catalog(_) => self.catalog_ops.push(op),
             }
         }
 
@@ -532,54 +1301,319 @@ impl WalBuffer {
 
     fn buffer_ops_with_response(
         &mut self,
-        ops: Vec<WalOp>,
+        ops: Vec<BufferedOp>,
         response: oneshot::Sender<WriteResult>,
     ) -> crate::Result<(), crate::Error> {
         self.write_op_responses.push(response);
-        for op in ops {
-            self.buffer_op_unconfirmed(op)?;
+        self.buffer_ops(ops)
+    }
+
+    /// Converts the buffer into the wal files to write, which is a single file unless the buffer
+    /// is larger than the maximum wal file size, in which case it is split across files with
+    /// consecutive sequence numbers. The writes to each database in a file are coalesced.
+    fn into_wal_contents_and_responses(self) -> (Vec<WalFile>, Vec<oneshot::Sender<WriteResult>>) {
+        // have the catalog ops come before any writes in ordering, and group the ops into files of
+        // up to the maximum size; catalog ops are small, so are not counted towards it
+        let mut files = vec![vec![]];
+        let mut file_size_bytes = 0;
+        for op in self.catalog_ops.into_iter().chain(self.write_ops) {
+            let op_size_bytes = op.size_bytes;
+            let file = files.last_mut().expect("there is always a file");
+            if self.max_file_size_bytes.is_some_and(|max_file_size_bytes| {
+                !file.is_empty() && file_size_bytes + op_size_bytes > max_file_size_bytes
+            }) {
+                files.push(vec![op]);
+                file_size_bytes = op_size_bytes;
+            } else {
+                file.push(op);
+                file_size_bytes += op_size_bytes;
+            }
         }
 
-        Ok(())
+        let mut wal_file_number = self.wal_file_sequence_number;
+        let wal_files = files
+            .into_iter()
+            .map(|ops| {
+                let (ops, entries) = coalesce_writes(ops).into_iter().unzip();
+                let wal_file = WalFile {
+                    contents: wal_contents(wal_file_number, ops),
+                    entries,
+                };
+                wal_file_number = wal_file_number.next();
+                wal_file
+            })
+            .collect();
+
+        (wal_files, self.write_op_responses)
+    }
+}
+
+/// An op in the buffer, along with the entry that it was encoded into when it was buffered
+#[derive(Debug)]
+struct BufferedOp {
+    op: WalOp,
+    entry: EncodedEntry,
+    /// The approximate size of the op, in bytes, which is zero for catalog ops
+    size_bytes: usize,
+}
+
+impl BufferedOp {
+    fn new(op: WalOp, entry: EncodedEntry) -> Self {
+        let size_bytes = match &op {
+            WalOp::Write(write_batch) => write_batch.size_bytes(),
+            WalOp::Catalog(_) => 0,
+        };
+        Self {
+            op,
+            entry,
+            size_bytes,
+        }
+    }
+}
+
+/// Merge the writes to each database in a wal file into a single write, so that the rows for
+/// each table and chunk time are together, and replaying or notifying the file touches each table
+/// once. Returns the ops with the entries that they were encoded into, which are `None` for the
+/// merged writes, as they need to be encoded again.
+fn coalesce_writes(ops: Vec<BufferedOp>) -> Vec<(WalOp, Option<EncodedEntry>)> {
+    let mut coalesced = Vec::with_capacity(ops.len());
+    let mut writes: IndexMap<Arc<str>, (WriteBatch, Option<EncodedEntry>)> = IndexMap::new();
+    for BufferedOp { op, entry, .. } in ops {
+        match op {
+            WalOp::Catalog(_) => coalesced.push((op, Some(entry))),
+            WalOp::Write(write_batch) => match writes.entry(Arc::clone(&write_batch.database_name))
+            {
+                Entry::Occupied(mut merged) => {
+                    let (merged_batch, merged_entry) = merged.get_mut();
+                    merged_batch.add_write_batch(
+                        write_batch.table_chunks,
+                        write_batch.min_time_ns,
+                        write_batch.max_time_ns,
+                    );
+                    *merged_entry = None;
+                }
+                Entry::Vacant(vacant) => {
+                    vacant.insert((write_batch, Some(entry)));
+                }
+            },
+        }
+    }
+    coalesced.extend(
+        writes
+            .into_values()
+            .map(|(write_batch, entry)| (WalOp::Write(write_batch), entry)),
+    );
+    coalesced
+}
+
+/// A wal file to write, along with the entries that its ops were encoded into, in order, which
+/// are `None` for the ops that were coalesced when the file was flushed
+#[derive(Debug)]
+struct WalFile {
+    contents: WalContents,
+    entries: Vec<Option<EncodedEntry>>,
+}
+
+/// The approximate size of the writes in a wal file, in bytes
+fn write_size_bytes(contents: &WalContents) -> usize {
+    contents
+        .ops
+        .iter()
+        .map(|op| match op {
+            WalOp::Write(write_batch) => write_batch.size_bytes(),
+            WalOp::Catalog(_) => 0,
+        })
+        .sum()
+}
+
+/// The contents of a wal file with the given ops
+fn wal_contents(wal_file_number: WalFileSequenceNumber, ops: Vec<WalOp>) -> WalContents {
+    // get the min and max data timestamps for writes into this wal file
+    let mut min_timestamp_ns = i64::MAX;
+    let mut max_timestamp_ns = i64::MIN;
+
+    for op in &ops {
+        let (op_min_timestamp_ns, op_max_timestamp_ns) = match op {
+            WalOp::Write(write_batch) => (write_batch.min_time_ns, write_batch.max_time_ns),
+            WalOp::Catalog(catalog_batch) => (catalog_batch.time_ns, catalog_batch.time_ns),
+        };
+        min_timestamp_ns = min_timestamp_ns.min(op_min_timestamp_ns);
+        max_timestamp_ns = max_timestamp_ns.max(op_max_timestamp_ns);
+    }
+
+    // a buffer can be flushed with no ops to force a snapshot, in which case there are no
+    // timestamps to track
+    if min_timestamp_ns > max_timestamp_ns {
+        min_timestamp_ns = 0;
+        max_timestamp_ns = 0;
     }
 
-    fn into_wal_contents_and_responses(self) -> (WalContents, Vec<oneshot::Sender<WriteResult>>) {
-        // get the min and max data timestamps for writes into this wal file
-        let mut min_timestamp_ns = i64::MAX;
-        let mut max_timestamp_ns = i64::MIN;
+    WalContents {
+        min_timestamp_ns,
+        max_timestamp_ns,
+        wal_file_number,
+        ops,
+        snapshot: None,
+    }
+}
 
-        for write_batch in self.database_to_write_batch.values() {
-            min_timestamp_ns = min_timestamp_ns.min(write_batch.min_time_ns);
-            max_timestamp_ns = max_timestamp_ns.max(write_batch.max_time_ns);
+/// The WAL files of the host from `from`, both those in the WAL and those that are retained,
+/// with their metadata, in the order they were written
+pub(crate) async fn list_wal_files_from(
+    object_store: &dyn ObjectStore,
+    host_identifier_prefix: &str,
+    from: WalFileSequenceNumber,
+) -> crate::Result<Vec<(WalFileSequenceNumber, ObjectMeta)>> {
+    let mut wal_files = vec![];
+    for dir in ["wal_retained", "wal"] {
+        let dir = Path::from(format!("{host_identifier_prefix}/{dir}"));
+        for meta in list_objects(object_store, &dir).await? {
+            let wal_file_number = WalFileSequenceNumber::try_from(&meta.location)?;
+            if wal_file_number >= from {
+                wal_files.push((wal_file_number, meta));
+            }
         }
+    }
+    wal_files.sort_by_key(|(wal_file_number, _)| *wal_file_number);
+    Ok(wal_files)
+}
 
-        for catalog_batch in &self.catalog_batches {
-            min_timestamp_ns = min_timestamp_ns.min(catalog_batch.time_ns);
-            max_timestamp_ns = max_timestamp_ns.max(catalog_batch.time_ns);
+/// A subscription to the contents of the WAL files that are sent on `receiver`, which first
+/// catches up from the files of the host in object store from `from`, if it is set
+pub(crate) async fn subscription(
+    receiver: broadcast::Receiver<Arc<WalContents>>,
+    object_store: Arc<dyn ObjectStore>,
+    host_identifier_prefix: &str,
+    key_provider: Option<Arc<dyn KeyProvider>>,
+    from: Option<WalFileSequenceNumber>,
+) -> crate::Result<WalSubscription> {
+    let catch_up = match from {
+        Some(from) => {
+            list_wal_files_from(object_store.as_ref(), host_identifier_prefix, from).await?
         }
+        None => vec![],
+    };
+    let catch_up = stream::iter(catch_up)
+        .then(move |(_, meta)| {
+            let path = meta.location;
+            let object_store = Arc::clone(&object_store);
+            let key_provider = key_provider.clone();
+            async move {
+                let file_bytes = object_store.get(&path).await?.bytes().await?;
+                // unreadable files are logged and skipped, as they are on replay:
+                let wal_contents = decode_wal_file(&path, key_provider.as_deref(), file_bytes)?;
+                Ok(wal_contents.map(Arc::new))
+            }
+        })
+        .filter_map(|result: crate::Result<_>| future::ready(result.transpose()));
+
+    let flushed = stream::unfold(receiver, |mut receiver| async move {
+        let result = match receiver.recv().await {
+            Ok(wal_contents) => Ok(wal_contents),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                Err(crate::Error::SubscriptionLagged(missed))
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((result, receiver))
+    });
+
+    // files that were flushed while catching up are received twice, and only sent once:
+    let mut next = from.unwrap_or_default();
+    let subscription = catch_up.chain(flushed).filter(move |result| {
+        let send = match result {
+            Ok(wal_contents) if wal_contents.wal_file_number < next => false,
+            Ok(wal_contents) => {
+                next = wal_contents.wal_file_number.next();
+                true
+            }
+            Err(_) => true,
+        };
+        future::ready(send)
+    });
+
+    Ok(subscription.boxed())
+}
+
+/// Lists the paths of the WAL files in object store for the host, in the order they were written
+pub(crate) async fn list_wal_file_paths(
+    object_store: &dyn ObjectStore,
+    host_identifier_prefix: &str,
+) -> crate::Result<Vec<Path>> {
+    let path = Path::from(format!("{host}/wal", host = host_identifier_prefix));
+    Ok(list_objects(object_store, &path)
+        .await?
+        .into_iter()
+        .map(|meta| meta.location)
+        .collect())
+}
 
-        // have the catalog ops come before any writes in ordering
-        let mut ops =
-            Vec::with_capacity(self.database_to_write_batch.len() + self.catalog_batches.len());
+/// List the objects under `dir`, sorted by their paths
+pub(crate) async fn list_objects(
+    object_store: &dyn ObjectStore,
+    dir: &Path,
+) -> crate::Result<Vec<ObjectMeta>> {
+    let mut objects: Vec<ObjectMeta> = Vec::new();
+    let mut offset: Option<Path> = None;
+    loop {
+        let mut listing = if let Some(offset) = offset {
+            object_store.list_with_offset(Some(dir), &offset)
+        } else {
+            object_store.list(Some(dir))
+        };
+        let object_count = objects.len();
 
-        for catalog_batch in self.catalog_batches {
-            ops.push(WalOp::Catalog(catalog_batch));
+        while let Some(item) = listing.next().await {
+            objects.push(item?);
         }
 
-        for write_batch in self.database_to_write_batch.into_values() {
-            ops.push(WalOp::Write(write_batch));
+        if object_count == objects.len() {
+            break;
         }
 
-        (
-            WalContents {
-                min_timestamp_ns,
-                max_timestamp_ns,
-                wal_file_number: self.wal_file_sequence_number,
-                ops,
-                snapshot: None,
-            },
-            self.write_op_responses,
-        )
+        objects.sort_by(|a, b| a.location.cmp(&b.location));
+        offset = Some(objects.last().unwrap().location.clone())
+    }
+    objects.sort_by(|a, b| a.location.cmp(&b.location));
+
+    Ok(objects)
+}
+
+/// Decrypts and deserializes the contents of a WAL file, logging any corrupt entries that are
+/// skipped, and returning `None` if the file cannot be read at all
+pub(crate) fn decode_wal_file(
+    path: &Path,
+    key_provider: Option<&dyn KeyProvider>,
+    file_bytes: Bytes,
+) -> crate::Result<Option<WalContents>> {
+    let corruption = |CorruptEntry { offset, error }| crate::Error::WalCorruption {
+        path: path.to_string(),
+        offset,
+        source: error,
+    };
+    let file_bytes = match maybe_decrypt(key_provider, file_bytes) {
+        Ok(file_bytes) => file_bytes,
+        Err(e @ (encryption::Error::Decryption | encryption::Error::Truncated)) => {
+            let corrupt_file = CorruptEntry {
+                offset: 0,
+                error: e.into(),
+            };
+            error!(error = %corruption(corrupt_file), "skipping unreadable WAL file");
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    match deserialize_skipping_corrupt_entries(file_bytes) {
+        Ok((wal_contents, corrupt_entries)) => {
+            for corrupt_entry in corrupt_entries {
+                error!(error = %corruption(corrupt_entry), "skipping corrupt WAL entry");
+            }
+            Ok(Some(wal_contents))
+        }
+        Err(corrupt_file) => {
+            error!(error = %corruption(corrupt_file), "skipping unreadable WAL file");
+            Ok(None)
+        }
     }
 }
 
@@ -590,6 +1624,17 @@ pub fn wal_path(host_identifier_prefix: &str, wal_file_number: WalFileSequenceNu
     ))
 }
 
+/// The path that a snapshot WAL file is moved to while it is retained, outside of the WAL
+pub fn retained_wal_path(
+    host_identifier_prefix: &str,
+    wal_file_number: WalFileSequenceNumber,
+) -> Path {
+    Path::from(format!(
+        "{host_identifier_prefix}/wal_retained/{:011}.wal",
+        wal_file_number.0
+    ))
+}
+
 impl<'a> TryFrom<&'a Path> for WalFileSequenceNumber {
     type Error = crate::Error;
 
@@ -609,10 +1654,13 @@ impl<'a> TryFrom<&'a Path> for WalFileSequenceNumber {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encryption::{EncryptionKey, StaticKeyProvider};
     use crate::{
-        Field, FieldData, Gen1Duration, Row, SnapshotSequenceNumber, TableChunk, TableChunks,
+        CatalogBatch, Field, FieldData, Gen1Duration, Row, SnapshotSequenceNumber, TableChunk,
+        TableChunks, WriteBatch,
     };
     use async_trait::async_trait;
+    use hashbrown::HashMap;
     use indexmap::IndexMap;
     use influxdb3_id::{ColumnId, DbId, TableId};
     use object_store::memory::InMemory;
@@ -628,6 +1676,12 @@ mod tests {
             flush_interval: Duration::from_secs(1),
             snapshot_size: 2,
             gen1_duration: Gen1Duration::new_1m(),
+            compression: Default::default(),
+            max_file_size_bytes: None,
+            retention: Default::default(),
+            ack_level: Default::default(),
+            snapshot_triggers: Default::default(),
+            handoff_max_bytes: None,
         };
         let wal = WalObjectStore::new_without_replay(
             Arc::clone(&object_store),
@@ -636,6 +1690,9 @@ mod tests {
             wal_config,
             None,
             None,
+            None,
+            None,
+            None,
         );
 
         let db_name: Arc<str> = "db1".into();
@@ -726,10 +1783,13 @@ mod tests {
         // create wal file 1
         let ret = wal.flush_buffer().await;
         assert!(ret.is_none());
+        // the writes to the same table are coalesced into a single write:
         let file_1_contents = WalContents {
             min_timestamp_ns: 1,
             max_timestamp_ns: 62_000000000,
             wal_file_number: WalFileSequenceNumber(1),
+            max_timestamp_ns: 62_000000000,
+            wal_file_number: WalFileSequenceNumber(1),
             ops: vec![WalOp::Write(WriteBatch {
                 database_id: DbId::from(0),
                 database_name: "db1".into(),
@@ -847,9 +1907,18 @@ mod tests {
                 max_write_buffer_size: 10,
                 flush_interval: Duration::from_millis(10),
                 snapshot_size: 2,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
             },
             None,
             None,
+            None,
+            None,
+            None,
         );
         assert_eq!(
             replay_wal.load_existing_wal_file_paths().await.unwrap(),
@@ -916,16 +1985,16 @@ mod tests {
                 last_wal_sequence_number: WalFileSequenceNumber(2),
             },
             wal_periods: vec![
-                WalPeriod {
-                    wal_file_number: WalFileSequenceNumber(1),
-                    min_time: Timestamp::new(1),
-                    max_time: Timestamp::new(62000000000),
-                },
-                WalPeriod {
-                    wal_file_number: WalFileSequenceNumber(2),
-                    min_time: Timestamp::new(62000000000),
-                    max_time: Timestamp::new(62000000000),
-                },
+                WalPeriod::new(
+                    WalFileSequenceNumber(1),
+                    Timestamp::new(1),
+                    Timestamp::new(62000000000),
+                ),
+                WalPeriod::new(
+                    WalFileSequenceNumber(2),
+                    Timestamp::new(62000000000),
+                    Timestamp::new(62000000000),
+                ),
             ],
         };
         assert_eq!(expected_info, snapshot_info);
@@ -996,6 +2065,9 @@ mod tests {
             wal_config,
             None,
             None,
+            None,
+            None,
+            None,
         );
         assert_eq!(
             replay_wal.load_existing_wal_file_paths().await.unwrap(),
@@ -1021,6 +2093,12 @@ mod tests {
             flush_interval: Duration::from_secs(1),
             snapshot_size: 2,
             gen1_duration: Gen1Duration::new_1m(),
+            compression: Default::default(),
+            max_file_size_bytes: None,
+            retention: Default::default(),
+            ack_level: Default::default(),
+            snapshot_triggers: Default::default(),
+            handoff_max_bytes: None,
         };
         let wal = WalObjectStore::new_without_replay(
             Arc::clone(&object_store),
@@ -1029,6 +2107,9 @@ mod tests {
             wal_config,
             None,
             None,
+            None,
+            None,
+            None,
         );
 
         assert!(wal.flush_buffer().await.is_none());
@@ -1039,6 +2120,953 @@ mod tests {
         assert!(object_store.list(None).next().await.is_none());
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn flush_and_snapshot_all_includes_buffered_writes() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let notifier: Arc<dyn WalFileNotifier> = Arc::new(TestNotfiier::default());
+        let wal_config = WalConfig {
+            max_write_buffer_size: 100,
+            flush_interval: Duration::from_secs(1),
+            snapshot_size: 100,
+            gen1_duration: Gen1Duration::new_1m(),
+            compression: Default::default(),
+            max_file_size_bytes: None,
+            retention: Default::default(),
+            ack_level: Default::default(),
+            snapshot_triggers: Default::default(),
+            handoff_max_bytes: None,
+        };
+        let wal = WalObjectStore::new_without_replay(
+            Arc::clone(&object_store),
+            "my_host",
+            Arc::clone(&notifier),
+            wal_config,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+
+        // nothing has been written, so there is nothing to snapshot:
+        wal.flush_and_snapshot_all().await;
+        let test_notifier = notifier.as_any().downcast_ref::<TestNotfiier>().unwrap();
+        assert!(test_notifier.snapshot_details.lock().is_none());
+
+        wal.buffer_op_unconfirmed(WalOp::Write(WriteBatch {
+            database_id: DbId::from(0),
+            database_name: "db1".into(),
+            table_chunks: IndexMap::from([(
+                TableId::from(0),
+                TableChunks {
+                    min_time: 90_000000000,
+                    max_time: 90_000000000,
+                    chunk_time_to_chunk: HashMap::from([(
+                        60_000000000,
+                        TableChunk {
+                            rows: vec![Row {
+                                time: 90_000000000,
+                                fields: vec![Field {
+                                    id: ColumnId::from(0),
+                                    value: FieldData::Integer(1),
+                                }],
+                            }],
+                        },
+                    )]),
+                },
+            )])
+            .into(),
+            min_time_ns: 90_000000000,
+            max_time_ns: 90_000000000,
+        }))
+        .await
+        .unwrap();
+        wal.flush_and_snapshot_all().await;
+
+        // the write is flushed to file 1, which is snapshot along with all of its data when the
+        // empty file 2 is flushed:
+        assert_eq!(
+            *test_notifier.snapshot_details.lock(),
+            Some(SnapshotDetails {
+                snapshot_sequence_number: SnapshotSequenceNumber::new(1),
+                end_time_marker: 120_000000000,
+                last_wal_sequence_number: WalFileSequenceNumber(1),
+            })
+        );
+        assert_eq!(
+            wal.load_existing_wal_file_paths().await.unwrap(),
+            vec![Path::from("my_host/wal/00000000002.wal")]
+        );
+    }
+
+    #[tokio::test]
+    async fn local_tier_uploads_and_reconciles_wal_files() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let local_dir = test_helpers::tmp_dir().unwrap();
+        let notifier: Arc<dyn WalFileNotifier> = Arc::new(TestNotfiier::default());
+        let wal = WalObjectStore::new_without_replay(
+            Arc::clone(&object_store),
+            "my_host",
+            Arc::clone(&notifier),
+            WalConfig::test_config(),
+            Some(local_dir.path().to_path_buf()),
+            None,
+            None,
+            None,
+            None,
+        );
+        let write = |time: i64| {
+            WalOp::Write(WriteBatch {
+                database_id: DbId::from(0),
+                database_name: "db1".into(),
+                table_chunks: IndexMap::from([(
+                    TableId::from(0),
+                    TableChunks {
+                        min_time: time,
+                        max_time: time,
+                        chunk_time_to_chunk: HashMap::from([(
+                            0,
+                            TableChunk {
+                                rows: vec![Row {
+                                    time,
+                                    fields: vec![Field {
+                                        id: ColumnId::from(0),
+                                        value: FieldData::Integer(time),
+                                    }],
+                                }],
+                            },
+                        )]),
+                    },
+                )])
+                .into(),
+                min_time_ns: time,
+                max_time_ns: time,
+            })
+        };
+
+        // the write is acknowledged once it is on local disk, and uploaded in the background:
+        wal.buffer_op_unconfirmed(write(1)).await.unwrap();
+        assert!(wal.flush_buffer().await.is_none());
+        let test_notifier = notifier.as_any().downcast_ref::<TestNotfiier>().unwrap();
+        assert_eq!(1, test_notifier.notified_writes.lock().len());
+        wal.local_tier
+            .as_ref()
+            .unwrap()
+            .wait_for_all_uploads()
+            .await;
+        assert_eq!(
+            wal.load_existing_wal_file_paths().await.unwrap(),
+            vec![Path::from("my_host/wal/00000000001.wal")]
+        );
+        assert_eq!(0, std::fs::read_dir(local_dir.path()).unwrap().count());
+
+        // a file that was left on local disk, e.g., because the server crashed before it was
+        // uploaded, is uploaded and replayed on restart:
+        let contents = WalContents {
+            min_timestamp_ns: 2,
+            max_timestamp_ns: 2,
+            wal_file_number: WalFileSequenceNumber::new(2),
+            ops: vec![write(2)],
+            snapshot: None,
+        };
+        std::fs::write(
+            local_dir.path().join("00000000002.wal"),
+            crate::serialize::serialize_to_file_bytes(&contents, WalCompression::None).unwrap(),
+        )
+        .unwrap();
+        let replay_notifier: Arc<dyn WalFileNotifier> = Arc::new(TestNotfiier::default());
+        let replay_wal = WalObjectStore::new(
+            Arc::clone(&object_store),
+            "my_host",
+            Arc::clone(&replay_notifier),
+            WalConfig::test_config(),
+            Some(local_dir.path().to_path_buf()),
+            None,
+            None,
+            None,
+            None,
+            None,
+            &Registry::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            replay_wal.load_existing_wal_file_paths().await.unwrap(),
+            vec![
+                Path::from("my_host/wal/00000000001.wal"),
+                Path::from("my_host/wal/00000000002.wal"),
+            ]
+        );
+        assert_eq!(0, std::fs::read_dir(local_dir.path()).unwrap().count());
+        let replay_notifier = replay_notifier
+            .as_any()
+            .downcast_ref::<TestNotfiier>()
+            .unwrap();
+        let notified_writes = replay_notifier.notified_writes.lock();
+        assert_eq!(2, notified_writes.len());
+        assert_eq!(contents, notified_writes[1]);
+    }
+
+    #[tokio::test]
+    async fn replay_skips_unreadable_wal_files() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let contents = WalContents {
+            min_timestamp_ns: 0,
+            max_timestamp_ns: 0,
+            wal_file_number: WalFileSequenceNumber::new(2),
+            ops: vec![],
+            snapshot: None,
+        };
+        object_store
+            .put(
+                &wal_path("my_host", WalFileSequenceNumber::new(1)),
+                PutPayload::from_static(b"idb3.003 not a wal file"),
+            )
+            .await
+            .unwrap();
+        object_store
+            .put(
+                &wal_path("my_host", WalFileSequenceNumber::new(2)),
+                crate::serialize::serialize_to_file_bytes(&contents, WalCompression::None)
+                    .unwrap()
+                    .into(),
+            )
+            .await
+            .unwrap();
+
+        let notifier: Arc<dyn WalFileNotifier> = Arc::new(TestNotfiier::default());
+        let wal = WalObjectStore::new_without_replay(
+            Arc::clone(&object_store),
+            "my_host",
+            Arc::clone(&notifier),
+            WalConfig::test_config(),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        wal.replay().await.unwrap();
+
+        let notifier = notifier.as_any().downcast_ref::<TestNotfiier>().unwrap();
+        assert_eq!(vec![contents], *notifier.notified_writes.lock());
+    }
+
+    #[tokio::test]
+    async fn encrypted_wal_files_are_replayed_with_the_key() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let key_provider: Arc<dyn KeyProvider> = Arc::new(StaticKeyProvider::new(
+            EncryptionKey::new([7; crate::encryption::KEY_LEN]),
+        ));
+        let notifier: Arc<dyn WalFileNotifier> = Arc::new(TestNotfiier::default());
+        let wal = WalObjectStore::new_without_replay(
+            Arc::clone(&object_store),
+            "my_host",
+            Arc::clone(&notifier),
+            WalConfig::test_config(),
+            None,
+            Some(Arc::clone(&key_provider)),
+            None,
+            None,
+            None,
+        );
+        wal.buffer_op_unconfirmed(WalOp::Catalog(CatalogBatch {
+            database_id: DbId::from(0),
+            database_name: "db1".into(),
+            time_ns: 0,
+            ops: vec![],
+        }))
+        .await
+        .unwrap();
+        assert!(wal.flush_buffer().await.is_none());
+
+        let file_bytes = object_store
+            .get(&wal_path("my_host", WalFileSequenceNumber::new(1)))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert!(file_bytes.starts_with(crate::encryption::ENCRYPTED_FILE_IDENTIFIER));
+
+        let replay_notifier: Arc<dyn WalFileNotifier> = Arc::new(TestNotfiier::default());
+        let replay_wal = WalObjectStore::new_without_replay(
+            Arc::clone(&object_store),
+            "my_host",
+            Arc::clone(&replay_notifier),
+            WalConfig::test_config(),
+            None,
+            Some(key_provider),
+            None,
+            None,
+            None,
+        );
+        replay_wal.replay().await.unwrap();
+        let test_notifier = notifier.as_any().downcast_ref::<TestNotfiier>().unwrap();
+        assert_eq!(1, test_notifier.notified_writes.lock().len());
+        let replay_notifier = replay_notifier
+            .as_any()
+            .downcast_ref::<TestNotfiier>()
+            .unwrap();
+        assert_eq!(
+            *test_notifier.notified_writes.lock(),
+            *replay_notifier.notified_writes.lock()
+        );
+
+        // without the key, the WAL cannot be replayed:
+        let replay_wal = WalObjectStore::new_without_replay(
+            object_store,
+            "my_host",
+            Arc::new(TestNotfiier::default()),
+            WalConfig::test_config(),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        assert!(matches!(
+            replay_wal.replay().await,
+            Err(crate::Error::Encryption(
+                crate::encryption::Error::NoKeyProvider
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn oversized_buffer_is_split_across_wal_files() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let row = |time: i64| Row {
+            time,
+            fields: vec![Field {
+                id: ColumnId::from(0),
+                value: FieldData::Integer(time),
+            }],
+        };
+        let notifier: Arc<dyn WalFileNotifier> = Arc::new(TestNotfiier::default());
+        let wal = WalObjectStore::new_without_replay(
+            Arc::clone(&object_store),
+            "my_host",
+            Arc::clone(&notifier),
+            WalConfig {
+                max_file_size_bytes: Some(row(0).size_bytes() * 2),
+                ..WalConfig::test_config()
+            },
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+
+        wal.buffer_op_unconfirmed(WalOp::Write(WriteBatch {
+            database_id: DbId::from(0),
+            database_name: "db1".into(),
+            table_chunks: IndexMap::from([(
+                TableId::from(0),
+                TableChunks {
+                    min_time: 1,
+                    max_time: 5,
+                    chunk_time_to_chunk: HashMap::from([(
+                        0,
+                        TableChunk {
+                            rows: (1..=5).map(row).collect(),
+                        },
+                    )]),
+                },
+            )])
+            .into(),
+            min_time_ns: 1,
+            max_time_ns: 5,
+        }))
+        .await
+        .unwrap();
+        assert!(wal.flush_buffer.lock().await.wal_buffer.is_full());
+        assert!(wal.flush_buffer().await.is_none());
+
+        // the five rows are split across three files, of two, two, and one row:
+        let notifier = notifier.as_any().downcast_ref::<TestNotfiier>().unwrap();
+        let notified_writes = notifier.notified_writes.lock().clone();
+        assert_eq!(
+            vec![
+                (WalFileSequenceNumber::new(1), 1, 2),
+                (WalFileSequenceNumber::new(2), 3, 4),
+                (WalFileSequenceNumber::new(3), 5, 5),
+            ],
+            notified_writes
+                .iter()
+                .map(|c| (c.wal_file_number, c.min_timestamp_ns, c.max_timestamp_ns))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(3, wal.load_existing_wal_file_paths().await.unwrap().len());
+        assert_eq!(
+            WalFileSequenceNumber::new(3),
+            wal.last_wal_sequence_number().await
+        );
+        assert!(!wal.flush_buffer.lock().await.wal_buffer.is_full());
+    }
+
+    #[tokio::test]
+    async fn subscribers_catch_up_and_receive_flushed_wal_files() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let notifier: Arc<dyn WalFileNotifier> = Arc::new(TestNotfiier::default());
+        let wal = WalObjectStore::new_without_replay(
+            Arc::clone(&object_store),
+            "my_host",
+            notifier,
+            WalConfig::test_config(),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let flush_op = || async {
+            wal.buffer_op_unconfirmed(WalOp::Catalog(CatalogBatch {
+                database_id: DbId::from(0),
+                database_name: "db1".into(),
+                time_ns: 0,
+                ops: vec![],
+            }))
+            .await
+            .unwrap();
+            assert!(wal.flush_buffer().await.is_none());
+        };
+
+        flush_op().await;
+        let mut caught_up = wal
+            .subscribe(Some(WalFileSequenceNumber::new(1)))
+            .await
+            .unwrap();
+        let mut flushed = wal.subscribe(None).await.unwrap();
+        flush_op().await;
+
+        for expected in [1, 2] {
+            let wal_contents = caught_up.next().await.unwrap().unwrap();
+            assert_eq!(
+                WalFileSequenceNumber::new(expected),
+                wal_contents.wal_file_number
+            );
+        }
+        let wal_contents = flushed.next().await.unwrap().unwrap();
+        assert_eq!(WalFileSequenceNumber::new(2), wal_contents.wal_file_number);
+    }
+
+    #[tokio::test]
+    async fn attached_notifiers_are_notified_until_detached() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let notifier: Arc<dyn WalFileNotifier> = Arc::new(TestNotfiier::default());
+        let wal = WalObjectStore::new_without_replay(
+            Arc::clone(&object_store),
+            "my_host",
+            Arc::clone(&notifier),
+            WalConfig::test_config(),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let flush_op = || async {
+            wal.buffer_op_unconfirmed(WalOp::Catalog(CatalogBatch {
+                database_id: DbId::from(0),
+                database_name: "db1".into(),
+                time_ns: 0,
+                ops: vec![],
+            }))
+            .await
+            .unwrap();
+            assert!(wal.flush_buffer().await.is_none());
+        };
+        let attached: Arc<dyn WalFileNotifier> = Arc::new(TestNotfiier::default());
+        let id = Wal::attach_notifier(&wal, Arc::clone(&attached));
+
+        flush_op().await;
+        assert!(Wal::detach_notifier(&wal, id));
+        assert!(!Wal::detach_notifier(&wal, id));
+        flush_op().await;
+
+        let notified_files = |notifier: &Arc<dyn WalFileNotifier>| {
+            let notifier = notifier.as_any().downcast_ref::<TestNotfiier>().unwrap();
+            notifier
+                .notified_writes
+                .lock()
+                .iter()
+                .map(|contents| contents.wal_file_number.as_u64())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![1, 2], notified_files(&notifier));
+        assert_eq!(vec![1], notified_files(&attached));
+    }
+
+    #[tokio::test]
+    async fn writes_are_acknowledged_at_the_configured_level() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let wal = |host: &str, ack_level: WalAckLevel, local_dir: Option<PathBuf>| {
+            Arc::new(WalObjectStore::new_without_replay(
+                Arc::clone(&object_store),
+                host,
+                Arc::new(TestNotfiier::default()),
+                WalConfig {
+                    ack_level,
+                    ..WalConfig::test_config()
+                },
+                local_dir,
+                None,
+                None,
+                None,
+                None,
+            ))
+        };
+        fn write() -> Vec<WalOp> {
+            vec![WalOp::Write(WriteBatch {
+                database_id: DbId::from(0),
+                database_name: "db1".into(),
+                table_chunks: Default::default(),
+                min_time_ns: 0,
+                max_time_ns: 0,
+            })]
+        }
+        // write, and flush the write once it is buffered:
+        let write_and_flush = |wal: Arc<WalObjectStore>| async move {
+            let ack = tokio::spawn({
+                let wal = Arc::clone(&wal);
+                async move { wal.write_ops(write()).await }
+            });
+            while wal.flush_buffer.lock().await.wal_buffer.is_empty() {
+                tokio::task::yield_now().await;
+            }
+            wal.flush_buffer().await;
+            ack.await.unwrap().unwrap()
+        };
+
+        // buffered writes are acknowledged without waiting for a flush:
+        let buffered = wal("buffered", WalAckLevel::Buffered, None);
+        assert_eq!(
+            WalAckLevel::Buffered,
+            buffered.write_ops(write()).await.unwrap()
+        );
+        assert!(!buffered.flush_buffer.lock().await.wal_buffer.is_empty());
+
+        // without a local directory, files are always put to object store:
+        for ack_level in [WalAckLevel::ObjectStorePut, WalAckLevel::LocalDurable] {
+            assert_eq!(
+                WalAckLevel::ObjectStorePut,
+                write_and_flush(wal("direct", ack_level, None)).await
+            );
+        }
+
+        let local_dir = test_helpers::tmp_dir().unwrap();
+        let local = wal(
+            "local",
+            WalAckLevel::LocalDurable,
+            Some(local_dir.path().to_path_buf()),
+        );
+        assert_eq!(WalAckLevel::LocalDurable, write_and_flush(local).await);
+
+        // writes that wait for object store are acknowledged once the file is uploaded:
+        let local_dir = test_helpers::tmp_dir().unwrap();
+        let uploaded = wal(
+            "uploaded",
+            WalAckLevel::ObjectStorePut,
+            Some(local_dir.path().to_path_buf()),
+        );
+        assert_eq!(WalAckLevel::ObjectStorePut, write_and_flush(uploaded).await);
+        assert_eq!(
+            vec![Path::from("uploaded/wal/00000000001.wal")],
+            list_wal_file_paths(object_store.as_ref(), "uploaded")
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn wal_files_are_queued_behind_those_in_the_handoff_queue() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let wal = Arc::new(WalObjectStore::new_without_replay(
+            Arc::clone(&object_store),
+            "my_host",
+            Arc::new(TestNotfiier::default()),
+            WalConfig {
+                handoff_max_bytes: Some(1_000_000),
+                ..WalConfig::test_config()
+            },
+            None,
+            None,
+            Some(WalFileSequenceNumber::new(1)),
+            None,
+            None,
+        ));
+        // file 1 could not be written to object store:
+        let handoff = Arc::clone(wal.handoff.as_ref().unwrap());
+        assert!(handoff.push(
+            WalFileSequenceNumber::new(1),
+            wal_path("my_host", WalFileSequenceNumber::new(1)),
+            Bytes::from_static(b"file 1"),
+        ));
+
+        // so file 2 is queued behind it, and its writes are acknowledged as buffered:
+        let ack = tokio::spawn({
+            let wal = Arc::clone(&wal);
+            async move {
+                wal.write_ops(vec![WalOp::Write(WriteBatch {
+                    database_id: DbId::from(0),
+                    database_name: "db1".into(),
+                    table_chunks: Default::default(),
+                    min_time_ns: 0,
+                    max_time_ns: 0,
+                })])
+                .await
+            }
+        });
+        while wal.flush_buffer.lock().await.wal_buffer.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        wal.flush_buffer().await;
+        assert_eq!(WalAckLevel::Buffered, ack.await.unwrap().unwrap());
+        assert_eq!(2, handoff.len());
+        assert!(list_wal_file_paths(object_store.as_ref(), "my_host")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // once object store is available, they are uploaded in order:
+        assert_eq!(
+            2,
+            handoff.upload(object_store.as_ref(), None).await.unwrap()
+        );
+        assert_eq!(
+            vec![
+                Path::from("my_host/wal/00000000001.wal"),
+                Path::from("my_host/wal/00000000002.wal"),
+            ],
+            list_wal_file_paths(object_store.as_ref(), "my_host")
+                .await
+                .unwrap()
+        );
+        let contents = wal
+            .load_wal_file(Path::from("my_host/wal/00000000002.wal"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, contents.ops.len());
+    }
+
+    #[tokio::test]
+    async fn garbage_collection_removes_orphaned_snapshot_wal_files() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        // file 3 triggered a snapshot of files 1 and 2, which were never removed:
+        for wal_file_number in 1..=4 {
+            let contents = WalContents {
+                min_timestamp_ns: 0,
+                max_timestamp_ns: 0,
+                wal_file_number: WalFileSequenceNumber::new(wal_file_number),
+                ops: vec![],
+                snapshot: (wal_file_number == 3).then_some(SnapshotDetails {
+                    snapshot_sequence_number: SnapshotSequenceNumber::new(1),
+                    end_time_marker: 0,
+                    last_wal_sequence_number: WalFileSequenceNumber::new(2),
+                }),
+            };
+            object_store
+                .put(
+                    &wal_path("my_host", contents.wal_file_number),
+                    crate::serialize::serialize_to_file_bytes(&contents, WalCompression::None)
+                        .unwrap()
+                        .into(),
+                )
+                .await
+                .unwrap();
+        }
+        let wal = WalObjectStore::new_without_replay(
+            Arc::clone(&object_store),
+            "my_host",
+            Arc::new(TestNotfiier::default()),
+            WalConfig::test_config(),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let n = WalFileSequenceNumber::new;
+
+        // a dry run only finds the orphaned files:
+        assert_eq!(
+            vec![n(1), n(2)],
+            wal.collect_garbage(n(3), true).await.unwrap()
+        );
+        assert_eq!(4, wal.load_existing_wal_file_paths().await.unwrap().len());
+
+        assert_eq!(
+            vec![n(1), n(2)],
+            wal.collect_garbage(n(3), false).await.unwrap()
+        );
+        assert_eq!(
+            wal.load_existing_wal_file_paths().await.unwrap(),
+            vec![
+                Path::from("my_host/wal/00000000003.wal"),
+                Path::from("my_host/wal/00000000004.wal")
+            ]
+        );
+        assert!(wal.collect_garbage(n(3), false).await.unwrap().is_empty());
+
+        assert!(matches!(
+            wal.collect_garbage(n(5), false).await,
+            Err(crate::Error::SnapshotWalFileMissing(missing)) if missing == n(5)
+        ));
+    }
+
+    #[tokio::test]
+    async fn writes_to_a_table_are_coalesced_in_a_wal_file() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let notifier: Arc<dyn WalFileNotifier> = Arc::new(TestNotfiier::default());
+        let wal = WalObjectStore::new_without_replay(
+            Arc::clone(&object_store),
+            "my_host",
+            Arc::clone(&notifier),
+            WalConfig::test_config(),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let row = |time: i64| Row {
+            time,
+            fields: vec![Field {
+                id: ColumnId::from(0),
+                value: FieldData::Integer(time),
+            }],
+        };
+        let write = |db: u32, rows: Vec<Row>| {
+            let min_time = rows.iter().map(|r| r.time).min().unwrap();
+            let max_time = rows.iter().map(|r| r.time).max().unwrap();
+            WalOp::Write(WriteBatch {
+                database_id: DbId::from(db),
+                database_name: format!("db{db}").into(),
+                table_chunks: IndexMap::from([(
+                    TableId::from(0),
+                    TableChunks {
+                        min_time,
+                        max_time,
+                        chunk_time_to_chunk: HashMap::from([(0, TableChunk { rows })]),
+                    },
+                )])
+                .into(),
+                min_time_ns: min_time,
+                max_time_ns: max_time,
+            })
+        };
+        let catalog_op = WalOp::Catalog(CatalogBatch {
+            database_id: DbId::from(2),
+            database_name: "db2".into(),
+            time_ns: 0,
+            ops: vec![],
+        });
+
+        for op in [
+            write(1, vec![row(1)]),
+            write(2, vec![row(2)]),
+            catalog_op.clone(),
+            write(1, vec![row(3)]),
+            write(1, vec![row(4)]),
+        ] {
+            wal.buffer_op_unconfirmed(op).await.unwrap();
+        }
+        assert!(wal.flush_buffer().await.is_none());
+
+        // the catalog op comes first, then a single write for each database, with the rows for
+        // the table in a single chunk, in the order they were buffered:
+        let expected = WalContents {
+            min_timestamp_ns: 0,
+            max_timestamp_ns: 4,
+            wal_file_number: WalFileSequenceNumber::new(1),
+            ops: vec![
+                catalog_op,
+                write(1, vec![row(1), row(3), row(4)]),
+                write(2, vec![row(2)]),
+            ],
+            snapshot: None,
+        };
+        let notifier = notifier.as_any().downcast_ref::<TestNotfiier>().unwrap();
+        assert_eq!(vec![expected.clone()], *notifier.notified_writes.lock());
+        assert_eq!(
+            Some(expected),
+            wal.load_wal_file(Path::from("my_host/wal/00000000001.wal"))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_applies_files_in_order_and_reports_progress() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let wal_file_count = REPLAY_CONCURRENCY as u64 * 3;
+        for wal_file_number in 1..=wal_file_count {
+            let contents = WalContents {
+                min_timestamp_ns: wal_file_number as i64,
+                max_timestamp_ns: wal_file_number as i64,
+                wal_file_number: WalFileSequenceNumber::new(wal_file_number),
+                ops: vec![],
+                snapshot: None,
+            };
+            object_store
+                .put(
+                    &wal_path("my_host", contents.wal_file_number),
+                    crate::serialize::serialize_to_file_bytes(&contents, WalCompression::None)
+                        .unwrap()
+                        .into(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let notifier: Arc<dyn WalFileNotifier> = Arc::new(TestNotfiier::default());
+        let wal = WalObjectStore::new_without_replay(
+            Arc::clone(&object_store),
+            "my_host",
+            Arc::clone(&notifier),
+            WalConfig::test_config(),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let (progress_tx, progress_rx) = watch::channel(ReplayProgress::default());
+        wal.replay_with_progress(Some(&progress_tx)).await.unwrap();
+
+        let notifier = notifier.as_any().downcast_ref::<TestNotfiier>().unwrap();
+        assert_eq!(
+            (1..=wal_file_count)
+                .map(WalFileSequenceNumber::new)
+                .collect::<Vec<_>>(),
+            notifier
+                .notified_writes
+                .lock()
+                .iter()
+                .map(|contents| contents.wal_file_number)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ReplayProgress {
+                replayed: wal_file_count as usize,
+                total: wal_file_count as usize,
+            },
+            *progress_rx.borrow()
+        );
+        assert_eq!(
+            WalFileSequenceNumber::new(wal_file_count),
+            wal.last_wal_sequence_number().await
+        );
+    }
+
+    #[tokio::test]
+    async fn snapshot_wal_files_are_retained_and_deleted_outside_the_window() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        for wal_file_number in 1..=4 {
+            let contents = WalContents {
+                min_timestamp_ns: 0,
+                max_timestamp_ns: 0,
+                wal_file_number: WalFileSequenceNumber::new(wal_file_number),
+                ops: vec![],
+                snapshot: None,
+            };
+            object_store
+                .put(
+                    &wal_path("my_host", contents.wal_file_number),
+                    crate::serialize::serialize_to_file_bytes(&contents, WalCompression::None)
+                        .unwrap()
+                        .into(),
+                )
+                .await
+                .unwrap();
+        }
+        let wal_with_retention = |retention: WalRetention| {
+            WalObjectStore::new_without_replay(
+                Arc::clone(&object_store),
+                "my_host",
+                Arc::new(TestNotfiier::default()),
+                WalConfig {
+                    retention,
+                    ..WalConfig::test_config()
+                },
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+        };
+
+        let wal = wal_with_retention(WalRetention {
+            keep_files: 2,
+            keep_duration: Duration::ZERO,
+        });
+        let snapshot_info = SnapshotInfo {
+            snapshot_details: SnapshotDetails {
+                snapshot_sequence_number: SnapshotSequenceNumber::new(1),
+                end_time_marker: 0,
+                last_wal_sequence_number: WalFileSequenceNumber::new(3),
+            },
+            wal_periods: (1..=3)
+                .map(|wal_file_number| {
+                    WalPeriod::new(
+                        WalFileSequenceNumber::new(wal_file_number),
+                        Timestamp::new(0),
+                        Timestamp::new(0),
+                    )
+                })
+                .collect(),
+        };
+        let snapshot_permit = Arc::new(Semaphore::new(1)).acquire_owned().await.unwrap();
+        wal.remove_snapshot_wal_files(snapshot_info, snapshot_permit)
+            .await;
+
+        // the snapshot files are no longer replayed, but are retained:
+        assert_eq!(
+            vec![Path::from("my_host/wal/00000000004.wal")],
+            wal.load_existing_wal_file_paths().await.unwrap()
+        );
+        assert_eq!(
+            Some(RetainedWalFiles {
+                first: WalFileSequenceNumber::new(1),
+                last: WalFileSequenceNumber::new(3),
+                count: 3,
+            }),
+            wal.retained_wal_files().await.unwrap()
+        );
+
+        // files within the retention period are kept, regardless of how many there are:
+        wal_with_retention(WalRetention {
+            keep_files: 0,
+            keep_duration: Duration::from_secs(3600),
+        })
+        .enforce_retention()
+        .await
+        .unwrap();
+        assert_eq!(3, wal.retained_wal_files().await.unwrap().unwrap().count);
+
+        // only the most recent files are kept once the retention period has passed:
+        wal.enforce_retention().await.unwrap();
+        assert_eq!(
+            Some(RetainedWalFiles {
+                first: WalFileSequenceNumber::new(2),
+                last: WalFileSequenceNumber::new(3),
+                count: 2,
+            }),
+            wal.retained_wal_files().await.unwrap()
+        );
+
+        // and all of them are deleted once retention is disabled:
+        wal_with_retention(WalRetention::default())
+            .enforce_retention()
+            .await
+            .unwrap();
+        assert_eq!(None, wal.retained_wal_files().await.unwrap());
+    }
+
     #[derive(Debug, Default)]
     struct TestNotfiier {
         notified_writes: parking_lot::Mutex<Vec<WalContents>>,
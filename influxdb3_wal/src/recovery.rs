@@ -0,0 +1,263 @@
+//! Point-in-time recovery of the WAL, which rewinds the WAL files in object storage to how they
+//! were when a given WAL file was written, so that the server replays the WAL up to that point,
+//! and the writes after it are discarded, e.g., to undo an accidental bad ingest.
+//!
+//! The snapshots taken after the recovery target must be discarded before the WAL is rewound,
+//! since the files that they were taken from are replayed, and snapshot again, instead. The WAL
+//! files written since the last snapshot that is kept must still be in the WAL, or be retained,
+//! so recovering to a point before the last snapshot needs [`crate::WalRetention`] to be set.
+//!
+//! Files are never deleted by a recovery: WAL files after the target are moved out of the WAL,
+//! so that they can be inspected, or moved back, if the recovery was a mistake.
+
+use std::{collections::BTreeMap, sync::Arc, time::SystemTime};
+
+use object_store::{path::Path, ObjectMeta, ObjectStore};
+use observability_deps::tracing::info;
+
+use crate::{
+    encryption::KeyProvider,
+    object_store::{decode_wal_file, list_objects, wal_path},
+    WalFileSequenceNumber,
+};
+
+/// The point to recover the WAL to
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RecoveryTarget {
+    /// Recover the writes up to and including those in this WAL file
+    WalFile(WalFileSequenceNumber),
+    /// Recover the writes in the WAL files that were written at or before this time, going by the
+    /// last modified time of the files in object storage. Retained files are timed from when they
+    /// were retained, so a time within the retained files may recover to an earlier point.
+    Time(SystemTime),
+}
+
+/// Where a WAL file is in object storage
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Location {
+    Wal,
+    Retained,
+}
+
+/// Rewinds the WAL files of a host in object storage to a [`RecoveryTarget`]
+#[derive(Debug)]
+pub struct WalRecovery {
+    object_store: Arc<dyn ObjectStore>,
+    host_identifier_prefix: String,
+    key_provider: Option<Arc<dyn KeyProvider>>,
+}
+
+impl WalRecovery {
+    pub fn new(
+        object_store: Arc<dyn ObjectStore>,
+        host_identifier_prefix: impl Into<String>,
+        key_provider: Option<Arc<dyn KeyProvider>>,
+    ) -> Self {
+        Self {
+            object_store,
+            host_identifier_prefix: host_identifier_prefix.into(),
+            key_provider,
+        }
+    }
+
+    /// The last WAL file to recover for the target. Fails if there are no WAL files at or before
+    /// the target.
+    pub async fn resolve(&self, target: RecoveryTarget) -> crate::Result<WalFileSequenceNumber> {
+        let wal_files = self.list().await?;
+        let resolved = match target {
+            RecoveryTarget::WalFile(wal_file_number) => wal_files
+                .contains_key(&wal_file_number)
+                .then_some(wal_file_number),
+            // the writes in a file are only recovered if those in the files before it are too:
+            RecoveryTarget::Time(time) => wal_files
+                .iter()
+                .take_while(|(_, (_, meta))| SystemTime::from(meta.last_modified) <= time)
+                .map(|(wal_file_number, _)| *wal_file_number)
+                .last(),
+        };
+        resolved.ok_or(crate::Error::RecoveryTargetNotFound)
+    }
+
+    /// Rewind the WAL to `target`, after the snapshots taken after it have been discarded.
+    /// `snapshot_wal_file` is the WAL file that triggered the last snapshot that is kept, if any.
+    ///
+    /// The files that have not been snapshot, up to and including the target, are moved back into
+    /// the WAL if they were retained, and those after the target are moved out of the WAL.
+    pub async fn rewind(
+        &self,
+        snapshot_wal_file: Option<WalFileSequenceNumber>,
+        target: WalFileSequenceNumber,
+    ) -> crate::Result<()> {
+        let wal_files = self.list().await?;
+        let last_snapshot_wal_file = match snapshot_wal_file {
+            Some(wal_file_number) => {
+                self.last_snapshot_wal_file(&wal_files, wal_file_number)
+                    .await?
+            }
+            None => WalFileSequenceNumber::default(),
+        };
+
+        let mut wal_file_number = last_snapshot_wal_file.next();
+        while wal_file_number <= target {
+            if !wal_files.contains_key(&wal_file_number) {
+                return Err(crate::Error::RecoveryWalFileMissing(wal_file_number));
+            }
+            wal_file_number = wal_file_number.next();
+        }
+
+        for (wal_file_number, (location, meta)) in &wal_files {
+            let to = match location {
+                Location::Retained if *wal_file_number <= last_snapshot_wal_file => continue,
+                Location::Retained if *wal_file_number <= target => {
+                    wal_path(&self.host_identifier_prefix, *wal_file_number)
+                }
+                Location::Wal if *wal_file_number <= target => continue,
+                Location::Retained | Location::Wal => {
+                    discarded_wal_path(&self.host_identifier_prefix, *wal_file_number)
+                }
+            };
+            info!(from = %meta.location, %to, "moving wal file for recovery");
+            self.object_store.copy(&meta.location, &to).await?;
+            self.object_store.delete(&meta.location).await?;
+        }
+
+        Ok(())
+    }
+
+    /// The last WAL file that was snapshot by the snapshot that `wal_file_number` triggered
+    async fn last_snapshot_wal_file(
+        &self,
+        wal_files: &BTreeMap<WalFileSequenceNumber, (Location, ObjectMeta)>,
+        wal_file_number: WalFileSequenceNumber,
+    ) -> crate::Result<WalFileSequenceNumber> {
+        let (_, meta) = wal_files
+            .get(&wal_file_number)
+            .ok_or(crate::Error::RecoveryWalFileMissing(wal_file_number))?;
+        let file_bytes = self.object_store.get(&meta.location).await?.bytes().await?;
+        decode_wal_file(&meta.location, self.key_provider.as_deref(), file_bytes)?
+            .and_then(|contents| contents.snapshot)
+            .map(|snapshot| snapshot.last_wal_sequence_number)
+            .ok_or(crate::Error::RecoverySnapshotUnreadable(wal_file_number))
+    }
+
+    /// The WAL files that are in the WAL, or retained, by number
+    async fn list(&self) -> crate::Result<BTreeMap<WalFileSequenceNumber, (Location, ObjectMeta)>> {
+        let mut wal_files = BTreeMap::new();
+        for (location, dir) in [(Location::Retained, "wal_retained"), (Location::Wal, "wal")] {
+            let dir = Path::from(format!("{}/{dir}", self.host_identifier_prefix));
+            for meta in list_objects(self.object_store.as_ref(), &dir).await? {
+                let wal_file_number = WalFileSequenceNumber::try_from(&meta.location)?;
+                wal_files.insert(wal_file_number, (location, meta));
+            }
+        }
+        Ok(wal_files)
+    }
+}
+
+/// The path that a WAL file after a recovery target is moved to, outside of the WAL
+pub fn discarded_wal_path(
+    host_identifier_prefix: &str,
+    wal_file_number: WalFileSequenceNumber,
+) -> Path {
+    Path::from(format!(
+        "{host_identifier_prefix}/wal_discarded/{:011}.wal",
+        wal_file_number.as_u64()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        object_store::{list_wal_file_paths, retained_wal_path},
+        serialize::serialize_to_file_bytes,
+        SnapshotDetails, SnapshotSequenceNumber, WalCompression, WalContents,
+    };
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn rewind_to_before_the_last_snapshot() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let put = |path: Path, wal_file_number: u64, snapshot_through: Option<u64>| {
+            let object_store = Arc::clone(&object_store);
+            async move {
+                let contents = WalContents {
+                    min_timestamp_ns: 0,
+                    max_timestamp_ns: 0,
+                    wal_file_number: WalFileSequenceNumber::new(wal_file_number),
+                    ops: vec![],
+                    snapshot: snapshot_through.map(|last| SnapshotDetails {
+                        snapshot_sequence_number: SnapshotSequenceNumber::new(last),
+                        end_time_marker: 0,
+                        last_wal_sequence_number: WalFileSequenceNumber::new(last),
+                    }),
+                };
+                let file_bytes = serialize_to_file_bytes(&contents, WalCompression::None).unwrap();
+                object_store.put(&path, file_bytes.into()).await.unwrap();
+            }
+        };
+        let n = WalFileSequenceNumber::new;
+        // file 3 triggered a snapshot of files 1 and 2, and file 5 one of files 3 and 4, all of
+        // which were retained:
+        put(retained_wal_path("my_host", n(1)), 1, None).await;
+        put(retained_wal_path("my_host", n(2)), 2, None).await;
+        put(retained_wal_path("my_host", n(3)), 3, Some(2)).await;
+        put(retained_wal_path("my_host", n(4)), 4, None).await;
+        put(wal_path("my_host", n(5)), 5, Some(4)).await;
+        put(wal_path("my_host", n(6)), 6, None).await;
+
+        let recovery = WalRecovery::new(Arc::clone(&object_store), "my_host", None);
+        assert_eq!(
+            n(3),
+            recovery
+                .resolve(RecoveryTarget::WalFile(n(3)))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            n(6),
+            recovery
+                .resolve(RecoveryTarget::Time(SystemTime::now()))
+                .await
+                .unwrap()
+        );
+        assert!(matches!(
+            recovery.resolve(RecoveryTarget::WalFile(n(7))).await,
+            Err(crate::Error::RecoveryTargetNotFound)
+        ));
+        assert!(matches!(
+            recovery
+                .resolve(RecoveryTarget::Time(SystemTime::UNIX_EPOCH))
+                .await,
+            Err(crate::Error::RecoveryTargetNotFound)
+        ));
+
+        // the snapshot triggered by file 5 is discarded, so file 3 is replayed again:
+        recovery.rewind(Some(n(3)), n(3)).await.unwrap();
+        let list = |dir: &'static str| {
+            let object_store = Arc::clone(&object_store);
+            async move {
+                list_objects(object_store.as_ref(), &Path::from(format!("my_host/{dir}")))
+                    .await
+                    .unwrap()
+                    .iter()
+                    .map(|meta| WalFileSequenceNumber::try_from(&meta.location).unwrap())
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(
+            vec![Path::from("my_host/wal/00000000003.wal")],
+            list_wal_file_paths(object_store.as_ref(), "my_host")
+                .await
+                .unwrap()
+        );
+        assert_eq!(vec![n(1), n(2)], list("wal_retained").await);
+        assert_eq!(vec![n(4), n(5), n(6)], list("wal_discarded").await);
+
+        // the files since the last snapshot that is kept must all be available:
+        assert!(matches!(
+            recovery.rewind(None, n(5)).await,
+            Err(crate::Error::RecoveryWalFileMissing(missing)) if missing == n(4)
+        ));
+    }
+}
@@ -1,10 +1,27 @@
 //! Module for serializing and deserializing the contents of a single WAL file. Since the WAL is
 //! buffered in memory before writing it in a single PUT operation to object store, this works
 //! a little differently than a traditional WAL that appends.
+//!
+//! There are three versions of the file format, identified by the first bytes of the file:
+//! * `idb3.001`: the identifier is followed by the crc32 checksum of the data, and then the
+//!   data, which is the JSON serialized [`WalContents`]
+//! * `idb3.002`: the identifier is followed by a single byte identifying the [`WalCompression`]
+//!   used, and then the checksum and data as above, except that the data is compressed
+//! * `idb3.003`: the identifier is followed by the compression byte, and then a sequence of
+//!   entries, each of which is the length of its data as a `u32`, the crc32 checksum of its data,
+//!   and then the data. The first entry holds the header of the file, i.e., everything in the
+//!   [`WalContents`] except its ops, and each of the following entries holds a single op. The
+//!   data of each entry is compressed separately.
+//!
+//! Files are written in the latest version, and files in any version can be replayed. Since the
+//! entries of the latest version are checked separately, a corrupt entry, or a truncated file,
+//! only loses the ops that are affected, rather than the whole file.
 
-use crate::WalContents;
+use crate::{SnapshotDetails, WalCompression, WalContents, WalFileSequenceNumber, WalOp};
 use byteorder::{BigEndian, ReadBytesExt};
 use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::io::Cursor;
 use std::mem::size_of;
 use thiserror::Error;
@@ -17,6 +34,12 @@ pub enum Error {
     #[error("crc32 checksum mismatch")]
     Crc32Mismatch,
 
+    #[error("unknown wal file compression {0}")]
+    UnknownCompression(u8),
+
+    #[error("wal file is truncated")]
+    Truncated,
+
     #[error("Serde error: {0}")]
     Serde(#[from] serde_json::Error),
 
@@ -25,66 +48,342 @@ pub enum Error {
 
     #[error("try from slice error {0}")]
     TryFromSlice(#[from] std::array::TryFromSliceError),
+
+    #[error("snappy error: {0}")]
+    Snappy(#[from] snap::Error),
+
+    #[error("lz4 decompression error: {0}")]
+    Lz4Decompress(#[from] lz4_flex::block::DecompressError),
+
+    #[error("encryption error: {0}")]
+    Encryption(#[from] crate::encryption::Error),
 }
 
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// A part of a wal file that could not be read, starting at `offset` bytes into the file
+#[derive(Debug)]
+pub struct CorruptEntry {
+    pub offset: usize,
+    pub error: Error,
+}
+
 /// The first bytes written into a wal file to identify it and its version.
 const FILE_TYPE_IDENTIFIER: &[u8] = b"idb3.001";
 
+/// The first bytes written into a compressed wal file, which is followed by the compression used
+const COMPRESSED_FILE_TYPE_IDENTIFIER: &[u8] = b"idb3.002";
+
+/// The first bytes written into a wal file made up of separately checksummed entries
+const ENTRIES_FILE_TYPE_IDENTIFIER: &[u8] = b"idb3.003";
+
+const LEN_SIZE: usize = size_of::<u32>();
+const CHECKSUM_LEN: usize = size_of::<u32>();
+
+/// The contents of a wal file other than its ops
+#[derive(Debug, Serialize, Deserialize)]
+struct WalFileHeader {
+    min_timestamp_ns: i64,
+    max_timestamp_ns: i64,
+    wal_file_number: WalFileSequenceNumber,
+    snapshot: Option<SnapshotDetails>,
+}
+
+/// Deserialize a wal file, failing if any part of it is corrupt
 pub fn verify_file_type_and_deserialize(b: Bytes) -> Result<WalContents> {
-    let contents = b.to_vec();
+    let (contents, mut corrupt_entries) =
+        deserialize_skipping_corrupt_entries(b).map_err(|corrupt| corrupt.error)?;
+    if corrupt_entries.is_empty() {
+        Ok(contents)
+    } else {
+        Err(corrupt_entries.swap_remove(0).error)
+    }
+}
+
+fn corrupt_at(offset: usize) -> impl Fn(Error) -> CorruptEntry {
+    move |error| CorruptEntry { offset, error }
+}
 
-    let pos = FILE_TYPE_IDENTIFIER.len();
+/// Deserialize a wal file, skipping any entries that are corrupt, which are returned along with
+/// the contents. If the file is truncated, the entries before the point that it was cut off are
+/// returned. Fails if the file cannot be read at all, e.g., because its header is corrupt.
+pub fn deserialize_skipping_corrupt_entries(
+    b: Bytes,
+) -> Result<(WalContents, Vec<CorruptEntry>), CorruptEntry> {
+    let contents = b.as_ref();
 
-    // Read and verify the file type identifier
+    let mut pos = FILE_TYPE_IDENTIFIER.len();
+    if contents.len() < pos {
+        return Err(corrupt_at(0)(Error::Truncated));
+    }
+
+    // Read and verify the file type identifier, and the compression used in the file
     let file_type = &contents[..pos];
+    let compression = match file_type {
+        FILE_TYPE_IDENTIFIER => WalCompression::None,
+        COMPRESSED_FILE_TYPE_IDENTIFIER | ENTRIES_FILE_TYPE_IDENTIFIER => {
+            let id = *contents.get(pos).ok_or(corrupt_at(pos)(Error::Truncated))?;
+            let compression = compression_from_id(id).map_err(corrupt_at(pos))?;
+            pos += 1;
+            compression
+        }
+        _ => return Err(corrupt_at(0)(Error::InvalidWalFile)),
+    };
 
-    if file_type != FILE_TYPE_IDENTIFIER {
-        return Err(Error::InvalidWalFile);
+    if file_type != ENTRIES_FILE_TYPE_IDENTIFIER {
+        let contents =
+            deserialize_whole_file(compression, &contents[pos..]).map_err(corrupt_at(pos))?;
+        return Ok((contents, vec![]));
     }
 
+    let mut entries = Entries {
+        contents,
+        pos,
+        compression,
+    };
+    let (offset, header) = entries.next().ok_or(corrupt_at(pos)(Error::Truncated))?;
+    let header: WalFileHeader = header
+        .and_then(|data| Ok(serde_json::from_slice(&data)?))
+        .map_err(corrupt_at(offset))?;
+
+    let mut ops = Vec::new();
+    let mut corrupt_entries = Vec::new();
+    for (offset, entry) in entries {
+        match entry.and_then(|data| Ok(serde_json::from_slice::<WalOp>(&data)?)) {
+            Ok(op) => ops.push(op),
+            Err(error) => corrupt_entries.push(CorruptEntry { offset, error }),
+        }
+    }
+
+    Ok((
+        WalContents {
+            min_timestamp_ns: header.min_timestamp_ns,
+            max_timestamp_ns: header.max_timestamp_ns,
+            wal_file_number: header.wal_file_number,
+            ops,
+            snapshot: header.snapshot,
+        },
+        corrupt_entries,
+    ))
+}
+
+/// Deserialize the data of a file in one of the versions with a single checksum for the file
+fn deserialize_whole_file(compression: WalCompression, contents: &[u8]) -> Result<WalContents> {
     // Read the crc32 checksum
-    const CHECKSUM_LEN: usize = size_of::<u32>();
-    let checksum_slice = &contents[pos..pos + CHECKSUM_LEN]; // Ensure this slice covers the 4 bytes for the checksum
-    let mut cursor = Cursor::new(checksum_slice);
-    let crc32_checksum = cursor.read_u32::<BigEndian>().unwrap();
+    if contents.len() < CHECKSUM_LEN {
+        return Err(Error::Truncated);
+    }
+    let mut cursor = Cursor::new(&contents[..CHECKSUM_LEN]);
+    let crc32_checksum = cursor.read_u32::<BigEndian>()?;
 
     // Validate the data against the checksum
-    let data = &contents[pos + CHECKSUM_LEN..];
-
-    let mut hasher = crc32fast::Hasher::new();
-    hasher.update(data);
-    let checksum = hasher.finalize();
-    if checksum != crc32_checksum {
+    let data = &contents[CHECKSUM_LEN..];
+    if crc32(data) != crc32_checksum {
         return Err(Error::Crc32Mismatch);
     }
 
-    // Deserialize the data into a WalContents
-    let contents: WalContents = serde_json::from_slice(data)?;
+    // Decompress and deserialize the data into a WalContents
+    let data = decompress(compression, data)?;
+    Ok(serde_json::from_slice(&data)?)
+}
 
-    Ok(contents)
+/// Iterates over the entries of a file, returning the offset of each along with its decompressed
+/// data. Stops once the end of the file, or an entry that extends past it, is reached.
+struct Entries<'a> {
+    contents: &'a [u8],
+    pos: usize,
+    compression: WalCompression,
 }
 
-pub(crate) fn serialize_to_file_bytes(contents: &WalContents) -> Result<Vec<u8>> {
-    let mut buf = Vec::new();
-    buf.extend_from_slice(FILE_TYPE_IDENTIFIER);
+impl Iterator for Entries<'_> {
+    type Item = (usize, Result<Vec<u8>>);
 
-    // serialize the contents into json bytes
-    let data = serde_json::to_vec(contents)?;
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.pos;
+        let remaining = &self.contents[offset..];
+        if remaining.is_empty() {
+            return None;
+        }
 
-    // calculate the crc32 checksum
-    let mut hasher = crc32fast::Hasher::new();
-    hasher.update(&data);
-    let checksum = hasher.finalize();
+        let header_len = LEN_SIZE + CHECKSUM_LEN;
+        let data_len = remaining
+            .get(..LEN_SIZE)
+            .map(|mut len| len.read_u32::<BigEndian>().expect("four bytes") as usize);
+        let data = data_len.and_then(|len| remaining.get(header_len..header_len + len));
+        let Some(data) = data else {
+            // nothing after a truncated entry can be read:
+            self.pos = self.contents.len();
+            return Some((offset, Err(Error::Truncated)));
+        };
+        self.pos += header_len + data.len();
+
+        let checksum = (&remaining[LEN_SIZE..header_len])
+            .read_u32::<BigEndian>()
+            .expect("four bytes");
+        if crc32(data) != checksum {
+            return Some((offset, Err(Error::Crc32Mismatch)));
+        }
+        Some((offset, decompress(self.compression, data)))
+    }
+}
+
+/// A wal file entry, i.e., the length and checksum of its data followed by the data, that an op
+/// is encoded into when it is buffered, so that it does not have to be serialized again when the
+/// buffer is flushed into a file
+#[derive(Debug, Clone)]
+pub(crate) struct EncodedEntry(Vec<u8>);
+
+impl EncodedEntry {
+    fn new(data: &[u8]) -> Self {
+        let mut entry = Vec::with_capacity(LEN_SIZE + CHECKSUM_LEN + data.len());
+        write_entry(&mut entry, data);
+        Self(entry)
+    }
+
+    /// The size of the entry in a wal file, in bytes
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// The most buffers that are kept in a [`SerializationBuffers`] pool
+const MAX_POOLED_BUFFERS: usize = 16;
+
+/// Buffers that have grown larger than this are dropped rather than returned to the pool, so that
+/// an unusually large op does not hold on to its memory
+const MAX_POOLED_BUFFER_CAPACITY: usize = 1024 * 1024;
+
+/// A pool of the buffers that values are serialized into before they are compressed, which are
+/// reused, so that encoding an op does not allocate a new buffer to serialize it into
+#[derive(Debug, Default)]
+pub(crate) struct SerializationBuffers {
+    pool: parking_lot::Mutex<Vec<Vec<u8>>>,
+}
+
+impl SerializationBuffers {
+    /// Serialize and compress the value into a wal file entry
+    pub(crate) fn encode(
+        &self,
+        value: &impl Serialize,
+        compression: WalCompression,
+    ) -> Result<EncodedEntry> {
+        let mut buf = self.pool.lock().pop().unwrap_or_default();
+        buf.clear();
+        let entry = serde_json::to_writer(&mut buf, value)
+            .map_err(Error::from)
+            .and_then(|()| Ok(EncodedEntry::new(&compress(compression, &buf)?)));
+
+        if buf.capacity() <= MAX_POOLED_BUFFER_CAPACITY {
+            let mut pool = self.pool.lock();
+            if pool.len() < MAX_POOLED_BUFFERS {
+                pool.push(buf);
+            }
+        }
+
+        entry
+    }
+}
 
-    // write the checksum and data to the buffer
-    buf.extend_from_slice(&checksum.to_be_bytes());
-    buf.extend_from_slice(&data);
+/// Serialize a wal file from the entries that its ops were encoded into, in order, and the header
+/// in `contents`. The file is allocated at its full size up front, so each entry is only copied
+/// once.
+pub(crate) fn serialize_entries_to_file_bytes(
+    contents: &WalContents,
+    entries: &[EncodedEntry],
+    compression: WalCompression,
+    buffers: &SerializationBuffers,
+) -> Result<Vec<u8>> {
+    let header = WalFileHeader {
+        min_timestamp_ns: contents.min_timestamp_ns,
+        max_timestamp_ns: contents.max_timestamp_ns,
+        wal_file_number: contents.wal_file_number,
+        snapshot: contents.snapshot,
+    };
+    let header = buffers.encode(&header, compression)?;
+
+    let len = ENTRIES_FILE_TYPE_IDENTIFIER.len()
+        + 1
+        + header.len()
+        + entries.iter().map(EncodedEntry::len).sum::<usize>();
+    let mut buf = Vec::with_capacity(len);
+    buf.extend_from_slice(ENTRIES_FILE_TYPE_IDENTIFIER);
+    buf.push(compression_id(compression));
+    buf.extend_from_slice(&header.0);
+    for entry in entries {
+        buf.extend_from_slice(&entry.0);
+    }
 
     Ok(buf)
 }
 
+/// Serialize the contents into a wal file, encoding each of its ops
+#[cfg(test)]
+pub(crate) fn serialize_to_file_bytes(
+    contents: &WalContents,
+    compression: WalCompression,
+) -> Result<Vec<u8>> {
+    let buffers = SerializationBuffers::default();
+    let entries = contents
+        .ops
+        .iter()
+        .map(|op| buffers.encode(op, compression))
+        .collect::<Result<Vec<_>>>()?;
+    serialize_entries_to_file_bytes(contents, &entries, compression, &buffers)
+}
+
+/// Write the data to the buffer as an entry, along with its length and checksum
+fn write_entry(buf: &mut Vec<u8>, data: &[u8]) {
+    let len = u32::try_from(data.len()).expect("wal file entry should be under 4GiB");
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(&crc32(data).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// The byte that identifies the compression in a compressed wal file. These must not be changed,
+/// or existing files will not be readable.
+fn compression_id(compression: WalCompression) -> u8 {
+    match compression {
+        WalCompression::None => 0,
+        WalCompression::Zstd => 1,
+        WalCompression::Lz4 => 2,
+        WalCompression::Snappy => 3,
+    }
+}
+
+fn compression_from_id(id: u8) -> Result<WalCompression> {
+    match id {
+        0 => Ok(WalCompression::None),
+        1 => Ok(WalCompression::Zstd),
+        2 => Ok(WalCompression::Lz4),
+        3 => Ok(WalCompression::Snappy),
+        _ => Err(Error::UnknownCompression(id)),
+    }
+}
+
+fn compress(compression: WalCompression, data: &[u8]) -> Result<Cow<'_, [u8]>> {
+    Ok(match compression {
+        WalCompression::None => Cow::Borrowed(data),
+        WalCompression::Zstd => Cow::Owned(zstd::encode_all(data, 0)?),
+        WalCompression::Lz4 => Cow::Owned(lz4_flex::compress_prepend_size(data)),
+        WalCompression::Snappy => Cow::Owned(snap::raw::Encoder::new().compress_vec(data)?),
+    })
+}
+
+fn decompress(compression: WalCompression, data: &[u8]) -> Result<Vec<u8>> {
+    Ok(match compression {
+        WalCompression::None => data.to_vec(),
+        WalCompression::Zstd => zstd::decode_all(data)?,
+        WalCompression::Lz4 => lz4_flex::decompress_size_prepended(data)?,
+        WalCompression::Snappy => snap::raw::Decoder::new().decompress_vec(data)?,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -133,9 +432,140 @@ mod tests {
             snapshot: None,
         };
 
-        let bytes = serialize_to_file_bytes(&contents).unwrap();
-        let deserialized = verify_file_type_and_deserialize(Bytes::from(bytes)).unwrap();
+        for compression in [
+            WalCompression::None,
+            WalCompression::Zstd,
+            WalCompression::Lz4,
+            WalCompression::Snappy,
+        ] {
+            let bytes = serialize_to_file_bytes(&contents, compression).unwrap();
+            assert_eq!(
+                ENTRIES_FILE_TYPE_IDENTIFIER,
+                &bytes[..ENTRIES_FILE_TYPE_IDENTIFIER.len()]
+            );
+            let deserialized = verify_file_type_and_deserialize(Bytes::from(bytes)).unwrap();
+            assert_eq!(contents, deserialized, "compression: {compression}");
+
+            // files written in the earlier versions can still be read:
+            let bytes = serialize_whole_file(&contents, compression);
+            let deserialized = verify_file_type_and_deserialize(Bytes::from(bytes)).unwrap();
+            assert_eq!(contents, deserialized, "compression: {compression}");
+        }
+    }
+
+    #[test]
+    fn deserialize_skips_corrupt_entries() {
+        let op = |time: i64| {
+            WalOp::Write(WriteBatch {
+                database_id: DbId::from(0),
+                database_name: "foo".into(),
+                table_chunks: SerdeVecMap::new(),
+                min_time_ns: time,
+                max_time_ns: time,
+            })
+        };
+        let contents = WalContents {
+            min_timestamp_ns: 1,
+            max_timestamp_ns: 3,
+            wal_file_number: WalFileSequenceNumber::new(1),
+            ops: vec![op(1), op(2), op(3)],
+            snapshot: None,
+        };
+        let bytes = serialize_to_file_bytes(&contents, WalCompression::None).unwrap();
+
+        // find the offsets of the entries, so that the entry for the second op can be corrupted:
+        let mut offsets = vec![];
+        let mut pos = ENTRIES_FILE_TYPE_IDENTIFIER.len() + 1;
+        while pos < bytes.len() {
+            offsets.push(pos);
+            let len = u32::from_be_bytes(bytes[pos..pos + LEN_SIZE].try_into().unwrap());
+            pos += LEN_SIZE + CHECKSUM_LEN + len as usize;
+        }
+        assert_eq!(4, offsets.len());
+
+        let mut corrupted = bytes.clone();
+        corrupted[offsets[2] + LEN_SIZE + CHECKSUM_LEN] ^= 0xff;
+        let (deserialized, corrupt_entries) =
+            deserialize_skipping_corrupt_entries(Bytes::from(corrupted.clone())).unwrap();
+        assert_eq!(vec![op(1), op(3)], deserialized.ops);
+        assert_eq!(1, corrupt_entries.len());
+        assert_eq!(offsets[2], corrupt_entries[0].offset);
+        assert!(matches!(corrupt_entries[0].error, Error::Crc32Mismatch));
+        assert!(matches!(
+            verify_file_type_and_deserialize(Bytes::from(corrupted)),
+            Err(Error::Crc32Mismatch)
+        ));
+
+        // the entries before a truncated tail are kept:
+        let truncated = bytes[..offsets[3] + 5].to_vec();
+        let (deserialized, corrupt_entries) =
+            deserialize_skipping_corrupt_entries(Bytes::from(truncated)).unwrap();
+        assert_eq!(vec![op(1), op(2)], deserialized.ops);
+        assert_eq!(1, corrupt_entries.len());
+        assert_eq!(offsets[3], corrupt_entries[0].offset);
+        assert!(matches!(corrupt_entries[0].error, Error::Truncated));
+
+        // but nothing can be read if the header is corrupt:
+        let mut corrupted = bytes;
+        corrupted[offsets[0] + LEN_SIZE + CHECKSUM_LEN] ^= 0xff;
+        let corrupt = deserialize_skipping_corrupt_entries(Bytes::from(corrupted)).unwrap_err();
+        assert_eq!(offsets[0], corrupt.offset);
+    }
+
+    #[test]
+    fn encoded_entries_are_written_as_they_are() {
+        let op = |time: i64| {
+            WalOp::Write(WriteBatch {
+                database_id: DbId::from(0),
+                database_name: "foo".into(),
+                table_chunks: SerdeVecMap::new(),
+                min_time_ns: time,
+                max_time_ns: time,
+            })
+        };
+        let contents = WalContents {
+            min_timestamp_ns: 1,
+            max_timestamp_ns: 2,
+            wal_file_number: WalFileSequenceNumber::new(1),
+            ops: vec![op(1), op(2)],
+            snapshot: None,
+        };
+
+        let buffers = SerializationBuffers::default();
+        for compression in [WalCompression::None, WalCompression::Zstd] {
+            let entries = contents
+                .ops
+                .iter()
+                .map(|op| buffers.encode(op, compression).unwrap())
+                .collect::<Vec<_>>();
+            let bytes = serialize_entries_to_file_bytes(&contents, &entries, compression, &buffers)
+                .unwrap();
+            assert_eq!(bytes.len(), bytes.capacity());
+            assert_eq!(
+                contents,
+                verify_file_type_and_deserialize(Bytes::from(bytes)).unwrap()
+            );
+        }
+
+        // the buffer that each value was serialized into was reused for the next:
+        assert_eq!(1, buffers.pool.lock().len());
+    }
 
-        assert_eq!(contents, deserialized);
+    /// Serialize the contents in the earlier versions of the format, which have a single checksum
+    /// for the whole file
+    fn serialize_whole_file(contents: &WalContents, compression: WalCompression) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match compression {
+            WalCompression::None => buf.extend_from_slice(FILE_TYPE_IDENTIFIER),
+            _ => {
+                buf.extend_from_slice(COMPRESSED_FILE_TYPE_IDENTIFIER);
+                buf.push(compression_id(compression));
+            }
+        }
+        let json = serde_json::to_vec(contents).unwrap();
+        let data = compress(compression, &json).unwrap();
+        buf.extend_from_slice(&crc32(&data).to_be_bytes());
+        buf.extend_from_slice(&data);
+        buf
     }
 }
@@ -4,8 +4,11 @@
 //! configured as it can be used to ensure that data in the write buffer is persisted in blocks
 //! that are not too large and unlikely to overlap.
 
-use crate::{Gen1Duration, SnapshotDetails, SnapshotSequenceNumber, WalFileSequenceNumber};
+use crate::{
+    Gen1Duration, SnapshotDetails, SnapshotSequenceNumber, SnapshotTriggers, WalFileSequenceNumber,
+};
 use data_types::Timestamp;
+use std::time::Instant;
 
 /// A struct that tracks the WAL periods (files if using object store) and decides when to snapshot the WAL.
 #[derive(Debug)]
@@ -15,6 +18,13 @@ pub(crate) struct SnapshotTracker {
     wal_periods: Vec<WalPeriod>,
     snapshot_size: usize,
     gen1_duration: Gen1Duration,
+    /// If set, the next call to `snapshot` will snapshot everything it can, regardless of the
+    /// snapshot size
+    force_snapshot: bool,
+    /// If set, the next call to `snapshot` will snapshot every period, including the last
+    force_full_snapshot: bool,
+    /// The limits on the age and size of the wal periods that trigger a snapshot of them all
+    triggers: SnapshotTriggers,
 }
 
 impl SnapshotTracker {
@@ -32,9 +42,62 @@ impl SnapshotTracker {
             wal_periods: Vec::new(),
             snapshot_size,
             gen1_duration,
+            force_snapshot: false,
+            force_full_snapshot: false,
+            triggers: SnapshotTriggers::default(),
         }
     }
 
+    /// Snapshot everything up to the last period as soon as one of `triggers` is reached
+    pub(crate) fn with_triggers(self, triggers: SnapshotTriggers) -> Self {
+        Self { triggers, ..self }
+    }
+
+    /// Change the snapshot size and gen1 duration, which apply from the next snapshot
+    pub(crate) fn update_config(&mut self, snapshot_size: usize, gen1_duration: Gen1Duration) {
+        self.snapshot_size = snapshot_size;
+        self.gen1_duration = gen1_duration;
+    }
+
+    /// Request that the next snapshot includes everything up to, but not including, the last WAL
+    /// period, regardless of the snapshot size. This is used to drain the write buffer early.
+    pub(crate) fn request_forced_snapshot(&mut self) {
+        self.force_snapshot = true;
+    }
+
+    /// Request that the next snapshot includes every WAL period, including the last, as soon as
+    /// there is one, so that it does not wait for another WAL file to be written. This is used
+    /// when writes are held back until the write buffer is drained.
+    pub(crate) fn request_full_snapshot(&mut self) {
+        self.force_full_snapshot = true;
+    }
+
+    /// Returns true if a forced snapshot has been requested and there is a WAL period for it
+    /// to snapshot.
+    pub(crate) fn forced_snapshot_pending(&self) -> bool {
+        (self.force_snapshot || self.force_full_snapshot) && !self.wal_periods.is_empty()
+    }
+
+    /// Returns true if the wal periods that have not been snapshot are older, or larger, than the
+    /// limits of the snapshot triggers. Only periods with writes in them count towards the age,
+    /// so that the empty period that is left after a triggered snapshot does not trigger another.
+    pub(crate) fn triggered_snapshot_pending(&self) -> bool {
+        let too_old = self.triggers.max_age.is_some_and(|max_age| {
+            self.wal_periods
+                .iter()
+                .find(|period| period.size_bytes > 0)
+                .is_some_and(|period| period.added_at.elapsed() >= max_age)
+        });
+        let too_large = self.triggers.max_bytes.is_some_and(|max_bytes| {
+            self.wal_periods
+                .iter()
+                .map(|period| period.size_bytes)
+                .sum::<usize>()
+                >= max_bytes
+        });
+        too_old || too_large
+    }
+
     /// Add a wal period to the tracker. This should be called when a new wal file is created.
     ///
     /// # Panics
@@ -56,7 +119,23 @@ impl SnapshotTracker {
     /// In the case of data coming in for future times, we will be unable to snapshot older data.
     /// Over time this will back up the WAL. To guard against this, if the number of WAL periods
     /// is >= 3x the snapshot size, snapshot everything up to the last period.
+    ///
+    /// If a forced snapshot was requested, or one of the snapshot triggers has been reached,
+    /// everything up to the last period is snapshot as well. A full snapshot takes every period,
+    /// including the last.
     pub(crate) fn snapshot(&mut self) -> Option<SnapshotInfo> {
+        if self.force_full_snapshot && !self.wal_periods.is_empty() {
+            self.force_full_snapshot = false;
+            self.force_snapshot = false;
+            return Some(self.snapshot_first_periods(self.wal_periods.len()));
+        }
+
+        if (self.force_snapshot || self.triggered_snapshot_pending()) && self.wal_periods.len() > 1
+        {
+            self.force_snapshot = false;
+            return Some(self.snapshot_all_but_last_period());
+        }
+
         if self.wal_periods.is_empty()
             || self.wal_periods.len() < self.number_of_periods_to_snapshot_after()
         {
@@ -66,28 +145,7 @@ impl SnapshotTracker {
         // if the number of wal periods is >= 3x the snapshot size, snapshot everything up to, but
         // not including, the last period:
         if self.wal_periods.len() >= 3 * self.snapshot_size {
-            let n_periods_to_take = self.wal_periods.len() - 1;
-            let wal_periods: Vec<WalPeriod> =
-                self.wal_periods.drain(0..n_periods_to_take).collect();
-            let max_time = wal_periods
-                .iter()
-                .map(|period| period.max_time)
-                .max()
-                .unwrap();
-            let t = max_time - (max_time.get() % self.gen1_duration.as_nanos())
-                + self.gen1_duration.as_nanos();
-            let last_wal_sequence_number = wal_periods.last().unwrap().wal_file_number;
-
-            let snapshot_details = SnapshotDetails {
-                snapshot_sequence_number: self.increment_snapshot_sequence_number(),
-                end_time_marker: t.get(),
-                last_wal_sequence_number,
-            };
-
-            return Some(SnapshotInfo {
-                snapshot_details,
-                wal_periods,
-            });
+            return Some(self.snapshot_all_but_last_period());
         }
 
         let t = self.wal_periods.last().unwrap().max_time;
@@ -118,6 +176,35 @@ impl SnapshotTracker {
         })
     }
 
+    fn snapshot_all_but_last_period(&mut self) -> SnapshotInfo {
+        self.snapshot_first_periods(self.wal_periods.len() - 1)
+    }
+
+    /// Snapshot the first `n_periods_to_take` wal periods, up to the end of the gen1 chunk
+    /// that the latest of their data is in
+    fn snapshot_first_periods(&mut self, n_periods_to_take: usize) -> SnapshotInfo {
+        let wal_periods: Vec<WalPeriod> = self.wal_periods.drain(0..n_periods_to_take).collect();
+        let max_time = wal_periods
+            .iter()
+            .map(|period| period.max_time)
+            .max()
+            .unwrap();
+        let t = max_time - (max_time.get() % self.gen1_duration.as_nanos())
+            + self.gen1_duration.as_nanos();
+        let last_wal_sequence_number = wal_periods.last().unwrap().wal_file_number;
+
+        let snapshot_details = SnapshotDetails {
+            snapshot_sequence_number: self.increment_snapshot_sequence_number(),
+            end_time_marker: t.get(),
+            last_wal_sequence_number,
+        };
+
+        SnapshotInfo {
+            snapshot_details,
+            wal_periods,
+        }
+    }
+
     /// The number of wal periods we need to see before we attempt a snapshot. This is to ensure that we
     /// don't snapshot before we've buffered up enough data to fill a gen1 chunk.
     fn number_of_periods_to_snapshot_after(&self) -> usize {
@@ -134,6 +221,12 @@ impl SnapshotTracker {
         self.last_snapshot_sequence_number
     }
 
+    /// Take the next [`SnapshotSequenceNumber`] for a snapshot that is not of the WAL, so that
+    /// the snapshots of the WAL are numbered after it
+    pub(crate) fn reserve_snapshot_sequence_number(&mut self) -> SnapshotSequenceNumber {
+        self.increment_snapshot_sequence_number()
+    }
+
     fn increment_snapshot_sequence_number(&mut self) -> SnapshotSequenceNumber {
         self.last_snapshot_sequence_number = self.last_snapshot_sequence_number.next();
         self.last_snapshot_sequence_number
@@ -148,11 +241,15 @@ pub struct SnapshotInfo {
 
 /// A struct that represents a period of time in the WAL. This is used to track the data timestamps
 /// and sequence numbers for each period of the WAL (which will be a file in object store, if enabled).
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone)]
 pub(crate) struct WalPeriod {
     pub(crate) wal_file_number: WalFileSequenceNumber,
     pub(crate) min_time: Timestamp,
     pub(crate) max_time: Timestamp,
+    /// The approximate size of the writes in the period, in bytes
+    pub(crate) size_bytes: usize,
+    /// When the period was added to the tracker
+    pub(crate) added_at: Instant,
 }
 
 impl WalPeriod {
@@ -167,13 +264,32 @@ impl WalPeriod {
             wal_file_number,
             min_time,
             max_time,
+            size_bytes: 0,
+            added_at: Instant::now(),
         }
     }
+
+    pub(crate) fn with_size_bytes(self, size_bytes: usize) -> Self {
+        Self { size_bytes, ..self }
+    }
+}
+
+/// Periods are the same if they are for the same file and times; their size and when they were
+/// added to the tracker are only used to decide when to snapshot.
+impl PartialEq for WalPeriod {
+    fn eq(&self, other: &Self) -> bool {
+        self.wal_file_number == other.wal_file_number
+            && self.min_time == other.min_time
+            && self.max_time == other.max_time
+    }
 }
 
+impl Eq for WalPeriod {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
 
     #[test]
     fn snapshot() {
@@ -315,4 +431,146 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn forced_snapshot_takes_all_but_last_period() {
+        let mut tracker = SnapshotTracker::new(10, Gen1Duration::new_1m(), None);
+        let p1 = WalPeriod::new(
+            WalFileSequenceNumber::new(1),
+            Timestamp::new(0),
+            Timestamp::new(30_000000000),
+        );
+        let p2 = WalPeriod::new(
+            WalFileSequenceNumber::new(2),
+            Timestamp::new(30_000000000),
+            Timestamp::new(90_000000000),
+        );
+
+        // a forced snapshot needs more than one period:
+        tracker.request_forced_snapshot();
+        assert!(!tracker.forced_snapshot_pending());
+        tracker.add_wal_period(p1.clone());
+        assert!(tracker.forced_snapshot_pending());
+        assert!(tracker.snapshot().is_none());
+
+        tracker.add_wal_period(p2.clone());
+        assert_eq!(
+            tracker.snapshot(),
+            Some(SnapshotInfo {
+                snapshot_details: SnapshotDetails {
+                    snapshot_sequence_number: SnapshotSequenceNumber::new(1),
+                    end_time_marker: 60_000000000,
+                    last_wal_sequence_number: WalFileSequenceNumber::new(1)
+                },
+                wal_periods: vec![p1]
+            })
+        );
+        assert_eq!(tracker.wal_periods, vec![p2]);
+        assert!(!tracker.forced_snapshot_pending());
+        assert!(tracker.snapshot().is_none());
+    }
+
+    #[test]
+    fn full_snapshot_takes_all_periods() {
+        let mut tracker = SnapshotTracker::new(10, Gen1Duration::new_1m(), None);
+        let p1 = WalPeriod::new(
+            WalFileSequenceNumber::new(1),
+            Timestamp::new(0),
+            Timestamp::new(30_000000000),
+        );
+        let p2 = WalPeriod::new(
+            WalFileSequenceNumber::new(2),
+            Timestamp::new(30_000000000),
+            Timestamp::new(90_000000000),
+        );
+
+        // a full snapshot needs a period to snapshot, but one is enough:
+        tracker.request_full_snapshot();
+        assert!(!tracker.forced_snapshot_pending());
+        assert!(tracker.snapshot().is_none());
+        tracker.add_wal_period(p1.clone());
+        assert!(tracker.forced_snapshot_pending());
+        assert_eq!(
+            tracker.snapshot(),
+            Some(SnapshotInfo {
+                snapshot_details: SnapshotDetails {
+                    snapshot_sequence_number: SnapshotSequenceNumber::new(1),
+                    end_time_marker: 60_000000000,
+                    last_wal_sequence_number: WalFileSequenceNumber::new(1)
+                },
+                wal_periods: vec![p1]
+            })
+        );
+        assert!(tracker.wal_periods.is_empty());
+        assert!(!tracker.forced_snapshot_pending());
+
+        tracker.add_wal_period(p2.clone());
+        assert!(tracker.snapshot().is_none());
+        tracker.request_full_snapshot();
+        assert_eq!(
+            tracker.snapshot(),
+            Some(SnapshotInfo {
+                snapshot_details: SnapshotDetails {
+                    snapshot_sequence_number: SnapshotSequenceNumber::new(2),
+                    end_time_marker: 120_000000000,
+                    last_wal_sequence_number: WalFileSequenceNumber::new(2)
+                },
+                wal_periods: vec![p2]
+            })
+        );
+    }
+
+    #[test]
+    fn triggers_snapshot_all_but_last_period() {
+        let triggers = SnapshotTriggers {
+            max_age: Some(Duration::from_secs(60)),
+            max_bytes: Some(1_000),
+        };
+        let mut tracker =
+            SnapshotTracker::new(10, Gen1Duration::new_1m(), None).with_triggers(triggers);
+        let period = |n: u64, size_bytes: usize| {
+            WalPeriod::new(
+                WalFileSequenceNumber::new(n),
+                Timestamp::new(0),
+                Timestamp::new(30_000000000),
+            )
+            .with_size_bytes(size_bytes)
+        };
+
+        // too many bytes:
+        let p1 = period(1, 600);
+        let p2 = period(2, 600);
+        tracker.add_wal_period(p1.clone());
+        assert!(!tracker.triggered_snapshot_pending());
+        tracker.add_wal_period(p2.clone());
+        assert!(tracker.triggered_snapshot_pending());
+        assert_eq!(
+            tracker.snapshot(),
+            Some(SnapshotInfo {
+                snapshot_details: SnapshotDetails {
+                    snapshot_sequence_number: SnapshotSequenceNumber::new(1),
+                    end_time_marker: 60_000000000,
+                    last_wal_sequence_number: WalFileSequenceNumber::new(1)
+                },
+                wal_periods: vec![p1]
+            })
+        );
+        assert_eq!(tracker.wal_periods, vec![p2]);
+        assert!(!tracker.triggered_snapshot_pending());
+
+        // too old:
+        let mut tracker =
+            SnapshotTracker::new(10, Gen1Duration::new_1m(), None).with_triggers(triggers);
+        let mut p1 = period(1, 10);
+        p1.added_at -= Duration::from_secs(120);
+        tracker.add_wal_period(p1.clone());
+        assert!(tracker.triggered_snapshot_pending());
+        // the last period is never snapshot, so the wal adds an empty one to snapshot it:
+        assert!(tracker.snapshot().is_none());
+        tracker.add_wal_period(period(2, 0));
+        assert_eq!(vec![p1], tracker.snapshot().unwrap().wal_periods);
+        // the empty period that is left does not trigger another snapshot, however old it gets:
+        tracker.wal_periods[0].added_at -= Duration::from_secs(120);
+        assert!(!tracker.triggered_snapshot_pending());
+    }
 }
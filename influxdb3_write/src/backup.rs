@@ -0,0 +1,486 @@
+//! Backups of a host, as of one of its snapshots, to another object store location, and restores
+//! of them under the prefix of any host.
+//!
+//! A backup is laid out as the files of a host are, with the catalog as it was at the time of the
+//! snapshot, and a single snapshot that has all of the parquet files that the host had then,
+//! which are copied along with it. Its manifest is written last, so that a backup without one is
+//! known to be incomplete. The WAL is not backed up, so writes that had not been snapshot yet are
+//! not in the backup.
+
+use crate::paths::ParquetFilePath;
+use crate::persister::{self, Persister};
+use crate::write_buffer::persisted_files::PersistedFiles;
+use crate::{DatabaseTables, ParquetFile, PersistedFilesIndex, PersistedSnapshot};
+use futures_util::stream::{self, StreamExt};
+use influxdb3_catalog::catalog::{Catalog, CatalogSequenceNumber};
+use influxdb3_id::{DbId, TableId};
+use influxdb3_wal::{SnapshotSequenceNumber, WalFileSequenceNumber};
+use object_store::path::Path as ObjPath;
+use object_store::ObjectStore;
+use observability_deps::tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("error from persister: {0}")]
+    Persister(#[from] persister::Error),
+
+    #[error("object_store error: {0}")]
+    ObjectStore(#[from] object_store::Error),
+
+    #[error("there are no snapshots to back up")]
+    NoSnapshots,
+
+    #[error("snapshot {0} does not exist")]
+    SnapshotNotFound(SnapshotSequenceNumber),
+
+    #[error("there is no catalog to back up")]
+    NoCatalog,
+
+    #[error(
+        "parquet file {0} was compacted with data from after the snapshot, and no longer exists, \
+        so a more recent snapshot must be backed up"
+    )]
+    CompactedAfterSnapshot(String),
+
+    #[error("parquet file {0} is missing from the backup")]
+    MissingFile(String),
+
+    #[error("parquet file {path} is not under the prefix {prefix}")]
+    UnexpectedPath { path: String, prefix: String },
+
+    #[error("there is no complete backup under {0}")]
+    NoBackup(String),
+
+    #[error("backup has version {0}, but only version {BACKUP_VERSION} can be restored")]
+    UnsupportedBackupVersion(u32),
+
+    #[error("there are already files under {0}")]
+    TargetNotEmpty(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The version of the layout of backups, which is in their manifest
+pub const BACKUP_VERSION: u32 = 1;
+
+/// What a backup has, which is written once all of it has been
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub version: u32,
+    /// The prefix of the host that was backed up
+    pub host_id: String,
+    /// The snapshot that the host was backed up as of
+    pub snapshot_sequence_number: SnapshotSequenceNumber,
+    /// The WAL file that triggered the snapshot, which is the last WAL file with data in the
+    /// backup
+    pub wal_file_sequence_number: WalFileSequenceNumber,
+    /// The sequence number of the catalog at the time of the snapshot
+    pub catalog_sequence_number: CatalogSequenceNumber,
+    pub parquet_file_count: usize,
+    pub parquet_size_bytes: u64,
+}
+
+/// Back up the host of `persister`, as of the snapshot `up_to_snapshot`, or its most recent
+/// snapshot, under `target_prefix` in `target`, which must not have any files yet. The backup is
+/// encrypted with the keys of `persister`, if it has any.
+///
+/// The parquet files in the backup are those of the snapshot and the snapshots before it, with
+/// the compactions of only those files applied. Files that no longer exist, since retention, or a
+/// hard delete, removed them, are left out. A snapshot whose files were compacted along with
+/// those of later snapshots cannot be backed up, once the files have been deleted.
+pub async fn create_backup(
+    persister: &Persister,
+    target: Arc<dyn ObjectStore>,
+    target_prefix: &str,
+    up_to_snapshot: Option<SnapshotSequenceNumber>,
+) -> Result<BackupManifest> {
+    ensure_empty(target.as_ref(), target_prefix).await?;
+
+    let mut snapshots = persister.load_snapshots(usize::MAX).await?;
+    if let Some(up_to_snapshot) = up_to_snapshot {
+        snapshots.retain(|snapshot| snapshot.snapshot_sequence_number <= up_to_snapshot);
+        if snapshots
+            .first()
+            .map(|snapshot| snapshot.snapshot_sequence_number)
+            != Some(up_to_snapshot)
+        {
+            return Err(Error::SnapshotNotFound(up_to_snapshot));
+        }
+    }
+    let persisted_files = PersistedFiles::new_from_persisted_snapshots(snapshots);
+    let mut paths = persisted_files.paths();
+    let mut compacted_later = HashSet::new();
+    let mut not_applied = HashSet::new();
+    for generation in persister.load_compaction_generations().await? {
+        for compaction in &generation.compactions {
+            // the compactions in a checkpoint also replace the gen2 files of the compactions that
+            // were folded into them, which are not persisted by any snapshot, and whose replaced
+            // files are among theirs:
+            if compaction.replaced.iter().all(|file| {
+                paths.contains(&file.path)
+                    || (ParquetFilePath::is_gen2(&file.path) && !not_applied.contains(&file.path))
+            }) {
+                persisted_files.apply_compaction(compaction);
+                for file in &compaction.replaced {
+                    paths.remove(&file.path);
+                }
+                paths.insert(compaction.file.path.clone());
+            } else {
+                compacted_later.extend(compaction.replaced.iter().map(|file| file.path.clone()));
+                not_applied.insert(compaction.file.path.clone());
+            }
+        }
+    }
+    let snapshot = persisted_files
+        .index()
+        .ok_or(Error::NoSnapshots)?
+        .into_snapshot();
+
+    // the catalog may have changed since the snapshot, so it is backed up as it was then, or as
+    // it is now if no version of it that old has been retained:
+    let mut catalog = match persister
+        .load_catalog_at(snapshot.catalog_sequence_number)
+        .await?
+    {
+        Some(catalog) => catalog,
+        None => persister.load_catalog().await?.ok_or(Error::NoCatalog)?,
+    };
+
+    let backup = persister_under(target, target_prefix, persister);
+    let (snapshot, missing) = copy_snapshot_files(persister, &backup, snapshot).await?;
+    for file in missing {
+        if compacted_later.contains(&file.path) {
+            return Err(Error::CompactedAfterSnapshot(file.path));
+        }
+        warn!(path = %file.path, "parquet file no longer exists, leaving it out of the backup");
+    }
+    catalog.set_host_id(Arc::from(target_prefix));
+    backup
+        .persist_catalog(&Catalog::from_inner(catalog))
+        .await?;
+    backup.persist_snapshot(&snapshot).await?;
+
+    let manifest = BackupManifest {
+        version: BACKUP_VERSION,
+        host_id: persister.host_identifier_prefix().to_string(),
+        snapshot_sequence_number: snapshot.snapshot_sequence_number,
+        wal_file_sequence_number: snapshot.wal_file_sequence_number,
+        catalog_sequence_number: snapshot.catalog_sequence_number,
+        parquet_file_count: snapshot
+            .databases
+            .values()
+            .map(|db| db.tables.values().map(Vec::len).sum::<usize>())
+            .sum(),
+        parquet_size_bytes: snapshot.parquet_size_bytes,
+    };
+    backup.persist_backup_manifest(&manifest).await?;
+    info!(
+        target_prefix,
+        snapshot_sequence_number = %manifest.snapshot_sequence_number,
+        parquet_file_count = manifest.parquet_file_count,
+        "created backup"
+    );
+    Ok(manifest)
+}
+
+/// Restore the backup under `backup_prefix` in `source` to the host of `persister`, which must
+/// not have any files yet. The backup is read with the keys of `persister`, if it has any.
+///
+/// The files of the backup are copied under the prefix of the host, with the paths of the parquet
+/// files, and the host of the catalog and snapshot, rewritten to be its own. A server started on
+/// the host continues from the snapshot that was backed up, with the ids that were in use as of
+/// it, so that none of them are used again.
+pub async fn restore_from_backup(
+    source: Arc<dyn ObjectStore>,
+    backup_prefix: &str,
+    persister: &Persister,
+) -> Result<BackupManifest> {
+    ensure_empty(
+        persister.object_store().as_ref(),
+        persister.host_identifier_prefix(),
+    )
+    .await?;
+
+    let backup = persister_under(source, backup_prefix, persister);
+    let no_backup = || Error::NoBackup(backup_prefix.to_string());
+    let manifest = backup.load_backup_manifest().await?.ok_or_else(no_backup)?;
+    if manifest.version != BACKUP_VERSION {
+        return Err(Error::UnsupportedBackupVersion(manifest.version));
+    }
+    let snapshot = backup
+        .load_snapshots(1)
+        .await?
+        .pop()
+        .ok_or_else(no_backup)?;
+    let mut catalog = backup.load_catalog().await?.ok_or_else(no_backup)?;
+
+    let (snapshot, missing) = copy_snapshot_files(&backup, persister, snapshot).await?;
+    if let Some(file) = missing.into_iter().next() {
+        return Err(Error::MissingFile(file.path));
+    }
+    catalog.set_host_id(Arc::from(persister.host_identifier_prefix()));
+    persister
+        .persist_catalog(&Catalog::from_inner(catalog))
+        .await?;
+    // the files are only loaded by a server once the snapshot that has them is persisted:
+    persister.persist_snapshot(&snapshot).await?;
+    info!(
+        backup_prefix,
+        snapshot_sequence_number = %manifest.snapshot_sequence_number,
+        parquet_file_count = manifest.parquet_file_count,
+        "restored backup"
+    );
+    Ok(manifest)
+}
+
+/// A persister for the files under `prefix` in `object_store`, with the keys and concurrency of
+/// `persister`
+fn persister_under(
+    object_store: Arc<dyn ObjectStore>,
+    prefix: &str,
+    persister: &Persister,
+) -> Persister {
+    let under = Persister::new(object_store, prefix)
+        .with_persist_concurrency(persister.persist_concurrency());
+    match persister.key_provider() {
+        Some(key_provider) => under.with_key_provider(key_provider),
+        None => under,
+    }
+}
+
+async fn ensure_empty(object_store: &dyn ObjectStore, prefix: &str) -> Result<()> {
+    let prefix_path = ObjPath::from(prefix);
+    if object_store
+        .list(Some(&prefix_path))
+        .next()
+        .await
+        .transpose()?
+        .is_some()
+    {
+        return Err(Error::TargetNotEmpty(prefix.to_string()));
+    }
+    Ok(())
+}
+
+/// Copy the parquet files of `snapshot` from under the prefix of `from` to the same paths under
+/// the prefix of `to`. Returns the snapshot with the files that were copied, at their new paths,
+/// as a snapshot of the host of `to`, along with the files that did not exist.
+async fn copy_snapshot_files(
+    from: &Persister,
+    to: &Persister,
+    mut snapshot: PersistedSnapshot,
+) -> Result<(PersistedSnapshot, Vec<ParquetFile>)> {
+    let files = std::mem::take(&mut snapshot.databases)
+        .into_iter()
+        .flat_map(|(db_id, db)| {
+            db.tables.into_iter().flat_map(move |(table_id, files)| {
+                files.into_iter().map(move |file| (db_id, table_id, file))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let copied = stream::iter(files)
+        .map(|(db_id, table_id, mut file)| async move {
+            let path = rehost_path(
+                &file.path,
+                from.host_identifier_prefix(),
+                to.host_identifier_prefix(),
+            )?;
+            let bytes = match from
+                .object_store()
+                .get(&ObjPath::from(file.path.as_str()))
+                .await
+            {
+                Ok(result) => result.bytes().await?,
+                Err(object_store::Error::NotFound { .. }) => {
+                    return Ok((db_id, table_id, file, false))
+                }
+                Err(e) => return Err(e.into()),
+            };
+            to.object_store()
+                .put(&ObjPath::from(path.as_str()), bytes.into())
+                .await?;
+            file.path = path;
+            Ok((db_id, table_id, file, true))
+        })
+        .buffered(from.persist_concurrency())
+        .collect::<Vec<Result<(DbId, TableId, ParquetFile, bool)>>>()
+        .await;
+
+    let mut databases: HashMap<DbId, DatabaseTables> = HashMap::new();
+    let mut missing = vec![];
+    for copy in copied {
+        let (db_id, table_id, file, exists) = copy?;
+        if exists {
+            databases
+                .entry(db_id)
+                .or_default()
+                .tables
+                .entry(table_id)
+                .or_default()
+                .push(file);
+        } else {
+            missing.push(file);
+        }
+    }
+    let snapshot = PersistedFilesIndex {
+        snapshot: PersistedSnapshot {
+            host_id: to.host_identifier_prefix().to_string(),
+            ..snapshot
+        },
+        databases,
+    }
+    .into_snapshot();
+    Ok((snapshot, missing))
+}
+
+/// The path that the file at `path`, under `from_prefix`, has under `to_prefix`
+fn rehost_path(path: &str, from_prefix: &str, to_prefix: &str) -> Result<String> {
+    path.strip_prefix(from_prefix)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .map(|rest| format!("{to_prefix}/{rest}"))
+        .ok_or_else(|| Error::UnexpectedPath {
+            path: path.to_string(),
+            prefix: from_prefix.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParquetFileId;
+    use object_store::memory::InMemory;
+
+    fn parquet_file(prefix: &str, n: u64) -> ParquetFile {
+        ParquetFile {
+            id: ParquetFileId::new(),
+            path: format!("{prefix}/dbs/db-0/cpu-0/1970-01-01/00-00/{n:010}.parquet"),
+            size_bytes: 3,
+            row_count: 1,
+            chunk_time: 0,
+            min_time: n as i64,
+            max_time: n as i64,
+            indexes: Default::default(),
+            column_ranges: Default::default(),
+            sort_key: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn backup_and_restore_to_another_host() {
+        let source = Persister::new(Arc::new(InMemory::new()), "host_a");
+        source.load_or_create_catalog().await.unwrap();
+        let mut files = vec![];
+        for n in 0..2 {
+            let file = parquet_file("host_a", n);
+            source
+                .object_store()
+                .put(
+                    &ObjPath::from(file.path.as_str()),
+                    format!("{n:03}").into_bytes().into(),
+                )
+                .await
+                .unwrap();
+            let mut snapshot = PersistedSnapshot::new(
+                "host_a".to_string(),
+                SnapshotSequenceNumber::new(n),
+                WalFileSequenceNumber::new(n),
+                CatalogSequenceNumber::new(0),
+            );
+            snapshot.add_parquet_file(DbId::from(0), TableId::from(0), file.clone());
+            source.persist_snapshot(&snapshot).await.unwrap();
+            files.push(file);
+        }
+
+        // the backup of the first snapshot only has its file:
+        let backups: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let manifest = create_backup(
+            &source,
+            Arc::clone(&backups),
+            "backups/first",
+            Some(SnapshotSequenceNumber::new(0)),
+        )
+        .await
+        .unwrap();
+        assert_eq!("host_a", manifest.host_id);
+        assert_eq!(1, manifest.parquet_file_count);
+        assert!(matches!(
+            create_backup(&source, Arc::clone(&backups), "backups/first", None).await,
+            Err(Error::TargetNotEmpty(_))
+        ));
+        assert!(matches!(
+            create_backup(
+                &source,
+                Arc::clone(&backups),
+                "backups/missing",
+                Some(SnapshotSequenceNumber::new(5)),
+            )
+            .await,
+            Err(Error::SnapshotNotFound(_))
+        ));
+
+        let restored = Persister::new(Arc::new(InMemory::new()), "host_b");
+        assert_eq!(
+            manifest,
+            restore_from_backup(Arc::clone(&backups), "backups/first", &restored)
+                .await
+                .unwrap()
+        );
+        let snapshots = restored.load_snapshots(10).await.unwrap();
+        assert_eq!(1, snapshots.len());
+        assert_eq!("host_b", snapshots[0].host_id);
+        assert_eq!(
+            SnapshotSequenceNumber::new(0),
+            snapshots[0].snapshot_sequence_number
+        );
+        let restored_files = &snapshots[0].databases[&DbId::from(0)].tables[&TableId::from(0)];
+        assert_eq!(1, restored_files.len());
+        assert_eq!(
+            "host_b/dbs/db-0/cpu-0/1970-01-01/00-00/0000000000.parquet",
+            restored_files[0].path
+        );
+        let bytes = restored
+            .object_store()
+            .get(&ObjPath::from(restored_files[0].path.as_str()))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(&b"000"[..], &bytes[..]);
+        let catalog = restored.load_catalog().await.unwrap().unwrap();
+        assert_eq!("host_b", &*Catalog::from_inner(catalog).host_id());
+        assert!(matches!(
+            restore_from_backup(Arc::clone(&backups), "backups/first", &restored).await,
+            Err(Error::TargetNotEmpty(_))
+        ));
+        assert!(matches!(
+            restore_from_backup(
+                Arc::clone(&backups),
+                "backups/missing",
+                &Persister::new(Arc::new(InMemory::new()), "host_c"),
+            )
+            .await,
+            Err(Error::NoBackup(_))
+        ));
+
+        // files that no longer exist, e.g., as retention removed them, are left out:
+        source
+            .object_store()
+            .delete(&ObjPath::from(files[0].path.as_str()))
+            .await
+            .unwrap();
+        let manifest = create_backup(&source, Arc::clone(&backups), "backups/latest", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            SnapshotSequenceNumber::new(1),
+            manifest.snapshot_sequence_number
+        );
+        assert_eq!(1, manifest.parquet_file_count);
+    }
+}
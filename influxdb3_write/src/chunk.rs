@@ -1,3 +1,4 @@
+use crate::parquet_cache::CachePin;
 use arrow::array::RecordBatch;
 use data_types::{ChunkId, ChunkOrder, TransitionPartitionId};
 use datafusion::common::Statistics;
@@ -71,6 +72,9 @@ pub struct ParquetChunk {
     pub(crate) id: ChunkId,
     pub(crate) chunk_order: ChunkOrder,
     pub(crate) parquet_exec: ParquetExecInput,
+    /// Keeps the file pinned in the parquet cache, if there is one, while the chunk is in use
+    #[allow(dead_code)]
+    pub(crate) cache_pin: Option<CachePin>,
 }
 
 impl QueryChunk for ParquetChunk {
@@ -0,0 +1,756 @@
+//! A cache of the distinct values written to a set of tag or string field columns of a table,
+//! e.g., for `SHOW TAG VALUES` style queries, which are then answered without scanning the data
+//! that has been persisted to parquet.
+//!
+//! The caches are fed from the WAL, in the same way as the last-N-value caches in
+//! [`crate::last_cache`], and are defined in the catalog, so they are recreated on restart.
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use arrow::{
+    array::{ArrayRef, RecordBatch, StringBuilder},
+    datatypes::{
+        DataType, Field as ArrowField, Schema as ArrowSchema, SchemaRef as ArrowSchemaRef,
+    },
+    error::ArrowError,
+};
+use hashbrown::HashMap;
+use influxdb3_catalog::catalog::{Catalog, TableDefinition};
+use influxdb3_id::{ColumnId, DbId, TableId};
+use influxdb3_wal::{DistinctCacheDefinition, FieldData, Row, WalContents, WalOp};
+use observability_deps::tracing::debug;
+use parking_lot::RwLock;
+use schema::{InfluxColumnType, InfluxFieldType};
+
+mod table_function;
+pub use table_function::DistinctCacheFunction;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("a distinct cache must have at least one column")]
+    NoColumns,
+    #[error("specified column (id: {column_id}) does not exist in the table definition")]
+    ColumnDoesNotExist { column_id: ColumnId },
+    #[error("distinct cache columns must be tag or string field columns")]
+    InvalidColumnType,
+    #[error("the max cardinality of a distinct cache must be greater than zero")]
+    InvalidMaxCardinality,
+    #[error("distinct cache already exists for database and table, but it was configured differently: {reason}")]
+    CacheAlreadyExists { reason: String },
+    #[error("requested distinct cache does not exist")]
+    CacheDoesNotExist,
+}
+
+impl Error {
+    fn cache_already_exists(reason: impl Into<String>) -> Self {
+        Self::CacheAlreadyExists {
+            reason: reason.into(),
+        }
+    }
+}
+
+/// A three level hashmap storing DbId -> TableId -> Cache Name -> DistinctCache
+///
+/// The caches of each table have their own lock, so that writing to the caches of one table does
+/// not block queries of the caches of other tables.
+type CacheMap = RwLock<HashMap<DbId, HashMap<TableId, TableCaches>>>;
+
+/// The caches of a table, by name
+type TableCaches = RwLock<HashMap<Arc<str>, DistinctCache>>;
+
+/// Provides all distinct value caches for the entire database
+pub struct DistinctCacheProvider {
+    catalog: Arc<Catalog>,
+    cache_map: CacheMap,
+}
+
+impl std::fmt::Debug for DistinctCacheProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DistinctCacheProvider")
+    }
+}
+
+/// The default maximum number of distinct combinations of values held in a cache
+pub const DEFAULT_MAX_CARDINALITY: usize = 100_000;
+
+/// The default cache time-to-live (TTL) is 24 hours
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// Arguments to the [`DistinctCacheProvider::create_cache`] method
+pub struct CreateDistinctCacheArgs {
+    /// The id of the database to create the cache for
+    pub db_id: DbId,
+    /// The definition of the table for which the cache is being created
+    pub table_def: Arc<TableDefinition>,
+    /// An optional name for the cache
+    ///
+    /// The cache name will default to `<table_name>_<columns>_distinct_cache`
+    pub cache_name: Option<Arc<str>>,
+    /// The columns whose distinct values are cached, which must be tag or string field columns
+    pub columns: Vec<ColumnId>,
+    /// The maximum number of distinct combinations of values to hold in the cache
+    ///
+    /// This will default to [`DEFAULT_MAX_CARDINALITY`]. Once it is reached, the combinations
+    /// that were written least recently are evicted.
+    pub max_cardinality: Option<usize>,
+    /// The time-to-live (TTL) for values in the cache that are not written again
+    ///
+    /// This will default to [`DEFAULT_CACHE_TTL`]
+    pub ttl: Option<Duration>,
+}
+
+impl DistinctCacheProvider {
+    /// Initialize a [`DistinctCacheProvider`] from a [`Catalog`]
+    pub fn new_from_catalog(catalog: Arc<Catalog>) -> Arc<Self> {
+        let provider = Arc::new(Self {
+            catalog: Arc::clone(&catalog),
+            cache_map: Default::default(),
+        });
+        for db_schema in catalog.list_db_schema() {
+            for table_def in db_schema.tables() {
+                for (cache_name, cache_def) in table_def.distinct_caches() {
+                    debug!(%cache_name, ?cache_def, "adding distinct cache from catalog");
+                    provider.create_cache_from_definition(
+                        db_schema.id,
+                        Arc::clone(&table_def),
+                        cache_def,
+                    );
+                }
+            }
+        }
+        provider
+    }
+
+    /// Get a particular cache's name and arrow schema
+    ///
+    /// This is used for the implementation of DataFusion's `TableFunctionImpl` and `TableProvider`
+    /// traits.
+    fn get_cache_name_and_schema(
+        &self,
+        db_id: DbId,
+        table_id: TableId,
+        cache_name: Option<&str>,
+    ) -> Option<(Arc<str>, ArrowSchemaRef)> {
+        self.cache_map
+            .read()
+            .get(&db_id)
+            .and_then(|db| db.get(&table_id))
+            .and_then(|table| {
+                let table = table.read();
+                if let Some(cache_name) = cache_name {
+                    table
+                        .get_key_value(cache_name)
+                        .map(|(name, dc)| (Arc::clone(name), Arc::clone(&dc.schema)))
+                } else if table.len() == 1 {
+                    table
+                        .iter()
+                        .map(|(name, dc)| (Arc::clone(name), Arc::clone(&dc.schema)))
+                        .next()
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Get the [`DistinctCacheDefinition`] for all caches contained in a database
+    pub fn get_distinct_caches_for_db(&self, db_id: DbId) -> Vec<DistinctCacheDefinition> {
+        let Some(db_schema) = self.catalog.db_schema_by_id(&db_id) else {
+            return vec![];
+        };
+        self.cache_map
+            .read()
+            .get(&db_id)
+            .map(|db| {
+                db.iter()
+                    .flat_map(|(table_id, table)| {
+                        let table_name =
+                            db_schema.table_id_to_name(table_id).expect("table exists");
+                        table
+                            .read()
+                            .iter()
+                            .map(|(name, dc)| {
+                                dc.to_definition(
+                                    *table_id,
+                                    Arc::clone(&table_name),
+                                    Arc::clone(name),
+                                )
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Get the distinct values held in a cache, with one column for each of the cache's columns,
+    /// sorted by the values of the columns in order, or `None` if the cache does not exist
+    pub fn get_distinct_values(
+        &self,
+        db_id: DbId,
+        table_id: TableId,
+        cache_name: &str,
+    ) -> Option<Result<RecordBatch, ArrowError>> {
+        self.cache_map
+            .read()
+            .get(&db_id)
+            .and_then(|db| db.get(&table_id))
+            .and_then(|table| {
+                table
+                    .read()
+                    .get(cache_name)
+                    .map(DistinctCache::to_record_batch)
+            })
+    }
+
+    /// Create a new distinct value cache for a given database and table, with the given
+    /// parameters.
+    ///
+    /// If a new cache is created, its definition is returned. If the provided arguments are
+    /// identical to those of an existing cache (along with any defaults), then `None` is returned.
+    pub fn create_cache(
+        &self,
+        CreateDistinctCacheArgs {
+            db_id,
+            table_def,
+            cache_name,
+            columns,
+            max_cardinality,
+            ttl,
+        }: CreateDistinctCacheArgs,
+    ) -> Result<Option<DistinctCacheDefinition>, Error> {
+        if columns.is_empty() {
+            return Err(Error::NoColumns);
+        }
+        let mut column_names = Vec::with_capacity(columns.len());
+        for column_id in &columns {
+            let column = table_def
+                .columns
+                .get(column_id)
+                .ok_or(Error::ColumnDoesNotExist {
+                    column_id: *column_id,
+                })?;
+            match column.data_type {
+                InfluxColumnType::Tag | InfluxColumnType::Field(InfluxFieldType::String) => (),
+                _ => return Err(Error::InvalidColumnType),
+            }
+            column_names.push(Arc::clone(&column.name));
+        }
+        let max_cardinality = max_cardinality.unwrap_or(DEFAULT_MAX_CARDINALITY);
+        if max_cardinality == 0 {
+            return Err(Error::InvalidMaxCardinality);
+        }
+
+        // Generate the cache name if it was not provided
+        let cache_name = cache_name.unwrap_or_else(|| {
+            format!(
+                "{table_name}_{columns}_distinct_cache",
+                table_name = table_def.table_name,
+                columns = column_names.join("_")
+            )
+            .into()
+        });
+        let definition = DistinctCacheDefinition {
+            table_id: table_def.table_id,
+            table: Arc::clone(&table_def.table_name),
+            name: Arc::clone(&cache_name),
+            column_ids: columns,
+            max_cardinality,
+            ttl: ttl.unwrap_or(DEFAULT_CACHE_TTL).as_secs(),
+        };
+
+        // Check to see if there is already a cache with the same name. If its configuration is
+        // identical, then `None` is returned, otherwise, this is an error:
+        let mut lock = self.cache_map.write();
+        if let Some(table) = lock.get(&db_id).and_then(|db| db.get(&table_def.table_id)) {
+            if let Some(dc) = table.read().get(&cache_name) {
+                return dc.compare_config(&definition).map(|_| None);
+            }
+        }
+
+        lock.entry(db_id)
+            .or_default()
+            .entry(table_def.table_id)
+            .or_default()
+            .get_mut()
+            .insert(cache_name, DistinctCache::new(&table_def, &definition));
+
+        Ok(Some(definition))
+    }
+
+    /// Create a cache from its definition, e.g., when it is replayed from the WAL, replacing any
+    /// cache with the same name
+    pub fn create_cache_from_definition(
+        &self,
+        db_id: DbId,
+        table_def: Arc<TableDefinition>,
+        definition: &DistinctCacheDefinition,
+    ) {
+        self.cache_map
+            .write()
+            .entry(db_id)
+            .or_default()
+            .entry(definition.table_id)
+            .or_default()
+            .get_mut()
+            .insert(
+                Arc::clone(&definition.name),
+                DistinctCache::new(&table_def, definition),
+            );
+    }
+
+    /// Delete a cache from the provider
+    ///
+    /// This will also clean up empty levels in the provider hierarchy, in the same way as
+    /// [`crate::last_cache::LastCacheProvider::delete_cache`].
+    pub fn delete_cache(
+        &self,
+        db_id: DbId,
+        table_id: TableId,
+        cache_name: &str,
+    ) -> Result<(), Error> {
+        let mut lock = self.cache_map.write();
+
+        let Some(db) = lock.get_mut(&db_id) else {
+            return Err(Error::CacheDoesNotExist);
+        };
+
+        let Some(table) = db.get_mut(&table_id).map(RwLock::get_mut) else {
+            return Err(Error::CacheDoesNotExist);
+        };
+
+        if table.remove(cache_name).is_none() {
+            return Err(Error::CacheDoesNotExist);
+        }
+
+        if table.is_empty() {
+            db.remove(&table_id);
+        }
+
+        if db.is_empty() {
+            lock.remove(&db_id);
+        }
+
+        Ok(())
+    }
+
+    /// Delete all of the caches of a table, e.g., once the table has been deleted
+    pub fn delete_caches_for_table(&self, db_id: DbId, table_id: TableId) {
+        let mut lock = self.cache_map.write();
+        let Some(db) = lock.get_mut(&db_id) else {
+            return;
+        };
+        db.remove(&table_id);
+        if db.is_empty() {
+            lock.remove(&db_id);
+        }
+    }
+
+    /// Recreate the caches of a table from its definition, which empties them, e.g., once one of
+    /// its columns has been renamed, since the names of the columns are part of a cache's schema
+    pub fn recreate_caches_for_table(&self, db_id: DbId, table_def: Arc<TableDefinition>) {
+        self.delete_caches_for_table(db_id, table_def.table_id);
+        for (_, definition) in table_def.distinct_caches() {
+            self.create_cache_from_definition(db_id, Arc::clone(&table_def), definition);
+        }
+    }
+
+    /// Write the contents of a WAL file into the caches of the tables that were written to
+    ///
+    /// The caches that are written to also have their expired values evicted, and are brought
+    /// back down to their max cardinality. Only the caches of the table being written to are
+    /// locked while this is done.
+    pub fn write_wal_contents_to_cache(&self, wal_contents: &WalContents) {
+        let cache_map = self.cache_map.read();
+        for op in &wal_contents.ops {
+            match op {
+                WalOp::Write(batch) => {
+                    let Some(db_cache) = cache_map.get(&batch.database_id) else {
+                        continue;
+                    };
+                    for (table_id, table_chunks) in &batch.table_chunks {
+                        let Some(table_cache) = db_cache.get(table_id) else {
+                            continue;
+                        };
+                        let mut table_cache = table_cache.write();
+                        let now = Instant::now();
+                        for distinct_cache in table_cache.values_mut() {
+                            for chunk in table_chunks.chunk_time_to_chunk.values() {
+                                for row in &chunk.rows {
+                                    distinct_cache.push(row, now);
+                                }
+                            }
+                            distinct_cache.prune(now);
+                        }
+                    }
+                }
+                WalOp::Catalog(_) => (),
+            }
+        }
+    }
+}
+
+/// The distinct values of a set of columns of a table
+struct DistinctCache {
+    column_ids: Vec<ColumnId>,
+    max_cardinality: usize,
+    ttl: Duration,
+    schema: ArrowSchemaRef,
+    /// The distinct combinations of values of the columns, in column order, mapped to the last
+    /// time that they were written. Rows that do not have a value for a column have a null in
+    /// its place.
+    values: BTreeMap<DistinctKey, LastWritten>,
+    /// The keys of `values`, ordered from the least to the most recently written, so that they
+    /// can be evicted without scanning the whole cache
+    recency: BTreeMap<LastWritten, DistinctKey>,
+    next_sequence: u64,
+}
+
+/// A distinct combination of the values of the columns of a cache
+type DistinctKey = Vec<Option<Arc<str>>>;
+
+/// When a key was last written to a cache, along with a sequence number that orders the keys
+/// written at the same time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct LastWritten {
+    time: Instant,
+    sequence: u64,
+}
+
+impl DistinctCache {
+    fn new(table_def: &TableDefinition, definition: &DistinctCacheDefinition) -> Self {
+        let fields = definition
+            .column_ids
+            .iter()
+            .map(|id| {
+                let name = table_def
+                    .column_id_to_name(id)
+                    .expect("a valid column id for distinct cache column");
+                ArrowField::new(name.as_ref(), DataType::Utf8, true)
+            })
+            .collect::<Vec<_>>();
+        Self {
+            column_ids: definition.column_ids.clone(),
+            max_cardinality: definition.max_cardinality,
+            ttl: Duration::from_secs(definition.ttl),
+            schema: Arc::new(ArrowSchema::new(fields)),
+            values: BTreeMap::new(),
+            recency: BTreeMap::new(),
+            next_sequence: 0,
+        }
+    }
+
+    fn to_definition(
+        &self,
+        table_id: TableId,
+        table: Arc<str>,
+        name: Arc<str>,
+    ) -> DistinctCacheDefinition {
+        DistinctCacheDefinition {
+            table_id,
+            table,
+            name,
+            column_ids: self.column_ids.clone(),
+            max_cardinality: self.max_cardinality,
+            ttl: self.ttl.as_secs(),
+        }
+    }
+
+    fn compare_config(&self, definition: &DistinctCacheDefinition) -> Result<(), Error> {
+        if self.column_ids != definition.column_ids {
+            return Err(Error::cache_already_exists(
+                "columns are not the same as in the existing cache",
+            ));
+        }
+        if self.max_cardinality != definition.max_cardinality {
+            return Err(Error::cache_already_exists(
+                "max cardinality is not the same as in the existing cache",
+            ));
+        }
+        if self.ttl.as_secs() != definition.ttl {
+            return Err(Error::cache_already_exists(
+                "ttl is not the same as in the existing cache",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Record the values of the cache's columns in a row, if it has any of them
+    fn push(&mut self, row: &Row, now: Instant) {
+        let key = self
+            .column_ids
+            .iter()
+            .map(|id| {
+                row.fields
+                    .iter()
+                    .find(|field| field.id == *id)
+                    .and_then(|field| match &field.value {
+                        FieldData::Tag(s) | FieldData::Key(s) => Some(Arc::clone(s)),
+                        FieldData::String(s) => Some(Arc::from(s.as_str())),
+                        _ => None,
+                    })
+            })
+            .collect::<Vec<_>>();
+        if key.iter().all(Option::is_none) {
+            return;
+        }
+        let last_written = LastWritten {
+            time: now,
+            sequence: self.next_sequence,
+        };
+        self.next_sequence += 1;
+        if let Some(previous) = self.values.insert(key.clone(), last_written) {
+            self.recency.remove(&previous);
+        }
+        self.recency.insert(last_written, key);
+    }
+
+    /// Evict the values that have expired, and then those that were written least recently, until
+    /// the cache is within its max cardinality
+    fn prune(&mut self, now: Instant) {
+        while let Some(oldest) = self.recency.first_entry() {
+            let expired = now.duration_since(oldest.key().time) >= self.ttl;
+            if !expired && self.values.len() <= self.max_cardinality {
+                break;
+            }
+            self.values.remove(&oldest.remove());
+        }
+    }
+
+    fn to_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let now = Instant::now();
+        let mut builders = self
+            .column_ids
+            .iter()
+            .map(|_| StringBuilder::new())
+            .collect::<Vec<_>>();
+        for (key, _) in self
+            .values
+            .iter()
+            .filter(|(_, last_written)| now.duration_since(last_written.time) < self.ttl)
+        {
+            for (builder, value) in builders.iter_mut().zip(key) {
+                builder.append_option(value.as_deref());
+            }
+        }
+        let columns = builders
+            .into_iter()
+            .map(|mut builder| Arc::new(builder.finish()) as ArrayRef)
+            .collect();
+        RecordBatch::try_new(Arc::clone(&self.schema), columns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        distinct_cache::{DistinctCache, Error},
+        last_cache::LastCacheProvider,
+        parquet_cache::test_cached_obj_store_and_oracle,
+        persister::Persister,
+        write_buffer::{WriteBufferImpl, WriteBufferImplArgs},
+        Bufferer, DistinctCacheManager, Precision,
+    };
+    use ::object_store::{memory::InMemory, ObjectStore};
+    use arrow::datatypes::{DataType, Field as ArrowField, Schema as ArrowSchema};
+    use arrow_util::assert_batches_eq;
+    use data_types::NamespaceName;
+    use influxdb3_catalog::catalog::Catalog;
+    use influxdb3_id::ColumnId;
+    use influxdb3_wal::{Field, FieldData, Row, WalConfig};
+    use iox_time::{MockProvider, Time, TimeProvider};
+
+    async fn setup_write_buffer() -> WriteBufferImpl {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let time_provider: Arc<dyn TimeProvider> =
+            Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let (obj_store, parquet_cache) =
+            test_cached_obj_store_and_oracle(obj_store, Arc::clone(&time_provider));
+        let persister = Arc::new(Persister::new(obj_store, "test_host"));
+        let host_id = Arc::from("sample-host-id");
+        let instance_id = Arc::from("sample-instance-id");
+        let catalog = Arc::new(Catalog::new(host_id, instance_id));
+        WriteBufferImpl::new(WriteBufferImplArgs::new(
+            persister,
+            Arc::clone(&catalog),
+            LastCacheProvider::new_from_catalog(catalog as _).unwrap(),
+            time_provider,
+            crate::test_help::make_exec(),
+            WalConfig::test_config(),
+            Some(parquet_cache),
+        ))
+        .await
+        .unwrap()
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn distinct_values_with_max_cardinality() {
+        let db_name = "foo";
+        let tbl_name = "cpu";
+        let wbuf = setup_write_buffer().await;
+
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},region=us,host=a usage=1").as_str(),
+            Time::from_timestamp_nanos(1_000),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        let (db_id, db_schema) = wbuf.catalog().db_schema_and_id(db_name).unwrap();
+        let (tbl_id, table_def) = db_schema.table_definition_and_id(tbl_name).unwrap();
+        let region_id = table_def.column_name_to_id("region").unwrap();
+        let host_id = table_def.column_name_to_id("host").unwrap();
+        let usage_id = table_def.column_name_to_id("usage").unwrap();
+
+        // only tag and string field columns can be cached:
+        assert!(matches!(
+            wbuf.create_distinct_cache(db_id, tbl_id, None, vec![usage_id], None, None)
+                .await,
+            Err(crate::write_buffer::Error::DistinctCacheError(
+                Error::InvalidColumnType
+            ))
+        ));
+
+        let definition = wbuf
+            .create_distinct_cache(
+                db_id,
+                tbl_id,
+                None,
+                vec![region_id, host_id],
+                Some(3),
+                Some(Duration::from_secs(60)),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!("cpu_region_host_distinct_cache", definition.name.as_ref());
+        assert!(wbuf
+            .create_distinct_cache(
+                db_id,
+                tbl_id,
+                None,
+                vec![region_id, host_id],
+                Some(3),
+                Some(Duration::from_secs(60)),
+            )
+            .await
+            .unwrap()
+            .is_none());
+
+        // the cache only holds values written after it was created, and the values written
+        // least recently are evicted once there are more than three:
+        for (t, lp) in [
+            (
+                2_000,
+                "cpu,region=us,host=a usage=2\ncpu,region=us,host=b usage=2",
+            ),
+            (
+                3_000,
+                "cpu,region=eu,host=c usage=3\ncpu,region=us,host=a usage=3",
+            ),
+            (4_000, "cpu,region=ca,host=d usage=4"),
+        ] {
+            wbuf.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                lp,
+                Time::from_timestamp_nanos(t),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        }
+
+        let batch = wbuf
+            .distinct_cache_provider()
+            .get_distinct_values(db_id, tbl_id, &definition.name)
+            .unwrap()
+            .unwrap();
+        assert_batches_eq!(
+            [
+                "+--------+------+",
+                "| region | host |",
+                "+--------+------+",
+                "| ca     | d    |",
+                "| eu     | c    |",
+                "| us     | a    |",
+                "+--------+------+",
+            ],
+            &[batch]
+        );
+
+        wbuf.delete_distinct_cache(db_id, tbl_id, &definition.name)
+            .await
+            .unwrap();
+        assert!(wbuf
+            .distinct_cache_provider()
+            .get_distinct_values(db_id, tbl_id, &definition.name)
+            .is_none());
+        assert!(wbuf
+            .catalog()
+            .db_schema_by_id(&db_id)
+            .unwrap()
+            .table_definition_by_id(&tbl_id)
+            .unwrap()
+            .distinct_caches
+            .is_empty());
+    }
+
+    #[test]
+    fn prune_evicts_expired_then_least_recently_written() {
+        let host_id = ColumnId::from(0);
+        let mut cache = DistinctCache {
+            column_ids: vec![host_id],
+            max_cardinality: 2,
+            ttl: Duration::from_secs(10),
+            schema: Arc::new(ArrowSchema::new(vec![ArrowField::new(
+                "host",
+                DataType::Utf8,
+                true,
+            )])),
+            values: BTreeMap::new(),
+            recency: BTreeMap::new(),
+            next_sequence: 0,
+        };
+        let row = |host: &str| Row {
+            time: 0,
+            fields: vec![Field::new(host_id, FieldData::Tag(Arc::from(host)))],
+        };
+        let hosts = |cache: &DistinctCache| {
+            cache
+                .values
+                .keys()
+                .map(|key| key[0].as_deref().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let start = Instant::now();
+
+        cache.push(&row("a"), start);
+        cache.push(&row("b"), start);
+        // writing a again makes b the least recently written:
+        cache.push(&row("a"), start);
+        cache.push(&row("c"), start);
+        cache.prune(start);
+        assert_eq!(vec!["a", "c"], hosts(&cache));
+        assert_eq!(2, cache.recency.len());
+
+        // only c is written again, so a expires:
+        let later = start + Duration::from_secs(5);
+        cache.push(&row("c"), later);
+        cache.prune(start + Duration::from_secs(10));
+        assert_eq!(vec!["c"], hosts(&cache));
+        assert_eq!(1, cache.recency.len());
+    }
+}
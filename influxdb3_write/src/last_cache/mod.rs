@@ -1,6 +1,9 @@
 use std::{
     collections::VecDeque,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
@@ -25,15 +28,16 @@ use hashbrown::{HashMap, HashSet};
 use indexmap::{IndexMap, IndexSet};
 use influxdb3_catalog::catalog::{Catalog, TableDefinition};
 use influxdb3_id::TableId;
-use influxdb3_id::{ColumnId, DbId};
+use influxdb3_id::{ColumnId, DbId, SerdeVecMap};
 use influxdb3_wal::{
-    Field, FieldData, LastCacheDefinition, LastCacheSize, LastCacheValueColumnsDef, Row,
-    WalContents, WalOp,
+    Field, FieldData, LastCacheAggregate, LastCacheAggregateFunction, LastCacheDefinition,
+    LastCacheSize, LastCacheValueColumnsDef, Row, TableChunks, WalContents, WalOp, WriteBatch,
 };
 use iox_time::Time;
-use observability_deps::tracing::debug;
+use observability_deps::tracing::{debug, info};
 use parking_lot::RwLock;
 use schema::{InfluxColumnType, InfluxFieldType, TIME_COLUMN_NAME};
+use serde::{Deserialize, Serialize};
 
 mod table_function;
 pub use table_function::LastCacheFunction;
@@ -58,6 +62,14 @@ pub enum Error {
     ValueColumnDoesNotExist { column_id: ColumnId },
     #[error("requested last cache does not exist")]
     CacheDoesNotExist,
+    #[error(
+        "cannot compute {function} of column (id: {column_id}), it must be a value column in the \
+        cache, and a numeric field unless the function is count"
+    )]
+    InvalidAggregate {
+        column_id: ColumnId,
+        function: LastCacheAggregateFunction,
+    },
 }
 
 impl Error {
@@ -76,6 +88,9 @@ type CacheMap = RwLock<HashMap<DbId, HashMap<TableId, HashMap<Arc<str>, LastCach
 pub struct LastCacheProvider {
     catalog: Arc<Catalog>,
     cache_map: CacheMap,
+    /// The total number of values that have been evicted from the caches because their TTL
+    /// expired
+    evicted: AtomicU64,
 }
 
 impl std::fmt::Debug for LastCacheProvider {
@@ -115,6 +130,67 @@ pub struct CreateCacheArguments {
     ///
     /// This will default to all non-key columns. The `time` column is always included.
     pub value_columns: Option<Vec<(ColumnId, Arc<str>)>>,
+    /// Aggregates of value columns to compute over the last values of each key
+    ///
+    /// This will default to none.
+    pub aggregates: Option<Vec<LastCacheAggregate>>,
+    /// Whether tag columns that are added to the table later become key columns of the cache,
+    /// with the values already in the cache re-keyed by them
+    pub auto_key_columns: bool,
+}
+
+/// Arguments to the [`LastCacheProvider::update_cache`] method
+pub struct UpdateCacheArguments {
+    /// The id of the database that the cache is in
+    pub db_id: DbId,
+    /// The definition of the table that the cache is on
+    pub table_def: Arc<TableDefinition>,
+    /// The name of the cache to update
+    pub cache_name: Arc<str>,
+    /// The new number of values to hold in the cache, or `None` to keep the current count
+    pub count: Option<usize>,
+    /// The new time-to-live (TTL) for the cache, or `None` to keep the current TTL
+    pub ttl: Option<Duration>,
+    /// The new value columns for the cache, or `None` to keep the current value columns
+    ///
+    /// The `time` column is always included.
+    pub value_columns: Option<LastCacheValueColumnsDef>,
+    /// The new aggregates for the cache, or `None` to keep the current aggregates
+    pub aggregates: Option<Vec<LastCacheAggregate>>,
+}
+
+/// Statistics about the values held in a last cache, and how it has been queried
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LastCacheStats {
+    /// The number of values in the cache that have not expired
+    pub entries: usize,
+    /// An estimate of the memory used by the values in the cache, in bytes
+    pub memory_bytes: usize,
+    /// The number of queries of the cache that produced values
+    pub hits: u64,
+    /// The number of queries of the cache that produced no values
+    pub misses: u64,
+    /// The timestamp of the oldest value in the cache, in nanoseconds
+    pub oldest_time_ns: Option<i64>,
+    /// The timestamp of the newest value in the cache, in nanoseconds
+    pub newest_time_ns: Option<i64>,
+}
+
+/// The values held in the last caches, which are persisted so that the caches do not start out
+/// empty when the server is restarted
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LastCacheContents {
+    pub caches: Vec<CacheContents>,
+}
+
+/// The values held in a single last cache
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheContents {
+    pub db_id: DbId,
+    pub table_id: TableId,
+    pub name: Arc<str>,
+    /// The rows in the cache, from oldest to newest
+    pub rows: Vec<Row>,
 }
 
 impl LastCacheProvider {
@@ -123,6 +199,7 @@ impl LastCacheProvider {
         let provider = Arc::new(LastCacheProvider {
             catalog: Arc::clone(&catalog),
             cache_map: Default::default(),
+            evicted: AtomicU64::new(0),
         });
         for db_schema in catalog.list_db_schema() {
             for table_def in db_schema.tables() {
@@ -162,6 +239,8 @@ impl LastCacheProvider {
                                 ttl: Some(Duration::from_secs(cache_def.ttl)),
                                 key_columns: Some(key_columns),
                                 value_columns,
+                                aggregates: Some(cache_def.aggregates.clone()),
+                                auto_key_columns: cache_def.auto_key_columns,
                             })?
                             .is_some(),
                         "catalog should not contain duplicate last cache definitions"
@@ -174,14 +253,20 @@ impl LastCacheProvider {
     }
 
     /// Initialize a [`LastCacheProvider`] from a [`Catalog`] and run a background process to
-    /// evict expired entries from the cache
+    /// evict expired entries from the cache, and the least recently written keys if the caches
+    /// use more memory than the `memory_budget_bytes`
     pub fn new_from_catalog_with_background_eviction(
         catalog: Arc<Catalog>,
         eviction_interval: Duration,
+        memory_budget_bytes: Option<usize>,
     ) -> Result<Arc<Self>, Error> {
         let provider = Self::new_from_catalog(catalog)?;
 
-        background_eviction_process(Arc::clone(&provider), eviction_interval);
+        background_eviction_process(
+            Arc::clone(&provider),
+            eviction_interval,
+            memory_budget_bytes,
+        );
 
         Ok(provider)
     }
@@ -239,6 +324,75 @@ impl LastCacheProvider {
             .unwrap_or_default()
     }
 
+    /// Get the [`LastCacheStats`] of a particular cache, or `None` if it does not exist
+    pub fn get_cache_stats(
+        &self,
+        db_id: DbId,
+        table_id: TableId,
+        cache_name: &str,
+    ) -> Option<LastCacheStats> {
+        self.cache_map
+            .read()
+            .get(&db_id)
+            .and_then(|db| db.get(&table_id))
+            .and_then(|table| table.get(cache_name))
+            .map(LastCache::stats)
+    }
+
+    /// An estimate of the memory used by the values in all of the caches, in bytes
+    pub fn memory_bytes(&self) -> usize {
+        self.cache_map
+            .read()
+            .values()
+            .flat_map(|db| db.values())
+            .flat_map(|table| table.values())
+            .map(|cache| cache.stats().memory_bytes)
+            .sum()
+    }
+
+    /// Evict the values of the least recently written keys from the caches until the memory they
+    /// use is within `budget_bytes`
+    ///
+    /// Returns the number of keys that were evicted.
+    pub fn enforce_memory_budget(&self, budget_bytes: usize) -> usize {
+        let mut cache_map = self.cache_map.write();
+        let mut entries = vec![];
+        for (db_id, db) in cache_map.iter() {
+            for (table_id, table) in db.iter() {
+                for (cache_name, cache) in table.iter() {
+                    let mut cache_entries = vec![];
+                    cache.state.key_entries(&mut vec![], &mut cache_entries);
+                    entries.extend(
+                        cache_entries
+                            .into_iter()
+                            .map(|entry| (*db_id, *table_id, Arc::clone(cache_name), entry)),
+                    );
+                }
+            }
+        }
+        let mut memory_bytes: usize = entries.iter().map(|(_, _, _, e)| e.memory_bytes).sum();
+        if memory_bytes <= budget_bytes {
+            return 0;
+        }
+        entries.sort_by_key(|(_, _, _, e)| e.last_write);
+        let mut evicted = 0;
+        for (db_id, table_id, cache_name, entry) in entries {
+            if memory_bytes <= budget_bytes {
+                break;
+            }
+            if let Some(cache) = cache_map
+                .get_mut(&db_id)
+                .and_then(|db| db.get_mut(&table_id))
+                .and_then(|table| table.get_mut(&cache_name))
+            {
+                cache.evict_key(&entry.key);
+                memory_bytes = memory_bytes.saturating_sub(entry.memory_bytes);
+                evicted += 1;
+            }
+        }
+        evicted
+    }
+
     /// Create a new entry in the last cache for a given database and table, along with the given
     /// parameters.
     ///
@@ -254,6 +408,8 @@ impl LastCacheProvider {
             ttl,
             key_columns,
             value_columns,
+            aggregates,
+            auto_key_columns,
         }: CreateCacheArguments,
     ) -> Result<Option<LastCacheDefinition>, Error> {
         let key_columns = if let Some(keys) = key_columns {
@@ -348,6 +504,14 @@ impl LastCacheProvider {
             };
 
         let last_cache_value_columns_def = LastCacheValueColumnsDef::from(&value_columns);
+        let aggregates = aggregates.unwrap_or_default();
+        validate_aggregates(
+            &table_def,
+            &key_columns.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            &last_cache_value_columns_def,
+            &aggregates,
+        )?;
+        let schema = with_aggregate_fields(schema, &table_def, &aggregates);
 
         let series_key = table_def.series_key.as_deref();
 
@@ -362,6 +526,8 @@ impl LastCacheProvider {
             ttl,
             key_columns.clone(),
             value_columns,
+            aggregates.clone(),
+            auto_key_columns,
             schema,
             series_key,
         );
@@ -394,6 +560,8 @@ impl LastCacheProvider {
             value_columns: last_cache_value_columns_def,
             count,
             ttl: ttl.as_secs(),
+            aggregates,
+            auto_key_columns,
         }))
     }
 
@@ -413,29 +581,12 @@ impl LastCacheProvider {
                     .expect("a valid column id for key column")
             })
             .collect::<Vec<(ColumnId, Arc<str>)>>();
-        let (value_columns, schema) = match &definition.value_columns {
-            LastCacheValueColumnsDef::AllNonKeyColumns => {
-                let (schema, seen) = last_cache_schema_from_table_def(
-                    Arc::clone(&table_def),
-                    key_columns.iter().map(|(id, _)| *id).collect(),
-                    None,
-                );
-                (ValueColumnType::AcceptNew { seen }, schema)
-            }
-            LastCacheValueColumnsDef::Explicit { columns } => {
-                let (schema, _) = last_cache_schema_from_table_def(
-                    Arc::clone(&table_def),
-                    key_columns.iter().map(|(id, _)| *id).collect(),
-                    Some(columns.as_slice()),
-                );
-                (
-                    ValueColumnType::Explicit {
-                        columns: columns.to_vec(),
-                    },
-                    schema,
-                )
-            }
-        };
+        let (value_columns, schema) = value_columns_and_schema(
+            Arc::clone(&table_def),
+            key_columns.iter().map(|(id, _)| *id).collect(),
+            &definition.value_columns,
+        );
+        let schema = with_aggregate_fields(schema, &table_def, &definition.aggregates);
         let series_key = table_def.series_key.as_deref();
 
         let last_cache = LastCache::new(
@@ -443,6 +594,8 @@ impl LastCacheProvider {
             Duration::from_secs(definition.ttl),
             key_columns,
             value_columns,
+            definition.aggregates.clone(),
+            definition.auto_key_columns,
             schema,
             series_key,
         );
@@ -456,6 +609,106 @@ impl LastCacheProvider {
             .insert(definition.name.clone(), last_cache);
     }
 
+    /// Change the count, TTL, value columns, or aggregates of an existing cache, keeping the values
+    /// that are in it
+    ///
+    /// Returns the new definition of the cache, or `None` if the provided arguments did not
+    /// change it.
+    pub fn update_cache(
+        &self,
+        UpdateCacheArguments {
+            db_id,
+            table_def,
+            cache_name,
+            count,
+            ttl,
+            value_columns,
+            aggregates,
+        }: UpdateCacheArguments,
+    ) -> Result<Option<LastCacheDefinition>, Error> {
+        let mut lock = self.cache_map.write();
+        let lc = lock
+            .get_mut(&db_id)
+            .and_then(|db| db.get_mut(&table_def.table_id))
+            .and_then(|table| table.get_mut(&cache_name))
+            .ok_or(Error::CacheDoesNotExist)?;
+        let current = lc.to_definition(
+            table_def.table_id,
+            Arc::clone(&table_def.table_name),
+            cache_name,
+        );
+
+        let value_columns = match value_columns {
+            Some(LastCacheValueColumnsDef::Explicit { mut columns }) => {
+                for column_id in &columns {
+                    if table_def.column_id_to_name(column_id).is_none() {
+                        return Err(Error::ValueColumnDoesNotExist {
+                            column_id: *column_id,
+                        });
+                    }
+                }
+                // Check that the `time` column is included, and add it if not:
+                let time_col_id =
+                    table_def
+                        .column_name_to_id(TIME_COLUMN_NAME)
+                        .ok_or_else(|| Error::ColumnDoesNotExistByName {
+                            column_name: TIME_COLUMN_NAME.to_string(),
+                        })?;
+                if !columns.contains(&time_col_id) {
+                    columns.push(time_col_id);
+                }
+                LastCacheValueColumnsDef::Explicit { columns }
+            }
+            Some(LastCacheValueColumnsDef::AllNonKeyColumns) => {
+                LastCacheValueColumnsDef::AllNonKeyColumns
+            }
+            None => current.value_columns.clone(),
+        };
+        let count = match count {
+            Some(count) => count.try_into().map_err(|_| Error::InvalidCacheSize)?,
+            None => current.count,
+        };
+        let aggregates = aggregates.unwrap_or_else(|| current.aggregates.clone());
+        validate_aggregates(
+            &table_def,
+            &current.key_columns,
+            &value_columns,
+            &aggregates,
+        )?;
+        let definition = LastCacheDefinition {
+            value_columns,
+            count,
+            ttl: ttl.map_or(current.ttl, |ttl| ttl.as_secs()),
+            aggregates,
+            ..current.clone()
+        };
+        if definition == current {
+            return Ok(None);
+        }
+
+        lc.reconfigure(table_def, &definition);
+        Ok(Some(definition))
+    }
+
+    /// Change the configuration of an existing cache to that of the given definition, keeping
+    /// the values that are in it, e.g., when a cache update is replayed from the WAL
+    pub fn update_cache_from_definition(
+        &self,
+        db_id: DbId,
+        table_def: Arc<TableDefinition>,
+        definition: &LastCacheDefinition,
+    ) {
+        if let Some(lc) = self
+            .cache_map
+            .write()
+            .get_mut(&db_id)
+            .and_then(|db| db.get_mut(&definition.table_id))
+            .and_then(|table| table.get_mut(&definition.name))
+        {
+            lc.reconfigure(table_def, definition);
+        }
+    }
+
     /// Delete a cache from the provider
     ///
     /// This will also clean up empty levels in the provider hierarchy, so if there are no more
@@ -492,6 +745,27 @@ impl LastCacheProvider {
         Ok(())
     }
 
+    /// Delete all of the caches of a table, e.g., once the table has been deleted
+    pub fn delete_caches_for_table(&self, db_id: DbId, table_id: TableId) {
+        let mut lock = self.cache_map.write();
+        let Some(db) = lock.get_mut(&db_id) else {
+            return;
+        };
+        db.remove(&table_id);
+        if db.is_empty() {
+            lock.remove(&db_id);
+        }
+    }
+
+    /// Recreate the caches of a table from its definition, which empties them, e.g., once one of
+    /// its columns has been dropped or renamed, since cached values are stored by column name
+    pub fn recreate_caches_for_table(&self, db_id: DbId, table_def: Arc<TableDefinition>) {
+        self.delete_caches_for_table(db_id, table_def.table_id);
+        for (_, definition) in table_def.last_caches() {
+            self.create_cache_from_definition(db_id, Arc::clone(&table_def), definition);
+        }
+    }
+
     /// Write the contents from a wal file into the cache by iterating over its database and table batches
     /// to find entries that belong in the cache.
     ///
@@ -531,14 +805,158 @@ impl LastCacheProvider {
         }
     }
 
+    /// The part of a write batch that is written to tables with caches, or `None` if none of it
+    /// is
+    pub fn cached_part_of_batch(&self, batch: &WriteBatch) -> Option<WriteBatch> {
+        let cache_map = self.cache_map.read();
+        let db_cache = cache_map.get(&batch.database_id)?;
+        let table_chunks: SerdeVecMap<TableId, TableChunks> = batch
+            .table_chunks
+            .iter()
+            .filter(|(table_id, _)| db_cache.get(*table_id).is_some_and(|t| !t.is_empty()))
+            .map(|(table_id, chunks)| (*table_id, chunks.clone()))
+            .collect();
+        (!table_chunks.is_empty()).then(|| WriteBatch {
+            database_id: batch.database_id,
+            database_name: Arc::clone(&batch.database_name),
+            table_chunks,
+            min_time_ns: batch.min_time_ns,
+            max_time_ns: batch.max_time_ns,
+        })
+    }
+
+    /// Write the rows in the batches to the caches, in the order that they were written, with
+    /// the tables split between threads so that their caches are written to in parallel.
+    ///
+    /// This is used to populate the caches once the WAL has been replayed, when the replayed
+    /// writes are not written through to them, see [`crate::LastCacheReplay`].
+    pub fn write_batches_to_cache_in_parallel(&self, batches: &[WriteBatch]) {
+        let mut table_writes: HashMap<(DbId, TableId), Vec<&TableChunks>> = HashMap::new();
+        for batch in batches {
+            for (table_id, table_chunks) in &batch.table_chunks {
+                table_writes
+                    .entry((batch.database_id, *table_id))
+                    .or_default()
+                    .push(table_chunks);
+            }
+        }
+        let n_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let mut thread_work: Vec<Vec<_>> = (0..n_threads).map(|_| vec![]).collect();
+        let mut cache_map = self.cache_map.write();
+        let mut n_tables = 0;
+        for (db_id, db_cache) in cache_map.iter_mut() {
+            let Some(db_schema) = self.catalog.db_schema_by_id(db_id) else {
+                continue;
+            };
+            for (table_id, table_cache) in db_cache.iter_mut() {
+                let Some(writes) = table_writes.remove(&(*db_id, *table_id)) else {
+                    continue;
+                };
+                let Some(table_def) = db_schema.table_definition_by_id(table_id) else {
+                    continue;
+                };
+                thread_work[n_tables % n_threads].push((table_def, table_cache, writes));
+                n_tables += 1;
+            }
+        }
+        debug!(n_tables, n_threads, "writing replayed rows to last caches");
+        std::thread::scope(|scope| {
+            for work in thread_work.into_iter().filter(|w| !w.is_empty()) {
+                scope.spawn(move || {
+                    for (table_def, table_cache, writes) in work {
+                        for last_cache in table_cache.values_mut() {
+                            for row in writes
+                                .iter()
+                                .flat_map(|chunks| chunks.chunk_time_to_chunk.values())
+                                .flat_map(|chunk| chunk.rows.iter())
+                            {
+                                last_cache.push(row, Arc::clone(&table_def));
+                            }
+                        }
+                    }
+                });
+            }
+        });
+    }
+
     /// Recurse down the cache structure to evict expired cache entries, based on their respective
     /// time-to-live (TTL).
-    pub fn evict_expired_cache_entries(&self) {
+    ///
+    /// Returns the number of values that were evicted.
+    pub fn evict_expired_cache_entries(&self) -> u64 {
+        let mut evicted = 0;
         let mut cache_map = self.cache_map.write();
         cache_map.iter_mut().for_each(|(_, db)| {
-            db.iter_mut()
-                .for_each(|(_, table)| table.iter_mut().for_each(|(_, lc)| lc.remove_expired()))
+            db.iter_mut().for_each(|(_, table)| {
+                table
+                    .iter_mut()
+                    .for_each(|(_, lc)| lc.remove_expired(&mut evicted))
+            })
         });
+        let evicted = evicted as u64;
+        self.evicted.fetch_add(evicted, Ordering::Relaxed);
+        evicted
+    }
+
+    /// The total number of values that have been evicted from the caches because their TTL
+    /// expired
+    pub fn evicted_count(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    /// The values that are held in all of the caches, which have not expired
+    pub fn contents(&self) -> LastCacheContents {
+        let cache_map = self.cache_map.read();
+        let caches = cache_map
+            .iter()
+            .flat_map(|(db_id, db)| {
+                db.iter().flat_map(move |(table_id, table)| {
+                    table.iter().map(move |(name, lc)| CacheContents {
+                        db_id: *db_id,
+                        table_id: *table_id,
+                        name: Arc::clone(name),
+                        rows: lc.to_rows(),
+                    })
+                })
+            })
+            .filter(|cache| !cache.rows.is_empty())
+            .collect();
+        LastCacheContents { caches }
+    }
+
+    /// Fill the caches with values that were taken from them with [`LastCacheProvider::contents`],
+    /// e.g., before the server was restarted
+    ///
+    /// Values for caches that no longer exist are ignored. The restored values are held for the
+    /// full TTL of their cache, counting from when they are restored.
+    pub fn restore_contents(&self, contents: LastCacheContents) {
+        let mut cache_map = self.cache_map.write();
+        for CacheContents {
+            db_id,
+            table_id,
+            name,
+            rows,
+        } in contents.caches
+        {
+            let Some(table_def) = self
+                .catalog
+                .db_schema_by_id(&db_id)
+                .and_then(|db| db.table_definition_by_id(&table_id))
+            else {
+                continue;
+            };
+            let Some(lc) = cache_map
+                .get_mut(&db_id)
+                .and_then(|db| db.get_mut(&table_id))
+                .and_then(|table| table.get_mut(&name))
+            else {
+                continue;
+            };
+            debug!(%name, rows = rows.len(), "restoring last cache contents");
+            for row in &rows {
+                lc.push(row, Arc::clone(&table_def));
+            }
+        }
     }
 
     /// Output the records for a given cache as arrow [`RecordBatch`]es
@@ -586,6 +1004,7 @@ impl LastCacheProvider {
 fn background_eviction_process(
     provider: Arc<LastCacheProvider>,
     eviction_interval: Duration,
+    memory_budget_bytes: Option<usize>,
 ) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(eviction_interval);
@@ -594,11 +1013,114 @@ fn background_eviction_process(
         loop {
             interval.tick().await;
 
-            provider.evict_expired_cache_entries();
+            let evicted = provider.evict_expired_cache_entries();
+            if evicted > 0 {
+                debug!(evicted, "evicted expired values from last caches");
+            }
+            if let Some(budget_bytes) = memory_budget_bytes {
+                let evicted = provider.enforce_memory_budget(budget_bytes);
+                if evicted > 0 {
+                    info!(
+                        evicted,
+                        budget_bytes, "evicted keys from last caches that were over memory budget"
+                    );
+                }
+            }
         }
     })
 }
 
+/// The value columns of a cache with the given definition of its value columns, along with the
+/// arrow schema of the cache
+fn value_columns_and_schema(
+    table_def: Arc<TableDefinition>,
+    key_columns: Vec<ColumnId>,
+    value_columns: &LastCacheValueColumnsDef,
+) -> (ValueColumnType, ArrowSchemaRef) {
+    match value_columns {
+        LastCacheValueColumnsDef::AllNonKeyColumns => {
+            let (schema, seen) = last_cache_schema_from_table_def(table_def, key_columns, None);
+            (ValueColumnType::AcceptNew { seen }, schema)
+        }
+        LastCacheValueColumnsDef::Explicit { columns } => {
+            let (schema, _) =
+                last_cache_schema_from_table_def(table_def, key_columns, Some(columns.as_slice()));
+            (
+                ValueColumnType::Explicit {
+                    columns: columns.to_vec(),
+                },
+                schema,
+            )
+        }
+    }
+}
+
+/// Check that the aggregates are of value columns of the cache, and that only numeric fields are
+/// aggregated with functions other than count
+fn validate_aggregates(
+    table_def: &TableDefinition,
+    key_columns: &[ColumnId],
+    value_columns: &LastCacheValueColumnsDef,
+    aggregates: &[LastCacheAggregate],
+) -> Result<(), Error> {
+    for aggregate in aggregates {
+        let is_value_column = match value_columns {
+            LastCacheValueColumnsDef::Explicit { columns } => {
+                columns.contains(&aggregate.column_id)
+            }
+            LastCacheValueColumnsDef::AllNonKeyColumns => {
+                !key_columns.contains(&aggregate.column_id)
+            }
+        };
+        let data_type = table_def
+            .columns
+            .get(&aggregate.column_id)
+            .map(|def| def.data_type);
+        let valid = match (aggregate.function, data_type) {
+            (_, None) => false,
+            (LastCacheAggregateFunction::Count, Some(_)) => true,
+            (_, Some(data_type)) => matches!(
+                data_type,
+                InfluxColumnType::Field(
+                    InfluxFieldType::Integer | InfluxFieldType::UInteger | InfluxFieldType::Float
+                )
+            ),
+        };
+        if !is_value_column || !valid {
+            return Err(Error::InvalidAggregate {
+                column_id: aggregate.column_id,
+                function: aggregate.function,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Add a field for each of the aggregates to the end of a cache's schema, named after the function
+/// and the column, e.g., `max_usage`
+fn with_aggregate_fields(
+    schema: ArrowSchemaRef,
+    table_def: &TableDefinition,
+    aggregates: &[LastCacheAggregate],
+) -> ArrowSchemaRef {
+    if aggregates.is_empty() {
+        return schema;
+    }
+    let mut schema_builder = ArrowSchemaBuilder::from(schema.fields());
+    for aggregate in aggregates {
+        let def = table_def
+            .columns
+            .get(&aggregate.column_id)
+            .expect("valid aggregate column");
+        let name = format!("{}_{}", aggregate.function, def.name);
+        schema_builder.push(match aggregate.function {
+            LastCacheAggregateFunction::Count => ArrowField::new(name, DataType::UInt64, false),
+            _ => ArrowField::new(name, DataType::from(&def.data_type), true),
+        });
+    }
+    Arc::new(schema_builder.finish())
+}
+
 fn last_cache_schema_from_table_def(
     table_def: Arc<TableDefinition>,
     key_columns: Vec<ColumnId>,
@@ -679,6 +1201,11 @@ pub(crate) struct LastCache {
     pub(crate) key_column_name_to_ids: Arc<HashMap<Arc<str>, ColumnId>>,
     /// The value columns for this cache
     pub(crate) value_columns: ValueColumnType,
+    /// The aggregates computed over the values of each key, whose fields are at the end of the
+    /// schema
+    pub(crate) aggregates: Vec<LastCacheAggregate>,
+    /// Whether tags that are added to the table become key columns of this cache
+    pub(crate) auto_key_columns: bool,
     /// The Arrow Schema for the table that this cache is associated with
     pub(crate) schema: ArrowSchemaRef,
     /// Optionally store the series key for tables that use it for ensuring non-nullability in the
@@ -689,6 +1216,10 @@ pub(crate) struct LastCache {
     series_key: Option<HashSet<ColumnId>>,
     /// The internal state of the cache
     state: LastCacheState,
+    /// The number of queries of the cache that produced values
+    hits: AtomicU64,
+    /// The number of queries of the cache that produced no values
+    misses: AtomicU64,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -715,6 +1246,8 @@ impl LastCache {
         ttl: Duration,
         key_columns: Vec<(ColumnId, Arc<str>)>,
         value_columns: ValueColumnType,
+        aggregates: Vec<LastCacheAggregate>,
+        auto_key_columns: bool,
         schema: ArrowSchemaRef,
         series_key: Option<&[ColumnId]>,
     ) -> Self {
@@ -730,9 +1263,13 @@ impl LastCache {
             key_column_ids: Arc::new(key_column_ids),
             key_column_name_to_ids: Arc::new(key_column_name_to_ids),
             value_columns,
+            aggregates,
+            auto_key_columns,
             schema,
             series_key: series_key.map(|sk| sk.iter().copied().collect()),
             state: LastCacheState::Init,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
         }
     }
 
@@ -754,6 +1291,16 @@ impl LastCache {
                 "provided value columns are not the same",
             ));
         }
+        if self.aggregates != other.aggregates {
+            return Err(Error::cache_already_exists(
+                "provided aggregates are not the same",
+            ));
+        }
+        if self.auto_key_columns != other.auto_key_columns {
+            return Err(Error::cache_already_exists(
+                "automatic key columns are not the same",
+            ));
+        }
         if self.series_key != other.series_key {
             return Err(Error::cache_already_exists(
                 "the series key is not the same",
@@ -786,6 +1333,10 @@ impl LastCache {
     /// This will panic if the internal cache state's keys are out-of-order with respect to the
     /// order of the `key_columns` on this [`LastCache`]
     pub(crate) fn push(&mut self, row: &Row, table_def: Arc<TableDefinition>) {
+        if self.auto_key_columns {
+            self.add_new_tag_key_columns(row, &table_def);
+        }
+        let auto_key_columns = self.auto_key_columns;
         let accept_new_fields = self.accept_new_fields();
         let mut target = &mut self.state;
         let mut key_iter = self.key_column_ids.iter().peekable();
@@ -801,6 +1352,15 @@ impl LastCache {
                 .iter()
                 .find(|f| f.id == *col_id)
                 .map(|f| KeyValue::from(&f.value))
+                .or_else(|| {
+                    // a row of a series without one of the tags that were added to the table
+                    // since is cached under an empty value for it:
+                    let is_tag = table_def
+                        .columns
+                        .get(col_id)
+                        .is_some_and(|def| def.data_type == InfluxColumnType::Tag);
+                    (auto_key_columns && is_tag).then(|| KeyValue::String(String::new()))
+                })
             else {
                 // ignore the row if it does not contain all key columns
                 return;
@@ -845,13 +1405,68 @@ impl LastCache {
         store.push(row);
         if self.should_update_schema_from_row(row) {
             let (schema, seen) = last_cache_schema_from_table_def(
-                table_def,
+                Arc::clone(&table_def),
                 self.key_column_ids.iter().copied().collect(),
                 None,
             );
-            self.schema = schema;
+            self.schema = with_aggregate_fields(schema, &table_def, &self.aggregates);
+            self.value_columns = ValueColumnType::AcceptNew { seen };
+        }
+    }
+
+    /// Make the tags in a row that are not yet key columns of the cache into key columns, and
+    /// re-key the values that are already in the cache by them
+    fn add_new_tag_key_columns(&mut self, row: &Row, table_def: &Arc<TableDefinition>) {
+        let mut new_key_columns = row
+            .fields
+            .iter()
+            .filter(|f| {
+                matches!(f.value, FieldData::Tag(_)) && !self.key_column_ids.contains(&f.id)
+            })
+            .filter_map(|f| table_def.column_id_to_name(&f.id).map(|name| (f.id, name)))
+            .collect::<Vec<_>>();
+        if new_key_columns.is_empty() {
+            return;
+        }
+        // the ids are in the order that the columns were added to the table, which is the order
+        // that they are added to the key columns in the catalog:
+        new_key_columns.sort_by_key(|(id, _)| *id);
+        debug!(
+            ?new_key_columns,
+            "adding new tags to last cache key columns"
+        );
+
+        let rows = self.to_rows();
+        let mut key_column_ids = IndexSet::clone(&self.key_column_ids);
+        let mut key_column_name_to_ids = HashMap::clone(&self.key_column_name_to_ids);
+        for (id, name) in new_key_columns {
+            key_column_ids.insert(id);
+            key_column_name_to_ids.insert(name, id);
+        }
+        if let ValueColumnType::Explicit { columns } = &mut self.value_columns {
+            columns.retain(|id| !key_column_ids.contains(id));
+        }
+        self.key_column_ids = Arc::new(key_column_ids);
+        self.key_column_name_to_ids = Arc::new(key_column_name_to_ids);
+        let explicit_columns = match &self.value_columns {
+            ValueColumnType::AcceptNew { .. } => None,
+            ValueColumnType::Explicit { columns } => Some(columns.as_slice()),
+        };
+        let (schema, seen) = last_cache_schema_from_table_def(
+            Arc::clone(table_def),
+            self.key_column_ids.iter().copied().collect(),
+            explicit_columns,
+        );
+        self.schema = with_aggregate_fields(schema, table_def, &self.aggregates);
+        if self.accept_new_fields() {
             self.value_columns = ValueColumnType::AcceptNew { seen };
         }
+
+        // the values are pushed back in, oldest first, under their new keys:
+        self.state = LastCacheState::Init;
+        for row in &rows {
+            self.push(row, Arc::clone(table_def));
+        }
     }
 
     /// Produce a set of [`RecordBatch`]es from the cache, using the given set of [`Predicate`]s
@@ -906,99 +1521,168 @@ impl LastCache {
             caches = new_caches;
         }
 
-        caches
+        let batches = caches
             .into_iter()
-            .map(|c| c.to_record_batch(Arc::clone(&table_def), Arc::clone(&self.schema)))
-            .collect()
+            .map(|c| {
+                c.to_record_batch(
+                    Arc::clone(&table_def),
+                    Arc::clone(&self.schema),
+                    &self.aggregates,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if batches.iter().any(|batch| batch.num_rows() > 0) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(batches)
+    }
+
+    /// Get the [`LastCacheStats`] for this cache
+    fn stats(&self) -> LastCacheStats {
+        let mut stats = LastCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        self.state.add_stats(&mut stats);
+        stats
+    }
+
+    /// Remove the values of a key from the cache, where `key` holds a value for each of the
+    /// cache's key columns
+    fn evict_key(&mut self, key: &[KeyValue]) {
+        if self.state.remove_key(key) {
+            self.state = LastCacheState::Init;
+        }
     }
 
     /// Convert a set of DataFusion filter [`Expr`]s into [`Predicate`]s
     ///
-    /// This only handles binary expressions, e.g., `foo = 'bar'`, and will use the `key_columns`
-    /// to filter out expressions that do not match key columns in the cache.
+    /// This handles comparisons of key columns with literals, e.g., `foo = 'bar'` or
+    /// `foo IN ('bar', 'baz')`, as well as `OR`s of equality comparisons on the same key column,
+    /// and will filter out expressions that do not match key columns in the cache.
     fn convert_filter_exprs(&self, exprs: &[Expr]) -> Vec<Predicate> {
         exprs
             .iter()
-            .filter_map(|expr| {
-                match expr {
-                    Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
-                        let col_id = if let Expr::Column(c) = left.as_ref() {
-                            self.key_column_name_to_ids.get(c.name()).copied()?
-                        } else {
-                            return None;
-                        };
-                        let value = match right.as_ref() {
-                            Expr::Literal(ScalarValue::Utf8(Some(v))) => {
-                                KeyValue::String(v.to_owned())
-                            }
-                            Expr::Literal(ScalarValue::Boolean(Some(v))) => KeyValue::Bool(*v),
-                            // TODO: handle integer types that can be casted up to i64/u64:
-                            Expr::Literal(ScalarValue::Int64(Some(v))) => KeyValue::Int(*v),
-                            Expr::Literal(ScalarValue::UInt64(Some(v))) => KeyValue::UInt(*v),
-                            _ => return None,
-                        };
-                        match op {
-                            Operator::Eq => Some(Predicate::new_eq(col_id, value)),
-                            Operator::NotEq => Some(Predicate::new_not_eq(col_id, value)),
-                            _ => None,
-                        }
-                    }
-                    Expr::InList(InList {
-                        expr,
-                        list,
-                        negated,
-                    }) => {
-                        let col_id = if let Expr::Column(c) = expr.as_ref() {
-                            self.key_column_name_to_ids.get(c.name()).copied()?
-                        } else {
-                            return None;
-                        };
-                        let values: Vec<KeyValue> = list
-                            .iter()
-                            .filter_map(|e| match e {
-                                Expr::Literal(ScalarValue::Utf8(Some(v))) => {
-                                    Some(KeyValue::String(v.to_owned()))
-                                }
-                                Expr::Literal(ScalarValue::Boolean(Some(v))) => {
-                                    Some(KeyValue::Bool(*v))
-                                }
-                                // TODO: handle integer types that can be casted up to i64/u64:
-                                Expr::Literal(ScalarValue::Int64(Some(v))) => {
-                                    Some(KeyValue::Int(*v))
-                                }
-                                Expr::Literal(ScalarValue::UInt64(Some(v))) => {
-                                    Some(KeyValue::UInt(*v))
-                                }
-                                _ => None,
-                            })
-                            .collect();
-                        if *negated {
-                            Some(Predicate::new_not_in(col_id, values))
-                        } else {
-                            Some(Predicate::new_in(col_id, values))
-                        }
-                    }
-                    _ => None,
-                }
-            })
+            .filter_map(|expr| self.key_column_predicate(expr))
             .collect()
     }
 
-    /// Remove expired values from the internal cache state
-    fn remove_expired(&mut self) {
-        self.state.remove_expired();
+    /// The filter [`Expr`]s that cannot be converted into [`Predicate`]s on key columns, but that
+    /// can be applied to the rows produced by the cache, because they only refer to columns in it
+    fn row_filter_exprs<'a>(&self, exprs: &'a [Expr]) -> Vec<&'a Expr> {
+        exprs
+            .iter()
+            .filter(|expr| {
+                self.key_column_predicate(expr).is_none()
+                    && expr
+                        .column_refs()
+                        .iter()
+                        .all(|c| self.schema.field_with_name(c.name()).is_ok())
+            })
+            .collect()
     }
 
-    /// Convert the `LastCache` into a `LastCacheDefinition`
-    fn to_definition(
-        &self,
-        table_id: TableId,
-        table: impl Into<Arc<str>>,
-        name: impl Into<Arc<str>>,
-    ) -> LastCacheDefinition {
-        LastCacheDefinition {
-            table_id,
-            table: table.into(),
+    /// Convert a filter [`Expr`] into a [`Predicate`] on one of the key columns, if it can be
+    fn key_column_predicate(&self, expr: &Expr) -> Option<Predicate> {
+        match expr {
+            Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+                if *op == Operator::Or {
+                    // `foo = 'bar' OR foo = 'baz'` is the same as `foo IN ('bar', 'baz')`:
+                    let left = self.key_column_predicate(left)?;
+                    let right = self.key_column_predicate(right)?;
+                    if left.column_id != right.column_id {
+                        return None;
+                    }
+                    let mut values = left.kind.into_eq_values()?;
+                    values.extend(right.kind.into_eq_values()?);
+                    return Some(Predicate::new_in(left.column_id, values));
+                }
+                // the column can be on either side of the comparison:
+                let (col_id, value) = match (left.as_ref(), right.as_ref()) {
+                    (Expr::Column(c), value) | (value, Expr::Column(c)) => (
+                        self.key_column_name_to_ids.get(c.name()).copied()?,
+                        key_value_from_literal(value)?,
+                    ),
+                    _ => return None,
+                };
+                match op {
+                    Operator::Eq => Some(Predicate::new_eq(col_id, value)),
+                    Operator::NotEq => Some(Predicate::new_not_eq(col_id, value)),
+                    _ => None,
+                }
+            }
+            Expr::InList(InList {
+                expr,
+                list,
+                negated,
+            }) => {
+                let col_id = if let Expr::Column(c) = expr.as_ref() {
+                    self.key_column_name_to_ids.get(c.name()).copied()?
+                } else {
+                    return None;
+                };
+                let values: Vec<KeyValue> =
+                    list.iter().filter_map(key_value_from_literal).collect();
+                if *negated {
+                    Some(Predicate::new_not_in(col_id, values))
+                } else {
+                    Some(Predicate::new_in(col_id, values))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Remove expired values from the internal cache state, adding the number of values that
+    /// were removed to `evicted`
+    fn remove_expired(&mut self, evicted: &mut usize) {
+        self.state.remove_expired(evicted);
+    }
+
+    /// Change the count, TTL, value columns, and aggregates of the cache to those in `definition`
+    ///
+    /// Values beyond the new count are evicted, and the values of columns that were not in the
+    /// cache before are null.
+    fn reconfigure(&mut self, table_def: Arc<TableDefinition>, definition: &LastCacheDefinition) {
+        let (value_columns, schema) = value_columns_and_schema(
+            Arc::clone(&table_def),
+            self.key_column_ids.iter().copied().collect(),
+            &definition.value_columns,
+        );
+        self.count = definition.count;
+        self.ttl = Duration::from_secs(definition.ttl);
+        let explicit_columns = match &value_columns {
+            ValueColumnType::Explicit { columns } => Some(columns.as_slice()),
+            ValueColumnType::AcceptNew { .. } => None,
+        };
+        self.state
+            .reconfigure(self.count.into(), self.ttl, &table_def, explicit_columns);
+        self.value_columns = value_columns;
+        self.schema = with_aggregate_fields(schema, &table_def, &definition.aggregates);
+        self.aggregates = definition.aggregates.clone();
+    }
+
+    /// Convert the values in the cache that have not expired into [`Row`]s, which reproduce the
+    /// cache's contents when they are pushed into a new cache in order
+    fn to_rows(&self) -> Vec<Row> {
+        let mut rows = vec![];
+        self.state.to_rows(&mut vec![], &mut rows);
+        rows
+    }
+
+    /// Convert the `LastCache` into a `LastCacheDefinition`
+    fn to_definition(
+        &self,
+        table_id: TableId,
+        table: impl Into<Arc<str>>,
+        name: impl Into<Arc<str>>,
+    ) -> LastCacheDefinition {
+        LastCacheDefinition {
+            table_id,
+            table: table.into(),
             name: name.into(),
             key_columns: self.key_column_ids.iter().copied().collect(),
             value_columns: match &self.value_columns {
@@ -1009,6 +1693,8 @@ impl LastCache {
             },
             count: self.count,
             ttl: self.ttl.as_secs(),
+            aggregates: self.aggregates.clone(),
+            auto_key_columns: self.auto_key_columns,
         }
     }
 }
@@ -1036,6 +1722,7 @@ impl<'a> ExtendedLastCacheState<'a> {
         &self,
         table_def: Arc<TableDefinition>,
         schema: ArrowSchemaRef,
+        aggregates: &[LastCacheAggregate],
     ) -> Result<RecordBatch, ArrowError> {
         let store = self
             .state
@@ -1083,7 +1770,7 @@ impl<'a> ExtendedLastCacheState<'a> {
                     .collect(),
             )
         };
-        store.to_record_batch(table_def, schema, extended, n_non_expired)
+        store.to_record_batch(table_def, schema, aggregates, extended, n_non_expired)
     }
 }
 
@@ -1134,6 +1821,30 @@ pub(crate) enum PredicateKind {
     NotIn(Vec<KeyValue>),
 }
 
+impl PredicateKind {
+    /// The values that a column must be equal to one of, to satisfy this predicate, if it is an
+    /// equality or `IN` predicate
+    fn into_eq_values(self) -> Option<Vec<KeyValue>> {
+        match self {
+            Self::Eq(value) => Some(vec![value]),
+            Self::In(values) => Some(values),
+            Self::NotEq(_) | Self::NotIn(_) => None,
+        }
+    }
+}
+
+/// Convert a literal [`Expr`] into the [`KeyValue`] that it is compared with in a predicate
+fn key_value_from_literal(expr: &Expr) -> Option<KeyValue> {
+    match expr {
+        Expr::Literal(ScalarValue::Utf8(Some(v))) => Some(KeyValue::String(v.to_owned())),
+        Expr::Literal(ScalarValue::Boolean(Some(v))) => Some(KeyValue::Bool(*v)),
+        // TODO: handle integer types that can be casted up to i64/u64:
+        Expr::Literal(ScalarValue::Int64(Some(v))) => Some(KeyValue::Int(*v)),
+        Expr::Literal(ScalarValue::UInt64(Some(v))) => Some(KeyValue::UInt(*v)),
+        _ => None,
+    }
+}
+
 /// Represents the hierarchical last cache structure
 #[derive(Debug)]
 enum LastCacheState {
@@ -1179,13 +1890,119 @@ impl LastCacheState {
     }
 
     /// Remove expired values from this [`LastCacheState`]
-    fn remove_expired(&mut self) -> bool {
+    fn remove_expired(&mut self, evicted: &mut usize) -> bool {
         match self {
-            LastCacheState::Key(k) => k.remove_expired(),
-            LastCacheState::Store(s) => s.remove_expired(),
+            LastCacheState::Key(k) => k.remove_expired(evicted),
+            LastCacheState::Store(s) => s.remove_expired(evicted),
             LastCacheState::Init => false,
         }
     }
+
+    /// Add the entries, memory, and time range of the values in this [`LastCacheState`] to `stats`
+    fn add_stats(&self, stats: &mut LastCacheStats) {
+        match self {
+            LastCacheState::Key(k) => {
+                for (value, state) in &k.value_map {
+                    stats.memory_bytes += value.size_bytes();
+                    state.add_stats(stats);
+                }
+            }
+            LastCacheState::Store(s) => s.add_stats(stats),
+            LastCacheState::Init => (),
+        }
+    }
+
+    /// Add a [`KeyEntry`] to `entries` for each [`LastCacheStore`] in this [`LastCacheState`],
+    /// where `key` holds the values of the key columns above it
+    fn key_entries(&self, key: &mut Vec<KeyValue>, entries: &mut Vec<KeyEntry>) {
+        match self {
+            LastCacheState::Key(k) => {
+                for (value, state) in &k.value_map {
+                    key.push(value.clone());
+                    state.key_entries(key, entries);
+                    key.pop();
+                }
+            }
+            LastCacheState::Store(s) => {
+                let mut stats = LastCacheStats::default();
+                s.add_stats(&mut stats);
+                entries.push(KeyEntry {
+                    key: key.clone(),
+                    last_write: s.instants.front().copied(),
+                    memory_bytes: stats.memory_bytes
+                        + key.iter().map(KeyValue::size_bytes).sum::<usize>(),
+                });
+            }
+            LastCacheState::Init => (),
+        }
+    }
+
+    /// Remove the [`LastCacheStore`] for the given `key` from this [`LastCacheState`]
+    ///
+    /// Returns whether or not this state is empty after the removal, in which case its parent
+    /// should drop it.
+    fn remove_key(&mut self, key: &[KeyValue]) -> bool {
+        match self {
+            LastCacheState::Key(k) => {
+                if let Some((value, rest)) = key.split_first() {
+                    if k.value_map
+                        .get_mut(value)
+                        .is_some_and(|state| state.remove_key(rest))
+                    {
+                        k.value_map.remove(value);
+                    }
+                }
+                k.value_map.is_empty()
+            }
+            LastCacheState::Store(_) | LastCacheState::Init => true,
+        }
+    }
+
+    /// Change the configuration of any [`LastCacheStore`] in this [`LastCacheState`]
+    fn reconfigure(
+        &mut self,
+        count: usize,
+        ttl: Duration,
+        table_def: &TableDefinition,
+        explicit_columns: Option<&[ColumnId]>,
+    ) {
+        match self {
+            LastCacheState::Key(k) => k
+                .value_map
+                .values_mut()
+                .for_each(|state| state.reconfigure(count, ttl, table_def, explicit_columns)),
+            LastCacheState::Store(s) => s.reconfigure(count, ttl, table_def, explicit_columns),
+            LastCacheState::Init => (),
+        }
+    }
+
+    /// Add the values in this [`LastCacheState`] to `rows`, along with the values of the key
+    /// columns above it, which are in `key_fields`
+    fn to_rows(&self, key_fields: &mut Vec<Field>, rows: &mut Vec<Row>) {
+        match self {
+            LastCacheState::Key(k) => {
+                for (value, state) in &k.value_map {
+                    key_fields.push(Field::new(k.column_id, FieldData::from(value)));
+                    state.to_rows(key_fields, rows);
+                    key_fields.pop();
+                }
+            }
+            LastCacheState::Store(s) => s.to_rows(key_fields, rows),
+            LastCacheState::Init => (),
+        }
+    }
+}
+
+/// The values held for a single key of a [`LastCache`], which are evicted together when the
+/// caches are over their memory budget
+#[derive(Debug)]
+struct KeyEntry {
+    /// The values of the key columns
+    key: Vec<KeyValue>,
+    /// When a value was last written for the key
+    last_write: Option<Instant>,
+    /// An estimate of the memory used by the values, in bytes
+    memory_bytes: usize,
 }
 
 /// Holds a node within a [`LastCache`] for a given key column
@@ -1246,8 +2063,12 @@ impl LastCacheKey {
     /// [`LastCacheStore`]s at the lowest level, then dropping any [`LastCacheStore`] that is
     /// completeley empty. As it walks back up the hierarchy, any [`LastCacheKey`] that is empty will
     /// also be dropped from its parent map.
-    fn remove_expired(&mut self) -> bool {
-        self.value_map.retain(|_, s| !s.remove_expired());
+    fn remove_expired(&mut self, evicted: &mut usize) -> bool {
+        self.value_map.retain(|_, s| !s.remove_expired(evicted));
+        if self.value_map.is_empty() {
+            // release the memory held by the map for the keys that were dropped:
+            self.value_map.shrink_to_fit();
+        }
         self.value_map.is_empty()
     }
 }
@@ -1261,6 +2082,16 @@ pub(crate) enum KeyValue {
     Bool(bool),
 }
 
+impl KeyValue {
+    /// An estimate of the memory used by this value, in bytes
+    fn size_bytes(&self) -> usize {
+        match self {
+            KeyValue::String(s) => std::mem::size_of::<Self>() + s.len(),
+            _ => std::mem::size_of::<Self>(),
+        }
+    }
+}
+
 #[cfg(test)]
 impl KeyValue {
     fn string(s: impl Into<String>) -> Self {
@@ -1271,9 +2102,8 @@ impl KeyValue {
 impl From<&FieldData> for KeyValue {
     fn from(field: &FieldData) -> Self {
         match field {
-            FieldData::Key(s) | FieldData::Tag(s) | FieldData::String(s) => {
-                Self::String(s.to_owned())
-            }
+            FieldData::Key(s) | FieldData::Tag(s) => Self::String(s.to_string()),
+            FieldData::String(s) => Self::String(s.to_owned()),
             FieldData::Integer(i) => Self::Int(*i),
             FieldData::UInteger(u) => Self::UInt(*u),
             FieldData::Boolean(b) => Self::Bool(*b),
@@ -1283,6 +2113,17 @@ impl From<&FieldData> for KeyValue {
     }
 }
 
+impl From<&KeyValue> for FieldData {
+    fn from(value: &KeyValue) -> Self {
+        match value {
+            KeyValue::String(s) => Self::String(s.to_owned()),
+            KeyValue::Int(i) => Self::Integer(*i),
+            KeyValue::UInt(u) => Self::UInteger(*u),
+            KeyValue::Bool(b) => Self::Boolean(*b),
+        }
+    }
+}
+
 /// Stores the cached column data for the field columns of a given [`LastCache`]
 #[derive(Debug)]
 struct LastCacheStore {
@@ -1427,48 +2268,67 @@ impl LastCacheStore {
     /// Accepts an `n_non_expired` argument to indicate the number of non-expired elements in the
     /// store. This is passed in vs. calling `self.len()`, since that is already invoked in the
     /// calling function, and calling it here _could_ produce a different result.
+    ///
+    /// The fields of the `aggregates` are at the end of the `schema`, and each row holds the same
+    /// value for them, i.e., the aggregate of the non-expired values in this store.
     fn to_record_batch(
         &self,
         table_def: Arc<TableDefinition>,
         schema: ArrowSchemaRef,
+        aggregates: &[LastCacheAggregate],
         extended: Option<Vec<ArrayRef>>,
         n_non_expired: usize,
     ) -> Result<RecordBatch, ArrowError> {
         let mut arrays = extended.unwrap_or_default();
-        if self.accept_new_fields {
-            for field in schema.fields().iter() {
-                let id = table_def
-                    .column_name_to_id(field.name().as_str())
-                    .ok_or_else(|| {
-                        ArrowError::from_external_error(Box::new(Error::ColumnDoesNotExistByName {
-                            column_name: field.name().to_string(),
-                        }))
-                    })?;
-                if self.key_column_ids.contains(&id) {
-                    continue;
-                }
-                arrays.push(self.cache.get(&id).map_or_else(
-                    || new_null_array(field.data_type(), n_non_expired),
-                    |c| c.data.as_array(n_non_expired),
-                ));
+        let n_columns = schema.fields().len() - aggregates.len();
+        // The columns are looked up by those in the schema, since the store can hold columns that
+        // are not in it, e.g., if the value columns of the cache were changed:
+        for field in schema.fields().iter().take(n_columns) {
+            let id = table_def
+                .column_name_to_id(field.name().as_str())
+                .ok_or_else(|| {
+                    ArrowError::from_external_error(Box::new(Error::ColumnDoesNotExistByName {
+                        column_name: field.name().to_string(),
+                    }))
+                })?;
+            if self.key_column_ids.contains(&id) {
+                continue;
             }
-        } else {
-            arrays.extend(
-                self.cache
-                    .iter()
-                    .map(|(_, col)| col.data.as_array(n_non_expired)),
-            );
+            arrays.push(self.cache.get(&id).map_or_else(
+                || new_null_array(field.data_type(), n_non_expired),
+                |c| c.data.as_array(n_non_expired),
+            ));
+        }
+        for (aggregate, field) in aggregates
+            .iter()
+            .zip(schema.fields().iter().skip(n_columns))
+        {
+            let value = match self.cache.get(&aggregate.column_id) {
+                Some(column) => column.data.aggregate(aggregate.function, n_non_expired),
+                None if aggregate.function == LastCacheAggregateFunction::Count => {
+                    Some(ScalarValue::UInt64(Some(0)))
+                }
+                None => None,
+            };
+            arrays.push(match value {
+                Some(value) => value
+                    .to_array_of_size(n_non_expired)
+                    .map_err(|e| ArrowError::from_external_error(Box::new(e)))?,
+                None => new_null_array(field.data_type(), n_non_expired),
+            });
         }
         RecordBatch::try_new(schema, arrays)
     }
 
     /// Remove expired values from the [`LastCacheStore`]
     ///
-    /// Returns whether or not the store is empty after expired entries are removed.
-    fn remove_expired(&mut self) -> bool {
+    /// Returns whether or not the store is empty after expired entries are removed, and adds the
+    /// number of entries that were removed to `evicted`.
+    fn remove_expired(&mut self, evicted: &mut usize) -> bool {
         while let Some(instant) = self.instants.back() {
             if instant.elapsed() >= self.ttl {
                 self.instants.pop_back();
+                *evicted += 1;
             } else {
                 break;
             }
@@ -1482,6 +2342,83 @@ impl LastCacheStore {
         }
         self.is_empty()
     }
+
+    /// Add the entries, memory, and time range of the values in this [`LastCacheStore`] to
+    /// `stats`
+    fn add_stats(&self, stats: &mut LastCacheStats) {
+        let n_non_expired = self.len();
+        if n_non_expired == 0 {
+            return;
+        }
+        stats.entries += n_non_expired;
+        stats.memory_bytes += self.instants.len() * std::mem::size_of::<Instant>();
+        for column in self.cache.values() {
+            stats.memory_bytes += column.data.size_bytes();
+            if let CacheColumnData::Time(times) = &column.data {
+                // the newest values are at the front of the column:
+                let newest = times[0];
+                let oldest = times[n_non_expired - 1];
+                stats.newest_time_ns = Some(stats.newest_time_ns.map_or(newest, |t| t.max(newest)));
+                stats.oldest_time_ns = Some(stats.oldest_time_ns.map_or(oldest, |t| t.min(oldest)));
+            }
+        }
+    }
+
+    /// Change the count and TTL of this [`LastCacheStore`], and the columns it accepts values for
+    ///
+    /// If `explicit_columns` are given, only those columns are accepted, otherwise new fields are
+    /// accepted as they are added to the table.
+    fn reconfigure(
+        &mut self,
+        count: usize,
+        ttl: Duration,
+        table_def: &TableDefinition,
+        explicit_columns: Option<&[ColumnId]>,
+    ) {
+        self.count = count;
+        self.ttl = ttl;
+        self.accept_new_fields = explicit_columns.is_none();
+        self.instants.truncate(count);
+        for column in self.cache.values_mut() {
+            column.size = count;
+            column.truncate(count);
+        }
+        // Columns that were not in the store before are back-filled with nulls:
+        for col_id in explicit_columns.unwrap_or_default() {
+            if self.cache.contains_key(col_id) || self.key_column_ids.contains(col_id) {
+                continue;
+            }
+            let Some(col_def) = table_def.columns.get(col_id) else {
+                continue;
+            };
+            let mut column = CacheColumn::new(col_def.data_type, count, false);
+            for _ in 0..self.instants.len() {
+                column.push_null();
+            }
+            self.cache.insert(*col_id, column);
+        }
+    }
+
+    /// Add the values in this [`LastCacheStore`] that have not expired to `rows`, from oldest to
+    /// newest, with the `key_fields` added to each row
+    fn to_rows(&self, key_fields: &[Field], rows: &mut Vec<Row>) {
+        for i in (0..self.len()).rev() {
+            let mut time = None;
+            let mut fields = key_fields.to_vec();
+            for (col_id, col) in &self.cache {
+                let Some(value) = col.data.field_data(i) else {
+                    continue;
+                };
+                if let FieldData::Timestamp(t) = value {
+                    time = Some(t);
+                }
+                fields.push(Field::new(*col_id, value));
+            }
+            if let Some(time) = time {
+                rows.push(Row { time, fields });
+            }
+        }
+    }
 }
 
 /// A column in a [`LastCache`]
@@ -1609,9 +2546,9 @@ impl CacheColumnData {
     fn push_front(&mut self, field_data: &FieldData) {
         match (field_data, self) {
             (FieldData::Timestamp(val), CacheColumnData::Time(buf)) => buf.push_front(*val),
-            (FieldData::Key(val), CacheColumnData::Key(buf)) => buf.push_front(val.to_owned()),
+            (FieldData::Key(val), CacheColumnData::Key(buf)) => buf.push_front(val.to_string()),
             (FieldData::Tag(val), CacheColumnData::Tag(buf)) => {
-                buf.push_front(Some(val.to_owned()))
+                buf.push_front(Some(val.to_string()))
             }
             (FieldData::String(val), CacheColumnData::String(buf)) => {
                 buf.push_front(Some(val.to_owned()))
@@ -1637,6 +2574,94 @@ impl CacheColumnData {
         }
     }
 
+    /// An estimate of the memory used by the values in this column, in bytes
+    fn size_bytes(&self) -> usize {
+        fn strings_size<'a>(strings: impl Iterator<Item = &'a String>) -> usize {
+            strings.map(|s| s.len()).sum()
+        }
+        match self {
+            CacheColumnData::I64(buf) => buf.len() * std::mem::size_of::<Option<i64>>(),
+            CacheColumnData::U64(buf) => buf.len() * std::mem::size_of::<Option<u64>>(),
+            CacheColumnData::F64(buf) => buf.len() * std::mem::size_of::<Option<f64>>(),
+            CacheColumnData::Bool(buf) => buf.len() * std::mem::size_of::<Option<bool>>(),
+            CacheColumnData::String(buf) | CacheColumnData::Tag(buf) => {
+                buf.len() * std::mem::size_of::<Option<String>>()
+                    + strings_size(buf.iter().flatten())
+            }
+            CacheColumnData::Key(buf) => {
+                buf.len() * std::mem::size_of::<String>() + strings_size(buf.iter())
+            }
+            CacheColumnData::Time(buf) => buf.len() * std::mem::size_of::<i64>(),
+        }
+    }
+
+    /// The value at index `i`, where the newest value is at index 0, or `None` if it is null
+    fn field_data(&self, i: usize) -> Option<FieldData> {
+        match self {
+            CacheColumnData::I64(buf) => buf.get(i)?.map(FieldData::Integer),
+            CacheColumnData::U64(buf) => buf.get(i)?.map(FieldData::UInteger),
+            CacheColumnData::F64(buf) => buf.get(i)?.map(FieldData::Float),
+            CacheColumnData::String(buf) => buf.get(i)?.clone().map(FieldData::String),
+            CacheColumnData::Bool(buf) => buf.get(i)?.map(FieldData::Boolean),
+            CacheColumnData::Tag(buf) => buf.get(i)?.as_deref().map(|v| FieldData::Tag(v.into())),
+            CacheColumnData::Key(buf) => buf.get(i).map(|v| FieldData::Key(v.as_str().into())),
+            CacheColumnData::Time(buf) => buf.get(i).copied().map(FieldData::Timestamp),
+        }
+    }
+
+    /// Compute an aggregate of the first `n_non_expired` values in the column, or `None` if the
+    /// function does not apply to the column's type
+    fn aggregate(
+        &self,
+        function: LastCacheAggregateFunction,
+        n_non_expired: usize,
+    ) -> Option<ScalarValue> {
+        fn reduce<T: Copy + PartialOrd>(
+            values: impl Iterator<Item = T>,
+            function: LastCacheAggregateFunction,
+            add: impl Fn(T, T) -> T,
+        ) -> Option<T> {
+            values.reduce(|acc, v| match function {
+                LastCacheAggregateFunction::Min if v < acc => v,
+                LastCacheAggregateFunction::Max if v > acc => v,
+                LastCacheAggregateFunction::Sum => add(acc, v),
+                _ => acc,
+            })
+        }
+
+        if function == LastCacheAggregateFunction::Count {
+            let n = n_non_expired.min(self.len());
+            let count = match self {
+                CacheColumnData::I64(buf) => buf.iter().take(n).flatten().count(),
+                CacheColumnData::U64(buf) => buf.iter().take(n).flatten().count(),
+                CacheColumnData::F64(buf) => buf.iter().take(n).flatten().count(),
+                CacheColumnData::String(buf) => buf.iter().take(n).flatten().count(),
+                CacheColumnData::Bool(buf) => buf.iter().take(n).flatten().count(),
+                CacheColumnData::Tag(buf) => buf.iter().take(n).flatten().count(),
+                CacheColumnData::Key(_) | CacheColumnData::Time(_) => n,
+            };
+            return Some(ScalarValue::UInt64(Some(count as u64)));
+        }
+        match self {
+            CacheColumnData::I64(buf) => Some(ScalarValue::Int64(reduce(
+                buf.iter().take(n_non_expired).flatten().copied(),
+                function,
+                i64::wrapping_add,
+            ))),
+            CacheColumnData::U64(buf) => Some(ScalarValue::UInt64(reduce(
+                buf.iter().take(n_non_expired).flatten().copied(),
+                function,
+                u64::wrapping_add,
+            ))),
+            CacheColumnData::F64(buf) => Some(ScalarValue::Float64(reduce(
+                buf.iter().take(n_non_expired).flatten().copied(),
+                function,
+                |a, b| a + b,
+            ))),
+            _ => None,
+        }
+    }
+
     /// Produce an arrow [`ArrayRef`] from this column for the sake of producing [`RecordBatch`]es
     ///
     /// Accepts `n_non_expired` to indicate how many of the first elements in the column buffer to
@@ -1742,19 +2767,30 @@ mod tests {
     use std::{cmp::Ordering, sync::Arc, time::Duration};
 
     use crate::{
-        last_cache::{KeyValue, LastCacheProvider, Predicate, DEFAULT_CACHE_TTL},
+        last_cache::{
+            KeyValue, LastCacheFunction, LastCacheProvider, LastCacheStats, Predicate,
+            PredicateKind, DEFAULT_CACHE_TTL,
+        },
         parquet_cache::test_cached_obj_store_and_oracle,
         persister::Persister,
-        write_buffer::WriteBufferImpl,
+        write_buffer::{WriteBufferImpl, WriteBufferImplArgs},
         Bufferer, LastCacheManager, Precision,
     };
     use ::object_store::{memory::InMemory, ObjectStore};
     use arrow_util::{assert_batches_eq, assert_batches_sorted_eq};
     use bimap::BiHashMap;
     use data_types::NamespaceName;
+    use datafusion::{
+        datasource::{function::TableFunctionImpl, TableProvider},
+        physical_plan::collect,
+        prelude::{col, lit, SessionContext},
+    };
     use influxdb3_catalog::catalog::{Catalog, DatabaseSchema, TableDefinition};
     use influxdb3_id::{ColumnId, DbId, SerdeVecMap, TableId};
-    use influxdb3_wal::{LastCacheDefinition, WalConfig};
+    use influxdb3_wal::{
+        LastCacheAggregate, LastCacheAggregateFunction, LastCacheDefinition,
+        LastCacheValueColumnsDef, WalConfig,
+    };
     use insta::assert_json_snapshot;
     use iox_time::{MockProvider, Time, TimeProvider};
 
@@ -1768,7 +2804,7 @@ mod tests {
         let host_id = Arc::from("sample-host-id");
         let instance_id = Arc::from("sample-instance-id");
         let catalog = Arc::new(Catalog::new(host_id, instance_id));
-        WriteBufferImpl::new(
+        WriteBufferImpl::new(WriteBufferImplArgs::new(
             persister,
             Arc::clone(&catalog),
             LastCacheProvider::new_from_catalog(catalog as _).unwrap(),
@@ -1776,7 +2812,7 @@ mod tests {
             crate::test_help::make_exec(),
             WalConfig::test_config(),
             Some(parquet_cache),
-        )
+        ))
         .await
         .unwrap()
     }
@@ -2397,6 +3433,12 @@ mod tests {
         // wait for the TTL to clear the cache
         tokio::time::sleep(Duration::from_millis(1000)).await;
 
+        // All six values have expired, so they are all evicted:
+        let provider = wbuf.last_cache_provider();
+        assert_eq!(6, provider.evict_expired_cache_entries());
+        assert_eq!(0, provider.evict_expired_cache_entries());
+        assert_eq!(6, provider.evicted_count());
+
         // Check what is in the last cache:
         let batches = wbuf
             .last_cache_provider()
@@ -2453,6 +3495,804 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn restore_cache_contents() {
+        let db_name = "foo";
+        let tbl_name = "cpu";
+        let wbuf = setup_write_buffer().await;
+
+        // Do one write to update the catalog with a db and table:
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},region=us,host=a usage=1").as_str(),
+            Time::from_timestamp_nanos(500),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        let (db_id, db_schema) = wbuf.catalog().db_schema_and_id(db_name).unwrap();
+        let (tbl_id, table_def) = db_schema.table_definition_and_id(tbl_name).unwrap();
+        let host_col_id = table_def.column_name_to_id("host").unwrap();
+
+        // Create a cache of the last two values for each host:
+        wbuf.create_last_cache(
+            db_id,
+            tbl_id,
+            Some("cache"),
+            Some(2),
+            None,
+            Some(vec![(host_col_id, "host".into())]),
+            None,
+        )
+        .await
+        .expect("create last cache");
+
+        for (time, lp) in [
+            (
+                1_000,
+                format!(
+                    "{tbl_name},region=us,host=a usage=10\n{tbl_name},region=ca,host=b usage=20"
+                ),
+            ),
+            (
+                2_000,
+                format!(
+                    "{tbl_name},region=us,host=a usage=11\n{tbl_name},region=ca,host=b usage=21"
+                ),
+            ),
+            (3_000, format!("{tbl_name},region=us,host=a usage=12")),
+        ] {
+            wbuf.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                lp.as_str(),
+                Time::from_timestamp_nanos(time),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        }
+
+        // Restore the contents, as they would be after being persisted, into a new provider,
+        // like the one created when the server restarts:
+        let contents = wbuf.last_cache_provider().contents();
+        let json = serde_json::to_vec(&contents).unwrap();
+        let provider = LastCacheProvider::new_from_catalog(wbuf.catalog()).unwrap();
+        assert_eq!(0, provider.contents().caches.len());
+        provider.restore_contents(serde_json::from_slice(&json).unwrap());
+        assert_eq!(4, provider.contents().caches[0].rows.len());
+
+        let batches = provider
+            .get_cache_record_batches(db_id, tbl_id, None, &[])
+            .unwrap()
+            .unwrap();
+        assert_batches_sorted_eq!(
+            [
+                "+------+--------+-----------------------------+-------+",
+                "| host | region | time                        | usage |",
+                "+------+--------+-----------------------------+-------+",
+                "| a    | us     | 1970-01-01T00:00:00.000002Z | 11.0  |",
+                "| a    | us     | 1970-01-01T00:00:00.000003Z | 12.0  |",
+                "| b    | ca     | 1970-01-01T00:00:00.000001Z | 20.0  |",
+                "| b    | ca     | 1970-01-01T00:00:00.000002Z | 21.0  |",
+                "+------+--------+-----------------------------+-------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn update_cache_in_place() {
+        let db_name = "foo";
+        let tbl_name = "cpu";
+        let wbuf = setup_write_buffer().await;
+
+        // Do one write to update the catalog with a db and table:
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},region=us,host=a usage=1").as_str(),
+            Time::from_timestamp_nanos(500),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        let (db_id, db_schema) = wbuf.catalog().db_schema_and_id(db_name).unwrap();
+        let (tbl_id, table_def) = db_schema.table_definition_and_id(tbl_name).unwrap();
+        let host_col_id = table_def.column_name_to_id("host").unwrap();
+        let usage_col_id = table_def.column_name_to_id("usage").unwrap();
+
+        wbuf.create_last_cache(
+            db_id,
+            tbl_id,
+            Some("cache"),
+            None,
+            None,
+            Some(vec![(host_col_id, "host".into())]),
+            None,
+        )
+        .await
+        .expect("create last cache");
+
+        let write = |time: i64, usage: i64| {
+            let wbuf = &wbuf;
+            async move {
+                wbuf.write_lp(
+                    NamespaceName::new(db_name).unwrap(),
+                    format!("{tbl_name},region=us,host=a usage={usage}").as_str(),
+                    Time::from_timestamp_nanos(time),
+                    false,
+                    Precision::Nanosecond,
+                )
+                .await
+                .unwrap();
+            }
+        };
+        write(1_000, 10).await;
+
+        // Increase the count of the cache, which keeps the value that is in it:
+        let definition = wbuf
+            .update_last_cache(db_id, tbl_id, "cache", Some(2), None, None, None)
+            .await
+            .unwrap()
+            .expect("cache was updated");
+        assert_eq!(2, definition.count);
+        assert_eq!(
+            Some(&definition),
+            wbuf.catalog()
+                .db_schema_by_id(&db_id)
+                .and_then(|db| db.table_definition_by_id(&tbl_id))
+                .unwrap()
+                .last_caches()
+                .find(|(name, _)| name.as_ref() == "cache")
+                .map(|(_, def)| def)
+        );
+        // Updating it with the same settings does nothing:
+        assert!(wbuf
+            .update_last_cache(db_id, tbl_id, "cache", Some(2), None, None, None)
+            .await
+            .unwrap()
+            .is_none());
+
+        write(2_000, 20).await;
+        let batches = wbuf
+            .last_cache_provider()
+            .get_cache_record_batches(db_id, tbl_id, None, &[])
+            .unwrap()
+            .unwrap();
+        assert_batches_sorted_eq!(
+            [
+                "+------+--------+-----------------------------+-------+",
+                "| host | region | time                        | usage |",
+                "+------+--------+-----------------------------+-------+",
+                "| a    | us     | 1970-01-01T00:00:00.000001Z | 10.0  |",
+                "| a    | us     | 1970-01-01T00:00:00.000002Z | 20.0  |",
+                "+------+--------+-----------------------------+-------+",
+            ],
+            &batches
+        );
+
+        // Only cache the usage, along with the time, which is always included:
+        wbuf.update_last_cache(
+            db_id,
+            tbl_id,
+            "cache",
+            None,
+            None,
+            Some(LastCacheValueColumnsDef::Explicit {
+                columns: vec![usage_col_id],
+            }),
+            None,
+        )
+        .await
+        .unwrap()
+        .expect("cache was updated");
+        let batches = wbuf
+            .last_cache_provider()
+            .get_cache_record_batches(db_id, tbl_id, None, &[])
+            .unwrap()
+            .unwrap();
+        assert_batches_sorted_eq!(
+            [
+                "+------+-----------------------------+-------+",
+                "| host | time                        | usage |",
+                "+------+-----------------------------+-------+",
+                "| a    | 1970-01-01T00:00:00.000001Z | 10.0  |",
+                "| a    | 1970-01-01T00:00:00.000002Z | 20.0  |",
+                "+------+-----------------------------+-------+",
+            ],
+            &batches
+        );
+
+        // Decreasing the count evicts the oldest values:
+        wbuf.update_last_cache(db_id, tbl_id, "cache", Some(1), None, None, None)
+            .await
+            .unwrap()
+            .expect("cache was updated");
+        let batches = wbuf
+            .last_cache_provider()
+            .get_cache_record_batches(db_id, tbl_id, None, &[])
+            .unwrap()
+            .unwrap();
+        assert_batches_sorted_eq!(
+            [
+                "+------+-----------------------------+-------+",
+                "| host | time                        | usage |",
+                "+------+-----------------------------+-------+",
+                "| a    | 1970-01-01T00:00:00.000002Z | 20.0  |",
+                "+------+-----------------------------+-------+",
+            ],
+            &batches
+        );
+
+        assert!(matches!(
+            wbuf.update_last_cache(db_id, tbl_id, "not_a_cache", Some(1), None, None, None)
+                .await,
+            Err(crate::write_buffer::Error::LastCacheError(
+                crate::last_cache::Error::CacheDoesNotExist
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn aggregate_columns() {
+        let db_name = "foo";
+        let tbl_name = "cpu";
+        let wbuf = setup_write_buffer().await;
+
+        // Do one write to update the catalog with a db and table:
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},host=a usage=1").as_str(),
+            Time::from_timestamp_nanos(500),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        let (db_id, db_schema) = wbuf.catalog().db_schema_and_id(db_name).unwrap();
+        let (tbl_id, table_def) = db_schema.table_definition_and_id(tbl_name).unwrap();
+        let host_col_id = table_def.column_name_to_id("host").unwrap();
+        let usage_col_id = table_def.column_name_to_id("usage").unwrap();
+
+        wbuf.create_last_cache(
+            db_id,
+            tbl_id,
+            Some("cache"),
+            Some(3),
+            None,
+            Some(vec![(host_col_id, "host".into())]),
+            None,
+        )
+        .await
+        .expect("create last cache");
+
+        // Aggregating a key column is not allowed:
+        assert!(matches!(
+            wbuf.update_last_cache(
+                db_id,
+                tbl_id,
+                "cache",
+                None,
+                None,
+                None,
+                Some(vec![LastCacheAggregate {
+                    column_id: host_col_id,
+                    function: LastCacheAggregateFunction::Max,
+                }]),
+            )
+            .await,
+            Err(crate::write_buffer::Error::LastCacheError(
+                crate::last_cache::Error::InvalidAggregate { .. }
+            ))
+        ));
+
+        wbuf.update_last_cache(
+            db_id,
+            tbl_id,
+            "cache",
+            None,
+            None,
+            None,
+            Some(vec![
+                LastCacheAggregate {
+                    column_id: usage_col_id,
+                    function: LastCacheAggregateFunction::Max,
+                },
+                LastCacheAggregate {
+                    column_id: usage_col_id,
+                    function: LastCacheAggregateFunction::Count,
+                },
+            ]),
+        )
+        .await
+        .unwrap()
+        .expect("cache was updated");
+
+        for (time, usage) in [(1_000, 10), (2_000, 30), (3_000, 20), (4_000, 15)] {
+            wbuf.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                format!("{tbl_name},host=a usage={usage}").as_str(),
+                Time::from_timestamp_nanos(time),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        }
+
+        // The aggregates are of the last three values, and are repeated on each row:
+        let batches = wbuf
+            .last_cache_provider()
+            .get_cache_record_batches(db_id, tbl_id, None, &[])
+            .unwrap()
+            .unwrap();
+        assert_batches_sorted_eq!(
+            [
+                "+------+-----------------------------+-------+-----------+-------------+",
+                "| host | time                        | usage | max_usage | count_usage |",
+                "+------+-----------------------------+-------+-----------+-------------+",
+                "| a    | 1970-01-01T00:00:00.000002Z | 30.0  | 30.0      | 3           |",
+                "| a    | 1970-01-01T00:00:00.000003Z | 20.0  | 30.0      | 3           |",
+                "| a    | 1970-01-01T00:00:00.000004Z | 15.0  | 30.0      | 3           |",
+                "+------+-----------------------------+-------+-----------+-------------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn auto_key_columns_adapt_to_new_tags() {
+        let db_name = "foo";
+        let tbl_name = "cpu";
+        let wbuf = setup_write_buffer().await;
+
+        // Do one write to update the catalog with a db and table:
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},host=a usage=1").as_str(),
+            Time::from_timestamp_nanos(500),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        let (db_id, db_schema) = wbuf.catalog().db_schema_and_id(db_name).unwrap();
+        let tbl_id = db_schema.table_name_to_id(tbl_name).unwrap();
+
+        wbuf.create_last_cache_with_auto_key_columns(
+            db_id,
+            tbl_id,
+            Some("cache"),
+            Some(1),
+            None,
+            None,
+        )
+        .await
+        .unwrap()
+        .expect("cache was created");
+
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},host=a usage=2").as_str(),
+            Time::from_timestamp_nanos(1_000),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        // The region tag is new, so the cache should start keying on it as well:
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},host=b,region=us usage=3").as_str(),
+            Time::from_timestamp_nanos(2_000),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        let table_def = wbuf
+            .catalog()
+            .db_schema_by_id(&db_id)
+            .unwrap()
+            .table_definition_by_id(&tbl_id)
+            .unwrap();
+        let key_columns = &table_def.last_caches.get("cache").unwrap().key_columns;
+        assert_eq!(
+            key_columns,
+            &[
+                table_def.column_name_to_id("host").unwrap(),
+                table_def.column_name_to_id("region").unwrap(),
+            ]
+        );
+
+        let batches = wbuf
+            .last_cache_provider()
+            .get_cache_record_batches(db_id, tbl_id, None, &[])
+            .unwrap()
+            .unwrap();
+        assert_batches_sorted_eq!(
+            [
+                "+------+--------+-----------------------------+-------+",
+                "| host | region | time                        | usage |",
+                "+------+--------+-----------------------------+-------+",
+                "| a    |        | 1970-01-01T00:00:00.000001Z | 2.0   |",
+                "| b    | us     | 1970-01-01T00:00:00.000002Z | 3.0   |",
+                "+------+--------+-----------------------------+-------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn list_caches_and_stats() {
+        let db_name = "foo";
+        let tbl_name = "cpu";
+        let wbuf = setup_write_buffer().await;
+
+        // Do one write to update the catalog with a db and table:
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},region=us,host=a usage=1").as_str(),
+            Time::from_timestamp_nanos(500),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        let (db_id, db_schema) = wbuf.catalog().db_schema_and_id(db_name).unwrap();
+        let (tbl_id, table_def) = db_schema.table_definition_and_id(tbl_name).unwrap();
+        let host_col_id = table_def.column_name_to_id("host").unwrap();
+
+        let definition = wbuf
+            .create_last_cache(
+                db_id,
+                tbl_id,
+                Some("cache"),
+                Some(2),
+                None,
+                Some(vec![(host_col_id, "host".into())]),
+                None,
+            )
+            .await
+            .expect("create last cache")
+            .unwrap();
+        assert_eq!(vec![definition], wbuf.list_last_caches(db_id));
+        assert_eq!(
+            Some(LastCacheStats::default()),
+            wbuf.last_cache_stats(db_id, tbl_id, "cache")
+        );
+        assert!(wbuf
+            .last_cache_stats(db_id, tbl_id, "not_a_cache")
+            .is_none());
+
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!(
+                "\
+                {tbl_name},region=us,host=a usage=10\n\
+                {tbl_name},region=us,host=b usage=20\n\
+                "
+            )
+            .as_str(),
+            Time::from_timestamp_nanos(1_000),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},region=us,host=a usage=11").as_str(),
+            Time::from_timestamp_nanos(2_000),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        // Query the cache for a host that is in it, and one that is not:
+        let provider = wbuf.last_cache_provider();
+        for host in ["a", "z"] {
+            provider
+                .get_cache_record_batches(
+                    db_id,
+                    tbl_id,
+                    None,
+                    &[Predicate::new_eq(host_col_id, KeyValue::string(host))],
+                )
+                .unwrap()
+                .unwrap();
+        }
+
+        let stats = wbuf.last_cache_stats(db_id, tbl_id, "cache").unwrap();
+        assert_eq!(3, stats.entries);
+        assert_eq!(1, stats.hits);
+        assert_eq!(1, stats.misses);
+        assert_eq!(Some(1_000), stats.oldest_time_ns);
+        assert_eq!(Some(2_000), stats.newest_time_ns);
+        assert!(stats.memory_bytes > 0);
+    }
+
+    #[tokio::test]
+    async fn filter_pushdown() {
+        let db_name = "foo";
+        let tbl_name = "cpu";
+        let wbuf = setup_write_buffer().await;
+
+        // Do one write to update the catalog with a db and table:
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},region=us,host=a usage=1").as_str(),
+            Time::from_timestamp_nanos(500),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        let (db_id, db_schema) = wbuf.catalog().db_schema_and_id(db_name).unwrap();
+        let (tbl_id, table_def) = db_schema.table_definition_and_id(tbl_name).unwrap();
+        let host_col_id = table_def.column_name_to_id("host").unwrap();
+
+        wbuf.create_last_cache(
+            db_id,
+            tbl_id,
+            Some("cache"),
+            None,
+            None,
+            Some(vec![(host_col_id, "host".into())]),
+            None,
+        )
+        .await
+        .expect("create last cache");
+
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!(
+                "\
+                {tbl_name},region=us,host=a usage=10\n\
+                {tbl_name},region=us,host=b usage=20\n\
+                {tbl_name},region=us,host=c usage=30\n\
+                "
+            )
+            .as_str(),
+            Time::from_timestamp_nanos(1_000),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        let provider = wbuf.last_cache_provider();
+        let host_or = col("host").eq(lit("a")).or(lit("b").eq(col("host")));
+        let usage_gt = col("usage").gt(lit(15.0));
+        {
+            let cache_map = provider.cache_map.read();
+            let cache = cache_map
+                .get(&db_id)
+                .and_then(|db| db.get(&tbl_id))
+                .and_then(|table| table.get("cache"))
+                .unwrap();
+
+            // Comparisons with the key column prune the cache, with the column on either side,
+            // and `OR`s of them are converted to `IN`s:
+            let predicates = cache.convert_filter_exprs(&[
+                lit("a").eq(col("host")),
+                host_or.clone(),
+                usage_gt.clone(),
+            ]);
+            assert_eq!(2, predicates.len());
+            assert!(matches!(
+                &predicates[1].kind,
+                PredicateKind::In(values) if values.len() == 2
+            ));
+
+            // Other filters are applied to the rows, if they only refer to columns in the cache:
+            let not_a_column = col("not_a_column").eq(lit(1));
+            let filters = [host_or.clone(), usage_gt.clone(), not_a_column];
+            assert_eq!(vec![&usage_gt], cache.row_filter_exprs(&filters));
+        }
+
+        let ctx = SessionContext::new();
+        let table = LastCacheFunction::new(db_id, Arc::clone(&provider))
+            .call(&[lit(tbl_name)])
+            .unwrap();
+        let plan = table
+            .scan(&ctx.state(), None, &[host_or, usage_gt], None)
+            .await
+            .unwrap();
+        let batches = collect(plan, ctx.task_ctx()).await.unwrap();
+        assert_batches_sorted_eq!(
+            [
+                "+------+--------+-----------------------------+-------+",
+                "| host | region | time                        | usage |",
+                "+------+--------+-----------------------------+-------+",
+                "| b    | us     | 1970-01-01T00:00:00.000001Z | 20.0  |",
+                "+------+--------+-----------------------------+-------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_budget_evicts_least_recently_written_keys() {
+        let db_name = "foo";
+        let tbl_name = "cpu";
+        let wbuf = setup_write_buffer().await;
+
+        // Do one write to update the catalog with a db and table:
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},host=a usage=1").as_str(),
+            Time::from_timestamp_nanos(500),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        let (db_id, db_schema) = wbuf.catalog().db_schema_and_id(db_name).unwrap();
+        let (tbl_id, table_def) = db_schema.table_definition_and_id(tbl_name).unwrap();
+        let host_col_id = table_def.column_name_to_id("host").unwrap();
+
+        wbuf.create_last_cache(
+            db_id,
+            tbl_id,
+            Some("cache"),
+            None,
+            None,
+            Some(vec![(host_col_id, "host".into())]),
+            None,
+        )
+        .await
+        .expect("create last cache");
+
+        for (time, host) in [(1_000, "a"), (2_000, "b"), (3_000, "c")] {
+            wbuf.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                format!("{tbl_name},host={host} usage={time}").as_str(),
+                Time::from_timestamp_nanos(time),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        }
+
+        let provider = wbuf.last_cache_provider();
+        let memory_bytes = wbuf.last_cache_memory_bytes();
+        assert!(memory_bytes > 0);
+        assert_eq!(
+            memory_bytes,
+            wbuf.last_cache_stats(db_id, tbl_id, "cache")
+                .unwrap()
+                .memory_bytes
+        );
+        assert_eq!(0, provider.enforce_memory_budget(memory_bytes));
+
+        // Going one byte over the budget evicts the key that was written to least recently:
+        assert_eq!(1, provider.enforce_memory_budget(memory_bytes - 1));
+        assert!(wbuf.last_cache_memory_bytes() < memory_bytes);
+        let batches = provider
+            .get_cache_record_batches(db_id, tbl_id, None, &[])
+            .unwrap()
+            .unwrap();
+        assert_batches_sorted_eq!(
+            [
+                "+------+-----------------------------+--------+",
+                "| host | time                        | usage  |",
+                "+------+-----------------------------+--------+",
+                "| b    | 1970-01-01T00:00:00.000002Z | 2000.0 |",
+                "| c    | 1970-01-01T00:00:00.000003Z | 3000.0 |",
+                "+------+-----------------------------+--------+",
+            ],
+            &batches
+        );
+
+        // With no budget at all, every key is evicted, and the cache is written to as usual after:
+        assert_eq!(2, provider.enforce_memory_budget(0));
+        assert_eq!(0, wbuf.last_cache_memory_bytes());
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},host=d usage=4000").as_str(),
+            Time::from_timestamp_nanos(4_000),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+        let batches = provider
+            .get_cache_record_batches(db_id, tbl_id, None, &[])
+            .unwrap()
+            .unwrap();
+        assert_batches_sorted_eq!(
+            [
+                "+------+-----------------------------+--------+",
+                "| host | time                        | usage  |",
+                "+------+-----------------------------+--------+",
+                "| d    | 1970-01-01T00:00:00.000004Z | 4000.0 |",
+                "+------+-----------------------------+--------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn table_function_of_other_database() {
+        let wbuf = setup_write_buffer().await;
+        for (db_name, lp) in [("foo", "mem,host=a used=1"), ("bar", "cpu,host=a usage=1")] {
+            wbuf.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                lp,
+                Time::from_timestamp_nanos(500),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        }
+        let (foo_id, _) = wbuf.catalog().db_schema_and_id("foo").unwrap();
+        let (bar_id, bar_schema) = wbuf.catalog().db_schema_and_id("bar").unwrap();
+        let (tbl_id, table_def) = bar_schema.table_definition_and_id("cpu").unwrap();
+        let host_col_id = table_def.column_name_to_id("host").unwrap();
+        wbuf.create_last_cache(
+            bar_id,
+            tbl_id,
+            Some("cache"),
+            None,
+            None,
+            Some(vec![(host_col_id, "host".into())]),
+            None,
+        )
+        .await
+        .expect("create last cache");
+        wbuf.write_lp(
+            NamespaceName::new("bar").unwrap(),
+            "cpu,host=a usage=10",
+            Time::from_timestamp_nanos(1_000),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        // Querying the `foo` database, the cache in `bar` is read by naming the database, with or
+        // without the name of the cache:
+        let ctx = SessionContext::new();
+        let function = LastCacheFunction::new(foo_id, wbuf.last_cache_provider());
+        for args in [
+            vec![lit("bar"), lit("cpu")],
+            vec![lit("bar"), lit("cpu"), lit("cache")],
+        ] {
+            let table = function.call(&args).unwrap();
+            let plan = table.scan(&ctx.state(), None, &[], None).await.unwrap();
+            let batches = collect(plan, ctx.task_ctx()).await.unwrap();
+            assert_batches_sorted_eq!(
+                [
+                    "+------+-----------------------------+-------+",
+                    "| host | time                        | usage |",
+                    "+------+-----------------------------+-------+",
+                    "| a    | 1970-01-01T00:00:00.000001Z | 10.0  |",
+                    "+------+-----------------------------+-------+",
+                ],
+                &batches
+            );
+        }
+
+        // A table in the database being queried takes precedence, and it has no cache:
+        assert!(function.call(&[lit("mem"), lit("cache")]).is_err());
+        assert!(function.call(&[lit("baz"), lit("cpu")]).is_err());
+    }
+
     #[tokio::test]
     async fn fields_as_key_columns() {
         let db_name = "cassini_mission";
@@ -3310,6 +5150,20 @@ mod tests {
                 map.insert(TableId::from(1), "test_table_2".into());
                 map
             },
+            retention_period: None,
+            future_write_limit: None,
+            past_write_limit: None,
+            schema_limits: Default::default(),
+            cardinality_limits: Default::default(),
+            schema_mode: Default::default(),
+            field_coercion: Default::default(),
+            compatibility_mode: Default::default(),
+            series_key_policy: Default::default(),
+            identifier_policy: Default::default(),
+            write_rate_limit: Default::default(),
+            gen1_duration: None,
+            metadata: Default::default(),
+            downsampling_tasks: Default::default(),
         };
         let table_id = TableId::from(0);
         use schema::InfluxColumnType::*;
@@ -1,12 +1,12 @@
 use std::{any::Any, sync::Arc};
 
-use arrow::datatypes::SchemaRef;
+use arrow::{compute::filter_record_batch, datatypes::SchemaRef};
 use async_trait::async_trait;
 use datafusion::{
     catalog::Session,
-    common::{plan_err, Result},
+    common::{cast::as_boolean_array, plan_err, DFSchema, Result},
     datasource::{function::TableFunctionImpl, TableProvider, TableType},
-    logical_expr::{Expr, TableProviderFilterPushDown},
+    logical_expr::{utils::conjunction, Expr, TableProviderFilterPushDown},
     physical_plan::{memory::MemoryExec, ExecutionPlan},
     scalar::ScalarValue,
 };
@@ -57,8 +57,26 @@ impl TableProvider for LastCacheFunctionProvider {
             .and_then(|db| db.get(&self.table_def.table_id))
             .and_then(|tbl| tbl.get(&self.cache_name))
         {
+            // filters on key columns prune the branches of the cache that are visited, and the
+            // rest are applied to the rows of each branch:
             let predicates = cache.convert_filter_exprs(filters);
-            cache.to_record_batches(Arc::clone(&self.table_def), &predicates)?
+            let batches = cache.to_record_batches(Arc::clone(&self.table_def), &predicates)?;
+            match conjunction(cache.row_filter_exprs(filters).into_iter().cloned()) {
+                Some(filter) => {
+                    let df_schema = DFSchema::try_from(cache.arrow_schema())?;
+                    let filter = ctx.create_physical_expr(filter, &df_schema)?;
+                    let mut filtered = Vec::with_capacity(batches.len());
+                    for batch in batches {
+                        let mask = filter.evaluate(&batch)?.into_array(batch.num_rows())?;
+                        let batch = filter_record_batch(&batch, as_boolean_array(&mask)?)?;
+                        if batch.num_rows() > 0 {
+                            filtered.push(batch);
+                        }
+                    }
+                    filtered
+                }
+                None => batches,
+            }
         } else {
             // If there is no cache, it means that it was removed, in which case, we just return
             // an empty set of record batches.
@@ -73,6 +91,10 @@ impl TableProvider for LastCacheFunctionProvider {
     }
 }
 
+/// The `last_cache` table function, which is called as `last_cache('table'[, 'cache'])` to read a
+/// cache of a table in the database that is being queried, or as
+/// `last_cache('db', 'table'[, 'cache'])` to read one in another database. With two arguments,
+/// the first is taken to be the table if the database being queried has a table by that name.
 pub struct LastCacheFunction {
     db_id: DbId,
     provider: Arc<LastCacheProvider>,
@@ -86,36 +108,48 @@ impl LastCacheFunction {
 
 impl TableFunctionImpl for LastCacheFunction {
     fn call(&self, args: &[Expr]) -> Result<Arc<dyn TableProvider>> {
-        let Some(Expr::Literal(ScalarValue::Utf8(Some(table_name)))) = args.first() else {
-            return plan_err!("first argument must be the table name as a string");
-        };
+        let mut names = Vec::with_capacity(args.len());
+        for arg in args {
+            let Expr::Literal(ScalarValue::Utf8(Some(name))) = arg else {
+                return plan_err!(
+                    "arguments must be the database, table, and cache names as strings"
+                );
+            };
+            names.push(name.as_str());
+        }
 
-        let cache_name = match args.get(1) {
-            Some(Expr::Literal(ScalarValue::Utf8(Some(name)))) => Some(name),
-            Some(_) => {
-                return plan_err!("second argument, if passed, must be the cache name as a string")
+        let catalog = &self.provider.catalog;
+        let query_db_schema = catalog.db_schema_by_id(&self.db_id).expect("db exists");
+        let (db_schema, table_name, cache_name) = match names.as_slice() {
+            [table_name] => (query_db_schema, *table_name, None),
+            [table_name, cache_name] if query_db_schema.table_definition(*table_name).is_some() => {
+                (query_db_schema, *table_name, Some(*cache_name))
+            }
+            [db_name, table_name] | [db_name, table_name, _] => {
+                let Some(db_schema) = catalog.db_schema(db_name) else {
+                    return plan_err!("provided database name is invalid");
+                };
+                (db_schema, *table_name, names.get(2).copied())
+            }
+            _ => {
+                return plan_err!(
+                    "expected the table name, optionally preceded by the database name and \
+                    followed by the cache name"
+                )
             }
-            None => None,
         };
-        let Some(table_def) = self
-            .provider
-            .catalog
-            .db_schema_by_id(&self.db_id)
-            .expect("db exists")
-            .table_definition(table_name.as_str())
-        else {
+        let Some(table_def) = db_schema.table_definition(table_name) else {
             return plan_err!("provided table name is invalid");
         };
-        let Some((cache_name, schema)) = self.provider.get_cache_name_and_schema(
-            self.db_id,
-            table_def.table_id,
-            cache_name.map(|x| x.as_str()),
-        ) else {
+        let Some((cache_name, schema)) =
+            self.provider
+                .get_cache_name_and_schema(db_schema.id, table_def.table_id, cache_name)
+        else {
             return plan_err!("could not find cache for the given arguments");
         };
 
         Ok(Arc::new(LastCacheFunctionProvider {
-            db_id: self.db_id,
+            db_id: db_schema.id,
             table_def,
             cache_name,
             schema,
@@ -4,33 +4,46 @@
 //! data into parquet files that are persisted to object storage. A snapshot file is written that contains the
 //! metadata of the parquet files that were written in that snapshot.
 
+pub mod backup;
 pub mod chunk;
+pub mod distinct_cache;
 pub mod last_cache;
 pub mod parquet_cache;
 pub mod paths;
 pub mod persister;
 pub mod write_buffer;
 
+use arrow::record_batch::RecordBatch;
 use async_trait::async_trait;
 use data_types::{NamespaceName, TimestampMinMax};
 use datafusion::catalog::Session;
 use datafusion::error::DataFusionError;
 use datafusion::prelude::Expr;
+use distinct_cache::DistinctCacheProvider;
 use influxdb3_catalog::catalog::Catalog;
 use influxdb3_catalog::catalog::CatalogSequenceNumber;
 use influxdb3_id::ParquetFileId;
 use influxdb3_id::TableId;
 use influxdb3_id::{ColumnId, DbId};
-use influxdb3_wal::{LastCacheDefinition, SnapshotSequenceNumber, WalFileSequenceNumber};
+use influxdb3_wal::inspect::WalFileSummary;
+use influxdb3_wal::{
+    CatalogOp, DistinctCacheDefinition, LastCacheAggregate, LastCacheDefinition,
+    LastCacheValueColumnsDef, RetainedWalFiles, SnapshotSequenceNumber, WalAckLevel,
+    WalFileSequenceNumber, WalSubscription,
+};
 use iox_query::QueryChunk;
 use iox_time::Time;
-use last_cache::LastCacheProvider;
+use last_cache::{LastCacheProvider, LastCacheStats};
+use parquet::format::FileMetaData;
+use parquet_cache::ParquetCacheOracle;
+use schema::InfluxFieldType;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
 use std::sync::Arc;
 use std::time::Duration;
 use thiserror::Error;
+use tokio::io::AsyncBufRead;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -45,11 +58,19 @@ pub enum Error {
 
     #[error("persister error: {0}")]
     Persister(#[from] persister::Error),
+
+    #[error(
+        "invalid last cache replay mode: {0}, expected one of write-through, parallel, or cold"
+    )]
+    InvalidLastCacheReplay(String),
+
+    #[error("invalid parquet cache table: {0}, expected <db>.<table> or <db>.*")]
+    InvalidCacheTable(String),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-pub trait WriteBuffer: Bufferer + ChunkContainer + LastCacheManager {}
+pub trait WriteBuffer: Bufferer + ChunkContainer + LastCacheManager + DistinctCacheManager {}
 
 /// The buffer is for buffering data in memory and in the wal before it is persisted as parquet files in storage.
 #[async_trait]
@@ -65,6 +86,29 @@ pub trait Bufferer: Debug + Send + Sync + 'static {
         precision: Precision,
     ) -> write_buffer::Result<BufferedWriteRequest>;
 
+    /// Like [`Bufferer::write_lp`], but with the given [`Durability`], which controls whether the
+    /// write is acknowledged once it is durable in the WAL, or as soon as it has been buffered.
+    async fn write_lp_with_durability(
+        &self,
+        database: NamespaceName<'static>,
+        lp: &str,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+        durability: Durability,
+    ) -> write_buffer::Result<BufferedWriteRequest>;
+
+    /// Writes line protocol to several databases in a single call. Each database's lines are
+    /// validated separately, but all of the data is written to the WAL together, rather than in
+    /// one WAL flush per database. Returns the result of the write to each database, in order.
+    async fn write_lp_multi(
+        &self,
+        writes: Vec<(NamespaceName<'static>, &str)>,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+    ) -> write_buffer::Result<Vec<BufferedWriteRequest>>;
+
     /// Write v3 line protocol
     async fn write_lp_v3(
         &self,
@@ -75,19 +119,112 @@ pub trait Bufferer: Debug + Send + Sync + 'static {
         precision: Precision,
     ) -> write_buffer::Result<BufferedWriteRequest>;
 
+    /// Like [`Bufferer::write_lp_with_durability`], but the line protocol is read from `lp`, and
+    /// validated and buffered in batches as it is read, so that large writes do not need to be
+    /// held in memory in their entirety.
+    ///
+    /// If reading from `lp` fails, or a line is rejected when `accept_partial` is `false`, the
+    /// batches that were read before it have already been written, and remain so.
+    async fn write_lp_stream(
+        &self,
+        database: NamespaceName<'static>,
+        lp: Box<dyn AsyncBufRead + Send + Unpin>,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+        durability: Durability,
+    ) -> write_buffer::Result<BufferedWriteRequest>;
+
+    /// Validates and writes rows for tables using the v1 data model, in the same way as [`Bufferer::write_lp`], but
+    /// without requiring the rows to be serialized as line protocol.
+    async fn write_rows(
+        &self,
+        database: NamespaceName<'static>,
+        rows: Vec<WriteRow>,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+    ) -> write_buffer::Result<BufferedWriteRequest>;
+
+    /// Validates and writes a body of tabular data, in CSV or JSON lines, to the table of
+    /// `mapping`, whose columns it maps to the tags, fields, and time of each row, in the same
+    /// way as [`Bufferer::write_rows`]. Each record, or line, that cannot be mapped to a row is
+    /// rejected, as a line of line protocol that cannot be parsed is.
+    #[allow(clippy::too_many_arguments)]
+    async fn write_tabular(
+        &self,
+        database: NamespaceName<'static>,
+        format: TabularFormat,
+        body: &str,
+        mapping: &ColumnMapping,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+    ) -> write_buffer::Result<BufferedWriteRequest>;
+
+    /// Writes the rows of an Arrow [`RecordBatch`] to the table `table_name`, using the v1 data
+    /// model. Dictionary encoded string columns are written as tags, a nanosecond timestamp column
+    /// named `time` provides the timestamp of each row, and all other columns are written as fields.
+    async fn write_record_batch(
+        &self,
+        database: NamespaceName<'static>,
+        table_name: &str,
+        batch: RecordBatch,
+        ingest_time: Time,
+        accept_partial: bool,
+    ) -> write_buffer::Result<BufferedWriteRequest>;
+
+    /// Validates the line protocol in the same way as [`Bufferer::write_lp`], including checking
+    /// for schema conflicts, but does not write it to the WAL or the buffer, or change the catalog.
+    /// Returns the lines that would be rejected and the schema changes the write would make.
+    async fn validate_lp(
+        &self,
+        database: NamespaceName<'static>,
+        lp: &str,
+        precision: Precision,
+    ) -> write_buffer::Result<WriteValidation>;
+
     /// Returns the database schema provider
     fn catalog(&self) -> Arc<Catalog>;
 
     /// Returns the parquet files for a given database and table
     fn parquet_files(&self, db_id: DbId, table_id: TableId) -> Vec<ParquetFile>;
 
+    /// Returns the in-memory parquet cache, if it is enabled
+    fn parquet_cache(&self) -> Option<Arc<dyn ParquetCacheOracle>>;
+
     /// A channel to watch for when new persisted snapshots are created
     fn watch_persisted_snapshots(&self) -> tokio::sync::watch::Receiver<Option<PersistedSnapshot>>;
+
+    /// Loads the entries of the audit log, which records every change made to the catalog, in the
+    /// order that they were made
+    async fn audit_log(&self) -> write_buffer::Result<Vec<write_buffer::audit::AuditLogEntry>>;
+
+    /// Returns the range of WAL files that have been snapshot, but are retained by the WAL
+    /// retention policy and are pending deletion
+    async fn retained_wal_files(&self) -> write_buffer::Result<Option<RetainedWalFiles>>;
+
+    /// Summarizes the WAL files in object storage that have not been snapshot yet, in the order
+    /// that they were written
+    async fn wal_files(&self) -> write_buffer::Result<Vec<WalFileSummary>>;
+
+    /// Subscribe to the contents of the WAL files as they are flushed, e.g., for change data
+    /// capture or replication, catching up on the WAL files from `from` first, if it is set
+    async fn subscribe_wal(
+        &self,
+        from: Option<WalFileSequenceNumber>,
+    ) -> write_buffer::Result<WalSubscription>;
 }
 
 /// ChunkContainer is used by the query engine to get chunks for a given table. Chunks will generally be in the
 /// `Bufferer` for those in memory from buffered writes or the `Persister` for parquet files that have been persisted.
+#[async_trait]
 pub trait ChunkContainer: Debug + Send + Sync + 'static {
+    /// Load the metadata of the persisted files that a query with `filters` may need, which is
+    /// not loaded on startup for the oldest snapshots. This must be called before
+    /// [`ChunkContainer::get_table_chunks`], which cannot wait on object storage.
+    async fn load_persisted_files(&self, filters: &[Expr]) -> Result<(), DataFusionError>;
+
     fn get_table_chunks(
         &self,
         database_name: &str,
@@ -96,6 +233,11 @@ pub trait ChunkContainer: Debug + Send + Sync + 'static {
         projection: Option<&Vec<usize>>,
         ctx: &dyn Session,
     ) -> Result<Vec<Arc<dyn QueryChunk>>, DataFusionError>;
+
+    /// The time before which the data of a database has expired under its retention period, if
+    /// it has one. The chunks of a table may still have rows from before it, in files and buffer
+    /// chunks that also have newer rows, so queries must filter them out.
+    fn retention_cutoff_ns(&self, database_name: &str) -> Option<i64>;
 }
 
 /// [`LastCacheManager`] is used to manage ineraction with a last-n-value cache provider. This enables
@@ -105,6 +247,18 @@ pub trait ChunkContainer: Debug + Send + Sync + 'static {
 pub trait LastCacheManager: Debug + Send + Sync + 'static {
     /// Get a reference to the last cache provider
     fn last_cache_provider(&self) -> Arc<LastCacheProvider>;
+    /// List the definitions of the last-n-value caches in a database
+    fn list_last_caches(&self, db_id: DbId) -> Vec<LastCacheDefinition>;
+    /// Get statistics about the values held in a last-n-value cache, and how it has been queried,
+    /// or `None` if the cache does not exist
+    fn last_cache_stats(
+        &self,
+        db_id: DbId,
+        tbl_id: TableId,
+        cache_name: &str,
+    ) -> Option<LastCacheStats>;
+    /// An estimate of the memory used by the values in all of the last-n-value caches, in bytes
+    fn last_cache_memory_bytes(&self) -> usize;
     /// Create a new last-n-value cache
     ///
     /// This should handle updating the catalog with the cache information, so that it will be
@@ -120,6 +274,37 @@ pub trait LastCacheManager: Debug + Send + Sync + 'static {
         key_columns: Option<Vec<(ColumnId, Arc<str>)>>,
         value_columns: Option<Vec<(ColumnId, Arc<str>)>>,
     ) -> Result<Option<LastCacheDefinition>, write_buffer::Error>;
+    /// Create a new last-n-value cache whose key columns are the tags of the table, including
+    /// the tags that are added to the table later, which the values already in the cache are
+    /// re-keyed by. Series that do not have a tag are cached under an empty value for it.
+    ///
+    /// This should handle updating the catalog with the cache information, so that it will be
+    /// preserved on server restarts.
+    async fn create_last_cache_with_auto_key_columns(
+        &self,
+        db_id: DbId,
+        tbl_id: TableId,
+        cache_name: Option<&str>,
+        count: Option<usize>,
+        ttl: Option<Duration>,
+        value_columns: Option<Vec<(ColumnId, Arc<str>)>>,
+    ) -> Result<Option<LastCacheDefinition>, write_buffer::Error>;
+    /// Change the count, TTL, value columns, or aggregates of an existing last-n-value cache,
+    /// without losing the values that are in it. Arguments that are `None` are left as they are.
+    ///
+    /// This should handle updating the cache's information in the catalog as well. Returns the
+    /// new definition of the cache, or `None` if it was not changed.
+    #[allow(clippy::too_many_arguments)]
+    async fn update_last_cache(
+        &self,
+        db_id: DbId,
+        tbl_id: TableId,
+        cache_name: &str,
+        count: Option<usize>,
+        ttl: Option<Duration>,
+        value_columns: Option<LastCacheValueColumnsDef>,
+        aggregates: Option<Vec<LastCacheAggregate>>,
+    ) -> Result<Option<LastCacheDefinition>, write_buffer::Error>;
     /// Delete a last-n-value cache
     ///
     /// This should handle removal of the cache's information from the catalog as well
@@ -131,15 +316,162 @@ pub trait LastCacheManager: Debug + Send + Sync + 'static {
     ) -> Result<(), write_buffer::Error>;
 }
 
+/// [`DistinctCacheManager`] is used to manage the distinct value caches in the underlying
+/// [`DistinctCacheProvider`], which hold the distinct values of tag or string field columns. As
+/// with the last-n-value caches, the caches must also be maintained in the catalog.
+#[async_trait::async_trait]
+pub trait DistinctCacheManager: Debug + Send + Sync + 'static {
+    /// Get a reference to the distinct value cache provider
+    fn distinct_cache_provider(&self) -> Arc<DistinctCacheProvider>;
+    /// Create a new distinct value cache of the given columns, which must be tag or string field
+    /// columns
+    ///
+    /// This should handle updating the catalog with the cache information, so that it will be
+    /// preserved on server restarts. Returns `None` if the parameters match those of an existing
+    /// cache.
+    async fn create_distinct_cache(
+        &self,
+        db_id: DbId,
+        tbl_id: TableId,
+        cache_name: Option<&str>,
+        columns: Vec<ColumnId>,
+        max_cardinality: Option<usize>,
+        ttl: Option<Duration>,
+    ) -> Result<Option<DistinctCacheDefinition>, write_buffer::Error>;
+    /// Delete a distinct value cache
+    ///
+    /// This should handle removal of the cache's information from the catalog as well
+    async fn delete_distinct_cache(
+        &self,
+        db_id: DbId,
+        tbl_id: TableId,
+        cache_name: &str,
+    ) -> Result<(), write_buffer::Error>;
+}
+
 /// A single write request can have many lines in it. A writer can request to accept all lines that are valid, while
 /// returning an error for any invalid lines. This is the error information for a single invalid line.
 #[derive(Debug, Serialize)]
 pub struct WriteLineError {
     pub original_line: String,
     pub line_number: usize,
+    pub error_category: WriteLineErrorCategory,
     pub error_message: String,
 }
 
+/// The category of a [`WriteLineError`], which allows clients to determine why a line was rejected
+/// without inspecting the error message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteLineErrorCategory {
+    /// The line could not be parsed
+    Parse,
+    /// The type of a field conflicts with the type of that column in the table's schema
+    TypeConflict,
+    /// The line does not conform to the table's schema, e.g., it has the wrong series key or uses a
+    /// different data model
+    SchemaConflict,
+    /// Writing the line would exceed a schema limit, e.g., the number of columns in a table
+    SchemaLimit,
+    /// The name of a new column is not allowed by the schema limits of the database
+    InvalidColumnName,
+    /// The line would change the schema of a database whose schema mode does not allow it
+    SchemaLocked,
+    /// Writing the line would exceed a cardinality limit, e.g., the number of series in a table
+    CardinalityLimit,
+    /// The timestamp of the line cannot be represented in nanoseconds
+    TimestampOutOfRange,
+}
+
+impl WriteLineErrorCategory {
+    /// The name of the category, as it is serialized
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Parse => "parse",
+            Self::TypeConflict => "type_conflict",
+            Self::SchemaConflict => "schema_conflict",
+            Self::SchemaLimit => "schema_limit",
+            Self::InvalidColumnName => "invalid_column_name",
+            Self::SchemaLocked => "schema_locked",
+            Self::CardinalityLimit => "cardinality_limit",
+            Self::TimestampOutOfRange => "timestamp_out_of_range",
+        }
+    }
+}
+
+/// A row of data for a table using the v1 data model. This allows writes to be made without first serializing them
+/// to line protocol, e.g., when ingesting from another protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteRow {
+    pub table_name: String,
+    pub tags: Vec<(String, String)>,
+    pub fields: Vec<(String, WriteFieldValue)>,
+    /// The timestamp of the row, in the precision of the write. If not provided, the ingest time is used.
+    pub timestamp: Option<i64>,
+}
+
+/// The value of a field in a [`WriteRow`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteFieldValue {
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    String(String),
+    Boolean(bool),
+}
+
+/// The format of a write of tabular data, whose columns are mapped to the rows of a table with a
+/// [`ColumnMapping`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TabularFormat {
+    /// CSV, with a header row that names the columns
+    Csv,
+    /// JSON lines, or NDJSON, with an object on each line, whose keys are the columns
+    JsonLines,
+}
+
+/// How the columns of a write of tabular data are mapped to the tags, fields, and time of the
+/// rows of a table, using the v1 data model. Columns that are not tags, the time, or ignored are
+/// fields, and empty or null values are left out of their row.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColumnMapping {
+    pub table_name: String,
+    /// The columns whose values are written as tags
+    pub tags: Vec<String>,
+    /// The column with the timestamp of each row, as an integer in the precision of the write.
+    /// Rows without a timestamp, or all rows if this is `None`, have the ingest time.
+    pub time_column: Option<String>,
+    /// The types of fields, which are otherwise inferred from each value: numbers are floats,
+    /// `true` and `false` are booleans, and anything else, including JSON strings, is a string
+    pub field_types: BTreeMap<String, InfluxFieldType>,
+    /// The columns that are not written
+    pub ignore: Vec<String>,
+}
+
+impl std::fmt::Display for WriteRow {
+    /// Displays the row as line protocol, which is used when reporting errors for the row
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.table_name)?;
+        for (key, value) in &self.tags {
+            write!(f, ",{key}={value}")?;
+        }
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            let sep = if i == 0 { ' ' } else { ',' };
+            match value {
+                WriteFieldValue::I64(v) => write!(f, "{sep}{key}={v}i")?,
+                WriteFieldValue::U64(v) => write!(f, "{sep}{key}={v}u")?,
+                WriteFieldValue::F64(v) => write!(f, "{sep}{key}={v}")?,
+                WriteFieldValue::String(v) => write!(f, "{sep}{key}={v:?}")?,
+                WriteFieldValue::Boolean(v) => write!(f, "{sep}{key}={v}")?,
+            }
+        }
+        if let Some(timestamp) = self.timestamp {
+            write!(f, " {timestamp}")?;
+        }
+        Ok(())
+    }
+}
+
 /// A write that has been validated against the catalog schema, written to the WAL (if configured), and buffered in
 /// memory. This is the summary information for the write along with any errors that were encountered.
 #[derive(Debug)]
@@ -149,6 +481,42 @@ pub struct BufferedWriteRequest {
     pub line_count: usize,
     pub field_count: usize,
     pub index_count: usize,
+    /// The level of durability that the write had been acknowledged at by the WAL
+    pub ack_level: WalAckLevel,
+}
+
+impl BufferedWriteRequest {
+    /// The number of invalid lines in the write for each error category
+    pub fn invalid_line_counts(&self) -> BTreeMap<WriteLineErrorCategory, usize> {
+        count_by_category(&self.invalid_lines)
+    }
+}
+
+fn count_by_category(lines: &[WriteLineError]) -> BTreeMap<WriteLineErrorCategory, usize> {
+    let mut counts = BTreeMap::new();
+    for line in lines {
+        *counts.entry(line.error_category).or_default() += 1;
+    }
+    counts
+}
+
+/// The result of validating a write without applying it, which contains the lines that would be
+/// rejected and the changes that the write would make to the schema of the database.
+#[derive(Debug)]
+pub struct WriteValidation {
+    pub db_name: NamespaceName<'static>,
+    pub invalid_lines: Vec<WriteLineError>,
+    pub line_count: usize,
+    pub field_count: usize,
+    pub index_count: usize,
+    pub catalog_ops: Vec<CatalogOp>,
+}
+
+impl WriteValidation {
+    /// The number of lines that would be rejected for each error category
+    pub fn invalid_line_counts(&self) -> BTreeMap<WriteLineErrorCategory, usize> {
+        count_by_category(&self.invalid_lines)
+    }
 }
 
 /// The collection of Parquet files that were persisted in a snapshot
@@ -235,6 +603,65 @@ pub struct DatabaseTables {
     pub tables: hashbrown::HashMap<TableId, Vec<ParquetFile>>,
 }
 
+/// All of the persisted files as of a snapshot, which is written periodically, so that they are
+/// loaded from it, and the snapshots taken since, on startup, rather than from every snapshot
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct PersistedFilesIndex {
+    /// The most recent snapshot that the files are from, without its own files
+    pub snapshot: PersistedSnapshot,
+    /// The persisted files of each database and table
+    pub databases: HashMap<DbId, DatabaseTables>,
+}
+
+impl PersistedFilesIndex {
+    /// A snapshot that has all of the files in the index, and the sequence numbers and next ids
+    /// of the snapshot that it was written as of
+    pub fn into_snapshot(self) -> PersistedSnapshot {
+        let mut snapshot = PersistedSnapshot {
+            parquet_size_bytes: 0,
+            row_count: 0,
+            min_time: i64::MAX,
+            max_time: i64::MIN,
+            databases: self.databases,
+            ..self.snapshot
+        };
+        for file in snapshot
+            .databases
+            .values()
+            .flat_map(|db| db.tables.values().flatten())
+        {
+            snapshot.parquet_size_bytes += file.size_bytes;
+            snapshot.row_count += file.row_count;
+            snapshot.min_time = snapshot.min_time.min(file.min_time);
+            snapshot.max_time = snapshot.max_time.max(file.max_time);
+        }
+        snapshot
+    }
+}
+
+/// The gen2 files written by a run of the compactor, each of which replaced the gen1 files, or
+/// older gen2 files, of a table in one window of time. Generations are applied to the persisted
+/// files, in order, after the snapshots are loaded on startup.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct CompactionGeneration {
+    /// The host identifier that persisted this generation
+    pub host_id: String,
+    /// The generation number, which is one higher than that of the generation before it
+    pub generation: u64,
+    /// The files that were compacted in this generation
+    pub compactions: Vec<Compaction>,
+}
+
+/// A gen2 file, and the files of the same table that were merged into it
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct Compaction {
+    pub db_id: DbId,
+    pub table_id: TableId,
+    pub file: ParquetFile,
+    /// The files that the gen2 file replaced, which are no longer queried
+    pub replaced: Vec<ParquetFile>,
+}
+
 /// The summary data for a persisted parquet file in a snapshot.
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct ParquetFile {
@@ -245,6 +672,58 @@ pub struct ParquetFile {
     pub chunk_time: i64,
     pub min_time: i64,
     pub max_time: i64,
+    /// The indexes that were written to the file, which are empty for files persisted before
+    /// they were written
+    #[serde(default)]
+    pub indexes: ParquetFileIndexes,
+    /// The minimum and maximum values of the tag columns in the file, which are empty for files
+    /// persisted before they were recorded
+    #[serde(default)]
+    pub column_ranges: BTreeMap<String, ColumnValueRange>,
+    /// The columns that the rows of the file are sorted by, which are empty for files persisted
+    /// before they were recorded
+    #[serde(default)]
+    pub sort_key: Vec<String>,
+}
+
+/// The minimum and maximum values of a column in a persisted parquet file
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct ColumnValueRange {
+    pub min: String,
+    pub max: String,
+}
+
+/// The indexes in a persisted parquet file that let queries skip the row groups, and pages, that
+/// cannot match their filters, beyond what the row group statistics allow
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
+pub struct ParquetFileIndexes {
+    /// Whether the file has a page index, with the statistics of each data page
+    pub page_index: bool,
+    /// The columns that the file has bloom filters for
+    pub bloom_filter_columns: Vec<String>,
+}
+
+impl ParquetFileIndexes {
+    /// The indexes that were written to a parquet file, from its metadata
+    pub fn from_meta(meta: &FileMetaData) -> Self {
+        let columns = || {
+            meta.row_groups
+                .iter()
+                .flat_map(|row_group| &row_group.columns)
+        };
+        let page_index = columns().any(|column| column.column_index_offset.is_some());
+        let mut bloom_filter_columns = columns()
+            .filter_map(|column| column.meta_data.as_ref())
+            .filter(|meta| meta.bloom_filter_offset.is_some())
+            .map(|meta| meta.path_in_schema.join("."))
+            .collect::<Vec<_>>();
+        bloom_filter_columns.sort();
+        bloom_filter_columns.dedup();
+        Self {
+            page_index,
+            bloom_filter_columns,
+        }
+    }
 }
 
 impl ParquetFile {
@@ -284,6 +763,57 @@ impl From<iox_http::write::Precision> for Precision {
     }
 }
 
+/// When a write is acknowledged
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability {
+    /// Once the WAL has been flushed to object storage, at which point the write is durable and
+    /// queryable
+    #[default]
+    WalSync,
+    /// As soon as the write has been validated and buffered in the WAL. It becomes durable and
+    /// queryable on the next WAL flush, and is lost if the server stops before then.
+    BufferedAck,
+}
+
+/// How the last caches are populated with the writes that are replayed from the WAL on startup
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LastCacheReplay {
+    /// Each replayed WAL file is written to the last caches as it is buffered, like new writes
+    #[default]
+    WriteThrough,
+    /// The replayed writes are held back from the last caches until the replay is finished, and
+    /// are then written to them with the tables handled in parallel
+    Parallel,
+    /// The replayed writes are not written to the last caches, which start out with only the
+    /// values that were restored from their last snapshot, for the fastest startup
+    Cold,
+}
+
+impl std::str::FromStr for LastCacheReplay {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "write-through" => Ok(Self::WriteThrough),
+            "parallel" => Ok(Self::Parallel),
+            "cold" => Ok(Self::Cold),
+            _ => Err(Error::InvalidLastCacheReplay(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for LastCacheReplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WriteThrough => write!(f, "write-through"),
+            Self::Parallel => write!(f, "parallel"),
+            Self::Cold => write!(f, "cold"),
+        }
+    }
+}
+
 /// Guess precision based off of a given timestamp.
 // Note that this will fail in June 2128, but that's not our problem
 pub(crate) fn guess_precision(timestamp: i64) -> Precision {
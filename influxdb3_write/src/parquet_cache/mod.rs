@@ -3,6 +3,7 @@ use std::{
     collections::BinaryHeap,
     fmt::Debug,
     ops::Range,
+    str::FromStr,
     sync::{
         atomic::{AtomicI64, AtomicUsize, Ordering},
         Arc,
@@ -12,24 +13,29 @@ use std::{
 
 use anyhow::bail;
 use async_trait::async_trait;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use dashmap::{DashMap, Entry};
 use futures::{
     future::{BoxFuture, Shared},
     stream::BoxStream,
     FutureExt, StreamExt, TryStreamExt,
 };
+use influxdb3_id::DbId;
 use iox_time::TimeProvider;
+use metric::{Registry, U64Counter, U64Gauge};
 use object_store::{
     path::Path, Error, GetOptions, GetResult, GetResultPayload, ListResult, MultipartUpload,
     ObjectMeta, ObjectStore, PutMultipartOpts, PutOptions, PutPayload, PutResult,
 };
-use observability_deps::tracing::{error, info, warn};
+use observability_deps::tracing::{debug, error, info, warn};
+use serde::Serialize;
 use tokio::sync::{
     mpsc::{channel, Receiver, Sender},
     oneshot, watch,
 };
 
+use crate::paths::ParquetFilePath;
+
 /// Shared future type for cache values that are being fetched
 type SharedCacheValueFuture = Shared<BoxFuture<'static, Result<Arc<CacheValue>, DynError>>>;
 
@@ -43,6 +49,10 @@ type DynError = Arc<dyn std::error::Error + Send + Sync>;
 pub struct CacheRequest {
     path: Path,
     notifier: oneshot::Sender<()>,
+    /// The size of the object in bytes, if it is known before it is fetched
+    size_bytes: Option<usize>,
+    /// The nano-second timestamp of the newest data in the parquet file, if it is known
+    max_time: Option<i64>,
 }
 
 impl CacheRequest {
@@ -50,7 +60,23 @@ impl CacheRequest {
     /// the cache request has been fulfilled.
     pub fn create(path: Path) -> (Self, oneshot::Receiver<()>) {
         let (notifier, receiver) = oneshot::channel();
-        (Self { path, notifier }, receiver)
+        (
+            Self {
+                path,
+                notifier,
+                size_bytes: None,
+                max_time: None,
+            },
+            receiver,
+        )
+    }
+
+    /// Provide the size of the parquet file and the time of the newest data in it, which lets
+    /// the [`CacheAdmissionPolicy`] decide if it is cached without fetching it first
+    pub fn with_file_info(mut self, size_bytes: usize, max_time: i64) -> Self {
+        self.size_bytes = Some(size_bytes);
+        self.max_time = Some(max_time);
+        self
     }
 
     /// Helper to get path used to create this request
@@ -66,13 +92,142 @@ pub trait ParquetCacheOracle: Send + Sync + Debug {
 
     // Get a receiver that is notified when a prune takes place and how much memory was freed
     fn prune_notifier(&self) -> watch::Receiver<usize>;
+
+    /// Pin the objects at the given paths, so that they are not pruned from the cache until the
+    /// returned [`CachePin`] is dropped
+    ///
+    /// Paths do not need to be in the cache already, e.g., a path that is pinned while it is
+    /// being fetched will not be pruned once it has been fetched.
+    fn pin(&self, paths: Vec<Path>) -> CachePin;
+
+    /// Get the current memory usage of the cache
+    fn usage(&self) -> ParquetCacheUsage;
+
+    /// Remove the object at the given path from the cache, e.g., once it has been deleted
+    fn invalidate(&self, path: &Path);
+
+    /// Remove all of the parquet files of the database with the given id from the cache
+    fn invalidate_db(&self, db_id: DbId);
+
+    /// Remove everything from the cache
+    fn clear(&self);
+}
+
+/// The memory usage of a parquet cache at a point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ParquetCacheUsage {
+    /// The amount of memory being used by the cache in bytes
+    pub used_bytes: usize,
+    /// The maximum amount of memory the cache should occupy in bytes
+    pub capacity_bytes: usize,
+    /// The number of objects in the cache, including those still being fetched
+    pub entries: usize,
+    /// The number of objects that only have some byte ranges in the cache
+    pub range_entries: usize,
+    /// The number of paths that are pinned, whether they are in the cache or not
+    pub pinned_paths: usize,
+}
+
+/// Keeps a set of paths pinned in the cache for as long as it is held, e.g., for the duration of
+/// a query that reads the files at those paths
+#[derive(Debug)]
+pub struct CachePin {
+    cache: Arc<Cache>,
+    paths: Vec<Path>,
+}
+
+impl Drop for CachePin {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            self.cache.unpin(path);
+        }
+    }
+}
+
+/// Rules for which of the objects that are registered with the [`ParquetCacheOracle`] are
+/// cached, e.g., so that a large file of historical data does not push the files of recent data
+/// out of the cache
+#[derive(Debug, Clone, Default)]
+pub struct CacheAdmissionPolicy {
+    /// Objects larger than this many bytes are not cached
+    pub max_file_size_bytes: Option<usize>,
+    /// Parquet files that only contain data older than this are not cached
+    pub max_file_age: Option<Duration>,
+    /// If not empty, only the parquet files of these tables are cached
+    pub allow_tables: Vec<CacheTable>,
+    /// The parquet files of these tables are not cached
+    pub deny_tables: Vec<CacheTable>,
+}
+
+impl CacheAdmissionPolicy {
+    /// Check if the object of a request is cached, where `now` is the current nano-second time
+    ///
+    /// Size and age are only checked when they are provided with the request, the size is also
+    /// checked once the object has been fetched.
+    fn admits(&self, request: &CacheRequest, now: i64) -> bool {
+        if let (Some(max_size), Some(size)) = (self.max_file_size_bytes, request.size_bytes) {
+            if size > max_size {
+                return false;
+            }
+        }
+        if let (Some(max_age), Some(max_time)) = (self.max_file_age, request.max_time) {
+            let max_age = i64::try_from(max_age.as_nanos()).unwrap_or(i64::MAX);
+            if max_time < now.saturating_sub(max_age) {
+                return false;
+            }
+        }
+        if self.allow_tables.is_empty() && self.deny_tables.is_empty() {
+            return true;
+        }
+        let Some((db, table)) = ParquetFilePath::db_and_table_names(&request.path) else {
+            // not a parquet file of a table, so there is nothing to match:
+            return self.allow_tables.is_empty();
+        };
+        let matches = |rule: &CacheTable| rule.matches(&db, &table);
+        !self.deny_tables.iter().any(matches)
+            && (self.allow_tables.is_empty() || self.allow_tables.iter().any(matches))
+    }
+}
+
+/// A table, or all of the tables in a database, in a [`CacheAdmissionPolicy`]
+///
+/// This is parsed from `<db>.<table>`, or `<db>.*` for all of the tables in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheTable {
+    db: String,
+    /// The table, or `None` for all tables
+    table: Option<String>,
+}
+
+impl CacheTable {
+    fn matches(&self, db: &str, table: &str) -> bool {
+        self.db == db && self.table.as_ref().is_none_or(|t| t == table)
+    }
+}
+
+impl FromStr for CacheTable {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // database names cannot contain a '.', so the table is everything after the first one:
+        match s.split_once('.') {
+            Some((db, table)) if !db.is_empty() && !table.is_empty() => Ok(Self {
+                db: db.to_string(),
+                table: (table != "*").then(|| table.to_string()),
+            }),
+            _ => Err(crate::Error::InvalidCacheTable(s.to_string())),
+        }
+    }
 }
 
 /// Concrete implementation of the [`ParquetCacheOracle`]
 ///
-/// This implementation sends all requests registered to be cached.
+/// This implementation sends the requests registered to be cached that are admitted by its
+/// [`CacheAdmissionPolicy`].
 #[derive(Debug, Clone)]
 pub struct MemCacheOracle {
+    cache: Arc<Cache>,
+    admission_policy: Arc<CacheAdmissionPolicy>,
     cache_request_tx: Sender<CacheRequest>,
     prune_notifier_tx: watch::Sender<usize>,
 }
@@ -86,12 +241,24 @@ impl MemCacheOracle {
     /// This spawns two background tasks:
     /// * one to handle registered [`CacheRequest`]s
     /// * one to prune deleted and un-needed cache entries on an interval
-    fn new(mem_cached_store: Arc<MemCachedObjectStore>, prune_interval: Duration) -> Self {
+    fn new(
+        mem_cached_store: Arc<MemCachedObjectStore>,
+        prune_interval: Duration,
+        admission_policy: CacheAdmissionPolicy,
+    ) -> Self {
+        let cache = Arc::clone(&mem_cached_store.cache);
+        let admission_policy = Arc::new(admission_policy);
         let (cache_request_tx, cache_request_rx) = channel(CACHE_REQUEST_BUFFER_SIZE);
-        background_cache_request_handler(Arc::clone(&mem_cached_store), cache_request_rx);
+        background_cache_request_handler(
+            Arc::clone(&mem_cached_store),
+            Arc::clone(&admission_policy),
+            cache_request_rx,
+        );
         let (prune_notifier_tx, _prune_notifier_rx) = watch::channel(0);
         background_cache_pruner(mem_cached_store, prune_notifier_tx.clone(), prune_interval);
         Self {
+            cache,
+            admission_policy,
             cache_request_tx,
             prune_notifier_tx,
         }
@@ -100,6 +267,12 @@ impl MemCacheOracle {
 
 impl ParquetCacheOracle for MemCacheOracle {
     fn register(&self, request: CacheRequest) {
+        let now = self.cache.time_provider.now().timestamp_nanos();
+        if !self.admission_policy.admits(&request, now) {
+            debug!(path = %request.path, "cache request not admitted");
+            let _ = request.notifier.send(());
+            return;
+        }
         let tx = self.cache_request_tx.clone();
         tokio::spawn(async move {
             if let Err(error) = tx.send(request).await {
@@ -111,6 +284,33 @@ impl ParquetCacheOracle for MemCacheOracle {
     fn prune_notifier(&self) -> watch::Receiver<usize> {
         self.prune_notifier_tx.subscribe()
     }
+
+    fn pin(&self, paths: Vec<Path>) -> CachePin {
+        for path in &paths {
+            self.cache.pin(path);
+        }
+        CachePin {
+            cache: Arc::clone(&self.cache),
+            paths,
+        }
+    }
+
+    fn usage(&self) -> ParquetCacheUsage {
+        self.cache.usage()
+    }
+
+    fn invalidate(&self, path: &Path) {
+        self.cache.remove(path);
+    }
+
+    fn invalidate_db(&self, db_id: DbId) {
+        self.cache
+            .remove_where(|path| ParquetFilePath::db_id(path) == Some(db_id));
+    }
+
+    fn clear(&self) {
+        self.cache.remove_where(|_| true);
+    }
 }
 
 /// Helper function for creation of a [`MemCachedObjectStore`] and [`MemCacheOracle`]
@@ -121,14 +321,21 @@ pub fn create_cached_obj_store_and_oracle(
     cache_capacity: usize,
     prune_percent: f64,
     prune_interval: Duration,
+    admission_policy: CacheAdmissionPolicy,
+    metric_registry: &Registry,
 ) -> (Arc<dyn ObjectStore>, Arc<dyn ParquetCacheOracle>) {
     let store = Arc::new(MemCachedObjectStore::new(
         object_store,
         cache_capacity,
         time_provider,
         prune_percent,
+        metric_registry,
+    ));
+    let oracle = Arc::new(MemCacheOracle::new(
+        Arc::clone(&store),
+        prune_interval,
+        admission_policy,
     ));
-    let oracle = Arc::new(MemCacheOracle::new(Arc::clone(&store), prune_interval));
     (store, oracle)
 }
 
@@ -143,6 +350,8 @@ pub fn test_cached_obj_store_and_oracle(
         1024 * 1024 * 1024,
         0.1,
         Duration::from_millis(10),
+        Default::default(),
+        &Registry::new(),
     )
 }
 
@@ -241,8 +450,80 @@ impl CacheEntryState {
     }
 }
 
+/// The byte ranges of an object that have been fetched, for objects that are not cached whole
+///
+/// The ranges are sorted by their start and coalesced, so that no two of them overlap or are
+/// adjacent to each other.
+#[derive(Debug)]
+struct RangeEntry {
+    ranges: Vec<(Range<usize>, Bytes)>,
+    /// The nano-second timestamp of when any of the ranges were last hit
+    hit_time: AtomicI64,
+}
+
+impl RangeEntry {
+    fn new(hit_time: i64) -> Self {
+        Self {
+            ranges: vec![],
+            hit_time: AtomicI64::new(hit_time),
+        }
+    }
+
+    /// Get the approximate memory footprint of this entry in bytes
+    fn size(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|(_, data)| data.len())
+            .sum::<usize>()
+            + std::mem::size_of::<AtomicI64>()
+    }
+
+    /// Get the bytes of `range`, if it is within one of the cached ranges
+    fn get(&self, range: &Range<usize>) -> Option<Bytes> {
+        if range.start > range.end {
+            return None;
+        }
+        // the cached range that starts closest before the start of the requested range is the
+        // only one that could contain it:
+        let i = self
+            .ranges
+            .partition_point(|(r, _)| r.start <= range.start)
+            .checked_sub(1)?;
+        let (r, data) = &self.ranges[i];
+        (range.end <= r.end).then(|| data.slice(range.start - r.start..range.end - r.start))
+    }
+
+    /// Add a fetched range, coalescing it with any of the cached ranges that it overlaps or is
+    /// adjacent to
+    fn insert(&mut self, range: Range<usize>, data: Bytes) {
+        if range.start > range.end || data.len() != range.len() {
+            return;
+        }
+        let first = self.ranges.partition_point(|(r, _)| r.end < range.start);
+        let last = self.ranges.partition_point(|(r, _)| r.start <= range.end);
+        if first == last {
+            self.ranges.insert(first, (range, data));
+            return;
+        }
+        let start = range.start.min(self.ranges[first].0.start);
+        let end = range.end.max(self.ranges[last - 1].0.end);
+        let mut coalesced = BytesMut::zeroed(end - start);
+        // objects are immutable, so the bytes where the ranges overlap are the same:
+        let new = (range, data);
+        for (r, data) in self.ranges[first..last].iter().chain([&new]) {
+            coalesced[r.start - start..r.end - start].copy_from_slice(data);
+        }
+        self.ranges
+            .splice(first..last, [(start..end, coalesced.freeze())]);
+    }
+}
+
 /// A cache for storing objects from object storage by their [`Path`]
 ///
+/// Objects are either cached whole, when they are registered with the [`ParquetCacheOracle`], or
+/// as the byte ranges of them that were requested, so that reading the footer of a large file
+/// does not need the whole file to be in memory.
+///
 /// This acts as a Least-Recently-Used (LRU) cache that allows for concurrent reads and writes. See
 /// the [`Cache::prune`] method for implementation of how the cache entries are pruned. Pruning must
 /// be invoked externally, e.g., on an interval.
@@ -256,19 +537,33 @@ struct Cache {
     prune_percent: f64,
     /// The map storing cache entries
     map: DashMap<Path, CacheEntry>,
+    /// The map storing the byte ranges of objects that are not in `map`
+    ranges: DashMap<Path, RangeEntry>,
+    /// The number of [`CachePin`]s held for each pinned path, pinned paths are not pruned
+    pinned: DashMap<Path, usize>,
     /// Provides timestamps for updating the hit time of each cache entry
     time_provider: Arc<dyn TimeProvider>,
+    /// The hits, misses, evictions, and memory usage of the cache
+    metrics: CacheMetrics,
 }
 
 impl Cache {
     /// Create a new cache with a given capacity and prune percent
-    fn new(capacity: usize, prune_percent: f64, time_provider: Arc<dyn TimeProvider>) -> Self {
+    fn new(
+        capacity: usize,
+        prune_percent: f64,
+        time_provider: Arc<dyn TimeProvider>,
+        metric_registry: &Registry,
+    ) -> Self {
         Self {
             capacity,
             used: AtomicUsize::new(0),
             prune_percent,
             map: DashMap::new(),
+            ranges: DashMap::new(),
+            pinned: DashMap::new(),
             time_provider,
+            metrics: CacheMetrics::new(metric_registry),
         }
     }
 
@@ -286,6 +581,59 @@ impl Cache {
         Some(entry.state.clone())
     }
 
+    /// Get the given byte ranges of an object from the ranges of it in the cache, with `None` for
+    /// each range that is not cached
+    ///
+    /// This updates the hit time of the entry if any of the ranges were cached
+    fn get_ranges(&self, path: &Path, ranges: &[Range<usize>]) -> Vec<Option<Bytes>> {
+        let Some(entry) = self.ranges.get(path) else {
+            return vec![None; ranges.len()];
+        };
+        let cached = ranges.iter().map(|r| entry.get(r)).collect::<Vec<_>>();
+        if cached.iter().any(Option::is_some) {
+            entry
+                .hit_time
+                .store(self.time_provider.now().timestamp_nanos(), Ordering::SeqCst);
+        }
+        cached
+    }
+
+    /// Add byte ranges of an object that were fetched from the object store
+    ///
+    /// This is a no-op for objects that are being fetched, or were fetched, whole
+    fn insert_ranges(&self, path: &Path, ranges: impl IntoIterator<Item = (Range<usize>, Bytes)>) {
+        if self.map.contains_key(path) {
+            return;
+        }
+        let now = self.time_provider.now().timestamp_nanos();
+        let mut entry = self
+            .ranges
+            .entry(path.clone())
+            .or_insert_with(|| RangeEntry::new(now));
+        let before = entry.size();
+        for (range, data) in ranges {
+            if range.len() <= self.capacity {
+                entry.insert(range, data);
+            }
+        }
+        entry.hit_time.store(now, Ordering::SeqCst);
+        let after = entry.size();
+        drop(entry);
+        if after > before {
+            self.used.fetch_add(after - before, Ordering::SeqCst);
+        } else {
+            self.used.fetch_sub(before - after, Ordering::SeqCst);
+        }
+    }
+
+    /// Remove the cached byte ranges of an object, as well as their size from the used capacity
+    fn remove_ranges(&self, path: &Path) {
+        let Some((_, entry)) = self.ranges.remove(path) else {
+            return;
+        };
+        self.used.fetch_sub(entry.size(), Ordering::SeqCst);
+    }
+
     /// Check if an entry in the cache is in process of being fetched or if it was already fetched
     /// successfully
     ///
@@ -321,46 +669,94 @@ impl Cache {
                 entry
                     .hit_time
                     .store(self.time_provider.now().timestamp_nanos(), Ordering::SeqCst);
-                // TODO(trevor): what if size is greater than cache capacity?
                 let additional = entry.size();
                 self.used.fetch_add(additional, Ordering::SeqCst);
-                Ok(())
             }
             Entry::Vacant(_) => bail!("attempted to set success state on an empty cache entry"),
         }
+        // the whole object is cached now, so its ranges are not needed:
+        self.remove_ranges(path);
+        Ok(())
     }
 
     /// Remove an entry from the cache, as well as its associated size from the used capacity
     fn remove(&self, path: &Path) {
+        self.remove_ranges(path);
         let Some((_, entry)) = self.map.remove(path) else {
             return;
         };
         self.used.fetch_sub(entry.state.size(), Ordering::SeqCst);
     }
 
+    /// Remove the entries, and byte ranges, of all of the paths that match `predicate`, as well
+    /// as their size from the used capacity
+    fn remove_where(&self, predicate: impl Fn(&Path) -> bool) {
+        self.map.retain(|path, entry| {
+            if !predicate(path) {
+                return true;
+            }
+            self.used.fetch_sub(entry.state.size(), Ordering::SeqCst);
+            false
+        });
+        self.ranges.retain(|path, entry| {
+            if !predicate(path) {
+                return true;
+            }
+            self.used.fetch_sub(entry.size(), Ordering::SeqCst);
+            false
+        });
+    }
+
+    /// Pin a path so that its entry is not pruned
+    fn pin(&self, path: &Path) {
+        *self.pinned.entry(path.clone()).or_default() += 1;
+    }
+
+    /// Release one pin on a path, the entry can be pruned again once all of its pins are released
+    fn unpin(&self, path: &Path) {
+        self.pinned.remove_if_mut(path, |_, count| {
+            *count = count.saturating_sub(1);
+            *count == 0
+        });
+    }
+
+    /// Get the current memory usage of the cache
+    fn usage(&self) -> ParquetCacheUsage {
+        ParquetCacheUsage {
+            used_bytes: self.used.load(Ordering::SeqCst),
+            capacity_bytes: self.capacity,
+            entries: self.map.len(),
+            range_entries: self.ranges.len(),
+            pinned_paths: self.pinned.len(),
+        }
+    }
+
     /// Prune least recently hit entries from the cache
     ///
-    /// This is a no-op if the `used` amount on the cache is not >= its `capacity`
+    /// This is a no-op if the `used` amount on the cache is not >= its `capacity`. Entries whose
+    /// paths are pinned are never pruned.
     fn prune(&self) -> Option<usize> {
         let used = self.used.load(Ordering::SeqCst);
-        let n_to_prune = (self.map.len() as f64 * self.prune_percent).floor() as usize;
+        self.metrics.used_bytes.set(used as u64);
+        let n_to_prune =
+            ((self.map.len() + self.ranges.len()) as f64 * self.prune_percent).floor() as usize;
         if used < self.capacity || n_to_prune == 0 {
             return None;
         }
         // use a BinaryHeap to determine the cut-off time, at which, entries that were
         // last hit before that time will be pruned:
         let mut prune_heap = BinaryHeap::with_capacity(n_to_prune);
-
-        for map_ref in self.map.iter() {
-            let hit_time = map_ref.value().hit_time.load(Ordering::SeqCst);
-            let size = map_ref.value().size();
-            let path = map_ref.key().as_ref();
+        let mut consider = |path: &Path, hit_time: i64, size: usize, ranges: bool| {
+            if self.pinned.contains_key(path) {
+                return;
+            }
             if prune_heap.len() < n_to_prune {
                 // if the heap isn't full yet, throw this item on:
                 prune_heap.push(PruneHeapItem {
                     hit_time,
-                    path_ref: path.into(),
+                    path_ref: path.as_ref().into(),
                     size,
+                    ranges,
                 });
             } else if hit_time < prune_heap.peek().map(|item| item.hit_time).unwrap() {
                 // otherwise, the heap is at its capacity, so only push if the hit_time
@@ -368,27 +764,105 @@ impl Cache {
                 // of the heap to make room)
                 prune_heap.pop();
                 prune_heap.push(PruneHeapItem {
-                    path_ref: path.into(),
+                    path_ref: path.as_ref().into(),
                     hit_time,
                     size,
+                    ranges,
                 });
             }
+        };
+
+        for map_ref in self.map.iter() {
+            let hit_time = map_ref.value().hit_time.load(Ordering::SeqCst);
+            consider(map_ref.key(), hit_time, map_ref.value().size(), false);
+        }
+        for map_ref in self.ranges.iter() {
+            let hit_time = map_ref.value().hit_time.load(Ordering::SeqCst);
+            consider(map_ref.key(), hit_time, map_ref.value().size(), true);
         }
 
+        self.metrics.evictions.inc(prune_heap.len() as u64);
         // track the total size of entries that get freed:
         let mut freed = 0;
         // drop entries with hit times before the cut-off:
         for item in prune_heap {
-            self.map.remove(&Path::from(item.path_ref.as_ref()));
+            let path = Path::from(item.path_ref.as_ref());
+            if item.ranges {
+                self.ranges.remove(&path);
+            } else {
+                self.map.remove(&path);
+            }
             freed += item.size;
         }
         // update used mem size with freed amount:
-        self.used.fetch_sub(freed, Ordering::SeqCst);
+        let used = self
+            .used
+            .fetch_sub(freed, Ordering::SeqCst)
+            .saturating_sub(freed);
+        self.metrics.used_bytes.set(used as u64);
 
         Some(freed)
     }
 }
 
+/// The metrics that the cache records in the metric registry
+#[derive(Debug)]
+struct CacheMetrics {
+    /// Requests that were served from the cache
+    hits: U64Counter,
+    /// Requests that had to be forwarded to the inner object store
+    misses: U64Counter,
+    /// Entries that were pruned from the cache to free up memory
+    evictions: U64Counter,
+    /// The amount of memory being used by the cache, as of the last time it was checked for
+    /// pruning
+    used_bytes: U64Gauge,
+}
+
+impl CacheMetrics {
+    fn new(metric_registry: &Registry) -> Self {
+        let hits = metric_registry
+            .register_metric::<U64Counter>(
+                "influxdb3_parquet_cache_hits",
+                "number of object store requests served from the parquet cache",
+            )
+            .recorder(&[]);
+        let misses = metric_registry
+            .register_metric::<U64Counter>(
+                "influxdb3_parquet_cache_misses",
+                "number of object store requests not served from the parquet cache",
+            )
+            .recorder(&[]);
+        let evictions = metric_registry
+            .register_metric::<U64Counter>(
+                "influxdb3_parquet_cache_evictions",
+                "number of entries pruned from the parquet cache",
+            )
+            .recorder(&[]);
+        let used_bytes = metric_registry
+            .register_metric::<U64Gauge>(
+                "influxdb3_parquet_cache_used_bytes",
+                "memory used by the parquet cache",
+            )
+            .recorder(&[]);
+        Self {
+            hits,
+            misses,
+            evictions,
+            used_bytes,
+        }
+    }
+
+    /// Record a request that was, or was not, served from the cache
+    fn record_lookup(&self, hit: bool) {
+        if hit {
+            self.hits.inc(1);
+        } else {
+            self.misses.inc(1);
+        }
+    }
+}
+
 /// An item that stores what is needed for pruning [`CacheEntry`]s
 #[derive(Debug, Eq)]
 struct PruneHeapItem {
@@ -398,6 +872,8 @@ struct PruneHeapItem {
     hit_time: i64,
     /// Entry size used to calculate the amount of memory freed after a prune
     size: usize,
+    /// Whether the entry holds byte ranges of an object, rather than the whole object
+    ranges: bool,
 }
 
 impl PartialEq for PruneHeapItem {
@@ -436,10 +912,16 @@ impl MemCachedObjectStore {
         memory_capacity: usize,
         time_provider: Arc<dyn TimeProvider>,
         prune_percent: f64,
+        metric_registry: &Registry,
     ) -> Self {
         Self {
             inner,
-            cache: Arc::new(Cache::new(memory_capacity, prune_percent, time_provider)),
+            cache: Arc::new(Cache::new(
+                memory_capacity,
+                prune_percent,
+                time_provider,
+                metric_registry,
+            )),
         }
     }
 }
@@ -455,8 +937,8 @@ impl std::fmt::Display for MemCachedObjectStore {
 /// from the cache if the delete to the object store was successful.
 ///
 /// GET-style methods will first check the cache for the object at the given path, before forwarding
-/// to the inner [`ObjectStore`]. They do not, however, populate the cache after data has been fetched
-/// from the inner store.
+/// to the inner [`ObjectStore`]. They do not, however, populate the cache with whole objects after
+/// data has been fetched from the inner store, only with the byte ranges fetched by `get_ranges`.
 #[async_trait]
 impl ObjectStore for MemCachedObjectStore {
     async fn put(&self, location: &Path, bytes: PutPayload) -> object_store::Result<PutResult> {
@@ -490,7 +972,9 @@ impl ObjectStore for MemCachedObjectStore {
     /// Get an object from the object store. If this object is cached, then it will not make a request
     /// to the inner object store.
     async fn get(&self, location: &Path) -> object_store::Result<GetResult> {
-        if let Some(state) = self.cache.get(location) {
+        let state = self.cache.get(location);
+        self.cache.metrics.record_lookup(state.is_some());
+        if let Some(state) = state {
             let v = state.value().await?;
             Ok(GetResult {
                 payload: GetResultPayload::Stream(
@@ -532,6 +1016,7 @@ impl ObjectStore for MemCachedObjectStore {
         ranges: &[Range<usize>],
     ) -> object_store::Result<Vec<Bytes>> {
         if let Some(state) = self.cache.get(location) {
+            self.cache.metrics.record_lookup(true);
             let v = state.value().await?;
             ranges
                 .iter()
@@ -561,12 +1046,35 @@ impl ObjectStore for MemCachedObjectStore {
                 })
                 .collect()
         } else {
-            self.inner.get_ranges(location, ranges).await
+            // serve what we can from the cached ranges of the object, and fetch the rest, which
+            // is then cached as well:
+            let mut cached = self.cache.get_ranges(location, ranges);
+            let missing = ranges
+                .iter()
+                .zip(&cached)
+                .filter(|(_, bytes)| bytes.is_none())
+                .map(|(range, _)| range.clone())
+                .collect::<Vec<_>>();
+            self.cache.metrics.record_lookup(missing.is_empty());
+            if missing.is_empty() {
+                return Ok(cached.into_iter().flatten().collect());
+            }
+            // the inner store coalesces ranges that are close together into single requests:
+            let fetched = self.inner.get_ranges(location, &missing).await?;
+            self.cache
+                .insert_ranges(location, missing.into_iter().zip(fetched.iter().cloned()));
+            let mut fetched = fetched.into_iter();
+            for bytes in cached.iter_mut().filter(|bytes| bytes.is_none()) {
+                *bytes = fetched.next();
+            }
+            Ok(cached.into_iter().flatten().collect())
         }
     }
 
     async fn head(&self, location: &Path) -> object_store::Result<ObjectMeta> {
-        if let Some(state) = self.cache.get(location) {
+        let state = self.cache.get(location);
+        self.cache.metrics.record_lookup(state.is_some());
+        if let Some(state) = state {
             let v = state.value().await?;
             Ok(v.meta.clone())
         } else {
@@ -632,10 +1140,11 @@ impl ObjectStore for MemCachedObjectStore {
 /// handled in parallel.
 fn background_cache_request_handler(
     mem_store: Arc<MemCachedObjectStore>,
+    admission_policy: Arc<CacheAdmissionPolicy>,
     mut rx: Receiver<CacheRequest>,
 ) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
-        while let Some(CacheRequest { path, notifier }) = rx.recv().await {
+        while let Some(CacheRequest { path, notifier, .. }) = rx.recv().await {
             // We assume that objects on object store are immutable, so we can skip objects that
             // we have already fetched:
             if mem_store.cache.path_already_fetched(&path) {
@@ -655,8 +1164,23 @@ fn background_cache_request_handler(
             // Put a `Fetching` state in the entry to prevent concurrent requests to the same path:
             mem_store.cache.set_fetching(&path, fut.clone());
             let mem_store_captured = Arc::clone(&mem_store);
+            let max_size = admission_policy
+                .max_file_size_bytes
+                .unwrap_or(usize::MAX)
+                .min(mem_store.cache.capacity);
             tokio::spawn(async move {
                 match fut.await {
+                    Ok(value) if value.size() > max_size => {
+                        // an object that does not fit in the cache on its own would have
+                        // everything else pruned to make room for it, so it is not cached:
+                        warn!(
+                            %path,
+                            size = value.size(),
+                            max_size,
+                            "object is larger than the parquet cache admits, not caching it"
+                        );
+                        mem_store_captured.cache.remove(&path);
+                    }
                     Ok(value) => {
                         if let Err(error) = mem_store_captured.cache.set_success(&path, value) {
                             // NOTE(trevor): this would be an error if A) it tried to insert on an already
@@ -706,13 +1230,17 @@ pub(crate) mod tests {
         RequestCountedObjectStore, SynchronizedObjectStore,
     };
     use iox_time::{MockProvider, Time, TimeProvider};
+    use metric::{Attributes, Metric, Registry, U64Counter};
     use object_store::{memory::InMemory, path::Path, ObjectStore, PutPayload};
 
     use pretty_assertions::assert_eq;
     use tokio::sync::Notify;
 
+    use influxdb3_id::DbId;
+
     use crate::parquet_cache::{
-        create_cached_obj_store_and_oracle, test_cached_obj_store_and_oracle, CacheRequest,
+        create_cached_obj_store_and_oracle, test_cached_obj_store_and_oracle, CacheAdmissionPolicy,
+        CacheRequest, CacheTable,
     };
 
     macro_rules! assert_payload_at_equals {
@@ -779,12 +1307,15 @@ pub(crate) mod tests {
         let cache_capacity_bytes = 60;
         let cache_prune_percent = 0.4;
         let cache_prune_interval = Duration::from_millis(10);
+        let registry = Registry::new();
         let (cached_store, oracle) = create_cached_obj_store_and_oracle(
             Arc::clone(&inner_store) as _,
             Arc::clone(&time_provider) as _,
             cache_capacity_bytes,
             cache_prune_percent,
             cache_prune_interval,
+            Default::default(),
+            &registry,
         );
         let mut prune_notifier = oracle.prune_notifier();
         // PUT an entry into the store:
@@ -888,6 +1419,243 @@ pub(crate) mod tests {
         assert_eq!(1, inner_store.total_read_request_count(&path_1));
         assert_eq!(2, inner_store.total_read_request_count(&path_2));
         assert_eq!(1, inner_store.total_read_request_count(&path_3));
+
+        // the four GETs served by the cache, the one that was not, and the eviction of paris are
+        // recorded in the metrics:
+        let counter = |name| {
+            registry
+                .get_instrument::<Metric<U64Counter>>(name)
+                .unwrap()
+                .get_observer(&Attributes::from(&[]))
+                .unwrap()
+                .fetch()
+        };
+        assert_eq!(4, counter("influxdb3_parquet_cache_hits"));
+        assert_eq!(1, counter("influxdb3_parquet_cache_misses"));
+        assert_eq!(1, counter("influxdb3_parquet_cache_evictions"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn pinned_entries_are_not_evicted() {
+        let inner_store = Arc::new(RequestCountedObjectStore::new(Arc::new(InMemory::new())));
+        let time_provider = Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        // same magic numbers as above, so that the third entry exceeds the cache capacity:
+        let (cached_store, oracle) = create_cached_obj_store_and_oracle(
+            Arc::clone(&inner_store) as _,
+            Arc::clone(&time_provider) as _,
+            60,
+            0.4,
+            Duration::from_millis(10),
+            Default::default(),
+            &Registry::new(),
+        );
+        let mut prune_notifier = oracle.prune_notifier();
+
+        // pin the first path before it is cached, it is the least recently used entry below, so
+        // it would be evicted if it were not pinned:
+        let path_1 = Path::from("0.parquet");
+        let pin = oracle.pin(vec![path_1.clone()]);
+        assert_eq!(1, oracle.usage().pinned_paths);
+
+        let path_2 = Path::from("1.parquet");
+        let path_3 = Path::from("2.parquet");
+        let payload_1 = b"Janeway";
+        let payload_2 = b"Paris";
+        let payload_3 = b"Neelix";
+        for (t, (path, payload)) in [
+            (&path_1, payload_1.as_slice()),
+            (&path_2, payload_2.as_slice()),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            time_provider.set(Time::from_timestamp_nanos(t as i64 + 1));
+            cached_store
+                .put(path, PutPayload::from_static(payload))
+                .await
+                .unwrap();
+            let (cache_request, notifier_rx) = CacheRequest::create(path.clone());
+            oracle.register(cache_request);
+            let _ = notifier_rx.await;
+        }
+        assert_eq!(2, oracle.usage().entries);
+
+        time_provider.set(Time::from_timestamp_nanos(3));
+        cached_store
+            .put(&path_3, PutPayload::from_static(payload_3))
+            .await
+            .unwrap();
+        let (cache_request, notifier_rx) = CacheRequest::create(path_3.clone());
+        oracle.register(cache_request);
+        let _ = notifier_rx.await;
+
+        // paris is evicted instead of janeway:
+        prune_notifier.changed().await.unwrap();
+        assert_eq!(2, oracle.usage().entries);
+        assert_payload_at_equals!(cached_store, payload_1, path_1);
+        assert_payload_at_equals!(cached_store, payload_2, path_2);
+        assert_eq!(1, inner_store.total_read_request_count(&path_1));
+        assert_eq!(2, inner_store.total_read_request_count(&path_2));
+
+        drop(pin);
+        assert_eq!(0, oracle.usage().pinned_paths);
+    }
+
+    #[tokio::test]
+    async fn objects_larger_than_capacity_are_not_cached() {
+        let inner_store = Arc::new(RequestCountedObjectStore::new(Arc::new(InMemory::new())));
+        let time_provider = Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let (cached_store, oracle) = create_cached_obj_store_and_oracle(
+            Arc::clone(&inner_store) as _,
+            Arc::clone(&time_provider) as _,
+            60,
+            0.1,
+            Duration::from_millis(10),
+            Default::default(),
+            &Registry::new(),
+        );
+        let path = Path::from("0.parquet");
+        let payload = [0u8; 100].as_slice();
+        cached_store
+            .put(&path, PutPayload::from(payload.to_vec()))
+            .await
+            .unwrap();
+
+        let (cache_request, notifier_rx) = CacheRequest::create(path.clone());
+        oracle.register(cache_request);
+        let _ = notifier_rx.await;
+        assert_eq!(0, oracle.usage().entries);
+
+        // the object is still served, but by the inner store:
+        assert_payload_at_equals!(cached_store, payload, path);
+        assert_eq!(2, inner_store.total_read_request_count(&path));
+    }
+
+    #[tokio::test]
+    async fn byte_ranges_are_cached_and_coalesced() {
+        let inner_store = Arc::new(RequestCountedObjectStore::new(Arc::new(InMemory::new())));
+        let time_provider: Arc<dyn TimeProvider> =
+            Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let (cached_store, oracle) =
+            test_cached_obj_store_and_oracle(Arc::clone(&inner_store) as _, time_provider);
+        let path = Path::from("0.parquet");
+        let payload = (0..100).collect::<Vec<u8>>();
+        cached_store
+            .put(&path, PutPayload::from(payload.clone()))
+            .await
+            .unwrap();
+
+        // fetch the "footer", which is then served from the cache:
+        for _ in 0..2 {
+            let bytes = cached_store.get_range(&path, 90..100).await.unwrap();
+            assert_eq!(&payload[90..100], bytes.as_ref());
+        }
+        assert_eq!(1, inner_store.get_ranges_request_count(&path));
+        assert_eq!(
+            (0, 1),
+            (oracle.usage().entries, oracle.usage().range_entries)
+        );
+
+        // a range that is only partly cached is fetched, and is coalesced with the cached one:
+        let bytes = cached_store.get_range(&path, 80..95).await.unwrap();
+        assert_eq!(&payload[80..95], bytes.as_ref());
+        assert_eq!(2, inner_store.get_ranges_request_count(&path));
+        let bytes = cached_store
+            .get_ranges(&path, &[85..100, 80..81])
+            .await
+            .unwrap();
+        assert_eq!(&payload[85..100], bytes[0].as_ref());
+        assert_eq!(&payload[80..81], bytes[1].as_ref());
+        assert_eq!(2, inner_store.get_ranges_request_count(&path));
+
+        // a mix of cached and un-cached ranges only fetches those that are not cached:
+        let bytes = cached_store
+            .get_ranges(&path, &[0..10, 90..100, 79..80])
+            .await
+            .unwrap();
+        assert_eq!(&payload[0..10], bytes[0].as_ref());
+        assert_eq!(&payload[90..100], bytes[1].as_ref());
+        assert_eq!(&payload[79..80], bytes[2].as_ref());
+        assert_eq!(3, inner_store.get_ranges_request_count(&path));
+
+        // once the whole object is cached, its ranges are dropped:
+        let (cache_request, notifier_rx) = CacheRequest::create(path.clone());
+        oracle.register(cache_request);
+        let _ = notifier_rx.await;
+        assert_eq!(
+            (1, 0),
+            (oracle.usage().entries, oracle.usage().range_entries)
+        );
+        let bytes = cached_store.get_range(&path, 20..30).await.unwrap();
+        assert_eq!(&payload[20..30], bytes.as_ref());
+        assert_eq!(3, inner_store.get_ranges_request_count(&path));
+    }
+
+    #[tokio::test]
+    async fn invalidate_and_clear() {
+        let inner_store = Arc::new(RequestCountedObjectStore::new(Arc::new(InMemory::new())));
+        let time_provider: Arc<dyn TimeProvider> =
+            Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let (cached_store, oracle) =
+            test_cached_obj_store_and_oracle(Arc::clone(&inner_store) as _, time_provider);
+        let paths = [
+            "host/dbs/foo-0/cpu-0/1970-01-01/00-00/0000000001.parquet",
+            "host/dbs/foo-0/mem-1/1970-01-01/00-00/0000000001.parquet",
+            "host/dbs/bar-1/cpu-2/1970-01-01/00-00/0000000001.parquet",
+        ]
+        .map(Path::from);
+        for path in &paths {
+            cached_store
+                .put(path, PutPayload::from_static(b"Seven"))
+                .await
+                .unwrap();
+            let (cache_request, notifier_rx) = CacheRequest::create(path.clone());
+            oracle.register(cache_request);
+            let _ = notifier_rx.await;
+        }
+        assert_eq!(3, oracle.usage().entries);
+
+        oracle.invalidate(&paths[1]);
+        assert_eq!(2, oracle.usage().entries);
+        oracle.invalidate_db(DbId::from(0));
+        assert_eq!(1, oracle.usage().entries);
+        // the file of the other database is still served from the cache:
+        cached_store.get(&paths[2]).await.unwrap();
+        assert_eq!(1, inner_store.total_read_request_count(&paths[2]));
+
+        oracle.clear();
+        assert_eq!(0, oracle.usage().entries);
+        cached_store.get(&paths[2]).await.unwrap();
+        assert_eq!(2, inner_store.total_read_request_count(&paths[2]));
+    }
+
+    #[test]
+    fn admission_policy() {
+        let policy = CacheAdmissionPolicy {
+            max_file_size_bytes: Some(100),
+            max_file_age: Some(Duration::from_secs(60)),
+            allow_tables: vec!["foo.*".parse().unwrap(), "bar.cpu".parse().unwrap()],
+            deny_tables: vec!["foo.mem".parse().unwrap()],
+        };
+        let now = Duration::from_secs(120).as_nanos() as i64;
+        let recent = now - 1;
+        let admits = |path: &str, size_bytes: usize, max_time: i64| {
+            let (request, _) = CacheRequest::create(Path::from(path));
+            policy.admits(&request.with_file_info(size_bytes, max_time), now)
+        };
+        assert!(admits("host/dbs/foo-0/cpu-0/0.parquet", 100, recent));
+        // too large:
+        assert!(!admits("host/dbs/foo-0/cpu-0/0.parquet", 101, recent));
+        // too old:
+        assert!(!admits("host/dbs/foo-0/cpu-0/0.parquet", 100, 0));
+        // denied:
+        assert!(!admits("host/dbs/foo-0/mem-1/0.parquet", 100, recent));
+        assert!(admits("host/dbs/bar-1/cpu-2/0.parquet", 100, recent));
+        // not allowed:
+        assert!(!admits("host/dbs/bar-1/mem-3/0.parquet", 100, recent));
+
+        assert!("foo".parse::<CacheTable>().is_err());
+        assert!(".cpu".parse::<CacheTable>().is_err());
     }
 
     #[tokio::test]
@@ -1,11 +1,17 @@
 use chrono::prelude::*;
 use influxdb3_catalog::catalog::CatalogSequenceNumber;
+use influxdb3_id::{DbId, ParquetFileId};
 use influxdb3_wal::{SnapshotSequenceNumber, WalFileSequenceNumber};
 use object_store::path::Path as ObjPath;
 use std::ops::Deref;
 
-/// File extension for catalog files
-pub const CATALOG_FILE_EXTENSION: &str = "json";
+/// File extension for catalog files, and catalog delta files, which are written in the binary
+/// format of the persister
+pub const CATALOG_FILE_EXTENSION: &str = "bin";
+
+/// File extension for catalog files written as JSON, before the binary format was introduced,
+/// which can still be loaded
+pub const JSON_CATALOG_FILE_EXTENSION: &str = "json";
 
 /// File extension for parquet files
 pub const PARQUET_FILE_EXTENSION: &str = "parquet";
@@ -13,6 +19,12 @@ pub const PARQUET_FILE_EXTENSION: &str = "parquet";
 /// File extension for snapshot info files
 pub const SNAPSHOT_INFO_FILE_EXTENSION: &str = "info.json";
 
+/// File extension for audit log files
+pub const AUDIT_LOG_FILE_EXTENSION: &str = "json";
+
+/// File extension for compaction generation files
+pub const COMPACTION_GENERATION_FILE_EXTENSION: &str = "json";
+
 fn object_store_file_stem(n: u64) -> u64 {
     u64::MAX - n
 }
@@ -33,6 +45,17 @@ impl CatalogFilePath {
     pub fn dir(host_prefix: &str) -> Self {
         Self(ObjPath::from(format!("{host_prefix}/catalogs")))
     }
+
+    /// The catalog sequence number of the catalog file at `path`, if it is a catalog file, in
+    /// either the binary or JSON format
+    pub fn sequence_number(path: &ObjPath) -> Option<CatalogSequenceNumber> {
+        let (stem, extension) = path.filename()?.split_once('.')?;
+        if ![CATALOG_FILE_EXTENSION, JSON_CATALOG_FILE_EXTENSION].contains(&extension) {
+            return None;
+        }
+        let num = u64::MAX - stem.parse::<u64>().ok()?;
+        Some(CatalogSequenceNumber::new(num.try_into().ok()?))
+    }
 }
 
 impl Deref for CatalogFilePath {
@@ -49,6 +72,49 @@ impl AsRef<ObjPath> for CatalogFilePath {
     }
 }
 
+/// The path of a catalog delta file, which has the WAL catalog batches that were applied to the
+/// catalog since its changes were last persisted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogDeltaFilePath(ObjPath);
+
+impl CatalogDeltaFilePath {
+    pub fn new(host_prefix: &str, catalog_sequence_number: CatalogSequenceNumber) -> Self {
+        let path = ObjPath::from(format!(
+            "{host_prefix}/catalog_deltas/{:010}.{}",
+            catalog_sequence_number.as_u32(),
+            CATALOG_FILE_EXTENSION
+        ));
+        Self(path)
+    }
+
+    pub fn dir(host_prefix: &str) -> Self {
+        Self(ObjPath::from(format!("{host_prefix}/catalog_deltas")))
+    }
+
+    /// The catalog sequence number of the delta file at `path`, if it is a delta file, in either
+    /// the binary or JSON format
+    pub fn sequence_number(path: &ObjPath) -> Option<CatalogSequenceNumber> {
+        let (stem, extension) = path.filename()?.split_once('.')?;
+        [CATALOG_FILE_EXTENSION, JSON_CATALOG_FILE_EXTENSION]
+            .contains(&extension)
+            .then(|| stem.parse().ok().map(CatalogSequenceNumber::new))?
+    }
+}
+
+impl Deref for CatalogDeltaFilePath {
+    type Target = ObjPath;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<ObjPath> for CatalogDeltaFilePath {
+    fn as_ref(&self) -> &ObjPath {
+        &self.0
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ParquetFilePath(ObjPath);
 
@@ -73,6 +139,100 @@ impl ParquetFilePath {
         ));
         Self(path)
     }
+
+    /// Generate the path of a gen2 parquet file, which a compaction merged the files of the
+    /// window starting at `window_start` into. Files are named after the compaction generation
+    /// that wrote them, as a window can be compacted again when late data is persisted into it.
+    pub fn new_gen2(
+        host_prefix: &str,
+        db_name: &str,
+        db_id: u32,
+        table_name: &str,
+        table_id: u32,
+        window_start: i64,
+        generation: u64,
+    ) -> Self {
+        let date_time = DateTime::<Utc>::from_timestamp_nanos(window_start);
+        let path = ObjPath::from(format!(
+            "{host_prefix}/dbs/{db_name}-{db_id}/{table_name}-{table_id}/{date_string}/gen2-{generation:010}.{ext}",
+            date_string = date_time.format("%Y-%m-%d/%H-%M"),
+            ext = PARQUET_FILE_EXTENSION
+        ));
+        Self(path)
+    }
+
+    /// Whether the parquet file at `path` is a gen2 file, which a compaction wrote
+    pub fn is_gen2(path: &str) -> bool {
+        ObjPath::from(path)
+            .filename()
+            .is_some_and(|filename| filename.starts_with("gen2-"))
+    }
+
+    /// Generate the path of a parquet file that was imported into a table, which is named after
+    /// the id that it was given, as any number of files can be imported into the same chunk
+    pub fn new_imported(
+        host_prefix: &str,
+        db_name: &str,
+        db_id: u32,
+        table_name: &str,
+        table_id: u32,
+        chunk_time: i64,
+        file_id: ParquetFileId,
+    ) -> Self {
+        let date_time = DateTime::<Utc>::from_timestamp_nanos(chunk_time);
+        let path = ObjPath::from(format!(
+            "{host_prefix}/dbs/{db_name}-{db_id}/{table_name}-{table_id}/{date_string}/import-{file_id:010}.{ext}",
+            date_string = date_time.format("%Y-%m-%d/%H-%M"),
+            file_id = file_id.as_u64(),
+            ext = PARQUET_FILE_EXTENSION
+        ));
+        Self(path)
+    }
+
+    /// Whether the parquet file at `path` was imported into its table, rather than persisted
+    /// from the buffer, in which case it can have data from any time, not only from the gen1
+    /// window of its chunk time
+    pub fn is_imported(path: &str) -> bool {
+        ObjPath::from(path)
+            .filename()
+            .is_some_and(|filename| filename.starts_with("import-"))
+    }
+
+    /// The directory that the parquet files of all databases are persisted under
+    pub fn dir(host_prefix: &str) -> Self {
+        Self(ObjPath::from(format!("{host_prefix}/dbs")))
+    }
+
+    /// The id of the database that the parquet file at `path` belongs to, from the
+    /// `dbs/{db_name}-{db_id}` part of the path
+    pub fn db_id(path: &ObjPath) -> Option<DbId> {
+        let (db, _) = Self::db_and_table_parts(path)?;
+        let (_, db_id) = db.rsplit_once('-')?;
+        db_id.parse::<u32>().ok().map(DbId::from)
+    }
+
+    /// The names of the database and table that the parquet file at `path` belongs to, from the
+    /// `dbs/{db_name}-{db_id}/{table_name}-{table_id}` part of the path
+    pub fn db_and_table_names(path: &ObjPath) -> Option<(String, String)> {
+        let (db, table) = Self::db_and_table_parts(path)?;
+        let (db_name, _) = db.rsplit_once('-')?;
+        let (table_name, _) = table.rsplit_once('-')?;
+        Some((db_name.to_string(), table_name.to_string()))
+    }
+
+    fn db_and_table_parts(path: &ObjPath) -> Option<(String, String)> {
+        let parts = path
+            .parts()
+            .map(|part| part.as_ref().to_string())
+            .collect::<Vec<_>>();
+        parts.windows(3).find_map(|parts| {
+            let is_db = parts[1]
+                .rsplit_once('-')
+                .is_some_and(|(_, id)| id.parse::<u32>().is_ok());
+            (parts[0] == "dbs" && is_db && parts[2].contains('-'))
+                .then(|| (parts[1].clone(), parts[2].clone()))
+        })
+    }
 }
 
 impl Deref for ParquetFilePath {
@@ -105,6 +265,17 @@ impl SnapshotInfoFilePath {
     pub fn dir(host_prefix: &str) -> Self {
         Self(ObjPath::from(format!("{host_prefix}/snapshots")))
     }
+
+    /// The path that a snapshot file is moved to when it is discarded by a recovery, outside of
+    /// the snapshots that are loaded on startup
+    pub fn discarded(host_prefix: &str, snapshot_sequence_number: SnapshotSequenceNumber) -> Self {
+        let path = ObjPath::from(format!(
+            "{host_prefix}/snapshots_discarded/{:020}.{}",
+            object_store_file_stem(snapshot_sequence_number.as_u64()),
+            SNAPSHOT_INFO_FILE_EXTENSION
+        ));
+        Self(path)
+    }
 }
 
 impl Deref for SnapshotInfoFilePath {
@@ -121,11 +292,344 @@ impl AsRef<ObjPath> for SnapshotInfoFilePath {
     }
 }
 
+/// The path of the marker of a snapshot that is being persisted, which lists the parquet files
+/// that it persists, so that the files of a snapshot that is interrupted can be deleted when the
+/// server restarts. The marker is deleted once the snapshot file has been persisted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotMarkerFilePath(ObjPath);
+
+impl SnapshotMarkerFilePath {
+    pub fn new(host_prefix: &str, snapshot_sequence_number: SnapshotSequenceNumber) -> Self {
+        let path = ObjPath::from(format!(
+            "{host_prefix}/snapshots_in_progress/{:020}.json",
+            object_store_file_stem(snapshot_sequence_number.as_u64()),
+        ));
+        Self(path)
+    }
+
+    pub fn dir(host_prefix: &str) -> Self {
+        Self(ObjPath::from(format!(
+            "{host_prefix}/snapshots_in_progress"
+        )))
+    }
+}
+
+impl Deref for SnapshotMarkerFilePath {
+    type Target = ObjPath;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<ObjPath> for SnapshotMarkerFilePath {
+    fn as_ref(&self) -> &ObjPath {
+        &self.0
+    }
+}
+
+/// The path of a compaction generation file, which records the gen2 files that a compaction wrote
+/// and the files they replaced. These are numbered in ascending order, so that listing them
+/// returns the generations in the order they are applied in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionGenerationFilePath(ObjPath);
+
+impl CompactionGenerationFilePath {
+    pub fn new(host_prefix: &str, generation: u64) -> Self {
+        let path = ObjPath::from(format!(
+            "{host_prefix}/compactions/{:020}.{}",
+            generation, COMPACTION_GENERATION_FILE_EXTENSION
+        ));
+        Self(path)
+    }
+
+    pub fn dir(host_prefix: &str) -> Self {
+        Self(ObjPath::from(format!("{host_prefix}/compactions")))
+    }
+
+    /// The generation of the file at `path`, if it is a compaction generation file
+    pub fn generation(path: &ObjPath) -> Option<u64> {
+        path.filename()?
+            .strip_suffix(COMPACTION_GENERATION_FILE_EXTENSION)?
+            .strip_suffix('.')?
+            .parse()
+            .ok()
+    }
+}
+
+impl Deref for CompactionGenerationFilePath {
+    type Target = ObjPath;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<ObjPath> for CompactionGenerationFilePath {
+    fn as_ref(&self) -> &ObjPath {
+        &self.0
+    }
+}
+
+/// The path of a compaction checkpoint file, which folds the compaction generations up to and
+/// including the one it is numbered with into a single generation, so that they need not all be
+/// loaded on startup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionCheckpointFilePath(ObjPath);
+
+impl CompactionCheckpointFilePath {
+    pub fn new(host_prefix: &str, generation: u64) -> Self {
+        let path = ObjPath::from(format!(
+            "{host_prefix}/compaction_checkpoints/{:020}.{}",
+            generation, COMPACTION_GENERATION_FILE_EXTENSION
+        ));
+        Self(path)
+    }
+
+    pub fn dir(host_prefix: &str) -> Self {
+        Self(ObjPath::from(format!(
+            "{host_prefix}/compaction_checkpoints"
+        )))
+    }
+
+    /// The last generation covered by the checkpoint at `path`, if it is a checkpoint file
+    pub fn generation(path: &ObjPath) -> Option<u64> {
+        CompactionGenerationFilePath::generation(path)
+    }
+}
+
+impl Deref for CompactionCheckpointFilePath {
+    type Target = ObjPath;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<ObjPath> for CompactionCheckpointFilePath {
+    fn as_ref(&self) -> &ObjPath {
+        &self.0
+    }
+}
+
+/// The path of a file in the audit log. Unlike the other files, these are numbered in ascending
+/// order, so that listing them returns the entries in the order they were written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditLogFilePath(ObjPath);
+
+impl AuditLogFilePath {
+    pub fn new(host_prefix: &str, sequence_number: u64) -> Self {
+        let path = ObjPath::from(format!(
+            "{host_prefix}/audit/{:020}.{}",
+            sequence_number, AUDIT_LOG_FILE_EXTENSION
+        ));
+        Self(path)
+    }
+
+    pub fn dir(host_prefix: &str) -> Self {
+        Self(ObjPath::from(format!("{host_prefix}/audit")))
+    }
+
+    /// The sequence number of the file at `path`, if it is an audit log file
+    pub fn sequence_number(path: &ObjPath) -> Option<u64> {
+        path.filename()?
+            .strip_suffix(AUDIT_LOG_FILE_EXTENSION)?
+            .strip_suffix('.')?
+            .parse()
+            .ok()
+    }
+}
+
+impl Deref for AuditLogFilePath {
+    type Target = ObjPath;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<ObjPath> for AuditLogFilePath {
+    fn as_ref(&self) -> &ObjPath {
+        &self.0
+    }
+}
+
+/// The path of the file with the WAL settings that were changed while the server was running,
+/// which there is only one of, as it is overwritten each time they are changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalConfigFilePath(ObjPath);
+
+impl WalConfigFilePath {
+    pub fn new(host_prefix: &str) -> Self {
+        Self(ObjPath::from(format!("{host_prefix}/wal_config.json")))
+    }
+}
+
+impl Deref for WalConfigFilePath {
+    type Target = ObjPath;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<ObjPath> for WalConfigFilePath {
+    fn as_ref(&self) -> &ObjPath {
+        &self.0
+    }
+}
+
+/// The path of the file with the contents of the last caches, which there is only one of, as it
+/// is overwritten each time they are persisted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastCacheFilePath(ObjPath);
+
+impl LastCacheFilePath {
+    pub fn new(host_prefix: &str) -> Self {
+        Self(ObjPath::from(format!("{host_prefix}/last_caches.json")))
+    }
+}
+
+impl Deref for LastCacheFilePath {
+    type Target = ObjPath;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<ObjPath> for LastCacheFilePath {
+    fn as_ref(&self) -> &ObjPath {
+        &self.0
+    }
+}
+
+/// The path of the file with the progress of the downsampling tasks, which there is only one of,
+/// as it is overwritten after each run of the tasks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownsamplingProgressFilePath(ObjPath);
+
+impl DownsamplingProgressFilePath {
+    pub fn new(host_prefix: &str) -> Self {
+        Self(ObjPath::from(format!("{host_prefix}/downsampling.json")))
+    }
+}
+
+impl Deref for DownsamplingProgressFilePath {
+    type Target = ObjPath;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<ObjPath> for DownsamplingProgressFilePath {
+    fn as_ref(&self) -> &ObjPath {
+        &self.0
+    }
+}
+
+/// The path of the index of the persisted files, which there is only one of, as it is overwritten
+/// each time that it is written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistedFilesIndexFilePath(ObjPath);
+
+impl PersistedFilesIndexFilePath {
+    pub fn new(host_prefix: &str) -> Self {
+        Self(ObjPath::from(format!(
+            "{host_prefix}/persisted_files_index.json"
+        )))
+    }
+}
+
+impl Deref for PersistedFilesIndexFilePath {
+    type Target = ObjPath;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<ObjPath> for PersistedFilesIndexFilePath {
+    fn as_ref(&self) -> &ObjPath {
+        &self.0
+    }
+}
+
+/// The path of the lease on a host identifier prefix, which there is only one of, as it is
+/// overwritten each time that the lease is renewed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaseFilePath(ObjPath);
+
+impl LeaseFilePath {
+    pub fn new(host_prefix: &str) -> Self {
+        Self(ObjPath::from(format!("{host_prefix}/lease.json")))
+    }
+}
+
+impl Deref for LeaseFilePath {
+    type Target = ObjPath;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<ObjPath> for LeaseFilePath {
+    fn as_ref(&self) -> &ObjPath {
+        &self.0
+    }
+}
+
+/// The path of the manifest of a backup, which is written once everything else in the backup
+/// has been, so a backup without one is incomplete
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupManifestFilePath(ObjPath);
+
+impl BackupManifestFilePath {
+    pub fn new(backup_prefix: &str) -> Self {
+        Self(ObjPath::from(format!("{backup_prefix}/backup.json")))
+    }
+}
+
+impl Deref for BackupManifestFilePath {
+    type Target = ObjPath;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<ObjPath> for BackupManifestFilePath {
+    fn as_ref(&self) -> &ObjPath {
+        &self.0
+    }
+}
+
 #[test]
 fn catalog_file_path_new() {
     assert_eq!(
         *CatalogFilePath::new("my_host", CatalogSequenceNumber::new(0)),
-        ObjPath::from("my_host/catalogs/18446744073709551615.json")
+        ObjPath::from("my_host/catalogs/18446744073709551615.bin")
+    );
+}
+
+#[test]
+fn catalog_file_path_sequence_number() {
+    let path = CatalogFilePath::new("my_host", CatalogSequenceNumber::new(42));
+    assert_eq!(
+        Some(CatalogSequenceNumber::new(42)),
+        CatalogFilePath::sequence_number(&path)
+    );
+    assert_eq!(
+        Some(CatalogSequenceNumber::new(0)),
+        CatalogFilePath::sequence_number(&ObjPath::from(
+            "my_host/catalogs/18446744073709551615.json"
+        ))
+    );
+    assert_eq!(
+        None,
+        CatalogFilePath::sequence_number(&ObjPath::from("my_host/catalogs/not_a_catalog.bin"))
     );
 }
 
@@ -148,6 +652,28 @@ fn parquet_file_path_new() {
     );
 }
 
+#[test]
+fn parquet_file_path_db_id() {
+    let path = ParquetFilePath::new(
+        "dbs",
+        "my-db",
+        42,
+        "my_table",
+        0,
+        0,
+        WalFileSequenceNumber::new(1),
+    );
+    assert_eq!(Some(DbId::from(42)), ParquetFilePath::db_id(&path));
+    assert_eq!(
+        Some(("my-db".to_string(), "my_table".to_string())),
+        ParquetFilePath::db_and_table_names(&path)
+    );
+    assert_eq!(
+        None,
+        ParquetFilePath::db_id(&ObjPath::from("my_host/snapshots/0.info.json"))
+    );
+}
+
 #[test]
 fn parquet_file_percent_encoded() {
     assert_eq!(
@@ -176,3 +702,55 @@ fn snapshot_info_file_path_new() {
         ObjPath::from("my_host/snapshots/18446744073709551615.info.json")
     );
 }
+
+#[test]
+fn audit_log_file_path_new() {
+    let path = AuditLogFilePath::new("my_host", 42);
+    assert_eq!(
+        *path,
+        ObjPath::from("my_host/audit/00000000000000000042.json")
+    );
+    assert_eq!(Some(42), AuditLogFilePath::sequence_number(&path));
+}
+
+#[test]
+fn compaction_checkpoint_file_path_new() {
+    let path = CompactionCheckpointFilePath::new("my_host", 42);
+    assert_eq!(
+        *path,
+        ObjPath::from("my_host/compaction_checkpoints/00000000000000000042.json")
+    );
+    assert_eq!(Some(42), CompactionCheckpointFilePath::generation(&path));
+}
+
+#[test]
+fn parquet_file_path_is_gen2() {
+    let gen2 = ParquetFilePath::new_gen2("my_host", "my_db", 0, "my_table", 0, 0, 7);
+    assert!(ParquetFilePath::is_gen2(gen2.as_ref().as_ref()));
+    let gen1 = ParquetFilePath::new(
+        "my_host",
+        "my_db",
+        0,
+        "my_table",
+        0,
+        0,
+        WalFileSequenceNumber::new(7),
+    );
+    assert!(!ParquetFilePath::is_gen2(gen1.as_ref().as_ref()));
+}
+
+#[test]
+fn parquet_file_path_is_imported() {
+    let imported = ParquetFilePath::new_imported(
+        "my_host",
+        "my_db",
+        0,
+        "my_table",
+        0,
+        0,
+        ParquetFileId::from(7),
+    );
+    assert!(ParquetFilePath::is_imported(imported.as_ref().as_ref()));
+    let gen2 = ParquetFilePath::new_gen2("my_host", "my_db", 0, "my_table", 0, 0, 7);
+    assert!(!ParquetFilePath::is_imported(gen2.as_ref().as_ref()));
+}
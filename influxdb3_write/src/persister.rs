@@ -1,10 +1,27 @@
 //! This is the implementation of the `Persister` used to write data from the buffer to object
 //! storage.
 
+use crate::backup::BackupManifest;
 use crate::last_cache;
+use crate::last_cache::LastCacheContents;
+use crate::paths::AuditLogFilePath;
+use crate::paths::BackupManifestFilePath;
+use crate::paths::CatalogDeltaFilePath;
 use crate::paths::CatalogFilePath;
+use crate::paths::CompactionCheckpointFilePath;
+use crate::paths::CompactionGenerationFilePath;
+use crate::paths::DownsamplingProgressFilePath;
+use crate::paths::LastCacheFilePath;
 use crate::paths::ParquetFilePath;
+use crate::paths::PersistedFilesIndexFilePath;
 use crate::paths::SnapshotInfoFilePath;
+use crate::paths::SnapshotMarkerFilePath;
+use crate::paths::WalConfigFilePath;
+use crate::paths::PARQUET_FILE_EXTENSION;
+use crate::write_buffer::audit::AuditLogEntry;
+use crate::write_buffer::downsampling::DownsamplingProgress;
+use crate::CompactionGeneration;
+use crate::PersistedFilesIndex;
 use crate::PersistedSnapshot;
 use arrow::datatypes::SchemaRef;
 use arrow::record_batch::RecordBatch;
@@ -20,17 +37,31 @@ use futures_util::pin_mut;
 use futures_util::stream::StreamExt;
 use futures_util::stream::TryStreamExt;
 use influxdb3_catalog::catalog::Catalog;
+use influxdb3_catalog::catalog::CatalogDelta;
+use influxdb3_catalog::catalog::CatalogExport;
+use influxdb3_catalog::catalog::CatalogSequenceNumber;
 use influxdb3_catalog::catalog::InnerCatalog;
+use influxdb3_catalog::catalog::NextIds;
+use influxdb3_catalog::catalog::CATALOG_EXPORT_VERSION;
+use influxdb3_wal::encryption::{self, KeyProvider};
+use influxdb3_wal::SnapshotSequenceNumber;
+use influxdb3_wal::WalRuntimeConfig;
+use influxdb3_wal::{write_fence_is_open, WriteFence};
+use metric::{DurationHistogram, Registry, U64Counter};
 use object_store::path::Path as ObjPath;
-use object_store::ObjectStore;
-use observability_deps::tracing::info;
+use object_store::{ObjectMeta, ObjectStore};
+use observability_deps::tracing::{info, warn};
 use parquet::arrow::ArrowWriter;
-use parquet::basic::Compression;
-use parquet::file::properties::WriterProperties;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::{EnabledStatistics, WriterProperties, DEFAULT_PAGE_SIZE};
 use parquet::format::FileMetaData;
+use parquet::schema::types::ColumnPath;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::any::Any;
 use std::io::Write;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -56,6 +87,55 @@ pub enum Error {
 
     #[error("failed to initialize last cache: {0}")]
     InitializingLastCache(#[from] last_cache::Error),
+
+    #[error("encryption error: {0}")]
+    Encryption(#[from] encryption::Error),
+
+    #[error("catalog error: {0}")]
+    Catalog(#[from] influxdb3_catalog::catalog::Error),
+
+    #[error(
+        "catalog export has version {0}, but only version {CATALOG_EXPORT_VERSION} can be imported"
+    )]
+    UnsupportedCatalogExportVersion(u32),
+
+    #[error("catalog file has format version {0}, which is newer than this server supports")]
+    UnsupportedCatalogFormatVersion(u8),
+
+    #[error("catalog file is truncated")]
+    CatalogFileTruncated,
+
+    #[error("failed to encode catalog file: {0}")]
+    CatalogEncode(#[from] rmp_serde::encode::Error),
+
+    #[error("failed to decode catalog file: {0}")]
+    CatalogDecode(#[from] rmp_serde::decode::Error),
+
+    #[error("invalid parquet compression: {0}, expected one of none, snappy, lz4, or zstd")]
+    InvalidParquetCompression(String),
+
+    #[error("invalid parquet statistics level: {0}, expected one of none, chunk, or page")]
+    InvalidParquetStatisticsLevel(String),
+
+    #[error("failed to persist parquet file {path} on attempt {attempt}: {source}")]
+    PersistParquetFile {
+        path: String,
+        attempt: usize,
+        #[source]
+        source: Box<Error>,
+    },
+
+    #[error("failed to recover the interrupted snapshot {snapshot_sequence_number:?}: {source}")]
+    RecoverInterruptedSnapshot {
+        snapshot_sequence_number: SnapshotSequenceNumber,
+        #[source]
+        source: Box<Error>,
+    },
+
+    #[error(
+        "cannot write to object storage, as the host identifier prefix may have been taken over"
+    )]
+    WriteFenced,
 }
 
 impl From<Error> for DataFusionError {
@@ -73,6 +153,268 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 pub const DEFAULT_OBJECT_STORE_URL: &str = "iox://influxdb3/";
 
+/// The first bytes of a catalog, or catalog delta, file, which are followed by a byte with the
+/// version of the format that the rest of the file is encoded in. Files that do not start with
+/// them were written as JSON, before the binary format was introduced.
+pub const CATALOG_FILE_IDENTIFIER: &[u8] = b"idb3.cat";
+
+/// The version of the format that catalog files are written in. In version 1, the catalog is
+/// encoded with MessagePack, with the fields of structs named, so that fields can be added and
+/// removed in the same ways that they can be in JSON.
+pub const CATALOG_FORMAT_VERSION: u8 = 1;
+
+/// The default number of catalog deltas that are persisted before the catalog is persisted in
+/// full again
+pub const DEFAULT_CATALOG_CHECKPOINT_INTERVAL: usize = 100;
+
+/// The default number of parquet files that are persisted at the same time in a snapshot
+pub const DEFAULT_PERSIST_CONCURRENCY: usize = 8;
+
+/// How long to wait before retrying a persist that failed for the first time
+const PERSIST_RETRY_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The longest to wait before retrying a persist that keeps failing
+const PERSIST_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Exponential backoff between the attempts of a persist that is retried until it succeeds,
+/// since writes are held back once the buffer is full if the object store cannot be reached
+#[derive(Debug)]
+pub struct PersistBackoff {
+    attempt: usize,
+    next: Duration,
+}
+
+impl Default for PersistBackoff {
+    fn default() -> Self {
+        Self {
+            attempt: 0,
+            next: PERSIST_RETRY_INITIAL_BACKOFF,
+        }
+    }
+}
+
+impl PersistBackoff {
+    /// The number of attempts that have failed so far
+    pub fn attempt(&self) -> usize {
+        self.attempt
+    }
+
+    /// Wait before the next attempt, which is twice as long as before the last one, up to
+    /// [`PERSIST_RETRY_MAX_BACKOFF`]
+    pub async fn wait(&mut self) {
+        self.attempt += 1;
+        tokio::time::sleep(self.next).await;
+        self.next = (self.next * 2).min(PERSIST_RETRY_MAX_BACKOFF);
+    }
+}
+
+/// A page of the persisted snapshots, loaded by [`Persister::load_snapshots_page`]
+#[derive(Debug, Default)]
+pub struct SnapshotPage {
+    /// The snapshots, most recent first
+    pub snapshots: Vec<PersistedSnapshot>,
+    /// Where the next page, of the snapshots older than these, starts, if there are any
+    pub continuation: Option<SnapshotContinuation>,
+}
+
+/// The position in the list of snapshots that a page of them continues from, which is the path
+/// of the last snapshot file of the previous page. Snapshot files are named so that the most
+/// recent is listed first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotContinuation(ObjPath);
+
+/// The parquet files that a snapshot persists, which are persisted before them, and deleted with
+/// the snapshot file once it is, so that the files of a snapshot that never finished can be
+/// found when the server restarts
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotMarker {
+    pub snapshot_sequence_number: SnapshotSequenceNumber,
+    pub parquet_files: Vec<String>,
+}
+
+/// How the parquet files that are persisted are compressed
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ParquetCompression {
+    None,
+    Snappy,
+    Lz4,
+    #[default]
+    Zstd,
+}
+
+impl std::str::FromStr for ParquetCompression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "snappy" => Ok(Self::Snappy),
+            "lz4" => Ok(Self::Lz4),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(Error::InvalidParquetCompression(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for ParquetCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Snappy => write!(f, "snappy"),
+            Self::Lz4 => write!(f, "lz4"),
+            Self::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// The level of the statistics that are written to the parquet files that are persisted, which
+/// are used to prune row groups, and pages, when they are queried
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ParquetStatisticsLevel {
+    /// No statistics are written
+    None,
+    /// Statistics are written for each column chunk
+    Chunk,
+    /// Statistics are written for each column chunk, and each page
+    #[default]
+    Page,
+}
+
+impl std::str::FromStr for ParquetStatisticsLevel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "chunk" => Ok(Self::Chunk),
+            "page" => Ok(Self::Page),
+            _ => Err(Error::InvalidParquetStatisticsLevel(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for ParquetStatisticsLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Chunk => write!(f, "chunk"),
+            Self::Page => write!(f, "page"),
+        }
+    }
+}
+
+/// The default level that parquet files are compressed at with zstd
+pub const DEFAULT_PARQUET_ZSTD_LEVEL: i32 = 1;
+
+/// The settings that the parquet files persisted from the buffer are written with
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PersisterConfig {
+    /// The maximum number of rows in a row group
+    pub row_group_size: usize,
+    /// The best-effort maximum size of a data page, in bytes
+    pub data_page_size: usize,
+    pub compression: ParquetCompression,
+    /// The level that files are compressed at, if they are compressed with zstd, from 1 to 22
+    pub zstd_level: i32,
+    /// Whether columns are dictionary encoded, which falls back to plain encoding for a column
+    /// chunk once its dictionary gets too large
+    pub dictionary_enabled: bool,
+    pub statistics: ParquetStatisticsLevel,
+}
+
+impl Default for PersisterConfig {
+    fn default() -> Self {
+        Self {
+            row_group_size: ROW_GROUP_WRITE_SIZE,
+            data_page_size: DEFAULT_PAGE_SIZE,
+            compression: ParquetCompression::default(),
+            zstd_level: DEFAULT_PARQUET_ZSTD_LEVEL,
+            dictionary_enabled: true,
+            statistics: ParquetStatisticsLevel::default(),
+        }
+    }
+}
+
+/// The columns of a parquet file that bloom filters are written for, so that the row groups without
+/// a value that a query filters on can be skipped
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct BloomFilters {
+    pub columns: Vec<String>,
+    /// The number of distinct values that the filter for each column in each row group is sized
+    /// for, which is capped at the row group size
+    pub distinct_values: u64,
+}
+
+impl PersisterConfig {
+    /// The properties that parquet files are written with, which fails if the zstd level is out
+    /// of range
+    pub fn writer_properties(&self, bloom_filters: &BloomFilters) -> Result<WriterProperties> {
+        let compression = match self.compression {
+            ParquetCompression::None => Compression::UNCOMPRESSED,
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Lz4 => Compression::LZ4_RAW,
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::try_new(self.zstd_level)?),
+        };
+        let statistics = match self.statistics {
+            ParquetStatisticsLevel::None => EnabledStatistics::None,
+            ParquetStatisticsLevel::Chunk => EnabledStatistics::Chunk,
+            ParquetStatisticsLevel::Page => EnabledStatistics::Page,
+        };
+        let mut builder = WriterProperties::builder()
+            .set_compression(compression)
+            .set_max_row_group_size(self.row_group_size)
+            .set_data_page_size_limit(self.data_page_size)
+            .set_dictionary_enabled(self.dictionary_enabled)
+            .set_statistics_enabled(statistics);
+        let distinct_values = bloom_filters
+            .distinct_values
+            .clamp(1, self.row_group_size.max(1) as u64);
+        for column in &bloom_filters.columns {
+            let path = ColumnPath::from(column.as_str());
+            builder = builder
+                .set_column_bloom_filter_enabled(path.clone(), true)
+                .set_column_bloom_filter_ndv(path, distinct_values);
+        }
+        Ok(builder.build())
+    }
+}
+
+/// The metrics that the persister records for the parquet files it persists
+#[derive(Debug)]
+struct PersisterMetrics {
+    /// How long it takes to serialize and write a parquet file
+    persist_duration: DurationHistogram,
+    /// The number of attempts to persist a parquet file that failed
+    persist_failures: U64Counter,
+}
+
+impl PersisterMetrics {
+    fn new(metric_registry: &Registry) -> Self {
+        let persist_duration = metric_registry
+            .register_metric::<DurationHistogram>(
+                "influxdb3_parquet_persist_duration",
+                "time taken to serialize and persist a parquet file",
+            )
+            .recorder(&[]);
+        let persist_failures = metric_registry
+            .register_metric::<U64Counter>(
+                "influxdb3_parquet_persist_failures",
+                "number of attempts to persist a parquet file that failed",
+            )
+            .recorder(&[]);
+        Self {
+            persist_duration,
+            persist_failures,
+        }
+    }
+}
+
+impl Default for PersisterMetrics {
+    fn default() -> Self {
+        Self::new(&Registry::new())
+    }
+}
+
 /// The persister is the primary interface with object storage where InfluxDB stores all Parquet
 /// data, catalog information, as well as WAL and snapshot data.
 #[derive(Debug)]
@@ -86,6 +428,24 @@ pub struct Persister {
     /// Prefix used for all paths in the object store for this persister
     host_identifier_prefix: String,
     pub(crate) mem_pool: Arc<dyn MemoryPool>,
+    /// If set, the catalog, snapshot, and audit log files are encrypted with the keys that it
+    /// provides
+    key_provider: Option<Arc<dyn KeyProvider>>,
+    /// The number of catalog deltas that are persisted before the catalog is persisted in full,
+    /// which the deltas are compacted into
+    catalog_checkpoint_interval: usize,
+    /// The number of versions of the catalog that are kept when the catalog is persisted in full,
+    /// along with the deltas persisted after them, or all versions if not set
+    retained_catalog_versions: Option<usize>,
+    /// The number of parquet files that are persisted at the same time in a snapshot
+    persist_concurrency: usize,
+    /// The latency and failures of persisting parquet files
+    metrics: PersisterMetrics,
+    /// The settings that parquet files are written with
+    config: PersisterConfig,
+    /// Once set, files are only written to, or deleted from, the host identifier prefix while it
+    /// is open
+    write_fence: OnceLock<Arc<dyn WriteFence>>,
 }
 
 impl Persister {
@@ -98,9 +458,92 @@ impl Persister {
             object_store,
             host_identifier_prefix: host_identifier_prefix.into(),
             mem_pool: Arc::new(UnboundedMemoryPool::default()),
+            key_provider: None,
+            catalog_checkpoint_interval: DEFAULT_CATALOG_CHECKPOINT_INTERVAL,
+            retained_catalog_versions: None,
+            persist_concurrency: DEFAULT_PERSIST_CONCURRENCY,
+            metrics: PersisterMetrics::default(),
+            config: PersisterConfig::default(),
+            write_fence: OnceLock::new(),
+        }
+    }
+
+    /// Write parquet files with the settings in `config`
+    pub fn with_config(mut self, config: PersisterConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// The settings that parquet files are written with
+    pub fn config(&self) -> &PersisterConfig {
+        &self.config
+    }
+
+    /// Refuse to write to, or delete from, the host identifier prefix once `write_fence` is
+    /// closed. It is set once the persister is shared, when the lease on the prefix is acquired,
+    /// and only the first fence that is set is used.
+    pub fn set_write_fence(&self, write_fence: Arc<dyn WriteFence>) {
+        if self.write_fence.set(write_fence).is_err() {
+            warn!("the persister already has a write fence");
+        }
+    }
+
+    /// Check that the persister may write to, or delete from, the host identifier prefix,
+    /// returning [`Error::WriteFenced`] if not
+    pub fn check_write_fence(&self) -> Result<()> {
+        if write_fence_is_open(self.write_fence.get()) {
+            Ok(())
+        } else {
+            Err(Error::WriteFenced)
         }
     }
 
+    /// Record the latency and failures of persisting parquet files in `metric_registry`
+    pub fn with_metric_registry(mut self, metric_registry: &Registry) -> Self {
+        self.metrics = PersisterMetrics::new(metric_registry);
+        self
+    }
+
+    /// Encrypt the files that are persisted with the keys from `key_provider`. Files that were
+    /// persisted unencrypted can still be loaded.
+    pub fn with_key_provider(mut self, key_provider: Arc<dyn KeyProvider>) -> Self {
+        self.key_provider = Some(key_provider);
+        self
+    }
+
+    /// The key provider used to encrypt persisted files, if they are encrypted
+    pub fn key_provider(&self) -> Option<Arc<dyn KeyProvider>> {
+        self.key_provider.clone()
+    }
+
+    /// Persist the catalog in full after `catalog_checkpoint_interval` deltas have been persisted
+    pub fn with_catalog_checkpoint_interval(mut self, catalog_checkpoint_interval: usize) -> Self {
+        self.catalog_checkpoint_interval = catalog_checkpoint_interval;
+        self
+    }
+
+    pub fn catalog_checkpoint_interval(&self) -> usize {
+        self.catalog_checkpoint_interval
+    }
+
+    /// Persist up to `persist_concurrency` of the parquet files of a snapshot at the same time
+    pub fn with_persist_concurrency(mut self, persist_concurrency: usize) -> Self {
+        self.persist_concurrency = persist_concurrency.max(1);
+        self
+    }
+
+    pub fn persist_concurrency(&self) -> usize {
+        self.persist_concurrency
+    }
+
+    /// Keep only the `retained_catalog_versions` most recent versions of the catalog that were
+    /// persisted in full, and the deltas persisted after them, rather than all of them. At least
+    /// one version is always kept.
+    pub fn with_retained_catalog_versions(mut self, retained_catalog_versions: usize) -> Self {
+        self.retained_catalog_versions = Some(retained_catalog_versions.max(1));
+        self
+    }
+
     /// Get the Object Store URL
     pub fn object_store_url(&self) -> &ObjectStoreUrl {
         &self.object_store_url
@@ -109,8 +552,15 @@ impl Persister {
     async fn serialize_to_parquet(
         &self,
         batches: SendableRecordBatchStream,
+        bloom_filters: &BloomFilters,
     ) -> Result<ParquetBytes> {
-        serialize_to_parquet(Arc::clone(&self.mem_pool), batches).await
+        serialize_to_parquet(
+            Arc::clone(&self.mem_pool),
+            batches,
+            &self.config,
+            bloom_filters,
+        )
+        .await
     }
 
     /// Get the host identifier prefix
@@ -121,8 +571,15 @@ impl Persister {
     /// Try loading the catalog, if there is no catalog generate new
     /// instance id and create a new catalog and persist it immediately
     pub async fn load_or_create_catalog(&self) -> Result<Catalog> {
-        let catalog = match self.load_catalog().await? {
-            Some(c) => Catalog::from_inner(c),
+        let catalog = match self.load_latest_catalog().await? {
+            Some((c, CatalogFormat::Binary)) => Catalog::from_inner(c),
+            Some((c, CatalogFormat::Json)) => {
+                // catalogs written as JSON are migrated by persisting them in the binary format:
+                info!("Migrating catalog from JSON to the binary format");
+                let catalog = Catalog::from_inner(c);
+                self.persist_catalog(&catalog).await?;
+                catalog
+            }
             None => {
                 let uuid = Uuid::new_v4().to_string();
                 let instance_id = Arc::from(uuid.as_str());
@@ -136,10 +593,20 @@ impl Persister {
         Ok(catalog)
     }
 
-    /// Loads the most recently persisted catalog from object storage.
+    /// Loads the most recently persisted catalog from object storage, with the deltas that were
+    /// persisted after it applied.
     ///
     /// This is used on server start.
     pub async fn load_catalog(&self) -> Result<Option<InnerCatalog>> {
+        Ok(self
+            .load_latest_catalog()
+            .await?
+            .map(|(catalog, _)| catalog))
+    }
+
+    /// Loads the most recently persisted catalog, as [`Persister::load_catalog`] does, along with
+    /// the format that it was persisted in
+    async fn load_latest_catalog(&self) -> Result<Option<(InnerCatalog, CatalogFormat)>> {
         let mut list = self
             .object_store
             .list(Some(&CatalogFilePath::dir(&self.host_identifier_prefix)));
@@ -173,77 +640,209 @@ impl Persister {
             };
         }
 
-        match catalog_path {
-            None => Ok(None),
-            Some(path) => {
-                let bytes = self.object_store.get(&path).await?.bytes().await?;
-                let catalog: InnerCatalog = serde_json::from_slice(&bytes)?;
-                Ok(Some(catalog))
+        let Some(catalog_path) = catalog_path else {
+            return Ok(None);
+        };
+        let bytes = self.get_decrypted(&catalog_path).await?;
+        let format = CatalogFormat::of(&bytes);
+        let mut catalog: InnerCatalog = deserialize_catalog_file(&bytes)?;
+
+        // apply the changes that were persisted as deltas since the catalog was persisted:
+        for (sequence_number, path) in self.list_catalog_delta_files().await? {
+            if sequence_number <= catalog.sequence_number() {
+                continue;
+            }
+            let bytes = self.get_decrypted(&path).await?;
+            let delta: CatalogDelta = deserialize_catalog_file(&bytes)?;
+            catalog.apply_delta(&delta)?;
+        }
+        Ok(Some((catalog, format)))
+    }
+
+    /// Loads the catalog as it was when it had the given sequence number, from the most recent
+    /// version persisted in full at or before it, with the deltas that were persisted after that
+    /// applied up to the sequence number, e.g., to find the schema that a parquet or WAL file was
+    /// written with. Returns `None` if no version of the catalog that old has been retained.
+    pub async fn load_catalog_at(
+        &self,
+        sequence_number: CatalogSequenceNumber,
+    ) -> Result<Option<InnerCatalog>> {
+        let Some((_, catalog_path)) = self
+            .list_catalog_files()
+            .await?
+            .into_iter()
+            .rev()
+            .find(|(catalog_sequence_number, _)| *catalog_sequence_number <= sequence_number)
+        else {
+            return Ok(None);
+        };
+        let bytes = self.get_decrypted(&catalog_path).await?;
+        let mut catalog: InnerCatalog = deserialize_catalog_file(&bytes)?;
+
+        for (delta_sequence_number, path) in self.list_catalog_delta_files().await? {
+            if catalog.sequence_number() >= sequence_number {
+                break;
+            }
+            if delta_sequence_number <= catalog.sequence_number() {
+                continue;
             }
+            let bytes = self.get_decrypted(&path).await?;
+            let delta: CatalogDelta = deserialize_catalog_file(&bytes)?;
+            catalog.apply_delta_until(&delta, sequence_number)?;
         }
+        Ok(Some(catalog))
+    }
+
+    /// Export the most recently persisted catalog as a self-contained, versioned JSON document,
+    /// which can be imported by another host with [`Persister::import_catalog`]. The document is
+    /// not encrypted. Returns `None` if no catalog has been persisted.
+    pub async fn export_catalog(&self) -> Result<Option<Bytes>> {
+        let Some(catalog) = self.load_catalog().await? else {
+            return Ok(None);
+        };
+        let json = serde_json::to_vec_pretty(&CatalogExport::new(catalog))?;
+        Ok(Some(Bytes::from(json)))
+    }
+
+    /// Import the databases of a catalog exported by [`Persister::export_catalog`], on any host,
+    /// into the catalog of this host, which is created if there is none, and persist the result.
+    ///
+    /// The imported databases, and their tables and columns, are given new ids that follow on
+    /// from those used in this host's catalog and snapshots, so that they cannot clash with them.
+    /// A running server does not see the imported databases until it is restarted.
+    pub async fn import_catalog(&self, export: &[u8]) -> Result<Catalog> {
+        let export: CatalogExport = serde_json::from_slice(export)?;
+        if export.version != CATALOG_EXPORT_VERSION {
+            return Err(Error::UnsupportedCatalogExportVersion(export.version));
+        }
+
+        let catalog = self.load_or_create_catalog().await?;
+        let mut next_ids = catalog.inner().read().next_ids();
+        if let Some(snapshot) = self.load_snapshots(1).await?.first() {
+            next_ids = next_ids.max(NextIds::new(
+                snapshot.next_db_id,
+                snapshot.next_table_id,
+                snapshot.next_column_id,
+            ));
+        }
+        catalog.inner().write().import(&export.catalog, next_ids)?;
+        self.persist_catalog(&catalog).await?;
+        Ok(catalog)
     }
 
     /// Loads the most recently persisted N snapshot parquet file lists from object storage.
     ///
     /// This is intended to be used on server start.
-    pub async fn load_snapshots(&self, mut most_recent_n: usize) -> Result<Vec<PersistedSnapshot>> {
-        let mut output = Vec::new();
-        let mut offset: Option<ObjPath> = None;
-        while most_recent_n > 0 {
-            let count = if most_recent_n > 1000 {
-                most_recent_n -= 1000;
-                1000
-            } else {
-                let count = most_recent_n;
-                most_recent_n = 0;
-                count
-            };
-
-            let mut snapshot_list = if let Some(offset) = offset {
-                self.object_store.list_with_offset(
-                    Some(&SnapshotInfoFilePath::dir(&self.host_identifier_prefix)),
-                    &offset,
-                )
-            } else {
-                self.object_store.list(Some(&SnapshotInfoFilePath::dir(
-                    &self.host_identifier_prefix,
-                )))
-            };
+    pub async fn load_snapshots(&self, most_recent_n: usize) -> Result<Vec<PersistedSnapshot>> {
+        Ok(self
+            .load_snapshots_page(None, most_recent_n)
+            .await?
+            .snapshots)
+    }
 
-            // Why not collect into a Result<Vec<ObjectMeta>, object_store::Error>>
-            // like we could with Iterators? Well because it's a stream it ends up
-            // using different traits and can't really do that. So we need to loop
-            // through to return any errors that might have occurred, then do an
-            // unstable sort (which is faster and we know won't have any
-            // duplicates) since these can arrive out of order, and then issue gets
-            // on the n most recent snapshots that we want and is returned in order
-            // of the moste recent to least.
-            let mut list = Vec::new();
-            while let Some(item) = snapshot_list.next().await {
-                list.push(item?);
+    /// Loads up to `limit` snapshots, most recent first, starting after `continuation`, or from
+    /// the most recent snapshot if there is none. The page that is returned has the continuation
+    /// of the next page if there are older snapshots than those in it.
+    pub async fn load_snapshots_page(
+        &self,
+        continuation: Option<&SnapshotContinuation>,
+        limit: usize,
+    ) -> Result<SnapshotPage> {
+        if limit == 0 {
+            return Ok(SnapshotPage {
+                snapshots: vec![],
+                continuation: continuation.cloned(),
+            });
+        }
+        let dir = SnapshotInfoFilePath::dir(&self.host_identifier_prefix);
+        let mut snapshot_list = match continuation {
+            Some(SnapshotContinuation(offset)) => {
+                self.object_store.list_with_offset(Some(&dir), offset)
             }
+            None => self.object_store.list(Some(&dir)),
+        };
 
-            list.sort_unstable_by(|a, b| a.location.cmp(&b.location));
+        // Why not collect into a Result<Vec<ObjectMeta>, object_store::Error>>
+        // like we could with Iterators? Well because it's a stream it ends up
+        // using different traits and can't really do that. So we need to loop
+        // through to return any errors that might have occurred, then do an
+        // unstable sort (which is faster and we know won't have any
+        // duplicates) since these can arrive out of order, and then issue gets
+        // on the n most recent snapshots that we want and is returned in order
+        // of the moste recent to least.
+        let mut list = Vec::new();
+        while let Some(item) = snapshot_list.next().await {
+            list.push(item?);
+        }
 
-            let len = list.len();
-            let end = if len <= count { len } else { count };
+        list.sort_unstable_by(|a, b| a.location.cmp(&b.location));
 
-            for item in &list[0..end] {
-                let bytes = self.object_store.get(&item.location).await?.bytes().await?;
-                output.push(serde_json::from_slice(&bytes)?);
-            }
+        let end = list.len().min(limit);
+        let mut snapshots = Vec::with_capacity(end);
+        for item in &list[0..end] {
+            let bytes = self.get_decrypted(&item.location).await?;
+            snapshots.push(serde_json::from_slice(&bytes)?);
+        }
 
-            if end == 0 {
-                break;
+        // the last path of the page is the offset that the next page is listed from, which
+        // relies on the list having been sorted, as the object store does not guarantee the
+        // order of the list call:
+        let continuation =
+            (list.len() > end).then(|| SnapshotContinuation(list[end - 1].location.clone()));
+
+        Ok(SnapshotPage {
+            snapshots,
+            continuation,
+        })
+    }
+
+    /// Loads the snapshots with a sequence number higher than `snapshot_sequence_number`, most
+    /// recent first, without getting any of the older snapshots
+    pub async fn load_snapshots_after(
+        &self,
+        snapshot_sequence_number: SnapshotSequenceNumber,
+    ) -> Result<Vec<PersistedSnapshot>> {
+        // the more recent a snapshot is, the lower its path sorts:
+        let after =
+            SnapshotInfoFilePath::new(&self.host_identifier_prefix, snapshot_sequence_number);
+        let mut snapshot_list = self.object_store.list(Some(&SnapshotInfoFilePath::dir(
+            &self.host_identifier_prefix,
+        )));
+        let mut list = Vec::new();
+        while let Some(item) = snapshot_list.next().await {
+            let item = item?;
+            if item.location < *after {
+                list.push(item);
             }
+        }
+        list.sort_unstable_by(|a, b| a.location.cmp(&b.location));
 
-            // Get the last path in the array to use as an offset. This assumes
-            // we sorted the list as we can't guarantee otherwise the order of
-            // the list call to the object store.
-            offset = Some(list[end - 1].location.clone());
+        let mut snapshots = Vec::with_capacity(list.len());
+        for item in &list {
+            let bytes = self.get_decrypted(&item.location).await?;
+            snapshots.push(serde_json::from_slice(&bytes)?);
         }
+        Ok(snapshots)
+    }
 
-        Ok(output)
+    /// Persists the index of the persisted files, replacing that persisted before
+    pub async fn persist_persisted_files_index(&self, index: &PersistedFilesIndex) -> Result<()> {
+        self.check_write_fence()?;
+        let path = PersistedFilesIndexFilePath::new(self.host_identifier_prefix.as_str());
+        let json = self.encrypt(serde_json::to_vec(index)?)?;
+        self.object_store.put(path.as_ref(), json.into()).await?;
+        Ok(())
+    }
+
+    /// Loads the index of the persisted files that was persisted with
+    /// [`Persister::persist_persisted_files_index`], if any
+    pub async fn load_persisted_files_index(&self) -> Result<Option<PersistedFilesIndex>> {
+        let path = PersistedFilesIndexFilePath::new(self.host_identifier_prefix.as_str());
+        match self.get_decrypted(&path).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(Error::ObjectStore(object_store::Error::NotFound { .. })) => Ok(None),
+            Err(e) => Err(e),
+        }
     }
 
     /// Loads a Parquet file from ObjectStore
@@ -255,44 +854,522 @@ impl Persister {
     /// Persists the catalog with the given `WalFileSequenceNumber`. If this is the highest ID, it will
     /// be the catalog that is returned the next time `load_catalog` is called.
     pub async fn persist_catalog(&self, catalog: &Catalog) -> Result<()> {
+        self.check_write_fence()?;
         let catalog_path = CatalogFilePath::new(
             self.host_identifier_prefix.as_str(),
             catalog.sequence_number(),
         );
-        let json = serde_json::to_vec_pretty(&catalog)?;
+        let bytes = self.encrypt(serialize_catalog_file(&catalog)?)?;
         self.object_store
-            .put(catalog_path.as_ref(), json.into())
+            .put(catalog_path.as_ref(), bytes.into())
+            .await?;
+
+        // the versions of the catalog that are no longer retained are deleted, along with the
+        // deltas that were persisted before the oldest version that is:
+        let Some(retained_catalog_versions) = self.retained_catalog_versions else {
+            return Ok(());
+        };
+        let catalog_files = self.list_catalog_files().await?;
+        let expired = catalog_files
+            .len()
+            .saturating_sub(retained_catalog_versions);
+        for (_, path) in &catalog_files[..expired] {
+            self.object_store.delete(path).await?;
+        }
+        let Some((oldest_sequence_number, _)) = catalog_files.get(expired) else {
+            return Ok(());
+        };
+        for (sequence_number, path) in self.list_catalog_delta_files().await? {
+            if sequence_number <= *oldest_sequence_number {
+                self.object_store.delete(&path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Persists the changes to the catalog since they were last persisted, which are applied to
+    /// the catalog, along with any other deltas persisted since it, when it is loaded
+    pub async fn persist_catalog_delta(&self, delta: &CatalogDelta) -> Result<()> {
+        self.check_write_fence()?;
+        let delta_path =
+            CatalogDeltaFilePath::new(self.host_identifier_prefix.as_str(), delta.sequence);
+        let bytes = self.encrypt(serialize_catalog_file(delta)?)?;
+        self.object_store
+            .put(delta_path.as_ref(), bytes.into())
             .await?;
         Ok(())
     }
 
     /// Persists the snapshot file
     pub async fn persist_snapshot(&self, persisted_snapshot: &PersistedSnapshot) -> Result<()> {
+        self.check_write_fence()?;
         let snapshot_file_path = SnapshotInfoFilePath::new(
             self.host_identifier_prefix.as_str(),
             persisted_snapshot.snapshot_sequence_number,
         );
-        let json = serde_json::to_vec_pretty(persisted_snapshot)?;
+        let json = self.encrypt(serde_json::to_vec_pretty(persisted_snapshot)?)?;
         self.object_store
             .put(snapshot_file_path.as_ref(), json.into())
             .await?;
         Ok(())
     }
 
+    /// Lists all of the parquet files in object storage, including those that are no longer
+    /// referred to by any snapshot
+    pub async fn list_parquet_files(&self) -> Result<Vec<ObjectMeta>> {
+        let mut files = Vec::new();
+        let mut list = self
+            .object_store
+            .list(Some(&ParquetFilePath::dir(&self.host_identifier_prefix)));
+        while let Some(item) = list.next().await {
+            let meta = item?;
+            if meta
+                .location
+                .extension()
+                .is_some_and(|extension| extension == PARQUET_FILE_EXTENSION)
+            {
+                files.push(meta);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Persists the file of a compaction generation, once all of its gen2 files have been
+    /// persisted
+    pub async fn persist_compaction_generation(
+        &self,
+        generation: &CompactionGeneration,
+    ) -> Result<()> {
+        self.check_write_fence()?;
+        let path = CompactionGenerationFilePath::new(
+            self.host_identifier_prefix.as_str(),
+            generation.generation,
+        );
+        let json = self.encrypt(serde_json::to_vec_pretty(generation)?)?;
+        self.object_store.put(path.as_ref(), json.into()).await?;
+        Ok(())
+    }
+
+    /// Loads the compaction generations, in the order that they were persisted. The generations
+    /// covered by the latest checkpoint are loaded as that checkpoint, which comes first.
+    pub async fn load_compaction_generations(&self) -> Result<Vec<CompactionGeneration>> {
+        let host_prefix = self.host_identifier_prefix.as_str();
+        let checkpoint = self
+            .list_compaction_files(
+                &CompactionCheckpointFilePath::dir(host_prefix),
+                CompactionCheckpointFilePath::generation,
+            )
+            .await?
+            .pop();
+        let files = self
+            .list_compaction_files(
+                &CompactionGenerationFilePath::dir(host_prefix),
+                CompactionGenerationFilePath::generation,
+            )
+            .await?;
+
+        let mut generations = Vec::with_capacity(files.len() + 1);
+        // the files of the generations that a checkpoint covers are deleted after it is
+        // persisted, so any that are left behind are ignored:
+        let covered = checkpoint.as_ref().map(|(generation, _)| *generation);
+        let files = files
+            .into_iter()
+            .filter(|(generation, _)| covered.map_or(true, |covered| *generation > covered));
+        for (_, path) in checkpoint.into_iter().chain(files) {
+            let bytes = self.get_decrypted(&path).await?;
+            generations.push(serde_json::from_slice(&bytes)?);
+        }
+        Ok(generations)
+    }
+
+    /// Persists a checkpoint of the compaction generations, which folds all of the generations up
+    /// to and including its own into one, and then deletes the files of the generations and the
+    /// older checkpoints that it covers
+    pub async fn persist_compaction_checkpoint(
+        &self,
+        checkpoint: &CompactionGeneration,
+    ) -> Result<()> {
+        self.check_write_fence()?;
+        let host_prefix = self.host_identifier_prefix.as_str();
+        let path = CompactionCheckpointFilePath::new(host_prefix, checkpoint.generation);
+        let json = self.encrypt(serde_json::to_vec_pretty(checkpoint)?)?;
+        self.object_store.put(path.as_ref(), json.into()).await?;
+
+        let generation_files = self
+            .list_compaction_files(
+                &CompactionGenerationFilePath::dir(host_prefix),
+                CompactionGenerationFilePath::generation,
+            )
+            .await?
+            .into_iter()
+            .filter(|(generation, _)| *generation <= checkpoint.generation);
+        let checkpoint_files = self
+            .list_compaction_files(
+                &CompactionCheckpointFilePath::dir(host_prefix),
+                CompactionCheckpointFilePath::generation,
+            )
+            .await?
+            .into_iter()
+            .filter(|(generation, _)| *generation < checkpoint.generation);
+        for (_, path) in generation_files.chain(checkpoint_files) {
+            match self.object_store.delete(&path).await {
+                Ok(()) | Err(object_store::Error::NotFound { .. }) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists the compaction generation or checkpoint files under `dir`, in the order of the
+    /// generations that they are numbered with
+    async fn list_compaction_files(
+        &self,
+        dir: &ObjPath,
+        generation: fn(&ObjPath) -> Option<u64>,
+    ) -> Result<Vec<(u64, ObjPath)>> {
+        let mut files = Vec::new();
+        let mut list = self.object_store.list(Some(dir));
+        while let Some(item) = list.next().await {
+            let location = item?.location;
+            if let Some(generation) = generation(&location) {
+                files.push((generation, location));
+            }
+        }
+        files.sort_unstable_by_key(|(generation, _)| *generation);
+        Ok(files)
+    }
+
+    /// Persists the marker of a snapshot that is about to persist its parquet files
+    pub async fn persist_snapshot_marker(&self, marker: &SnapshotMarker) -> Result<()> {
+        self.check_write_fence()?;
+        let path = SnapshotMarkerFilePath::new(
+            self.host_identifier_prefix.as_str(),
+            marker.snapshot_sequence_number,
+        );
+        let json = self.encrypt(serde_json::to_vec_pretty(marker)?)?;
+        self.object_store.put(path.as_ref(), json.into()).await?;
+        Ok(())
+    }
+
+    /// Deletes the marker of a snapshot once its snapshot file has been persisted
+    pub async fn delete_snapshot_marker(
+        &self,
+        snapshot_sequence_number: SnapshotSequenceNumber,
+    ) -> Result<()> {
+        self.check_write_fence()?;
+        let path = SnapshotMarkerFilePath::new(
+            self.host_identifier_prefix.as_str(),
+            snapshot_sequence_number,
+        );
+        self.object_store.delete(path.as_ref()).await?;
+        Ok(())
+    }
+
+    /// Finds the snapshots that were interrupted before their snapshot file was persisted, from
+    /// the markers that were left behind, and deletes the parquet files that they persisted,
+    /// which no snapshot refers to. The WAL files that the snapshots were taken from are still in
+    /// the WAL, so their writes are replayed and persisted again. The markers of snapshots that
+    /// did finish are deleted as well.
+    ///
+    /// This must be called on startup, before the WAL is replayed. Returns the paths of the
+    /// parquet files that were deleted.
+    pub async fn recover_interrupted_snapshots(&self) -> Result<Vec<String>> {
+        self.check_write_fence()?;
+        let host_prefix = self.host_identifier_prefix.as_str();
+        let markers = self
+            .object_store
+            .list(Some(&SnapshotMarkerFilePath::dir(host_prefix)))
+            .map_ok(|meta| meta.location)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        let mut deleted = vec![];
+        for marker_path in markers {
+            let marker: SnapshotMarker =
+                serde_json::from_slice(&self.get_decrypted(&marker_path).await?)?;
+            let snapshot_sequence_number = marker.snapshot_sequence_number;
+            let recover = async {
+                let snapshot_path =
+                    SnapshotInfoFilePath::new(host_prefix, snapshot_sequence_number);
+                let finished = match self.object_store.head(snapshot_path.as_ref()).await {
+                    Ok(_) => true,
+                    Err(object_store::Error::NotFound { .. }) => false,
+                    Err(e) => return Err(Error::from(e)),
+                };
+                let mut orphans = vec![];
+                if !finished {
+                    for path in marker.parquet_files {
+                        match self
+                            .object_store
+                            .delete(&ObjPath::from(path.as_str()))
+                            .await
+                        {
+                            Ok(()) => orphans.push(path),
+                            Err(object_store::Error::NotFound { .. }) => (),
+                            Err(e) => return Err(Error::from(e)),
+                        }
+                    }
+                }
+                self.object_store.delete(&marker_path).await?;
+                Ok(orphans)
+            };
+            let orphans = recover
+                .await
+                .map_err(|e| Error::RecoverInterruptedSnapshot {
+                    snapshot_sequence_number,
+                    source: Box::new(e),
+                })?;
+            info!(
+                ?snapshot_sequence_number,
+                n_orphans = orphans.len(),
+                "recovered interrupted snapshot"
+            );
+            deleted.extend(orphans);
+        }
+        Ok(deleted)
+    }
+
+    /// Moves the snapshot file out of the snapshots that are loaded on startup, so that the WAL
+    /// files it was taken from are replayed, e.g., when the server is recovered to a point before
+    /// the snapshot. The parquet files in the snapshot are left as they are.
+    ///
+    /// The index of the persisted files is deleted, since it may have been written after the
+    /// snapshot, with its files, and the files are loaded from the snapshots instead.
+    pub async fn discard_snapshot(
+        &self,
+        snapshot_sequence_number: SnapshotSequenceNumber,
+    ) -> Result<()> {
+        self.check_write_fence()?;
+        let host_prefix = self.host_identifier_prefix.as_str();
+        match self
+            .object_store
+            .delete(PersistedFilesIndexFilePath::new(host_prefix).as_ref())
+            .await
+        {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => (),
+            Err(e) => return Err(e.into()),
+        }
+        let snapshot_file_path = SnapshotInfoFilePath::new(host_prefix, snapshot_sequence_number);
+        self.object_store
+            .copy(
+                snapshot_file_path.as_ref(),
+                SnapshotInfoFilePath::discarded(host_prefix, snapshot_sequence_number).as_ref(),
+            )
+            .await?;
+        self.object_store
+            .delete(snapshot_file_path.as_ref())
+            .await?;
+        Ok(())
+    }
+
+    /// Persists a file of entries to the audit log with the given sequence number, which must be
+    /// higher than that of any existing audit log file
+    pub async fn persist_audit_log_entries(
+        &self,
+        sequence_number: u64,
+        entries: &[AuditLogEntry],
+    ) -> Result<()> {
+        self.check_write_fence()?;
+        let path = AuditLogFilePath::new(self.host_identifier_prefix.as_str(), sequence_number);
+        let json = self.encrypt(serde_json::to_vec_pretty(entries)?)?;
+        self.object_store.put(path.as_ref(), json.into()).await?;
+        Ok(())
+    }
+
+    /// The sequence number of the most recently persisted audit log file, if there is one
+    pub async fn last_audit_log_sequence_number(&self) -> Result<Option<u64>> {
+        Ok(self
+            .list_audit_log_files()
+            .await?
+            .last()
+            .map(|(sequence_number, _)| *sequence_number))
+    }
+
+    /// Loads all of the entries in the audit log, in the order that they were written
+    pub async fn load_audit_log(&self) -> Result<Vec<AuditLogEntry>> {
+        let mut entries = Vec::new();
+        for (_, path) in self.list_audit_log_files().await? {
+            let bytes = self.get_decrypted(&path).await?;
+            entries.extend(serde_json::from_slice::<Vec<AuditLogEntry>>(&bytes)?);
+        }
+        Ok(entries)
+    }
+
+    /// Persists the WAL settings that were changed while the server was running, replacing any
+    /// that were persisted before, so that they are used when the server is restarted
+    pub async fn persist_wal_config(&self, wal_config: &WalRuntimeConfig) -> Result<()> {
+        self.check_write_fence()?;
+        let path = WalConfigFilePath::new(self.host_identifier_prefix.as_str());
+        let json = self.encrypt(serde_json::to_vec_pretty(wal_config)?)?;
+        self.object_store.put(path.as_ref(), json.into()).await?;
+        Ok(())
+    }
+
+    /// Loads the WAL settings that were persisted with [`Persister::persist_wal_config`], if any
+    pub async fn load_wal_config(&self) -> Result<Option<WalRuntimeConfig>> {
+        let path = WalConfigFilePath::new(self.host_identifier_prefix.as_str());
+        match self.get_decrypted(&path).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(Error::ObjectStore(object_store::Error::NotFound { .. })) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Persists the contents of the last caches, replacing any that were persisted before
+    pub async fn persist_last_caches(&self, contents: &LastCacheContents) -> Result<()> {
+        self.check_write_fence()?;
+        let path = LastCacheFilePath::new(self.host_identifier_prefix.as_str());
+        let json = self.encrypt(serde_json::to_vec(contents)?)?;
+        self.object_store.put(path.as_ref(), json.into()).await?;
+        Ok(())
+    }
+
+    /// Loads the last cache contents that were persisted with
+    /// [`Persister::persist_last_caches`], if any
+    pub async fn load_last_caches(&self) -> Result<Option<LastCacheContents>> {
+        let path = LastCacheFilePath::new(self.host_identifier_prefix.as_str());
+        match self.get_decrypted(&path).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(Error::ObjectStore(object_store::Error::NotFound { .. })) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Persists the progress of the downsampling tasks, replacing that persisted before
+    pub async fn persist_downsampling_progress(
+        &self,
+        progress: &DownsamplingProgress,
+    ) -> Result<()> {
+        self.check_write_fence()?;
+        let path = DownsamplingProgressFilePath::new(self.host_identifier_prefix.as_str());
+        let json = self.encrypt(serde_json::to_vec_pretty(progress)?)?;
+        self.object_store.put(path.as_ref(), json.into()).await?;
+        Ok(())
+    }
+
+    /// Loads the progress of the downsampling tasks that was persisted with
+    /// [`Persister::persist_downsampling_progress`], if any
+    pub async fn load_downsampling_progress(&self) -> Result<Option<DownsamplingProgress>> {
+        let path = DownsamplingProgressFilePath::new(self.host_identifier_prefix.as_str());
+        match self.get_decrypted(&path).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(Error::ObjectStore(object_store::Error::NotFound { .. })) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Persists the manifest of a backup under the prefix of this persister, which is the last
+    /// file of a backup to be written
+    pub async fn persist_backup_manifest(&self, manifest: &BackupManifest) -> Result<()> {
+        self.check_write_fence()?;
+        let path = BackupManifestFilePath::new(self.host_identifier_prefix.as_str());
+        let json = self.encrypt(serde_json::to_vec_pretty(manifest)?)?;
+        self.object_store.put(path.as_ref(), json.into()).await?;
+        Ok(())
+    }
+
+    /// Loads the manifest of a backup under the prefix of this persister, if there is one
+    pub async fn load_backup_manifest(&self) -> Result<Option<BackupManifest>> {
+        let path = BackupManifestFilePath::new(self.host_identifier_prefix.as_str());
+        match self.get_decrypted(&path).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(Error::ObjectStore(object_store::Error::NotFound { .. })) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// List the catalog files, i.e., the versions of the catalog persisted in full, sorted by
+    /// their sequence number
+    async fn list_catalog_files(&self) -> Result<Vec<(CatalogSequenceNumber, ObjPath)>> {
+        let mut files = Vec::new();
+        let mut list = self
+            .object_store
+            .list(Some(&CatalogFilePath::dir(&self.host_identifier_prefix)));
+        while let Some(item) = list.next().await {
+            let location = item?.location;
+            if let Some(sequence_number) = CatalogFilePath::sequence_number(&location) {
+                files.push((sequence_number, location));
+            }
+        }
+        files.sort_unstable_by_key(|(sequence_number, _)| *sequence_number);
+        Ok(files)
+    }
+
+    /// List the catalog delta files, sorted by their sequence number
+    async fn list_catalog_delta_files(&self) -> Result<Vec<(CatalogSequenceNumber, ObjPath)>> {
+        let mut files = Vec::new();
+        let mut list = self.object_store.list(Some(&CatalogDeltaFilePath::dir(
+            &self.host_identifier_prefix,
+        )));
+        while let Some(item) = list.next().await {
+            let location = item?.location;
+            if let Some(sequence_number) = CatalogDeltaFilePath::sequence_number(&location) {
+                files.push((sequence_number, location));
+            }
+        }
+        files.sort_unstable_by_key(|(sequence_number, _)| *sequence_number);
+        Ok(files)
+    }
+
+    async fn list_audit_log_files(&self) -> Result<Vec<(u64, ObjPath)>> {
+        let mut files = Vec::new();
+        let mut list = self
+            .object_store
+            .list(Some(&AuditLogFilePath::dir(&self.host_identifier_prefix)));
+        while let Some(item) = list.next().await {
+            let location = item?.location;
+            if let Some(sequence_number) = AuditLogFilePath::sequence_number(&location) {
+                files.push((sequence_number, location));
+            }
+        }
+        files.sort_unstable_by_key(|(sequence_number, _)| *sequence_number);
+        Ok(files)
+    }
+
+    /// Encrypt the contents of a file to persist, if there is a key provider
+    fn encrypt(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(encryption::maybe_encrypt(
+            self.key_provider.as_deref(),
+            data,
+        )?)
+    }
+
+    /// Get a file from object storage, decrypting it if it is encrypted
+    async fn get_decrypted(&self, path: &ObjPath) -> Result<Bytes> {
+        let bytes = self.object_store.get(path).await?.bytes().await?;
+        Ok(encryption::maybe_decrypt(
+            self.key_provider.as_deref(),
+            bytes,
+        )?)
+    }
+
     /// Writes a [`SendableRecordBatchStream`] to the Parquet format and persists it to Object Store
-    /// at the given path. Returns the number of bytes written and the file metadata.
+    /// at the given path, with bloom filters for the given columns. Returns the number of bytes
+    /// written and the file metadata.
     pub async fn persist_parquet_file(
         &self,
         path: ParquetFilePath,
         record_batch: SendableRecordBatchStream,
+        bloom_filters: &BloomFilters,
     ) -> Result<(u64, FileMetaData)> {
-        let parquet = self.serialize_to_parquet(record_batch).await?;
-        let bytes_written = parquet.bytes.len() as u64;
-        self.object_store
-            .put(path.as_ref(), parquet.bytes.into())
-            .await?;
-
-        Ok((bytes_written, parquet.meta_data))
+        self.check_write_fence()?;
+        let start = Instant::now();
+        let result = async {
+            let parquet = self
+                .serialize_to_parquet(record_batch, bloom_filters)
+                .await?;
+            let bytes_written = parquet.bytes.len() as u64;
+            self.object_store
+                .put(path.as_ref(), parquet.bytes.into())
+                .await?;
+            Ok::<_, Error>((bytes_written, parquet.meta_data))
+        }
+        .await;
+        match &result {
+            Ok(_) => self.metrics.persist_duration.record(start.elapsed()),
+            Err(_) => self.metrics.persist_failures.inc(1),
+        }
+        result
     }
 
     /// Returns the configured `ObjectStore` that data is loaded from and persisted to.
@@ -308,6 +1385,8 @@ impl Persister {
 pub async fn serialize_to_parquet(
     mem_pool: Arc<dyn MemoryPool>,
     batches: SendableRecordBatchStream,
+    config: &PersisterConfig,
+    bloom_filters: &BloomFilters,
 ) -> Result<ParquetBytes> {
     // The ArrowWriter::write() call will return an error if any subsequent
     // batch does not match this schema, enforcing schema uniformity.
@@ -319,7 +1398,12 @@ pub async fn serialize_to_parquet(
 
     // Construct the arrow serializer with the metadata as part of the parquet
     // file properties.
-    let mut writer = TrackedMemoryArrowWriter::try_new(&mut bytes, Arc::clone(&schema), mem_pool)?;
+    let mut writer = TrackedMemoryArrowWriter::try_new_with_properties(
+        &mut bytes,
+        Arc::clone(&schema),
+        mem_pool,
+        config.writer_properties(bloom_filters)?,
+    )?;
 
     while let Some(batch) = stream.try_next().await? {
         writer.write(batch)?;
@@ -351,16 +1435,67 @@ pub struct TrackedMemoryArrowWriter<W: Write + Send> {
     reservation: MemoryReservation,
 }
 
+/// The format that a catalog file was written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CatalogFormat {
+    Binary,
+    Json,
+}
+
+impl CatalogFormat {
+    fn of(bytes: &[u8]) -> Self {
+        if bytes.starts_with(CATALOG_FILE_IDENTIFIER) {
+            Self::Binary
+        } else {
+            Self::Json
+        }
+    }
+}
+
+/// Serialize the contents of a catalog, or catalog delta, file in the latest version of the
+/// binary format
+fn serialize_catalog_file<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(4096);
+    buf.extend_from_slice(CATALOG_FILE_IDENTIFIER);
+    buf.push(CATALOG_FORMAT_VERSION);
+    rmp_serde::encode::write_named(&mut buf, value)?;
+    Ok(buf)
+}
+
+/// Deserialize the contents of a catalog, or catalog delta, file written in any version of the
+/// binary format, or as JSON
+fn deserialize_catalog_file<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    let Some(rest) = bytes.strip_prefix(CATALOG_FILE_IDENTIFIER) else {
+        return Ok(serde_json::from_slice(bytes)?);
+    };
+    match rest.split_first() {
+        Some((&CATALOG_FORMAT_VERSION, data)) => Ok(rmp_serde::from_slice(data)?),
+        Some((&version, _)) => Err(Error::UnsupportedCatalogFormatVersion(version)),
+        None => Err(Error::CatalogFileTruncated),
+    }
+}
+
 /// Parquet row group write size
 pub const ROW_GROUP_WRITE_SIZE: usize = 1024 * 1024;
 
 impl<W: Write + Send> TrackedMemoryArrowWriter<W> {
-    /// create a new `TrackedMemoryArrowWriter<`
+    /// create a new `TrackedMemoryArrowWriter<` with the default [`PersisterConfig`]
     pub fn try_new(sink: W, schema: SchemaRef, mem_pool: Arc<dyn MemoryPool>) -> Result<Self> {
-        let props = WriterProperties::builder()
-            .set_compression(Compression::ZSTD(Default::default()))
-            .set_max_row_group_size(ROW_GROUP_WRITE_SIZE)
-            .build();
+        Self::try_new_with_properties(
+            sink,
+            schema,
+            mem_pool,
+            PersisterConfig::default().writer_properties(&BloomFilters::default())?,
+        )
+    }
+
+    /// create a new `TrackedMemoryArrowWriter<` that writes with the given properties
+    pub fn try_new_with_properties(
+        sink: W,
+        schema: SchemaRef,
+        mem_pool: Arc<dyn MemoryPool>,
+        props: WriterProperties,
+    ) -> Result<Self> {
         let inner = ArrowWriter::try_new(sink, schema, Some(props))?;
         let consumer = MemoryConsumer::new("InfluxDB3 ParquetWriter (TrackedMemoryArrowWriter)");
         let reservation = consumer.register(&mem_pool);
@@ -396,13 +1531,19 @@ impl<W: Write + Send> TrackedMemoryArrowWriter<W> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::paths::JSON_CATALOG_FILE_EXTENSION;
     use crate::ParquetFileId;
-    use influxdb3_catalog::catalog::CatalogSequenceNumber;
+    use influxdb3_catalog::catalog::{DatabaseSchema, TableDefinition};
     use influxdb3_id::{ColumnId, DbId, TableId};
-    use influxdb3_wal::{SnapshotSequenceNumber, WalFileSequenceNumber};
+    use influxdb3_wal::encryption::{EncryptionKey, StaticKeyProvider};
+    use influxdb3_wal::{
+        create, FieldDataType, LastCacheDefinition, LastCacheValueColumnsDef,
+        SnapshotSequenceNumber, WalFileSequenceNumber,
+    };
     use object_store::memory::InMemory;
     use observability_deps::tracing::info;
     use pretty_assertions::assert_eq;
+    use schema::{InfluxColumnType, InfluxFieldType};
     use {
         arrow::array::Int32Array, arrow::datatypes::DataType, arrow::datatypes::Field,
         arrow::datatypes::Schema, chrono::Utc,
@@ -423,6 +1564,68 @@ mod tests {
         persister.persist_catalog(&catalog).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn recover_interrupted_snapshots_deletes_orphans() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let persister = Persister::new(Arc::clone(&object_store), "test_host");
+        let put = |path: &str| {
+            let object_store = Arc::clone(&object_store);
+            let path = ObjPath::from(path);
+            async move {
+                object_store
+                    .put(&path, Bytes::from_static(b"data").into())
+                    .await
+                    .unwrap();
+            }
+        };
+
+        // snapshot 1 finished, but its marker was left behind:
+        put("test_host/dbs/1.parquet").await;
+        put(&SnapshotInfoFilePath::new("test_host", SnapshotSequenceNumber::new(1)).to_string())
+            .await;
+        persister
+            .persist_snapshot_marker(&SnapshotMarker {
+                snapshot_sequence_number: SnapshotSequenceNumber::new(1),
+                parquet_files: vec!["test_host/dbs/1.parquet".to_string()],
+            })
+            .await
+            .unwrap();
+        // snapshot 2 was interrupted after persisting one of its two files:
+        put("test_host/dbs/2.parquet").await;
+        persister
+            .persist_snapshot_marker(&SnapshotMarker {
+                snapshot_sequence_number: SnapshotSequenceNumber::new(2),
+                parquet_files: vec![
+                    "test_host/dbs/2.parquet".to_string(),
+                    "test_host/dbs/3.parquet".to_string(),
+                ],
+            })
+            .await
+            .unwrap();
+
+        let deleted = persister.recover_interrupted_snapshots().await.unwrap();
+        assert_eq!(vec!["test_host/dbs/2.parquet".to_string()], deleted);
+        let mut remaining = object_store
+            .list(None)
+            .map_ok(|meta| meta.location.to_string())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        remaining.sort();
+        assert_eq!(
+            vec![
+                "test_host/dbs/1.parquet".to_string(),
+                SnapshotInfoFilePath::new("test_host", SnapshotSequenceNumber::new(1)).to_string(),
+            ],
+            remaining
+        );
+        assert!(persister
+            .recover_interrupted_snapshots()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
     #[tokio::test]
     async fn persist_and_load_newest_catalog() {
         let host_id: Arc<str> = Arc::from("sample-host-id");
@@ -452,6 +1655,326 @@ mod tests {
         assert!(!catalog.db_exists(DbId::from(0)));
     }
 
+    #[tokio::test]
+    async fn persist_and_load_encrypted_catalog_and_snapshot() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let key_provider = Arc::new(StaticKeyProvider::new(EncryptionKey::new(
+            [1; encryption::KEY_LEN],
+        )));
+        let persister =
+            Persister::new(Arc::clone(&object_store), "test_host").with_key_provider(key_provider);
+        let catalog = Catalog::new("test_host".into(), "sample-instance-id".into());
+        let _ = catalog.db_or_create("my_db");
+        persister.persist_catalog(&catalog).await.unwrap();
+        let snapshot = PersistedSnapshot {
+            host_id: "test_host".to_string(),
+            next_file_id: ParquetFileId::from(0),
+            next_db_id: DbId::from(1),
+            next_table_id: TableId::from(1),
+            next_column_id: ColumnId::from(1),
+            snapshot_sequence_number: SnapshotSequenceNumber::new(3),
+            wal_file_sequence_number: WalFileSequenceNumber::new(0),
+            catalog_sequence_number: CatalogSequenceNumber::new(0),
+            databases: HashMap::new(),
+            min_time: 0,
+            max_time: 1,
+            row_count: 0,
+            parquet_size_bytes: 0,
+        };
+        persister.persist_snapshot(&snapshot).await.unwrap();
+
+        // nothing in the files can be read without the key:
+        let paths = object_store
+            .list(None)
+            .map_ok(|meta| meta.location)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(2, paths.len());
+        for path in paths {
+            let bytes = object_store
+                .get(&path)
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap();
+            assert!(bytes.starts_with(encryption::ENCRYPTED_FILE_IDENTIFIER));
+            assert!(!bytes.windows(9).any(|w| w == b"test_host"));
+        }
+
+        let catalog = persister.load_catalog().await.unwrap().unwrap();
+        assert!(catalog.db_exists(DbId::from(0)));
+        let snapshots = persister.load_snapshots(10).await.unwrap();
+        assert_eq!(
+            SnapshotSequenceNumber::new(3),
+            snapshots[0].snapshot_sequence_number
+        );
+
+        let unencrypted_persister = Persister::new(object_store, "test_host");
+        assert!(matches!(
+            unencrypted_persister.load_catalog().await,
+            Err(Error::Encryption(encryption::Error::NoKeyProvider))
+        ));
+    }
+
+    #[tokio::test]
+    async fn persist_and_load_catalog_deltas() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let persister = &Persister::new(Arc::clone(&object_store), "test_host")
+            .with_catalog_checkpoint_interval(2)
+            .with_retained_catalog_versions(1);
+        let catalog = &persister.load_or_create_catalog().await.unwrap();
+        let db_id = DbId::new();
+        let create_table = |table_name: &str| {
+            let op = create::catalog_batch_op(
+                db_id,
+                "db",
+                0,
+                [create::create_table_op(
+                    db_id,
+                    "db",
+                    TableId::new(),
+                    table_name,
+                    [create::field_def(
+                        ColumnId::new(),
+                        "time",
+                        FieldDataType::Timestamp,
+                    )],
+                )],
+            );
+            catalog
+                .replay_catalog_batch(op.as_catalog().unwrap())
+                .unwrap();
+        };
+        let persist_changes = || async move {
+            let inner_catalog = catalog.clone_inner();
+            let delta = inner_catalog.unpersisted_delta();
+            let checkpoint =
+                inner_catalog.deltas_since_checkpoint() >= persister.catalog_checkpoint_interval();
+            if checkpoint {
+                persister.persist_catalog(catalog).await.unwrap();
+            } else {
+                persister.persist_catalog_delta(&delta).await.unwrap();
+            }
+            catalog.set_persisted(&delta, checkpoint);
+            delta
+        };
+        let delta_files = || {
+            let object_store = Arc::clone(&object_store);
+            async move {
+                object_store
+                    .list(Some(&CatalogDeltaFilePath::dir("test_host")))
+                    .map(|item| item.unwrap().location)
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        };
+
+        // each change is persisted as a delta, which is applied to the catalog when it is loaded:
+        for table_name in ["cpu", "mem"] {
+            create_table(table_name);
+            let delta = persist_changes().await;
+            assert_eq!(1, delta.batches.len());
+            assert!(!catalog.is_updated());
+            let loaded = persister.load_catalog().await.unwrap().unwrap();
+            assert_eq!(catalog.sequence_number(), loaded.sequence_number());
+            assert_eq!(
+                catalog.db_schema("db"),
+                Catalog::from_inner(loaded).db_schema("db")
+            );
+        }
+        assert_eq!(2, delta_files().await.len());
+
+        // until the catalog is persisted in full, and the deltas are compacted into it:
+        create_table("disk");
+        persist_changes().await;
+        assert!(delta_files().await.is_empty());
+        let loaded = persister.load_catalog().await.unwrap().unwrap();
+        assert_eq!(0, loaded.deltas_since_checkpoint());
+        assert_eq!(3, loaded.table_count());
+        assert_eq!(
+            catalog.db_schema("db"),
+            Catalog::from_inner(loaded).db_schema("db")
+        );
+    }
+
+    #[tokio::test]
+    async fn load_catalog_at_sequence_number() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let persister = &Persister::new(Arc::clone(&object_store), "test_host")
+            .with_catalog_checkpoint_interval(1)
+            .with_retained_catalog_versions(2);
+        let catalog = &persister.load_or_create_catalog().await.unwrap();
+        let db_id = DbId::new();
+        let create_table = |table_name: &str| {
+            let op = create::catalog_batch_op(
+                db_id,
+                "db",
+                0,
+                [create::create_table_op(
+                    db_id,
+                    "db",
+                    TableId::new(),
+                    table_name,
+                    [create::field_def(
+                        ColumnId::new(),
+                        "time",
+                        FieldDataType::Timestamp,
+                    )],
+                )],
+            );
+            catalog
+                .replay_catalog_batch(op.as_catalog().unwrap())
+                .unwrap();
+        };
+        let persist_changes = || async move {
+            let inner_catalog = catalog.clone_inner();
+            let delta = inner_catalog.unpersisted_delta();
+            let checkpoint =
+                inner_catalog.deltas_since_checkpoint() >= persister.catalog_checkpoint_interval();
+            if checkpoint {
+                persister.persist_catalog(catalog).await.unwrap();
+            } else {
+                persister.persist_catalog_delta(&delta).await.unwrap();
+            }
+            catalog.set_persisted(&delta, checkpoint);
+        };
+        let table_count_at = |sequence_number: u32| async move {
+            persister
+                .load_catalog_at(CatalogSequenceNumber::new(sequence_number))
+                .await
+                .unwrap()
+                .map(|catalog| {
+                    assert_eq!(sequence_number, catalog.sequence_number().as_u32());
+                    catalog.table_count()
+                })
+        };
+
+        // the changes alternate between being persisted as deltas, and in full:
+        for table_name in ["cpu", "mem", "disk", "net"] {
+            create_table(table_name);
+            persist_changes().await;
+        }
+        // two changes are persisted in a single delta:
+        create_table("a");
+        create_table("b");
+        persist_changes().await;
+        assert_eq!(6, catalog.sequence_number().as_u32());
+
+        // the catalog can be loaded as it was at any point since the oldest retained version:
+        assert_eq!(None, table_count_at(0).await);
+        assert_eq!(None, table_count_at(1).await);
+        assert_eq!(Some(2), table_count_at(2).await);
+        assert_eq!(Some(3), table_count_at(3).await);
+        assert_eq!(Some(4), table_count_at(4).await);
+        assert_eq!(Some(5), table_count_at(5).await);
+        assert_eq!(Some(6), table_count_at(6).await);
+    }
+
+    #[tokio::test]
+    async fn export_and_import_catalog() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let table = |table_id: u32, table_name: &str, first_column_id: u32| {
+            let column_id = |offset: u32| ColumnId::from(first_column_id + offset);
+            let mut table_def = TableDefinition::new(
+                TableId::from(table_id),
+                table_name.into(),
+                vec![
+                    (column_id(0), "host".into(), InfluxColumnType::Tag),
+                    (
+                        column_id(1),
+                        "usage".into(),
+                        InfluxColumnType::Field(InfluxFieldType::Float),
+                    ),
+                    (column_id(2), "time".into(), InfluxColumnType::Timestamp),
+                ],
+                Some(vec![column_id(0)]),
+            )
+            .unwrap();
+            table_def.add_last_cache(
+                LastCacheDefinition::new_with_explicit_value_columns(
+                    TableId::from(table_id),
+                    table_name,
+                    "cache",
+                    vec![column_id(0)],
+                    vec![column_id(1), column_id(2)],
+                    1,
+                    60,
+                )
+                .unwrap(),
+            );
+            Arc::new(table_def)
+        };
+
+        // the exported host, and the host it is imported into, use the same ids:
+        for (host, db_name) in [("host_a", "db_a"), ("host_b", "db_b")] {
+            let catalog = Catalog::new(host.into(), "sample-instance-id".into());
+            let mut db = DatabaseSchema::new(DbId::from(0), db_name.into());
+            db.insert_table(TableId::from(0), table(0, "cpu", 0));
+            catalog.insert_database(db);
+            Persister::new(Arc::clone(&object_store), host)
+                .persist_catalog(&catalog)
+                .await
+                .unwrap();
+        }
+
+        let export = Persister::new(Arc::clone(&object_store), "host_a")
+            .export_catalog()
+            .await
+            .unwrap()
+            .expect("there was a catalog to export");
+        let persister = Persister::new(Arc::clone(&object_store), "host_b");
+        let catalog = persister.import_catalog(&export).await.unwrap();
+        assert_eq!(&*catalog.host_id(), "host_b");
+        assert_eq!(Some(DbId::from(0)), catalog.db_name_to_id("db_b"));
+        assert_eq!(Some(DbId::from(1)), catalog.db_name_to_id("db_a"));
+
+        // the ids of the imported tables and columns follow on from those on the host, and are
+        // used consistently by the series key and last caches:
+        let db = catalog.db_schema("db_a").unwrap();
+        let (table_id, table_def) = db.table_definition_and_id("cpu").unwrap();
+        assert_eq!(TableId::from(1), table_id);
+        let mut column_ids = table_def.columns.keys().copied().collect::<Vec<_>>();
+        column_ids.sort();
+        assert_eq!(
+            vec![ColumnId::from(3), ColumnId::from(4), ColumnId::from(5)],
+            column_ids
+        );
+        let column_id = |name: &str| table_def.column_name_to_id(name).unwrap();
+        assert_eq!(Some(vec![column_id("host")]), table_def.series_key);
+        let (_, last_cache) = table_def.last_caches().next().unwrap();
+        assert_eq!(table_id, last_cache.table_id);
+        assert_eq!(vec![column_id("host")], last_cache.key_columns);
+        assert_eq!(
+            LastCacheValueColumnsDef::Explicit {
+                columns: vec![column_id("usage"), column_id("time")]
+            },
+            last_cache.value_columns
+        );
+
+        // the import was persisted:
+        let loaded = Catalog::from_inner(persister.load_catalog().await.unwrap().unwrap());
+        assert_eq!(catalog.sequence_number(), loaded.sequence_number());
+        assert_eq!(Some(db), loaded.db_schema("db_a"));
+
+        // databases that already exist cannot be imported again:
+        assert!(matches!(
+            persister.import_catalog(&export).await,
+            Err(Error::Catalog(
+                influxdb3_catalog::catalog::Error::DatabaseAlreadyExists(_)
+            ))
+        ));
+        let mut export: serde_json::Value = serde_json::from_slice(&export).unwrap();
+        export["version"] = 2.into();
+        assert!(matches!(
+            persister
+                .import_catalog(&serde_json::to_vec(&export).unwrap())
+                .await,
+            Err(Error::UnsupportedCatalogExportVersion(2))
+        ));
+    }
+
     #[tokio::test]
     async fn persist_snapshot_info_file() {
         let local_disk =
@@ -602,6 +2125,55 @@ mod tests {
         assert_eq!(snapshots[0].catalog_sequence_number.as_u32(), 9000);
     }
 
+    #[tokio::test]
+    async fn load_snapshots_in_pages() {
+        let persister = Persister::new(Arc::new(InMemory::new()), "test_host");
+        for id in 0..5 {
+            let info_file = PersistedSnapshot {
+                host_id: "test_host".to_string(),
+                next_file_id: ParquetFileId::from(id),
+                next_db_id: DbId::from(1),
+                next_table_id: TableId::from(1),
+                next_column_id: ColumnId::from(1),
+                snapshot_sequence_number: SnapshotSequenceNumber::new(id),
+                wal_file_sequence_number: WalFileSequenceNumber::new(id),
+                catalog_sequence_number: CatalogSequenceNumber::new(id as u32),
+                databases: HashMap::new(),
+                min_time: 0,
+                max_time: 1,
+                row_count: 0,
+                parquet_size_bytes: 0,
+            };
+            persister.persist_snapshot(&info_file).await.unwrap();
+        }
+
+        let mut sequence_numbers = vec![];
+        let mut continuation = None;
+        loop {
+            let page = persister
+                .load_snapshots_page(continuation.as_ref(), 2)
+                .await
+                .unwrap();
+            assert!(page.snapshots.len() <= 2);
+            sequence_numbers.extend(
+                page.snapshots
+                    .iter()
+                    .map(|s| s.snapshot_sequence_number.as_u64()),
+            );
+            match page.continuation {
+                Some(next) => continuation = Some(next),
+                None => break,
+            }
+        }
+        // the pages go from the most recent snapshot to the oldest, without gaps or repeats:
+        assert_eq!(sequence_numbers, vec![4, 3, 2, 1, 0]);
+
+        // a page that has the last of the snapshots has no continuation:
+        let page = persister.load_snapshots_page(None, 5).await.unwrap();
+        assert_eq!(page.snapshots.len(), 5);
+        assert!(page.continuation.is_none());
+    }
+
     #[tokio::test]
     // This test makes sure that the proper next_file_id is used if a parquet file
     // is added
@@ -633,6 +2205,9 @@ mod tests {
                 chunk_time: 5,
                 min_time: 0,
                 max_time: 1,
+                indexes: Default::default(),
+                column_ranges: Default::default(),
+                sort_key: Default::default(),
             },
         );
         persister.persist_snapshot(&info_file).await.unwrap();
@@ -673,7 +2248,7 @@ mod tests {
         stream_builder.tx().send(Ok(batch2)).await.unwrap();
 
         let parquet = persister
-            .serialize_to_parquet(stream_builder.build())
+            .serialize_to_parquet(stream_builder.build(), &BloomFilters::default())
             .await
             .unwrap();
 
@@ -681,6 +2256,90 @@ mod tests {
         assert_eq!(parquet.meta_data.num_rows, 10);
     }
 
+    #[tokio::test]
+    async fn parquet_files_are_written_with_config() {
+        let local_disk =
+            LocalFileSystem::new_with_prefix(test_helpers::tmp_dir().unwrap()).unwrap();
+        let persister =
+            Persister::new(Arc::new(local_disk), "test_host").with_config(PersisterConfig {
+                row_group_size: 4,
+                compression: "none".parse().unwrap(),
+                statistics: "none".parse().unwrap(),
+                ..Default::default()
+            });
+
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+        let stream_builder = RecordBatchReceiverStreamBuilder::new(schema.clone(), 5);
+        let id_array = Int32Array::from((1..=10).collect::<Vec<_>>());
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(id_array)]).unwrap();
+        stream_builder.tx().send(Ok(batch)).await.unwrap();
+
+        let parquet = persister
+            .serialize_to_parquet(stream_builder.build(), &BloomFilters::default())
+            .await
+            .unwrap();
+
+        assert_eq!(parquet.meta_data.num_rows, 10);
+        assert_eq!(parquet.meta_data.row_groups.len(), 3);
+        for row_group in &parquet.meta_data.row_groups {
+            let column_meta = row_group.columns[0].meta_data.as_ref().unwrap();
+            assert_eq!(
+                column_meta.codec,
+                parquet::format::CompressionCodec::UNCOMPRESSED
+            );
+            assert!(column_meta.statistics.is_none());
+        }
+
+        // a zstd level outside of 1 to 22 is rejected:
+        assert!(PersisterConfig {
+            zstd_level: 23,
+            ..Default::default()
+        }
+        .writer_properties(&BloomFilters::default())
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn parquet_files_have_page_index_and_bloom_filters() {
+        let local_disk =
+            LocalFileSystem::new_with_prefix(test_helpers::tmp_dir().unwrap()).unwrap();
+        let persister = Persister::new(Arc::new(local_disk), "test_host");
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, false),
+            Field::new("id", DataType::Int32, false),
+        ]));
+        let stream_builder = RecordBatchReceiverStreamBuilder::new(schema.clone(), 5);
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(arrow::array::StringArray::from(vec!["a", "b", "a"])),
+                Arc::new(Int32Array::from(vec![1, 2, 3])),
+            ],
+        )
+        .unwrap();
+        stream_builder.tx().send(Ok(batch)).await.unwrap();
+
+        let parquet = persister
+            .serialize_to_parquet(
+                stream_builder.build(),
+                &BloomFilters {
+                    columns: vec!["host".to_string()],
+                    distinct_values: 2,
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            crate::ParquetFileIndexes {
+                page_index: true,
+                bloom_filter_columns: vec!["host".to_string()],
+            },
+            crate::ParquetFileIndexes::from_meta(&parquet.meta_data)
+        );
+    }
+
     #[tokio::test]
     async fn persist_and_load_parquet_bytes() {
         let local_disk =
@@ -709,7 +2368,7 @@ mod tests {
             WalFileSequenceNumber::new(1),
         );
         let (bytes_written, meta) = persister
-            .persist_parquet_file(path.clone(), stream_builder.build())
+            .persist_parquet_file(path.clone(), stream_builder.build(), &Default::default())
             .await
             .unwrap();
 
@@ -752,18 +2411,63 @@ mod tests {
             LocalFileSystem::new_with_prefix(test_helpers::tmp_dir().unwrap()).unwrap();
         info!(local_disk = ?local_disk, "Using local disk");
 
-        let catalog_path = CatalogFilePath::new("test_host", CatalogSequenceNumber::new(0));
+        let json_catalog_path = ObjPath::from(format!(
+            "test_host/catalogs/{}.{JSON_CATALOG_FILE_EXTENSION}",
+            u64::MAX
+        ));
         let _ = local_disk
-            .put(&catalog_path, catalog_json.into())
+            .put(&json_catalog_path, catalog_json.into())
             .await
             .unwrap();
 
         // read json as catalog
-        let persister = Persister::new(Arc::new(local_disk), "test_host");
+        let object_store: Arc<dyn ObjectStore> = Arc::new(local_disk);
+        let persister = Persister::new(Arc::clone(&object_store), "test_host");
         let catalog = persister.load_or_create_catalog().await.unwrap();
         assert_eq!(
             &*catalog.instance_id(),
             "24b1e1bf-b301-4101-affa-e3d668fe7d20"
         );
+
+        // the catalog is migrated to the binary format, which is loaded from then on:
+        let catalog_path = CatalogFilePath::new("test_host", CatalogSequenceNumber::new(0));
+        let bytes = object_store
+            .get(&catalog_path)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert!(bytes.starts_with(CATALOG_FILE_IDENTIFIER));
+        let loaded = persister.load_catalog().await.unwrap().unwrap();
+        assert_eq!(
+            &*Catalog::from_inner(loaded).instance_id(),
+            "24b1e1bf-b301-4101-affa-e3d668fe7d20"
+        );
+    }
+
+    #[test]
+    fn catalog_file_format_versions() {
+        let catalog = Catalog::new("test_host".into(), "instance".into());
+        let bytes = serialize_catalog_file(&catalog).unwrap();
+        assert_eq!(CatalogFormat::Binary, CatalogFormat::of(&bytes));
+        let deserialized: InnerCatalog = deserialize_catalog_file(&bytes).unwrap();
+        assert_eq!(catalog.sequence_number(), deserialized.sequence_number());
+        assert_eq!(
+            catalog.instance_id(),
+            Catalog::from_inner(deserialized).instance_id()
+        );
+
+        // files in a version of the format from a newer server cannot be read:
+        let mut newer = bytes.clone();
+        newer[CATALOG_FILE_IDENTIFIER.len()] = CATALOG_FORMAT_VERSION + 1;
+        assert!(matches!(
+            deserialize_catalog_file::<InnerCatalog>(&newer),
+            Err(Error::UnsupportedCatalogFormatVersion(v)) if v == CATALOG_FORMAT_VERSION + 1
+        ));
+        assert!(matches!(
+            deserialize_catalog_file::<InnerCatalog>(CATALOG_FILE_IDENTIFIER),
+            Err(Error::CatalogFileTruncated)
+        ));
     }
 }
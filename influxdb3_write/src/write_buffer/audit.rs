@@ -0,0 +1,140 @@
+//! An append-only log of the changes made to the catalog, persisted to object storage, so that
+//! it is possible to find out when, and how, tables, columns, and caches were created or deleted,
+//! and the configuration of databases was changed
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use influxdb3_wal::{CatalogBatch, CatalogOp};
+use observability_deps::tracing::error;
+use serde::{Deserialize, Serialize};
+
+use crate::persister::{self, Persister};
+
+/// What requested a change to the catalog
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditSource {
+    /// A write that added databases, tables, or columns to the catalog
+    Write,
+    /// A request to the API, e.g., to create a last cache, or to change the configuration of a
+    /// database
+    Api,
+}
+
+impl AuditSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Write => "write",
+            Self::Api => "api",
+        }
+    }
+}
+
+/// A single change to the catalog that has been recorded in the audit log
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AuditLogEntry {
+    /// The time that the change was made, in nanoseconds since the epoch
+    pub time_ns: i64,
+    pub database_name: Arc<str>,
+    pub source: AuditSource,
+    pub op: CatalogOp,
+}
+
+impl AuditLogEntry {
+    /// The name of the kind of change that was made, e.g., `create_table`
+    pub fn operation(&self) -> &'static str {
+        match &self.op {
+            CatalogOp::CreateDatabase(_) => "create_database",
+            CatalogOp::CreateTable(_) => "create_table",
+            CatalogOp::AddFields(_) => "add_fields",
+            CatalogOp::CreateLastCache(_) => "create_last_cache",
+            CatalogOp::DeleteLastCache(_) => "delete_last_cache",
+            CatalogOp::UpdateLastCache(_) => "update_last_cache",
+            CatalogOp::CreateDistinctCache(_) => "create_distinct_cache",
+            CatalogOp::DeleteDistinctCache(_) => "delete_distinct_cache",
+            CatalogOp::SetRetentionPeriod(_) => "set_retention_period",
+            CatalogOp::SetWriteTimeLimits(_) => "set_write_time_limits",
+            CatalogOp::SetSchemaLimits(_) => "set_schema_limits",
+            CatalogOp::SetCardinalityLimits(_) => "set_cardinality_limits",
+            CatalogOp::SetSchemaMode(_) => "set_schema_mode",
+            CatalogOp::SetFieldCoercion(_) => "set_field_coercion",
+            CatalogOp::SetCompatibilityMode(_) => "set_compatibility_mode",
+            CatalogOp::SetSeriesKeyPolicy(_) => "set_series_key_policy",
+            CatalogOp::SetIdentifierPolicy(_) => "set_identifier_policy",
+            CatalogOp::SetWriteRateLimit(_) => "set_write_rate_limit",
+            CatalogOp::SetGen1Duration(_) => "set_gen1_duration",
+            CatalogOp::RenameDatabase(_) => "rename_database",
+            CatalogOp::RenameTable(_) => "rename_table",
+            CatalogOp::MarkTableDeleted(_) => "mark_table_deleted",
+            CatalogOp::UndeleteTable(_) => "undelete_table",
+            CatalogOp::HardDeleteTable(_) => "hard_delete_table",
+            CatalogOp::DropColumn(_) => "drop_column",
+            CatalogOp::RenameColumn(_) => "rename_column",
+            CatalogOp::SetDatabaseMetadata(_) => "set_database_metadata",
+            CatalogOp::SetTableMetadata(_) => "set_table_metadata",
+            CatalogOp::SetColumnDefault(_) => "set_column_default",
+            CatalogOp::CreateDownsamplingTask(_) => "create_downsampling_task",
+            CatalogOp::DeleteDownsamplingTask(_) => "delete_downsampling_task",
+        }
+    }
+}
+
+/// Records the changes made to the catalog in the audit log. Each call to [`AuditLog::record`]
+/// persists a new file, numbered one higher than the last, so existing files are never changed.
+#[derive(Debug)]
+pub struct AuditLog {
+    persister: Arc<Persister>,
+    next_sequence_number: AtomicU64,
+}
+
+impl AuditLog {
+    /// Create an audit log that appends to any existing audit log of the persister
+    pub(crate) async fn new(persister: Arc<Persister>) -> Result<Self, persister::Error> {
+        let next_sequence_number = persister
+            .last_audit_log_sequence_number()
+            .await?
+            .map(|n| n + 1)
+            .unwrap_or_default();
+        Ok(Self {
+            persister,
+            next_sequence_number: AtomicU64::new(next_sequence_number),
+        })
+    }
+
+    /// Record the ops in the catalog batches, which have already been written to the WAL. A
+    /// failure to persist the entries is logged, rather than returned, since the change has
+    /// already been made by then.
+    pub(crate) async fn record(&self, catalog_batches: &[CatalogBatch], source: AuditSource) {
+        let entries = catalog_batches
+            .iter()
+            .flat_map(|batch| {
+                batch.ops.iter().map(|op| AuditLogEntry {
+                    time_ns: batch.time_ns,
+                    database_name: Arc::clone(&batch.database_name),
+                    source,
+                    op: op.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        if entries.is_empty() {
+            return;
+        }
+
+        let sequence_number = self.next_sequence_number.fetch_add(1, Ordering::SeqCst);
+        if let Err(error) = self
+            .persister
+            .persist_audit_log_entries(sequence_number, &entries)
+            .await
+        {
+            error!(%error, ?entries, "failed to persist audit log entries");
+        }
+    }
+
+    /// Load all of the entries in the audit log, in the order they were recorded
+    pub async fn entries(&self) -> Result<Vec<AuditLogEntry>, persister::Error> {
+        self.persister.load_audit_log().await
+    }
+}
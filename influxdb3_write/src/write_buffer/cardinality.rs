@@ -0,0 +1,312 @@
+//! Limits on the cardinality of the data written to the buffer
+
+use std::{
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use influxdb3_catalog::catalog::DatabaseSchema;
+use influxdb3_id::{DbId, TableId};
+use metric::{Metric, Registry, U64Gauge};
+use parking_lot::{Mutex, RwLock};
+
+/// The distinct series that have been written to a table
+type TableSeries = Arc<Mutex<HashSet<u64>>>;
+
+/// Tracks the distinct series that have been written to each table, in order to enforce the
+/// [`CardinalityLimits`][influxdb3_wal::CardinalityLimits] of the databases in the catalog.
+///
+/// Series are only tracked for databases that have a limit on the number of series per table, so
+/// at most that many series are held for each of their tables, give or take those of concurrent
+/// writes, which are checked against the same recorded series. Series are tracked from when the
+/// server starts, so series written before a restart do not count towards the limit until they
+/// are written again.
+#[derive(Debug)]
+pub struct CardinalityTracker {
+    tables: RwLock<HashMap<(DbId, TableId), TableSeries>>,
+    series_cardinality: Metric<U64Gauge>,
+}
+
+/// The new series that a write adds to each table, which are checked against the limit along
+/// with the series already in the table, and recorded with [`CardinalityTracker::record`] once
+/// the write has been buffered
+#[derive(Debug, Default)]
+pub(crate) struct NewSeries {
+    db: Option<(DbId, Arc<str>)>,
+    tables: HashMap<TableId, TableNewSeries>,
+}
+
+#[derive(Debug)]
+struct TableNewSeries {
+    /// The series already recorded for the table, if there are any
+    recorded: Option<TableSeries>,
+    new: HashSet<u64>,
+}
+
+impl CardinalityTracker {
+    pub fn new(metric_registry: &Registry) -> Self {
+        let series_cardinality = metric_registry.register_metric::<U64Gauge>(
+            "influxdb3_series_cardinality",
+            "number of distinct series written to the tables of a database with a series limit \
+            since the server started",
+        );
+        Self {
+            tables: Default::default(),
+            series_cardinality,
+        }
+    }
+
+    /// Check that a new table can be added to the database, returning an error message if doing
+    /// so would exceed the table limit
+    pub(crate) fn check_new_table(&self, db_schema: &DatabaseSchema) -> Result<(), String> {
+        match db_schema.cardinality_limits.max_tables {
+            Some(limit) if db_schema.tables.len() >= limit => Err(format!(
+                "adding a table to database {db_name} would exceed the limit of {limit} tables",
+                db_name = db_schema.name,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check a write to the series identified by `tags` in a table, returning an error message if
+    /// it is a new series that would exceed the series limit for the table. New series are added
+    /// to `new_series`, rather than being recorded straight away.
+    pub(crate) fn check_series<'a>(
+        &self,
+        db_schema: &DatabaseSchema,
+        table_id: TableId,
+        tags: impl Iterator<Item = (&'a str, &'a str)>,
+        new_series: &mut NewSeries,
+    ) -> Result<(), String> {
+        let Some(limit) = db_schema.cardinality_limits.max_series_per_table else {
+            return Ok(());
+        };
+        let series_id = series_id(tags);
+
+        // the recorded series of the table are looked up once per write, and each line only
+        // takes the lock of its own table:
+        let table = new_series
+            .tables
+            .entry(table_id)
+            .or_insert_with(|| TableNewSeries {
+                recorded: self.tables.read().get(&(db_schema.id, table_id)).cloned(),
+                new: HashSet::new(),
+            });
+        if table.new.contains(&series_id) {
+            return Ok(());
+        }
+        let count = match &table.recorded {
+            Some(recorded) => {
+                let recorded = recorded.lock();
+                if recorded.contains(&series_id) {
+                    return Ok(());
+                }
+                recorded.len()
+            }
+            None => 0,
+        };
+        if count + table.new.len() >= limit {
+            return Err(format!(
+                "adding a series to table {table_name} would exceed the limit of {limit} \
+                series per table",
+                table_name = db_schema
+                    .table_id_to_name(&table_id)
+                    .unwrap_or_else(|| "(new table)".into()),
+            ));
+        }
+        table.new.insert(series_id);
+        new_series
+            .db
+            .get_or_insert_with(|| (db_schema.id, Arc::clone(&db_schema.name)));
+
+        Ok(())
+    }
+
+    /// Record the new series from a write that has been buffered
+    pub(crate) fn record(&self, new_series: NewSeries) {
+        let Some((db_id, db_name)) = new_series.db else {
+            return;
+        };
+        let mut added = 0;
+        for (table_id, table) in new_series.tables {
+            if table.new.is_empty() {
+                continue;
+            }
+            let recorded = table.recorded.unwrap_or_else(|| {
+                Arc::clone(self.tables.write().entry((db_id, table_id)).or_default())
+            });
+            let mut recorded = recorded.lock();
+            for series_id in table.new {
+                added += u64::from(recorded.insert(series_id));
+            }
+        }
+        self.series_cardinality
+            .recorder([("db", Cow::Owned(db_name.to_string()))])
+            .inc(added);
+    }
+}
+
+/// Identify a series by its tag set. The hashes of the tags are summed, so that the order of
+/// the tags does not matter.
+fn series_id<'a>(tags: impl Iterator<Item = (&'a str, &'a str)>) -> u64 {
+    tags.map(|tag| {
+        let mut hasher = DefaultHasher::new();
+        tag.hash(&mut hasher);
+        hasher.finish()
+    })
+    .fold(0, u64::wrapping_add)
+}
+
+#[cfg(test)]
+mod tests {
+    use influxdb3_wal::CardinalityLimits;
+    use metric::Attributes;
+
+    use super::*;
+
+    fn db_with_limits(cardinality_limits: CardinalityLimits) -> DatabaseSchema {
+        let mut db_schema = DatabaseSchema::new(DbId::new(), Arc::from("db"));
+        db_schema.cardinality_limits = cardinality_limits;
+        db_schema
+    }
+
+    #[test]
+    fn series_limit() {
+        let registry = Registry::new();
+        let tracker = CardinalityTracker::new(&registry);
+        let db_schema = db_with_limits(CardinalityLimits {
+            max_tables: None,
+            max_series_per_table: Some(2),
+        });
+        let table_id = TableId::new();
+
+        let mut new_series = NewSeries::default();
+        tracker
+            .check_series(
+                &db_schema,
+                table_id,
+                [("host", "a")].into_iter(),
+                &mut new_series,
+            )
+            .unwrap();
+        // tag order does not matter:
+        tracker
+            .check_series(
+                &db_schema,
+                table_id,
+                [("region", "us"), ("host", "b")].into_iter(),
+                &mut new_series,
+            )
+            .unwrap();
+        tracker
+            .check_series(
+                &db_schema,
+                table_id,
+                [("host", "b"), ("region", "us")].into_iter(),
+                &mut new_series,
+            )
+            .unwrap();
+        // a third series exceeds the limit, along with the new series from the same write:
+        assert!(tracker
+            .check_series(
+                &db_schema,
+                table_id,
+                [("host", "c")].into_iter(),
+                &mut new_series,
+            )
+            .is_err());
+        // but other tables have their own limit:
+        tracker
+            .check_series(
+                &db_schema,
+                TableId::new(),
+                [("host", "c")].into_iter(),
+                &mut new_series,
+            )
+            .unwrap();
+        tracker.record(new_series);
+
+        // once recorded, the series count towards the limit of later writes:
+        let mut new_series = NewSeries::default();
+        tracker
+            .check_series(
+                &db_schema,
+                table_id,
+                [("host", "a")].into_iter(),
+                &mut new_series,
+            )
+            .unwrap();
+        assert!(tracker
+            .check_series(
+                &db_schema,
+                table_id,
+                [("host", "d")].into_iter(),
+                &mut new_series,
+            )
+            .is_err());
+
+        let series_cardinality = registry
+            .get_instrument::<Metric<U64Gauge>>("influxdb3_series_cardinality")
+            .unwrap()
+            .get_observer(&Attributes::from(&[("db", "db")]))
+            .unwrap()
+            .fetch();
+        assert_eq!(3, series_cardinality);
+    }
+
+    #[test]
+    fn unchecked_series_are_not_recorded() {
+        let tracker = CardinalityTracker::new(&Registry::new());
+        let db_schema = db_with_limits(CardinalityLimits {
+            max_tables: None,
+            max_series_per_table: Some(1),
+        });
+        let table_id = TableId::new();
+
+        // the series of a write that is not recorded, e.g., because it failed, do not count
+        // towards the limit:
+        let mut new_series = NewSeries::default();
+        tracker
+            .check_series(
+                &db_schema,
+                table_id,
+                [("host", "a")].into_iter(),
+                &mut new_series,
+            )
+            .unwrap();
+        drop(new_series);
+        tracker
+            .check_series(
+                &db_schema,
+                table_id,
+                [("host", "b")].into_iter(),
+                &mut NewSeries::default(),
+            )
+            .unwrap();
+
+        // without a series limit, nothing is tracked:
+        let db_schema = db_with_limits(CardinalityLimits::default());
+        let mut new_series = NewSeries::default();
+        tracker
+            .check_series(
+                &db_schema,
+                table_id,
+                [("host", "a")].into_iter(),
+                &mut new_series,
+            )
+            .unwrap();
+        assert!(new_series.tables.is_empty());
+    }
+
+    #[test]
+    fn table_limit() {
+        let tracker = CardinalityTracker::new(&Registry::new());
+        let db_schema = db_with_limits(CardinalityLimits {
+            max_tables: Some(0),
+            max_series_per_table: None,
+        });
+        assert!(tracker.check_new_table(&db_schema).is_err());
+    }
+}
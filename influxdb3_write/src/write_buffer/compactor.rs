@@ -0,0 +1,582 @@
+//! The compactor merges the small gen1 files that snapshots persist into larger gen2 files, one
+//! for each table and window of time, which are sorted and deduplicated, so that queries of
+//! older data open fewer files.
+//!
+//! Each run of the compactor that compacts any files persists a [`CompactionGeneration`], which
+//! records the gen2 files and the files they replaced. The generations are applied to the
+//! persisted files loaded from the snapshots on startup, so the replaced files stay replaced.
+//! Once there are enough of them, the generations are folded into a checkpoint, which replaces
+//! their files, so that startup does not load every generation that was ever persisted.
+
+use crate::parquet_cache::ParquetCacheOracle;
+use crate::paths::ParquetFilePath;
+use crate::persister::{self, Persister};
+use crate::write_buffer::parquet_chunk_from_file;
+use crate::write_buffer::persisted_files::PersistedFiles;
+use crate::write_buffer::queryable_buffer::{tag_bloom_filters, tag_column_ranges};
+use crate::{ColumnValueRange, Compaction, CompactionGeneration, ParquetFile, ParquetFileIndexes};
+use datafusion::common::DataFusionError;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use futures_util::TryStreamExt;
+use influxdb3_catalog::catalog::{Catalog, DatabaseSchema, TableDefinition};
+use iox_query::exec::Executor;
+use iox_query::frontend::reorg::{self, ReorgPlanner};
+use iox_query::QueryChunk;
+use iox_time::TimeProvider;
+use object_store::path::Path as ObjPath;
+use observability_deps::tracing::{info, warn};
+use schema::sort::SortKey;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("error from persister: {0}")]
+    Persister(#[from] persister::Error),
+
+    #[error("error planning compaction: {0}")]
+    Plan(#[from] reorg::Error),
+
+    #[error("error executing compaction: {0}")]
+    DataFusion(#[from] DataFusionError),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The default duration of the windows of time that files are compacted in
+pub const DEFAULT_GEN2_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// The default number of files that a window must have to be compacted
+pub const DEFAULT_COMPACTION_MIN_FILES: usize = 4;
+
+/// The number of generation files that are loaded on startup before the generations are folded
+/// into a checkpoint
+const GENERATION_FILES_PER_CHECKPOINT: usize = 16;
+
+/// Which of the persisted files are compacted together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionConfig {
+    /// The duration of the windows of time that the files of a table are compacted in, which
+    /// should be a multiple of the gen1 duration, so that each gen1 file is in a single window.
+    /// A window is only compacted once it has ended.
+    pub gen2_duration: Duration,
+    /// The number of files that a window must have to be compacted, which is at least two
+    pub min_files: usize,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self {
+            gen2_duration: DEFAULT_GEN2_DURATION,
+            min_files: DEFAULT_COMPACTION_MIN_FILES,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Compactor {
+    catalog: Arc<Catalog>,
+    persister: Arc<Persister>,
+    persisted_files: Arc<PersistedFiles>,
+    executor: Arc<Executor>,
+    parquet_cache: Option<Arc<dyn ParquetCacheOracle>>,
+    time_provider: Arc<dyn TimeProvider>,
+    /// Held for the whole of a run, so that runs do not overlap
+    state: Mutex<CompactorState>,
+}
+
+#[derive(Debug)]
+struct CompactorState {
+    next_generation: u64,
+    /// The files replaced by the last generation. They are deleted at the start of the next run,
+    /// rather than straight away, so that the queries that were planned with them can finish.
+    superseded: Vec<ParquetFile>,
+    /// The number of files that loading the generations reads, including the checkpoint
+    generation_files: usize,
+}
+
+impl Compactor {
+    /// Load the compaction generations, and apply them to the persisted files, which must have
+    /// been loaded from the snapshots
+    pub(crate) async fn load(
+        catalog: Arc<Catalog>,
+        persister: Arc<Persister>,
+        persisted_files: Arc<PersistedFiles>,
+        executor: Arc<Executor>,
+        parquet_cache: Option<Arc<dyn ParquetCacheOracle>>,
+        time_provider: Arc<dyn TimeProvider>,
+    ) -> Result<Self> {
+        let generations = persister.load_compaction_generations().await?;
+        for generation in &generations {
+            persisted_files.apply_compaction_generation(generation);
+        }
+        // the files replaced by the generations before the last were deleted by the run after
+        // them, but those of the last one may not have been:
+        let state = CompactorState {
+            next_generation: generations.last().map_or(0, |last| last.generation + 1),
+            superseded: generations
+                .last()
+                .map(|last| {
+                    last.compactions
+                        .iter()
+                        .flat_map(|compaction| compaction.replaced.iter().cloned())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            generation_files: generations.len(),
+        };
+
+        let compactor = Self {
+            catalog,
+            persister,
+            persisted_files,
+            executor,
+            parquet_cache,
+            time_provider,
+            state: Mutex::new(state),
+        };
+        {
+            let mut state = compactor.state.lock().await;
+            if state.generation_files > GENERATION_FILES_PER_CHECKPOINT {
+                compactor.checkpoint(&mut state, generations).await;
+            }
+        }
+        Ok(compactor)
+    }
+
+    /// Compact the windows of each table that have enough files, returning the number of gen2
+    /// files that were written
+    pub(crate) async fn compact(&self, config: CompactionConfig) -> Result<usize> {
+        let mut state = self.state.lock().await;
+        self.delete_superseded(&mut state).await;
+
+        let window_nanos = i64::try_from(config.gen2_duration.as_nanos())
+            .unwrap_or(i64::MAX)
+            .max(1);
+        let now_ns = self.time_provider.now().timestamp_nanos();
+        let mut generation = CompactionGeneration {
+            host_id: self.persister.host_identifier_prefix().to_string(),
+            generation: state.next_generation,
+            compactions: vec![],
+        };
+        for db_schema in self.catalog.list_db_schema() {
+            for table_def in db_schema.tables() {
+                if table_def.deleted_at_ns.is_some() {
+                    continue;
+                }
+                let mut windows: BTreeMap<i64, Vec<ParquetFile>> = BTreeMap::new();
+                for file in self
+                    .persisted_files
+                    .get_files(db_schema.id, table_def.table_id)
+                {
+                    // imported files can have data from any time, rather than from the window of
+                    // their chunk time, so they are left as they are:
+                    if ParquetFilePath::is_imported(&file.path) {
+                        continue;
+                    }
+                    let window_start = file.chunk_time - file.chunk_time.rem_euclid(window_nanos);
+                    windows.entry(window_start).or_default().push(file);
+                }
+                for (window_start, files) in windows {
+                    if files.len() < config.min_files.max(2)
+                        || window_start.saturating_add(window_nanos) > now_ns
+                    {
+                        continue;
+                    }
+                    // if a later window fails, the gen2 files of this run are not in any
+                    // generation, and are overwritten by the next run, which has the same
+                    // generation number:
+                    let compaction = self
+                        .compact_window(
+                            &db_schema,
+                            &table_def,
+                            window_start,
+                            files,
+                            generation.generation,
+                        )
+                        .await?;
+                    generation.compactions.push(compaction);
+                }
+            }
+        }
+        if generation.compactions.is_empty() {
+            return Ok(0);
+        }
+
+        // the generation is persisted before the persisted files are changed, so that the gen2
+        // files are never queried along with the files that they replaced, even after a restart:
+        self.persister
+            .persist_compaction_generation(&generation)
+            .await?;
+        for compaction in &generation.compactions {
+            let replaced = self.persisted_files.apply_compaction(compaction);
+            if let Some(parquet_cache) = &self.parquet_cache {
+                for file in replaced {
+                    parquet_cache.invalidate(&ObjPath::from(file.path.as_str()));
+                }
+            }
+            state.superseded.extend(compaction.replaced.iter().cloned());
+        }
+        state.next_generation += 1;
+        state.generation_files += 1;
+        info!(
+            generation = generation.generation,
+            file_count = generation.compactions.len(),
+            "persisted compaction generation"
+        );
+        if state.generation_files > GENERATION_FILES_PER_CHECKPOINT {
+            match self.persister.load_compaction_generations().await {
+                Ok(generations) => self.checkpoint(&mut state, generations).await,
+                Err(e) => warn!(%e, "error loading compaction generations to checkpoint"),
+            }
+        }
+        Ok(generation.compactions.len())
+    }
+
+    /// Fold all but the last of the `generations` into a checkpoint. The last generation is left
+    /// out, so that the files it replaced, which may not have been deleted yet, are known after a
+    /// restart. If the checkpoint cannot be persisted, it is tried again after the next run.
+    async fn checkpoint(
+        &self,
+        state: &mut CompactorState,
+        mut generations: Vec<CompactionGeneration>,
+    ) {
+        generations.pop();
+        let Some(checkpoint) = fold_generations(generations) else {
+            return;
+        };
+        match self
+            .persister
+            .persist_compaction_checkpoint(&checkpoint)
+            .await
+        {
+            Ok(()) => {
+                // the checkpoint and the last generation:
+                state.generation_files = 2;
+                info!(
+                    generation = checkpoint.generation,
+                    file_count = checkpoint.compactions.len(),
+                    "persisted compaction checkpoint"
+                );
+            }
+            Err(e) => warn!(%e, "error persisting compaction checkpoint"),
+        }
+    }
+
+    /// Merge the files of a table in a window into a gen2 file, and persist it
+    async fn compact_window(
+        &self,
+        db_schema: &DatabaseSchema,
+        table_def: &TableDefinition,
+        window_start: i64,
+        mut files: Vec<ParquetFile>,
+        generation: u64,
+    ) -> Result<Compaction> {
+        // the rows of newer files replace those of older files with the same series key and time,
+        // as they do when the files are queried:
+        files.sort_by_key(|file| file.id);
+        let schema = table_def.parquet_schema();
+        let sort_key = SortKey::from(
+            schema
+                .primary_key()
+                .iter()
+                .map(|column| column.to_string())
+                .collect::<Vec<_>>(),
+        );
+        let chunks = files
+            .iter()
+            .enumerate()
+            .map(|(chunk_order, file)| {
+                Arc::new(parquet_chunk_from_file(
+                    file,
+                    &schema,
+                    None,
+                    self.persister.object_store_url().clone(),
+                    self.persister.object_store(),
+                    chunk_order as i64,
+                )) as Arc<dyn QueryChunk>
+            })
+            .collect();
+
+        let ctx = self.executor.new_context();
+        let logical_plan = ReorgPlanner::new().compact_plan(
+            data_types::TableId::new(0),
+            Arc::clone(&table_def.table_name),
+            &schema,
+            chunks,
+            sort_key.clone(),
+        )?;
+        let physical_plan = ctx.create_physical_plan(&logical_plan).await?;
+        // the merged rows are streamed into the gen2 file, rather than collected, so that the
+        // whole window is not held in memory, and the ranges of the tag columns are taken from
+        // the batches as they pass:
+        let column_ranges = Arc::new(parking_lot::Mutex::new(BTreeMap::new()));
+        let data = ctx.execute_stream(physical_plan).await?.inspect_ok({
+            let schema = schema.clone();
+            let column_ranges = Arc::clone(&column_ranges);
+            move |batch| {
+                merge_column_ranges(&mut column_ranges.lock(), tag_column_ranges(&schema, batch))
+            }
+        });
+
+        let path = ParquetFilePath::new_gen2(
+            self.persister.host_identifier_prefix(),
+            db_schema.name.as_ref(),
+            db_schema.id.as_u32(),
+            table_def.table_name.as_ref(),
+            table_def.table_id.as_u32(),
+            window_start,
+            generation,
+        );
+        // the rows of the replaced files are at least as many as those left once they are
+        // deduplicated:
+        let bloom_filters =
+            tag_bloom_filters(&schema, files.iter().map(|file| file.row_count).sum());
+        let (size_bytes, meta) = self
+            .persister
+            .persist_parquet_file(
+                path.clone(),
+                Box::pin(RecordBatchStreamAdapter::new(schema.as_arrow(), data)),
+                &bloom_filters,
+            )
+            .await?;
+        let column_ranges = std::mem::take(&mut *column_ranges.lock());
+
+        let file = ParquetFile {
+            // the gen2 file has the id of the newest file that it replaced, so that it is ordered
+            // before the files persisted since, which have newer data:
+            id: files.last().expect("window has files").id,
+            path: path.to_string(),
+            size_bytes,
+            row_count: meta.num_rows as u64,
+            chunk_time: window_start,
+            min_time: files.iter().map(|file| file.min_time).min().unwrap_or(0),
+            max_time: files.iter().map(|file| file.max_time).max().unwrap_or(0),
+            indexes: ParquetFileIndexes::from_meta(&meta),
+            column_ranges,
+            sort_key: sort_key
+                .to_columns()
+                .map(|column| column.to_string())
+                .collect(),
+        };
+        info!(
+            db_name = %db_schema.name,
+            table_name = %table_def.table_name,
+            file_count = files.len(),
+            path = %file.path,
+            "compacted files"
+        );
+        Ok(Compaction {
+            db_id: db_schema.id,
+            table_id: table_def.table_id,
+            file,
+            replaced: files,
+        })
+    }
+
+    /// The paths of the files that were replaced by the last generation, which are yet to be
+    /// deleted. This waits for a run that is in progress to finish.
+    pub(crate) async fn superseded_paths(&self) -> HashSet<String> {
+        let state = self.state.lock().await;
+        state
+            .superseded
+            .iter()
+            .map(|file| file.path.clone())
+            .collect()
+    }
+
+    /// Delete the files that were replaced by the last generation. Files that cannot be deleted
+    /// are tried again on the next run.
+    async fn delete_superseded(&self, state: &mut CompactorState) {
+        if let Err(e) = self.persister.check_write_fence() {
+            warn!(%e, n_files = state.superseded.len(), "not deleting compacted files");
+            return;
+        }
+        let object_store = self.persister.object_store();
+        let mut remaining = vec![];
+        for file in state.superseded.drain(..) {
+            match object_store
+                .delete(&ObjPath::from(file.path.as_str()))
+                .await
+            {
+                Ok(()) | Err(object_store::Error::NotFound { .. }) => (),
+                Err(e) => {
+                    warn!(%e, path = %file.path, "error deleting compacted file");
+                    remaining.push(file);
+                }
+            }
+        }
+        state.superseded = remaining;
+    }
+}
+
+/// Widen the ranges in `column_ranges` to include those in `ranges`
+fn merge_column_ranges(
+    column_ranges: &mut BTreeMap<String, ColumnValueRange>,
+    ranges: BTreeMap<String, ColumnValueRange>,
+) {
+    for (column, range) in ranges {
+        column_ranges
+            .entry(column)
+            .and_modify(|existing| {
+                if range.min < existing.min {
+                    existing.min.clone_from(&range.min);
+                }
+                if range.max > existing.max {
+                    existing.max.clone_from(&range.max);
+                }
+            })
+            .or_insert(range);
+    }
+}
+
+/// Fold `generations` into a single generation with the number of the last of them. A compaction
+/// that replaced the gen2 file of an earlier one takes over the files that the earlier one
+/// replaced, and the earlier gen2 file stays among its replaced files, as the index of the
+/// persisted files may refer to it.
+fn fold_generations(generations: Vec<CompactionGeneration>) -> Option<CompactionGeneration> {
+    let last = generations.last()?;
+    let (host_id, generation) = (last.host_id.clone(), last.generation);
+
+    let mut compactions: Vec<Option<Compaction>> = vec![];
+    let mut by_path: HashMap<String, usize> = HashMap::new();
+    for mut compaction in generations
+        .into_iter()
+        .flat_map(|generation| generation.compactions)
+    {
+        let earlier = compaction
+            .replaced
+            .iter()
+            .filter_map(|file| by_path.remove(&file.path))
+            .collect::<Vec<_>>();
+        for index in earlier {
+            if let Some(earlier) = compactions[index].take() {
+                compaction.replaced.extend(earlier.replaced);
+            }
+        }
+        by_path.insert(compaction.file.path.clone(), compactions.len());
+        compactions.push(Some(compaction));
+    }
+
+    Some(CompactionGeneration {
+        host_id,
+        generation,
+        compactions: compactions.into_iter().flatten().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths::CompactionGenerationFilePath;
+    use crate::ParquetFileId;
+    use influxdb3_id::{DbId, TableId};
+    use object_store::memory::InMemory;
+
+    fn file(name: &str) -> ParquetFile {
+        ParquetFile {
+            id: ParquetFileId::new(),
+            path: format!("host/dbs/db-0/cpu-0/1970-01-01/00-00/{name}.parquet"),
+            size_bytes: 0,
+            row_count: 0,
+            chunk_time: 0,
+            min_time: 0,
+            max_time: 0,
+            indexes: Default::default(),
+            column_ranges: Default::default(),
+            sort_key: vec![],
+        }
+    }
+
+    fn generation(generation: u64, compactions: Vec<(&str, Vec<&str>)>) -> CompactionGeneration {
+        CompactionGeneration {
+            host_id: "host".to_string(),
+            generation,
+            compactions: compactions
+                .into_iter()
+                .map(|(name, replaced)| Compaction {
+                    db_id: DbId::from(0),
+                    table_id: TableId::from(0),
+                    file: file(name),
+                    replaced: replaced.into_iter().map(file).collect(),
+                })
+                .collect(),
+        }
+    }
+
+    fn names(generation: &CompactionGeneration) -> Vec<(String, Vec<String>)> {
+        let name = |file: &ParquetFile| {
+            ObjPath::from(file.path.as_str())
+                .filename()
+                .unwrap()
+                .trim_end_matches(".parquet")
+                .to_string()
+        };
+        generation
+            .compactions
+            .iter()
+            .map(|compaction| {
+                let mut replaced = compaction.replaced.iter().map(name).collect::<Vec<_>>();
+                replaced.sort();
+                (name(&compaction.file), replaced)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn checkpoint_replaces_the_generations_it_folds() {
+        let persister = Persister::new(Arc::new(InMemory::new()), "host");
+        let generations = [
+            generation(0, vec![("gen2-0", vec!["a", "b"])]),
+            // the window is compacted again, with a file that was persisted into it late:
+            generation(1, vec![("gen2-1", vec!["gen2-0", "c"])]),
+            generation(2, vec![("gen2-2", vec!["d", "e"])]),
+            generation(3, vec![("gen2-3", vec!["f", "g"])]),
+        ];
+        for generation in &generations {
+            persister
+                .persist_compaction_generation(generation)
+                .await
+                .unwrap();
+        }
+
+        let checkpoint = fold_generations(generations[..3].to_vec()).unwrap();
+        assert_eq!(2, checkpoint.generation);
+        let expected = names(&generation(
+            2,
+            vec![
+                ("gen2-1", vec!["a", "b", "c", "gen2-0"]),
+                ("gen2-2", vec!["d", "e"]),
+            ],
+        ));
+        assert_eq!(expected, names(&checkpoint));
+        persister
+            .persist_compaction_checkpoint(&checkpoint)
+            .await
+            .unwrap();
+
+        // the checkpoint is loaded in place of the generations it covers, whose files are gone:
+        let loaded = persister.load_compaction_generations().await.unwrap();
+        assert_eq!(
+            vec![2, 3],
+            loaded
+                .iter()
+                .map(|generation| generation.generation)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(expected, names(&loaded[0]));
+        assert_eq!(names(&generations[3]), names(&loaded[1]));
+        for generation in 0..=2 {
+            assert!(matches!(
+                persister
+                    .object_store()
+                    .head(&CompactionGenerationFilePath::new("host", generation))
+                    .await,
+                Err(object_store::Error::NotFound { .. })
+            ));
+        }
+    }
+}
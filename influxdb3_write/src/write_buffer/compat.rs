@@ -0,0 +1,198 @@
+//! Compatibility with the way that InfluxDB 1.x, and the clients written for it, handle field
+//! values in line protocol.
+//!
+//! Lines are rewritten into a form that the line protocol parser accepts before they are parsed:
+//! * the shorthand booleans `t`, `T`, `f`, and `F`, as well as the capitalized `True`, `TRUE`,
+//!   `False`, and `FALSE`, are written out as `true` or `false`
+//! * floats in scientific notation, e.g., `1.5e3`, are written out in decimal notation
+//! * integer and unsigned integer values that overflow their type are clamped to its range, in
+//!   the same way that the 1.x Telegraf output does for unsigned values sent as integers
+//!
+//! Tag values, string field values, and timestamps are left as they are.
+
+use std::{borrow::Cow, ops::Range};
+
+/// Rewrite each of the lines in `lp`, as described in the [module docs](self), preserving the
+/// line structure so that line numbers are unchanged
+pub(crate) fn normalize_lp(lp: &str) -> Cow<'_, str> {
+    let mut normalized: Option<String> = None;
+    let mut offset = 0;
+    for line in lp.split_inclusive('\n') {
+        let normalized_line = normalize_line(line);
+        // only start building a new string once a line has been changed:
+        if matches!(normalized_line, Cow::Owned(_)) && normalized.is_none() {
+            let mut s = String::with_capacity(lp.len());
+            s.push_str(&lp[..offset]);
+            normalized = Some(s);
+        }
+        if let Some(normalized) = normalized.as_mut() {
+            normalized.push_str(&normalized_line);
+        }
+        offset += line.len();
+    }
+    normalized.map(Cow::Owned).unwrap_or(Cow::Borrowed(lp))
+}
+
+/// Rewrite the field values of a single line, as described in the [module docs](self)
+pub(crate) fn normalize_line(line: &str) -> Cow<'_, str> {
+    if line.trim_start().starts_with('#') {
+        return Cow::Borrowed(line);
+    }
+    let replacements: Vec<(Range<usize>, String)> = field_value_ranges(line)
+        .into_iter()
+        .filter_map(|range| normalize_value(&line[range.clone()]).map(|v| (range, v)))
+        .collect();
+    if replacements.is_empty() {
+        return Cow::Borrowed(line);
+    }
+
+    let mut normalized = String::with_capacity(line.len());
+    let mut last = 0;
+    for (range, value) in replacements {
+        normalized.push_str(&line[last..range.start]);
+        normalized.push_str(&value);
+        last = range.end;
+    }
+    normalized.push_str(&line[last..]);
+    Cow::Owned(normalized)
+}
+
+/// Find the byte ranges of the unquoted field values in `line`
+fn field_value_ranges(line: &str) -> Vec<Range<usize>> {
+    let bytes = line.as_bytes();
+    let mut ranges = vec![];
+
+    // skip over the measurement and tag set, which end at the first unescaped space:
+    let mut i = 0;
+    while i < bytes.len() && bytes[i] != b' ' {
+        i += if bytes[i] == b'\\' { 2 } else { 1 };
+    }
+    while i < bytes.len() && bytes[i] == b' ' {
+        i += 1;
+    }
+
+    // scan the field set, which ends at the next unescaped space outside of a quoted value:
+    while i < bytes.len() {
+        while i < bytes.len() && bytes[i] != b'=' {
+            i += if bytes[i] == b'\\' { 2 } else { 1 };
+        }
+        i += 1;
+        if i >= bytes.len() {
+            break;
+        }
+        if bytes[i] == b'"' {
+            i += 1;
+            while i < bytes.len() && bytes[i] != b'"' {
+                i += if bytes[i] == b'\\' { 2 } else { 1 };
+            }
+            i += 1;
+        } else {
+            let start = i;
+            while i < bytes.len() && !matches!(bytes[i], b',' | b' ' | b'\n' | b'\r') {
+                i += 1;
+            }
+            ranges.push(start..i);
+        }
+        if i >= bytes.len() || bytes[i] != b',' {
+            break;
+        }
+        i += 1;
+    }
+
+    ranges
+}
+
+/// Rewrite an unquoted field value, returning `None` if it does not need to be changed
+fn normalize_value(value: &str) -> Option<String> {
+    match value {
+        "t" | "T" | "True" | "TRUE" => return Some("true".to_string()),
+        "f" | "F" | "False" | "FALSE" => return Some("false".to_string()),
+        _ => (),
+    }
+    if let Some(digits) = value.strip_suffix('i') {
+        if digits.parse::<i64>().is_ok() || !is_integer(digits) {
+            return None;
+        }
+        let clamped = if digits.starts_with('-') {
+            i64::MIN
+        } else {
+            i64::MAX
+        };
+        return Some(format!("{clamped}i"));
+    }
+    if let Some(digits) = value.strip_suffix('u') {
+        if digits.parse::<u64>().is_ok() || !is_integer(digits) || digits.starts_with('-') {
+            return None;
+        }
+        return Some(format!("{}u", u64::MAX));
+    }
+    if value.contains(['e', 'E']) && value.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+        return value
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .map(|v| v.to_string());
+    }
+    None
+}
+
+fn is_integer(digits: &str) -> bool {
+    let digits = digits.strip_prefix('-').unwrap_or(digits);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_field_values() {
+        for (line, expected) in [
+            ("cpu,host=a usage=0.5 1", "cpu,host=a usage=0.5 1"),
+            (
+                "cpu,host=t up=t,down=F 1",
+                "cpu,host=t up=true,down=false 1",
+            ),
+            ("cpu up=TRUE", "cpu up=true"),
+            ("cpu val=1.5e3,other=-2E-2 1", "cpu val=1500,other=-0.02 1"),
+            (
+                "cpu count=18446744073709551615i",
+                "cpu count=9223372036854775807i",
+            ),
+            (
+                "cpu count=-9223372036854775809i",
+                "cpu count=-9223372036854775808i",
+            ),
+            (
+                "cpu count=18446744073709551616u",
+                "cpu count=18446744073709551615u",
+            ),
+            // string field values, tags, and escaped characters are left as they are:
+            (
+                r#"cpu,host=1e5 msg="t, f=1e5",val=t"#,
+                r#"cpu,host=1e5 msg="t, f=1e5",val=true"#,
+            ),
+            (
+                r#"cpu\ load,host=a\ b val\=x=f"#,
+                r#"cpu\ load,host=a\ b val\=x=false"#,
+            ),
+            ("# cpu val=t", "# cpu val=t"),
+            ("", ""),
+        ] {
+            assert_eq!(expected, normalize_line(line), "line: {line}");
+        }
+    }
+
+    #[test]
+    fn normalize_preserves_lines() {
+        let lp = "cpu val=1 1\n\ncpu val=t 2\r\ncpu val=1e1 3";
+        assert_eq!(
+            "cpu val=1 1\n\ncpu val=true 2\r\ncpu val=10 3",
+            normalize_lp(lp)
+        );
+        assert!(matches!(
+            normalize_lp("cpu val=1 1\ncpu val=true 2"),
+            Cow::Borrowed(_)
+        ));
+    }
+}
@@ -0,0 +1,170 @@
+//! Decompression of line protocol payloads that were sent with a compressed content encoding
+
+use std::{fmt::Display, io::Read, str::FromStr};
+
+use bytes::Bytes;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unsupported content encoding: {0}")]
+    UnsupportedEncoding(String),
+
+    #[error("error decoding {encoding} payload: {source}")]
+    Decode {
+        encoding: ContentEncoding,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("decompressed payload exceeds the maximum size of {0} bytes")]
+    SizeExceeded(usize),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The encodings that a write payload can be sent with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    #[default]
+    Identity,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for ContentEncoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "" | "identity" => Ok(Self::Identity),
+            "gzip" | "x-gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            other => Err(Error::UnsupportedEncoding(other.to_string())),
+        }
+    }
+}
+
+impl Display for ContentEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Identity => write!(f, "identity"),
+            Self::Gzip => write!(f, "gzip"),
+            Self::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// Decompress the `payload`, which was encoded with `encoding`, so that it can be parsed as line
+/// protocol.
+///
+/// At most `max_bytes` of decompressed data are produced, to guard against decompression bombs;
+/// if the payload decompresses to anything larger, [`Error::SizeExceeded`] is returned.
+pub fn decompress(encoding: ContentEncoding, payload: Bytes, max_bytes: usize) -> Result<Bytes> {
+    let decoder: Box<dyn Read + '_> = match encoding {
+        ContentEncoding::Identity => {
+            if payload.len() > max_bytes {
+                return Err(Error::SizeExceeded(max_bytes));
+            }
+            return Ok(payload);
+        }
+        ContentEncoding::Gzip => Box::new(flate2::read::MultiGzDecoder::new(&payload[..])),
+        ContentEncoding::Zstd => Box::new(
+            zstd::stream::read::Decoder::new(&payload[..])
+                .map_err(|source| Error::Decode { encoding, source })?,
+        ),
+    };
+
+    // In order to detect if the entire stream has been read, or truncated, read an extra byte
+    // beyond the limit and check the resulting data length:
+    let mut decoded = Vec::new();
+    decoder
+        .take(max_bytes as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|source| Error::Decode { encoding, source })?;
+    if decoded.len() > max_bytes {
+        return Err(Error::SizeExceeded(max_bytes));
+    }
+
+    Ok(decoded.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const LP: &str = "cpu,host=a usage=0.5 1\ncpu,host=b usage=0.7 2\n";
+
+    fn gzip(data: &[u8]) -> Bytes {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap().into()
+    }
+
+    fn zstd(data: &[u8]) -> Bytes {
+        zstd::stream::encode_all(data, 0).unwrap().into()
+    }
+
+    #[test]
+    fn parse_content_encoding() {
+        assert_eq!(ContentEncoding::Identity, "identity".parse().unwrap());
+        assert_eq!(ContentEncoding::Gzip, "gzip".parse().unwrap());
+        assert_eq!(ContentEncoding::Gzip, "x-gzip".parse().unwrap());
+        assert_eq!(ContentEncoding::Zstd, "zstd".parse().unwrap());
+        assert!(matches!(
+            "br".parse::<ContentEncoding>(),
+            Err(Error::UnsupportedEncoding(e)) if e == "br"
+        ));
+    }
+
+    #[test]
+    fn decompress_payloads() {
+        for (encoding, payload) in [
+            (ContentEncoding::Identity, Bytes::from_static(LP.as_bytes())),
+            (ContentEncoding::Gzip, gzip(LP.as_bytes())),
+            (ContentEncoding::Zstd, zstd(LP.as_bytes())),
+        ] {
+            let decoded = decompress(encoding, payload, 1024).unwrap();
+            assert_eq!(LP.as_bytes(), &decoded[..], "encoding: {encoding}");
+        }
+    }
+
+    #[test]
+    fn decompress_enforces_max_size() {
+        let max_bytes = LP.len() - 1;
+        for (encoding, payload) in [
+            (ContentEncoding::Identity, Bytes::from_static(LP.as_bytes())),
+            (ContentEncoding::Gzip, gzip(LP.as_bytes())),
+            (ContentEncoding::Zstd, zstd(LP.as_bytes())),
+        ] {
+            assert!(
+                matches!(
+                    decompress(encoding, payload, max_bytes),
+                    Err(Error::SizeExceeded(n)) if n == max_bytes
+                ),
+                "encoding: {encoding}"
+            );
+        }
+        // exactly at the limit is fine:
+        decompress(ContentEncoding::Zstd, zstd(LP.as_bytes()), LP.len()).unwrap();
+    }
+
+    #[test]
+    fn decompress_invalid_payload() {
+        let err = decompress(
+            ContentEncoding::Gzip,
+            Bytes::from_static(LP.as_bytes()),
+            1024,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Decode {
+                encoding: ContentEncoding::Gzip,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,405 @@
+//! Downsampling tasks aggregate the data of a table into windows of time, e.g., the mean of each
+//! field per series and minute, and write the results to another table through the write path,
+//! so that queries over long ranges of time do not have to read every row that was written.
+//!
+//! The tasks are defined in the catalog. Each run of a task aggregates the whole of the windows
+//! that the files persisted since its last run overlap, including the data for them that is still
+//! buffered. The rows that are written for a window replace those written for it by earlier runs,
+//! since they have the same series and time. The newest file that each task has aggregated is
+//! persisted in the [`DownsamplingProgress`], so that tasks carry on where they left off after a
+//! restart.
+
+use crate::persister::{self, Persister};
+use crate::write_buffer::persisted_files::PersistedFiles;
+use crate::write_buffer::WriteBufferImpl;
+use crate::{Bufferer, ParquetFile};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Schema as ArrowSchema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use data_types::NamespaceName;
+use datafusion::common::DataFusionError;
+use datafusion::prelude::{col, lit};
+use datafusion::scalar::ScalarValue;
+use influxdb3_catalog::catalog::{DatabaseSchema, TableDefinition};
+use influxdb3_id::{DbId, ParquetFileId};
+use influxdb3_wal::{DownsamplingAggregate, DownsamplingTask};
+use iox_query::exec::Executor;
+use iox_query::provider::ProviderBuilder;
+use observability_deps::tracing::{error, info, warn};
+use schema::{InfluxColumnType, InfluxFieldType, TIME_COLUMN_NAME};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("error from persister: {0}")]
+    Persister(#[from] persister::Error),
+
+    #[error("error aggregating data: {0}")]
+    DataFusion(#[from] DataFusionError),
+
+    #[error("error converting aggregated data: {0}")]
+    Arrow(#[from] ArrowError),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The name that the source table of a task is queried under when its data is aggregated
+const SOURCE_TABLE_NAME: &str = "source";
+
+/// How far each of the downsampling tasks has got
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownsamplingProgress {
+    pub tasks: Vec<TaskProgress>,
+}
+
+/// The newest file of its source table that a downsampling task has aggregated
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskProgress {
+    pub db_id: DbId,
+    /// The definition of the task, so that a task that is replaced by one with a different
+    /// definition starts over
+    pub task: DownsamplingTask,
+    pub last_file_id: ParquetFileId,
+}
+
+#[derive(Debug)]
+pub(crate) struct Downsampler {
+    persister: Arc<Persister>,
+    persisted_files: Arc<PersistedFiles>,
+    executor: Arc<Executor>,
+    /// Held for the whole of a run, so that runs do not overlap
+    progress: Mutex<DownsamplingProgress>,
+}
+
+impl Downsampler {
+    /// Load the progress of the downsampling tasks
+    pub(crate) async fn load(
+        persister: Arc<Persister>,
+        persisted_files: Arc<PersistedFiles>,
+        executor: Arc<Executor>,
+    ) -> Result<Self> {
+        let progress = persister
+            .load_downsampling_progress()
+            .await?
+            .unwrap_or_default();
+        Ok(Self {
+            persister,
+            persisted_files,
+            executor,
+            progress: Mutex::new(progress),
+        })
+    }
+
+    /// Run each of the downsampling tasks over the windows that the files persisted since its
+    /// last run overlap, returning the number of rows that were written. A task that fails is
+    /// logged, and runs over the same windows again the next time.
+    pub(crate) async fn run(&self, write_buffer: &WriteBufferImpl) -> Result<usize> {
+        let mut progress = self.progress.lock().await;
+        let mut next_progress = DownsamplingProgress::default();
+        let mut rows_written = 0;
+        for db_schema in write_buffer.catalog().list_db_schema() {
+            for task in db_schema.downsampling_tasks.values() {
+                let last_file_id = progress
+                    .tasks
+                    .iter()
+                    .find(|p| p.db_id == db_schema.id && p.task == *task)
+                    .map(|p| p.last_file_id);
+                let source_table = db_schema
+                    .table_definition_by_id(&task.source_table_id)
+                    .filter(|table_def| table_def.deleted_at_ns.is_none());
+                let result = match source_table {
+                    Some(table_def) => {
+                        self.run_task(write_buffer, &db_schema, &table_def, task, last_file_id)
+                            .await
+                    }
+                    None => Ok(None),
+                };
+                let last_file_id = match result {
+                    Ok(Some((file_id, rows))) => {
+                        rows_written += rows;
+                        Some(file_id)
+                    }
+                    Ok(None) => last_file_id,
+                    Err(e) => {
+                        error!(
+                            %e,
+                            db_name = %db_schema.name,
+                            task_name = %task.name,
+                            "error running downsampling task"
+                        );
+                        last_file_id
+                    }
+                };
+                if let Some(last_file_id) = last_file_id {
+                    next_progress.tasks.push(TaskProgress {
+                        db_id: db_schema.id,
+                        task: task.clone(),
+                        last_file_id,
+                    });
+                }
+            }
+        }
+
+        // the progress of the tasks that were deleted is dropped along with them:
+        if next_progress != *progress {
+            self.persister
+                .persist_downsampling_progress(&next_progress)
+                .await?;
+            *progress = next_progress;
+        }
+        Ok(rows_written)
+    }
+
+    /// Run a task over the windows that the files of its source table that are newer than
+    /// `last_file_id` overlap, returning the newest of those files and the number of rows that
+    /// were written, or `None` if there were no new files
+    async fn run_task(
+        &self,
+        write_buffer: &WriteBufferImpl,
+        db_schema: &DatabaseSchema,
+        table_def: &TableDefinition,
+        task: &DownsamplingTask,
+        last_file_id: Option<ParquetFileId>,
+    ) -> crate::write_buffer::Result<Option<(ParquetFileId, usize)>> {
+        let files = self
+            .persisted_files
+            .get_files(db_schema.id, table_def.table_id)
+            .into_iter()
+            .filter(|file| last_file_id.map_or(true, |last| file.id > last))
+            .collect::<Vec<_>>();
+        let Some(newest_file_id) = files.iter().map(|file| file.id).max() else {
+            return Ok(None);
+        };
+
+        let db_name = NamespaceName::new(db_schema.name.to_string())?;
+        let mut rows_written = 0;
+        for (start, end) in window_ranges(&files, interval_nanos(task)) {
+            let batches = self
+                .aggregate(write_buffer, db_schema, table_def, task, start, end)
+                .await?;
+            for batch in batches {
+                let request = write_buffer
+                    .write_record_batch(
+                        db_name.clone(),
+                        &task.target_table_name,
+                        batch,
+                        write_buffer.time_provider.now(),
+                        true,
+                    )
+                    .await?;
+                if let Some(invalid) = request.invalid_lines.first() {
+                    warn!(
+                        db_name = %db_schema.name,
+                        task_name = %task.name,
+                        invalid_count = request.invalid_lines.len(),
+                        error = %invalid.error_message,
+                        "downsampled rows were rejected"
+                    );
+                }
+                rows_written += request
+                    .line_count
+                    .saturating_sub(request.invalid_lines.len());
+            }
+        }
+        info!(
+            db_name = %db_schema.name,
+            task_name = %task.name,
+            file_count = files.len(),
+            rows_written,
+            "ran downsampling task"
+        );
+        Ok(Some((newest_file_id, rows_written)))
+    }
+
+    /// Aggregate the data of the source table of a task, from `start` to `end`, which are the
+    /// bounds of whole windows, into a row per series and window.
+    ///
+    /// Only the columns that the table has now are aggregated, so the values that files
+    /// persisted before a column was renamed have under its old name are left out.
+    async fn aggregate(
+        &self,
+        write_buffer: &WriteBufferImpl,
+        db_schema: &DatabaseSchema,
+        table_def: &TableDefinition,
+        task: &DownsamplingTask,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<RecordBatch>> {
+        let mut tags = HashSet::new();
+        let mut select = vec![];
+        let mut group_by = vec![];
+        for (column_type, field) in table_def.schema.iter() {
+            let name = quote_identifier(field.name());
+            match column_type {
+                InfluxColumnType::Tag => {
+                    tags.insert(field.name().as_str());
+                    select.push(name.clone());
+                    group_by.push(name);
+                }
+                InfluxColumnType::Field(field_type) => {
+                    let numeric = matches!(
+                        field_type,
+                        InfluxFieldType::Float
+                            | InfluxFieldType::Integer
+                            | InfluxFieldType::UInteger
+                    );
+                    if numeric || task.aggregate == DownsamplingAggregate::Count {
+                        let function = sql_function(task.aggregate);
+                        select.push(format!("{function}({name}) AS {name}"));
+                    }
+                }
+                InfluxColumnType::Timestamp => (),
+            }
+        }
+        if select.len() == group_by.len() {
+            // there are no fields that the aggregate can be computed for:
+            return Ok(vec![]);
+        }
+        let time = quote_identifier(TIME_COLUMN_NAME);
+        let interval_ns = interval_nanos(task);
+        let window = format!("date_bin(INTERVAL '{interval_ns} nanoseconds', {time})");
+        select.push(format!("{window} AS {time}"));
+        group_by.push(window);
+        let sql = format!(
+            "SELECT {} FROM {SOURCE_TABLE_NAME} \
+            WHERE {time} >= to_timestamp_nanos({start}) AND {time} < to_timestamp_nanos({end}) \
+            GROUP BY {}",
+            select.join(", "),
+            group_by.join(", ")
+        );
+
+        // the rows are deduplicated, as they are when the table is queried:
+        let ctx = self.executor.new_context();
+        let filters = [
+            col(TIME_COLUMN_NAME).gt_eq(lit(ScalarValue::TimestampNanosecond(Some(start), None))),
+            col(TIME_COLUMN_NAME).lt(lit(ScalarValue::TimestampNanosecond(Some(end), None))),
+        ];
+        let chunks = write_buffer.get_table_chunks(
+            &db_schema.name,
+            &table_def.table_name,
+            &filters,
+            None,
+            &ctx.inner().state(),
+        )?;
+        let mut builder =
+            ProviderBuilder::new(Arc::clone(&table_def.table_name), table_def.schema.clone());
+        for chunk in chunks {
+            builder = builder.add_chunk(chunk);
+        }
+        let provider = builder
+            .build()
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        ctx.inner()
+            .register_table(SOURCE_TABLE_NAME, Arc::new(provider))?;
+        let batches = ctx.inner().sql(&sql).await?.collect().await?;
+
+        batches
+            .into_iter()
+            .map(|batch| Ok(tags_as_dictionaries(batch, &tags)?))
+            .collect()
+    }
+}
+
+/// The ranges of whole windows of `interval_ns` that the times of the data in `files` overlap, as
+/// `(start, end)`, with `end` exclusive, where ranges that overlap or are adjacent are merged
+fn window_ranges(files: &[ParquetFile], interval_ns: i64) -> Vec<(i64, i64)> {
+    let mut ranges = files
+        .iter()
+        .map(|file| {
+            let start = file.min_time - file.min_time.rem_euclid(interval_ns);
+            let last_start = file.max_time - file.max_time.rem_euclid(interval_ns);
+            (start, last_start.saturating_add(interval_ns))
+        })
+        .collect::<Vec<_>>();
+    ranges.sort_unstable();
+    let mut merged: Vec<(i64, i64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// The duration of the windows of a task, in nanoseconds
+fn interval_nanos(task: &DownsamplingTask) -> i64 {
+    i64::try_from(task.interval.as_nanos())
+        .unwrap_or(i64::MAX)
+        .max(1)
+}
+
+/// The name of the SQL function that computes an aggregate
+fn sql_function(aggregate: DownsamplingAggregate) -> &'static str {
+    match aggregate {
+        DownsamplingAggregate::Mean => "avg",
+        DownsamplingAggregate::Min => "min",
+        DownsamplingAggregate::Max => "max",
+        DownsamplingAggregate::Sum => "sum",
+        DownsamplingAggregate::Count => "count",
+    }
+}
+
+/// Quote a column name for use in SQL
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Dictionary encode the tag columns of `batch`, if grouping by them did not keep them dictionary
+/// encoded, so that they are written as tags
+fn tags_as_dictionaries(
+    batch: RecordBatch,
+    tags: &HashSet<&str>,
+) -> Result<RecordBatch, ArrowError> {
+    let dictionary_type = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+    let schema = batch.schema();
+    let mut fields = Vec::with_capacity(batch.num_columns());
+    let mut columns = Vec::with_capacity(batch.num_columns());
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        if tags.contains(field.name().as_str()) && field.data_type() != &dictionary_type {
+            fields.push(Field::new(field.name(), dictionary_type.clone(), true));
+            columns.push(cast(column, &dictionary_type)?);
+        } else {
+            fields.push(field.as_ref().clone());
+            columns.push(Arc::clone(column));
+        }
+    }
+    RecordBatch::try_new(Arc::new(ArrowSchema::new(fields)), columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParquetFileIndexes;
+
+    fn file(min_time: i64, max_time: i64) -> ParquetFile {
+        ParquetFile {
+            id: ParquetFileId::new(),
+            path: String::new(),
+            size_bytes: 0,
+            row_count: 0,
+            chunk_time: min_time,
+            min_time,
+            max_time,
+            indexes: ParquetFileIndexes::default(),
+            column_ranges: Default::default(),
+            sort_key: vec![],
+        }
+    }
+
+    #[test]
+    fn window_ranges_are_merged() {
+        let files = [file(130, 150), file(0, 15), file(15, 25), file(-5, -1)];
+        assert_eq!(
+            vec![(-10, 30), (130, 160)],
+            window_ranges(&files, 10),
+            "windows that are adjacent, or overlap, are merged"
+        );
+        assert_eq!(vec![(0, 10)], window_ranges(&[file(0, 9)], 10));
+    }
+}
@@ -0,0 +1,64 @@
+//! Notification of the events in the write buffer to registered [`WriteEventListener`]s, so that
+//! they can be used for alerting, auditing, or forwarding data elsewhere
+
+use std::{fmt::Debug, sync::Arc};
+
+use parking_lot::RwLock;
+
+use crate::{BufferedWriteRequest, PersistedSnapshot, WriteLineError};
+
+/// Receives notifications of the events in the write buffer. All methods do nothing by default,
+/// so implementors only need to override those for the events they are interested in.
+///
+/// Listeners are called inline, on the write and persistence paths, so they should return
+/// quickly, e.g., by handing the event off to a channel or a background task.
+pub trait WriteEventListener: Debug + Send + Sync + 'static {
+    /// Called once a write has been accepted into the buffer, with the summary that is returned
+    /// to the client
+    fn on_write_accepted(&self, _write: &BufferedWriteRequest) {}
+
+    /// Called with the lines that were rejected by a write to the database `db_name`, either
+    /// because they were dropped from a partial write, or because they failed the whole write
+    fn on_lines_rejected(&self, _db_name: &str, _lines: &[WriteLineError]) {}
+
+    /// Called once a snapshot of the buffer has been persisted to object storage
+    fn on_snapshot_persisted(&self, _snapshot: &PersistedSnapshot) {}
+}
+
+/// The [`WriteEventListener`]s registered with a write buffer
+#[derive(Debug, Default)]
+pub struct WriteEventListeners {
+    listeners: RwLock<Vec<Arc<dyn WriteEventListener>>>,
+}
+
+impl WriteEventListeners {
+    pub(crate) fn register(&self, listener: Arc<dyn WriteEventListener>) {
+        self.listeners.write().push(listener);
+    }
+
+    pub(crate) fn write_accepted(&self, write: &BufferedWriteRequest) {
+        for listener in self.listeners() {
+            listener.on_write_accepted(write);
+        }
+    }
+
+    pub(crate) fn lines_rejected(&self, db_name: &str, lines: &[WriteLineError]) {
+        if lines.is_empty() {
+            return;
+        }
+        for listener in self.listeners() {
+            listener.on_lines_rejected(db_name, lines);
+        }
+    }
+
+    pub(crate) fn snapshot_persisted(&self, snapshot: &PersistedSnapshot) {
+        for listener in self.listeners() {
+            listener.on_snapshot_persisted(snapshot);
+        }
+    }
+
+    /// Take a copy of the listeners, so that the lock is not held while they are called
+    fn listeners(&self) -> Vec<Arc<dyn WriteEventListener>> {
+        self.listeners.read().clone()
+    }
+}
@@ -0,0 +1,149 @@
+//! Exports of the data of a table, both buffered and persisted, deduplicated and sorted as it is
+//! when the table is queried, to a file in object storage, e.g., to migrate it to another system.
+//! The file is uploaded in parts as the data is read, so that the whole of it is never in memory.
+
+use crate::persister;
+use arrow::csv::WriterBuilder;
+use arrow::error::ArrowError;
+use datafusion::common::DataFusionError;
+use datafusion::physical_plan::SendableRecordBatchStream;
+use futures_util::StreamExt;
+use object_store::path::Path as ObjPath;
+use object_store::{ObjectStore, WriteMultipart};
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("error reading the data to export: {0}")]
+    DataFusion(#[from] DataFusionError),
+
+    #[error("error writing csv: {0}")]
+    Csv(#[from] ArrowError),
+
+    #[error("error writing parquet: {0}")]
+    Parquet(#[from] ParquetError),
+
+    #[error("error from persister: {0}")]
+    Persister(#[from] persister::Error),
+
+    #[error("object_store error: {0}")]
+    ObjectStore(#[from] object_store::Error),
+
+    #[error("invalid export format: {0}, expected one of parquet or csv")]
+    InvalidExportFormat(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The number of parts of an export that may be uploading at once, before reading more of the
+/// data waits for them
+const MAX_CONCURRENT_PARTS: usize = 8;
+
+/// The format of the file that a table is exported to
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ExportFormat {
+    /// A parquet file, written with the settings of the persisted files
+    #[default]
+    Parquet,
+    /// A CSV file with a header, and timestamps in RFC 3339 format
+    Csv,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "parquet" => Ok(Self::Parquet),
+            "csv" => Ok(Self::Csv),
+            _ => Err(Error::InvalidExportFormat(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parquet => write!(f, "parquet"),
+            Self::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// Where a table is exported to, which is replaced if it already exists
+#[derive(Debug, Clone)]
+pub struct ExportTarget {
+    pub object_store: Arc<dyn ObjectStore>,
+    pub path: ObjPath,
+}
+
+/// Write the batches of `stream` to `target` in `format`, returning the number of rows that were
+/// written. If anything fails, the upload is aborted, so that `target` is not left with part of
+/// the data.
+pub(crate) async fn write_export(
+    stream: SendableRecordBatchStream,
+    format: ExportFormat,
+    writer_properties: WriterProperties,
+    target: &ExportTarget,
+) -> Result<u64> {
+    let upload = target.object_store.put_multipart(&target.path).await?;
+    let mut upload = WriteMultipart::new(upload);
+    match write_batches(stream, format, writer_properties, &mut upload).await {
+        Ok(row_count) => {
+            upload.finish().await?;
+            Ok(row_count)
+        }
+        Err(e) => {
+            // the error that stopped the export is the one that is returned:
+            let _ = upload.abort().await;
+            Err(e)
+        }
+    }
+}
+
+async fn write_batches(
+    mut stream: SendableRecordBatchStream,
+    format: ExportFormat,
+    writer_properties: WriterProperties,
+    upload: &mut WriteMultipart,
+) -> Result<u64> {
+    let mut row_count = 0;
+    match format {
+        ExportFormat::Parquet => {
+            let mut writer =
+                ArrowWriter::try_new(vec![], stream.schema(), Some(writer_properties))?;
+            while let Some(batch) = stream.next().await {
+                let batch = batch?;
+                row_count += batch.num_rows() as u64;
+                writer.write(&batch)?;
+                // the row groups that have been flushed are uploaded as they are:
+                upload.write(&std::mem::take(writer.inner_mut()));
+                upload.wait_for_capacity(MAX_CONCURRENT_PARTS).await?;
+            }
+            upload.write(&writer.into_inner()?);
+        }
+        ExportFormat::Csv => {
+            let mut header = true;
+            while let Some(batch) = stream.next().await {
+                let batch = batch?;
+                if batch.num_rows() == 0 {
+                    continue;
+                }
+                row_count += batch.num_rows() as u64;
+                let mut bytes = vec![];
+                WriterBuilder::new()
+                    .with_header(header)
+                    .build(&mut bytes)
+                    .write(&batch)?;
+                upload.write(&bytes);
+                upload.wait_for_capacity(MAX_CONCURRENT_PARTS).await?;
+                header = false;
+            }
+        }
+    }
+    Ok(row_count)
+}
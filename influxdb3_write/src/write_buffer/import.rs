@@ -0,0 +1,249 @@
+//! Imports of parquet files that were written outside of the server, e.g., exported from another
+//! system, into a table. The data of the files does not go through the WAL or the buffer, which
+//! is far too slow to backfill years of history with. Instead, each file is checked against the
+//! schema of the table in the catalog, and persisted again as the server persists its own files,
+//! and they are all added to the persisted files in a snapshot of their own.
+
+use crate::paths::ParquetFilePath;
+use crate::persister::{self, Persister};
+use crate::write_buffer::queryable_buffer::{tag_bloom_filters, tag_column_ranges};
+use crate::{ColumnValueRange, ParquetFile, ParquetFileIndexes};
+use arrow::array::{new_null_array, ArrayRef, AsArray};
+use arrow::compute::{cast, max, min};
+use arrow::datatypes::{DataType, TimestampNanosecondType};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use data_types::Timestamp;
+use datafusion_util::stream_from_batches;
+use influxdb3_catalog::catalog::{DatabaseSchema, TableDefinition};
+use influxdb3_id::ParquetFileId;
+use influxdb3_wal::Gen1Duration;
+use object_store::path::Path as ObjPath;
+use object_store::ObjectStore;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::errors::ParquetError;
+use schema::{InfluxColumnType, Schema, TIME_COLUMN_NAME};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("error from persister: {0}")]
+    Persister(#[from] persister::Error),
+
+    #[error("object_store error: {0}")]
+    ObjectStore(#[from] object_store::Error),
+
+    #[error("error reading {path}: {source}")]
+    Parquet { path: String, source: ParquetError },
+
+    #[error("error converting the data of {path}: {source}")]
+    Arrow { path: String, source: ArrowError },
+
+    #[error("{path} has no {TIME_COLUMN_NAME} column")]
+    MissingTime { path: String },
+
+    #[error("column {column} of {path} is not in table {table_name}")]
+    UnknownColumn {
+        path: String,
+        column: String,
+        table_name: String,
+    },
+
+    #[error("column {column} of {path} has type {actual}, which cannot be imported as {expected}")]
+    ColumnType {
+        path: String,
+        column: String,
+        expected: DataType,
+        actual: DataType,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Read the parquet file at `path` in `source`, check it against the schema of `table_def`, and
+/// persist its data as a file of the table with the id `id`. Returns the persisted file, or
+/// `None` if the file has no rows.
+///
+/// The columns of the file must be columns of the table. Tags may be strings, rather than
+/// dictionaries, and the time may be any timestamp, which are converted. Columns of the table
+/// that the file does not have are null.
+pub(crate) async fn import_file(
+    persister: &Persister,
+    db_schema: &DatabaseSchema,
+    table_def: &TableDefinition,
+    gen1_duration: Gen1Duration,
+    source: &dyn ObjectStore,
+    path: &ObjPath,
+    id: ParquetFileId,
+) -> Result<Option<ParquetFile>> {
+    let parquet_error = |source| Error::Parquet {
+        path: path.to_string(),
+        source,
+    };
+    let arrow_error = |source| Error::Arrow {
+        path: path.to_string(),
+        source,
+    };
+
+    let bytes = source.get(path).await?.bytes().await?;
+    let builder = ParquetRecordBatchReaderBuilder::try_new(bytes).map_err(parquet_error)?;
+    check_schema(builder.schema().fields(), table_def, path)?;
+    let batches = builder
+        .build()
+        .map_err(parquet_error)?
+        .map(|batch| conform_batch(&batch?, &table_def.schema))
+        .collect::<Result<Vec<_>, ArrowError>>()
+        .map_err(arrow_error)?;
+
+    let mut row_count = 0;
+    let mut min_time = i64::MAX;
+    let mut max_time = i64::MIN;
+    let mut column_ranges: BTreeMap<String, ColumnValueRange> = BTreeMap::new();
+    for batch in &batches {
+        row_count += batch.num_rows() as u64;
+        let times = batch
+            .column_by_name(TIME_COLUMN_NAME)
+            .expect("conformed batch has a time column")
+            .as_primitive::<TimestampNanosecondType>();
+        min_time = min(times).map_or(min_time, |time| time.min(min_time));
+        max_time = max(times).map_or(max_time, |time| time.max(max_time));
+        for (column, range) in tag_column_ranges(&table_def.schema, batch) {
+            column_ranges
+                .entry(column)
+                .and_modify(|existing| {
+                    if range.min < existing.min {
+                        existing.min.clone_from(&range.min);
+                    }
+                    if range.max > existing.max {
+                        existing.max.clone_from(&range.max);
+                    }
+                })
+                .or_insert(range);
+        }
+    }
+    if row_count == 0 {
+        return Ok(None);
+    }
+
+    let chunk_time = gen1_duration.chunk_time_for_timestamp(Timestamp::new(min_time));
+    let file_path = ParquetFilePath::new_imported(
+        persister.host_identifier_prefix(),
+        db_schema.name.as_ref(),
+        db_schema.id.as_u32(),
+        table_def.table_name.as_ref(),
+        table_def.table_id.as_u32(),
+        chunk_time,
+        id,
+    );
+    let bloom_filters = tag_bloom_filters(&table_def.schema, row_count);
+    let (size_bytes, meta) = persister
+        .persist_parquet_file(
+            file_path.clone(),
+            stream_from_batches(table_def.schema.as_arrow(), batches),
+            &bloom_filters,
+        )
+        .await?;
+
+    Ok(Some(ParquetFile {
+        id,
+        path: file_path.to_string(),
+        size_bytes,
+        row_count,
+        chunk_time,
+        min_time,
+        max_time,
+        indexes: ParquetFileIndexes::from_meta(&meta),
+        column_ranges,
+        // the rows of the file are not known to be sorted, so it has no sort key:
+        sort_key: vec![],
+    }))
+}
+
+/// Check that each of the columns of a file is a column of the table, of a type that it can be
+/// converted from, and that the file has a time column
+fn check_schema(
+    fields: &arrow::datatypes::Fields,
+    table_def: &TableDefinition,
+    path: &ObjPath,
+) -> Result<()> {
+    if fields.find(TIME_COLUMN_NAME).is_none() {
+        return Err(Error::MissingTime {
+            path: path.to_string(),
+        });
+    }
+    for field in fields {
+        let Some((column_type, column)) = table_def.schema.field_by_name(field.name()) else {
+            return Err(Error::UnknownColumn {
+                path: path.to_string(),
+                column: field.name().to_string(),
+                table_name: table_def.table_name.to_string(),
+            });
+        };
+        if !can_import(column_type, field.data_type(), column.data_type()) {
+            return Err(Error::ColumnType {
+                path: path.to_string(),
+                column: field.name().to_string(),
+                expected: column.data_type().clone(),
+                actual: field.data_type().clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Whether a column of `actual` type can be imported into a column of `column_type`, which is
+/// `expected` in the schema of the table
+fn can_import(column_type: InfluxColumnType, actual: &DataType, expected: &DataType) -> bool {
+    if actual == expected {
+        return true;
+    }
+    match column_type {
+        InfluxColumnType::Tag => match actual {
+            DataType::Utf8 | DataType::LargeUtf8 => true,
+            DataType::Dictionary(_, value) => {
+                matches!(value.as_ref(), DataType::Utf8 | DataType::LargeUtf8)
+            }
+            _ => false,
+        },
+        InfluxColumnType::Timestamp => matches!(actual, DataType::Timestamp(_, _)),
+        InfluxColumnType::Field(_) => false,
+    }
+}
+
+/// Convert a batch of a file, which has passed [`check_schema`], to the schema of its table
+fn conform_batch(batch: &RecordBatch, schema: &Schema) -> Result<RecordBatch, ArrowError> {
+    let columns = schema
+        .iter()
+        .map(|(_, field)| match batch.column_by_name(field.name()) {
+            Some(column) if column.data_type() == field.data_type() => Ok(Arc::clone(column)),
+            Some(column) => cast(column, field.data_type()),
+            None => Ok(new_null_array(field.data_type(), batch.num_rows())),
+        })
+        .collect::<Result<Vec<ArrayRef>, _>>()?;
+    RecordBatch::try_new(schema.as_arrow(), columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_and_times_can_be_converted() {
+        let tag = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+        let time = DataType::Timestamp(arrow::datatypes::TimeUnit::Nanosecond, None);
+        assert!(can_import(InfluxColumnType::Tag, &DataType::Utf8, &tag));
+        assert!(!can_import(InfluxColumnType::Tag, &DataType::Int64, &tag));
+        assert!(can_import(
+            InfluxColumnType::Timestamp,
+            &DataType::Timestamp(arrow::datatypes::TimeUnit::Millisecond, Some("UTC".into())),
+            &time
+        ));
+        assert!(!can_import(
+            InfluxColumnType::Field(schema::InfluxFieldType::Float),
+            &DataType::Int64,
+            &DataType::Float64
+        ));
+    }
+}
@@ -0,0 +1,398 @@
+//! The lease that a write buffer holds on its host identifier prefix, so that only one process
+//! writes to it at a time. Two processes pointed at the same prefix would otherwise interleave
+//! their WAL files and snapshots, and corrupt each other's state.
+//!
+//! The lease is a file in object storage, with the holder of the lease and when it expires, that
+//! is only ever written with conditional puts: it is created if there is none, and otherwise
+//! replaced only if it has not changed since it was read. The holder renews the lease well
+//! before it expires, and a process that finds the lease held by another waits for it to expire
+//! before taking it over. Once a holder has lost its lease, to another process or because it
+//! could not renew it before it expired, its writes are refused until it is restarted, and, as
+//! the lease is the [`WriteFence`] of its WAL and persister, so are its writes to the prefix.
+//!
+//! Holding a lease needs an object store that supports both conditional creates and updates,
+//! which not all of them do, e.g., the local file system does not support updates, and S3 only
+//! supports them when configured to. The lease is renewed once when it is acquired, so that a
+//! server pointed at a store that does not support them fails to start, rather than losing its
+//! lease later on.
+
+use crate::paths::LeaseFilePath;
+use influxdb3_wal::WriteFence;
+use iox_time::{Time, TimeProvider};
+use object_store::path::Path as ObjPath;
+use object_store::{ObjectStore, PutMode, PutOptions, PutPayload, UpdateVersion};
+use observability_deps::tracing::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("object store error: {0}")]
+    ObjectStore(#[from] object_store::Error),
+
+    #[error("the lease file is invalid: {0}")]
+    InvalidLeaseFile(#[from] serde_json::Error),
+
+    #[error("the lease was taken over by {holder}")]
+    Lost { holder: String },
+
+    #[error(
+        "the object store does not support the conditional puts that the lease needs, run \
+        without a lease by setting its duration to 0s"
+    )]
+    ConditionalPutNotSupported,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The default duration of the lease, which the holder renews every third of
+pub const DEFAULT_LEASE_DURATION: Duration = Duration::from_secs(30);
+
+/// The contents of the lease file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct LeaseRecord {
+    /// The process that holds the lease
+    holder: String,
+    /// When the lease expires, unless it is renewed before then
+    expires_at_ns: i64,
+}
+
+#[derive(Debug)]
+pub struct Lease {
+    object_store: Arc<dyn ObjectStore>,
+    path: LeaseFilePath,
+    /// The id of this process, which identifies it as the holder of the lease
+    holder: String,
+    duration: Duration,
+    time_provider: Arc<dyn TimeProvider>,
+    state: parking_lot::Mutex<LeaseState>,
+    /// Set once the lease has been released, which stops it being renewed
+    released: AtomicBool,
+}
+
+#[derive(Debug)]
+struct LeaseState {
+    /// The version of the lease file that was last written, which the next write is conditional
+    /// on
+    version: UpdateVersion,
+    expires_at: Time,
+    /// The process that took over the lease, if another one has
+    lost_to: Option<String>,
+}
+
+impl Lease {
+    /// Acquire the lease on `host_identifier_prefix`, waiting for the lease of another process to
+    /// expire, if there is one, and then renew it in the background every third of `duration`
+    pub async fn acquire(
+        object_store: Arc<dyn ObjectStore>,
+        host_identifier_prefix: &str,
+        duration: Duration,
+        time_provider: Arc<dyn TimeProvider>,
+    ) -> Result<Arc<Self>> {
+        let path = LeaseFilePath::new(host_identifier_prefix);
+        let holder = Uuid::new_v4().to_string();
+        let state = loop {
+            let expires_at = time_provider.now() + duration;
+            let record = LeaseRecord {
+                holder: holder.clone(),
+                expires_at_ns: expires_at.timestamp_nanos(),
+            };
+            let mode = match read_lease(object_store.as_ref(), &path).await? {
+                None => PutMode::Create,
+                Some((current, version)) => {
+                    let current_expires_at = Time::from_timestamp_nanos(current.expires_at_ns);
+                    if current_expires_at > time_provider.now() {
+                        warn!(
+                            holder = %current.holder,
+                            expires_at = %current_expires_at,
+                            "waiting for the lease on the host identifier prefix to expire, as \
+                            another process holds it"
+                        );
+                        time_provider
+                            .sleep_until(current_expires_at.min(time_provider.now() + duration))
+                            .await;
+                        continue;
+                    }
+                    PutMode::Update(version)
+                }
+            };
+            match put_lease(object_store.as_ref(), &path, &record, mode).await {
+                Ok(version) => {
+                    break LeaseState {
+                        version,
+                        expires_at,
+                        lost_to: None,
+                    }
+                }
+                // another process took the lease first, which is waited on again:
+                Err(Error::ObjectStore(
+                    object_store::Error::AlreadyExists { .. }
+                    | object_store::Error::Precondition { .. },
+                )) => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        info!(
+            %holder,
+            expires_at = %state.expires_at,
+            "acquired the lease on the host identifier prefix"
+        );
+
+        let lease = Arc::new(Self {
+            object_store,
+            path,
+            holder,
+            duration,
+            time_provider,
+            state: parking_lot::Mutex::new(state),
+            released: AtomicBool::new(false),
+        });
+        // a store may support conditional creates, but not updates, which renewing relies on:
+        if let Err(e) = lease.renew().await {
+            if matches!(e, Error::ConditionalPutNotSupported) {
+                // the lease cannot be renewed, nor released, so the lease file is removed, in
+                // order not to hold up a server that is started without one:
+                if let Err(e) = lease.object_store.delete(&lease.path).await {
+                    warn!(%e, "error removing the lease file");
+                }
+            }
+            return Err(e);
+        }
+        background_lease_renewal(Arc::clone(&lease));
+        Ok(lease)
+    }
+
+    /// Whether this process holds the lease, i.e., it has not been taken over, and was renewed
+    /// before it expired
+    pub fn is_held(&self) -> bool {
+        let state = self.state.lock();
+        state.lost_to.is_none() && self.time_provider.now() < state.expires_at
+    }
+
+    /// Renew the lease, if it has not been taken over by another process. If the lease could not
+    /// be renewed before it expired, but no other process has taken it over since, it is held
+    /// again once it is renewed.
+    pub async fn renew(&self) -> Result<()> {
+        self.write(self.time_provider.now() + self.duration).await
+    }
+
+    /// Release the lease, so that another process can acquire it without waiting for it to
+    /// expire, and stop renewing it
+    pub async fn release(&self) -> Result<()> {
+        self.released.store(true, Ordering::Relaxed);
+        self.write(Time::from_timestamp_nanos(0)).await?;
+        info!(holder = %self.holder, "released the lease on the host identifier prefix");
+        Ok(())
+    }
+
+    async fn write(&self, expires_at: Time) -> Result<()> {
+        let version = {
+            let state = self.state.lock();
+            if let Some(holder) = &state.lost_to {
+                return Err(Error::Lost {
+                    holder: holder.clone(),
+                });
+            }
+            state.version.clone()
+        };
+        let record = LeaseRecord {
+            holder: self.holder.clone(),
+            expires_at_ns: expires_at.timestamp_nanos(),
+        };
+        match put_lease(
+            self.object_store.as_ref(),
+            &self.path,
+            &record,
+            PutMode::Update(version),
+        )
+        .await
+        {
+            Ok(version) => {
+                let mut state = self.state.lock();
+                state.version = version;
+                state.expires_at = expires_at;
+                Ok(())
+            }
+            Err(Error::ObjectStore(object_store::Error::Precondition { .. })) => {
+                let holder = read_lease(self.object_store.as_ref(), &self.path)
+                    .await
+                    .ok()
+                    .flatten()
+                    .map(|(record, _)| record.holder)
+                    .unwrap_or_else(|| "another process".to_string());
+                self.state.lock().lost_to = Some(holder.clone());
+                Err(Error::Lost { holder })
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl WriteFence for Lease {
+    fn is_open(&self) -> bool {
+        self.is_held()
+    }
+}
+
+/// Renew the lease every third of its duration, until it is released or lost
+fn background_lease_renewal(lease: Arc<Lease>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(lease.duration / 3);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        // the first tick completes immediately, and the lease was just acquired:
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            if lease.released.load(Ordering::Relaxed) {
+                return;
+            }
+            match lease.renew().await {
+                Ok(()) => (),
+                Err(e @ Error::Lost { .. }) => {
+                    error!(%e, "lost the lease on the host identifier prefix, refusing writes");
+                    return;
+                }
+                Err(e) => error!(%e, "error renewing the lease on the host identifier prefix"),
+            }
+        }
+    })
+}
+
+/// Read the lease file, along with its version, or `None` if there is none
+async fn read_lease(
+    object_store: &dyn ObjectStore,
+    path: &ObjPath,
+) -> Result<Option<(LeaseRecord, UpdateVersion)>> {
+    let result = match object_store.get(path).await {
+        Ok(result) => result,
+        Err(object_store::Error::NotFound { .. }) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let version = UpdateVersion {
+        e_tag: result.meta.e_tag.clone(),
+        version: result.meta.version.clone(),
+    };
+    let bytes = result.bytes().await?;
+    Ok(Some((serde_json::from_slice(&bytes)?, version)))
+}
+
+/// Write the lease file with `mode`, returning the version that was written
+async fn put_lease(
+    object_store: &dyn ObjectStore,
+    path: &ObjPath,
+    record: &LeaseRecord,
+    mode: PutMode,
+) -> Result<UpdateVersion> {
+    let json = serde_json::to_vec(record)?;
+    let result = match object_store
+        .put_opts(path, PutPayload::from(json), PutOptions::from(mode))
+        .await
+    {
+        Ok(result) => result,
+        Err(object_store::Error::NotImplemented) => return Err(Error::ConditionalPutNotSupported),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(UpdateVersion {
+        e_tag: result.e_tag,
+        version: result.version,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iox_time::MockProvider;
+    use object_store::local::LocalFileSystem;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn lease_is_only_held_by_one_process_at_a_time() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let time_provider = Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let acquire = || {
+            Lease::acquire(
+                Arc::clone(&object_store),
+                "host",
+                Duration::from_secs(30),
+                Arc::clone(&time_provider) as _,
+            )
+        };
+        let first = acquire().await.unwrap();
+        assert!(first.is_held());
+
+        // a second process waits for the lease of the first to expire:
+        let second = tokio::spawn(acquire());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!second.is_finished());
+        first.renew().await.unwrap();
+        assert!(first.is_held());
+
+        // and takes it over once it does, after which the first cannot renew it:
+        time_provider.set(Time::from_timestamp_nanos(0) + Duration::from_secs(31));
+        assert!(!first.is_held());
+        let second = second.await.unwrap().unwrap();
+        assert!(second.is_held());
+        assert!(matches!(first.renew().await, Err(Error::Lost { .. })));
+        assert!(!first.is_held());
+
+        // a released lease is acquired without waiting:
+        second.release().await.unwrap();
+        assert!(!second.is_held());
+        let third = acquire().await.unwrap();
+        assert!(third.is_held());
+    }
+
+    #[tokio::test]
+    async fn lease_is_not_acquired_without_conditional_updates() {
+        // the local file system supports conditional creates, but not updates:
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix(test_helpers::tmp_dir().unwrap()).unwrap());
+        let time_provider = Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let result = Lease::acquire(
+            Arc::clone(&object_store),
+            "host",
+            Duration::from_secs(30),
+            time_provider,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::ConditionalPutNotSupported)));
+
+        // and the lease file that was created is removed again:
+        assert!(
+            read_lease(object_store.as_ref(), &LeaseFilePath::new("host"))
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn lease_closes_the_write_fence_once_it_is_lost() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let time_provider = Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let lease = Lease::acquire(
+            Arc::clone(&object_store),
+            "host",
+            Duration::from_secs(30),
+            Arc::clone(&time_provider) as _,
+        )
+        .await
+        .unwrap();
+        let persister = crate::persister::Persister::new(Arc::clone(&object_store), "host");
+        persister.set_write_fence(Arc::clone(&lease) as Arc<dyn WriteFence>);
+        assert!(lease.is_open());
+        persister.check_write_fence().unwrap();
+
+        time_provider.set(Time::from_timestamp_nanos(0) + Duration::from_secs(31));
+        assert!(!lease.is_open());
+        assert!(matches!(
+            persister.check_write_fence(),
+            Err(crate::persister::Error::WriteFenced)
+        ));
+    }
+}
@@ -1,47 +1,108 @@
 //! Implementation of an in-memory buffer for writes that persists data into a wal if it is configured.
 
+pub mod audit;
+pub mod cardinality;
+pub mod compactor;
+mod compat;
+pub mod decompress;
+pub mod downsampling;
+pub mod events;
+pub mod export;
+pub mod import;
+pub mod lease;
+mod parquet_gc;
 pub mod persisted_files;
 pub mod queryable_buffer;
+pub mod rate_limit;
+pub mod replication;
+pub mod rows;
 mod table_buffer;
+mod tabular;
+pub mod tag_values;
+pub mod transform;
 pub(crate) mod validator;
+pub mod writers;
 
 use crate::chunk::ParquetChunk;
-use crate::last_cache::{self, CreateCacheArguments, LastCacheProvider};
-use crate::parquet_cache::ParquetCacheOracle;
-use crate::persister::Persister;
-use crate::write_buffer::persisted_files::PersistedFiles;
+use crate::distinct_cache::{self, CreateDistinctCacheArgs, DistinctCacheProvider};
+use crate::last_cache::{
+    self, CreateCacheArguments, LastCacheProvider, LastCacheStats, UpdateCacheArguments,
+};
+use crate::parquet_cache::{CacheRequest, ParquetCacheOracle};
+use crate::persister::{BloomFilters, Persister, SnapshotPage};
+use crate::write_buffer::audit::{AuditLog, AuditLogEntry, AuditSource};
+use crate::write_buffer::cardinality::CardinalityTracker;
+use crate::write_buffer::compactor::{CompactionConfig, Compactor};
+use crate::write_buffer::downsampling::Downsampler;
+use crate::write_buffer::events::{WriteEventListener, WriteEventListeners};
+use crate::write_buffer::export::{ExportFormat, ExportTarget};
+use crate::write_buffer::lease::Lease;
+use crate::write_buffer::parquet_gc::ParquetGarbageCollector;
+use crate::write_buffer::persisted_files::{PersistedFiles, TimeRange};
 use crate::write_buffer::queryable_buffer::QueryableBuffer;
-use crate::write_buffer::validator::WriteValidator;
+use crate::write_buffer::rate_limit::WriteRateLimiter;
+use crate::write_buffer::replication::ReplicationConfig;
+use crate::write_buffer::tag_values::TagValueDictionary;
+use crate::write_buffer::transform::{WriteTransform, WriteTransforms};
+use crate::write_buffer::validator::{ValidatedLines, WriteValidator};
+use crate::write_buffer::writers::WriterFiles;
 use crate::{
-    BufferedWriteRequest, Bufferer, ChunkContainer, LastCacheManager, ParquetFile,
-    PersistedSnapshot, Precision, WriteBuffer, WriteLineError,
+    BufferedWriteRequest, Bufferer, ChunkContainer, ColumnMapping, DistinctCacheManager,
+    Durability, LastCacheManager, LastCacheReplay, ParquetFile, PersistedSnapshot, Precision,
+    TabularFormat, WriteBuffer, WriteFieldValue, WriteLineError, WriteRow, WriteValidation,
 };
+use arrow::record_batch::RecordBatch;
 use async_trait::async_trait;
 use data_types::{ChunkId, ChunkOrder, ColumnType, NamespaceName, NamespaceNameError};
 use datafusion::catalog::Session;
 use datafusion::common::DataFusionError;
 use datafusion::datasource::object_store::ObjectStoreUrl;
 use datafusion::logical_expr::Expr;
-use influxdb3_catalog::catalog::Catalog;
-use influxdb3_id::{ColumnId, DbId, TableId};
-use influxdb3_wal::object_store::WalObjectStore;
+use datafusion::prelude::{col, lit};
+use datafusion::scalar::ScalarValue;
+use futures_util::StreamExt;
+use influxdb3_catalog::catalog::{Catalog, TableDefinition, TIME_COLUMN_NAME};
+use influxdb3_id::{ColumnId, DbId, ParquetFileId, TableId};
+use influxdb3_wal::follower::WalFollower;
+use influxdb3_wal::inspect::{WalFileSummary, WalInspector};
+use influxdb3_wal::object_store::{ReplayProgress, WalObjectStore};
+use influxdb3_wal::recovery::{RecoveryTarget, WalRecovery};
 use influxdb3_wal::CatalogOp::CreateLastCache;
 use influxdb3_wal::{
-    CatalogBatch, CatalogOp, LastCacheDefinition, LastCacheDelete, Wal, WalConfig, WalFileNotifier,
-    WalOp,
+    CardinalityLimits, CardinalityLimitsDefinition, CatalogBatch, CatalogOp,
+    ColumnDefaultDefinition, CompatibilityMode, CompatibilityModeDefinition, DatabaseDefinition,
+    DatabaseMetadataDefinition, DeleteDownsamplingTaskDefinition, DeleteTableDefinition,
+    DistinctCacheDefinition, DistinctCacheDelete, DownsamplingAggregate, DownsamplingTask,
+    DownsamplingTaskDefinition, DropColumnDefinition, FieldCoercion, FieldCoercionDefinition,
+    FieldData, FieldDataType, FieldDefinition, Gen1Duration, Gen1DurationDefinition,
+    IdentifierPolicy, IdentifierPolicyDefinition, LastCacheAggregate, LastCacheDefinition,
+    LastCacheDelete, LastCacheValueColumnsDef, NotifierId, RenameColumnDefinition,
+    RenameDatabaseDefinition, RenameTableDefinition, RetainedWalFiles, RetentionPeriodDefinition,
+    SchemaLimits, SchemaLimitsDefinition, SchemaMode, SchemaModeDefinition, SeriesKeyPolicy,
+    SeriesKeyPolicyDefinition, TableMetadataDefinition, Wal, WalAckLevel, WalConfig,
+    WalFileNotifier, WalFileSequenceNumber, WalOp, WalSubscription, WriteFence, WriteRateLimit,
+    WriteRateLimitDefinition, WriteTimeLimitsDefinition,
 };
-use iox_query::chunk_statistics::{create_chunk_statistics, NoColumnRanges};
+use iox_query::chunk_statistics::{create_chunk_statistics, ColumnRange, ColumnRanges};
+use iox_query::provider::ProviderBuilder;
 use iox_query::QueryChunk;
 use iox_time::{Time, TimeProvider};
+use metric::Registry;
 use object_store::path::Path as ObjPath;
 use object_store::{ObjectMeta, ObjectStore};
-use observability_deps::tracing::{debug, error};
+use observability_deps::tracing::{debug, error, info, warn};
 use parquet_file::storage::ParquetExecInput;
+use schema::sort::SortKey;
 use schema::Schema;
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use thiserror::Error;
+use tokio::io::AsyncBufRead;
 use tokio::sync::watch::Receiver;
+use tokio::sync::{watch, Mutex, RwLock, RwLockReadGuard};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -73,6 +134,9 @@ pub enum Error {
     #[error("error in last cache: {0}")]
     LastCacheError(#[from] last_cache::Error),
 
+    #[error("error in distinct cache: {0}")]
+    DistinctCacheError(#[from] distinct_cache::Error),
+
     #[error("tried accessing database and table that do not exist")]
     DbDoesNotExist,
 
@@ -93,6 +157,63 @@ pub enum Error {
 
     #[error("cannot write to a read-only server")]
     NoWriteInReadOnly,
+
+    #[error(
+        "cannot write, as this server does not hold the lease on its host identifier prefix, \
+        which another server may have taken over"
+    )]
+    NotLeader,
+
+    #[error("error acquiring the lease on the host identifier prefix: {0}")]
+    Lease(#[from] lease::Error),
+
+    #[error(
+        "write buffer is full ({size} bytes buffered, limit is {limit} bytes), \
+        try again once buffered data has been persisted"
+    )]
+    BufferFull { size: usize, limit: usize },
+
+    #[error("error reading line protocol: {0}")]
+    ReadLineProtocol(#[source] std::io::Error),
+
+    #[error("cardinality limit exceeded: {}", .0.error_message)]
+    CardinalityLimitExceeded(WriteLineError),
+
+    #[error("error converting record batch to rows: {0}")]
+    RecordBatchConversion(#[from] rows::Error),
+
+    #[error(
+        "write rate limit exceeded for database {db_name}, try again in {:.3}s",
+        retry_after.as_secs_f64()
+    )]
+    RateLimited {
+        db_name: String,
+        retry_after: Duration,
+    },
+
+    #[error("table {table_name} already exists in database {db_name}")]
+    TableAlreadyExists { db_name: String, table_name: String },
+
+    #[error("invalid table definition: {0}")]
+    InvalidTableDefinition(String),
+
+    #[error("error compacting persisted files: {0}")]
+    Compaction(#[from] compactor::Error),
+
+    #[error("error running downsampling tasks: {0}")]
+    Downsampling(#[from] downsampling::Error),
+
+    #[error("error exporting table: {0}")]
+    Export(#[from] export::Error),
+
+    #[error("error importing parquet files: {0}")]
+    Import(#[from] import::Error),
+
+    #[error("invalid downsampling task: {0}")]
+    InvalidDownsamplingTask(String),
+
+    #[error("downsampling task {0} does not exist")]
+    DownsamplingTaskDoesNotExist(String),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -104,27 +225,114 @@ pub struct WriteRequest<'a> {
     pub default_time: u64,
 }
 
+/// Whether the write buffer accepts writes, which can be changed at runtime with
+/// [`WriteBufferImpl::set_mode`]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum BufferMode {
+    /// Writes are accepted
+    #[default]
+    ReadWrite,
+    /// Writes are rejected, but data that has already been written stays in the buffer until it
+    /// is snapshot as usual
+    ReadOnly,
+    /// Writes are rejected, and all of the data in the buffer is persisted, e.g., so that the
+    /// server can be replaced without needing to replay the WAL
+    DrainAndPersist,
+}
+
 #[derive(Debug)]
 pub struct WriteBufferImpl {
     catalog: Arc<Catalog>,
     persister: Arc<Persister>,
-    // NOTE(trevor): the parquet cache interface may be used to register other cache
-    // requests from the write buffer, e.g., during query...
-    #[allow(dead_code)]
     parquet_cache: Option<Arc<dyn ParquetCacheOracle>>,
     persisted_files: Arc<PersistedFiles>,
     buffer: Arc<QueryableBuffer>,
-    wal_config: WalConfig,
+    /// The WAL config, which is updated when the settings that can be changed at runtime are
+    wal_config: parking_lot::RwLock<WalConfig>,
     wal: Arc<dyn Wal>,
     time_provider: Arc<dyn TimeProvider>,
     last_cache: Arc<LastCacheProvider>,
+    distinct_cache: Arc<DistinctCacheProvider>,
+    buffer_mem_limit_bytes: Option<usize>,
+    buffer_full_timeout: Option<Duration>,
+    cardinality: Arc<CardinalityTracker>,
+    tag_values: Arc<TagValueDictionary>,
+    record_rejected_lines: bool,
+    rate_limiter: Arc<WriteRateLimiter>,
+    mode: RwLock<BufferMode>,
+    event_listeners: Arc<WriteEventListeners>,
+    transforms: WriteTransforms,
+    audit_log: AuditLog,
+    compactor: Compactor,
+    /// The files that retention dropped on its last run. They are deleted by the next run, rather
+    /// than straight away, so that the queries that were planned with them can finish.
+    expired_files: Mutex<Vec<ParquetFile>>,
+    parquet_gc: ParquetGarbageCollector,
+    downsampler: Downsampler,
+    writer_files: WriterFiles,
+    /// The lease on the host identifier prefix, if one is held
+    lease: Option<Arc<Lease>>,
 }
 
-/// The maximum number of snapshots to load on start
+/// The maximum number of snapshots to load on start, and the number of older snapshots that are
+/// loaded at a time once a query needs them
 pub const N_SNAPSHOTS_TO_LOAD_ON_START: usize = 1_000;
 
-impl WriteBufferImpl {
-    pub async fn new(
+/// The table that the lines dropped from partial writes are recorded in, when enabled with
+/// [`WriteBufferImplArgs::record_rejected_lines`]
+pub const REJECTED_WRITES_TABLE_NAME: &str = "_rejected_writes";
+
+/// The number of bytes of line protocol that are read from a streamed write, with
+/// [`Bufferer::write_lp_stream`], before they are validated and buffered as a batch
+const STREAM_WRITE_BATCH_BYTES: usize = 4 * 1024 * 1024;
+
+/// Arguments to [`WriteBufferImpl::new`]
+#[derive(Debug)]
+pub struct WriteBufferImplArgs {
+    pub persister: Arc<Persister>,
+    pub catalog: Arc<Catalog>,
+    pub last_cache: Arc<LastCacheProvider>,
+    pub time_provider: Arc<dyn TimeProvider>,
+    pub executor: Arc<iox_query::exec::Executor>,
+    pub wal_config: WalConfig,
+    /// A directory on local disk that WAL files are written to before they are uploaded to object
+    /// storage, so that writes are acknowledged without waiting on object storage. If `None`,
+    /// WAL files are written directly to object storage.
+    pub wal_local_dir: Option<PathBuf>,
+    /// If set, the progress of replaying the WAL on startup is sent on this channel
+    pub wal_replay_progress: Option<watch::Sender<ReplayProgress>>,
+    pub parquet_cache: Option<Arc<dyn ParquetCacheOracle>>,
+    /// The number of the most recent persisted parquet files to load into the parquet cache on
+    /// startup, as many of them as fit in it
+    pub parquet_cache_prefill_files: usize,
+    /// The amount of memory, in bytes, the in-memory buffer may use before it is snapshot early.
+    /// If `None`, the buffer is unbounded.
+    pub buffer_mem_limit_bytes: Option<usize>,
+    /// If set, writes that arrive while the buffer is over `buffer_mem_limit_bytes` are held back
+    /// until the early snapshot brings it under the limit, and are rejected with
+    /// [`Error::BufferFull`] if that takes longer than this. If `None`, writes are accepted
+    /// regardless, and the limit only forces the snapshot.
+    pub buffer_full_timeout: Option<Duration>,
+    /// Whether the lines dropped from partial writes are recorded in the
+    /// [`REJECTED_WRITES_TABLE_NAME`] table of their database
+    pub record_rejected_lines: bool,
+    /// How the last caches are populated with the writes that are replayed from the WAL
+    pub last_cache_replay: LastCacheReplay,
+    /// The host identifier prefixes of the other writers whose persisted files are queried along
+    /// with those of this host
+    pub other_writers: Vec<String>,
+    /// If set, a lease on the host identifier prefix of the `persister` is acquired for this long
+    /// before anything is written to it, and renewed until the buffer is shut down, so that only
+    /// one server writes to the prefix at a time. Writes are refused if the lease is lost.
+    pub lease_duration: Option<Duration>,
+    pub metric_registry: Arc<Registry>,
+}
+
+impl WriteBufferImplArgs {
+    /// Arguments with the given dependencies, and the defaults for everything else: the WAL is
+    /// written directly to object storage, the buffer is unbounded, no other writers are queried,
+    /// and no lease is held. The defaults can be overridden with struct update syntax.
+    pub fn new(
         persister: Arc<Persister>,
         catalog: Arc<Catalog>,
         last_cache: Arc<LastCacheProvider>,
@@ -132,11 +340,130 @@ impl WriteBufferImpl {
         executor: Arc<iox_query::exec::Executor>,
         wal_config: WalConfig,
         parquet_cache: Option<Arc<dyn ParquetCacheOracle>>,
+    ) -> Self {
+        Self {
+            persister,
+            catalog,
+            last_cache,
+            time_provider,
+            executor,
+            wal_config,
+            wal_local_dir: None,
+            wal_replay_progress: None,
+            parquet_cache,
+            parquet_cache_prefill_files: 0,
+            buffer_mem_limit_bytes: None,
+            buffer_full_timeout: None,
+            record_rejected_lines: false,
+            last_cache_replay: LastCacheReplay::default(),
+            other_writers: vec![],
+            lease_duration: None,
+            metric_registry: Default::default(),
+        }
+    }
+}
+
+impl WriteBufferImpl {
+    pub async fn new(args: WriteBufferImplArgs) -> Result<Self> {
+        let lease = Self::acquire_lease(&args).await?;
+        Self::load(args, None, lease).await
+    }
+
+    /// Creates the write buffer of a read-only replica of the leader, the host that
+    /// `args.persister` persists the files of, and `args.catalog` was loaded from. The replica
+    /// follows the leader's WAL, and loads the snapshots that the leader persists, so that it can
+    /// be queried in place of the leader, but never writes to object storage itself, nor accepts
+    /// writes. The WAL settings, and directory, in `args` are not used.
+    pub async fn new_follower(
+        args: WriteBufferImplArgs,
+        replication: ReplicationConfig,
     ) -> Result<Self> {
-        // load snapshots and replay the wal into the in memory buffer
-        let persisted_snapshots = persister
-            .load_snapshots(N_SNAPSHOTS_TO_LOAD_ON_START)
-            .await?;
+        Self::load(args, Some(replication), None).await
+    }
+
+    /// Acquire the lease on the host identifier prefix of the write buffer, if it is configured
+    /// to hold one, waiting for any other server that holds it to release it, or for it to expire
+    async fn acquire_lease(args: &WriteBufferImplArgs) -> Result<Option<Arc<Lease>>> {
+        let Some(lease_duration) = args.lease_duration else {
+            return Ok(None);
+        };
+        let lease = Lease::acquire(
+            args.persister.object_store(),
+            args.persister.host_identifier_prefix(),
+            lease_duration,
+            Arc::clone(&args.time_provider),
+        )
+        .await?;
+        args.persister
+            .set_write_fence(Arc::clone(&lease) as Arc<dyn WriteFence>);
+        Ok(Some(lease))
+    }
+
+    async fn load(
+        WriteBufferImplArgs {
+            persister,
+            catalog,
+            last_cache,
+            time_provider,
+            executor,
+            wal_config,
+            wal_local_dir,
+            wal_replay_progress,
+            parquet_cache,
+            parquet_cache_prefill_files,
+            buffer_mem_limit_bytes,
+            buffer_full_timeout,
+            record_rejected_lines,
+            last_cache_replay,
+            other_writers,
+            lease_duration: _,
+            metric_registry,
+        }: WriteBufferImplArgs,
+        replication: Option<ReplicationConfig>,
+        lease: Option<Arc<Lease>>,
+    ) -> Result<Self> {
+        // the files persisted by a snapshot that was interrupted are not in any snapshot, and
+        // are persisted again when the wal files it was taken from are replayed. Those of the
+        // leader of a replica may be of a snapshot that it is still persisting:
+        if replication.is_none() {
+            let orphans = persister.recover_interrupted_snapshots().await?;
+            if !orphans.is_empty() {
+                info!(
+                    n_orphans = orphans.len(),
+                    "deleted the parquet files of interrupted snapshots"
+                );
+            }
+        }
+
+        // load snapshots and replay the wal into the in memory buffer. If the persisted files
+        // have an index, only the snapshots taken since it was written are loaded, along with it.
+        // Otherwise, the snapshots older than those loaded here are loaded when a query needs
+        // them:
+        let SnapshotPage {
+            snapshots: persisted_snapshots,
+            continuation: older_snapshots,
+        } = match persister.load_persisted_files_index().await? {
+            Some(index) => {
+                let mut snapshots = persister
+                    .load_snapshots_after(index.snapshot.snapshot_sequence_number)
+                    .await?;
+                info!(
+                    snapshot_sequence_number = %index.snapshot.snapshot_sequence_number,
+                    n_snapshots_since = snapshots.len(),
+                    "loading persisted files from their index"
+                );
+                snapshots.push(index.into_snapshot());
+                SnapshotPage {
+                    snapshots,
+                    continuation: None,
+                }
+            }
+            None => {
+                persister
+                    .load_snapshots_page(None, N_SNAPSHOTS_TO_LOAD_ON_START)
+                    .await?
+            }
+        };
         let last_wal_sequence_number = persisted_snapshots
             .first()
             .map(|s| s.wal_file_sequence_number);
@@ -163,43 +490,243 @@ impl WriteBufferImpl {
             .first()
             .map(|s| s.next_file_id.set_next_id())
             .unwrap_or(());
-        let persisted_files = Arc::new(PersistedFiles::new_from_persisted_snapshots(
-            persisted_snapshots,
-        ));
-        let queryable_buffer = Arc::new(QueryableBuffer::new(
+        // the catalog can use ids that are not in the snapshots, if databases were imported into
+        // it from another host, and they must not be used again:
+        let next_ids = catalog.inner().read().next_ids();
+        if next_ids.db_id > DbId::next_id() {
+            next_ids.db_id.set_next_id();
+        }
+        if next_ids.table_id > TableId::next_id() {
+            next_ids.table_id.set_next_id();
+        }
+        if next_ids.column_id > ColumnId::next_id() {
+            next_ids.column_id.set_next_id();
+        }
+        let persisted_files = Arc::new(PersistedFiles::new_from_snapshot_page(SnapshotPage {
+            snapshots: persisted_snapshots,
+            continuation: older_snapshots,
+        }));
+        // the files that were compacted are replaced by the gen2 files they were compacted into:
+        let compactor = Compactor::load(
+            Arc::clone(&catalog),
+            Arc::clone(&persister),
+            Arc::clone(&persisted_files),
+            Arc::clone(&executor),
+            parquet_cache.clone(),
+            Arc::clone(&time_provider),
+        )
+        .await?;
+        let parquet_gc = ParquetGarbageCollector::new(
+            Arc::clone(&persister),
+            Arc::clone(&persisted_files),
+            &metric_registry,
+        );
+        let downsampler = Downsampler::load(
+            Arc::clone(&persister),
+            Arc::clone(&persisted_files),
+            Arc::clone(&executor),
+        )
+        .await?;
+        let writer_files =
+            WriterFiles::load(&persister, &other_writers, N_SNAPSHOTS_TO_LOAD_ON_START).await?;
+        // the files that have expired since the snapshots, and compactions, that have them were
+        // persisted are not loaded:
+        let now_ns = time_provider.now().timestamp_nanos();
+        for db_schema in catalog.list_db_schema() {
+            if let Some(cutoff_ns) = db_schema.retention_cutoff_ns(now_ns) {
+                persisted_files.remove_files_older_than(db_schema.id, cutoff_ns);
+            }
+        }
+        // neither are those of the tables that were hard deleted since. A replica's snapshots
+        // may have tables that its leader created after the catalog was loaded, so it keeps them:
+        if replication.is_none() {
+            let tables = catalog
+                .list_db_schema()
+                .iter()
+                .flat_map(|db_schema| {
+                    db_schema
+                        .table_ids()
+                        .into_iter()
+                        .map(|table_id| (db_schema.id, table_id))
+                })
+                .collect();
+            persisted_files.retain_tables(tables);
+        }
+        if let Some(parquet_cache) = &parquet_cache {
+            prefill_parquet_cache(
+                parquet_cache.as_ref(),
+                &persisted_files,
+                parquet_cache_prefill_files,
+            );
+        }
+        // the settings that were changed at runtime take precedence over those passed in:
+        let wal_config = match persister.load_wal_config().await? {
+            Some(runtime_config) => {
+                info!(
+                    ?runtime_config,
+                    "using wal config that was updated at runtime"
+                );
+                wal_config.with_runtime_config(runtime_config)
+            }
+            None => wal_config,
+        };
+        // the values in the last caches when they were last persisted are restored before the WAL
+        // is replayed, which pushes the newer values into them. They are only a cache, so the
+        // server starts with them empty if they cannot be loaded:
+        match persister.load_last_caches().await {
+            Ok(Some(contents)) => last_cache.restore_contents(contents),
+            Ok(None) => (),
+            Err(e) => error!(%e, "failed to load the last cache contents, starting them empty"),
+        }
+        let distinct_cache = DistinctCacheProvider::new_from_catalog(Arc::clone(&catalog));
+        let audit_log = AuditLog::new(Arc::clone(&persister)).await?;
+        let event_listeners = Arc::new(WriteEventListeners::default());
+        let tag_values = Arc::new(TagValueDictionary::default());
+        let mut queryable_buffer = QueryableBuffer::new(
             executor,
             Arc::clone(&catalog),
             Arc::clone(&persister),
             Arc::clone(&last_cache),
+            Arc::clone(&distinct_cache),
             Arc::clone(&persisted_files),
             parquet_cache.clone(),
-        ));
+            Arc::clone(&event_listeners),
+            last_cache_replay,
+            Arc::clone(&tag_values),
+            &metric_registry,
+        );
+        if replication.is_some() {
+            queryable_buffer =
+                queryable_buffer.with_leader_snapshots(last_snapshot_sequence_number);
+        }
+        let queryable_buffer = Arc::new(queryable_buffer);
 
         // create the wal instance, which will replay into the queryable buffer and start
-        // the background flush task.
-        let wal = WalObjectStore::new(
-            persister.object_store(),
-            persister.host_identifier_prefix(),
-            Arc::clone(&queryable_buffer) as Arc<dyn WalFileNotifier>,
-            wal_config,
-            last_wal_sequence_number,
-            last_snapshot_sequence_number,
-        )
-        .await?;
+        // the background flush task. A replica follows the wal of the leader instead, catching
+        // up with it before it starts polling it for new files.
+        let wal: Arc<dyn Wal> = match replication {
+            None => {
+                WalObjectStore::new(
+                    persister.object_store(),
+                    persister.host_identifier_prefix(),
+                    Arc::clone(&queryable_buffer) as Arc<dyn WalFileNotifier>,
+                    wal_config,
+                    wal_local_dir,
+                    persister.key_provider(),
+                    last_wal_sequence_number,
+                    last_snapshot_sequence_number,
+                    wal_replay_progress,
+                    lease.clone().map(|lease| lease as Arc<dyn WriteFence>),
+                    &metric_registry,
+                )
+                .await?
+            }
+            Some(ReplicationConfig { poll_interval }) => {
+                WalFollower::new(
+                    persister.object_store(),
+                    persister.host_identifier_prefix(),
+                    Arc::clone(&queryable_buffer) as Arc<dyn WalFileNotifier>,
+                    persister.key_provider(),
+                    last_wal_sequence_number,
+                    last_snapshot_sequence_number,
+                    poll_interval,
+                    &metric_registry,
+                )
+                .await?
+            }
+        };
+        queryable_buffer.finish_wal_replay();
+        let mode = match replication {
+            None => BufferMode::ReadWrite,
+            Some(_) => BufferMode::ReadOnly,
+        };
 
         Ok(Self {
             catalog,
             parquet_cache,
             persister,
-            wal_config,
+            wal_config: parking_lot::RwLock::new(wal_config),
             wal,
             time_provider,
             last_cache,
+            distinct_cache,
             persisted_files,
             buffer: queryable_buffer,
+            buffer_mem_limit_bytes,
+            buffer_full_timeout,
+            cardinality: Arc::new(CardinalityTracker::new(&metric_registry)),
+            tag_values,
+            record_rejected_lines,
+            rate_limiter: Arc::new(WriteRateLimiter::default()),
+            mode: RwLock::new(mode),
+            event_listeners,
+            transforms: WriteTransforms::default(),
+            audit_log,
+            compactor,
+            expired_files: Mutex::new(vec![]),
+            parquet_gc,
+            downsampler,
+            writer_files,
+            lease,
         })
     }
 
+    /// Creates the write buffer in the state it was in at the recovery target, discarding the
+    /// writes after it, e.g., to undo an accidental bad ingest.
+    ///
+    /// The snapshots taken after the target are discarded, and the WAL files they were taken from
+    /// are replayed instead, so the WAL files since the last snapshot before the target must
+    /// still be in the WAL or retained. Nothing is deleted: the discarded snapshot and WAL files
+    /// are moved aside in object storage. The catalog is not rewound, so it keeps any tables and
+    /// columns that were created after the target.
+    pub async fn recover_to(args: WriteBufferImplArgs, target: RecoveryTarget) -> Result<Self> {
+        // the lease is held before the WAL and snapshots are rewound:
+        let lease = Self::acquire_lease(&args).await?;
+        let persister = Arc::clone(&args.persister);
+        let recovery = WalRecovery::new(
+            persister.object_store(),
+            persister.host_identifier_prefix(),
+            persister.key_provider(),
+        );
+        let target = recovery.resolve(target).await?;
+        info!(%target, "recovering to wal file");
+
+        // a snapshot is kept if it was triggered by the file after the target, or earlier, since
+        // it was only taken from the files before the one that triggered it:
+        let mut newest_snapshot = None;
+        let kept_snapshot = loop {
+            match persister.load_snapshots(1).await?.pop() {
+                Some(snapshot) if snapshot.wal_file_sequence_number > target.next() => {
+                    info!(
+                        snapshot_sequence_number = %snapshot.snapshot_sequence_number,
+                        "discarding snapshot taken after the recovery target"
+                    );
+                    persister
+                        .discard_snapshot(snapshot.snapshot_sequence_number)
+                        .await?;
+                    newest_snapshot.get_or_insert(snapshot);
+                }
+                snapshot => break snapshot,
+            }
+        };
+        recovery
+            .rewind(kept_snapshot.map(|s| s.wal_file_sequence_number), target)
+            .await?;
+
+        let write_buffer = Self::load(args, None, lease).await?;
+
+        // the catalog is not rewound, so the ids that were allocated after the target must not be
+        // used again:
+        if let Some(snapshot) = newest_snapshot {
+            snapshot.next_db_id.set_next_id();
+            snapshot.next_table_id.set_next_id();
+            snapshot.next_column_id.set_next_id();
+            snapshot.next_file_id.set_next_id();
+        }
+
+        Ok(write_buffer)
+    }
+
     pub fn catalog(&self) -> Arc<Catalog> {
         Arc::clone(&self.catalog)
     }
@@ -208,6 +735,29 @@ impl WriteBufferImpl {
         Arc::clone(&self.persisted_files)
     }
 
+    /// Attach a notifier to the WAL, which is sent the contents of every WAL file that is flushed
+    /// alongside the buffer, e.g., to record metrics or ship the WAL to a replica
+    pub fn attach_wal_notifier(&self, notifier: Arc<dyn WalFileNotifier>) -> NotifierId {
+        self.wal.attach_notifier(notifier)
+    }
+
+    /// Detach a notifier that was attached with [`WriteBufferImpl::attach_wal_notifier`]
+    pub fn detach_wal_notifier(&self, id: NotifierId) -> bool {
+        self.wal.detach_notifier(id)
+    }
+
+    /// Set the [`WriteTransform`] that is applied to the rows written to a database using the v1
+    /// data model, replacing any existing transform, or remove it by passing `None`. Transforms
+    /// are not persisted, so need to be set again when the server is restarted.
+    pub fn set_write_transform(&self, db_name: &str, transform: Option<Arc<dyn WriteTransform>>) {
+        self.transforms.set(db_name, transform);
+    }
+
+    /// Register a listener to be notified of writes to, and snapshots of, the buffer
+    pub fn register_event_listener(&self, listener: Arc<dyn WriteEventListener>) {
+        self.event_listeners.register(listener);
+    }
+
     async fn write_lp(
         &self,
         db_name: NamespaceName<'static>,
@@ -215,41 +765,146 @@ impl WriteBufferImpl {
         ingest_time: Time,
         accept_partial: bool,
         precision: Precision,
+    ) -> Result<BufferedWriteRequest> {
+        self.write_lp_with_durability(
+            db_name,
+            lp,
+            ingest_time,
+            accept_partial,
+            precision,
+            Durability::WalSync,
+        )
+        .await
+    }
+
+    async fn write_lp_with_durability(
+        &self,
+        db_name: NamespaceName<'static>,
+        lp: &str,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+        durability: Durability,
     ) -> Result<BufferedWriteRequest> {
         debug!("write_lp to {} in writebuffer", db_name);
 
+        let _mode = self.check_writable().await?;
+        self.wait_for_buffer_capacity().await?;
+
         // validated lines will update the in-memory catalog, ensuring that all write operations
         // past this point will be infallible
-        let result = WriteValidator::initialize(
-            db_name.clone(),
-            self.catalog(),
-            ingest_time.timestamp_nanos(),
-        )?
-        .v1_parse_lines_and_update_schema(lp, accept_partial, ingest_time, precision)?
-        .convert_lines_to_buffer(self.wal_config.gen1_duration);
+        let result =
+            self.validate_v1_lines(&db_name, lp, ingest_time, accept_partial, precision)?;
 
-        // if there were catalog updates, ensure they get persisted to the wal, so they're
-        // replayed on restart
-        let mut ops = Vec::with_capacity(2);
-        if let Some(catalog_batch) = result.catalog_updates {
-            ops.push(WalOp::Catalog(catalog_batch));
+        self.write_validated_lines(db_name, result, durability)
+            .await
+    }
+
+    /// Write line protocol to several databases, which are validated separately, but written to
+    /// the WAL together, so that they are flushed to a single WAL file.
+    ///
+    /// If the write to one of the databases fails validation, none of the data is written, but
+    /// any schema changes from the writes to the databases before it are still written to the WAL,
+    /// since they have already been applied to the catalog.
+    async fn write_lp_multi(
+        &self,
+        writes: Vec<(NamespaceName<'static>, &str)>,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+    ) -> Result<Vec<BufferedWriteRequest>> {
+        let _mode = self.check_writable().await?;
+        self.wait_for_buffer_capacity().await?;
+
+        let mut catalog_ops = vec![];
+        let mut write_ops = Vec::with_capacity(writes.len());
+        let mut new_series = Vec::with_capacity(writes.len());
+        let mut results = Vec::with_capacity(writes.len());
+        for (db_name, lp) in writes {
+            let result = match self.validate_v1_lines(
+                &db_name,
+                lp,
+                ingest_time,
+                accept_partial,
+                precision,
+            ) {
+                Ok(result) => result,
+                Err(e) => {
+                    if !catalog_ops.is_empty() {
+                        let catalog_batches = catalog_batches(&catalog_ops);
+                        self.wal.write_ops(catalog_ops).await?;
+                        self.audit_log
+                            .record(&catalog_batches, AuditSource::Write)
+                            .await;
+                    }
+                    return Err(e);
+                }
+            };
+            if let Some(catalog_batch) = result.catalog_updates {
+                catalog_ops.push(WalOp::Catalog(catalog_batch));
+            }
+            write_ops.push(WalOp::Write(result.valid_data));
+            new_series.push(result.new_series);
+            if let Some(rejected) = self.validate_rejected_lines(&db_name, &result.errors) {
+                catalog_ops.extend(rejected.catalog_updates.map(WalOp::Catalog));
+                write_ops.push(WalOp::Write(rejected.valid_data));
+            }
+            results.push(BufferedWriteRequest {
+                db_name,
+                invalid_lines: result.errors,
+                line_count: result.line_count,
+                field_count: result.field_count,
+                index_count: result.index_count,
+                // set once the ops have been written to the WAL:
+                ack_level: WalAckLevel::Buffered,
+            });
         }
-        ops.push(WalOp::Write(result.valid_data));
 
-        // write to the wal. Behind the scenes the ops get buffered in memory and once a second (or
-        // whatever the configured wal flush interval is set to) the buffer is flushed and all the
-        // data is persisted into a single wal file in the configured object store. Then the
-        // contents are sent to the configured notifier, which in this case is the queryable buffer.
-        // Thus, after this returns, the data is both durable and queryable.
-        self.wal.write_ops(ops).await?;
+        // the catalog ops go first, so that they are applied before any of the writes that
+        // depend on them when the WAL is replayed:
+        let catalog_batches = catalog_batches(&catalog_ops);
+        catalog_ops.extend(write_ops);
+        let ack_level = self.wal.write_ops(catalog_ops).await?;
+        for result in &mut results {
+            result.ack_level = ack_level;
+        }
+        for new_series in new_series {
+            self.cardinality.record(new_series);
+        }
+        self.audit_log
+            .record(&catalog_batches, AuditSource::Write)
+            .await;
+        self.snapshot_if_buffer_over_limit().await;
 
-        Ok(BufferedWriteRequest {
-            db_name,
-            invalid_lines: result.errors,
-            line_count: result.line_count,
-            field_count: result.field_count,
-            index_count: result.index_count,
-        })
+        for result in &results {
+            self.notify_write_accepted(result);
+        }
+
+        Ok(results)
+    }
+
+    /// Validate line protocol using the v1 data model, updating the catalog with any schema
+    /// changes it makes
+    fn validate_v1_lines(
+        &self,
+        db_name: &NamespaceName<'static>,
+        lp: &str,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+    ) -> Result<ValidatedLines> {
+        Ok(WriteValidator::initialize(
+            db_name.clone(),
+            self.catalog(),
+            ingest_time.timestamp_nanos(),
+        )?
+        .with_cardinality_tracker(Arc::clone(&self.cardinality))
+        .with_rate_limiter(Arc::clone(&self.rate_limiter), self.time_provider.now())
+        .with_transform(self.transforms.get(db_name.as_str()))
+        .with_tag_values(Arc::clone(&self.tag_values))
+        .v1_parse_lines_and_update_schema(lp, accept_partial, ingest_time, precision)
+        .inspect_err(|e| self.notify_write_failed(db_name, e))?
+        .convert_lines_to_buffer(self.gen1_duration()))
     }
 
     async fn write_lp_v3(
@@ -260,6 +915,9 @@ impl WriteBufferImpl {
         accept_partial: bool,
         precision: Precision,
     ) -> Result<BufferedWriteRequest> {
+        let _mode = self.check_writable().await?;
+        self.wait_for_buffer_capacity().await?;
+
         // validated lines will update the in-memory catalog, ensuring that all write operations
         // past this point will be infallible
         let result = WriteValidator::initialize(
@@ -267,168 +925,447 @@ impl WriteBufferImpl {
             self.catalog(),
             ingest_time.timestamp_nanos(),
         )?
-        .v3_parse_lines_and_update_schema(lp, accept_partial, ingest_time, precision)?
-        .convert_lines_to_buffer(self.wal_config.gen1_duration);
-
-        // if there were catalog updates, ensure they get persisted to the wal, so they're
-        // replayed on restart
-        let mut ops = Vec::with_capacity(2);
-        if let Some(catalog_batch) = result.catalog_updates {
-            ops.push(WalOp::Catalog(catalog_batch));
-        }
-        ops.push(WalOp::Write(result.valid_data));
-
-        // write to the wal. Behind the scenes the ops get buffered in memory and once a second (or
-        // whatever the configured wal flush interval is set to) the buffer is flushed and all the
-        // data is persisted into a single wal file in the configured object store. Then the
-        // contents are sent to the configured notifier, which in this case is the queryable buffer.
-        // Thus, after this returns, the data is both durable and queryable.
-        self.wal.write_ops(ops).await?;
-
-        Ok(BufferedWriteRequest {
-            db_name,
-            invalid_lines: result.errors,
-            line_count: result.line_count,
-            field_count: result.field_count,
-            index_count: result.index_count,
-        })
+        .with_cardinality_tracker(Arc::clone(&self.cardinality))
+        .with_tag_values(Arc::clone(&self.tag_values))
+        .with_rate_limiter(Arc::clone(&self.rate_limiter), self.time_provider.now())
+        .v3_parse_lines_and_update_schema(lp, accept_partial, ingest_time, precision)
+        .inspect_err(|e| self.notify_write_failed(&db_name, e))?
+        .convert_lines_to_buffer(self.gen1_duration());
+
+        self.write_validated_lines(db_name, result, Durability::WalSync)
+            .await
     }
 
-    fn get_table_chunks(
+    /// Write line protocol that is read from `lp`, in batches of [`STREAM_WRITE_BATCH_BYTES`],
+    /// each of which is validated and written to the WAL before the next is read.
+    ///
+    /// If a batch fails, the batches before it have already been written, and are not rolled
+    /// back.
+    async fn write_lp_stream(
         &self,
-        database_name: &str,
-        table_name: &str,
-        filters: &[Expr],
-        projection: Option<&Vec<usize>>,
-        ctx: &dyn Session,
-    ) -> Result<Vec<Arc<dyn QueryChunk>>, DataFusionError> {
-        let db_schema = self.catalog.db_schema(database_name).ok_or_else(|| {
-            DataFusionError::Execution(format!("database {} not found", database_name))
-        })?;
+        db_name: NamespaceName<'static>,
+        mut lp: Box<dyn AsyncBufRead + Send + Unpin>,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+        durability: Durability,
+    ) -> Result<BufferedWriteRequest> {
+        debug!("write_lp_stream to {} in writebuffer", db_name);
 
-        let (table_id, table_schema) =
-            db_schema.table_schema_and_id(table_name).ok_or_else(|| {
-                DataFusionError::Execution(format!(
-                    "table {} not found in db {}",
-                    table_name, database_name
-                ))
-            })?;
+        let _mode = self.check_writable().await?;
 
-        let mut chunks = self.buffer.get_table_chunks(
-            Arc::clone(&db_schema),
-            table_name,
-            filters,
-            projection,
-            ctx,
-        )?;
+        let mut line_idx = 0;
+        let mut request: Option<BufferedWriteRequest> = None;
+        loop {
+            self.wait_for_buffer_capacity().await?;
+
+            // validated lines will update the in-memory catalog, ensuring that all write
+            // operations past this point will be infallible
+            let (validator, more) = WriteValidator::initialize(
+                db_name.clone(),
+                self.catalog(),
+                ingest_time.timestamp_nanos(),
+            )?
+            .with_cardinality_tracker(Arc::clone(&self.cardinality))
+            .with_rate_limiter(Arc::clone(&self.rate_limiter), self.time_provider.now())
+            .with_transform(self.transforms.get(db_name.as_str()))
+            .with_tag_values(Arc::clone(&self.tag_values))
+            .v1_parse_lines_batch_and_update_schema(
+                &mut lp,
+                &mut line_idx,
+                STREAM_WRITE_BATCH_BYTES,
+                accept_partial,
+                ingest_time,
+                precision,
+            )
+            .await
+            .inspect_err(|e| self.notify_write_failed(&db_name, e))?;
+            let result = validator.convert_lines_to_buffer(self.gen1_duration());
+
+            let batch = self
+                .write_validated_lines(db_name.clone(), result, durability)
+                .await?;
+            match request.as_mut() {
+                Some(request) => {
+                    request.invalid_lines.extend(batch.invalid_lines);
+                    request.line_count += batch.line_count;
+                    request.field_count += batch.field_count;
+                    request.index_count += batch.index_count;
+                }
+                None => request = Some(batch),
+            }
 
-        let parquet_files = self.persisted_files.get_files(db_schema.id, table_id);
+            if !more {
+                break;
+            }
+        }
 
-        let mut chunk_order = chunks.len() as i64;
+        Ok(request.expect("at least one batch is written"))
+    }
 
-        for parquet_file in parquet_files {
-            let parquet_chunk = parquet_chunk_from_file(
-                &parquet_file,
-                &table_schema,
-                self.persister.object_store_url().clone(),
-                self.persister.object_store(),
-                chunk_order,
-            );
-
-            chunk_order += 1;
+    async fn write_rows(
+        &self,
+        db_name: NamespaceName<'static>,
+        rows: Vec<WriteRow>,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+    ) -> Result<BufferedWriteRequest> {
+        debug!("write_rows to {} in writebuffer", db_name);
 
-            chunks.push(Arc::new(parquet_chunk));
-        }
+        let _mode = self.check_writable().await?;
+        self.wait_for_buffer_capacity().await?;
 
-        Ok(chunks)
+        // validated rows will update the in-memory catalog, ensuring that all write operations
+        // past this point will be infallible
+        let result = WriteValidator::initialize(
+            db_name.clone(),
+            self.catalog(),
+            ingest_time.timestamp_nanos(),
+        )?
+        .with_cardinality_tracker(Arc::clone(&self.cardinality))
+        .with_rate_limiter(Arc::clone(&self.rate_limiter), self.time_provider.now())
+        .with_transform(self.transforms.get(db_name.as_str()))
+        .with_tag_values(Arc::clone(&self.tag_values))
+        .v1_validate_rows_and_update_schema(&rows, accept_partial, ingest_time, precision)
+        .inspect_err(|e| self.notify_write_failed(&db_name, e))?
+        .convert_lines_to_buffer(self.gen1_duration());
+
+        self.write_validated_lines(db_name, result, Durability::WalSync)
+            .await
     }
-}
-
-pub fn parquet_chunk_from_file(
-    parquet_file: &ParquetFile,
-    table_schema: &Schema,
-    object_store_url: ObjectStoreUrl,
-    object_store: Arc<dyn ObjectStore>,
-    chunk_order: i64,
-) -> ParquetChunk {
-    let partition_key = data_types::PartitionKey::from(parquet_file.chunk_time.to_string());
-    let partition_id = data_types::partition::TransitionPartitionId::new(
-        data_types::TableId::new(0),
-        &partition_key,
-    );
 
-    let chunk_stats = create_chunk_statistics(
-        Some(parquet_file.row_count as usize),
-        table_schema,
-        Some(parquet_file.timestamp_min_max()),
-        &NoColumnRanges,
-    );
+    #[allow(clippy::too_many_arguments)]
+    async fn write_tabular(
+        &self,
+        db_name: NamespaceName<'static>,
+        format: TabularFormat,
+        body: &str,
+        mapping: &ColumnMapping,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+    ) -> Result<BufferedWriteRequest> {
+        debug!("write_tabular to {} in writebuffer", db_name);
 
-    let location = ObjPath::from(parquet_file.path.clone());
+        let _mode = self.check_writable().await?;
+        self.wait_for_buffer_capacity().await?;
 
-    let parquet_exec = ParquetExecInput {
-        object_store_url,
-        object_meta: ObjectMeta {
-            location,
-            last_modified: Default::default(),
-            size: parquet_file.size_bytes as usize,
-            e_tag: None,
-            version: None,
-        },
-        object_store,
-    };
+        // validated rows will update the in-memory catalog, ensuring that all write operations
+        // past this point will be infallible
+        let validator = WriteValidator::initialize(
+            db_name.clone(),
+            self.catalog(),
+            ingest_time.timestamp_nanos(),
+        )?
+        .with_cardinality_tracker(Arc::clone(&self.cardinality))
+        .with_rate_limiter(Arc::clone(&self.rate_limiter), self.time_provider.now())
+        .with_transform(self.transforms.get(db_name.as_str()))
+        .with_tag_values(Arc::clone(&self.tag_values));
+        let result = match format {
+            TabularFormat::Csv => validator.parse_csv_and_update_schema(
+                body,
+                mapping,
+                accept_partial,
+                ingest_time,
+                precision,
+            ),
+            TabularFormat::JsonLines => validator.parse_json_lines_and_update_schema(
+                body,
+                mapping,
+                accept_partial,
+                ingest_time,
+                precision,
+            ),
+        }
+        .inspect_err(|e| self.notify_write_failed(&db_name, e))?
+        .convert_lines_to_buffer(self.gen1_duration());
 
-    ParquetChunk {
-        schema: table_schema.clone(),
-        stats: Arc::new(chunk_stats),
-        partition_id,
-        sort_key: None,
-        id: ChunkId::new(),
-        chunk_order: ChunkOrder::new(chunk_order),
-        parquet_exec,
+        self.write_validated_lines(db_name, result, Durability::WalSync)
+            .await
     }
-}
 
-#[async_trait]
-impl Bufferer for WriteBufferImpl {
-    async fn write_lp(
+    async fn write_record_batch(
         &self,
-        database: NamespaceName<'static>,
-        lp: &str,
+        db_name: NamespaceName<'static>,
+        table_name: &str,
+        batch: RecordBatch,
         ingest_time: Time,
         accept_partial: bool,
-        precision: Precision,
     ) -> Result<BufferedWriteRequest> {
-        self.write_lp(database, lp, ingest_time, accept_partial, precision)
-            .await
+        let rows = rows::record_batch_to_rows(table_name, &batch)?;
+        self.write_rows(
+            db_name,
+            rows,
+            ingest_time,
+            accept_partial,
+            Precision::Nanosecond,
+        )
+        .await
     }
 
-    async fn write_lp_v3(
+    async fn validate_lp(
         &self,
-        database: NamespaceName<'static>,
+        db_name: NamespaceName<'static>,
         lp: &str,
-        ingest_time: Time,
-        accept_partial: bool,
         precision: Precision,
+    ) -> Result<WriteValidation> {
+        let ingest_time = self.time_provider.now();
+
+        // the new series of the lines are checked against the cardinality limits, but are only
+        // recorded by writes, and the lines are not charged to the write rate limit:
+        let result = WriteValidator::initialize_dry_run(
+            db_name.clone(),
+            self.catalog(),
+            ingest_time.timestamp_nanos(),
+        )?
+        .with_cardinality_tracker(Arc::clone(&self.cardinality))
+        .with_transform(self.transforms.get(db_name.as_str()))
+        .v1_parse_lines_and_update_schema(lp, true, ingest_time, precision)?
+        .convert_lines_to_buffer(self.gen1_duration());
+
+        Ok(WriteValidation {
+            db_name,
+            invalid_lines: result.errors,
+            line_count: result.line_count,
+            field_count: result.field_count,
+            index_count: result.index_count,
+            catalog_ops: result
+                .catalog_updates
+                .map(|batch| batch.ops)
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Write the validated lines, along with any catalog updates they made, to the WAL
+    async fn write_validated_lines(
+        &self,
+        db_name: NamespaceName<'static>,
+        result: ValidatedLines,
+        durability: Durability,
     ) -> Result<BufferedWriteRequest> {
-        self.write_lp_v3(database, lp, ingest_time, accept_partial, precision)
+        // if there were catalog updates, ensure they get persisted to the wal, so they're
+        // replayed on restart
+        let mut ops = Vec::with_capacity(2);
+        if let Some(catalog_batch) = result.catalog_updates {
+            ops.push(WalOp::Catalog(catalog_batch));
+        }
+        ops.push(WalOp::Write(result.valid_data));
+        if let Some(rejected) = self.validate_rejected_lines(&db_name, &result.errors) {
+            ops.extend(rejected.catalog_updates.map(WalOp::Catalog));
+            ops.push(WalOp::Write(rejected.valid_data));
+        }
+
+        let catalog_batches = catalog_batches(&ops);
+
+        // write to the wal. Behind the scenes the ops get buffered in memory and once a second (or
+        // whatever the configured wal flush interval is set to) the buffer is flushed and all the
+        // data is persisted into a single wal file in the configured object store. Then the
+        // contents are sent to the configured notifier, which in this case is the queryable buffer.
+        // Thus, after this returns, the data is both durable and queryable.
+        //
+        // If the write only needs to be buffered, the ops are added to the wal buffer without
+        // waiting for the flush, so the data is neither durable nor queryable until it happens.
+        let ack_level = match durability {
+            Durability::WalSync => self.wal.write_ops(ops).await?,
+            Durability::BufferedAck => {
+                for op in ops {
+                    self.wal.buffer_op_unconfirmed(op).await?;
+                }
+                WalAckLevel::Buffered
+            }
+        };
+        self.cardinality.record(result.new_series);
+        self.audit_log
+            .record(&catalog_batches, AuditSource::Write)
+            .await;
+        self.snapshot_if_buffer_over_limit().await;
+
+        let request = BufferedWriteRequest {
+            db_name,
+            invalid_lines: result.errors,
+            line_count: result.line_count,
+            field_count: result.field_count,
+            index_count: result.index_count,
+            ack_level,
+        };
+        self.notify_write_accepted(&request);
+
+        Ok(request)
+    }
+
+    /// Validate the rows that record the lines rejected from a partial write in the
+    /// [`REJECTED_WRITES_TABLE_NAME`] table, if that is enabled, so that they can be written to
+    /// the WAL along with the write. Failing to record the lines does not fail the write.
+    fn validate_rejected_lines(
+        &self,
+        db_name: &NamespaceName<'static>,
+        errors: &[WriteLineError],
+    ) -> Option<ValidatedLines> {
+        if !self.record_rejected_lines || errors.is_empty() {
+            return None;
+        }
+        let rows = errors
+            .iter()
+            .map(|e| WriteRow {
+                table_name: REJECTED_WRITES_TABLE_NAME.to_string(),
+                tags: vec![
+                    (
+                        "category".to_string(),
+                        e.error_category.as_str().to_string(),
+                    ),
+                    ("line_number".to_string(), e.line_number.to_string()),
+                ],
+                fields: vec![
+                    (
+                        "line".to_string(),
+                        WriteFieldValue::String(e.original_line.clone()),
+                    ),
+                    (
+                        "error".to_string(),
+                        WriteFieldValue::String(e.error_message.clone()),
+                    ),
+                ],
+                timestamp: None,
+            })
+            .collect::<Vec<_>>();
+
+        let now = self.time_provider.now();
+        let result =
+            WriteValidator::initialize(db_name.clone(), self.catalog(), now.timestamp_nanos())
+                .and_then(|validator| {
+                    validator.v1_validate_rows_and_update_schema(
+                        &rows,
+                        true,
+                        now,
+                        Precision::Nanosecond,
+                    )
+                })
+                .map(|validator| validator.convert_lines_to_buffer(self.gen1_duration()));
+        match result {
+            Ok(result) => {
+                // e.g., if the schema mode of the database does not allow the table to be created:
+                for error in &result.errors {
+                    error!(
+                        %db_name,
+                        error = %error.error_message,
+                        "unable to record rejected line"
+                    );
+                }
+                Some(result)
+            }
+            Err(e) => {
+                error!(%db_name, %e, "unable to record rejected lines");
+                None
+            }
+        }
+    }
+
+    /// Notify the event listeners of an accepted write, and of any lines that were dropped from it
+    fn notify_write_accepted(&self, request: &BufferedWriteRequest) {
+        self.event_listeners
+            .lines_rejected(request.db_name.as_str(), &request.invalid_lines);
+        self.event_listeners.write_accepted(request);
+    }
+
+    /// Notify the event listeners of the line that caused a write to fail validation
+    fn notify_write_failed(&self, db_name: &NamespaceName<'static>, error: &Error) {
+        if let Error::ParseError(line) | Error::CardinalityLimitExceeded(line) = error {
+            self.event_listeners
+                .lines_rejected(db_name.as_str(), std::slice::from_ref(line));
+        }
+    }
+
+    /// Check that the buffer is accepting writes, returning [`Error::NoWriteInReadOnly`] if it is
+    /// not, or [`Error::NotLeader`] if it has lost its lease. The returned guard holds off changes
+    /// to the mode until the write is done.
+    async fn check_writable(&self) -> Result<RwLockReadGuard<'_, BufferMode>> {
+        self.check_lease()?;
+        let mode = self.mode.read().await;
+        match *mode {
+            BufferMode::ReadWrite => Ok(mode),
+            BufferMode::ReadOnly | BufferMode::DrainAndPersist => Err(Error::NoWriteInReadOnly),
+        }
+    }
+
+    /// Check that the buffer holds its lease, if it has one, returning [`Error::NotLeader`] if
+    /// it does not
+    fn check_lease(&self) -> Result<()> {
+        match &self.lease {
+            Some(lease) if !lease.is_held() => Err(Error::NotLeader),
+            _ => Ok(()),
+        }
+    }
+
+    /// If writes are held back while the buffer is over its memory limit, and it is, force a
+    /// snapshot and wait for persistence to bring it back under the limit. Returns
+    /// [`Error::BufferFull`] if that doesn't happen within the `buffer_full_timeout`.
+    async fn wait_for_buffer_capacity(&self) -> Result<()> {
+        let (Some(limit), Some(timeout)) = (self.buffer_mem_limit_bytes, self.buffer_full_timeout)
+        else {
+            return Ok(());
+        };
+        if self.buffer.buffer_size_bytes() < limit {
+            return Ok(());
+        }
+
+        // writes are held back, so the snapshot cannot wait for another WAL file to be written:
+        self.wal.force_full_snapshot().await;
+        tokio::time::timeout(timeout, self.buffer.wait_for_size_below(limit))
             .await
+            .map_err(|_| Error::BufferFull {
+                size: self.buffer.buffer_size_bytes(),
+                limit,
+            })
     }
 
-    fn catalog(&self) -> Arc<Catalog> {
-        self.catalog()
+    /// Trigger an early snapshot if the buffer has grown past its memory limit
+    async fn snapshot_if_buffer_over_limit(&self) {
+        if let Some(limit) = self.buffer_mem_limit_bytes {
+            let size = self.buffer.buffer_size_bytes();
+            if size >= limit {
+                info!(
+                    size,
+                    limit, "write buffer over memory limit, forcing snapshot"
+                );
+                self.wal.force_snapshot().await;
+            }
+        }
     }
 
-    fn parquet_files(&self, db_id: DbId, table_id: TableId) -> Vec<ParquetFile> {
-        self.buffer.persisted_parquet_files(db_id, table_id)
+    /// Load the older snapshots that have the persisted files a query with `filters` may need,
+    /// if they have not been loaded yet
+    pub async fn load_older_snapshots(&self, filters: &[Expr]) -> Result<usize> {
+        let time_range = TimeRange::from_filters(filters);
+        let mut loaded = self
+            .writer_files
+            .load_older_snapshots(time_range.min, N_SNAPSHOTS_TO_LOAD_ON_START)
+            .await?;
+        if self.persisted_files.needs_older_snapshots(time_range.min) {
+            loaded += self
+                .persisted_files
+                .load_older_snapshots(
+                    &self.persister,
+                    time_range.min,
+                    N_SNAPSHOTS_TO_LOAD_ON_START,
+                )
+                .await?;
+        }
+        if loaded > 0 {
+            info!(n_snapshots = loaded, "loaded older snapshots for query");
+            // the older snapshots may have files that have expired since they were written:
+            self.remove_expired_files();
+        }
+        Ok(loaded)
     }
 
-    fn watch_persisted_snapshots(&self) -> Receiver<Option<PersistedSnapshot>> {
-        self.buffer.persisted_snapshot_notify_rx()
+    /// Load the catalogs of the other writers, and the snapshots and compaction generations that
+    /// they have persisted since they were last loaded, so that their newly persisted files are
+    /// queried. Returns the number of snapshots that were loaded.
+    pub async fn refresh_other_writers(&self) -> Result<usize> {
+        Ok(self.writer_files.refresh().await?)
     }
-}
 
-impl ChunkContainer for WriteBufferImpl {
     fn get_table_chunks(
         &self,
         database_name: &str,
@@ -436,1446 +1373,6809 @@ impl ChunkContainer for WriteBufferImpl {
         filters: &[Expr],
         projection: Option<&Vec<usize>>,
         ctx: &dyn Session,
-    ) -> crate::Result<Vec<Arc<dyn QueryChunk>>, DataFusionError> {
-        self.get_table_chunks(database_name, table_name, filters, projection, ctx)
-    }
-}
+    ) -> Result<Vec<Arc<dyn QueryChunk>>, DataFusionError> {
+        let db_schema = self.catalog.db_schema(database_name).ok_or_else(|| {
+            DataFusionError::Execution(format!("database {} not found", database_name))
+        })?;
 
-#[async_trait::async_trait]
-impl LastCacheManager for WriteBufferImpl {
-    fn last_cache_provider(&self) -> Arc<LastCacheProvider> {
-        Arc::clone(&self.last_cache)
-    }
+        let table_def = db_schema.table_definition(table_name).ok_or_else(|| {
+            DataFusionError::Execution(format!(
+                "table {} not found in db {}",
+                table_name, database_name
+            ))
+        })?;
+        let table_id = table_def.table_id;
+        // files persisted before a column was renamed have it under its old name, so they are
+        // read with a schema that has the old names too, which queries map to the new names:
+        let parquet_schema = table_def.parquet_schema();
 
-    /// Create a new last-N-value cache in the specified database and table, along with the given
-    /// parameters.
-    ///
-    /// Returns the name of the newly created cache, or `None` if a cache was not created, but the
-    /// provided parameters match those of an existing cache.
-    #[allow(clippy::too_many_arguments)]
-    async fn create_last_cache(
-        &self,
-        db_id: DbId,
-        table_id: TableId,
-        cache_name: Option<&str>,
-        count: Option<usize>,
-        ttl: Option<Duration>,
-        key_columns: Option<Vec<(ColumnId, Arc<str>)>>,
-        value_columns: Option<Vec<(ColumnId, Arc<str>)>>,
-    ) -> Result<Option<LastCacheDefinition>, Error> {
-        let cache_name = cache_name.map(Into::into);
-        let catalog = self.catalog();
-        let db_schema = catalog
-            .db_schema_by_id(&db_id)
-            .ok_or(Error::DbDoesNotExist)?;
-        let table_def = db_schema
-            .table_definition_by_id(&table_id)
-            .ok_or(Error::TableDoesNotExist)?;
+        let retention_cutoff_ns =
+            db_schema.retention_cutoff_ns(self.time_provider.now().timestamp_nanos());
 
-        if let Some(info) = self.last_cache.create_cache(CreateCacheArguments {
-            db_id,
-            table_def,
-            cache_name,
-            count,
-            ttl,
-            key_columns,
-            value_columns,
-        })? {
-            self.catalog.add_last_cache(db_id, table_id, info.clone());
-            let add_cache_catalog_batch = WalOp::Catalog(CatalogBatch {
-                time_ns: self.time_provider.now().timestamp_nanos(),
-                database_id: db_schema.id,
-                database_name: Arc::clone(&db_schema.name),
-                ops: vec![CreateLastCache(info.clone())],
+        let mut chunks = self.buffer.get_table_chunks(
+            Arc::clone(&db_schema),
+            table_name,
+            filters,
+            retention_cutoff_ns,
+            projection,
+            ctx,
+        )?;
+
+        // files that only have data outside of the time range that the query is filtered to are
+        // not planned at all:
+        let time_range = TimeRange::from_filters(filters);
+        let mut parquet_files =
+            self.persisted_files
+                .get_files_in_time_range(db_schema.id, table_id, time_range);
+        // chunks are deduplicated on the series key and time by the query, keeping the row from
+        // the chunk with the highest order. The files are ordered by when they were persisted,
+        // and the buffer chunks come after all of them, so that the last write of a row wins:
+        parquet_files.sort_by_key(|file| file.id);
+        // the files of the other writers are found by the names of the database and table, as
+        // their ids differ from host to host, and ordered after those of this host, as rows are
+        // not expected to be written to more than one writer:
+        parquet_files.extend(self.writer_files.get_files_in_time_range(
+            database_name,
+            table_name,
+            time_range,
+        ));
+
+        // only the columns that the query needs are read from the files:
+        let parquet_projection = projection
+            .map(|projection| parquet_projection(&table_def, &parquet_schema, projection));
+
+        let mut chunk_order = chunks.len() as i64;
+
+        for parquet_file in parquet_files {
+            // skip files that only contain data outside of the retention period, they may not
+            // have been removed by the retention enforcement task yet
+            if retention_cutoff_ns.is_some_and(|cutoff| parquet_file.max_time < cutoff) {
+                continue;
+            }
+
+            let mut parquet_chunk = parquet_chunk_from_file(
+                &parquet_file,
+                &parquet_schema,
+                parquet_projection.as_deref(),
+                self.persister.object_store_url().clone(),
+                self.persister.object_store(),
+                chunk_order,
+            );
+            // files that are being queried should not be pruned from the parquet cache:
+            parquet_chunk.cache_pin = self.parquet_cache.as_ref().map(|cache| {
+                cache.pin(vec![parquet_chunk
+                    .parquet_exec
+                    .object_meta
+                    .location
+                    .clone()])
             });
-            self.wal.write_ops(vec![add_cache_catalog_batch]).await?;
 
-            Ok(Some(info))
-        } else {
-            Ok(None)
+            chunk_order += 1;
+
+            chunks.push(Arc::new(parquet_chunk));
         }
+
+        Ok(chunks)
     }
 
-    async fn delete_last_cache(
+    /// Set the retention period for a database, or clear it by passing `None`. The change is
+    /// written to the WAL so that it is durable and replayed on restart.
+    pub async fn set_retention_period(
         &self,
-        db_id: DbId,
-        tbl_id: TableId,
-        cache_name: &str,
-    ) -> crate::Result<(), self::Error> {
-        let catalog = self.catalog();
-        let db_schema = catalog.db_schema_by_id(&db_id).expect("db should exist");
-        self.last_cache.delete_cache(db_id, tbl_id, cache_name)?;
-        catalog.delete_last_cache(db_id, tbl_id, cache_name);
+        db_name: &str,
+        retention_period: Option<Duration>,
+    ) -> Result<()> {
+        self.apply_database_op(db_name, |database_id, database_name| {
+            CatalogOp::SetRetentionPeriod(RetentionPeriodDefinition {
+                database_id,
+                database_name,
+                retention_period,
+            })
+        })
+        .await
+    }
 
-        // NOTE: if this fails then the cache will be gone from the running server, but will be
-        // resurrected on server restart.
-        self.wal
-            .write_ops(vec![WalOp::Catalog(CatalogBatch {
-                time_ns: self.time_provider.now().timestamp_nanos(),
+    /// Set the limits on how far into the future or past, relative to the time of the write, the
+    /// timestamps of writes to a database may be; lines outside of these limits are rejected.
+    /// Passing `None` removes a limit. The change is written to the WAL so that it is durable and
+    /// replayed on restart.
+    pub async fn set_write_time_limits(
+        &self,
+        db_name: &str,
+        future_write_limit: Option<Duration>,
+        past_write_limit: Option<Duration>,
+    ) -> Result<()> {
+        self.apply_database_op(db_name, |database_id, database_name| {
+            CatalogOp::SetWriteTimeLimits(WriteTimeLimitsDefinition {
+                database_id,
+                database_name,
+                future_write_limit,
+                past_write_limit,
+            })
+        })
+        .await
+    }
+
+    /// Set the limits on the number of columns, and on the names of columns, of the tables in a
+    /// database. The limits only apply to columns added after they are set; existing columns are
+    /// left as they are.
+    pub async fn set_schema_limits(
+        &self,
+        db_name: &str,
+        schema_limits: SchemaLimits,
+    ) -> Result<()> {
+        self.apply_database_op(db_name, |database_id, database_name| {
+            CatalogOp::SetSchemaLimits(SchemaLimitsDefinition {
+                database_id,
+                database_name,
+                schema_limits,
+            })
+        })
+        .await
+    }
+
+    /// Set the limits on the number of tables in a database, and on the number of series in each
+    /// of its tables. The limits only apply to tables and series added after they are set.
+    pub async fn set_cardinality_limits(
+        &self,
+        db_name: &str,
+        cardinality_limits: CardinalityLimits,
+    ) -> Result<()> {
+        self.apply_database_op(db_name, |database_id, database_name| {
+            CatalogOp::SetCardinalityLimits(CardinalityLimitsDefinition {
+                database_id,
+                database_name,
+                cardinality_limits,
+            })
+        })
+        .await
+    }
+
+    /// Set whether writes to a database may add tables and columns to it. Changing the mode does
+    /// not affect the existing schema of the database.
+    pub async fn set_schema_mode(&self, db_name: &str, schema_mode: SchemaMode) -> Result<()> {
+        self.apply_database_op(db_name, |database_id, database_name| {
+            CatalogOp::SetSchemaMode(SchemaModeDefinition {
+                database_id,
+                database_name,
+                schema_mode,
+            })
+        })
+        .await
+    }
+
+    /// Set how v1 writes to a database of field values whose type does not match that of their
+    /// existing column are handled, e.g., to store integer values written to a float column
+    pub async fn set_field_coercion(
+        &self,
+        db_name: &str,
+        field_coercion: FieldCoercion,
+    ) -> Result<()> {
+        self.apply_database_op(db_name, |database_id, database_name| {
+            CatalogOp::SetFieldCoercion(FieldCoercionDefinition {
+                database_id,
+                database_name,
+                field_coercion,
+            })
+        })
+        .await
+    }
+
+    /// Set how line protocol written to a database is interpreted, e.g., to accept the field
+    /// values that InfluxDB 1.x accepted
+    pub async fn set_compatibility_mode(
+        &self,
+        db_name: &str,
+        compatibility_mode: CompatibilityMode,
+    ) -> Result<()> {
+        self.apply_database_op(db_name, |database_id, database_name| {
+            CatalogOp::SetCompatibilityMode(CompatibilityModeDefinition {
+                database_id,
+                database_name,
+                compatibility_mode,
+            })
+        })
+        .await
+    }
+
+    /// Set how v3 writes to a database whose series key does not match that of the table they
+    /// are written to are handled, e.g., to fill in the tags that a line leaves out
+    pub async fn set_series_key_policy(
+        &self,
+        db_name: &str,
+        series_key_policy: SeriesKeyPolicy,
+    ) -> Result<()> {
+        self.apply_database_op(db_name, |database_id, database_name| {
+            CatalogOp::SetSeriesKeyPolicy(SeriesKeyPolicyDefinition {
+                database_id,
+                database_name,
+                series_key_policy,
+            })
+        })
+        .await
+    }
+
+    /// Set how the names of the tables and columns that are written to a database are normalized,
+    /// e.g., so that `CPU` and `cpu` are the same table. Existing tables and columns are not
+    /// renamed, but can still be found by their exact names.
+    pub async fn set_identifier_policy(
+        &self,
+        db_name: &str,
+        identifier_policy: IdentifierPolicy,
+    ) -> Result<()> {
+        self.apply_database_op(db_name, |database_id, database_name| {
+            CatalogOp::SetIdentifierPolicy(IdentifierPolicyDefinition {
+                database_id,
+                database_name,
+                identifier_policy,
+            })
+        })
+        .await
+    }
+
+    /// Set the limit on the number of lines and bytes of line protocol that can be written to a
+    /// database per second; writes over the limit fail with [`Error::RateLimited`]. The change is
+    /// written to the WAL so that it is durable and replayed on restart.
+    pub async fn set_write_rate_limit(
+        &self,
+        db_name: &str,
+        write_rate_limit: WriteRateLimit,
+    ) -> Result<()> {
+        self.apply_database_op(db_name, |database_id, database_name| {
+            CatalogOp::SetWriteRateLimit(WriteRateLimitDefinition {
+                database_id,
+                database_name,
+                write_rate_limit,
+            })
+        })
+        .await
+    }
+
+    /// Set the duration of the chunks that writes to a database are buffered in, and persisted as
+    /// parquet files, or use the gen1 duration of the server by passing `None`. Data that is
+    /// already buffered keeps the duration it was buffered with. The change is written to the WAL
+    /// so that it is durable and replayed on restart.
+    ///
+    /// Snapshots are still taken on the gen1 duration of the server, so a chunk that is longer
+    /// than it may be persisted across more than one parquet file.
+    pub async fn set_gen1_duration(
+        &self,
+        db_name: &str,
+        gen1_duration: Option<Gen1Duration>,
+    ) -> Result<()> {
+        self.apply_database_op(db_name, |database_id, database_name| {
+            CatalogOp::SetGen1Duration(Gen1DurationDefinition {
+                database_id,
+                database_name,
+                gen1_duration,
+            })
+        })
+        .await
+    }
+
+    /// Create a table with an explicit schema, so that its columns and their types are not
+    /// inferred from the first write to it. The database is created if it does not exist. A
+    /// `time` column is always added to the table.
+    ///
+    /// If `series_key` is set, the table uses the v3 data model, with its tags, in the order
+    /// given, as its series key, and is written to with [`Bufferer::write_lp_v3`]. Otherwise it
+    /// uses the v1 data model.
+    ///
+    /// Combined with [`SchemaMode::Strict`], this ensures that the schema of a table is exactly
+    /// the one that was declared.
+    pub async fn create_table(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        tags: &[&str],
+        fields: &[(&str, FieldDataType)],
+        series_key: bool,
+    ) -> Result<()> {
+        if let Some((name, data_type)) = fields.iter().find(|(_, data_type)| {
+            matches!(
+                data_type,
+                FieldDataType::Tag | FieldDataType::Key | FieldDataType::Timestamp
+            )
+        }) {
+            return Err(Error::InvalidTableDefinition(format!(
+                "field {name} cannot have type {data_type:?}"
+            )));
+        }
+        let mut names = HashSet::with_capacity(tags.len() + fields.len());
+        for name in tags
+            .iter()
+            .chain(fields.iter().map(|(name, _)| name))
+            .copied()
+        {
+            if name.is_empty() || name == TIME_COLUMN_NAME {
+                return Err(Error::InvalidTableDefinition(format!(
+                    "invalid column name '{name}'"
+                )));
+            }
+            if !names.insert(name) {
+                return Err(Error::InvalidTableDefinition(format!(
+                    "column {name} is defined more than once"
+                )));
+            }
+        }
+
+        let db_schema = self.catalog.db_schema(db_name);
+        if db_schema
+            .as_ref()
+            .is_some_and(|db_schema| db_schema.table_definition(table_name).is_some())
+        {
+            return Err(Error::TableAlreadyExists {
+                db_name: db_name.to_string(),
+                table_name: table_name.to_string(),
+            });
+        }
+        let (db_id, db_name, create_db) = match db_schema {
+            Some(db_schema) => (db_schema.id, Arc::clone(&db_schema.name), false),
+            None => {
+                let db_name = NamespaceName::new(db_name.to_string())?;
+                (DbId::new(), Arc::from(db_name.as_str()), true)
+            }
+        };
+
+        let mut field_definitions = Vec::with_capacity(tags.len() + fields.len() + 1);
+        let mut key = Vec::with_capacity(tags.len());
+        for tag in tags {
+            let id = ColumnId::new();
+            key.push(id);
+            field_definitions.push(FieldDefinition::new(id, *tag, FieldDataType::Tag));
+        }
+        for (name, data_type) in fields {
+            field_definitions.push(FieldDefinition::new(ColumnId::new(), *name, *data_type));
+        }
+        field_definitions.push(FieldDefinition::new(
+            ColumnId::new(),
+            TIME_COLUMN_NAME,
+            FieldDataType::Timestamp,
+        ));
+        let table_definition = influxdb3_wal::TableDefinition {
+            database_id: db_id,
+            database_name: Arc::clone(&db_name),
+            table_name: table_name.into(),
+            table_id: TableId::new(),
+            field_definitions,
+            key: series_key.then_some(key),
+        };
+        // check that the table is within the column limit before it is applied to the catalog:
+        TableDefinition::new(
+            table_definition.table_id,
+            Arc::clone(&table_definition.table_name),
+            table_definition
+                .field_definitions
+                .iter()
+                .map(|def| (def.id, Arc::clone(&def.name), def.data_type.into()))
+                .collect(),
+            table_definition.key.clone(),
+        )?;
+
+        let mut ops = Vec::with_capacity(2);
+        if create_db {
+            ops.push(CatalogOp::CreateDatabase(DatabaseDefinition {
                 database_id: db_id,
+                database_name: Arc::clone(&db_name),
+            }));
+        }
+        ops.push(CatalogOp::CreateTable(table_definition));
+        self.apply_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_id,
+            database_name: db_name,
+            ops,
+        })
+        .await
+    }
+
+    /// Rename a database. The database keeps its id, so the data that is buffered, or has already
+    /// been persisted, remains part of it under the new name. Fails if the new name is in use.
+    pub async fn rename_database(&self, db_name: &str, new_db_name: &str) -> Result<()> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        let new_db_name = NamespaceName::new(new_db_name.to_string())?;
+        self.apply_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::RenameDatabase(RenameDatabaseDefinition {
+                database_id: db_schema.id,
                 database_name: Arc::clone(&db_schema.name),
-                ops: vec![CatalogOp::DeleteLastCache(LastCacheDelete {
-                    table_id: tbl_id,
-                    table_name: db_schema.table_id_to_name(&tbl_id).expect("table exists"),
-                    name: cache_name.into(),
-                })],
-            })])
-            .await?;
+                new_database_name: new_db_name.as_str().into(),
+            })],
+        })
+        .await
+    }
+
+    /// Rename a table in a database. The table keeps its id, so the data that is buffered, or has
+    /// already been persisted, remains part of it under the new name, as do its last caches.
+    /// Fails if the database already has a table with the new name.
+    pub async fn rename_table(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        new_table_name: &str,
+    ) -> Result<()> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        let table_def = db_schema
+            .table_definition(table_name)
+            .ok_or(Error::TableDoesNotExist)?;
+        self.apply_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::RenameTable(RenameTableDefinition {
+                database_id: db_schema.id,
+                database_name: Arc::clone(&db_schema.name),
+                table_id: table_def.table_id,
+                table_name: Arc::clone(&table_def.table_name),
+                new_table_name: new_table_name.into(),
+            })],
+        })
+        .await
+    }
 
+    /// Mark a table as deleted, which hides it from queries and writes, but keeps its data, so
+    /// that it can be undeleted with [`WriteBufferImpl::undelete_table`] until it is hard
+    /// deleted by [`WriteBufferImpl::hard_delete_expired_tables`]. Writes to the name of the table
+    /// create a new table in its place.
+    pub async fn delete_table(&self, db_name: &str, table_name: &str) -> Result<()> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        let table_def = db_schema
+            .table_definition(table_name)
+            .ok_or(Error::TableDoesNotExist)?;
+        self.apply_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::MarkTableDeleted(DeleteTableDefinition {
+                database_id: db_schema.id,
+                database_name: Arc::clone(&db_schema.name),
+                table_id: table_def.table_id,
+                table_name: Arc::clone(&table_def.table_name),
+            })],
+        })
+        .await?;
+        // the files are kept in case the table is undeleted, but they are not queried anymore:
+        self.invalidate_parquet_cache(
+            &self
+                .persisted_files
+                .get_files(db_schema.id, table_def.table_id),
+        );
         Ok(())
     }
-}
 
-impl WriteBuffer for WriteBufferImpl {}
+    /// Restore the table with the given name that was most recently deleted, and has not been
+    /// hard deleted yet. Fails if a table with the same name has been created since.
+    pub async fn undelete_table(&self, db_name: &str, table_name: &str) -> Result<()> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        let table_def = db_schema
+            .deleted_tables()
+            .filter(|table_def| table_def.table_name.as_ref() == table_name)
+            .max_by_key(|table_def| table_def.deleted_at_ns)
+            .ok_or(Error::TableDoesNotExist)?;
+        self.apply_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::UndeleteTable(DeleteTableDefinition {
+                database_id: db_schema.id,
+                database_name: Arc::clone(&db_schema.name),
+                table_id: table_def.table_id,
+                table_name: Arc::clone(&table_def.table_name),
+            })],
+        })
+        .await
+    }
 
-#[cfg(test)]
-#[allow(clippy::await_holding_lock)]
-mod tests {
-    use super::*;
-    use crate::parquet_cache::test_cached_obj_store_and_oracle;
-    use crate::paths::{CatalogFilePath, SnapshotInfoFilePath};
-    use crate::persister::Persister;
-    use crate::PersistedSnapshot;
-    use arrow::record_batch::RecordBatch;
-    use arrow_util::{assert_batches_eq, assert_batches_sorted_eq};
-    use bytes::Bytes;
-    use datafusion_util::config::register_iox_object_store;
-    use futures_util::StreamExt;
-    use influxdb3_catalog::catalog::CatalogSequenceNumber;
-    use influxdb3_id::{DbId, ParquetFileId};
-    use influxdb3_test_helpers::object_store::RequestCountedObjectStore;
-    use influxdb3_wal::{Gen1Duration, SnapshotSequenceNumber, WalFileSequenceNumber};
-    use iox_query::exec::IOxSessionContext;
-    use iox_time::{MockProvider, Time};
-    use object_store::local::LocalFileSystem;
-    use object_store::memory::InMemory;
-    use object_store::{ObjectStore, PutPayload};
+    /// Remove the tables that were deleted longer than `grace_period` ago from the catalog, along
+    /// with their buffered data, last caches, and persisted files, which are deleted from object
+    /// storage, after which they can no longer be undeleted. Returns the number of tables that
+    /// were removed.
+    pub async fn hard_delete_expired_tables(&self, grace_period: Duration) -> Result<usize> {
+        let now_ns = self.time_provider.now().timestamp_nanos();
+        let cutoff_ns =
+            now_ns.saturating_sub(grace_period.as_nanos().try_into().unwrap_or(i64::MAX));
+        let mut removed = 0;
+        for db_schema in self.catalog.list_db_schema() {
+            for table_def in db_schema.deleted_tables() {
+                if table_def
+                    .deleted_at_ns
+                    .is_some_and(|deleted_at| deleted_at > cutoff_ns)
+                {
+                    continue;
+                }
+                self.apply_catalog_batch(CatalogBatch {
+                    time_ns: now_ns,
+                    database_id: db_schema.id,
+                    database_name: Arc::clone(&db_schema.name),
+                    ops: vec![CatalogOp::HardDeleteTable(DeleteTableDefinition {
+                        database_id: db_schema.id,
+                        database_name: Arc::clone(&db_schema.name),
+                        table_id: table_def.table_id,
+                        table_name: Arc::clone(&table_def.table_name),
+                    })],
+                })
+                .await?;
+                let files = self
+                    .persisted_files
+                    .remove_table_files(db_schema.id, table_def.table_id);
+                self.invalidate_parquet_cache(&files);
+                // the table has not been queried since it was deleted, so its files are no
+                // longer read:
+                let object_store = self.persister.object_store();
+                for file in &files {
+                    match object_store
+                        .delete(&ObjPath::from(file.path.as_str()))
+                        .await
+                    {
+                        Ok(()) | Err(object_store::Error::NotFound { .. }) => (),
+                        // the file is no longer referenced, so garbage collection deletes it later:
+                        Err(e) => warn!(%e, path = %file.path, "error deleting hard deleted file"),
+                    }
+                }
+                info!(
+                    db_name = %db_schema.name,
+                    table_name = %table_def.table_name,
+                    file_count = files.len(),
+                    "hard deleted table"
+                );
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
 
-    #[test]
-    fn parse_lp_into_buffer() {
-        let host_id = Arc::from("sample-host-id");
-        let instance_id = Arc::from("sample-instance-id");
-        let catalog = Arc::new(Catalog::new(host_id, instance_id));
-        let db_name = NamespaceName::new("foo").unwrap();
-        let lp = "cpu,region=west user=23.2 100\nfoo f1=1i";
-        WriteValidator::initialize(db_name, Arc::clone(&catalog), 0)
-            .unwrap()
-            .v1_parse_lines_and_update_schema(
-                lp,
+    /// Drop a field column from a table. Data that was already written to it is no longer part of
+    /// the table, and its name cannot be used for a new column, since files that were already
+    /// persisted still have a column by that name.
+    pub async fn drop_column(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        column_name: &str,
+    ) -> Result<()> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        let table_def = db_schema
+            .table_definition(table_name)
+            .ok_or(Error::TableDoesNotExist)?;
+        let column_id = table_def
+            .column_name_to_id(column_name)
+            .ok_or_else(|| Error::ColumnDoesNotExist(column_name.to_string()))?;
+        self.apply_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::DropColumn(DropColumnDefinition {
+                database_id: db_schema.id,
+                database_name: Arc::clone(&db_schema.name),
+                table_id: table_def.table_id,
+                table_name: Arc::clone(&table_def.table_name),
+                column_id,
+                column_name: column_name.into(),
+            })],
+        })
+        .await
+    }
+
+    /// Rename a field column of a table. Data that was already written to it is queried under
+    /// the new name, and the old name cannot be used for a new column.
+    pub async fn rename_column(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        column_name: &str,
+        new_column_name: &str,
+    ) -> Result<()> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        let table_def = db_schema
+            .table_definition(table_name)
+            .ok_or(Error::TableDoesNotExist)?;
+        let column_id = table_def
+            .column_name_to_id(column_name)
+            .ok_or_else(|| Error::ColumnDoesNotExist(column_name.to_string()))?;
+        self.apply_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::RenameColumn(RenameColumnDefinition {
+                database_id: db_schema.id,
+                database_name: Arc::clone(&db_schema.name),
+                table_id: table_def.table_id,
+                table_name: Arc::clone(&table_def.table_name),
+                column_id,
+                column_name: column_name.into(),
+                new_column_name: new_column_name.into(),
+            })],
+        })
+        .await
+    }
+
+    /// Set the value that a field column of a table has in rows that are written without it,
+    /// instead of null, or remove it by passing `None`. Rows that were already written are not
+    /// changed.
+    pub async fn set_column_default(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        column_name: &str,
+        default: Option<FieldData>,
+    ) -> Result<()> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        let table_def = db_schema
+            .table_definition(table_name)
+            .ok_or(Error::TableDoesNotExist)?;
+        let column_id = table_def
+            .column_name_to_id(column_name)
+            .ok_or_else(|| Error::ColumnDoesNotExist(column_name.to_string()))?;
+        self.apply_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::SetColumnDefault(ColumnDefaultDefinition {
+                database_id: db_schema.id,
+                database_name: Arc::clone(&db_schema.name),
+                table_id: table_def.table_id,
+                table_name: Arc::clone(&table_def.table_name),
+                column_id,
+                column_name: column_name.into(),
+                default,
+            })],
+        })
+        .await
+    }
+
+    /// Replace the metadata of a database, i.e., the key/value pairs that describe it, such as its
+    /// owner or team
+    pub async fn set_database_metadata(
+        &self,
+        db_name: &str,
+        metadata: BTreeMap<String, String>,
+    ) -> Result<()> {
+        self.apply_database_op(db_name, |database_id, database_name| {
+            CatalogOp::SetDatabaseMetadata(DatabaseMetadataDefinition {
+                database_id,
+                database_name,
+                metadata,
+            })
+        })
+        .await
+    }
+
+    /// Replace the metadata of a table, as [`WriteBufferImpl::set_database_metadata`] does for a
+    /// database
+    pub async fn set_table_metadata(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        metadata: BTreeMap<String, String>,
+    ) -> Result<()> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        let table_def = db_schema
+            .table_definition(table_name)
+            .ok_or(Error::TableDoesNotExist)?;
+        self.apply_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::SetTableMetadata(TableMetadataDefinition {
+                database_id: db_schema.id,
+                database_name: Arc::clone(&db_schema.name),
+                table_id: table_def.table_id,
+                table_name: Arc::clone(&table_def.table_name),
+                metadata,
+            })],
+        })
+        .await
+    }
+
+    /// Apply the catalog op that `op` creates, from the id and name of the database, to the
+    /// database named `db_name`
+    async fn apply_database_op(
+        &self,
+        db_name: &str,
+        op: impl FnOnce(DbId, Arc<str>) -> CatalogOp,
+    ) -> Result<()> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        self.apply_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![op(db_schema.id, Arc::clone(&db_schema.name))],
+        })
+        .await
+    }
+
+    /// Apply a batch of catalog changes that is not part of a write, e.g., to create a table with
+    /// an explicit schema, or to change the settings of a database. The batch is validated against
+    /// the catalog, and either all of its changes are applied, or none are. It is then written to
+    /// the WAL, so that it is durable and replayed on restart, like the changes made by writes.
+    ///
+    /// Fails with [`Error::NoWriteInReadOnly`] if the buffer is not accepting writes, since the
+    /// batch may create databases and tables.
+    pub async fn apply_catalog_batch(&self, catalog_batch: CatalogBatch) -> Result<()> {
+        let _mode = self.check_writable().await?;
+        self.catalog.apply_catalog_batch(&catalog_batch)?;
+        self.write_catalog_batch(catalog_batch).await
+    }
+
+    /// Write a catalog batch for a change requested through the API to the WAL, and record it in
+    /// the audit log
+    async fn write_catalog_batch(&self, catalog_batch: CatalogBatch) -> Result<()> {
+        self.check_lease()?;
+        self.wal
+            .write_ops(vec![WalOp::Catalog(catalog_batch.clone())])
+            .await?;
+        self.audit_log
+            .record(&[catalog_batch], AuditSource::Api)
+            .await;
+        Ok(())
+    }
+
+    /// The current mode of the buffer
+    pub async fn mode(&self) -> BufferMode {
+        *self.mode.read().await
+    }
+
+    /// Change whether the buffer accepts writes. Writes that are in progress are allowed to
+    /// finish before the mode is changed.
+    ///
+    /// When switching to [`BufferMode::DrainAndPersist`], the WAL is flushed and all of the data
+    /// in the buffer is snapshot; this resolves once the snapshot has been persisted to object
+    /// storage.
+    pub async fn set_mode(&self, mode: BufferMode) {
+        *self.mode.write().await = mode;
+        info!(?mode, "write buffer mode changed");
+
+        if mode == BufferMode::DrainAndPersist {
+            self.wal.flush_and_snapshot_all().await;
+            info!("write buffer drained and persisted");
+        }
+    }
+
+    /// Shut down the buffer, by rejecting new writes, flushing the WAL, and persisting all of the
+    /// data in the buffer, so that it does not need to be replayed from the WAL when the server
+    /// is restarted. This resolves once the data has been persisted to object storage.
+    pub async fn shutdown(&self) {
+        info!("shutting down write buffer");
+        self.set_mode(BufferMode::DrainAndPersist).await;
+        self.wal.shutdown().await;
+        if let Some(lease) = &self.lease {
+            if let Err(e) = lease.release().await {
+                error!(%e, "error releasing the lease on the host identifier prefix");
+            }
+        }
+        info!("write buffer shut down");
+    }
+
+    /// Drop persisted files that only contain data older than the retention period of their
+    /// database, returning the number of files that were dropped. The files that were dropped by
+    /// the last run are deleted from object storage, rather than those dropped by this one, so
+    /// that the queries that were planned with them can finish.
+    ///
+    /// Expired files are not loaded again on restart, since the files of the snapshots that are
+    /// loaded are dropped by their retention periods. Those that were dropped, but not deleted,
+    /// before a restart are deleted by garbage collection.
+    pub async fn enforce_retention(&self) -> Result<usize> {
+        let mut expired_files = self.expired_files.lock().await;
+        self.delete_expired_files(&mut expired_files).await;
+
+        let now_ns = self.time_provider.now().timestamp_nanos();
+        let Some(oldest_cutoff_ns) = self
+            .catalog
+            .list_db_schema()
+            .iter()
+            .filter_map(|db_schema| db_schema.retention_cutoff_ns(now_ns))
+            .min()
+        else {
+            return Ok(0);
+        };
+        // the snapshots are only loaded as far back as they have data from before the oldest
+        // cutoff. The files of those older than that are dropped once they are loaded, e.g., by
+        // a query:
+        self.persisted_files
+            .load_older_snapshots(
+                &self.persister,
+                oldest_cutoff_ns,
+                N_SNAPSHOTS_TO_LOAD_ON_START,
+            )
+            .await?;
+        let files = self.remove_expired_files();
+        let n_files = files.len();
+        expired_files.extend(files);
+        Ok(n_files)
+    }
+
+    /// Delete the files that retention dropped on its last run. Files that cannot be deleted are
+    /// tried again on the next run.
+    async fn delete_expired_files(&self, expired_files: &mut Vec<ParquetFile>) {
+        if let Err(e) = self.persister.check_write_fence() {
+            warn!(%e, n_files = expired_files.len(), "not deleting expired files");
+            return;
+        }
+        let object_store = self.persister.object_store();
+        let mut remaining = vec![];
+        for file in expired_files.drain(..) {
+            match object_store
+                .delete(&ObjPath::from(file.path.as_str()))
+                .await
+            {
+                Ok(()) | Err(object_store::Error::NotFound { .. }) => (),
+                Err(e) => {
+                    warn!(%e, path = %file.path, "error deleting expired parquet file");
+                    remaining.push(file);
+                }
+            }
+        }
+        *expired_files = remaining;
+    }
+
+    /// Remove the persisted files that only contain data older than the retention period of
+    /// their database from the persisted files, returning the files that were removed
+    fn remove_expired_files(&self) -> Vec<ParquetFile> {
+        let now_ns = self.time_provider.now().timestamp_nanos();
+        let mut removed = vec![];
+        for db_schema in self.catalog.list_db_schema() {
+            let Some(cutoff_ns) = db_schema.retention_cutoff_ns(now_ns) else {
+                continue;
+            };
+            let files = self
+                .persisted_files
+                .remove_files_older_than(db_schema.id, cutoff_ns);
+            if !files.is_empty() {
+                info!(
+                    db_name = %db_schema.name,
+                    file_count = files.len(),
+                    "removed expired parquet files from persisted files"
+                );
+            }
+            removed.extend(files);
+        }
+        self.invalidate_parquet_cache(&removed);
+        removed
+    }
+
+    /// Compact the persisted files of each table, in windows of time, into one sorted and
+    /// deduplicated gen2 file per window, returning the number of gen2 files that were written.
+    /// The files that were replaced are deleted by the next compaction.
+    pub async fn compact(&self, config: CompactionConfig) -> Result<usize> {
+        Ok(self.compactor.compact(config).await?)
+    }
+
+    /// Write the index of the persisted files, which they are loaded from on startup, along with
+    /// the snapshots taken since, rather than from every snapshot. Returns the number of files in
+    /// the index, or `None` if nothing has been snapshot yet.
+    pub async fn persist_persisted_files_index(&self) -> Result<Option<usize>> {
+        // the index has all of the persisted files, so the snapshots that have not been loaded
+        // must be loaded first:
+        self.persisted_files
+            .load_older_snapshots(&self.persister, i64::MIN, N_SNAPSHOTS_TO_LOAD_ON_START)
+            .await?;
+        let Some(index) = self.persisted_files.index() else {
+            return Ok(None);
+        };
+        let file_count = index
+            .databases
+            .values()
+            .flat_map(|db| db.tables.values())
+            .map(|files| files.len())
+            .sum();
+        self.persister.persist_persisted_files_index(&index).await?;
+        info!(
+            snapshot_sequence_number = %index.snapshot.snapshot_sequence_number,
+            file_count,
+            "persisted the index of the persisted files"
+        );
+        Ok(Some(file_count))
+    }
+
+    /// Remove the given files from the parquet cache, if there is one, since they are no longer
+    /// queried
+    fn invalidate_parquet_cache(&self, files: &[ParquetFile]) {
+        let Some(parquet_cache) = &self.parquet_cache else {
+            return;
+        };
+        for file in files {
+            parquet_cache.invalidate(&ObjPath::from(file.path.as_str()));
+        }
+    }
+
+    /// Remove the WAL files that were snapshot, but left in the WAL, e.g., by a snapshot that
+    /// was interrupted, so that they are not replayed again. Returns the files that were found,
+    /// which are only logged if `dry_run` is set.
+    pub async fn collect_wal_garbage(&self, dry_run: bool) -> Result<Vec<WalFileSequenceNumber>> {
+        // the files of the last snapshot may still be being removed, which the snapshot after it
+        // waits for, so only the files of the snapshot before it are collected:
+        let snapshots = self.persister.load_snapshots(2).await?;
+        let Some(snapshot) = snapshots.get(1) else {
+            return Ok(vec![]);
+        };
+        Ok(self
+            .wal
+            .collect_garbage(snapshot.wal_file_sequence_number, dry_run)
+            .await?)
+    }
+
+    /// Remove the parquet files in object storage that are not referenced by the persisted files,
+    /// and were last modified longer than `min_age` ago, which should be longer than a snapshot,
+    /// or compaction, takes to persist its files. Returns the files that were found, which are
+    /// only logged if `dry_run` is set.
+    pub async fn collect_parquet_garbage(
+        &self,
+        min_age: Duration,
+        dry_run: bool,
+    ) -> Result<Vec<ObjPath>> {
+        let Some(cutoff) = self.time_provider.now().checked_sub(min_age) else {
+            return Ok(vec![]);
+        };
+        // the files of the snapshots that have not been loaded are not in the persisted files, so
+        // they must all be loaded first, or nothing can be collected:
+        self.persisted_files
+            .load_older_snapshots(&self.persister, i64::MIN, N_SNAPSHOTS_TO_LOAD_ON_START)
+            .await?;
+        if !self.persisted_files.all_snapshots_loaded() {
+            return Ok(vec![]);
+        }
+        // the files replaced by the last compaction, and those dropped by the last run of
+        // retention, may still be being queried, and are deleted by the next one:
+        let mut pending = self.compactor.superseded_paths().await;
+        pending.extend(
+            self.expired_files
+                .lock()
+                .await
+                .iter()
+                .map(|file| file.path.clone()),
+        );
+        Ok(self
+            .parquet_gc
+            .collect(cutoff.date_time(), &pending, dry_run)
+            .await?)
+    }
+
+    /// Create a downsampling task in a database, or replace the task with the same name, which
+    /// aggregates the data of the source table into windows of `interval` and writes the results
+    /// to the target table, on each run of [`WriteBufferImpl::run_downsampling_tasks`]
+    pub async fn create_downsampling_task(
+        &self,
+        db_name: &str,
+        task_name: &str,
+        source_table_name: &str,
+        target_table_name: &str,
+        interval: Duration,
+        aggregate: DownsamplingAggregate,
+    ) -> Result<()> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        let table_def = db_schema
+            .table_definition(source_table_name)
+            .ok_or(Error::TableDoesNotExist)?;
+        if interval.is_zero() {
+            return Err(Error::InvalidDownsamplingTask(
+                "interval must be greater than zero".to_string(),
+            ));
+        }
+        // the rows written by the task would be aggregated again by it:
+        if table_def.table_name.as_ref() == target_table_name {
+            return Err(Error::InvalidDownsamplingTask(
+                "target table must not be the source table".to_string(),
+            ));
+        }
+        self.apply_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::CreateDownsamplingTask(
+                DownsamplingTaskDefinition {
+                    database_id: db_schema.id,
+                    database_name: Arc::clone(&db_schema.name),
+                    source_table_name: Arc::clone(&table_def.table_name),
+                    task: DownsamplingTask {
+                        name: task_name.into(),
+                        source_table_id: table_def.table_id,
+                        target_table_name: target_table_name.into(),
+                        interval,
+                        aggregate,
+                    },
+                },
+            )],
+        })
+        .await
+    }
+
+    /// Delete a downsampling task from a database. The data that it wrote is kept.
+    pub async fn delete_downsampling_task(&self, db_name: &str, task_name: &str) -> Result<()> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        if !db_schema.downsampling_tasks.contains_key(task_name) {
+            return Err(Error::DownsamplingTaskDoesNotExist(task_name.to_string()));
+        }
+        self.apply_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::DeleteDownsamplingTask(
+                DeleteDownsamplingTaskDefinition {
+                    database_id: db_schema.id,
+                    database_name: Arc::clone(&db_schema.name),
+                    task_name: task_name.into(),
+                },
+            )],
+        })
+        .await
+    }
+
+    /// Run the downsampling tasks of each database over the data persisted since they last ran,
+    /// returning the number of rows that they wrote
+    pub async fn run_downsampling_tasks(&self) -> Result<usize> {
+        Ok(self.downsampler.run(self).await?)
+    }
+
+    /// Export the rows of a table with times in `time_range`, inclusive, from both the buffer and
+    /// the persisted files, to `target` in `format`, returning the number of rows that were
+    /// exported. The rows are deduplicated, as they are when the table is queried, and sorted by
+    /// their series key and time.
+    pub async fn export_table(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        time_range: TimeRange,
+        format: ExportFormat,
+        target: &ExportTarget,
+    ) -> Result<u64> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        let table_def = db_schema
+            .table_definition(table_name)
+            .ok_or(Error::TableDoesNotExist)?;
+
+        let time = |ns| lit(ScalarValue::TimestampNanosecond(Some(ns), None));
+        let mut filters = vec![];
+        if time_range.min != i64::MIN {
+            filters.push(col(TIME_COLUMN_NAME).gt_eq(time(time_range.min)));
+        }
+        if time_range.max != i64::MAX {
+            filters.push(col(TIME_COLUMN_NAME).lt_eq(time(time_range.max)));
+        }
+        self.load_older_snapshots(&filters).await?;
+
+        let ctx = self.buffer.executor.new_context();
+        let chunks = self
+            .get_table_chunks(db_name, table_name, &filters, None, &ctx.inner().state())
+            .map_err(export::Error::from)?;
+        let mut builder =
+            ProviderBuilder::new(Arc::clone(&table_def.table_name), table_def.schema.clone());
+        for chunk in chunks {
+            builder = builder.add_chunk(chunk);
+        }
+        let provider = builder
+            .build()
+            .map_err(|e| export::Error::DataFusion(DataFusionError::External(Box::new(e))))?;
+
+        let sort = table_def
+            .schema
+            .primary_key()
+            .into_iter()
+            .map(|column| col(column).sort(true, false))
+            .collect::<Vec<_>>();
+        let stream = async {
+            let mut df = ctx.inner().read_table(Arc::new(provider))?;
+            for filter in filters {
+                df = df.filter(filter)?;
+            }
+            df.sort(sort)?.execute_stream().await
+        }
+        .await
+        .map_err(export::Error::from)?;
+
+        let writer_properties = self
+            .persister
+            .config()
+            .writer_properties(&BloomFilters::default())?;
+        let row_count = export::write_export(stream, format, writer_properties, target).await?;
+        info!(
+            db_name,
+            table_name,
+            %format,
+            path = %target.path,
+            row_count,
+            "exported table"
+        );
+        Ok(row_count)
+    }
+
+    /// Import the parquet files at `paths` in `source` into a table, returning the files that
+    /// they were persisted as. This is for backfilling historical data, which does not go through
+    /// the WAL, so it is much faster than writing it, and is queryable once this returns.
+    ///
+    /// Each file is checked against the schema of the table, which must exist, and persisted
+    /// again, with its statistics and the indexes of the files that the server persists. The
+    /// files are then added to the persisted files in a snapshot of their own. Their rows are
+    /// newer than those already persisted, so they replace any rows with the same series key and
+    /// time. If any file cannot be imported, none of them are, and the files that were persisted
+    /// are removed by the garbage collection of parquet files.
+    pub async fn import_parquet_files(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        source: Arc<dyn ObjectStore>,
+        paths: &[ObjPath],
+    ) -> Result<Vec<ParquetFile>> {
+        let db_schema = self
+            .catalog
+            .db_schema(db_name)
+            .ok_or(Error::DbDoesNotExist)?;
+        let table_def = db_schema
+            .table_definition(table_name)
+            .ok_or(Error::TableDoesNotExist)?;
+        let gen1_duration = self.gen1_duration();
+
+        // the ids are assigned in the order of the paths, so that later files replace the rows of
+        // earlier ones:
+        let imported = futures_util::stream::iter(paths)
+            .map(|path| {
+                let id = ParquetFileId::new();
+                let db_schema = &db_schema;
+                let table_def = &table_def;
+                let source = Arc::clone(&source);
+                async move {
+                    import::import_file(
+                        &self.persister,
+                        db_schema,
+                        table_def,
+                        gen1_duration,
+                        source.as_ref(),
+                        path,
+                        id,
+                    )
+                    .await
+                }
+            })
+            .buffered(self.persister.persist_concurrency())
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .filter_map(|file| file.transpose())
+            .collect::<Result<Vec<_>, import::Error>>()?;
+        if imported.is_empty() {
+            return Ok(imported);
+        }
+
+        // the snapshot takes a sequence number from the WAL, so that it is ordered with the
+        // snapshots of the WAL, along with the last WAL file that was written, so that the WAL
+        // files are numbered after it when the WAL is loaded from it on restart:
+        let (snapshot_sequence_number, wal_file_sequence_number) =
+            self.wal.reserve_snapshot_sequence_number().await;
+        let mut snapshot = PersistedSnapshot::new(
+            self.persister.host_identifier_prefix().to_string(),
+            snapshot_sequence_number,
+            wal_file_sequence_number,
+            self.catalog.sequence_number(),
+        );
+        for file in &imported {
+            snapshot.add_parquet_file(db_schema.id, table_def.table_id, file.clone());
+        }
+        self.persister.persist_snapshot(&snapshot).await?;
+        self.event_listeners.snapshot_persisted(&snapshot);
+        info!(
+            db_name,
+            table_name,
+            snapshot_sequence_number = %snapshot.snapshot_sequence_number,
+            file_count = imported.len(),
+            row_count = snapshot.row_count,
+            "imported parquet files"
+        );
+        self.persisted_files.add_persisted_snapshot_files(snapshot);
+        Ok(imported)
+    }
+
+    /// The current config of the WAL
+    pub fn wal_config(&self) -> WalConfig {
+        *self.wal_config.read()
+    }
+
+    /// Change the flush interval, snapshot size, and gen1 duration of the WAL without a restart.
+    /// The other settings in `wal_config` are ignored, since they can only be changed with a
+    /// restart.
+    ///
+    /// The new settings are persisted, and are used instead of those that the server is started
+    /// with when it is restarted. Writes that are already buffered keep the gen1 duration that
+    /// they were buffered with.
+    pub async fn update_wal_config(&self, wal_config: WalConfig) -> Result<()> {
+        let runtime_config = wal_config.runtime_config();
+        runtime_config.validate()?;
+        self.persister.persist_wal_config(&runtime_config).await?;
+        self.wal.update_config(runtime_config).await?;
+        let mut wal_config = self.wal_config.write();
+        *wal_config = wal_config.with_runtime_config(runtime_config);
+        Ok(())
+    }
+
+    fn gen1_duration(&self) -> Gen1Duration {
+        self.wal_config.read().gen1_duration
+    }
+
+    /// Create a last cache, and if it is new, add it to the catalog and write it to the WAL
+    async fn create_last_cache_with_args(
+        &self,
+        args: CreateCacheArguments,
+    ) -> Result<Option<LastCacheDefinition>> {
+        let db_schema = self
+            .catalog
+            .db_schema_by_id(&args.db_id)
+            .ok_or(Error::DbDoesNotExist)?;
+        let Some(info) = self.last_cache.create_cache(args)? else {
+            return Ok(None);
+        };
+        self.catalog
+            .add_last_cache(db_schema.id, info.table_id, info.clone());
+        self.write_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CreateLastCache(info.clone())],
+        })
+        .await?;
+
+        Ok(Some(info))
+    }
+}
+
+/// Copy the catalog batches out of the ops, to record them in the audit log once they have been
+/// written to the WAL
+fn catalog_batches(ops: &[WalOp]) -> Vec<CatalogBatch> {
+    ops.iter()
+        .filter_map(|op| match op {
+            WalOp::Catalog(catalog_batch) => Some(catalog_batch.clone()),
+            WalOp::Write(_) => None,
+        })
+        .collect()
+}
+
+/// Spawn a background task that periodically drops data that is outside of the retention period
+/// of its database.
+pub fn background_retention_enforcement(
+    write_buffer: Arc<WriteBufferImpl>,
+    enforcement_interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    spawn_periodic(
+        enforcement_interval,
+        "enforcing retention periods",
+        move || {
+            let write_buffer = Arc::clone(&write_buffer);
+            async move { write_buffer.enforce_retention().await }
+        },
+    )
+}
+
+/// Spawn a background task that periodically hard deletes the tables that were deleted longer
+/// than `grace_period` ago.
+pub fn background_deleted_table_removal(
+    write_buffer: Arc<WriteBufferImpl>,
+    check_interval: Duration,
+    grace_period: Duration,
+) -> tokio::task::JoinHandle<()> {
+    spawn_periodic(check_interval, "hard deleting tables", move || {
+        let write_buffer = Arc::clone(&write_buffer);
+        async move { write_buffer.hard_delete_expired_tables(grace_period).await }
+    })
+}
+
+/// Spawn a background task that periodically compacts the persisted files into gen2 files.
+pub fn background_compaction(
+    write_buffer: Arc<WriteBufferImpl>,
+    compaction_interval: Duration,
+    config: CompactionConfig,
+) -> tokio::task::JoinHandle<()> {
+    spawn_periodic(
+        compaction_interval,
+        "compacting persisted files",
+        move || {
+            let write_buffer = Arc::clone(&write_buffer);
+            async move { write_buffer.compact(config).await }
+        },
+    )
+}
+
+/// Spawn a background task that periodically runs the downsampling tasks.
+pub fn background_downsampling(
+    write_buffer: Arc<WriteBufferImpl>,
+    downsampling_interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    spawn_periodic(
+        downsampling_interval,
+        "running downsampling tasks",
+        move || {
+            let write_buffer = Arc::clone(&write_buffer);
+            async move { write_buffer.run_downsampling_tasks().await }
+        },
+    )
+}
+
+/// Spawn a background task that periodically writes the index of the persisted files.
+pub fn background_persisted_files_index(
+    write_buffer: Arc<WriteBufferImpl>,
+    index_interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    spawn_periodic(
+        index_interval,
+        "persisting the index of the persisted files",
+        move || {
+            let write_buffer = Arc::clone(&write_buffer);
+            async move { write_buffer.persist_persisted_files_index().await }
+        },
+    )
+}
+
+/// Spawn a background task that periodically loads the files that the other writers have
+/// persisted since they were last loaded.
+pub fn background_other_writers_refresh(
+    write_buffer: Arc<WriteBufferImpl>,
+    refresh_interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    spawn_periodic(
+        refresh_interval,
+        "refreshing the persisted files of the other writers",
+        move || {
+            let write_buffer = Arc::clone(&write_buffer);
+            async move { write_buffer.refresh_other_writers().await }
+        },
+    )
+}
+
+/// Spawn a background task that periodically removes the WAL files that were snapshot, but left
+/// in the WAL. If `dry_run` is set, they are only logged.
+pub fn background_wal_garbage_collection(
+    write_buffer: Arc<WriteBufferImpl>,
+    collection_interval: Duration,
+    dry_run: bool,
+) -> tokio::task::JoinHandle<()> {
+    spawn_periodic(
+        collection_interval,
+        "collecting orphaned wal files",
+        move || {
+            let write_buffer = Arc::clone(&write_buffer);
+            async move { write_buffer.collect_wal_garbage(dry_run).await }
+        },
+    )
+}
+
+/// Spawn a background task that periodically removes the parquet files that are not referenced by
+/// the persisted files. If `dry_run` is set, they are only logged.
+pub fn background_parquet_garbage_collection(
+    write_buffer: Arc<WriteBufferImpl>,
+    collection_interval: Duration,
+    min_age: Duration,
+    dry_run: bool,
+) -> tokio::task::JoinHandle<()> {
+    spawn_periodic(
+        collection_interval,
+        "collecting unreferenced parquet files",
+        move || {
+            let write_buffer = Arc::clone(&write_buffer);
+            async move { write_buffer.collect_parquet_garbage(min_age, dry_run).await }
+        },
+    )
+}
+
+/// Spawn a background task that runs `f` every `period`, logging the errors that it returns as
+/// errors `doing` it, e.g., "compacting persisted files"
+fn spawn_periodic<F, Fut, T, E>(
+    period: Duration,
+    doing: &'static str,
+    mut f: F,
+) -> tokio::task::JoinHandle<()>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = std::result::Result<T, E>> + Send,
+    E: std::fmt::Display,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = f().await {
+                error!(%e, "error {doing}");
+            }
+        }
+    })
+}
+
+/// Register the `n_files` most recent persisted files, by the time of the data in them, to be
+/// fetched into the parquet cache, stopping at the first file that would not fit in the cache
+fn prefill_parquet_cache(
+    parquet_cache: &dyn ParquetCacheOracle,
+    persisted_files: &PersistedFiles,
+    n_files: usize,
+) {
+    if n_files == 0 {
+        return;
+    }
+    let capacity_bytes = parquet_cache.usage().capacity_bytes as u64;
+    let mut total_bytes = 0;
+    let mut n_registered = 0;
+    for file in persisted_files.most_recent_files(n_files) {
+        total_bytes += file.size_bytes;
+        if total_bytes > capacity_bytes {
+            break;
+        }
+        let (cache_request, _) = CacheRequest::create(ObjPath::from(file.path));
+        parquet_cache
+            .register(cache_request.with_file_info(file.size_bytes as usize, file.max_time));
+        n_registered += 1;
+    }
+    info!(
+        n_registered,
+        "prefilling the parquet cache with the most recent files"
+    );
+}
+
+/// The indexes of the columns in `parquet_schema` to read for a query with `projection`, which
+/// is of the columns of the table. The old names of renamed columns are included, as are the
+/// columns of the primary key, since chunks are deduplicated on them.
+fn parquet_projection(
+    table_def: &TableDefinition,
+    parquet_schema: &Schema,
+    projection: &[usize],
+) -> Vec<usize> {
+    let renamed_columns = table_def.renamed_columns().collect::<Vec<_>>();
+    let mut names = projection
+        .iter()
+        .map(|i| table_def.schema.field(*i).1.name().as_str())
+        .collect::<Vec<_>>();
+    let old_names = renamed_columns
+        .iter()
+        .filter(|(_, new_name)| names.contains(&new_name.as_ref()))
+        .map(|(old_name, _)| old_name.as_ref())
+        .collect::<Vec<_>>();
+    names.extend(old_names);
+    names.extend(parquet_schema.primary_key());
+
+    let mut indexes = names
+        .into_iter()
+        .filter_map(|name| parquet_schema.find_index_of(name))
+        .collect::<Vec<_>>();
+    indexes.sort_unstable();
+    indexes.dedup();
+    indexes
+}
+
+/// Create a chunk for a persisted file. If `projection` is set, the chunk only has those columns
+/// of `table_schema`.
+pub fn parquet_chunk_from_file(
+    parquet_file: &ParquetFile,
+    table_schema: &Schema,
+    projection: Option<&[usize]>,
+    object_store_url: ObjectStoreUrl,
+    object_store: Arc<dyn ObjectStore>,
+    chunk_order: i64,
+) -> ParquetChunk {
+    let schema = match projection {
+        Some(projection) => table_schema.select_by_indices(projection),
+        None => table_schema.clone(),
+    };
+    let partition_key = data_types::PartitionKey::from(parquet_file.chunk_time.to_string());
+    let partition_id = data_types::partition::TransitionPartitionId::new(
+        data_types::TableId::new(0),
+        &partition_key,
+    );
+
+    // the ranges of the tag columns let DataFusion prune the files that cannot match a filter on
+    // a tag without opening them:
+    let column_ranges: ColumnRanges = Arc::new(
+        parquet_file
+            .column_ranges
+            .iter()
+            .map(|(column, range)| {
+                (
+                    Arc::from(column.as_str()),
+                    ColumnRange {
+                        min_value: Arc::new(ScalarValue::from(range.min.as_str())),
+                        max_value: Arc::new(ScalarValue::from(range.max.as_str())),
+                    },
+                )
+            })
+            .collect(),
+    );
+    let chunk_stats = create_chunk_statistics(
+        Some(parquet_file.row_count as usize),
+        &schema,
+        Some(parquet_file.timestamp_min_max()),
+        &column_ranges,
+    );
+
+    // the rows of the file are sorted by its sort key, which lets DataFusion merge it with other
+    // chunks without sorting it again. Only the columns of the sort key up to the first that is
+    // not in the schema of the chunk can be used, since the rows are not sorted by those after it:
+    let sort_key = parquet_file
+        .sort_key
+        .iter()
+        .take_while(|column| schema.find_index_of(column).is_some())
+        .cloned()
+        .collect::<Vec<_>>();
+    let sort_key = (!sort_key.is_empty()).then(|| SortKey::from(sort_key));
+
+    let location = ObjPath::from(parquet_file.path.clone());
+
+    let parquet_exec = ParquetExecInput {
+        object_store_url,
+        object_meta: ObjectMeta {
+            location,
+            last_modified: Default::default(),
+            size: parquet_file.size_bytes as usize,
+            e_tag: None,
+            version: None,
+        },
+        object_store,
+    };
+
+    ParquetChunk {
+        schema,
+        stats: Arc::new(chunk_stats),
+        partition_id,
+        sort_key,
+        id: ChunkId::new(),
+        chunk_order: ChunkOrder::new(chunk_order),
+        parquet_exec,
+        cache_pin: None,
+    }
+}
+
+#[async_trait]
+impl Bufferer for WriteBufferImpl {
+    async fn write_lp(
+        &self,
+        database: NamespaceName<'static>,
+        lp: &str,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+    ) -> Result<BufferedWriteRequest> {
+        self.write_lp(database, lp, ingest_time, accept_partial, precision)
+            .await
+    }
+
+    async fn write_lp_with_durability(
+        &self,
+        database: NamespaceName<'static>,
+        lp: &str,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+        durability: Durability,
+    ) -> Result<BufferedWriteRequest> {
+        self.write_lp_with_durability(
+            database,
+            lp,
+            ingest_time,
+            accept_partial,
+            precision,
+            durability,
+        )
+        .await
+    }
+
+    async fn write_lp_multi(
+        &self,
+        writes: Vec<(NamespaceName<'static>, &str)>,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+    ) -> Result<Vec<BufferedWriteRequest>> {
+        self.write_lp_multi(writes, ingest_time, accept_partial, precision)
+            .await
+    }
+
+    async fn write_lp_v3(
+        &self,
+        database: NamespaceName<'static>,
+        lp: &str,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+    ) -> Result<BufferedWriteRequest> {
+        self.write_lp_v3(database, lp, ingest_time, accept_partial, precision)
+            .await
+    }
+
+    async fn write_lp_stream(
+        &self,
+        database: NamespaceName<'static>,
+        lp: Box<dyn AsyncBufRead + Send + Unpin>,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+        durability: Durability,
+    ) -> Result<BufferedWriteRequest> {
+        self.write_lp_stream(
+            database,
+            lp,
+            ingest_time,
+            accept_partial,
+            precision,
+            durability,
+        )
+        .await
+    }
+
+    async fn write_rows(
+        &self,
+        database: NamespaceName<'static>,
+        rows: Vec<WriteRow>,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+    ) -> Result<BufferedWriteRequest> {
+        self.write_rows(database, rows, ingest_time, accept_partial, precision)
+            .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn write_tabular(
+        &self,
+        database: NamespaceName<'static>,
+        format: TabularFormat,
+        body: &str,
+        mapping: &ColumnMapping,
+        ingest_time: Time,
+        accept_partial: bool,
+        precision: Precision,
+    ) -> Result<BufferedWriteRequest> {
+        self.write_tabular(
+            database,
+            format,
+            body,
+            mapping,
+            ingest_time,
+            accept_partial,
+            precision,
+        )
+        .await
+    }
+
+    async fn write_record_batch(
+        &self,
+        database: NamespaceName<'static>,
+        table_name: &str,
+        batch: RecordBatch,
+        ingest_time: Time,
+        accept_partial: bool,
+    ) -> Result<BufferedWriteRequest> {
+        self.write_record_batch(database, table_name, batch, ingest_time, accept_partial)
+            .await
+    }
+
+    async fn validate_lp(
+        &self,
+        database: NamespaceName<'static>,
+        lp: &str,
+        precision: Precision,
+    ) -> Result<WriteValidation> {
+        self.validate_lp(database, lp, precision).await
+    }
+
+    fn catalog(&self) -> Arc<Catalog> {
+        self.catalog()
+    }
+
+    fn parquet_files(&self, db_id: DbId, table_id: TableId) -> Vec<ParquetFile> {
+        self.buffer.persisted_parquet_files(db_id, table_id)
+    }
+
+    fn parquet_cache(&self) -> Option<Arc<dyn ParquetCacheOracle>> {
+        self.parquet_cache.clone()
+    }
+
+    fn watch_persisted_snapshots(&self) -> Receiver<Option<PersistedSnapshot>> {
+        self.buffer.persisted_snapshot_notify_rx()
+    }
+
+    async fn audit_log(&self) -> Result<Vec<AuditLogEntry>> {
+        Ok(self.audit_log.entries().await?)
+    }
+
+    async fn retained_wal_files(&self) -> Result<Option<RetainedWalFiles>> {
+        Ok(self.wal.retained_wal_files().await?)
+    }
+
+    async fn wal_files(&self) -> Result<Vec<WalFileSummary>> {
+        let inspector = WalInspector::new(
+            self.persister.object_store(),
+            self.persister.host_identifier_prefix(),
+            self.persister.key_provider(),
+        );
+        let mut summaries = Vec::new();
+        for path in inspector.list().await? {
+            match inspector.summarize(&path).await {
+                Ok(summary) => summaries.push(summary),
+                // the file was removed by a snapshot since it was listed:
+                Err(influxdb3_wal::Error::ObjectStoreError(object_store::Error::NotFound {
+                    ..
+                })) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(summaries)
+    }
+
+    async fn subscribe_wal(&self, from: Option<WalFileSequenceNumber>) -> Result<WalSubscription> {
+        Ok(self.wal.subscribe(from).await?)
+    }
+}
+
+#[async_trait::async_trait]
+impl ChunkContainer for WriteBufferImpl {
+    async fn load_persisted_files(&self, filters: &[Expr]) -> Result<(), DataFusionError> {
+        self.load_older_snapshots(filters)
+            .await
+            .map(|_| ())
+            .map_err(|e| DataFusionError::External(Box::new(e)))
+    }
+
+    fn get_table_chunks(
+        &self,
+        database_name: &str,
+        table_name: &str,
+        filters: &[Expr],
+        projection: Option<&Vec<usize>>,
+        ctx: &dyn Session,
+    ) -> crate::Result<Vec<Arc<dyn QueryChunk>>, DataFusionError> {
+        self.get_table_chunks(database_name, table_name, filters, projection, ctx)
+    }
+
+    fn retention_cutoff_ns(&self, database_name: &str) -> Option<i64> {
+        self.catalog
+            .db_schema(database_name)?
+            .retention_cutoff_ns(self.time_provider.now().timestamp_nanos())
+    }
+}
+
+#[async_trait::async_trait]
+impl LastCacheManager for WriteBufferImpl {
+    fn last_cache_provider(&self) -> Arc<LastCacheProvider> {
+        Arc::clone(&self.last_cache)
+    }
+
+    fn list_last_caches(&self, db_id: DbId) -> Vec<LastCacheDefinition> {
+        self.last_cache.get_last_caches_for_db(db_id)
+    }
+
+    fn last_cache_stats(
+        &self,
+        db_id: DbId,
+        table_id: TableId,
+        cache_name: &str,
+    ) -> Option<LastCacheStats> {
+        self.last_cache.get_cache_stats(db_id, table_id, cache_name)
+    }
+
+    fn last_cache_memory_bytes(&self) -> usize {
+        self.last_cache.memory_bytes()
+    }
+
+    /// Create a new last-N-value cache in the specified database and table, along with the given
+    /// parameters.
+    ///
+    /// Returns the name of the newly created cache, or `None` if a cache was not created, but the
+    /// provided parameters match those of an existing cache.
+    #[allow(clippy::too_many_arguments)]
+    async fn create_last_cache(
+        &self,
+        db_id: DbId,
+        table_id: TableId,
+        cache_name: Option<&str>,
+        count: Option<usize>,
+        ttl: Option<Duration>,
+        key_columns: Option<Vec<(ColumnId, Arc<str>)>>,
+        value_columns: Option<Vec<(ColumnId, Arc<str>)>>,
+    ) -> Result<Option<LastCacheDefinition>, Error> {
+        let table_def = self
+            .catalog
+            .db_schema_by_id(&db_id)
+            .ok_or(Error::DbDoesNotExist)?
+            .table_definition_by_id(&table_id)
+            .ok_or(Error::TableDoesNotExist)?;
+        self.create_last_cache_with_args(CreateCacheArguments {
+            db_id,
+            table_def,
+            cache_name: cache_name.map(Into::into),
+            count,
+            ttl,
+            key_columns,
+            value_columns,
+            aggregates: None,
+            auto_key_columns: false,
+        })
+        .await
+    }
+
+    async fn create_last_cache_with_auto_key_columns(
+        &self,
+        db_id: DbId,
+        table_id: TableId,
+        cache_name: Option<&str>,
+        count: Option<usize>,
+        ttl: Option<Duration>,
+        value_columns: Option<Vec<(ColumnId, Arc<str>)>>,
+    ) -> Result<Option<LastCacheDefinition>, Error> {
+        let table_def = self
+            .catalog
+            .db_schema_by_id(&db_id)
+            .ok_or(Error::DbDoesNotExist)?
+            .table_definition_by_id(&table_id)
+            .ok_or(Error::TableDoesNotExist)?;
+        // the key columns start out as the tags, or the series key, of the table:
+        self.create_last_cache_with_args(CreateCacheArguments {
+            db_id,
+            table_def,
+            cache_name: cache_name.map(Into::into),
+            count,
+            ttl,
+            key_columns: None,
+            value_columns,
+            aggregates: None,
+            auto_key_columns: true,
+        })
+        .await
+    }
+
+    async fn update_last_cache(
+        &self,
+        db_id: DbId,
+        table_id: TableId,
+        cache_name: &str,
+        count: Option<usize>,
+        ttl: Option<Duration>,
+        value_columns: Option<LastCacheValueColumnsDef>,
+        aggregates: Option<Vec<LastCacheAggregate>>,
+    ) -> Result<Option<LastCacheDefinition>, Error> {
+        let catalog = self.catalog();
+        let db_schema = catalog
+            .db_schema_by_id(&db_id)
+            .ok_or(Error::DbDoesNotExist)?;
+        let table_def = db_schema
+            .table_definition_by_id(&table_id)
+            .ok_or(Error::TableDoesNotExist)?;
+
+        let Some(info) = self.last_cache.update_cache(UpdateCacheArguments {
+            db_id,
+            table_def,
+            cache_name: cache_name.into(),
+            count,
+            ttl,
+            value_columns,
+            aggregates,
+        })?
+        else {
+            return Ok(None);
+        };
+        self.catalog.add_last_cache(db_id, table_id, info.clone());
+        self.write_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::UpdateLastCache(info.clone())],
+        })
+        .await?;
+
+        Ok(Some(info))
+    }
+
+    async fn delete_last_cache(
+        &self,
+        db_id: DbId,
+        tbl_id: TableId,
+        cache_name: &str,
+    ) -> crate::Result<(), self::Error> {
+        let catalog = self.catalog();
+        let db_schema = catalog.db_schema_by_id(&db_id).expect("db should exist");
+        self.last_cache.delete_cache(db_id, tbl_id, cache_name)?;
+        catalog.delete_last_cache(db_id, tbl_id, cache_name);
+
+        // NOTE: if this fails then the cache will be gone from the running server, but will be
+        // resurrected on server restart.
+        self.write_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::DeleteLastCache(LastCacheDelete {
+                table_id: tbl_id,
+                table_name: db_schema.table_id_to_name(&tbl_id).expect("table exists"),
+                name: cache_name.into(),
+            })],
+        })
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl DistinctCacheManager for WriteBufferImpl {
+    fn distinct_cache_provider(&self) -> Arc<DistinctCacheProvider> {
+        Arc::clone(&self.distinct_cache)
+    }
+
+    async fn create_distinct_cache(
+        &self,
+        db_id: DbId,
+        table_id: TableId,
+        cache_name: Option<&str>,
+        columns: Vec<ColumnId>,
+        max_cardinality: Option<usize>,
+        ttl: Option<Duration>,
+    ) -> Result<Option<DistinctCacheDefinition>, Error> {
+        let catalog = self.catalog();
+        let db_schema = catalog
+            .db_schema_by_id(&db_id)
+            .ok_or(Error::DbDoesNotExist)?;
+        let table_def = db_schema
+            .table_definition_by_id(&table_id)
+            .ok_or(Error::TableDoesNotExist)?;
+
+        let Some(info) = self.distinct_cache.create_cache(CreateDistinctCacheArgs {
+            db_id,
+            table_def,
+            cache_name: cache_name.map(Into::into),
+            columns,
+            max_cardinality,
+            ttl,
+        })?
+        else {
+            return Ok(None);
+        };
+        self.catalog
+            .add_distinct_cache(db_id, table_id, info.clone());
+        self.write_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_schema.id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::CreateDistinctCache(info.clone())],
+        })
+        .await?;
+
+        Ok(Some(info))
+    }
+
+    async fn delete_distinct_cache(
+        &self,
+        db_id: DbId,
+        tbl_id: TableId,
+        cache_name: &str,
+    ) -> Result<(), Error> {
+        let catalog = self.catalog();
+        let db_schema = catalog
+            .db_schema_by_id(&db_id)
+            .ok_or(Error::DbDoesNotExist)?;
+        self.distinct_cache
+            .delete_cache(db_id, tbl_id, cache_name)?;
+        catalog.delete_distinct_cache(db_id, tbl_id, cache_name);
+
+        // NOTE: as with last caches, if this fails then the cache will be gone from the running
+        // server, but will be resurrected on server restart.
+        self.write_catalog_batch(CatalogBatch {
+            time_ns: self.time_provider.now().timestamp_nanos(),
+            database_id: db_id,
+            database_name: Arc::clone(&db_schema.name),
+            ops: vec![CatalogOp::DeleteDistinctCache(DistinctCacheDelete {
+                table_id: tbl_id,
+                table_name: db_schema.table_id_to_name(&tbl_id).expect("table exists"),
+                name: cache_name.into(),
+            })],
+        })
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl WriteBuffer for WriteBufferImpl {}
+
+#[cfg(test)]
+#[allow(clippy::await_holding_lock)]
+mod tests {
+    use super::*;
+    use crate::parquet_cache::test_cached_obj_store_and_oracle;
+    use crate::paths::{CatalogFilePath, SnapshotInfoFilePath};
+    use crate::persister::{Persister, DEFAULT_PERSIST_CONCURRENCY};
+    use crate::PersistedSnapshot;
+    use crate::WriteLineErrorCategory;
+    use arrow_util::{assert_batches_eq, assert_batches_sorted_eq};
+    use bytes::Bytes;
+    use datafusion_util::config::register_iox_object_store;
+    use futures_util::StreamExt;
+    use influxdb3_catalog::catalog::CatalogSequenceNumber;
+    use influxdb3_id::{DbId, ParquetFileId};
+    use influxdb3_test_helpers::object_store::RequestCountedObjectStore;
+    use influxdb3_wal::{create, ColumnNamePolicy, SnapshotSequenceNumber, WalFileSequenceNumber};
+    use iox_query::exec::IOxSessionContext;
+    use iox_time::{MockProvider, Time};
+    use object_store::local::LocalFileSystem;
+    use object_store::memory::InMemory;
+    use object_store::{ObjectStore, PutPayload};
+
+    #[test]
+    fn parse_lp_into_buffer() {
+        let host_id = Arc::from("sample-host-id");
+        let instance_id = Arc::from("sample-instance-id");
+        let catalog = Arc::new(Catalog::new(host_id, instance_id));
+        let db_name = NamespaceName::new("foo").unwrap();
+        let lp = "cpu,region=west user=23.2 100\nfoo f1=1i";
+        WriteValidator::initialize(db_name, Arc::clone(&catalog), 0)
+            .unwrap()
+            .v1_parse_lines_and_update_schema(
+                lp,
+                false,
+                Time::from_timestamp_nanos(0),
+                Precision::Nanosecond,
+            )
+            .unwrap()
+            .convert_lines_to_buffer(Gen1Duration::new_5m());
+
+        let db = catalog.db_schema_by_id(&DbId::from(0)).unwrap();
+
+        assert_eq!(db.tables.len(), 2);
+        // cpu table
+        assert_eq!(db.tables.get(&TableId::from(0)).unwrap().num_columns(), 3);
+        // foo table
+        assert_eq!(db.tables.get(&TableId::from(1)).unwrap().num_columns(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn writes_data_to_wal_and_is_queryable() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let time_provider: Arc<dyn TimeProvider> =
+            Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let (object_store, parquet_cache) =
+            test_cached_obj_store_and_oracle(object_store, Arc::clone(&time_provider));
+        let persister = Arc::new(Persister::new(Arc::clone(&object_store), "test_host"));
+        let catalog = Arc::new(persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let write_buffer = WriteBufferImpl::new(WriteBufferImplArgs::new(
+            Arc::clone(&persister),
+            catalog,
+            last_cache,
+            Arc::clone(&time_provider),
+            crate::test_help::make_exec(),
+            WalConfig::test_config(),
+            Some(Arc::clone(&parquet_cache)),
+        ))
+        .await
+        .unwrap();
+        let session_context = IOxSessionContext::with_testing();
+        let runtime_env = session_context.inner().runtime_env();
+        register_iox_object_store(runtime_env, "influxdb3", Arc::clone(&object_store));
+
+        let _ = write_buffer
+            .write_lp(
+                NamespaceName::new("foo").unwrap(),
+                "cpu bar=1 10",
+                Time::from_timestamp_nanos(123),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+
+        let expected = [
+            "+-----+--------------------------------+",
+            "| bar | time                           |",
+            "+-----+--------------------------------+",
+            "| 1.0 | 1970-01-01T00:00:00.000000010Z |",
+            "+-----+--------------------------------+",
+        ];
+        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
+        assert_batches_eq!(&expected, &actual);
+
+        // do two more writes to trigger a snapshot
+        let _ = write_buffer
+            .write_lp(
+                NamespaceName::new("foo").unwrap(),
+                "cpu bar=2 20",
+                Time::from_timestamp_nanos(124),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+
+        let _ = write_buffer
+            .write_lp(
+                NamespaceName::new("foo").unwrap(),
+                "cpu bar=3 30",
+                Time::from_timestamp_nanos(125),
+                false,
+                Precision::Nanosecond,
+            )
+            .await;
+
+        // query the buffer and make sure we get the data back
+        let expected = [
+            "+-----+--------------------------------+",
+            "| bar | time                           |",
+            "+-----+--------------------------------+",
+            "| 1.0 | 1970-01-01T00:00:00.000000010Z |",
+            "| 2.0 | 1970-01-01T00:00:00.000000020Z |",
+            "| 3.0 | 1970-01-01T00:00:00.000000030Z |",
+            "+-----+--------------------------------+",
+        ];
+        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
+        assert_batches_eq!(&expected, &actual);
+
+        // now load a new buffer from object storage
+        let catalog = Arc::new(persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let write_buffer = WriteBufferImpl::new(WriteBufferImplArgs::new(
+            Arc::clone(&persister),
+            catalog,
+            last_cache,
+            Arc::clone(&time_provider),
+            crate::test_help::make_exec(),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(50),
+                snapshot_size: 100,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+            Some(Arc::clone(&parquet_cache)),
+        ))
+        .await
+        .unwrap();
+
+        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
+        assert_batches_eq!(&expected, &actual);
+    }
+
+    #[tokio::test]
+    async fn last_cache_create_and_delete_is_durable() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+        let db_name = "db";
+        let db_id = DbId::from(0);
+        let tbl_name = "table";
+        let tbl_id = TableId::from(0);
+        let cache_name = "cache";
+        // Write some data to the current segment and update the catalog:
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},t1=a f1=true").as_str(),
+            Time::from_timestamp(20, 0).unwrap(),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+        // Create a last cache:
+        wbuf.create_last_cache(db_id, tbl_id, Some(cache_name), None, None, None, None)
+            .await
+            .unwrap();
+
+        // load a new write buffer to ensure its durable
+        let catalog = Arc::new(wbuf.persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let wbuf = WriteBufferImpl::new(WriteBufferImplArgs::new(
+            Arc::clone(&wbuf.persister),
+            catalog,
+            last_cache,
+            Arc::clone(&wbuf.time_provider),
+            Arc::clone(&wbuf.buffer.executor),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+            wbuf.parquet_cache.clone(),
+        ))
+        .await
+        .unwrap();
+
+        let catalog_json = catalog_to_json(&wbuf.catalog);
+        insta::assert_json_snapshot!("catalog-immediately-after-last-cache-create",
+            catalog_json,
+            { ".instance_id" => "[uuid]" }
+        );
+
+        // Do another write that will update the state of the catalog, specifically, the table
+        // that the last cache was created for, and add a new field to the table/cache `f2`:
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},t1=a f1=false,f2=42i").as_str(),
+            Time::from_timestamp(30, 0).unwrap(),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        // and do another replay and verification
+        let catalog = Arc::new(wbuf.persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let wbuf = WriteBufferImpl::new(WriteBufferImplArgs::new(
+            Arc::clone(&wbuf.persister),
+            catalog,
+            last_cache,
+            Arc::clone(&wbuf.time_provider),
+            Arc::clone(&wbuf.buffer.executor),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+            wbuf.parquet_cache.clone(),
+        ))
+        .await
+        .unwrap();
+
+        let catalog_json = catalog_to_json(&wbuf.catalog);
+        insta::assert_json_snapshot!(
+           "catalog-after-last-cache-create-and-new-field",
+           catalog_json,
+           { ".instance_id" => "[uuid]" }
+        );
+
+        // write a new data point to fill the cache
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},t1=a f1=true,f2=53i").as_str(),
+            Time::from_timestamp(40, 0).unwrap(),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        // Fetch record batches from the last cache directly:
+        let expected = [
+            "+----+------+----+----------------------+",
+            "| t1 | f1   | f2 | time                 |",
+            "+----+------+----+----------------------+",
+            "| a  | true | 53 | 1970-01-01T00:00:40Z |",
+            "+----+------+----+----------------------+",
+        ];
+        let actual = wbuf
+            .last_cache_provider()
+            .get_cache_record_batches(db_id, tbl_id, None, &[])
+            .unwrap()
+            .unwrap();
+        assert_batches_eq!(&expected, &actual);
+        // Delete the last cache:
+        wbuf.delete_last_cache(db_id, tbl_id, cache_name)
+            .await
+            .unwrap();
+
+        // do another reload and verify it's gone
+        let catalog = Arc::new(wbuf.persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let wbuf = WriteBufferImpl::new(WriteBufferImplArgs::new(
+            Arc::clone(&wbuf.persister),
+            catalog,
+            last_cache,
+            Arc::clone(&wbuf.time_provider),
+            Arc::clone(&wbuf.buffer.executor),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+            wbuf.parquet_cache.clone(),
+        ))
+        .await
+        .unwrap();
+        let catalog_json = catalog_to_json(&wbuf.catalog);
+        insta::assert_json_snapshot!("catalog-immediately-after-last-cache-delete",
+            catalog_json,
+            { ".instance_id" => "[uuid]" }
+        );
+    }
+
+    #[tokio::test]
+    async fn last_cache_replay_modes() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let wal_config = WalConfig {
+            gen1_duration: Gen1Duration::new_1m(),
+            max_write_buffer_size: 100,
+            flush_interval: Duration::from_millis(10),
+            snapshot_size: 100,
+            compression: Default::default(),
+            max_file_size_bytes: None,
+            retention: Default::default(),
+            ack_level: Default::default(),
+            snapshot_triggers: Default::default(),
+            handoff_max_bytes: None,
+        };
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            wal_config,
+        )
+        .await;
+        let db_name = "db";
+        let tbl_name = "cpu";
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            format!("{tbl_name},host=a usage=1").as_str(),
+            Time::from_timestamp(10, 0).unwrap(),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+        let (db_id, db_schema) = wbuf.catalog().db_schema_and_id(db_name).unwrap();
+        let (tbl_id, table_def) = db_schema.table_definition_and_id(tbl_name).unwrap();
+        let host_col_id = table_def.column_name_to_id("host").unwrap();
+        wbuf.create_last_cache(
+            db_id,
+            tbl_id,
+            Some("cache"),
+            None,
+            None,
+            Some(vec![(host_col_id, "host".into())]),
+            None,
+        )
+        .await
+        .unwrap();
+        for (t, lp) in [
+            (20, "host=a usage=2"),
+            (30, "host=b usage=3"),
+            (40, "host=a usage=4"),
+        ] {
+            wbuf.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                format!("{tbl_name},{lp}").as_str(),
+                Time::from_timestamp(t, 0).unwrap(),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        }
+
+        // replay the WAL into new write buffers with each of the modes:
+        let replay = |last_cache_replay| {
+            let wbuf = &wbuf;
+            async move {
+                let catalog = Arc::new(wbuf.persister.load_or_create_catalog().await.unwrap());
+                let last_cache =
+                    LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+                let replayed = WriteBufferImpl::new(WriteBufferImplArgs {
+                    last_cache_replay,
+                    ..WriteBufferImplArgs::new(
+                        Arc::clone(&wbuf.persister),
+                        catalog,
+                        last_cache,
+                        Arc::clone(&wbuf.time_provider),
+                        Arc::clone(&wbuf.buffer.executor),
+                        wal_config,
+                        None,
+                    )
+                })
+                .await
+                .unwrap();
+                replayed
+                    .last_cache_provider()
+                    .get_cache_record_batches(db_id, tbl_id, None, &[])
+                    .unwrap()
+                    .unwrap()
+            }
+        };
+        let expected = [
+            "+------+----------------------+-------+",
+            "| host | time                 | usage |",
+            "+------+----------------------+-------+",
+            "| a    | 1970-01-01T00:00:40Z | 4.0   |",
+            "| b    | 1970-01-01T00:00:30Z | 3.0   |",
+            "+------+----------------------+-------+",
+        ];
+        assert_batches_sorted_eq!(expected, &replay(LastCacheReplay::WriteThrough).await);
+        assert_batches_sorted_eq!(expected, &replay(LastCacheReplay::Parallel).await);
+        // nothing was snapshot, so there are no values to restore the cold cache with:
+        let batches = replay(LastCacheReplay::Cold).await;
+        assert_eq!(0, batches.iter().map(|b| b.num_rows()).sum::<usize>());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn returns_chunks_across_parquet_and_buffered_data() {
+        let (write_buffer, session_context) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 2,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+
+        let _ = write_buffer
+            .write_lp(
+                NamespaceName::new("foo").unwrap(),
+                "cpu bar=1",
+                Time::from_timestamp(10, 0).unwrap(),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+
+        let expected = [
+            "+-----+----------------------+",
+            "| bar | time                 |",
+            "+-----+----------------------+",
+            "| 1.0 | 1970-01-01T00:00:10Z |",
+            "+-----+----------------------+",
+        ];
+        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
+        assert_batches_sorted_eq!(&expected, &actual);
+
+        let _ = write_buffer
+            .write_lp(
+                NamespaceName::new("foo").unwrap(),
+                "cpu bar=2",
+                Time::from_timestamp(65, 0).unwrap(),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+
+        let expected = [
+            "+-----+----------------------+",
+            "| bar | time                 |",
+            "+-----+----------------------+",
+            "| 1.0 | 1970-01-01T00:00:10Z |",
+            "| 2.0 | 1970-01-01T00:01:05Z |",
+            "+-----+----------------------+",
+        ];
+        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
+        assert_batches_sorted_eq!(&expected, &actual);
+
+        // trigger snapshot with a third write, creating parquet files
+        let _ = write_buffer
+            .write_lp(
+                NamespaceName::new("foo").unwrap(),
+                "cpu bar=3 147000000000",
+                Time::from_timestamp(147, 0).unwrap(),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+
+        // give the snapshot some time to persist in the background
+        let mut ticks = 0;
+        loop {
+            ticks += 1;
+            let persisted = write_buffer.persister.load_snapshots(1000).await.unwrap();
+            if !persisted.is_empty() {
+                assert_eq!(persisted.len(), 1);
+                assert_eq!(persisted[0].min_time, 10000000000);
+                assert_eq!(persisted[0].row_count, 2);
+                break;
+            } else if ticks > 10 {
+                panic!("not persisting");
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let expected = [
+            "+-----+----------------------+",
+            "| bar | time                 |",
+            "+-----+----------------------+",
+            "| 3.0 | 1970-01-01T00:02:27Z |",
+            "| 2.0 | 1970-01-01T00:01:05Z |",
+            "| 1.0 | 1970-01-01T00:00:10Z |",
+            "+-----+----------------------+",
+        ];
+        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
+        assert_batches_sorted_eq!(&expected, &actual);
+
+        // now validate that buffered data and parquet data are all returned
+        let _ = write_buffer
+            .write_lp(
+                NamespaceName::new("foo").unwrap(),
+                "cpu bar=4",
+                Time::from_timestamp(250, 0).unwrap(),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+
+        let expected = [
+            "+-----+----------------------+",
+            "| bar | time                 |",
+            "+-----+----------------------+",
+            "| 3.0 | 1970-01-01T00:02:27Z |",
+            "| 4.0 | 1970-01-01T00:04:10Z |",
+            "| 2.0 | 1970-01-01T00:01:05Z |",
+            "| 1.0 | 1970-01-01T00:00:10Z |",
+            "+-----+----------------------+",
+        ];
+        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
+        assert_batches_sorted_eq!(&expected, &actual);
+
+        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
+        assert_batches_sorted_eq!(&expected, &actual);
+        // and now replay in a new write buffer and attempt to write
+        let catalog = Arc::new(
+            write_buffer
+                .persister
+                .load_or_create_catalog()
+                .await
+                .unwrap(),
+        );
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let write_buffer = WriteBufferImpl::new(WriteBufferImplArgs::new(
+            Arc::clone(&write_buffer.persister),
+            catalog,
+            last_cache,
+            Arc::clone(&write_buffer.time_provider),
+            Arc::clone(&write_buffer.buffer.executor),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 2,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+            write_buffer.parquet_cache.clone(),
+        ))
+        .await
+        .unwrap();
+        let ctx = IOxSessionContext::with_testing();
+        let runtime_env = ctx.inner().runtime_env();
+        register_iox_object_store(
+            runtime_env,
+            "influxdb3",
+            write_buffer.persister.object_store(),
+        );
+
+        // verify the data is still there
+        let actual = get_table_batches(&write_buffer, "foo", "cpu", &ctx).await;
+        assert_batches_sorted_eq!(&expected, &actual);
+
+        // now write some new data
+        let _ = write_buffer
+            .write_lp(
+                NamespaceName::new("foo").unwrap(),
+                "cpu bar=5",
+                Time::from_timestamp(300, 0).unwrap(),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+
+        // and write more to force another snapshot
+        let _ = write_buffer
+            .write_lp(
+                NamespaceName::new("foo").unwrap(),
+                "cpu bar=6",
+                Time::from_timestamp(330, 0).unwrap(),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+
+        let expected = [
+            "+-----+----------------------+",
+            "| bar | time                 |",
+            "+-----+----------------------+",
+            "| 1.0 | 1970-01-01T00:00:10Z |",
+            "| 2.0 | 1970-01-01T00:01:05Z |",
+            "| 3.0 | 1970-01-01T00:02:27Z |",
+            "| 4.0 | 1970-01-01T00:04:10Z |",
+            "| 5.0 | 1970-01-01T00:05:00Z |",
+            "| 6.0 | 1970-01-01T00:05:30Z |",
+            "+-----+----------------------+",
+        ];
+        let actual = get_table_batches(&write_buffer, "foo", "cpu", &ctx).await;
+        assert_batches_sorted_eq!(&expected, &actual);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn catalog_snapshots_only_if_updated() {
+        let (write_buffer, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(5),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+
+        let db_name = "foo";
+        // do three writes to force a snapshot
+        do_writes(
+            db_name,
+            &write_buffer,
+            &[
+                TestWrite {
+                    lp: "cpu bar=1",
+                    time_seconds: 10,
+                },
+                TestWrite {
+                    lp: "cpu bar=2",
+                    time_seconds: 20,
+                },
+                TestWrite {
+                    lp: "cpu bar=3",
+                    time_seconds: 30,
+                },
+            ],
+        )
+        .await;
+
+        verify_catalog_count(2, write_buffer.persister.object_store()).await;
+        verify_snapshot_count(1, &write_buffer.persister).await;
+
+        // only another two writes are needed to trigger a snapshot, because there is still one
+        // WAL period left from before:
+        do_writes(
+            db_name,
+            &write_buffer,
+            &[
+                TestWrite {
+                    lp: "cpu bar=4",
+                    time_seconds: 40,
+                },
+                TestWrite {
+                    lp: "cpu bar=5",
+                    time_seconds: 50,
+                },
+            ],
+        )
+        .await;
+
+        // verify the catalog didn't get persisted, but a snapshot did
+        verify_catalog_count(2, write_buffer.persister.object_store()).await;
+        verify_snapshot_count(2, &write_buffer.persister).await;
+
+        // and finally, do two more, with a catalog update, forcing persistence
+        do_writes(
+            db_name,
+            &write_buffer,
+            &[
+                TestWrite {
+                    lp: "cpu bar=6,asdf=true",
+                    time_seconds: 60,
+                },
+                TestWrite {
+                    lp: "cpu bar=7,asdf=true",
+                    time_seconds: 70,
+                },
+            ],
+        )
+        .await;
+
+        verify_catalog_count(3, write_buffer.persister.object_store()).await;
+        verify_snapshot_count(3, &write_buffer.persister).await;
+    }
+
+    /// Check that when a WriteBuffer is initialized with existing snapshot files, that newly
+    /// generated snapshot files use the next sequence number.
+    #[tokio::test]
+    async fn new_snapshots_use_correct_sequence() {
+        // set up a local file system object store:
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix(test_helpers::tmp_dir().unwrap()).unwrap());
+
+        // create a snapshot file that will be loaded on initialization of the write buffer:
+        // Set ParquetFileId to a non zero number for the snapshot
+        ParquetFileId::from(500).set_next_id();
+        let prev_snapshot_seq = SnapshotSequenceNumber::new(42);
+        let prev_snapshot = PersistedSnapshot::new(
+            "test_host".to_string(),
+            prev_snapshot_seq,
+            WalFileSequenceNumber::new(0),
+            CatalogSequenceNumber::new(0),
+        );
+        let snapshot_json = serde_json::to_vec(&prev_snapshot).unwrap();
+        // set ParquetFileId to be 0 so that we can make sure when it's loaded from the
+        // snapshot that it becomes the expected number
+        ParquetFileId::from(0).set_next_id();
+
+        // put the snapshot file in object store:
+        object_store
+            .put(
+                &SnapshotInfoFilePath::new("test_host", prev_snapshot_seq),
+                PutPayload::from_bytes(Bytes::from(snapshot_json)),
+            )
+            .await
+            .unwrap();
+
+        // setup the write buffer:
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&object_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(5),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+
+        // Assert that loading the snapshots sets ParquetFileId to the correct id number
+        assert_eq!(ParquetFileId::new().as_u64(), 500);
+
+        // there should be one snapshot already, i.e., the one we created above:
+        verify_snapshot_count(1, &wbuf.persister).await;
+        // there is only one initial catalog so far:
+        verify_catalog_count(1, object_store.clone()).await;
+
+        // do three writes to force a new snapshot
+        wbuf.write_lp(
+            NamespaceName::new("foo").unwrap(),
+            "cpu bar=1",
+            Time::from_timestamp(10, 0).unwrap(),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+        wbuf.write_lp(
+            NamespaceName::new("foo").unwrap(),
+            "cpu bar=2",
+            Time::from_timestamp(20, 0).unwrap(),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+        wbuf.write_lp(
+            NamespaceName::new("foo").unwrap(),
+            "cpu bar=3",
+            Time::from_timestamp(30, 0).unwrap(),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        // Check that there are now 2 snapshots:
+        verify_snapshot_count(2, &wbuf.persister).await;
+        // Check that the next sequence number is used for the new snapshot:
+        assert_eq!(
+            prev_snapshot_seq.next(),
+            wbuf.wal.last_snapshot_sequence_number().await
+        );
+        // There should be a catalog now, since the above writes updated the catalog
+        verify_catalog_count(2, object_store.clone()).await;
+        // Check the catalog sequence number in the latest snapshot is correct:
+        let persisted_snapshot_bytes = object_store
+            .get(&SnapshotInfoFilePath::new(
+                "test_host",
+                prev_snapshot_seq.next(),
+            ))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let persisted_snapshot =
+            serde_json::from_slice::<PersistedSnapshot>(&persisted_snapshot_bytes).unwrap();
+        // NOTE: it appears that writes which create a new db increment the catalog sequence twice.
+        // This is likely due to the catalog sequence being incremented first for the db creation and
+        // then again for the updates to the table written to. Hence the sequence number is 2 here.
+        // If we manage to make it so that scenario only increments the catalog sequence once, then
+        // this assertion may fail:
+        assert_eq!(
+            CatalogSequenceNumber::new(2),
+            persisted_snapshot.catalog_sequence_number
+        );
+    }
+
+    #[tokio::test]
+    async fn next_id_is_correct_number() {
+        // set up a local file system object store:
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix(test_helpers::tmp_dir().unwrap()).unwrap());
+
+        let prev_snapshot_seq = SnapshotSequenceNumber::new(42);
+        let mut prev_snapshot = PersistedSnapshot::new(
+            "test_host".to_string(),
+            prev_snapshot_seq,
+            WalFileSequenceNumber::new(0),
+            CatalogSequenceNumber::new(0),
+        );
+
+        assert_eq!(prev_snapshot.next_file_id.as_u64(), 0);
+        assert_eq!(prev_snapshot.next_table_id.as_u32(), 0);
+        assert_eq!(prev_snapshot.next_db_id.as_u32(), 0);
+
+        for _ in 0..=5 {
+            prev_snapshot.add_parquet_file(
+                DbId::from(0),
+                TableId::from(0),
+                ParquetFile {
+                    id: ParquetFileId::new(),
+                    path: "file/path2".into(),
+                    size_bytes: 20,
+                    row_count: 1,
+                    chunk_time: 1,
+                    min_time: 0,
+                    max_time: 1,
+                    indexes: Default::default(),
+                    column_ranges: Default::default(),
+                    sort_key: Default::default(),
+                },
+            );
+        }
+
+        assert_eq!(prev_snapshot.databases.len(), 1);
+        let files = prev_snapshot.databases[&DbId::from(0)].tables[&TableId::from(0)].clone();
+
+        // Assert that all of the files are smaller than the next_file_id field
+        // and that their index corresponds to the order they were added in
+        assert_eq!(prev_snapshot.next_file_id.as_u64(), 6);
+        assert_eq!(files.len(), 6);
+        for (i, file) in files.iter().enumerate() {
+            assert_ne!(file.id, ParquetFileId::from(6));
+            assert!(file.id.as_u64() < 6);
+            assert_eq!(file.id.as_u64(), i as u64);
+        }
+
+        let snapshot_json = serde_json::to_vec(&prev_snapshot).unwrap();
+
+        // put the snapshot file in object store:
+        object_store
+            .put(
+                &SnapshotInfoFilePath::new("test_host", prev_snapshot_seq),
+                PutPayload::from_bytes(Bytes::from(snapshot_json)),
+            )
+            .await
+            .unwrap();
+
+        // setup the write buffer:
+        let (_wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&object_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(5),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+
+        // Test that the next_file_id has been set properly
+        assert_eq!(ParquetFileId::next_id().as_u64(), 6);
+    }
+
+    /// This is the reproducer for [#25277][see]
+    ///
+    /// [see]: https://github.com/influxdata/influxdb/issues/25277
+    #[tokio::test]
+    async fn writes_not_dropped_on_snapshot() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, _) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+
+        let db_name = "coffee_shop";
+        let tbl_name = "menu";
+
+        // do some writes to get a snapshot:
+        do_writes(
+            db_name,
+            &wbuf,
+            &[
+                TestWrite {
+                    lp: format!("{tbl_name},name=espresso price=2.50"),
+                    time_seconds: 1,
+                },
+                TestWrite {
+                    lp: format!("{tbl_name},name=americano price=3.00"),
+                    time_seconds: 2,
+                },
+                TestWrite {
+                    lp: format!("{tbl_name},name=latte price=4.50"),
+                    time_seconds: 3,
+                },
+            ],
+        )
+        .await;
+
+        // wait for snapshot to be created:
+        verify_snapshot_count(1, &wbuf.persister).await;
+
+        // Now drop the write buffer, and create a new one that replays:
+        drop(wbuf);
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+
+        // Get the record batches from replayed buffer:
+        let batches = get_table_batches(&wbuf, db_name, tbl_name, &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+-----------+-------+----------------------+",
+                "| name      | price | time                 |",
+                "+-----------+-------+----------------------+",
+                "| americano | 3.0   | 1970-01-01T00:00:02Z |",
+                "| espresso  | 2.5   | 1970-01-01T00:00:01Z |",
+                "| latte     | 4.5   | 1970-01-01T00:00:03Z |",
+                "+-----------+-------+----------------------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_not_dropped_on_larger_snapshot_size() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, _) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 2,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+
+        let db_name = "coffee_shop";
+        let tbl_name = "menu";
+
+        // Do six writes to trigger a snapshot
+        do_writes(
+            db_name,
+            &wbuf,
+            &[
+                TestWrite {
+                    lp: format!("{tbl_name},name=espresso,type=drink price=2.50"),
+                    time_seconds: 1,
+                },
+                TestWrite {
+                    lp: format!("{tbl_name},name=americano,type=drink price=3.00"),
+                    time_seconds: 2,
+                },
+                TestWrite {
+                    lp: format!("{tbl_name},name=latte,type=drink price=4.50"),
+                    time_seconds: 3,
+                },
+                TestWrite {
+                    lp: format!("{tbl_name},name=croissant,type=food price=5.50"),
+                    time_seconds: 4,
+                },
+                TestWrite {
+                    lp: format!("{tbl_name},name=muffin,type=food price=4.50"),
+                    time_seconds: 5,
+                },
+                TestWrite {
+                    lp: format!("{tbl_name},name=biscotto,type=food price=3.00"),
+                    time_seconds: 6,
+                },
+            ],
+        )
+        .await;
+
+        verify_snapshot_count(1, &wbuf.persister).await;
+
+        // Drop the write buffer, and create a new one that replays:
+        drop(wbuf);
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 2,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+
+        // Get the record batches from replyed buffer:
+        let batches = get_table_batches(&wbuf, db_name, tbl_name, &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+-----------+-------+----------------------+-------+",
+                "| name      | price | time                 | type  |",
+                "+-----------+-------+----------------------+-------+",
+                "| americano | 3.0   | 1970-01-01T00:00:02Z | drink |",
+                "| biscotto  | 3.0   | 1970-01-01T00:00:06Z | food  |",
+                "| croissant | 5.5   | 1970-01-01T00:00:04Z | food  |",
+                "| espresso  | 2.5   | 1970-01-01T00:00:01Z | drink |",
+                "| latte     | 4.5   | 1970-01-01T00:00:03Z | drink |",
+                "| muffin    | 4.5   | 1970-01-01T00:00:05Z | food  |",
+                "+-----------+-------+----------------------+-------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_not_dropped_with_future_writes() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, _) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+
+        let db_name = "coffee_shop";
+        let tbl_name = "menu";
+
+        // do some writes to get a snapshot:
+        do_writes(
+            db_name,
+            &wbuf,
+            &[
+                TestWrite {
+                    lp: format!("{tbl_name},name=espresso price=2.50"),
+                    time_seconds: 1,
+                },
+                // This write is way out in the future, so as to be outside the normal
+                // range for a snapshot:
+                TestWrite {
+                    lp: format!("{tbl_name},name=americano price=3.00"),
+                    time_seconds: 20_000,
+                },
+                // This write will trigger the snapshot:
+                TestWrite {
+                    lp: format!("{tbl_name},name=latte price=4.50"),
+                    time_seconds: 3,
+                },
+            ],
+        )
+        .await;
+
+        // Wait for snapshot to be created:
+        verify_snapshot_count(1, &wbuf.persister).await;
+
+        // Now drop the write buffer, and create a new one that replays:
+        drop(wbuf);
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+
+        // Get the record batches from replayed buffer:
+        let batches = get_table_batches(&wbuf, db_name, tbl_name, &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+-----------+-------+----------------------+",
+                "| name      | price | time                 |",
+                "+-----------+-------+----------------------+",
+                "| americano | 3.0   | 1970-01-01T05:33:20Z |",
+                "| espresso  | 2.5   | 1970-01-01T00:00:01Z |",
+                "| latte     | 4.5   | 1970-01-01T00:00:03Z |",
+                "+-----------+-------+----------------------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn notifies_watchers_of_snapshot() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, _) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+
+        let mut watcher = wbuf.watch_persisted_snapshots();
+        watcher.mark_changed();
+
+        let db_name = "coffee_shop";
+        let tbl_name = "menu";
+
+        // do some writes to get a snapshot:
+        do_writes(
+            db_name,
+            &wbuf,
+            &[
+                TestWrite {
+                    lp: format!("{tbl_name},name=espresso price=2.50"),
+                    time_seconds: 1,
+                },
+                TestWrite {
+                    lp: format!("{tbl_name},name=americano price=3.00"),
+                    time_seconds: 2,
+                },
+                TestWrite {
+                    lp: format!("{tbl_name},name=latte price=4.50"),
+                    time_seconds: 3,
+                },
+            ],
+        )
+        .await;
+
+        // wait for snapshot to be created:
+        verify_snapshot_count(1, &wbuf.persister).await;
+        watcher.changed().await.unwrap();
+        let snapshot = watcher.borrow();
+        assert!(snapshot.is_some(), "watcher should be notified of snapshot");
+    }
+
+    #[tokio::test]
+    async fn test_db_id_is_persisted_and_updated() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, _) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+        let db_name = "coffee_shop";
+        let tbl_name = "menu";
+
+        // do some writes to get a snapshot:
+        do_writes(
+            db_name,
+            &wbuf,
+            &[
+                TestWrite {
+                    lp: format!("{tbl_name},name=espresso price=2.50"),
+                    time_seconds: 1,
+                },
+                // This write is way out in the future, so as to be outside the normal
+                // range for a snapshot:
+                TestWrite {
+                    lp: format!("{tbl_name},name=americano price=3.00"),
+                    time_seconds: 20_000,
+                },
+                // This write will trigger the snapshot:
+                TestWrite {
+                    lp: format!("{tbl_name},name=latte price=4.50"),
+                    time_seconds: 3,
+                },
+            ],
+        )
+        .await;
+
+        // Wait for snapshot to be created:
+        verify_snapshot_count(1, &wbuf.persister).await;
+
+        // Now drop the write buffer, and create a new one that replays:
+        drop(wbuf);
+
+        // Set DbId to a large number to make sure it is properly set on replay
+        // and assert that it's what we expect it to be before we replay
+        dbg!(DbId::next_id());
+        DbId::from(10_000).set_next_id();
+        assert_eq!(DbId::next_id().as_u32(), 10_000);
+        dbg!(DbId::next_id());
+        let (_wbuf, _) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+        )
+        .await;
+        dbg!(DbId::next_id());
+
+        assert_eq!(DbId::next_id().as_u32(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_parquet_cache() {
+        // set up a write buffer using a TestObjectStore so we can spy on requests that get
+        // through to the object store for parquet files:
+        let test_store = Arc::new(RequestCountedObjectStore::new(Arc::new(InMemory::new())));
+        let obj_store: Arc<dyn ObjectStore> = Arc::clone(&test_store) as _;
+        let (wbuf, ctx) = setup_cache_optional(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+            true,
+        )
+        .await;
+        let db_name = "my_corp";
+        let db_id = DbId::from(0);
+        let tbl_name = "temp";
+        let tbl_id = TableId::from(0);
+
+        // make some writes to generate a snapshot:
+        do_writes(
+            db_name,
+            &wbuf,
+            &[
+                TestWrite {
+                    lp: format!(
+                        "\
+                        {tbl_name},warehouse=us-east,room=01a,device=10001 reading=36\n\
+                        {tbl_name},warehouse=us-east,room=01b,device=10002 reading=29\n\
+                        {tbl_name},warehouse=us-east,room=02a,device=30003 reading=33\n\
+                        "
+                    ),
+                    time_seconds: 1,
+                },
+                TestWrite {
+                    lp: format!(
+                        "\
+                        {tbl_name},warehouse=us-east,room=01a,device=10001 reading=37\n\
+                        {tbl_name},warehouse=us-east,room=01b,device=10002 reading=28\n\
+                        {tbl_name},warehouse=us-east,room=02a,device=30003 reading=32\n\
+                        "
+                    ),
+                    time_seconds: 2,
+                },
+                // This write will trigger the snapshot:
+                TestWrite {
+                    lp: format!(
+                        "\
+                        {tbl_name},warehouse=us-east,room=01a,device=10001 reading=35\n\
+                        {tbl_name},warehouse=us-east,room=01b,device=10002 reading=24\n\
+                        {tbl_name},warehouse=us-east,room=02a,device=30003 reading=30\n\
+                        "
+                    ),
+                    time_seconds: 3,
+                },
+            ],
+        )
+        .await;
+
+        // Wait for snapshot to be created, once this is done, then the parquet has been persisted:
+        verify_snapshot_count(1, &wbuf.persister).await;
+
+        // get the path for the created parquet file:
+        let persisted_files = wbuf.persisted_files().get_files(db_id, tbl_id);
+        assert_eq!(1, persisted_files.len());
+        let path = ObjPath::from(persisted_files[0].path.as_str());
+
+        // check the number of requests to that path before making a query:
+        // there should be one get request, made by the cache oracle:
+        assert_eq!(1, test_store.get_request_count(&path));
+        assert_eq!(0, test_store.get_opts_request_count(&path));
+        assert_eq!(0, test_store.get_ranges_request_count(&path));
+        assert_eq!(0, test_store.get_range_request_count(&path));
+        assert_eq!(0, test_store.head_request_count(&path));
+
+        let batches = get_table_batches(&wbuf, db_name, tbl_name, &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+--------+---------+------+----------------------+-----------+",
+                "| device | reading | room | time                 | warehouse |",
+                "+--------+---------+------+----------------------+-----------+",
+                "| 10001  | 35.0    | 01a  | 1970-01-01T00:00:03Z | us-east   |",
+                "| 10001  | 36.0    | 01a  | 1970-01-01T00:00:01Z | us-east   |",
+                "| 10001  | 37.0    | 01a  | 1970-01-01T00:00:02Z | us-east   |",
+                "| 10002  | 24.0    | 01b  | 1970-01-01T00:00:03Z | us-east   |",
+                "| 10002  | 28.0    | 01b  | 1970-01-01T00:00:02Z | us-east   |",
+                "| 10002  | 29.0    | 01b  | 1970-01-01T00:00:01Z | us-east   |",
+                "| 30003  | 30.0    | 02a  | 1970-01-01T00:00:03Z | us-east   |",
+                "| 30003  | 32.0    | 02a  | 1970-01-01T00:00:02Z | us-east   |",
+                "| 30003  | 33.0    | 02a  | 1970-01-01T00:00:01Z | us-east   |",
+                "+--------+---------+------+----------------------+-----------+",
+            ],
+            &batches
+        );
+
+        // counts should not change, since requests for this parquet file hit the cache:
+        assert_eq!(1, test_store.get_request_count(&path));
+        assert_eq!(0, test_store.get_opts_request_count(&path));
+        assert_eq!(0, test_store.get_ranges_request_count(&path));
+        assert_eq!(0, test_store.get_range_request_count(&path));
+        assert_eq!(0, test_store.head_request_count(&path));
+    }
+    #[tokio::test]
+    async fn parquet_cache_prefilled_on_startup() {
+        let test_store = Arc::new(RequestCountedObjectStore::new(Arc::new(InMemory::new())));
+        let obj_store: Arc<dyn ObjectStore> = Arc::clone(&test_store) as _;
+        let wal_config = WalConfig {
+            gen1_duration: Gen1Duration::new_1m(),
+            max_write_buffer_size: 100,
+            flush_interval: Duration::from_millis(10),
+            snapshot_size: 1,
+            compression: Default::default(),
+            max_file_size_bytes: None,
+            retention: Default::default(),
+            ack_level: Default::default(),
+            snapshot_triggers: Default::default(),
+            handoff_max_bytes: None,
+        };
+        // the first write buffer does not use a cache, so it does not fetch the file it persists:
+        let (wbuf, _) = setup_cache_optional(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            wal_config,
+            false,
+        )
+        .await;
+        let db_name = "my_corp";
+        let tbl_name = "temp";
+        do_writes(
+            db_name,
+            &wbuf,
+            &(1..=3)
+                .map(|time_seconds| TestWrite {
+                    lp: format!("{tbl_name},room=01a reading={time_seconds}"),
+                    time_seconds,
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await;
+        verify_snapshot_count(1, &wbuf.persister).await;
+        let persisted_files = wbuf
+            .persisted_files()
+            .get_files(DbId::from(0), TableId::from(0));
+        assert_eq!(1, persisted_files.len());
+        let path = ObjPath::from(persisted_files[0].path.as_str());
+        assert_eq!(0, test_store.get_request_count(&path));
+
+        // start a new write buffer with a cache that is prefilled with the file:
+        let (cached_store, parquet_cache) =
+            test_cached_obj_store_and_oracle(obj_store, Arc::clone(&wbuf.time_provider));
+        let persister = Arc::new(Persister::new(Arc::clone(&cached_store), "test_host"));
+        let catalog = Arc::new(persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let wbuf = WriteBufferImpl::new(WriteBufferImplArgs {
+            parquet_cache_prefill_files: 1,
+            ..WriteBufferImplArgs::new(
+                persister,
+                catalog,
+                last_cache,
+                Arc::clone(&wbuf.time_provider),
+                Arc::clone(&wbuf.buffer.executor),
+                wal_config,
+                Some(Arc::clone(&parquet_cache)),
+            )
+        })
+        .await
+        .unwrap();
+
+        // wait for the file to be fetched into the cache:
+        let file_size = persisted_files[0].size_bytes as usize;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while parquet_cache.usage().used_bytes < file_size {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("file was prefilled into the cache");
+        assert_eq!(1, test_store.get_request_count(&path));
+
+        // the query is served from the cache:
+        let ctx = IOxSessionContext::with_testing();
+        register_iox_object_store(ctx.inner().runtime_env(), "influxdb3", cached_store);
+        let batches = get_table_batches(&wbuf, db_name, tbl_name, &ctx).await;
+        assert_eq!(3, batches.iter().map(|b| b.num_rows()).sum::<usize>());
+        assert_eq!(1, test_store.get_request_count(&path));
+        assert_eq!(0, test_store.get_ranges_request_count(&path));
+    }
+
+    #[tokio::test]
+    async fn test_no_parquet_cache() {
+        // set up a write buffer using a TestObjectStore so we can spy on requests that get
+        // through to the object store for parquet files:
+        let test_store = Arc::new(RequestCountedObjectStore::new(Arc::new(InMemory::new())));
+        let obj_store: Arc<dyn ObjectStore> = Arc::clone(&test_store) as _;
+        let (wbuf, ctx) = setup_cache_optional(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig {
+                gen1_duration: Gen1Duration::new_1m(),
+                max_write_buffer_size: 100,
+                flush_interval: Duration::from_millis(10),
+                snapshot_size: 1,
+                compression: Default::default(),
+                max_file_size_bytes: None,
+                retention: Default::default(),
+                ack_level: Default::default(),
+                snapshot_triggers: Default::default(),
+                handoff_max_bytes: None,
+            },
+            false,
+        )
+        .await;
+        let db_name = "my_corp";
+        let db_id = DbId::from(0);
+        let tbl_name = "temp";
+        let tbl_id = TableId::from(0);
+
+        // make some writes to generate a snapshot:
+        do_writes(
+            db_name,
+            &wbuf,
+            &[
+                TestWrite {
+                    lp: format!(
+                        "\
+                        {tbl_name},warehouse=us-east,room=01a,device=10001 reading=36\n\
+                        {tbl_name},warehouse=us-east,room=01b,device=10002 reading=29\n\
+                        {tbl_name},warehouse=us-east,room=02a,device=30003 reading=33\n\
+                        "
+                    ),
+                    time_seconds: 1,
+                },
+                TestWrite {
+                    lp: format!(
+                        "\
+                        {tbl_name},warehouse=us-east,room=01a,device=10001 reading=37\n\
+                        {tbl_name},warehouse=us-east,room=01b,device=10002 reading=28\n\
+                        {tbl_name},warehouse=us-east,room=02a,device=30003 reading=32\n\
+                        "
+                    ),
+                    time_seconds: 2,
+                },
+                // This write will trigger the snapshot:
+                TestWrite {
+                    lp: format!(
+                        "\
+                        {tbl_name},warehouse=us-east,room=01a,device=10001 reading=35\n\
+                        {tbl_name},warehouse=us-east,room=01b,device=10002 reading=24\n\
+                        {tbl_name},warehouse=us-east,room=02a,device=30003 reading=30\n\
+                        "
+                    ),
+                    time_seconds: 3,
+                },
+            ],
+        )
+        .await;
+
+        // Wait for snapshot to be created, once this is done, then the parquet has been persisted:
+        verify_snapshot_count(1, &wbuf.persister).await;
+
+        // get the path for the created parquet file:
+        let persisted_files = wbuf.persisted_files().get_files(db_id, tbl_id);
+        assert_eq!(1, persisted_files.len());
+        let path = ObjPath::from(persisted_files[0].path.as_str());
+
+        // the ranges of the tag columns were recorded when the file was persisted:
+        let column_ranges = &persisted_files[0].column_ranges;
+        assert_eq!(
+            vec!["device", "room", "warehouse"],
+            column_ranges.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(&crate::ColumnValueRange {
+                min: "01a".to_string(),
+                max: "02a".to_string(),
+            }),
+            column_ranges.get("room")
+        );
+
+        // check the number of requests to that path before making a query:
+        // there should be no get or get_range requests since nothing has asked for this file yet:
+        assert_eq!(0, test_store.get_request_count(&path));
+        assert_eq!(0, test_store.get_opts_request_count(&path));
+        assert_eq!(0, test_store.get_ranges_request_count(&path));
+        assert_eq!(0, test_store.get_range_request_count(&path));
+        assert_eq!(0, test_store.head_request_count(&path));
+
+        let batches = get_table_batches(&wbuf, db_name, tbl_name, &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+--------+---------+------+----------------------+-----------+",
+                "| device | reading | room | time                 | warehouse |",
+                "+--------+---------+------+----------------------+-----------+",
+                "| 10001  | 35.0    | 01a  | 1970-01-01T00:00:03Z | us-east   |",
+                "| 10001  | 36.0    | 01a  | 1970-01-01T00:00:01Z | us-east   |",
+                "| 10001  | 37.0    | 01a  | 1970-01-01T00:00:02Z | us-east   |",
+                "| 10002  | 24.0    | 01b  | 1970-01-01T00:00:03Z | us-east   |",
+                "| 10002  | 28.0    | 01b  | 1970-01-01T00:00:02Z | us-east   |",
+                "| 10002  | 29.0    | 01b  | 1970-01-01T00:00:01Z | us-east   |",
+                "| 30003  | 30.0    | 02a  | 1970-01-01T00:00:03Z | us-east   |",
+                "| 30003  | 32.0    | 02a  | 1970-01-01T00:00:02Z | us-east   |",
+                "| 30003  | 33.0    | 02a  | 1970-01-01T00:00:01Z | us-east   |",
+                "+--------+---------+------+----------------------+-----------+",
+            ],
+            &batches
+        );
+
+        // counts should change, since requests for this parquet file were made with no cache:
+        assert_eq!(0, test_store.get_request_count(&path));
+        assert_eq!(0, test_store.get_opts_request_count(&path));
+        assert_eq!(1, test_store.get_ranges_request_count(&path));
+        assert_eq!(2, test_store.get_range_request_count(&path));
+        assert_eq!(0, test_store.head_request_count(&path));
+    }
+
+    #[tokio::test]
+    async fn retention_period_prunes_expired_data_and_is_durable() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let wal_config = WalConfig {
+            gen1_duration: Gen1Duration::new_1m(),
+            max_write_buffer_size: 100,
+            flush_interval: Duration::from_millis(10),
+            snapshot_size: 100,
+            compression: Default::default(),
+            max_file_size_bytes: None,
+            retention: Default::default(),
+            ack_level: Default::default(),
+            snapshot_triggers: Default::default(),
+            handoff_max_bytes: None,
+        };
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp(1_000, 0).unwrap(),
+            Arc::clone(&obj_store),
+            wal_config,
+        )
+        .await;
+        let db_name = "db";
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            "cpu bar=1 10\ncpu bar=2 990",
+            Time::from_timestamp(1_000, 0).unwrap(),
+            false,
+            Precision::Second,
+        )
+        .await
+        .unwrap();
+
+        // without a retention period, all data is returned:
+        let batches = get_table_batches(&wbuf, db_name, "cpu", &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+-----+----------------------+",
+                "| bar | time                 |",
+                "+-----+----------------------+",
+                "| 1.0 | 1970-01-01T00:00:10Z |",
+                "| 2.0 | 1970-01-01T00:16:30Z |",
+                "+-----+----------------------+",
+            ],
+            &batches
+        );
+
+        // with a retention period of 100 seconds, only the newer data is returned:
+        wbuf.set_retention_period(db_name, Some(Duration::from_secs(100)))
+            .await
+            .unwrap();
+        let batches = get_table_batches(&wbuf, db_name, "cpu", &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+-----+----------------------+",
+                "| bar | time                 |",
+                "+-----+----------------------+",
+                "| 2.0 | 1970-01-01T00:16:30Z |",
+                "+-----+----------------------+",
+            ],
+            &batches
+        );
+
+        // setting the retention period on a database that doesn't exist fails:
+        assert!(matches!(
+            wbuf.set_retention_period("not_a_db", None).await,
+            Err(Error::DbDoesNotExist)
+        ));
+
+        // load a new write buffer to ensure the retention period is durable
+        let catalog = Arc::new(wbuf.persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let wbuf = WriteBufferImpl::new(WriteBufferImplArgs::new(
+            Arc::clone(&wbuf.persister),
+            catalog,
+            last_cache,
+            Arc::clone(&wbuf.time_provider),
+            Arc::clone(&wbuf.buffer.executor),
+            wal_config,
+            wbuf.parquet_cache.clone(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            Some(Duration::from_secs(100)),
+            wbuf.catalog().db_schema(db_name).unwrap().retention_period
+        );
+    }
+
+    #[tokio::test]
+    async fn write_time_limits_reject_lines_outside_window() {
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp(1_000, 0).unwrap(),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+        let db_name = "db";
+        let now = Time::from_timestamp(1_000, 0).unwrap();
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            "cpu bar=1 1000",
+            now,
+            false,
+            Precision::Second,
+        )
+        .await
+        .unwrap();
+
+        wbuf.set_write_time_limits(
+            db_name,
+            Some(Duration::from_secs(60)),
+            Some(Duration::from_secs(100)),
+        )
+        .await
+        .unwrap();
+
+        let result = wbuf
+            .write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                "cpu bar=1 899\ncpu bar=2 900\ncpu bar=3 1060\ncpu bar=4 1061\ncpu bar=5",
+                now,
+                true,
+                Precision::Second,
+            )
+            .await
+            .unwrap();
+        assert_eq!(3, result.line_count);
+        let rejected = result
+            .invalid_lines
+            .iter()
+            .map(|e| (e.line_number, e.error_category))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (1, WriteLineErrorCategory::TimestampOutOfRange),
+                (4, WriteLineErrorCategory::TimestampOutOfRange),
+            ],
+            rejected
+        );
+
+        // setting the limits on a database that doesn't exist fails:
+        assert!(matches!(
+            wbuf.set_write_time_limits("not_a_db", None, None).await,
+            Err(Error::DbDoesNotExist)
+        ));
+
+        // clearing the limits allows the lines to be written:
+        wbuf.set_write_time_limits(db_name, None, None)
+            .await
+            .unwrap();
+        let result = wbuf
+            .write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                "cpu bar=1 899\ncpu bar=4 1061",
+                now,
+                false,
+                Precision::Second,
+            )
+            .await
+            .unwrap();
+        assert_eq!(2, result.line_count);
+    }
+
+    #[tokio::test]
+    async fn schema_limits_reject_new_columns() {
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+        let db_name = "db";
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            "cpu,host=a usage=1 1",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        wbuf.set_schema_limits(
+            db_name,
+            SchemaLimits {
+                max_columns_per_table: Some(4),
+                max_column_name_length: Some(8),
+                column_name_policy: ColumnNamePolicy::Identifier,
+            },
+        )
+        .await
+        .unwrap();
+
+        let result = wbuf
+            .write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                "cpu,host=a usage=2,idle=1 2\n\
+                cpu,host=a usage=3,system=1 3\n\
+                cpu,host=a usage=4,a_very_long_name=1 4\n\
+                cpu,host=a usage=5,bad-name=1 5\n\
+                mem,host=a free=1 6",
+                Time::from_timestamp_nanos(0),
+                true,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        assert_eq!(2, result.line_count);
+        let rejected = result
+            .invalid_lines
+            .iter()
+            .map(|e| (e.line_number, e.error_category))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (2, WriteLineErrorCategory::SchemaLimit),
+                (3, WriteLineErrorCategory::InvalidColumnName),
+                (4, WriteLineErrorCategory::InvalidColumnName),
+            ],
+            rejected
+        );
+    }
+
+    #[tokio::test]
+    async fn gen1_duration_is_set_per_database() {
+        use std::collections::{BTreeMap, BTreeSet};
+
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+        for db_name in ["db_1m", "db_default"] {
+            wbuf.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                "cpu,host=a usage=1 0",
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        }
+        wbuf.set_gen1_duration("db_1m", Some(Gen1Duration::new_1m()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            wbuf.set_gen1_duration("not_a_db", None).await,
+            Err(Error::DbDoesNotExist)
+        ));
+
+        let mut subscription = wbuf.subscribe_wal(None).await.unwrap();
+        for db_name in ["db_1m", "db_default"] {
+            wbuf.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                "cpu,host=a usage=2 30000000000\n\
+                cpu,host=a usage=3 90000000000",
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        }
+
+        // the rows are split into 1m chunks for the database with its own gen1 duration, and
+        // into the 5m chunks of the server for the other:
+        let mut chunk_times = BTreeMap::new();
+        while chunk_times.len() < 2 {
+            let wal_contents = subscription.next().await.unwrap().unwrap();
+            for write_batch in wal_contents.ops.iter().filter_map(WalOp::as_write) {
+                let times = chunk_times
+                    .entry(write_batch.database_name.to_string())
+                    .or_insert_with(BTreeSet::new);
+                for table_chunks in write_batch.table_chunks.values() {
+                    times.extend(table_chunks.chunk_time_to_chunk.keys().copied());
+                }
+            }
+        }
+        assert_eq!(
+            BTreeMap::from([
+                ("db_1m".to_string(), BTreeSet::from([0, 60_000_000_000])),
+                ("db_default".to_string(), BTreeSet::from([0])),
+            ]),
+            chunk_times
+        );
+    }
+
+    #[tokio::test]
+    async fn schema_mode_rejects_schema_changes() {
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+
+        let lp = "cpu,host=b usage=2 2\n\
+                  cpu,host=a usage=3,idle=1 3\n\
+                  cpu,host=a,region=us usage=4 4\n\
+                  mem,host=a free=1 5";
+        for (schema_mode, expected_rejected) in [
+            (SchemaMode::Auto, vec![]),
+            (SchemaMode::TagLocked, vec![3, 4]),
+            (SchemaMode::Strict, vec![2, 3, 4]),
+        ] {
+            // use a fresh database for each mode, so that the columns added by previous
+            // iterations do not affect the result:
+            let db_name = format!("db_{schema_mode}").replace('-', "_");
+            wbuf.write_lp(
+                NamespaceName::new(db_name.clone()).unwrap(),
+                "cpu,host=a usage=1 1",
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+            wbuf.set_schema_mode(&db_name, schema_mode).await.unwrap();
+
+            let result = wbuf
+                .write_lp(
+                    NamespaceName::new(db_name).unwrap(),
+                    lp,
+                    Time::from_timestamp_nanos(0),
+                    true,
+                    Precision::Nanosecond,
+                )
+                .await
+                .unwrap();
+            let rejected = result
+                .invalid_lines
+                .iter()
+                .map(|e| {
+                    assert_eq!(WriteLineErrorCategory::SchemaLocked, e.error_category);
+                    e.line_number
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(expected_rejected, rejected, "schema mode: {schema_mode}");
+        }
+
+        // setting the mode on a database that doesn't exist fails:
+        assert!(matches!(
+            wbuf.set_schema_mode("not_a_db", SchemaMode::Strict).await,
+            Err(Error::DbDoesNotExist)
+        ));
+    }
+
+    #[tokio::test]
+    async fn field_coercion_is_set_per_database() {
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+
+        for db_name in ["coerced", "not_coerced"] {
+            wbuf.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                "cpu usage=1.5 1",
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        }
+        wbuf.set_field_coercion("coerced", FieldCoercion::WidenNumeric)
+            .await
+            .unwrap();
+
+        for (db_name, expected_rejected) in [("coerced", vec![]), ("not_coerced", vec![1])] {
+            let result = wbuf
+                .write_lp(
+                    NamespaceName::new(db_name).unwrap(),
+                    "cpu usage=2i 2",
+                    Time::from_timestamp_nanos(0),
+                    true,
+                    Precision::Nanosecond,
+                )
+                .await
+                .unwrap();
+            let rejected = result
+                .invalid_lines
+                .iter()
+                .map(|e| e.line_number)
+                .collect::<Vec<_>>();
+            assert_eq!(expected_rejected, rejected, "database: {db_name}");
+        }
+
+        assert!(matches!(
+            wbuf.set_field_coercion("not_a_db", FieldCoercion::Stringify)
+                .await,
+            Err(Error::DbDoesNotExist)
+        ));
+    }
+
+    #[tokio::test]
+    async fn influxdb1_compatibility_mode() {
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+        let db_name = "db";
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            "cpu up=true,usage=1.0 0",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+
+        wbuf.set_compatibility_mode(db_name, CompatibilityMode::InfluxDb1)
+            .await
+            .unwrap();
+        let result = wbuf
+            .write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                "cpu up=t,usage=1.5e2 1\ncpu up=F,usage=2E-1 2",
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        assert_eq!(2, result.line_count);
+
+        let ctx = IOxSessionContext::with_testing();
+        let batches = get_table_batches(&wbuf, db_name, "cpu", &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+-------+-------+--------------------------------+",
+                "| up    | usage | time                           |",
+                "+-------+-------+--------------------------------+",
+                "| false | 0.2   | 1970-01-01T00:00:00.000000002Z |",
+                "| true  | 1.0   | 1970-01-01T00:00:00Z           |",
+                "| true  | 150.0 | 1970-01-01T00:00:00.000000001Z |",
+                "+-------+-------+--------------------------------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn series_key_policy() {
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+        let db_name = "db";
+        wbuf.create_table(
+            db_name,
+            "cpu",
+            &["region", "host"],
+            &[("usage", FieldDataType::Integer)],
+            true,
+        )
+        .await
+        .unwrap();
+        let wbuf = &wbuf;
+        let write = |lp: &'static str| async move {
+            wbuf.write_lp_v3(
+                NamespaceName::new(db_name).unwrap(),
+                lp,
+                Time::from_timestamp_nanos(0),
+                true,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap()
+        };
+
+        // by default, lines must have exactly the series key of the table:
+        let result = write("cpu,region/us/host/a usage=1i 1\ncpu,region/us usage=2i 2").await;
+        assert_eq!(1, result.line_count);
+        assert_eq!(
+            WriteLineErrorCategory::SchemaConflict,
+            result.invalid_lines[0].error_category
+        );
+
+        // members of the series key may be left out once the policy allows it, but lines with
+        // tags that are not in the series key are still rejected:
+        wbuf.set_series_key_policy(db_name, SeriesKeyPolicy::Fill)
+            .await
+            .unwrap();
+        let result = write(
+            "cpu,region/eu usage=3i 3\n\
+             cpu,region/us/rack/1 usage=4i 4\n\
+             cpu usage=5i 5\n\
+             cpu,host/b usage=6i 6",
+        )
+        .await;
+        assert_eq!(3, result.line_count);
+        assert_eq!(
+            vec![WriteLineErrorCategory::SchemaConflict],
+            result
+                .invalid_lines
+                .iter()
+                .map(|e| e.error_category)
+                .collect::<Vec<_>>()
+        );
+
+        let ctx = IOxSessionContext::with_testing();
+        let batches = get_table_batches(&wbuf, db_name, "cpu", &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+------+--------+--------------------------------+-------+",
+                "| host | region | time                           | usage |",
+                "+------+--------+--------------------------------+-------+",
+                "|      |        | 1970-01-01T00:00:00.000000005Z | 5     |",
+                "|      | eu     | 1970-01-01T00:00:00.000000003Z | 3     |",
+                "| a    | us     | 1970-01-01T00:00:00.000000001Z | 1     |",
+                "| b    |        | 1970-01-01T00:00:00.000000006Z | 6     |",
+                "+------+--------+--------------------------------+-------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn identifier_policy() {
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+        let db_name = "db";
+        let wbuf = &wbuf;
+        let write = |lp: &'static str| async move {
+            wbuf.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                lp,
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap()
+        };
+        write("cpu,host=a usage=1 1").await;
+
+        wbuf.set_identifier_policy(db_name, IdentifierPolicy::Lowercase)
+            .await
+            .unwrap();
+        write("CPU,Host=b Usage=2 2").await;
+        wbuf.set_identifier_policy(db_name, IdentifierPolicy::Normalize)
+            .await
+            .unwrap();
+        // written with fullwidth letters:
+        write("\u{ff23}\u{ff30}\u{ff35},host=c usage=3 3").await;
+
+        let db_schema = wbuf.catalog().db_schema(db_name).unwrap();
+        assert_eq!(vec![Arc::<str>::from("cpu")], db_schema.table_names());
+        assert_eq!(3, db_schema.table_definition("Cpu").unwrap().num_columns());
+
+        let ctx = IOxSessionContext::with_testing();
+        let batches = get_table_batches(wbuf, db_name, "CPU", &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+------+--------------------------------+-------+",
+                "| host | time                           | usage |",
+                "+------+--------------------------------+-------+",
+                "| a    | 1970-01-01T00:00:00.000000001Z | 1.0   |",
+                "| b    | 1970-01-01T00:00:00.000000002Z | 2.0   |",
+                "| c    | 1970-01-01T00:00:00.000000003Z | 3.0   |",
+                "+------+--------------------------------+-------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn column_defaults() {
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+        let db_name = "db";
+        let wbuf = &wbuf;
+        let write = |lp: &'static str| async move {
+            wbuf.write_lp(
+                NamespaceName::new(db_name).unwrap(),
+                lp,
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap()
+        };
+        write("cpu,host=a usage=1,idle=2,state=\"up\" 1\ncpu,host=b usage=3 2").await;
+
+        wbuf.set_column_default(db_name, "cpu", "idle", Some(FieldData::Float(0.0)))
+            .await
+            .unwrap();
+        wbuf.set_column_default(
+            db_name,
+            "cpu",
+            "state",
+            Some(FieldData::String("unknown".to_string())),
+        )
+        .await
+        .unwrap();
+        write("cpu,host=c usage=4 3").await;
+        wbuf.set_column_default(db_name, "cpu", "state", None)
+            .await
+            .unwrap();
+        write("cpu,host=d usage=5 4").await;
+
+        // only the rows written while the columns had defaults are given them:
+        let ctx = IOxSessionContext::with_testing();
+        let batches = get_table_batches(wbuf, db_name, "cpu", &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+------+------+---------+--------------------------------+-------+",
+                "| host | idle | state   | time                           | usage |",
+                "+------+------+---------+--------------------------------+-------+",
+                "| a    | 2.0  | up      | 1970-01-01T00:00:00.000000001Z | 1.0   |",
+                "| b    |      |         | 1970-01-01T00:00:00.000000002Z | 3.0   |",
+                "| c    | 0.0  | unknown | 1970-01-01T00:00:00.000000003Z | 4.0   |",
+                "| d    | 0.0  |         | 1970-01-01T00:00:00.000000004Z | 5.0   |",
+                "+------+------+---------+--------------------------------+-------+",
+            ],
+            &batches
+        );
+
+        // defaults must have the type of their column, which must be a field:
+        assert!(matches!(
+            wbuf.set_column_default(db_name, "cpu", "idle", Some(FieldData::Integer(0)))
+                .await,
+            Err(Error::CatalogUpdateError(
+                influxdb3_catalog::catalog::Error::FieldTypeMismatch { .. }
+            ))
+        ));
+        assert!(matches!(
+            wbuf.set_column_default(db_name, "cpu", "host", Some(FieldData::Tag("none".into())))
+                .await,
+            Err(Error::CatalogUpdateError(
+                influxdb3_catalog::catalog::Error::NotAFieldColumn { .. }
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn validate_lp_does_not_change_catalog() {
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+        let db_name = "db";
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            "cpu,host=a usage=1 1",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+        let catalog_before = wbuf.catalog().clone_inner();
+
+        let result = wbuf
+            .validate_lp(
+                NamespaceName::new(db_name).unwrap(),
+                "cpu,host=a usage=2,idle=1 2\n\
+                cpu,host=a usage=\"high\" 3\n\
+                mem,host=a free=1 4",
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        assert_eq!(2, result.line_count);
+        assert_eq!(
+            vec![(2, WriteLineErrorCategory::TypeConflict)],
+            result
+                .invalid_lines
+                .iter()
+                .map(|e| (e.line_number, e.error_category))
+                .collect::<Vec<_>>()
+        );
+        // the new field and table would be added:
+        assert!(matches!(
+            result.catalog_ops.as_slice(),
+            [CatalogOp::AddFields(_), CatalogOp::CreateTable(_)]
+        ));
+        // but the catalog is left as it was:
+        assert_eq!(catalog_before, wbuf.catalog().clone_inner());
+
+        // validating a write to a database that does not exist does not create it:
+        let result = wbuf
+            .validate_lp(
+                NamespaceName::new("new_db").unwrap(),
+                "cpu,host=a usage=1 1",
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        assert_eq!(1, result.line_count);
+        assert!(wbuf.catalog().db_schema("new_db").is_none());
+    }
+
+    #[tokio::test]
+    async fn write_lp_multi_uses_single_wal_flush() {
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+        let wal_sequence_before = wbuf.wal.last_wal_sequence_number().await;
+
+        let results = wbuf
+            .write_lp_multi(
+                vec![
+                    (NamespaceName::new("db_a").unwrap(), "cpu,host=a usage=1 1"),
+                    (
+                        NamespaceName::new("db_b").unwrap(),
+                        "mem,host=b free=2 2\nmem,host=b free=",
+                    ),
+                ],
+                Time::from_timestamp_nanos(0),
+                true,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![("db_a", 1, 0), ("db_b", 1, 1)],
+            results
+                .iter()
+                .map(|r| (r.db_name.as_str(), r.line_count, r.invalid_lines.len()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            wal_sequence_before.next(),
+            wbuf.wal.last_wal_sequence_number().await
+        );
+
+        assert_batches_eq!(
+            [
+                "+------+-------+--------------------------------+",
+                "| host | usage | time                           |",
+                "+------+-------+--------------------------------+",
+                "| a    | 1.0   | 1970-01-01T00:00:00.000000001Z |",
+                "+------+-------+--------------------------------+",
+            ],
+            &get_table_batches(&wbuf, "db_a", "cpu", &ctx).await
+        );
+        assert_batches_eq!(
+            [
+                "+------+------+--------------------------------+",
+                "| host | free | time                           |",
+                "+------+------+--------------------------------+",
+                "| b    | 2.0  | 1970-01-01T00:00:00.000000002Z |",
+                "+------+------+--------------------------------+",
+            ],
+            &get_table_batches(&wbuf, "db_b", "mem", &ctx).await
+        );
+    }
+
+    #[tokio::test]
+    async fn buffered_ack_write_does_not_wait_for_wal_flush() {
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig {
+                flush_interval: Duration::from_secs(2),
+                ..WalConfig::test_config()
+            },
+        )
+        .await;
+
+        // the write is acknowledged well before the next wal flush:
+        let result = tokio::time::timeout(
+            Duration::from_millis(500),
+            wbuf.write_lp_with_durability(
+                NamespaceName::new("db").unwrap(),
+                "cpu bar=1 1",
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+                Durability::BufferedAck,
+            ),
+        )
+        .await
+        .expect("buffered write should not wait for the wal flush")
+        .unwrap();
+        assert_eq!(1, result.line_count);
+        assert_eq!(WalAckLevel::Buffered, result.ack_level);
+
+        // and becomes queryable once the flush happens:
+        let batches = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let batches = get_table_batches(&wbuf, "db", "cpu", &ctx).await;
+                if !batches.is_empty() {
+                    break batches;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_batches_eq!(
+            [
+                "+-----+--------------------------------+",
+                "| bar | time                           |",
+                "+-----+--------------------------------+",
+                "| 1.0 | 1970-01-01T00:00:00.000000001Z |",
+                "+-----+--------------------------------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn write_rate_limit_rejects_writes_over_limit() {
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+        let db_name = NamespaceName::new("db").unwrap();
+        wbuf.write_lp(
+            db_name.clone(),
+            "cpu bar=1 1",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+        wbuf.set_write_rate_limit(
+            "db",
+            WriteRateLimit {
+                lines_per_second: Some(2),
+                bytes_per_second: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        wbuf.write_lp(
+            db_name.clone(),
+            "cpu bar=2 2\ncpu bar=3 3",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+        // the time provider does not advance, so the bucket is not refilled:
+        let err = wbuf
+            .write_lp(
+                db_name.clone(),
+                "cpu bar=4 4",
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::RateLimited { ref db_name, retry_after }
+                    if db_name == "db" && retry_after == Duration::from_millis(500)
+            ),
+            "unexpected error: {err}"
+        );
+        // as are writes of rows, and streamed writes:
+        let err = wbuf
+            .write_rows(
+                db_name.clone(),
+                vec![WriteRow {
+                    table_name: "cpu".to_string(),
+                    tags: vec![],
+                    fields: vec![("bar".to_string(), WriteFieldValue::F64(4.0))],
+                    timestamp: Some(4),
+                }],
+                Time::from_timestamp_nanos(0),
                 false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::RateLimited { .. }),
+            "unexpected error: {err}"
+        );
+        let err = wbuf
+            .write_lp_stream(
+                db_name.clone(),
+                Box::new(std::io::Cursor::new(b"cpu bar=4 4".to_vec())),
                 Time::from_timestamp_nanos(0),
+                false,
                 Precision::Nanosecond,
+                Durability::WalSync,
             )
-            .unwrap()
-            .convert_lines_to_buffer(Gen1Duration::new_5m());
-
-        let db = catalog.db_schema_by_id(&DbId::from(0)).unwrap();
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::RateLimited { .. }),
+            "unexpected error: {err}"
+        );
 
-        assert_eq!(db.tables.len(), 2);
-        // cpu table
-        assert_eq!(db.tables.get(&TableId::from(0)).unwrap().num_columns(), 3);
-        // foo table
-        assert_eq!(db.tables.get(&TableId::from(1)).unwrap().num_columns(), 2);
+        // other databases are not limited:
+        wbuf.write_lp(
+            NamespaceName::new("other").unwrap(),
+            "cpu bar=4 4",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
     }
 
-    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-    async fn writes_data_to_wal_and_is_queryable() {
-        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
-        let time_provider: Arc<dyn TimeProvider> =
-            Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
-        let (object_store, parquet_cache) =
-            test_cached_obj_store_and_oracle(object_store, Arc::clone(&time_provider));
-        let persister = Arc::new(Persister::new(Arc::clone(&object_store), "test_host"));
-        let catalog = Arc::new(persister.load_or_create_catalog().await.unwrap());
-        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
-        let write_buffer = WriteBufferImpl::new(
-            Arc::clone(&persister),
-            catalog,
-            last_cache,
-            Arc::clone(&time_provider),
-            crate::test_help::make_exec(),
+    #[tokio::test]
+    async fn set_mode_rejects_writes_and_drains_buffer() {
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
             WalConfig::test_config(),
-            Some(Arc::clone(&parquet_cache)),
+        )
+        .await;
+        let db_name = NamespaceName::new("db").unwrap();
+        wbuf.write_lp(
+            db_name.clone(),
+            "cpu bar=1 1",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
         )
         .await
         .unwrap();
-        let session_context = IOxSessionContext::with_testing();
-        let runtime_env = session_context.inner().runtime_env();
-        register_iox_object_store(runtime_env, "influxdb3", Arc::clone(&object_store));
 
-        let _ = write_buffer
+        wbuf.set_mode(BufferMode::ReadOnly).await;
+        assert_eq!(BufferMode::ReadOnly, wbuf.mode().await);
+        let err = wbuf
             .write_lp(
-                NamespaceName::new("foo").unwrap(),
-                "cpu bar=1 10",
-                Time::from_timestamp_nanos(123),
+                db_name.clone(),
+                "cpu bar=2 2",
+                Time::from_timestamp_nanos(0),
                 false,
                 Precision::Nanosecond,
             )
             .await
-            .unwrap();
-
-        let expected = [
-            "+-----+--------------------------------+",
-            "| bar | time                           |",
-            "+-----+--------------------------------+",
-            "| 1.0 | 1970-01-01T00:00:00.000000010Z |",
-            "+-----+--------------------------------+",
-        ];
-        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
-        assert_batches_eq!(&expected, &actual);
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::NoWriteInReadOnly),
+            "unexpected error: {err}"
+        );
+        assert!(wbuf.watch_persisted_snapshots().borrow().is_none());
+
+        // draining persists everything in the buffer before returning:
+        wbuf.set_mode(BufferMode::DrainAndPersist).await;
+        let snapshot = wbuf
+            .watch_persisted_snapshots()
+            .borrow()
+            .clone()
+            .expect("buffer should have been persisted");
+        assert_eq!(1, snapshot.row_count);
+
+        // writes are accepted again once switched back:
+        wbuf.set_mode(BufferMode::ReadWrite).await;
+        wbuf.write_lp(
+            db_name,
+            "cpu bar=3 3",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+    }
 
-        // do two more writes to trigger a snapshot
-        let _ = write_buffer
-            .write_lp(
-                NamespaceName::new("foo").unwrap(),
-                "cpu bar=2 20",
-                Time::from_timestamp_nanos(124),
+    #[tokio::test]
+    async fn shutdown_persists_buffer() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
+        )
+        .await;
+        for (i, lp) in ["cpu bar=1 1", "cpu bar=2 2"].into_iter().enumerate() {
+            wbuf.write_lp(
+                NamespaceName::new("db").unwrap(),
+                lp,
+                Time::from_timestamp_nanos(i as i64),
                 false,
                 Precision::Nanosecond,
             )
             .await
             .unwrap();
+        }
 
-        let _ = write_buffer
-            .write_lp(
-                NamespaceName::new("foo").unwrap(),
-                "cpu bar=3 30",
-                Time::from_timestamp_nanos(125),
+        wbuf.shutdown().await;
+        assert!(matches!(
+            wbuf.write_lp(
+                NamespaceName::new("db").unwrap(),
+                "cpu bar=3 3",
+                Time::from_timestamp_nanos(3),
                 false,
                 Precision::Nanosecond,
             )
-            .await;
+            .await,
+            Err(Error::NoWriteInReadOnly)
+        ));
 
-        // query the buffer and make sure we get the data back
-        let expected = [
-            "+-----+--------------------------------+",
-            "| bar | time                           |",
-            "+-----+--------------------------------+",
-            "| 1.0 | 1970-01-01T00:00:00.000000010Z |",
-            "| 2.0 | 1970-01-01T00:00:00.000000020Z |",
-            "| 3.0 | 1970-01-01T00:00:00.000000030Z |",
-            "+-----+--------------------------------+",
-        ];
-        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
-        assert_batches_eq!(&expected, &actual);
+        // only the empty WAL file that carried the final snapshot is left to replay:
+        let wal_files = obj_store
+            .list(Some(&ObjPath::from("test_host/wal")))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(1, wal_files.len());
 
-        // now load a new buffer from object storage
-        let catalog = Arc::new(persister.load_or_create_catalog().await.unwrap());
-        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
-        let write_buffer = WriteBufferImpl::new(
-            Arc::clone(&persister),
-            catalog,
-            last_cache,
-            Arc::clone(&time_provider),
-            crate::test_help::make_exec(),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(50),
-                snapshot_size: 100,
-            },
-            Some(Arc::clone(&parquet_cache)),
+        // and the data is loaded from the persisted snapshot on restart:
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
         )
-        .await
-        .unwrap();
+        .await;
+        let batches = get_table_batches(&wbuf, "db", "cpu", &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+-----+--------------------------------+",
+                "| bar | time                           |",
+                "+-----+--------------------------------+",
+                "| 1.0 | 1970-01-01T00:00:00.000000001Z |",
+                "| 2.0 | 1970-01-01T00:00:00.000000002Z |",
+                "+-----+--------------------------------+",
+            ],
+            &batches
+        );
+    }
 
-        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
-        assert_batches_eq!(&expected, &actual);
+    #[derive(Debug, Default)]
+    struct RecordingListener {
+        events: parking_lot::Mutex<Vec<String>>,
+    }
+
+    impl WriteEventListener for RecordingListener {
+        fn on_write_accepted(&self, write: &BufferedWriteRequest) {
+            self.events.lock().push(format!(
+                "accepted {} lines to {}",
+                write.line_count, write.db_name
+            ));
+        }
+
+        fn on_lines_rejected(&self, db_name: &str, lines: &[WriteLineError]) {
+            self.events
+                .lock()
+                .push(format!("rejected {} lines to {db_name}", lines.len()));
+        }
+
+        fn on_snapshot_persisted(&self, snapshot: &PersistedSnapshot) {
+            self.events
+                .lock()
+                .push(format!("persisted {} rows", snapshot.row_count));
+        }
     }
 
     #[tokio::test]
-    async fn last_cache_create_and_delete_is_durable() {
-        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    async fn event_listeners_are_notified() {
         let (wbuf, _ctx) = setup(
             Time::from_timestamp_nanos(0),
-            Arc::clone(&obj_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
         )
         .await;
-        let db_name = "db";
-        let db_id = DbId::from(0);
-        let tbl_name = "table";
-        let tbl_id = TableId::from(0);
-        let cache_name = "cache";
-        // Write some data to the current segment and update the catalog:
+        let listener = Arc::new(RecordingListener::default());
+        wbuf.register_event_listener(Arc::clone(&listener) as _);
+
         wbuf.write_lp(
-            NamespaceName::new(db_name).unwrap(),
-            format!("{tbl_name},t1=a f1=true").as_str(),
-            Time::from_timestamp(20, 0).unwrap(),
-            false,
+            NamespaceName::new("db").unwrap(),
+            "cpu bar=1 1\ncpu bar=",
+            Time::from_timestamp_nanos(0),
+            true,
             Precision::Nanosecond,
         )
         .await
         .unwrap();
-        // Create a last cache:
-        wbuf.create_last_cache(db_id, tbl_id, Some(cache_name), None, None, None, None)
-            .await
-            .unwrap();
-
-        // load a new write buffer to ensure its durable
-        let catalog = Arc::new(wbuf.persister.load_or_create_catalog().await.unwrap());
-        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
-        let wbuf = WriteBufferImpl::new(
-            Arc::clone(&wbuf.persister),
-            catalog,
-            last_cache,
-            Arc::clone(&wbuf.time_provider),
-            Arc::clone(&wbuf.buffer.executor),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
-            wbuf.parquet_cache.clone(),
+        wbuf.write_lp(
+            NamespaceName::new("db").unwrap(),
+            "cpu bar=",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
         )
         .await
-        .unwrap();
+        .unwrap_err();
+        wbuf.set_mode(BufferMode::DrainAndPersist).await;
 
-        let catalog_json = catalog_to_json(&wbuf.catalog);
-        insta::assert_json_snapshot!("catalog-immediately-after-last-cache-create",
-            catalog_json,
-            { ".instance_id" => "[uuid]" }
+        assert_eq!(
+            *listener.events.lock(),
+            [
+                "rejected 1 lines to db",
+                "accepted 1 lines to db",
+                "rejected 1 lines to db",
+                "persisted 1 rows",
+            ]
+        );
+    }
+
+    /// Renames the `host` tag, drops the `secret` field, derives a `usage_pct` field, routes
+    /// rows from `cpu_raw` to `cpu`, and drops rows from `debug`
+    #[derive(Debug)]
+    struct TestTransform;
+
+    impl WriteTransform for TestTransform {
+        fn transform(&self, mut row: WriteRow) -> Option<WriteRow> {
+            match row.table_name.as_str() {
+                "debug" => return None,
+                "cpu_raw" => row.table_name = "cpu".to_string(),
+                _ => (),
+            }
+            for (key, _) in row.tags.iter_mut() {
+                if key == "host" {
+                    *key = "hostname".to_string();
+                }
+            }
+            row.fields.retain(|(name, _)| name != "secret");
+            let usage = row.fields.iter().find_map(|(name, value)| match value {
+                WriteFieldValue::F64(v) if name == "usage" => Some(*v),
+                _ => None,
+            });
+            if let Some(usage) = usage {
+                row.fields
+                    .push(("usage_pct".to_string(), WriteFieldValue::F64(usage * 100.0)));
+            }
+            Some(row)
+        }
+    }
+
+    #[tokio::test]
+    async fn write_transform_is_applied_before_validation() {
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
+        )
+        .await;
+        wbuf.set_write_transform("db", Some(Arc::new(TestTransform)));
+
+        let result = wbuf
+            .write_lp(
+                NamespaceName::new("db").unwrap(),
+                "cpu_raw,host=a usage=0.5,secret=\"x\" 1\n\
+                debug msg=\"hello\" 2\n\
+                cpu,host=b usage=0.25 3",
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        // the dropped line is not counted:
+        assert_eq!(2, result.line_count);
+
+        let db_schema = wbuf.catalog().db_schema("db").unwrap();
+        assert!(db_schema.table_definition("cpu_raw").is_none());
+        assert!(db_schema.table_definition("debug").is_none());
+        let batches = get_table_batches(&wbuf, "db", "cpu", &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+----------+-------+-----------+--------------------------------+",
+                "| hostname | usage | usage_pct | time                           |",
+                "+----------+-------+-----------+--------------------------------+",
+                "| a        | 0.5   | 50.0      | 1970-01-01T00:00:00.000000001Z |",
+                "| b        | 0.25  | 25.0      | 1970-01-01T00:00:00.000000003Z |",
+                "+----------+-------+-----------+--------------------------------+",
+            ],
+            &batches
         );
 
-        // Do another write that will update the state of the catalog, specifically, the table
-        // that the last cache was created for, and add a new field to the table/cache `f2`:
+        // other databases are not transformed:
         wbuf.write_lp(
-            NamespaceName::new(db_name).unwrap(),
-            format!("{tbl_name},t1=a f1=false,f2=42i").as_str(),
-            Time::from_timestamp(30, 0).unwrap(),
+            NamespaceName::new("other").unwrap(),
+            "cpu_raw,host=a usage=0.5 1",
+            Time::from_timestamp_nanos(0),
             false,
             Precision::Nanosecond,
         )
         .await
         .unwrap();
+        assert!(wbuf
+            .catalog()
+            .db_schema("other")
+            .unwrap()
+            .table_definition("cpu_raw")
+            .is_some());
 
-        // and do another replay and verification
-        let catalog = Arc::new(wbuf.persister.load_or_create_catalog().await.unwrap());
-        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
-        let wbuf = WriteBufferImpl::new(
-            Arc::clone(&wbuf.persister),
-            catalog,
-            last_cache,
-            Arc::clone(&wbuf.time_provider),
-            Arc::clone(&wbuf.buffer.executor),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
-            wbuf.parquet_cache.clone(),
-        )
-        .await
-        .unwrap();
-
-        let catalog_json = catalog_to_json(&wbuf.catalog);
-        insta::assert_json_snapshot!(
-           "catalog-after-last-cache-create-and-new-field",
-           catalog_json,
-           { ".instance_id" => "[uuid]" }
-        );
-
-        // write a new data point to fill the cache
-        wbuf.write_lp(
-            NamespaceName::new(db_name).unwrap(),
-            format!("{tbl_name},t1=a f1=true,f2=53i").as_str(),
-            Time::from_timestamp(40, 0).unwrap(),
+        // nor are writes of v3 line protocol, whose series key is fixed by the table:
+        wbuf.write_lp_v3(
+            NamespaceName::new("db").unwrap(),
+            "cpu_raw,host/a usage=0.5 1",
+            Time::from_timestamp_nanos(0),
             false,
             Precision::Nanosecond,
         )
         .await
         .unwrap();
-
-        // Fetch record batches from the last cache directly:
-        let expected = [
-            "+----+------+----+----------------------+",
-            "| t1 | f1   | f2 | time                 |",
-            "+----+------+----+----------------------+",
-            "| a  | true | 53 | 1970-01-01T00:00:40Z |",
-            "+----+------+----+----------------------+",
-        ];
-        let actual = wbuf
-            .last_cache_provider()
-            .get_cache_record_batches(db_id, tbl_id, None, &[])
+        let table_def = wbuf
+            .catalog()
+            .db_schema("db")
             .unwrap()
+            .table_definition("cpu_raw")
             .unwrap();
-        assert_batches_eq!(&expected, &actual);
-        // Delete the last cache:
-        wbuf.delete_last_cache(db_id, tbl_id, cache_name)
+        assert!(table_def.column_name_to_id("host").is_some());
+        assert!(table_def.column_name_to_id("usage_pct").is_none());
+    }
+
+    #[tokio::test]
+    async fn rejected_lines_are_recorded() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let time_provider: Arc<dyn TimeProvider> =
+            Arc::new(MockProvider::new(Time::from_timestamp_nanos(10)));
+        let persister = Arc::new(Persister::new(Arc::clone(&obj_store), "test_host"));
+        let catalog = Arc::new(persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let wbuf = WriteBufferImpl::new(WriteBufferImplArgs {
+            record_rejected_lines: true,
+            ..WriteBufferImplArgs::new(
+                persister,
+                catalog,
+                last_cache,
+                time_provider,
+                crate::test_help::make_exec(),
+                WalConfig::test_config(),
+                None,
+            )
+        })
+        .await
+        .unwrap();
+        let ctx = IOxSessionContext::with_testing();
+
+        let result = wbuf
+            .write_lp(
+                NamespaceName::new("db").unwrap(),
+                "cpu,host=a val=1 1\ncpu,host=a val= 2\ncpu,host=b val= 3",
+                Time::from_timestamp_nanos(10),
+                true,
+                Precision::Nanosecond,
+            )
             .await
             .unwrap();
+        assert_eq!(2, result.invalid_lines.len());
 
-        // do another reload and verify it's gone
-        let catalog = Arc::new(wbuf.persister.load_or_create_catalog().await.unwrap());
-        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
-        let wbuf = WriteBufferImpl::new(
-            Arc::clone(&wbuf.persister),
-            catalog,
-            last_cache,
-            Arc::clone(&wbuf.time_provider),
-            Arc::clone(&wbuf.buffer.executor),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
-            wbuf.parquet_cache.clone(),
+        let batches = get_table_batches(&wbuf, "db", REJECTED_WRITES_TABLE_NAME, &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+----------+-------------+-------------------+-------------------------+--------------------------------+",
+                "| category | line_number | line              | error                   | time                           |",
+                "+----------+-------------+-------------------+-------------------------+--------------------------------+",
+                "| parse    | 2           | cpu,host=a val= 2 | No fields were provided | 1970-01-01T00:00:00.000000010Z |",
+                "| parse    | 3           | cpu,host=b val= 3 | No fields were provided | 1970-01-01T00:00:00.000000010Z |",
+                "+----------+-------------+-------------------+-------------------------+--------------------------------+",
+            ],
+            &batches
+        );
+    }
+
+    #[tokio::test]
+    async fn catalog_batches_are_applied_atomically() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
         )
+        .await;
+
+        // create a database with a table with an explicit schema, along with its retention period:
+        let db_id = DbId::new();
+        let table_id = TableId::new();
+        let retention_period = Some(Duration::from_secs(3600));
+        let batch = |ops: Vec<CatalogOp>| CatalogBatch {
+            database_id: db_id,
+            database_name: "db".into(),
+            time_ns: 0,
+            ops,
+        };
+        wbuf.apply_catalog_batch(batch(vec![
+            CatalogOp::CreateDatabase(DatabaseDefinition {
+                database_id: db_id,
+                database_name: "db".into(),
+            }),
+            create::create_table_op(
+                db_id,
+                "db",
+                table_id,
+                "cpu",
+                [
+                    create::field_def(ColumnId::new(), "host", FieldDataType::Tag),
+                    create::field_def(ColumnId::new(), "usage", FieldDataType::Float),
+                    create::field_def(ColumnId::new(), "time", FieldDataType::Timestamp),
+                ],
+            ),
+            create::set_retention_period_op(db_id, "db", retention_period),
+        ]))
         .await
         .unwrap();
-        let catalog_json = catalog_to_json(&wbuf.catalog);
-        insta::assert_json_snapshot!("catalog-immediately-after-last-cache-delete",
-            catalog_json,
-            { ".instance_id" => "[uuid]" }
+        let db_schema = wbuf.catalog().db_schema("db").unwrap();
+        assert_eq!(retention_period, db_schema.retention_period);
+        let table_def = db_schema.table_definition("cpu").unwrap();
+        let mut columns = table_def
+            .column_map
+            .right_values()
+            .map(|name| name.as_ref())
+            .collect::<Vec<_>>();
+        columns.sort_unstable();
+        assert_eq!(vec!["host", "time", "usage"], columns);
+
+        // none of the changes in a batch are applied if any of them is invalid:
+        assert!(matches!(
+            wbuf.apply_catalog_batch(batch(vec![
+                create::set_retention_period_op(db_id, "db", None),
+                create::add_fields_op(
+                    db_id,
+                    "db",
+                    TableId::new(),
+                    "not_a_table",
+                    [create::field_def(
+                        ColumnId::new(),
+                        "f",
+                        FieldDataType::Float
+                    )],
+                ),
+            ]))
+            .await,
+            Err(Error::CatalogUpdateError(_))
+        ));
+        assert_eq!(
+            retention_period,
+            wbuf.catalog().db_schema("db").unwrap().retention_period
         );
+
+        // the changes are replayed from the wal on restart:
+        drop(wbuf);
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
+        )
+        .await;
+        let db_schema = wbuf.catalog().db_schema("db").unwrap();
+        assert_eq!(retention_period, db_schema.retention_period);
+        assert!(db_schema.table_definition("cpu").is_some());
     }
 
-    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-    async fn returns_chunks_across_parquet_and_buffered_data() {
-        let (write_buffer, session_context) = setup(
+    #[tokio::test]
+    async fn rename_database_and_table() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, ctx) = setup(
             Time::from_timestamp_nanos(0),
-            Arc::new(InMemory::new()),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 2,
-            },
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
         )
         .await;
 
-        let _ = write_buffer
-            .write_lp(
-                NamespaceName::new("foo").unwrap(),
-                "cpu bar=1",
-                Time::from_timestamp(10, 0).unwrap(),
+        // persist one row, and leave another in the buffer:
+        for (i, lp) in ["cpu,host=a usage=1 1", "cpu,host=a usage=2 2"]
+            .into_iter()
+            .enumerate()
+        {
+            wbuf.write_lp(
+                NamespaceName::new("db").unwrap(),
+                lp,
+                Time::from_timestamp_nanos(0),
                 false,
                 Precision::Nanosecond,
             )
             .await
             .unwrap();
-
-        let expected = [
-            "+-----+----------------------+",
-            "| bar | time                 |",
-            "+-----+----------------------+",
-            "| 1.0 | 1970-01-01T00:00:10Z |",
-            "+-----+----------------------+",
-        ];
-        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
-        assert_batches_sorted_eq!(&expected, &actual);
-
-        let _ = write_buffer
-            .write_lp(
-                NamespaceName::new("foo").unwrap(),
-                "cpu bar=2",
-                Time::from_timestamp(65, 0).unwrap(),
-                false,
-                Precision::Nanosecond,
-            )
+            if i == 0 {
+                wbuf.set_mode(BufferMode::DrainAndPersist).await;
+                wbuf.set_mode(BufferMode::ReadWrite).await;
+            }
+        }
+        let (db_id, db_schema) = wbuf.catalog().db_schema_and_id("db").unwrap();
+        let table_id = db_schema.table_name_to_id("cpu").unwrap();
+        wbuf.create_last_cache(db_id, table_id, Some("cache"), None, None, None, None)
             .await
             .unwrap();
+        wbuf.write_lp(
+            NamespaceName::new("db").unwrap(),
+            "mem,host=a free=1 1",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
 
-        let expected = [
-            "+-----+----------------------+",
-            "| bar | time                 |",
-            "+-----+----------------------+",
-            "| 1.0 | 1970-01-01T00:00:10Z |",
-            "| 2.0 | 1970-01-01T00:01:05Z |",
-            "+-----+----------------------+",
-        ];
-        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
-        assert_batches_sorted_eq!(&expected, &actual);
+        wbuf.rename_table("db", "cpu", "cpu_renamed").await.unwrap();
+        wbuf.rename_database("db", "db_renamed").await.unwrap();
+        assert!(matches!(
+            wbuf.rename_table("db_renamed", "mem", "cpu_renamed").await,
+            Err(Error::CatalogUpdateError(_))
+        ));
+        assert!(matches!(
+            wbuf.rename_database("db", "db_2").await,
+            Err(Error::DbDoesNotExist)
+        ));
+        assert!(matches!(
+            wbuf.rename_table("db_renamed", "cpu", "cpu_2").await,
+            Err(Error::TableDoesNotExist)
+        ));
 
-        // trigger snapshot with a third write, creating parquet files
-        let _ = write_buffer
-            .write_lp(
-                NamespaceName::new("foo").unwrap(),
-                "cpu bar=3 147000000000",
-                Time::from_timestamp(147, 0).unwrap(),
-                false,
-                Precision::Nanosecond,
-            )
-            .await
-            .unwrap();
+        let check = |wbuf: WriteBufferImpl, ctx: IOxSessionContext| async move {
+            let db_schema = wbuf.catalog().db_schema("db_renamed").unwrap();
+            assert_eq!(db_id, db_schema.id);
+            let table_def = db_schema.table_definition("cpu_renamed").unwrap();
+            assert_eq!(table_id, table_def.table_id);
+            assert_eq!(
+                "cpu_renamed",
+                table_def.last_caches.get("cache").unwrap().table.as_ref()
+            );
+            assert!(wbuf.catalog().db_schema("db").is_none());
+            assert!(db_schema.table_definition("cpu").is_none());
+
+            // both the persisted and the buffered data is found under the new names:
+            let batches = get_table_batches(&wbuf, "db_renamed", "cpu_renamed", &ctx).await;
+            assert_batches_sorted_eq!(
+                [
+                    "+------+--------------------------------+-------+",
+                    "| host | time                           | usage |",
+                    "+------+--------------------------------+-------+",
+                    "| a    | 1970-01-01T00:00:00.000000001Z | 1.0   |",
+                    "| a    | 1970-01-01T00:00:00.000000002Z | 2.0   |",
+                    "+------+--------------------------------+-------+",
+                ],
+                &batches
+            );
+        };
+        check(wbuf, ctx).await;
 
-        // give the snapshot some time to persist in the background
-        let mut ticks = 0;
-        loop {
-            ticks += 1;
-            let persisted = write_buffer.persister.load_snapshots(1000).await.unwrap();
-            if !persisted.is_empty() {
-                assert_eq!(persisted.len(), 1);
-                assert_eq!(persisted[0].min_time, 10000000000);
-                assert_eq!(persisted[0].row_count, 2);
-                break;
-            } else if ticks > 10 {
-                panic!("not persisting");
-            }
-            tokio::time::sleep(Duration::from_millis(10)).await;
-        }
+        // the renames are replayed from the wal on restart:
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
+        )
+        .await;
+        check(wbuf, ctx).await;
+    }
 
-        let expected = [
-            "+-----+----------------------+",
-            "| bar | time                 |",
-            "+-----+----------------------+",
-            "| 3.0 | 1970-01-01T00:02:27Z |",
-            "| 2.0 | 1970-01-01T00:01:05Z |",
-            "| 1.0 | 1970-01-01T00:00:10Z |",
-            "+-----+----------------------+",
-        ];
-        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
-        assert_batches_sorted_eq!(&expected, &actual);
+    #[tokio::test]
+    async fn persisted_chunks_only_have_projected_columns() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
+        )
+        .await;
+        wbuf.write_lp(
+            NamespaceName::new("db").unwrap(),
+            "cpu,host=a,region=us usage=1,temp=2,load=3 1",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+        wbuf.set_mode(BufferMode::DrainAndPersist).await;
+        wbuf.set_mode(BufferMode::ReadWrite).await;
+
+        let db_schema = wbuf.catalog().db_schema("db").unwrap();
+        let table_def = db_schema.table_definition("cpu").unwrap();
+        let usage = table_def.schema.find_index_of("usage").unwrap();
+        let chunks = wbuf
+            .get_table_chunks("db", "cpu", &[], Some(&vec![usage]), &ctx.inner().state())
+            .unwrap();
+        assert_eq!(1, chunks.len());
+        // the columns of the primary key are always read, for deduplication:
+        let mut columns = chunks[0]
+            .schema()
+            .iter()
+            .map(|(_, field)| field.name().clone())
+            .collect::<Vec<_>>();
+        columns.sort();
+        assert_eq!(vec!["host", "region", "time", "usage"], columns);
+        // the file was sorted by the primary key when it was persisted:
+        let primary_key = table_def
+            .schema
+            .primary_key()
+            .into_iter()
+            .map(|column| column.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(Some(&SortKey::from(primary_key)), chunks[0].sort_key());
+
+        let chunks = wbuf
+            .get_table_chunks("db", "cpu", &[], None, &ctx.inner().state())
+            .unwrap();
+        assert_eq!(6, chunks[0].schema().len());
+    }
+
+    #[tokio::test]
+    async fn snapshot_persists_every_table() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
+        )
+        .await;
+        // more tables than are persisted at the same time:
+        let n_tables = DEFAULT_PERSIST_CONCURRENCY * 2 + 1;
+        let lp = (0..n_tables)
+            .map(|i| format!("table_{i},host=a usage={i} 1"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        wbuf.write_lp(
+            NamespaceName::new("db").unwrap(),
+            &lp,
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+        wbuf.set_mode(BufferMode::DrainAndPersist).await;
+        wbuf.set_mode(BufferMode::ReadWrite).await;
 
-        // now validate that buffered data and parquet data are all returned
-        let _ = write_buffer
-            .write_lp(
-                NamespaceName::new("foo").unwrap(),
-                "cpu bar=4",
-                Time::from_timestamp(250, 0).unwrap(),
+        for i in 0..n_tables {
+            let chunks = wbuf
+                .get_table_chunks("db", &format!("table_{i}"), &[], None, &ctx.inner().state())
+                .unwrap();
+            assert_eq!(1, chunks.len(), "table_{i} should have been persisted");
+        }
+    }
+
+    #[tokio::test]
+    async fn compaction_replaces_files_durably_and_deletes_them() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let wal_config = WalConfig::test_config();
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp(7_200, 0).unwrap(),
+            Arc::clone(&obj_store),
+            wal_config,
+        )
+        .await;
+        // the compactor plans its queries with the executor of the buffer:
+        register_iox_object_store(
+            wbuf.buffer.executor.new_context().inner().runtime_env(),
+            "influxdb3",
+            Arc::clone(&obj_store),
+        );
+        // each write is persisted to its own file, and the second overwrites a row of the first:
+        for lp in [
+            "cpu,host=a usage=1 10",
+            "cpu,host=a usage=2 10\ncpu,host=b usage=3 20",
+        ] {
+            wbuf.write_lp(
+                NamespaceName::new("db").unwrap(),
+                lp,
+                Time::from_timestamp(7_200, 0).unwrap(),
                 false,
-                Precision::Nanosecond,
+                Precision::Second,
             )
             .await
             .unwrap();
+            wbuf.set_mode(BufferMode::DrainAndPersist).await;
+            wbuf.set_mode(BufferMode::ReadWrite).await;
+        }
+        let db_schema = wbuf.catalog().db_schema("db").unwrap();
+        let table_id = db_schema.table_name_to_id("cpu").unwrap();
+        let gen1_files = wbuf.persisted_files.get_files(db_schema.id, table_id);
+        assert_eq!(2, gen1_files.len());
+
+        // a window with fewer files than the minimum is not compacted:
+        let config = CompactionConfig {
+            gen2_duration: Duration::from_secs(3_600),
+            min_files: 3,
+        };
+        assert_eq!(0, wbuf.compact(config).await.unwrap());
+        let config = CompactionConfig {
+            min_files: 2,
+            ..config
+        };
+        assert_eq!(1, wbuf.compact(config).await.unwrap());
 
         let expected = [
-            "+-----+----------------------+",
-            "| bar | time                 |",
-            "+-----+----------------------+",
-            "| 3.0 | 1970-01-01T00:02:27Z |",
-            "| 4.0 | 1970-01-01T00:04:10Z |",
-            "| 2.0 | 1970-01-01T00:01:05Z |",
-            "| 1.0 | 1970-01-01T00:00:10Z |",
-            "+-----+----------------------+",
+            "+------+----------------------+-------+",
+            "| host | time                 | usage |",
+            "+------+----------------------+-------+",
+            "| a    | 1970-01-01T00:00:10Z | 2.0   |",
+            "| b    | 1970-01-01T00:00:20Z | 3.0   |",
+            "+------+----------------------+-------+",
         ];
-        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
-        assert_batches_sorted_eq!(&expected, &actual);
+        let files = wbuf.persisted_files.get_files(db_schema.id, table_id);
+        assert_eq!(1, files.len());
+        assert_eq!(2, files[0].row_count);
+        let batches = get_table_batches(&wbuf, "db", "cpu", &ctx).await;
+        assert_batches_sorted_eq!(expected, &batches);
 
-        let actual = get_table_batches(&write_buffer, "foo", "cpu", &session_context).await;
-        assert_batches_sorted_eq!(&expected, &actual);
-        // and now replay in a new write buffer and attempt to write
-        let catalog = Arc::new(
-            write_buffer
-                .persister
-                .load_or_create_catalog()
-                .await
-                .unwrap(),
-        );
+        // the gen2 file still replaces the gen1 files after a restart:
+        let catalog = Arc::new(wbuf.persister.load_or_create_catalog().await.unwrap());
         let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
-        let write_buffer = WriteBufferImpl::new(
-            Arc::clone(&write_buffer.persister),
+        let wbuf = WriteBufferImpl::new(WriteBufferImplArgs::new(
+            Arc::clone(&wbuf.persister),
             catalog,
             last_cache,
-            Arc::clone(&write_buffer.time_provider),
-            Arc::clone(&write_buffer.buffer.executor),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 2,
-            },
-            write_buffer.parquet_cache.clone(),
+            Arc::clone(&wbuf.time_provider),
+            Arc::clone(&wbuf.buffer.executor),
+            wal_config,
+            wbuf.parquet_cache.clone(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            files,
+            wbuf.persisted_files.get_files(db_schema.id, table_id)
+        );
+        let batches = get_table_batches(&wbuf, "db", "cpu", &ctx).await;
+        assert_batches_sorted_eq!(expected, &batches);
+
+        // the replaced files are deleted by the next compaction:
+        for file in &gen1_files {
+            assert!(obj_store
+                .head(&ObjPath::from(file.path.as_str()))
+                .await
+                .is_ok());
+        }
+        assert_eq!(0, wbuf.compact(config).await.unwrap());
+        for file in &gen1_files {
+            assert!(matches!(
+                obj_store.head(&ObjPath::from(file.path.as_str())).await,
+                Err(object_store::Error::NotFound { .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn persisted_files_are_loaded_from_their_index() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let wal_config = WalConfig::test_config();
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp(7_200, 0).unwrap(),
+            Arc::clone(&obj_store),
+            wal_config,
         )
+        .await;
+        // nothing has been snapshot yet, so there is nothing to index:
+        assert_eq!(None, wbuf.persist_persisted_files_index().await.unwrap());
+
+        let write_and_persist = |lp: &'static str| {
+            let wbuf = &wbuf;
+            async move {
+                wbuf.write_lp(
+                    NamespaceName::new("db").unwrap(),
+                    lp,
+                    Time::from_timestamp(7_200, 0).unwrap(),
+                    false,
+                    Precision::Second,
+                )
+                .await
+                .unwrap();
+                wbuf.set_mode(BufferMode::DrainAndPersist).await;
+                wbuf.set_mode(BufferMode::ReadWrite).await;
+            }
+        };
+        write_and_persist("cpu,host=a usage=1 10").await;
+        write_and_persist("cpu,host=b usage=2 20").await;
+        assert_eq!(Some(2), wbuf.persist_persisted_files_index().await.unwrap());
+        // the files of the snapshots taken after the index was written are loaded from them:
+        write_and_persist("cpu,host=c usage=3 30").await;
+
+        let db_schema = wbuf.catalog().db_schema("db").unwrap();
+        let table_id = db_schema.table_name_to_id("cpu").unwrap();
+        let mut files = wbuf.persisted_files.get_files(db_schema.id, table_id);
+        files.sort_by_key(|file| file.id);
+        assert_eq!(3, files.len());
+        let snapshots = wbuf.persister.load_snapshots(1).await.unwrap();
+
+        let catalog = Arc::new(wbuf.persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let restarted = WriteBufferImpl::new(WriteBufferImplArgs::new(
+            Arc::clone(&wbuf.persister),
+            catalog,
+            last_cache,
+            Arc::clone(&wbuf.time_provider),
+            Arc::clone(&wbuf.buffer.executor),
+            wal_config,
+            wbuf.parquet_cache.clone(),
+        ))
         .await
         .unwrap();
-        let ctx = IOxSessionContext::with_testing();
-        let runtime_env = ctx.inner().runtime_env();
+        let mut restarted_files = restarted.persisted_files.get_files(db_schema.id, table_id);
+        restarted_files.sort_by_key(|file| file.id);
+        assert_eq!(files, restarted_files);
+        // the index of the restarted write buffer is as of the most recent snapshot:
+        let index = restarted.persisted_files.index().unwrap();
+        assert_eq!(
+            snapshots[0].snapshot_sequence_number,
+            index.snapshot.snapshot_sequence_number
+        );
+        assert_eq!(snapshots[0].next_file_id, index.snapshot.next_file_id);
+    }
+
+    #[tokio::test]
+    async fn export_table_merges_buffered_and_persisted_rows() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp(7_200, 0).unwrap(),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
+        )
+        .await;
         register_iox_object_store(
-            runtime_env,
+            wbuf.buffer.executor.new_context().inner().runtime_env(),
             "influxdb3",
-            write_buffer.persister.object_store(),
+            Arc::clone(&obj_store),
         );
-
-        // verify the data is still there
-        let actual = get_table_batches(&write_buffer, "foo", "cpu", &ctx).await;
-        assert_batches_sorted_eq!(&expected, &actual);
-
-        // now write some new data
-        let _ = write_buffer
-            .write_lp(
-                NamespaceName::new("foo").unwrap(),
-                "cpu bar=5",
-                Time::from_timestamp(300, 0).unwrap(),
-                false,
-                Precision::Nanosecond,
+        let write = |lp: &'static str| {
+            let wbuf = &wbuf;
+            async move {
+                wbuf.write_lp(
+                    NamespaceName::new("db").unwrap(),
+                    lp,
+                    Time::from_timestamp(7_200, 0).unwrap(),
+                    false,
+                    Precision::Second,
+                )
+                .await
+                .unwrap();
+            }
+        };
+        write("cpu,host=b usage=2 20\ncpu,host=a usage=1 10").await;
+        wbuf.set_mode(BufferMode::DrainAndPersist).await;
+        wbuf.set_mode(BufferMode::ReadWrite).await;
+        // the buffered row of host a replaces the persisted one:
+        write("cpu,host=c usage=4 30\ncpu,host=a usage=3 10").await;
+
+        let export_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let target = |path: &str| ExportTarget {
+            object_store: Arc::clone(&export_store),
+            path: ObjPath::from(path),
+        };
+        let csv_target = target("export/cpu.csv");
+        let row_count = wbuf
+            .export_table(
+                "db",
+                "cpu",
+                TimeRange::default(),
+                ExportFormat::Csv,
+                &csv_target,
             )
             .await
             .unwrap();
+        assert_eq!(3, row_count);
+        let csv = export_store
+            .get(&csv_target.path)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let csv = String::from_utf8(csv.to_vec()).unwrap();
+        let mut lines = csv.lines();
+        let header = lines.next().unwrap().split(',').collect::<Vec<_>>();
+        let host = header.iter().position(|column| *column == "host").unwrap();
+        let usage = header.iter().position(|column| *column == "usage").unwrap();
+        let rows = lines
+            .map(|line| {
+                let values = line.split(',').collect::<Vec<_>>();
+                (
+                    values[host].to_string(),
+                    values[usage].parse::<f64>().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("a".to_string(), 3.0),
+                ("b".to_string(), 2.0),
+                ("c".to_string(), 4.0)
+            ],
+            rows
+        );
 
-        // and write more to force another snapshot
-        let _ = write_buffer
-            .write_lp(
-                NamespaceName::new("foo").unwrap(),
-                "cpu bar=6",
-                Time::from_timestamp(330, 0).unwrap(),
-                false,
-                Precision::Nanosecond,
+        // only the rows in the time range are exported:
+        let parquet_target = target("export/cpu.parquet");
+        let row_count = wbuf
+            .export_table(
+                "db",
+                "cpu",
+                TimeRange {
+                    min: 15_000_000_000,
+                    max: i64::MAX,
+                },
+                ExportFormat::Parquet,
+                &parquet_target,
             )
             .await
             .unwrap();
+        assert_eq!(2, row_count);
+        let data = export_store
+            .get(&parquet_target.path)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(data)
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            2,
+            batches.iter().map(|batch| batch.num_rows()).sum::<usize>()
+        );
 
-        let expected = [
-            "+-----+----------------------+",
-            "| bar | time                 |",
-            "+-----+----------------------+",
-            "| 1.0 | 1970-01-01T00:00:10Z |",
-            "| 2.0 | 1970-01-01T00:01:05Z |",
-            "| 3.0 | 1970-01-01T00:02:27Z |",
-            "| 4.0 | 1970-01-01T00:04:10Z |",
-            "| 5.0 | 1970-01-01T00:05:00Z |",
-            "| 6.0 | 1970-01-01T00:05:30Z |",
-            "+-----+----------------------+",
-        ];
-        let actual = get_table_batches(&write_buffer, "foo", "cpu", &ctx).await;
-        assert_batches_sorted_eq!(&expected, &actual);
+        assert!(matches!(
+            wbuf.export_table(
+                "db",
+                "mem",
+                TimeRange::default(),
+                ExportFormat::Csv,
+                &csv_target
+            )
+            .await,
+            Err(Error::TableDoesNotExist)
+        ));
     }
 
-    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-    async fn catalog_snapshots_only_if_updated() {
-        let (write_buffer, _ctx) = setup(
-            Time::from_timestamp_nanos(0),
-            Arc::new(InMemory::new()),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(5),
-                snapshot_size: 1,
-            },
-        )
-        .await;
-
-        let db_name = "foo";
-        // do three writes to force a snapshot
-        do_writes(
-            db_name,
-            &write_buffer,
-            &[
-                TestWrite {
-                    lp: "cpu bar=1",
-                    time_seconds: 10,
-                },
-                TestWrite {
-                    lp: "cpu bar=2",
-                    time_seconds: 20,
-                },
-                TestWrite {
-                    lp: "cpu bar=3",
-                    time_seconds: 30,
-                },
-            ],
-        )
-        .await;
-
-        verify_catalog_count(2, write_buffer.persister.object_store()).await;
-        verify_snapshot_count(1, &write_buffer.persister).await;
-
-        // only another two writes are needed to trigger a snapshot, because there is still one
-        // WAL period left from before:
-        do_writes(
-            db_name,
-            &write_buffer,
-            &[
-                TestWrite {
-                    lp: "cpu bar=4",
-                    time_seconds: 40,
-                },
-                TestWrite {
-                    lp: "cpu bar=5",
-                    time_seconds: 50,
-                },
-            ],
-        )
-        .await;
-
-        // verify the catalog didn't get persisted, but a snapshot did
-        verify_catalog_count(2, write_buffer.persister.object_store()).await;
-        verify_snapshot_count(2, &write_buffer.persister).await;
+    #[tokio::test]
+    async fn parquet_files_are_imported_into_a_table() {
+        use arrow::array::{Float64Array, StringArray, TimestampMillisecondArray};
+        use arrow::datatypes::{DataType, Field, Schema as ArrowSchema, TimeUnit};
 
-        // and finally, do two more, with a catalog update, forcing persistence
-        do_writes(
-            db_name,
-            &write_buffer,
-            &[
-                TestWrite {
-                    lp: "cpu bar=6,asdf=true",
-                    time_seconds: 60,
-                },
-                TestWrite {
-                    lp: "cpu bar=7,asdf=true",
-                    time_seconds: 70,
-                },
-            ],
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp(7_200, 0).unwrap(),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
         )
         .await;
+        register_iox_object_store(
+            ctx.inner().runtime_env(),
+            "influxdb3",
+            Arc::clone(&obj_store),
+        );
+        wbuf.write_lp(
+            NamespaceName::new("db").unwrap(),
+            "cpu,host=a usage=1 10",
+            Time::from_timestamp(7_200, 0).unwrap(),
+            false,
+            Precision::Second,
+        )
+        .await
+        .unwrap();
 
-        verify_catalog_count(3, write_buffer.persister.object_store()).await;
-        verify_snapshot_count(3, &write_buffer.persister).await;
-    }
+        // the file has the tag as a string, and the time in milliseconds:
+        let source: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let put_file = |path: &'static str, usage_column: &'static str| {
+            let source = Arc::clone(&source);
+            async move {
+                let schema = Arc::new(ArrowSchema::new(vec![
+                    Field::new("host", DataType::Utf8, true),
+                    Field::new(usage_column, DataType::Float64, true),
+                    Field::new(
+                        "time",
+                        DataType::Timestamp(TimeUnit::Millisecond, None),
+                        false,
+                    ),
+                ]));
+                let batch = RecordBatch::try_new(
+                    Arc::clone(&schema),
+                    vec![
+                        Arc::new(StringArray::from(vec!["b", "a"])),
+                        Arc::new(Float64Array::from(vec![2.0, 5.0])),
+                        Arc::new(TimestampMillisecondArray::from(vec![20_000, 5_000])),
+                    ],
+                )
+                .unwrap();
+                let mut writer =
+                    parquet::arrow::ArrowWriter::try_new(vec![], schema, None).unwrap();
+                writer.write(&batch).unwrap();
+                let bytes = writer.into_inner().unwrap();
+                source
+                    .put(&ObjPath::from(path), bytes.into())
+                    .await
+                    .unwrap();
+                ObjPath::from(path)
+            }
+        };
+        let good = put_file("backfill/good.parquet", "usage").await;
+        let bad = put_file("backfill/bad.parquet", "idle").await;
 
-    /// Check that when a WriteBuffer is initialized with existing snapshot files, that newly
-    /// generated snapshot files use the next sequence number.
-    #[tokio::test]
-    async fn new_snapshots_use_correct_sequence() {
-        // set up a local file system object store:
-        let object_store: Arc<dyn ObjectStore> =
-            Arc::new(LocalFileSystem::new_with_prefix(test_helpers::tmp_dir().unwrap()).unwrap());
+        assert!(matches!(
+            wbuf.import_parquet_files("db", "cpu", Arc::clone(&source), &[bad])
+                .await,
+            Err(Error::Import(import::Error::UnknownColumn { .. }))
+        ));
 
-        // create a snapshot file that will be loaded on initialization of the write buffer:
-        // Set ParquetFileId to a non zero number for the snapshot
-        ParquetFileId::from(500).set_next_id();
-        let prev_snapshot_seq = SnapshotSequenceNumber::new(42);
-        let prev_snapshot = PersistedSnapshot::new(
-            "test_host".to_string(),
-            prev_snapshot_seq,
-            WalFileSequenceNumber::new(0),
-            CatalogSequenceNumber::new(0),
+        let last_snapshot_sequence_number = wbuf.wal.last_snapshot_sequence_number().await;
+        let last_wal_file = wbuf.wal.last_wal_sequence_number().await;
+        let imported = wbuf
+            .import_parquet_files("db", "cpu", Arc::clone(&source), &[good])
+            .await
+            .unwrap();
+        assert_eq!(1, imported.len());
+        assert_eq!(2, imported[0].row_count);
+        assert_eq!(5_000_000_000, imported[0].min_time);
+        assert_eq!(20_000_000_000, imported[0].max_time);
+        assert_eq!("a", imported[0].column_ranges["host"].min);
+        assert_eq!("b", imported[0].column_ranges["host"].max);
+
+        // the imported rows are queryable along with the buffered one:
+        let batches = get_table_batches(&wbuf, "db", "cpu", &ctx).await;
+        assert_eq!(
+            3,
+            batches.iter().map(|batch| batch.num_rows()).sum::<usize>()
         );
-        let snapshot_json = serde_json::to_vec(&prev_snapshot).unwrap();
-        // set ParquetFileId to be 0 so that we can make sure when it's loaded from the
-        // snapshot that it becomes the expected number
-        ParquetFileId::from(0).set_next_id();
 
-        // put the snapshot file in object store:
-        object_store
-            .put(
-                &SnapshotInfoFilePath::new("test_host", prev_snapshot_seq),
-                PutPayload::from_bytes(Bytes::from(snapshot_json)),
-            )
-            .await
+        // the snapshot of the import is persisted, and the snapshots of the WAL come after it:
+        wbuf.set_mode(BufferMode::DrainAndPersist).await;
+        wbuf.set_mode(BufferMode::ReadWrite).await;
+        let snapshots = wbuf.persister.load_snapshots(2).await.unwrap();
+        assert_eq!(
+            last_snapshot_sequence_number.next().next(),
+            snapshots[0].snapshot_sequence_number
+        );
+        assert_eq!(
+            last_snapshot_sequence_number.next(),
+            snapshots[1].snapshot_sequence_number
+        );
+        let db_id = wbuf.catalog().db_name_to_id("db").unwrap();
+        let table_id = wbuf
+            .catalog()
+            .db_schema("db")
+            .unwrap()
+            .table_name_to_id("cpu")
             .unwrap();
+        assert_eq!(imported, snapshots[1].databases[&db_id].tables[&table_id]);
+        // the snapshot of the import is of the last WAL file that was written before it:
+        assert_eq!(last_wal_file, snapshots[1].wal_file_sequence_number);
+    }
 
-        // setup the write buffer:
-        let (wbuf, _ctx) = setup(
+    #[tokio::test]
+    async fn follower_replicates_the_writes_and_snapshots_of_the_leader() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (leader, ctx) = setup(
             Time::from_timestamp_nanos(0),
-            Arc::clone(&object_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(5),
-                snapshot_size: 1,
-            },
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
         )
         .await;
+        let write = |lp: &'static str| {
+            leader.write_lp(
+                NamespaceName::new("db").unwrap(),
+                lp,
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+        };
+        write("cpu bar=1 1").await.unwrap();
 
-        // Assert that loading the snapshots sets ParquetFileId to the correct id number
-        assert_eq!(ParquetFileId::new().as_u64(), 500);
-
-        // there should be one snapshot already, i.e., the one we created above:
-        verify_snapshot_count(1, &wbuf.persister).await;
-        // there is only one initial catalog so far:
-        verify_catalog_count(1, object_store.clone()).await;
-
-        // do three writes to force a new snapshot
-        wbuf.write_lp(
-            NamespaceName::new("foo").unwrap(),
-            "cpu bar=1",
-            Time::from_timestamp(10, 0).unwrap(),
-            false,
-            Precision::Nanosecond,
+        let time_provider: Arc<dyn TimeProvider> =
+            Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let persister = Arc::new(Persister::new(Arc::clone(&obj_store), "test_host"));
+        let catalog = Arc::new(persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let follower = WriteBufferImpl::new_follower(
+            WriteBufferImplArgs::new(
+                persister,
+                catalog,
+                last_cache,
+                time_provider,
+                crate::test_help::make_exec(),
+                WalConfig::test_config(),
+                None,
+            ),
+            ReplicationConfig {
+                poll_interval: Duration::from_millis(10),
+            },
         )
         .await
         .unwrap();
-        wbuf.write_lp(
-            NamespaceName::new("foo").unwrap(),
-            "cpu bar=2",
-            Time::from_timestamp(20, 0).unwrap(),
-            false,
-            Precision::Nanosecond,
+
+        // the writes before the follower started are replayed from the leader's wal:
+        let row_count = |batches: Vec<RecordBatch>| -> usize {
+            batches.iter().map(|batch| batch.num_rows()).sum()
+        };
+        assert_eq!(
+            1,
+            row_count(get_table_batches(&follower, "db", "cpu", &ctx).await)
+        );
+        assert_eq!(BufferMode::ReadOnly, follower.mode().await);
+        assert!(matches!(
+            follower
+                .write_lp(
+                    NamespaceName::new("db").unwrap(),
+                    "cpu bar=3 3",
+                    Time::from_timestamp_nanos(0),
+                    false,
+                    Precision::Nanosecond,
+                )
+                .await,
+            Err(Error::NoWriteInReadOnly)
+        ));
+
+        // and the snapshot that the leader persists of the later writes is loaded:
+        write("cpu bar=2 2").await.unwrap();
+        leader.set_mode(BufferMode::DrainAndPersist).await;
+        let mut snapshots = follower.watch_persisted_snapshots();
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            snapshots.wait_for(|snapshot| snapshot.is_some()),
         )
         .await
+        .expect("follower should load the snapshot of the leader")
         .unwrap();
-        wbuf.write_lp(
-            NamespaceName::new("foo").unwrap(),
-            "cpu bar=3",
-            Time::from_timestamp(30, 0).unwrap(),
-            false,
-            Precision::Nanosecond,
+        let (db_id, db_schema) = follower.catalog().db_schema_and_id("db").unwrap();
+        let table_id = db_schema.table_name_to_id("cpu").unwrap();
+        assert_eq!(
+            1,
+            follower.persisted_files().get_files(db_id, table_id).len()
+        );
+        assert_eq!(
+            2,
+            row_count(get_table_batches(&follower, "db", "cpu", &ctx).await)
+        );
+    }
+
+    #[tokio::test]
+    async fn queries_the_files_persisted_by_other_writers() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (writer, ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
         )
+        .await;
+        async fn write(write_buffer: &WriteBufferImpl, lp: &str) -> Result<BufferedWriteRequest> {
+            write_buffer
+                .write_lp(
+                    NamespaceName::new("db").unwrap(),
+                    lp,
+                    Time::from_timestamp_nanos(0),
+                    false,
+                    Precision::Nanosecond,
+                )
+                .await
+        }
+        write(&writer, "cpu,host=a bar=1 1").await.unwrap();
+        writer.set_mode(BufferMode::DrainAndPersist).await;
+        writer.set_mode(BufferMode::ReadWrite).await;
+
+        let time_provider: Arc<dyn TimeProvider> =
+            Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let persister = Arc::new(Persister::new(Arc::clone(&obj_store), "query_host"));
+        let catalog = Arc::new(persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let query_host = WriteBufferImpl::new(WriteBufferImplArgs {
+            other_writers: vec!["test_host".to_string()],
+            ..WriteBufferImplArgs::new(
+                persister,
+                catalog,
+                last_cache,
+                time_provider,
+                crate::test_help::make_exec(),
+                WalConfig::test_config(),
+                None,
+            )
+        })
         .await
         .unwrap();
+        // the table is only queried on a host that has it in its catalog too:
+        write(&query_host, "cpu,host=b bar=2 2").await.unwrap();
 
-        // Check that there are now 2 snapshots:
-        verify_snapshot_count(2, &wbuf.persister).await;
-        // Check that the next sequence number is used for the new snapshot:
+        let row_count = |batches: Vec<RecordBatch>| -> usize {
+            batches.iter().map(|batch| batch.num_rows()).sum()
+        };
         assert_eq!(
-            prev_snapshot_seq.next(),
-            wbuf.wal.last_snapshot_sequence_number().await
+            2,
+            row_count(get_table_batches(&query_host, "db", "cpu", &ctx).await)
         );
-        // There should be a catalog now, since the above writes updated the catalog
-        verify_catalog_count(2, object_store.clone()).await;
-        // Check the catalog sequence number in the latest snapshot is correct:
-        let persisted_snapshot_bytes = object_store
-            .get(&SnapshotInfoFilePath::new(
-                "test_host",
-                prev_snapshot_seq.next(),
-            ))
-            .await
-            .unwrap()
-            .bytes()
-            .await
-            .unwrap();
-        let persisted_snapshot =
-            serde_json::from_slice::<PersistedSnapshot>(&persisted_snapshot_bytes).unwrap();
-        // NOTE: it appears that writes which create a new db increment the catalog sequence twice.
-        // This is likely due to the catalog sequence being incremented first for the db creation and
-        // then again for the updates to the table written to. Hence the sequence number is 2 here.
-        // If we manage to make it so that scenario only increments the catalog sequence once, then
-        // this assertion may fail:
+
+        // the files that the writer persists later are queried once they have been refreshed:
+        write(&writer, "cpu,host=a bar=3 3").await.unwrap();
+        writer.set_mode(BufferMode::DrainAndPersist).await;
         assert_eq!(
-            CatalogSequenceNumber::new(2),
-            persisted_snapshot.catalog_sequence_number
+            2,
+            row_count(get_table_batches(&query_host, "db", "cpu", &ctx).await)
+        );
+        assert_eq!(1, query_host.refresh_other_writers().await.unwrap());
+        assert_eq!(
+            3,
+            row_count(get_table_batches(&query_host, "db", "cpu", &ctx).await)
         );
+        assert_eq!(0, query_host.refresh_other_writers().await.unwrap());
     }
 
     #[tokio::test]
-    async fn next_id_is_correct_number() {
-        // set up a local file system object store:
-        let object_store: Arc<dyn ObjectStore> =
-            Arc::new(LocalFileSystem::new_with_prefix(test_helpers::tmp_dir().unwrap()).unwrap());
-
-        let prev_snapshot_seq = SnapshotSequenceNumber::new(42);
-        let mut prev_snapshot = PersistedSnapshot::new(
-            "test_host".to_string(),
-            prev_snapshot_seq,
-            WalFileSequenceNumber::new(0),
-            CatalogSequenceNumber::new(0),
-        );
+    async fn writes_are_refused_once_the_lease_is_taken_over() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let time_provider = Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let persister = Arc::new(Persister::new(Arc::clone(&obj_store), "test_host"));
+        let catalog = Arc::new(persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let lease_duration = Duration::from_secs(30);
+        let write_buffer = WriteBufferImpl::new(WriteBufferImplArgs {
+            lease_duration: Some(lease_duration),
+            ..WriteBufferImplArgs::new(
+                persister,
+                catalog,
+                last_cache,
+                Arc::clone(&time_provider) as _,
+                crate::test_help::make_exec(),
+                WalConfig::test_config(),
+                None,
+            )
+        })
+        .await
+        .unwrap();
+        let write = || {
+            write_buffer.write_lp(
+                NamespaceName::new("db").unwrap(),
+                "cpu,host=a bar=1 1",
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+        };
+        write().await.unwrap();
 
-        assert_eq!(prev_snapshot.next_file_id.as_u64(), 0);
-        assert_eq!(prev_snapshot.next_table_id.as_u32(), 0);
-        assert_eq!(prev_snapshot.next_db_id.as_u32(), 0);
+        // another server takes over the lease once it expires:
+        time_provider.set(Time::from_timestamp_nanos(0) + lease_duration * 2);
+        let _lease = lease::Lease::acquire(
+            Arc::clone(&obj_store),
+            "test_host",
+            lease_duration,
+            Arc::clone(&time_provider) as _,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(write().await, Err(Error::NotLeader)));
+    }
 
-        for _ in 0..=5 {
-            prev_snapshot.add_parquet_file(
-                DbId::from(0),
-                TableId::from(0),
-                ParquetFile {
-                    id: ParquetFileId::new(),
-                    path: "file/path2".into(),
-                    size_bytes: 20,
-                    row_count: 1,
-                    chunk_time: 1,
-                    min_time: 0,
-                    max_time: 1,
-                },
-            );
-        }
+    #[tokio::test]
+    async fn downsampling_tasks_aggregate_persisted_data() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, ctx) = setup(
+            Time::from_timestamp(7_200, 0).unwrap(),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
+        )
+        .await;
+        register_iox_object_store(
+            wbuf.buffer.executor.new_context().inner().runtime_env(),
+            "influxdb3",
+            Arc::clone(&obj_store),
+        );
+        let write_and_persist = |lp: &'static str| {
+            let wbuf = &wbuf;
+            async move {
+                wbuf.write_lp(
+                    NamespaceName::new("db").unwrap(),
+                    lp,
+                    Time::from_timestamp(7_200, 0).unwrap(),
+                    false,
+                    Precision::Second,
+                )
+                .await
+                .unwrap();
+                wbuf.set_mode(BufferMode::DrainAndPersist).await;
+                wbuf.set_mode(BufferMode::ReadWrite).await;
+            }
+        };
+        write_and_persist(
+            "cpu,host=a usage=1,state=\"ok\" 10\n\
+            cpu,host=a usage=3 20\n\
+            cpu,host=b usage=5 70",
+        )
+        .await;
 
-        assert_eq!(prev_snapshot.databases.len(), 1);
-        let files = prev_snapshot.databases[&DbId::from(0)].tables[&TableId::from(0)].clone();
+        assert!(matches!(
+            wbuf.create_downsampling_task(
+                "db",
+                "cpu_1m",
+                "cpu",
+                "cpu",
+                Duration::from_secs(60),
+                DownsamplingAggregate::Mean,
+            )
+            .await,
+            Err(Error::InvalidDownsamplingTask(_))
+        ));
+        wbuf.create_downsampling_task(
+            "db",
+            "cpu_1m",
+            "cpu",
+            "cpu_1m",
+            Duration::from_secs(60),
+            DownsamplingAggregate::Mean,
+        )
+        .await
+        .unwrap();
+        // the string field is left out of the means:
+        assert_eq!(2, wbuf.run_downsampling_tasks().await.unwrap());
+        let batches = get_table_batches(&wbuf, "db", "cpu_1m", &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+------+----------------------+-------+",
+                "| host | time                 | usage |",
+                "+------+----------------------+-------+",
+                "| a    | 1970-01-01T00:00:00Z | 2.0   |",
+                "| b    | 1970-01-01T00:01:00Z | 5.0   |",
+                "+------+----------------------+-------+",
+            ],
+            &batches
+        );
+        // the rows written by the task are not persisted yet, and there are no new files of the
+        // source table:
+        assert_eq!(0, wbuf.run_downsampling_tasks().await.unwrap());
+
+        // a window that is written to again is aggregated again, replacing its rows:
+        write_and_persist("cpu,host=b usage=7 100\ncpu,host=a usage=9 130").await;
+        assert_eq!(2, wbuf.run_downsampling_tasks().await.unwrap());
+        let batches = get_table_batches(&wbuf, "db", "cpu_1m", &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+------+----------------------+-------+",
+                "| host | time                 | usage |",
+                "+------+----------------------+-------+",
+                "| a    | 1970-01-01T00:00:00Z | 2.0   |",
+                "| a    | 1970-01-01T00:02:00Z | 9.0   |",
+                "| b    | 1970-01-01T00:01:00Z | 6.0   |",
+                "+------+----------------------+-------+",
+            ],
+            &batches
+        );
 
-        // Assert that all of the files are smaller than the next_file_id field
-        // and that their index corresponds to the order they were added in
-        assert_eq!(prev_snapshot.next_file_id.as_u64(), 6);
-        assert_eq!(files.len(), 6);
-        for (i, file) in files.iter().enumerate() {
-            assert_ne!(file.id, ParquetFileId::from(6));
-            assert!(file.id.as_u64() < 6);
-            assert_eq!(file.id.as_u64(), i as u64);
-        }
+        wbuf.delete_downsampling_task("db", "cpu_1m").await.unwrap();
+        assert!(matches!(
+            wbuf.delete_downsampling_task("db", "cpu_1m").await,
+            Err(Error::DownsamplingTaskDoesNotExist(_))
+        ));
+    }
 
-        let snapshot_json = serde_json::to_vec(&prev_snapshot).unwrap();
+    #[tokio::test]
+    async fn unreferenced_parquet_files_are_collected() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        // the files in the object store were modified long before this:
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp(4_102_444_800, 0).unwrap(),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
+        )
+        .await;
+        wbuf.write_lp(
+            NamespaceName::new("db").unwrap(),
+            "cpu,host=a usage=1 10",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Second,
+        )
+        .await
+        .unwrap();
+        wbuf.set_mode(BufferMode::DrainAndPersist).await;
+        let db_schema = wbuf.catalog().db_schema("db").unwrap();
+        let table_id = db_schema.table_name_to_id("cpu").unwrap();
+        let persisted = wbuf.persisted_files.get_files(db_schema.id, table_id);
+        assert_eq!(1, persisted.len());
+        let orphan = ObjPath::from("test_host/dbs/db-0/cpu-0/1970-01-01/00-00/orphan.parquet");
+        obj_store
+            .put(&orphan, PutPayload::from_static(b"orphan"))
+            .await
+            .unwrap();
 
-        // put the snapshot file in object store:
-        object_store
-            .put(
-                &SnapshotInfoFilePath::new("test_host", prev_snapshot_seq),
-                PutPayload::from_bytes(Bytes::from(snapshot_json)),
-            )
+        // files modified within the minimum age are left alone:
+        let found = wbuf
+            .collect_parquet_garbage(Duration::from_secs(200 * 365 * 24 * 3600), false)
             .await
             .unwrap();
+        assert!(found.is_empty());
+
+        // a dry run only finds the file:
+        let min_age = Duration::from_secs(3_600);
+        let found = wbuf.collect_parquet_garbage(min_age, true).await.unwrap();
+        assert_eq!(vec![orphan.clone()], found);
+        assert!(obj_store.head(&orphan).await.is_ok());
+
+        let found = wbuf.collect_parquet_garbage(min_age, false).await.unwrap();
+        assert_eq!(vec![orphan.clone()], found);
+        assert!(matches!(
+            obj_store.head(&orphan).await,
+            Err(object_store::Error::NotFound { .. })
+        ));
+        let persisted_path = ObjPath::from(persisted[0].path.as_str());
+        assert!(obj_store.head(&persisted_path).await.is_ok());
+    }
 
-        // setup the write buffer:
-        let (_wbuf, _ctx) = setup(
+    #[tokio::test]
+    async fn queries_are_not_blocked_by_writes_to_other_tables() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, ctx) = setup(
             Time::from_timestamp_nanos(0),
-            Arc::clone(&object_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(5),
-                snapshot_size: 1,
-            },
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
         )
         .await;
+        wbuf.write_lp(
+            NamespaceName::new("db").unwrap(),
+            "cpu,host=a usage=1 1\nmem,host=a free=2 1",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
 
-        // Test that the next_file_id has been set properly
-        assert_eq!(ParquetFileId::next_id().as_u64(), 6);
+        let db_schema = wbuf.catalog().db_schema("db").unwrap();
+        let mem_id = db_schema.table_name_to_id("mem").unwrap();
+        let mem_buffer = wbuf.buffer.table_buffer(db_schema.id, mem_id).unwrap();
+        // hold the lock of the mem buffer, as a write to it would, while querying cpu:
+        let _mem_guard = mem_buffer.write();
+        let chunks = wbuf
+            .get_table_chunks("db", "cpu", &[], None, &ctx.inner().state())
+            .unwrap();
+        assert_eq!(1, chunks.len());
     }
 
-    /// This is the reproducer for [#25277][see]
-    ///
-    /// [see]: https://github.com/influxdata/influxdb/issues/25277
     #[tokio::test]
-    async fn writes_not_dropped_on_snapshot() {
+    async fn delete_undelete_and_hard_delete_table() {
         let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
-        let (wbuf, _) = setup(
+        let (wbuf, ctx) = setup(
             Time::from_timestamp_nanos(0),
             Arc::clone(&obj_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
+            WalConfig::test_config(),
         )
         .await;
+        let write = |wbuf: &WriteBufferImpl, lp: &'static str| {
+            wbuf.write_lp(
+                NamespaceName::new("db").unwrap(),
+                lp,
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+        };
 
-        let db_name = "coffee_shop";
-        let tbl_name = "menu";
-
-        // do some writes to get a snapshot:
-        do_writes(
-            db_name,
-            &wbuf,
-            &[
-                TestWrite {
-                    lp: format!("{tbl_name},name=espresso price=2.50"),
-                    time_seconds: 1,
-                },
-                TestWrite {
-                    lp: format!("{tbl_name},name=americano price=3.00"),
-                    time_seconds: 2,
-                },
-                TestWrite {
-                    lp: format!("{tbl_name},name=latte price=4.50"),
-                    time_seconds: 3,
-                },
+        // persist one row, and leave another in the buffer:
+        write(&wbuf, "cpu,host=a usage=1 1").await.unwrap();
+        wbuf.set_mode(BufferMode::DrainAndPersist).await;
+        wbuf.set_mode(BufferMode::ReadWrite).await;
+        write(&wbuf, "cpu,host=a usage=2 2").await.unwrap();
+        let (db_id, db_schema) = wbuf.catalog().db_schema_and_id("db").unwrap();
+        let table_id = db_schema.table_name_to_id("cpu").unwrap();
+
+        wbuf.delete_table("db", "cpu").await.unwrap();
+        let db_schema = wbuf.catalog().db_schema("db").unwrap();
+        assert!(db_schema.table_definition("cpu").is_none());
+        assert!(wbuf
+            .get_table_chunks("db", "cpu", &[], None, &ctx.inner().state())
+            .is_err());
+
+        // all of the data is back once the table is undeleted:
+        wbuf.undelete_table("db", "cpu").await.unwrap();
+        let batches = get_table_batches(&wbuf, "db", "cpu", &ctx).await;
+        assert_batches_sorted_eq!(
+            [
+                "+------+--------------------------------+-------+",
+                "| host | time                           | usage |",
+                "+------+--------------------------------+-------+",
+                "| a    | 1970-01-01T00:00:00.000000001Z | 1.0   |",
+                "| a    | 1970-01-01T00:00:00.000000002Z | 2.0   |",
+                "+------+--------------------------------+-------+",
             ],
-        )
-        .await;
+            &batches
+        );
 
-        // wait for snapshot to be created:
-        verify_snapshot_count(1, &wbuf.persister).await;
+        // tables are only hard deleted once the grace period has passed:
+        let files = wbuf.parquet_files(db_id, table_id);
+        assert_eq!(1, files.len());
+        wbuf.delete_table("db", "cpu").await.unwrap();
+        assert_eq!(
+            0,
+            wbuf.hard_delete_expired_tables(Duration::from_secs(3600))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            1,
+            wbuf.hard_delete_expired_tables(Duration::ZERO)
+                .await
+                .unwrap()
+        );
+        assert!(matches!(
+            wbuf.undelete_table("db", "cpu").await,
+            Err(Error::TableDoesNotExist)
+        ));
+        assert!(wbuf.parquet_files(db_id, table_id).is_empty());
+        let db_schema = wbuf.catalog().db_schema("db").unwrap();
+        assert!(db_schema.table_definition_by_id(&table_id).is_none());
+        assert!(matches!(
+            obj_store.head(&ObjPath::from(files[0].path.as_str())).await,
+            Err(object_store::Error::NotFound { .. })
+        ));
+
+        // a write to the same name creates a new table, without the old data:
+        write(&wbuf, "cpu,host=b usage=3 3").await.unwrap();
+        let expected = [
+            "+------+--------------------------------+-------+",
+            "| host | time                           | usage |",
+            "+------+--------------------------------+-------+",
+            "| b    | 1970-01-01T00:00:00.000000003Z | 3.0   |",
+            "+------+--------------------------------+-------+",
+        ];
+        let batches = get_table_batches(&wbuf, "db", "cpu", &ctx).await;
+        assert_batches_sorted_eq!(expected, &batches);
 
-        // Now drop the write buffer, and create a new one that replays:
         drop(wbuf);
         let (wbuf, ctx) = setup(
             Time::from_timestamp_nanos(0),
             Arc::clone(&obj_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
+            WalConfig::test_config(),
         )
         .await;
-
-        // Get the record batches from replayed buffer:
-        let batches = get_table_batches(&wbuf, db_name, tbl_name, &ctx).await;
-        assert_batches_sorted_eq!(
-            [
-                "+-----------+-------+----------------------+",
-                "| name      | price | time                 |",
-                "+-----------+-------+----------------------+",
-                "| americano | 3.0   | 1970-01-01T00:00:02Z |",
-                "| espresso  | 2.5   | 1970-01-01T00:00:01Z |",
-                "| latte     | 4.5   | 1970-01-01T00:00:03Z |",
-                "+-----------+-------+----------------------+",
-            ],
-            &batches
-        );
+        let batches = get_table_batches(&wbuf, "db", "cpu", &ctx).await;
+        assert_batches_sorted_eq!(expected, &batches);
+        // the files of the hard deleted table are not loaded from the snapshot that has them:
+        assert!(wbuf.parquet_files(db_id, table_id).is_empty());
     }
 
     #[tokio::test]
-    async fn writes_not_dropped_on_larger_snapshot_size() {
-        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
-        let (wbuf, _) = setup(
+    async fn create_table_with_explicit_schema() {
+        let (wbuf, _ctx) = setup(
             Time::from_timestamp_nanos(0),
-            Arc::clone(&obj_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 2,
-            },
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
         )
         .await;
 
-        let db_name = "coffee_shop";
-        let tbl_name = "menu";
+        // the database is created along with the table:
+        wbuf.create_table(
+            "db",
+            "cpu",
+            &["host"],
+            &[("usage", FieldDataType::Integer)],
+            false,
+        )
+        .await
+        .unwrap();
+        wbuf.create_table(
+            "db",
+            "mem",
+            &["host"],
+            &[("free", FieldDataType::Float)],
+            true,
+        )
+        .await
+        .unwrap();
+        let db_schema = wbuf.catalog().db_schema("db").unwrap();
+        assert!(!db_schema.table_definition("cpu").unwrap().is_v3());
+        assert!(db_schema.table_definition("mem").unwrap().is_v3());
 
-        // Do six writes to trigger a snapshot
-        do_writes(
-            db_name,
-            &wbuf,
-            &[
-                TestWrite {
-                    lp: format!("{tbl_name},name=espresso,type=drink price=2.50"),
-                    time_seconds: 1,
-                },
-                TestWrite {
-                    lp: format!("{tbl_name},name=americano,type=drink price=3.00"),
-                    time_seconds: 2,
-                },
-                TestWrite {
-                    lp: format!("{tbl_name},name=latte,type=drink price=4.50"),
-                    time_seconds: 3,
-                },
-                TestWrite {
-                    lp: format!("{tbl_name},name=croissant,type=food price=5.50"),
-                    time_seconds: 4,
-                },
-                TestWrite {
-                    lp: format!("{tbl_name},name=muffin,type=food price=4.50"),
-                    time_seconds: 5,
-                },
-                TestWrite {
-                    lp: format!("{tbl_name},name=biscotto,type=food price=3.00"),
-                    time_seconds: 6,
-                },
+        // with the schema locked, writes must match the declared schema:
+        wbuf.set_schema_mode("db", SchemaMode::Strict)
+            .await
+            .unwrap();
+        let result = wbuf
+            .write_lp(
+                NamespaceName::new("db").unwrap(),
+                "cpu,host=a usage=1i 1\n\
+                 cpu,host=a usage=1.5 2\n\
+                 cpu,region=us usage=2i 3",
+                Time::from_timestamp_nanos(0),
+                true,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        assert_eq!(1, result.line_count);
+        assert_eq!(
+            vec![
+                WriteLineErrorCategory::TypeConflict,
+                WriteLineErrorCategory::SchemaLocked
             ],
+            result
+                .invalid_lines
+                .iter()
+                .map(|e| e.error_category)
+                .collect::<Vec<_>>()
+        );
+
+        assert!(matches!(
+            wbuf.create_table("db", "cpu", &[], &[("usage", FieldDataType::Float)], false)
+                .await,
+            Err(Error::TableAlreadyExists { .. })
+        ));
+        for (tags, fields) in [
+            (vec!["host"], vec![("host", FieldDataType::Float)]),
+            (vec!["time"], vec![]),
+            (vec![], vec![("region", FieldDataType::Tag)]),
+        ] {
+            assert!(matches!(
+                wbuf.create_table("db", "disk", &tags, &fields, false).await,
+                Err(Error::InvalidTableDefinition(_))
+            ));
+        }
+        assert!(wbuf
+            .catalog()
+            .db_schema("db")
+            .unwrap()
+            .table_definition("disk")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn catalog_changes_are_recorded_in_audit_log() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&obj_store),
+            WalConfig::test_config(),
         )
         .await;
+        for lp in [
+            "cpu,host=a val=1 1",
+            "cpu,host=a val=2 2",
+            "cpu,host=a val=3,other=4 3",
+        ] {
+            wbuf.write_lp(
+                NamespaceName::new("db").unwrap(),
+                lp,
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        }
+        wbuf.set_retention_period("db", Some(Duration::from_secs(3600)))
+            .await
+            .unwrap();
 
-        verify_snapshot_count(1, &wbuf.persister).await;
+        let operations = |entries: Vec<AuditLogEntry>| {
+            entries
+                .iter()
+                .map(|e| (e.database_name.to_string(), e.operation(), e.source))
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            ("db".to_string(), "create_table", AuditSource::Write),
+            ("db".to_string(), "add_fields", AuditSource::Write),
+            ("db".to_string(), "set_retention_period", AuditSource::Api),
+        ];
+        assert_eq!(expected, operations(wbuf.audit_log().await.unwrap()));
 
-        // Drop the write buffer, and create a new one that replays:
+        // entries are appended to the existing log after a restart:
         drop(wbuf);
-        let (wbuf, ctx) = setup(
+        let (wbuf, _ctx) = setup(
             Time::from_timestamp_nanos(0),
             Arc::clone(&obj_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 2,
-            },
+            WalConfig::test_config(),
         )
-        .await;
-
-        // Get the record batches from replyed buffer:
-        let batches = get_table_batches(&wbuf, db_name, tbl_name, &ctx).await;
-        assert_batches_sorted_eq!(
-            [
-                "+-----------+-------+----------------------+-------+",
-                "| name      | price | time                 | type  |",
-                "+-----------+-------+----------------------+-------+",
-                "| americano | 3.0   | 1970-01-01T00:00:02Z | drink |",
-                "| biscotto  | 3.0   | 1970-01-01T00:00:06Z | food  |",
-                "| croissant | 5.5   | 1970-01-01T00:00:04Z | food  |",
-                "| espresso  | 2.5   | 1970-01-01T00:00:01Z | drink |",
-                "| latte     | 4.5   | 1970-01-01T00:00:03Z | drink |",
-                "| muffin    | 4.5   | 1970-01-01T00:00:05Z | food  |",
-                "+-----------+-------+----------------------+-------+",
-            ],
-            &batches
-        );
+        .await;
+        wbuf.set_retention_period("db", None).await.unwrap();
+        let mut expected = expected;
+        expected.push(("db".to_string(), "set_retention_period", AuditSource::Api));
+        assert_eq!(expected, operations(wbuf.audit_log().await.unwrap()));
     }
 
     #[tokio::test]
-    async fn writes_not_dropped_with_future_writes() {
+    async fn enforce_retention_removes_expired_persisted_files() {
         let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
-        let (wbuf, _) = setup(
-            Time::from_timestamp_nanos(0),
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp(1_000, 0).unwrap(),
             Arc::clone(&obj_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
+            WalConfig::test_config(),
         )
         .await;
+        let db_name = "db";
+        wbuf.write_lp(
+            NamespaceName::new(db_name).unwrap(),
+            "cpu bar=1 990",
+            Time::from_timestamp(1_000, 0).unwrap(),
+            false,
+            Precision::Second,
+        )
+        .await
+        .unwrap();
+        let db_schema = wbuf.catalog().db_schema(db_name).unwrap();
+        let table_id = db_schema.table_name_to_id("cpu").unwrap();
+        for (min_time, max_time) in [(0, 100), (800_000_000_000, 950_000_000_000)] {
+            obj_store
+                .put(&ObjPath::from(format!("file-{min_time}")), "data".into())
+                .await
+                .unwrap();
+            wbuf.persisted_files.add_file(
+                db_schema.id,
+                table_id,
+                ParquetFile {
+                    id: ParquetFileId::new(),
+                    path: format!("file-{min_time}"),
+                    size_bytes: 0,
+                    row_count: 0,
+                    chunk_time: min_time,
+                    min_time,
+                    max_time,
+                    indexes: Default::default(),
+                    column_ranges: Default::default(),
+                    sort_key: Default::default(),
+                },
+            );
+        }
 
-        let db_name = "coffee_shop";
-        let tbl_name = "menu";
+        // nothing is removed without a retention period:
+        assert_eq!(0, wbuf.enforce_retention().await.unwrap());
 
-        // do some writes to get a snapshot:
-        do_writes(
-            db_name,
-            &wbuf,
-            &[
-                TestWrite {
-                    lp: format!("{tbl_name},name=espresso price=2.50"),
-                    time_seconds: 1,
-                },
-                // This write is way out in the future, so as to be outside the normal
-                // range for a snapshot:
-                TestWrite {
-                    lp: format!("{tbl_name},name=americano price=3.00"),
-                    time_seconds: 20_000,
-                },
-                // This write will trigger the snapshot:
-                TestWrite {
-                    lp: format!("{tbl_name},name=latte price=4.50"),
-                    time_seconds: 3,
+        // now is 1,000s, so with a 100s retention period anything older than 900s has expired:
+        wbuf.set_retention_period(db_name, Some(Duration::from_secs(100)))
+            .await
+            .unwrap();
+        assert_eq!(1, wbuf.enforce_retention().await.unwrap());
+        let files = wbuf.persisted_files.get_files(db_schema.id, table_id);
+        assert_eq!(1, files.len());
+        assert_eq!(950_000_000_000, files[0].max_time);
+
+        // the expired file is only deleted by the next run, so queries that have it can finish:
+        let expired_path = ObjPath::from("file-0");
+        assert!(obj_store.head(&expired_path).await.is_ok());
+        assert_eq!(0, wbuf.enforce_retention().await.unwrap());
+        assert!(matches!(
+            obj_store.head(&expired_path).await,
+            Err(object_store::Error::NotFound { .. })
+        ));
+        assert!(obj_store
+            .head(&ObjPath::from("file-800000000000"))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn buffer_over_mem_limit_forces_snapshot() {
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let time_provider: Arc<dyn TimeProvider> =
+            Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let persister = Arc::new(Persister::new(Arc::clone(&obj_store), "test_host"));
+        let catalog = Arc::new(persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        // the snapshot size is large enough that a snapshot would not normally happen for
+        // the writes in this test:
+        let wbuf = WriteBufferImpl::new(WriteBufferImplArgs {
+            buffer_mem_limit_bytes: Some(1),
+            buffer_full_timeout: Some(Duration::from_secs(10)),
+            ..WriteBufferImplArgs::new(
+                Arc::clone(&persister),
+                catalog,
+                last_cache,
+                time_provider,
+                crate::test_help::make_exec(),
+                WalConfig {
+                    gen1_duration: Gen1Duration::new_1m(),
+                    max_write_buffer_size: 100,
+                    flush_interval: Duration::from_millis(10),
+                    snapshot_size: 100,
+                    compression: Default::default(),
+                    max_file_size_bytes: None,
+                    retention: Default::default(),
+                    ack_level: Default::default(),
+                    snapshot_triggers: Default::default(),
+                    handoff_max_bytes: None,
                 },
-            ],
+                None,
+            )
+        })
+        .await
+        .unwrap();
+
+        let db_name = NamespaceName::new("db").unwrap();
+        wbuf.write_lp(
+            db_name.clone(),
+            "cpu,host=a usage=1 10",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
         )
-        .await;
+        .await
+        .unwrap();
 
-        // Wait for snapshot to be created:
-        verify_snapshot_count(1, &wbuf.persister).await;
+        // the buffer is over its limit, so a snapshot is forced and the data persisted:
+        verify_snapshot_count(1, &persister).await;
 
-        // Now drop the write buffer, and create a new one that replays:
-        drop(wbuf);
+        // the next write waits for the buffer to drain and then succeeds:
+        wbuf.write_lp(
+            db_name,
+            "cpu,host=a usage=2 20",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_lp_stream_buffers_in_batches() {
         let (wbuf, ctx) = setup(
             Time::from_timestamp_nanos(0),
-            Arc::clone(&obj_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
         )
         .await;
-
-        // Get the record batches from replayed buffer:
-        let batches = get_table_batches(&wbuf, db_name, tbl_name, &ctx).await;
-        assert_batches_sorted_eq!(
-            [
-                "+-----------+-------+----------------------+",
-                "| name      | price | time                 |",
-                "+-----------+-------+----------------------+",
-                "| americano | 3.0   | 1970-01-01T05:33:20Z |",
-                "| espresso  | 2.5   | 1970-01-01T00:00:01Z |",
-                "| latte     | 4.5   | 1970-01-01T00:00:03Z |",
-                "+-----------+-------+----------------------+",
-            ],
-            &batches
+        let wal_sequence_before = wbuf.wal.last_wal_sequence_number().await;
+
+        // enough lines for more than one batch, with an invalid line at the end:
+        let n_lines = 200_000;
+        let mut lp = (0..n_lines)
+            .map(|i| format!("cpu,host=a usage={i} {i}\n"))
+            .collect::<String>();
+        assert!(lp.len() > STREAM_WRITE_BATCH_BYTES);
+        lp.push_str("cpu,host=a usage=");
+
+        let result = wbuf
+            .write_lp_stream(
+                NamespaceName::new("foo").unwrap(),
+                Box::new(std::io::Cursor::new(lp.into_bytes())),
+                Time::from_timestamp_nanos(0),
+                true,
+                Precision::Nanosecond,
+                Durability::WalSync,
+            )
+            .await
+            .unwrap();
+        assert_eq!(n_lines, result.line_count);
+        assert_eq!(
+            vec![n_lines + 1],
+            result
+                .invalid_lines
+                .iter()
+                .map(|e| e.line_number)
+                .collect::<Vec<_>>()
+        );
+        // each batch is written to the WAL separately:
+        assert_eq!(
+            wal_sequence_before.next().next(),
+            wbuf.wal.last_wal_sequence_number().await
         );
+
+        let rows: usize = get_table_batches(&wbuf, "foo", "cpu", &ctx)
+            .await
+            .iter()
+            .map(|b| b.num_rows())
+            .sum();
+        assert_eq!(n_lines, rows);
     }
 
     #[tokio::test]
-    async fn notifies_watchers_of_snapshot() {
-        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
-        let (wbuf, _) = setup(
+    async fn cardinality_limits_reject_new_series_and_tables() {
+        let (wbuf, _ctx) = setup(
             Time::from_timestamp_nanos(0),
-            Arc::clone(&obj_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
         )
         .await;
+        let db_name = NamespaceName::new("db").unwrap();
+        // series written before the limits are set are not counted:
+        wbuf.write_lp(
+            db_name.clone(),
+            "cpu,host=a usage=1 1",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
+        )
+        .await
+        .unwrap();
+        wbuf.set_cardinality_limits(
+            db_name.as_str(),
+            CardinalityLimits {
+                max_tables: Some(1),
+                max_series_per_table: Some(2),
+            },
+        )
+        .await
+        .unwrap();
 
-        let mut watcher = wbuf.watch_persisted_snapshots();
-        watcher.mark_changed();
-
-        let db_name = "coffee_shop";
-        let tbl_name = "menu";
+        // the series of a write that fails are not counted either:
+        assert!(matches!(
+            wbuf.write_lp(
+                db_name.clone(),
+                "cpu,host=x usage=1 1\n\
+                cpu,host=y usage=1 1\n\
+                cpu,host=z usage=1 1",
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+            .await,
+            Err(Error::CardinalityLimitExceeded(_))
+        ));
 
-        // do some writes to get a snapshot:
-        do_writes(
-            db_name,
-            &wbuf,
-            &[
-                TestWrite {
-                    lp: format!("{tbl_name},name=espresso price=2.50"),
-                    time_seconds: 1,
-                },
-                TestWrite {
-                    lp: format!("{tbl_name},name=americano price=3.00"),
-                    time_seconds: 2,
-                },
-                TestWrite {
-                    lp: format!("{tbl_name},name=latte price=4.50"),
-                    time_seconds: 3,
-                },
-            ],
-        )
-        .await;
+        let result = wbuf
+            .write_lp(
+                db_name.clone(),
+                "cpu,host=a usage=1 1\n\
+                cpu,host=b usage=1 1\n\
+                cpu,host=a usage=2 2\n\
+                cpu,host=c usage=1 1\n\
+                mem,host=a used=1 1",
+                Time::from_timestamp_nanos(0),
+                true,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        assert_eq!(3, result.line_count);
+        assert_eq!(
+            vec![4, 5],
+            result
+                .invalid_lines
+                .iter()
+                .map(|e| e.line_number)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(&2),
+            result
+                .invalid_line_counts()
+                .get(&WriteLineErrorCategory::CardinalityLimit)
+        );
 
-        // wait for snapshot to be created:
-        verify_snapshot_count(1, &wbuf.persister).await;
-        watcher.changed().await.unwrap();
-        let snapshot = watcher.borrow();
-        assert!(snapshot.is_some(), "watcher should be notified of snapshot");
+        // without accepting partial writes, the whole write fails:
+        assert!(matches!(
+            wbuf.write_lp(
+                db_name,
+                "cpu,host=d usage=1 1",
+                Time::from_timestamp_nanos(0),
+                false,
+                Precision::Nanosecond,
+            )
+            .await,
+            Err(Error::CardinalityLimitExceeded(_))
+        ));
     }
 
     #[tokio::test]
-    async fn test_db_id_is_persisted_and_updated() {
-        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
-        let (wbuf, _) = setup(
+    async fn cardinality_limits_apply_to_v3_writes() {
+        let (wbuf, _ctx) = setup(
             Time::from_timestamp_nanos(0),
-            Arc::clone(&obj_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
         )
         .await;
-        let db_name = "coffee_shop";
-        let tbl_name = "menu";
-
-        // do some writes to get a snapshot:
-        do_writes(
-            db_name,
-            &wbuf,
-            &[
-                TestWrite {
-                    lp: format!("{tbl_name},name=espresso price=2.50"),
-                    time_seconds: 1,
-                },
-                // This write is way out in the future, so as to be outside the normal
-                // range for a snapshot:
-                TestWrite {
-                    lp: format!("{tbl_name},name=americano price=3.00"),
-                    time_seconds: 20_000,
-                },
-                // This write will trigger the snapshot:
-                TestWrite {
-                    lp: format!("{tbl_name},name=latte price=4.50"),
-                    time_seconds: 3,
-                },
-            ],
+        let db_name = NamespaceName::new("db").unwrap();
+        wbuf.write_lp_v3(
+            db_name.clone(),
+            "cpu,host/a usage=1i 1",
+            Time::from_timestamp_nanos(0),
+            false,
+            Precision::Nanosecond,
         )
-        .await;
+        .await
+        .unwrap();
+        wbuf.set_cardinality_limits(
+            db_name.as_str(),
+            CardinalityLimits {
+                max_tables: Some(1),
+                max_series_per_table: Some(2),
+            },
+        )
+        .await
+        .unwrap();
 
-        // Wait for snapshot to be created:
-        verify_snapshot_count(1, &wbuf.persister).await;
+        // the series of a v3 line is identified by its series key:
+        let result = wbuf
+            .write_lp_v3(
+                db_name,
+                "cpu,host/a usage=2i 2\n\
+                cpu,host/b usage=1i 1\n\
+                cpu,host/c usage=1i 1\n\
+                mem,host/a used=1i 1",
+                Time::from_timestamp_nanos(0),
+                true,
+                Precision::Nanosecond,
+            )
+            .await
+            .unwrap();
+        assert_eq!(2, result.line_count);
+        assert_eq!(
+            vec![
+                (3, WriteLineErrorCategory::CardinalityLimit),
+                (4, WriteLineErrorCategory::CardinalityLimit)
+            ],
+            result
+                .invalid_lines
+                .iter()
+                .map(|e| (e.line_number, e.error_category))
+                .collect::<Vec<_>>()
+        );
+    }
 
-        // Now drop the write buffer, and create a new one that replays:
-        drop(wbuf);
+    #[tokio::test]
+    async fn buffer_and_wal_metrics_are_recorded() {
+        use metric::{Attributes, Metric, Registry, U64Gauge, U64Histogram};
 
-        // Set DbId to a large number to make sure it is properly set on replay
-        // and assert that it's what we expect it to be before we replay
-        dbg!(DbId::next_id());
-        DbId::from(10_000).set_next_id();
-        assert_eq!(DbId::next_id().as_u32(), 10_000);
-        dbg!(DbId::next_id());
-        let (_wbuf, _) = setup(
+        let obj_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let time_provider: Arc<dyn TimeProvider> =
+            Arc::new(MockProvider::new(Time::from_timestamp_nanos(0)));
+        let persister = Arc::new(Persister::new(Arc::clone(&obj_store), "test_host"));
+        let catalog = Arc::new(persister.load_or_create_catalog().await.unwrap());
+        let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
+        let registry = Arc::new(Registry::new());
+        let wbuf = WriteBufferImpl::new(WriteBufferImplArgs {
+            metric_registry: Arc::clone(&registry),
+            ..WriteBufferImplArgs::new(
+                persister,
+                catalog,
+                last_cache,
+                time_provider,
+                crate::test_help::make_exec(),
+                WalConfig::test_config(),
+                None,
+            )
+        })
+        .await
+        .unwrap();
+
+        wbuf.write_lp(
+            NamespaceName::new("db").unwrap(),
+            "cpu,host=a usage=1 1\n\
+            cpu,host=b usage=1 1\n\
+            mem,host=a used=1 1",
             Time::from_timestamp_nanos(0),
-            Arc::clone(&obj_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
+            false,
+            Precision::Nanosecond,
         )
-        .await;
-        dbg!(DbId::next_id());
+        .await
+        .unwrap();
 
-        assert_eq!(DbId::next_id().as_u32(), 1);
+        let buffered = |name, table| {
+            registry
+                .get_instrument::<Metric<U64Gauge>>(name)
+                .unwrap()
+                .get_observer(&Attributes::from(&[("db", "db"), ("table", table)]))
+                .unwrap()
+                .fetch()
+        };
+        assert_eq!(2, buffered("influxdb3_buffered_rows", "cpu"));
+        assert_eq!(1, buffered("influxdb3_buffered_rows", "mem"));
+        assert!(buffered("influxdb3_buffered_bytes", "cpu") > 0);
+
+        // the write was acknowledged once the wal file with it was written:
+        let wal_files = registry
+            .get_instrument::<Metric<U64Histogram>>("influxdb3_wal_file_size_bytes")
+            .unwrap()
+            .get_observer(&Attributes::from(&[]))
+            .unwrap()
+            .fetch();
+        assert_eq!(1, wal_files.sample_count());
     }
 
     #[tokio::test]
-    async fn test_parquet_cache() {
-        // set up a write buffer using a TestObjectStore so we can spy on requests that get
-        // through to the object store for parquet files:
-        let test_store = Arc::new(RequestCountedObjectStore::new(Arc::new(InMemory::new())));
-        let obj_store: Arc<dyn ObjectStore> = Arc::clone(&test_store) as _;
-        let (wbuf, ctx) = setup_cache_optional(
+    async fn write_record_batch() {
+        use arrow::array::{DictionaryArray, Float64Array, TimestampNanosecondArray};
+        use arrow::datatypes::{DataType, Field, Int32Type, Schema as ArrowSchema, TimeUnit};
+
+        let (wbuf, ctx) = setup(
             Time::from_timestamp_nanos(0),
-            Arc::clone(&obj_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
-            true,
+            Arc::new(InMemory::new()),
+            WalConfig::test_config(),
         )
         .await;
-        let db_name = "my_corp";
-        let db_id = DbId::from(0);
-        let tbl_name = "temp";
-        let tbl_id = TableId::from(0);
 
-        // make some writes to generate a snapshot:
-        do_writes(
-            db_name,
-            &wbuf,
-            &[
-                TestWrite {
-                    lp: format!(
-                        "\
-                        {tbl_name},warehouse=us-east,room=01a,device=10001 reading=36\n\
-                        {tbl_name},warehouse=us-east,room=01b,device=10002 reading=29\n\
-                        {tbl_name},warehouse=us-east,room=02a,device=30003 reading=33\n\
-                        "
-                    ),
-                    time_seconds: 1,
-                },
-                TestWrite {
-                    lp: format!(
-                        "\
-                        {tbl_name},warehouse=us-east,room=01a,device=10001 reading=37\n\
-                        {tbl_name},warehouse=us-east,room=01b,device=10002 reading=28\n\
-                        {tbl_name},warehouse=us-east,room=02a,device=30003 reading=32\n\
-                        "
-                    ),
-                    time_seconds: 2,
-                },
-                // This write will trigger the snapshot:
-                TestWrite {
-                    lp: format!(
-                        "\
-                        {tbl_name},warehouse=us-east,room=01a,device=10001 reading=35\n\
-                        {tbl_name},warehouse=us-east,room=01b,device=10002 reading=24\n\
-                        {tbl_name},warehouse=us-east,room=02a,device=30003 reading=30\n\
-                        "
-                    ),
-                    time_seconds: 3,
-                },
+        let schema = Arc::new(ArrowSchema::new(vec![
+            Field::new(
+                "host",
+                DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+                true,
+            ),
+            Field::new("usage", DataType::Float64, true),
+            Field::new(
+                "time",
+                DataType::Timestamp(TimeUnit::Nanosecond, None),
+                false,
+            ),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(
+                    vec!["a", "b"]
+                        .into_iter()
+                        .collect::<DictionaryArray<Int32Type>>(),
+                ),
+                Arc::new(Float64Array::from(vec![0.5, 0.7])),
+                Arc::new(TimestampNanosecondArray::from(vec![
+                    1_000_000_000,
+                    2_000_000_000,
+                ])),
             ],
         )
-        .await;
-
-        // Wait for snapshot to be created, once this is done, then the parquet has been persisted:
-        verify_snapshot_count(1, &wbuf.persister).await;
-
-        // get the path for the created parquet file:
-        let persisted_files = wbuf.persisted_files().get_files(db_id, tbl_id);
-        assert_eq!(1, persisted_files.len());
-        let path = ObjPath::from(persisted_files[0].path.as_str());
+        .unwrap();
 
-        // check the number of requests to that path before making a query:
-        // there should be one get request, made by the cache oracle:
-        assert_eq!(1, test_store.get_request_count(&path));
-        assert_eq!(0, test_store.get_opts_request_count(&path));
-        assert_eq!(0, test_store.get_ranges_request_count(&path));
-        assert_eq!(0, test_store.get_range_request_count(&path));
-        assert_eq!(0, test_store.head_request_count(&path));
+        let result = wbuf
+            .write_record_batch(
+                NamespaceName::new("db").unwrap(),
+                "cpu",
+                batch,
+                Time::from_timestamp_nanos(0),
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(2, result.line_count);
+        assert!(result.invalid_lines.is_empty());
 
-        let batches = get_table_batches(&wbuf, db_name, tbl_name, &ctx).await;
+        let batches = get_table_batches(&wbuf, "db", "cpu", &ctx).await;
         assert_batches_sorted_eq!(
             [
-                "+--------+---------+------+----------------------+-----------+",
-                "| device | reading | room | time                 | warehouse |",
-                "+--------+---------+------+----------------------+-----------+",
-                "| 10001  | 35.0    | 01a  | 1970-01-01T00:00:03Z | us-east   |",
-                "| 10001  | 36.0    | 01a  | 1970-01-01T00:00:01Z | us-east   |",
-                "| 10001  | 37.0    | 01a  | 1970-01-01T00:00:02Z | us-east   |",
-                "| 10002  | 24.0    | 01b  | 1970-01-01T00:00:03Z | us-east   |",
-                "| 10002  | 28.0    | 01b  | 1970-01-01T00:00:02Z | us-east   |",
-                "| 10002  | 29.0    | 01b  | 1970-01-01T00:00:01Z | us-east   |",
-                "| 30003  | 30.0    | 02a  | 1970-01-01T00:00:03Z | us-east   |",
-                "| 30003  | 32.0    | 02a  | 1970-01-01T00:00:02Z | us-east   |",
-                "| 30003  | 33.0    | 02a  | 1970-01-01T00:00:01Z | us-east   |",
-                "+--------+---------+------+----------------------+-----------+",
+                "+------+----------------------+-------+",
+                "| host | time                 | usage |",
+                "+------+----------------------+-------+",
+                "| a    | 1970-01-01T00:00:01Z | 0.5   |",
+                "| b    | 1970-01-01T00:00:02Z | 0.7   |",
+                "+------+----------------------+-------+",
             ],
             &batches
         );
-
-        // counts should not change, since requests for this parquet file hit the cache:
-        assert_eq!(1, test_store.get_request_count(&path));
-        assert_eq!(0, test_store.get_opts_request_count(&path));
-        assert_eq!(0, test_store.get_ranges_request_count(&path));
-        assert_eq!(0, test_store.get_range_request_count(&path));
-        assert_eq!(0, test_store.head_request_count(&path));
     }
+
     #[tokio::test]
-    async fn test_no_parquet_cache() {
-        // set up a write buffer using a TestObjectStore so we can spy on requests that get
-        // through to the object store for parquet files:
-        let test_store = Arc::new(RequestCountedObjectStore::new(Arc::new(InMemory::new())));
-        let obj_store: Arc<dyn ObjectStore> = Arc::clone(&test_store) as _;
-        let (wbuf, ctx) = setup_cache_optional(
+    async fn update_wal_config_survives_restart() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let (wbuf, _ctx) = setup(
             Time::from_timestamp_nanos(0),
-            Arc::clone(&obj_store),
-            WalConfig {
-                gen1_duration: Gen1Duration::new_1m(),
-                max_write_buffer_size: 100,
-                flush_interval: Duration::from_millis(10),
-                snapshot_size: 1,
-            },
-            false,
-        )
-        .await;
-        let db_name = "my_corp";
-        let db_id = DbId::from(0);
-        let tbl_name = "temp";
-        let tbl_id = TableId::from(0);
-
-        // make some writes to generate a snapshot:
-        do_writes(
-            db_name,
-            &wbuf,
-            &[
-                TestWrite {
-                    lp: format!(
-                        "\
-                        {tbl_name},warehouse=us-east,room=01a,device=10001 reading=36\n\
-                        {tbl_name},warehouse=us-east,room=01b,device=10002 reading=29\n\
-                        {tbl_name},warehouse=us-east,room=02a,device=30003 reading=33\n\
-                        "
-                    ),
-                    time_seconds: 1,
-                },
-                TestWrite {
-                    lp: format!(
-                        "\
-                        {tbl_name},warehouse=us-east,room=01a,device=10001 reading=37\n\
-                        {tbl_name},warehouse=us-east,room=01b,device=10002 reading=28\n\
-                        {tbl_name},warehouse=us-east,room=02a,device=30003 reading=32\n\
-                        "
-                    ),
-                    time_seconds: 2,
-                },
-                // This write will trigger the snapshot:
-                TestWrite {
-                    lp: format!(
-                        "\
-                        {tbl_name},warehouse=us-east,room=01a,device=10001 reading=35\n\
-                        {tbl_name},warehouse=us-east,room=01b,device=10002 reading=24\n\
-                        {tbl_name},warehouse=us-east,room=02a,device=30003 reading=30\n\
-                        "
-                    ),
-                    time_seconds: 3,
-                },
-            ],
+            Arc::clone(&object_store),
+            WalConfig::test_config(),
         )
         .await;
 
-        // Wait for snapshot to be created, once this is done, then the parquet has been persisted:
-        verify_snapshot_count(1, &wbuf.persister).await;
-
-        // get the path for the created parquet file:
-        let persisted_files = wbuf.persisted_files().get_files(db_id, tbl_id);
-        assert_eq!(1, persisted_files.len());
-        let path = ObjPath::from(persisted_files[0].path.as_str());
-
-        // check the number of requests to that path before making a query:
-        // there should be no get or get_range requests since nothing has asked for this file yet:
-        assert_eq!(0, test_store.get_request_count(&path));
-        assert_eq!(0, test_store.get_opts_request_count(&path));
-        assert_eq!(0, test_store.get_ranges_request_count(&path));
-        assert_eq!(0, test_store.get_range_request_count(&path));
-        assert_eq!(0, test_store.head_request_count(&path));
-
-        let batches = get_table_batches(&wbuf, db_name, tbl_name, &ctx).await;
-        assert_batches_sorted_eq!(
-            [
-                "+--------+---------+------+----------------------+-----------+",
-                "| device | reading | room | time                 | warehouse |",
-                "+--------+---------+------+----------------------+-----------+",
-                "| 10001  | 35.0    | 01a  | 1970-01-01T00:00:03Z | us-east   |",
-                "| 10001  | 36.0    | 01a  | 1970-01-01T00:00:01Z | us-east   |",
-                "| 10001  | 37.0    | 01a  | 1970-01-01T00:00:02Z | us-east   |",
-                "| 10002  | 24.0    | 01b  | 1970-01-01T00:00:03Z | us-east   |",
-                "| 10002  | 28.0    | 01b  | 1970-01-01T00:00:02Z | us-east   |",
-                "| 10002  | 29.0    | 01b  | 1970-01-01T00:00:01Z | us-east   |",
-                "| 30003  | 30.0    | 02a  | 1970-01-01T00:00:03Z | us-east   |",
-                "| 30003  | 32.0    | 02a  | 1970-01-01T00:00:02Z | us-east   |",
-                "| 30003  | 33.0    | 02a  | 1970-01-01T00:00:01Z | us-east   |",
-                "+--------+---------+------+----------------------+-----------+",
-            ],
-            &batches
+        let updated = WalConfig {
+            gen1_duration: Gen1Duration::new_1m(),
+            flush_interval: Duration::from_millis(20),
+            snapshot_size: 10,
+            // this can only be changed with a restart, so is ignored:
+            max_write_buffer_size: 1,
+            ..WalConfig::test_config()
+        };
+        wbuf.update_wal_config(updated).await.unwrap();
+        assert_eq!(updated.runtime_config(), wbuf.wal_config().runtime_config());
+        assert_eq!(
+            WalConfig::test_config().max_write_buffer_size,
+            wbuf.wal_config().max_write_buffer_size
         );
 
-        // counts should change, since requests for this parquet file were made with no cache:
-        assert_eq!(0, test_store.get_request_count(&path));
-        assert_eq!(0, test_store.get_opts_request_count(&path));
-        assert_eq!(1, test_store.get_ranges_request_count(&path));
-        assert_eq!(2, test_store.get_range_request_count(&path));
-        assert_eq!(0, test_store.head_request_count(&path));
+        // invalid settings are rejected, and not persisted:
+        assert!(matches!(
+            wbuf.update_wal_config(WalConfig {
+                snapshot_size: 0,
+                ..updated
+            })
+            .await,
+            Err(Error::WalError(influxdb3_wal::Error::InvalidWalConfig(_)))
+        ));
+
+        // the updated settings take precedence over those the server is restarted with:
+        drop(wbuf);
+        let (wbuf, _ctx) = setup(
+            Time::from_timestamp_nanos(0),
+            Arc::clone(&object_store),
+            WalConfig::test_config(),
+        )
+        .await;
+        assert_eq!(updated.runtime_config(), wbuf.wal_config().runtime_config());
     }
 
     struct TestWrite<LP> {
@@ -1979,7 +8279,7 @@ mod tests {
         let persister = Arc::new(Persister::new(Arc::clone(&object_store), "test_host"));
         let catalog = Arc::new(persister.load_or_create_catalog().await.unwrap());
         let last_cache = LastCacheProvider::new_from_catalog(Arc::clone(&catalog) as _).unwrap();
-        let wbuf = WriteBufferImpl::new(
+        let wbuf = WriteBufferImpl::new(WriteBufferImplArgs::new(
             Arc::clone(&persister),
             catalog,
             last_cache,
@@ -1987,7 +8287,7 @@ mod tests {
             crate::test_help::make_exec(),
             wal_config,
             parquet_cache,
-        )
+        ))
         .await
         .unwrap();
         let ctx = IOxSessionContext::with_testing();
@@ -0,0 +1,119 @@
+//! Garbage collection of the parquet files in object storage that are not persisted files, which
+//! are never queried, e.g., the files of a snapshot that failed, or those removed by retention or
+//! by a hard delete, which are otherwise only removed from the persisted files.
+
+use crate::persister::{self, Persister};
+use crate::write_buffer::persisted_files::PersistedFiles;
+use chrono::{DateTime, Utc};
+use metric::{Registry, U64Counter};
+use object_store::path::Path as ObjPath;
+use observability_deps::tracing::{error, warn};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// The metrics of the parquet files found by garbage collection
+#[derive(Debug)]
+struct ParquetGcMetrics {
+    /// The number of unreferenced files that were found, including by dry runs
+    found_files: U64Counter,
+    /// The number of unreferenced files that were deleted
+    deleted_files: U64Counter,
+    /// The size of the unreferenced files that were deleted
+    deleted_bytes: U64Counter,
+}
+
+impl ParquetGcMetrics {
+    fn new(metric_registry: &Registry) -> Self {
+        let found_files = metric_registry
+            .register_metric::<U64Counter>(
+                "influxdb3_parquet_gc_found_files",
+                "number of parquet files found that are not referenced by any snapshot",
+            )
+            .recorder(&[]);
+        let deleted_files = metric_registry
+            .register_metric::<U64Counter>(
+                "influxdb3_parquet_gc_deleted_files",
+                "number of unreferenced parquet files that were deleted",
+            )
+            .recorder(&[]);
+        let deleted_bytes = metric_registry
+            .register_metric::<U64Counter>(
+                "influxdb3_parquet_gc_deleted_bytes",
+                "size in bytes of the unreferenced parquet files that were deleted",
+            )
+            .recorder(&[]);
+        Self {
+            found_files,
+            deleted_files,
+            deleted_bytes,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ParquetGarbageCollector {
+    persister: Arc<Persister>,
+    persisted_files: Arc<PersistedFiles>,
+    metrics: ParquetGcMetrics,
+}
+
+impl ParquetGarbageCollector {
+    pub(crate) fn new(
+        persister: Arc<Persister>,
+        persisted_files: Arc<PersistedFiles>,
+        metric_registry: &Registry,
+    ) -> Self {
+        Self {
+            persister,
+            persisted_files,
+            metrics: ParquetGcMetrics::new(metric_registry),
+        }
+    }
+
+    /// Find the parquet files that are not persisted files, or in `pending`, and were last
+    /// modified before `cutoff`, and delete them, unless `dry_run` is set, in which case they are
+    /// only logged. Returns the files that were found.
+    ///
+    /// Files that were modified after `cutoff` are left alone, since a snapshot, or compaction,
+    /// may have persisted them without having added them to the persisted files yet.
+    pub(crate) async fn collect(
+        &self,
+        cutoff: DateTime<Utc>,
+        pending: &HashSet<String>,
+        dry_run: bool,
+    ) -> Result<Vec<ObjPath>, persister::Error> {
+        if !dry_run {
+            self.persister.check_write_fence()?;
+        }
+        // the persisted files are read after the listing, so any file that was added to them
+        // while listing is still seen as referenced:
+        let files = self.persister.list_parquet_files().await?;
+        let referenced = self.persisted_files.paths();
+
+        let mut unreferenced = vec![];
+        for meta in files {
+            let path = meta.location.to_string();
+            if meta.last_modified >= cutoff || referenced.contains(&path) || pending.contains(&path)
+            {
+                continue;
+            }
+            self.metrics.found_files.inc(1);
+            if dry_run {
+                warn!(%path, "found unreferenced parquet file");
+            } else {
+                warn!(%path, "removing unreferenced parquet file");
+                // any file that fails to be removed is found again on the next collection:
+                match self.persister.object_store().delete(&meta.location).await {
+                    Ok(()) => {
+                        self.metrics.deleted_files.inc(1);
+                        self.metrics.deleted_bytes.inc(meta.size as u64);
+                    }
+                    Err(object_store::Error::NotFound { .. }) => (),
+                    Err(e) => error!(%e, %path, "error removing unreferenced parquet file"),
+                }
+            }
+            unreferenced.push(meta.location);
+        }
+        Ok(unreferenced)
+    }
+}
@@ -1,13 +1,26 @@
 //! This tracks what files have been persisted by the write buffer, limited to the last 72 hours.
 //! When queries come in they will combine whatever chunks exist from `QueryableBuffer` with
 //! the persisted files to get the full set of data to query.
-
-use crate::{ParquetFile, PersistedSnapshot};
+//!
+//! Only the most recent snapshots are loaded on startup. The older snapshots are loaded a page at
+//! a time, by [`PersistedFiles::load_older_snapshots`], once a query needs data from before the
+//! earliest data in those that have been loaded.
+
+use crate::persister::{self, Persister, SnapshotContinuation, SnapshotPage};
+use crate::{
+    Compaction, CompactionGeneration, DatabaseTables, ParquetFile, PersistedFilesIndex,
+    PersistedSnapshot,
+};
+use datafusion::logical_expr::{Between, BinaryExpr, Expr, Operator};
+use datafusion::scalar::ScalarValue;
 use hashbrown::HashMap;
+use influxdb3_catalog::catalog::TIME_COLUMN_NAME;
 use influxdb3_id::DbId;
 use influxdb3_id::TableId;
 use influxdb3_telemetry::ParquetMetrics;
+use influxdb3_wal::SnapshotSequenceNumber;
 use parking_lot::RwLock;
+use std::collections::HashSet;
 
 type DatabaseToTables = HashMap<DbId, TableToFiles>;
 type TableToFiles = HashMap<TableId, Vec<ParquetFile>>;
@@ -15,14 +28,107 @@ type TableToFiles = HashMap<TableId, Vec<ParquetFile>>;
 #[derive(Debug, Default)]
 pub struct PersistedFiles {
     inner: RwLock<Inner>,
+    /// Held while older snapshots are loaded, so that each page of them is only loaded once
+    loading: tokio::sync::Mutex<()>,
 }
 
 impl PersistedFiles {
     /// Create a new `PersistedFiles` from a list of persisted snapshots
     pub fn new_from_persisted_snapshots(persisted_snapshots: Vec<PersistedSnapshot>) -> Self {
-        let inner = Inner::new_from_persisted_snapshots(persisted_snapshots);
+        Self::new_from_snapshot_page(SnapshotPage {
+            snapshots: persisted_snapshots,
+            continuation: None,
+        })
+    }
+
+    /// Create a new `PersistedFiles` from the most recent page of persisted snapshots, along with
+    /// where the snapshots older than them continue from, which are loaded when they are needed
+    pub fn new_from_snapshot_page(page: SnapshotPage) -> Self {
+        let mut inner = Inner::new_from_persisted_snapshots(page.snapshots);
+        inner.older_snapshots = page.continuation;
         Self {
             inner: RwLock::new(inner),
+            loading: Default::default(),
+        }
+    }
+
+    /// Whether there are snapshots that have not been loaded yet, which may have data from before
+    /// `min_time`, i.e., the snapshots that have been loaded do not go back as far as it
+    pub fn needs_older_snapshots(&self, min_time: i64) -> bool {
+        let inner = self.inner.read();
+        inner.older_snapshots.is_some() && min_time < inner.loaded_min_time
+    }
+
+    /// The index of all of the persisted files, as of the most recent snapshot that has been
+    /// added, or `None` if there are older snapshots that have not been loaded yet, or there are
+    /// no snapshots at all
+    pub fn index(&self) -> Option<PersistedFilesIndex> {
+        let inner = self.inner.read();
+        if inner.older_snapshots.is_some() {
+            return None;
+        }
+        let snapshot = inner.latest_snapshot.clone()?;
+        let databases = inner
+            .files
+            .iter()
+            .map(|(db_id, tables)| {
+                let tables = tables
+                    .iter()
+                    .filter(|(_, files)| !files.is_empty())
+                    .map(|(table_id, files)| (*table_id, files.clone()))
+                    .collect();
+                (*db_id, DatabaseTables { tables })
+            })
+            .filter(|(_, db)| !db.tables.is_empty())
+            .collect();
+        Some(PersistedFilesIndex {
+            snapshot,
+            databases,
+        })
+    }
+
+    /// The sequence number of the most recent snapshot that has been added, which the snapshots
+    /// persisted after it are loaded from
+    pub fn latest_snapshot_sequence_number(&self) -> Option<SnapshotSequenceNumber> {
+        self.inner
+            .read()
+            .latest_snapshot
+            .as_ref()
+            .map(|snapshot| snapshot.snapshot_sequence_number)
+    }
+
+    /// Whether all of the persisted snapshots have been loaded
+    pub fn all_snapshots_loaded(&self) -> bool {
+        self.inner.read().older_snapshots.is_none()
+    }
+
+    /// Load the snapshots older than those loaded so far, `page_size` at a time, until they have
+    /// data from as far back as `min_time`, or all of them have been loaded. Returns the number
+    /// of snapshots that were loaded.
+    ///
+    /// The files that compactions replaced, and those of the tables that were removed, are left
+    /// out of those of the older snapshots, since they may have been deleted.
+    pub async fn load_older_snapshots(
+        &self,
+        persister: &Persister,
+        min_time: i64,
+        page_size: usize,
+    ) -> persister::Result<usize> {
+        let _loading = self.loading.lock().await;
+        let mut loaded = 0;
+        loop {
+            let continuation = {
+                let inner = self.inner.read();
+                match &inner.older_snapshots {
+                    Some(continuation) if min_time < inner.loaded_min_time => continuation.clone(),
+                    _ => return Ok(loaded),
+                }
+            };
+            let page = persister
+                .load_snapshots_page(Some(&continuation), page_size.max(1))
+                .await?;
+            loaded += page.snapshots.len();
+            self.inner.write().add_older_snapshots(page);
         }
     }
 
@@ -42,13 +148,30 @@ impl PersistedFiles {
 
     /// Get the list of files for a given database and table, always return in descending order of min_time
     pub fn get_files(&self, db_id: DbId, table_id: TableId) -> Vec<ParquetFile> {
+        self.get_files_in_time_range(db_id, table_id, TimeRange::default())
+    }
+
+    /// Get the list of files for a given database and table that have data in `time_range`, in
+    /// descending order of min_time
+    pub fn get_files_in_time_range(
+        &self,
+        db_id: DbId,
+        table_id: TableId,
+        time_range: TimeRange,
+    ) -> Vec<ParquetFile> {
         let mut files = {
             let inner = self.inner.read();
             inner
                 .files
                 .get(&db_id)
                 .and_then(|tables| tables.get(&table_id))
-                .cloned()
+                .map(|files| {
+                    files
+                        .iter()
+                        .filter(|file| time_range.overlaps(file.min_time, file.max_time))
+                        .cloned()
+                        .collect::<Vec<_>>()
+                })
                 .unwrap_or_default()
         };
 
@@ -56,6 +179,86 @@ impl PersistedFiles {
 
         files
     }
+
+    /// Get the `n` files across all databases and tables with the most recent data, in descending
+    /// order of max_time
+    pub fn most_recent_files(&self, n: usize) -> Vec<ParquetFile> {
+        let mut files = {
+            let inner = self.inner.read();
+            inner
+                .files
+                .values()
+                .flat_map(|tables| tables.values().flatten())
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        files.sort_by(|a, b| b.max_time.cmp(&a.max_time));
+        files.truncate(n);
+
+        files
+    }
+
+    /// The paths of all of the persisted files
+    pub fn paths(&self) -> HashSet<String> {
+        let inner = self.inner.read();
+        inner
+            .files
+            .values()
+            .flat_map(|tables| tables.values().flatten())
+            .map(|file| file.path.clone())
+            .collect()
+    }
+
+    /// Remove all files in the given database that only contain data older than `cutoff_time_ns`,
+    /// returning the files that were removed
+    pub fn remove_files_older_than(&self, db_id: DbId, cutoff_time_ns: i64) -> Vec<ParquetFile> {
+        let mut inner = self.inner.write();
+        inner.remove_files_older_than(db_id, cutoff_time_ns)
+    }
+
+    /// Remove all of the files of a table, e.g., once it has been deleted, returning the files
+    /// that were removed
+    pub fn remove_table_files(&self, db_id: DbId, table_id: TableId) -> Vec<ParquetFile> {
+        let mut inner = self.inner.write();
+        inner.remove_table_files(db_id, table_id)
+    }
+
+    /// Remove all of the files of the tables that are not in `tables`, e.g., those that were
+    /// hard deleted from the catalog, returning the files that were removed. The files of these
+    /// tables are also left out of the older snapshots, when they are loaded.
+    pub fn retain_tables(&self, tables: HashSet<(DbId, TableId)>) -> Vec<ParquetFile> {
+        let mut inner = self.inner.write();
+        let removed_tables = inner
+            .files
+            .iter()
+            .flat_map(|(db_id, files)| files.keys().map(|table_id| (*db_id, *table_id)))
+            .filter(|table| !tables.contains(table))
+            .collect::<Vec<_>>();
+        let removed = removed_tables
+            .into_iter()
+            .flat_map(|(db_id, table_id)| inner.remove_table_files(db_id, table_id))
+            .collect();
+        if inner.older_snapshots.is_some() {
+            inner.retained_tables = Some(tables);
+        }
+        removed
+    }
+
+    /// Replace the files that were compacted into a gen2 file with it, returning those of them
+    /// that were still persisted files, i.e., that were not removed since they were compacted
+    pub fn apply_compaction(&self, compaction: &Compaction) -> Vec<ParquetFile> {
+        let mut inner = self.inner.write();
+        inner.apply_compaction(compaction)
+    }
+
+    /// Apply the compactions of a generation that was loaded on startup
+    pub fn apply_compaction_generation(&self, generation: &CompactionGeneration) {
+        let mut inner = self.inner.write();
+        for compaction in &generation.compactions {
+            inner.apply_compaction(compaction);
+        }
+    }
 }
 
 impl ParquetMetrics for PersistedFiles {
@@ -70,6 +273,114 @@ impl ParquetMetrics for PersistedFiles {
     }
 }
 
+/// The range of times, inclusive, that the filters of a query restrict it to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub min: i64,
+    pub max: i64,
+}
+
+impl Default for TimeRange {
+    fn default() -> Self {
+        Self {
+            min: i64::MIN,
+            max: i64::MAX,
+        }
+    }
+}
+
+impl TimeRange {
+    /// The range of times that rows must be in to match all of `filters`, from the comparisons
+    /// of the time column with timestamps in them. Filters that do not restrict the time column,
+    /// or that restrict it in ways that are not understood, are ignored, so this is unbounded if
+    /// there are none.
+    pub fn from_filters(filters: &[Expr]) -> Self {
+        filters
+            .iter()
+            .fold(Self::default(), |range, filter| range.restrict(filter))
+    }
+
+    /// Whether any of the times from `min_time` to `max_time`, inclusive, are in this range
+    pub fn overlaps(&self, min_time: i64, max_time: i64) -> bool {
+        max_time >= self.min && min_time <= self.max
+    }
+
+    fn restrict(self, expr: &Expr) -> Self {
+        match expr {
+            Expr::BinaryExpr(BinaryExpr {
+                left,
+                op: Operator::And,
+                right,
+            }) => self.restrict(left).restrict(right),
+            Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+                // the time column can be on either side of the comparison:
+                let (op, value) = match (left.as_ref(), right.as_ref()) {
+                    (Expr::Column(c), value) if c.name() == TIME_COLUMN_NAME => (*op, value),
+                    (value, Expr::Column(c)) if c.name() == TIME_COLUMN_NAME => {
+                        let Some(op) = op.swap() else {
+                            return self;
+                        };
+                        (op, value)
+                    }
+                    _ => return self,
+                };
+                let Some(value) = timestamp_nanos(value) else {
+                    return self;
+                };
+                match op {
+                    Operator::Eq => self.with_min(value).with_max(value),
+                    Operator::Gt => self.with_min(value.saturating_add(1)),
+                    Operator::GtEq => self.with_min(value),
+                    Operator::Lt => self.with_max(value.saturating_sub(1)),
+                    Operator::LtEq => self.with_max(value),
+                    _ => self,
+                }
+            }
+            Expr::Between(Between {
+                expr,
+                negated: false,
+                low,
+                high,
+            }) if matches!(expr.as_ref(), Expr::Column(c) if c.name() == TIME_COLUMN_NAME) => {
+                let mut range = self;
+                if let Some(low) = timestamp_nanos(low) {
+                    range = range.with_min(low);
+                }
+                if let Some(high) = timestamp_nanos(high) {
+                    range = range.with_max(high);
+                }
+                range
+            }
+            _ => self,
+        }
+    }
+
+    fn with_min(self, min: i64) -> Self {
+        Self {
+            min: self.min.max(min),
+            max: self.max,
+        }
+    }
+
+    fn with_max(self, max: i64) -> Self {
+        Self {
+            min: self.min,
+            max: self.max.min(max),
+        }
+    }
+}
+
+/// The nanoseconds since the epoch of a timestamp literal
+fn timestamp_nanos(expr: &Expr) -> Option<i64> {
+    match expr {
+        Expr::Literal(ScalarValue::TimestampNanosecond(Some(v), _)) => Some(*v),
+        Expr::Literal(ScalarValue::TimestampMicrosecond(Some(v), _)) => v.checked_mul(1_000),
+        Expr::Literal(ScalarValue::TimestampMillisecond(Some(v), _)) => v.checked_mul(1_000_000),
+        Expr::Literal(ScalarValue::TimestampSecond(Some(v), _)) => v.checked_mul(1_000_000_000),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Default)]
 struct Inner {
     /// The map of databases to tables to files
@@ -80,6 +391,21 @@ struct Inner {
     pub parquet_files_size_mb: f64,
     /// Overall row count within the parquet files
     pub parquet_files_row_count: u64,
+    /// Where the snapshots older than those that have been loaded continue from, if there are any
+    pub older_snapshots: Option<SnapshotContinuation>,
+    /// The earliest time of the data in the snapshots that have been loaded
+    pub loaded_min_time: i64,
+    /// The paths of the files that compactions replaced, which are kept while there are older
+    /// snapshots to load, since they may have some of these files
+    pub compacted_paths: HashSet<String>,
+    /// The tables whose files were removed, which are kept while there are older snapshots to
+    /// load, since they may have files of these tables
+    pub removed_tables: HashSet<(DbId, TableId)>,
+    /// The tables that the files of the older snapshots are limited to, if they are, which are
+    /// kept while there are older snapshots to load
+    pub retained_tables: Option<HashSet<(DbId, TableId)>>,
+    /// The most recent snapshot that has been added, without its files
+    pub latest_snapshot: Option<PersistedSnapshot>,
 }
 
 impl Inner {
@@ -87,12 +413,15 @@ impl Inner {
         let mut file_count = 0;
         let mut size_in_mb = 0.0;
         let mut row_count = 0;
+        let mut loaded_min_time = i64::MAX;
+        let latest_snapshot = persisted_snapshots.first().map(without_files);
 
         let files = persisted_snapshots.into_iter().fold(
             hashbrown::HashMap::new(),
             |mut files, persisted_snapshot| {
                 size_in_mb += as_mb(persisted_snapshot.parquet_size_bytes);
                 row_count += persisted_snapshot.row_count;
+                loaded_min_time = loaded_min_time.min(persisted_snapshot.min_time);
                 let parquet_files_added =
                     update_persisted_files_with_snapshot(true, persisted_snapshot, &mut files);
                 file_count += parquet_files_added;
@@ -105,16 +434,157 @@ impl Inner {
             parquet_files_count: file_count,
             parquet_files_row_count: row_count,
             parquet_files_size_mb: size_in_mb,
+            older_snapshots: None,
+            loaded_min_time,
+            compacted_paths: HashSet::new(),
+            removed_tables: HashSet::new(),
+            retained_tables: None,
+            latest_snapshot,
+        }
+    }
+
+    pub fn add_older_snapshots(&mut self, page: SnapshotPage) {
+        for mut persisted_snapshot in page.snapshots {
+            self.loaded_min_time = self.loaded_min_time.min(persisted_snapshot.min_time);
+            let mut dropped_bytes = 0;
+            let mut dropped_rows = 0;
+            for (db_id, tables) in persisted_snapshot.databases.iter_mut() {
+                for (table_id, files) in tables.tables.iter_mut() {
+                    let table = (*db_id, *table_id);
+                    let removed_table = self.removed_tables.contains(&table)
+                        || self
+                            .retained_tables
+                            .as_ref()
+                            .is_some_and(|tables| !tables.contains(&table));
+                    files.retain(|file| {
+                        let dropped = removed_table || self.compacted_paths.contains(&file.path);
+                        if dropped {
+                            dropped_bytes += file.size_bytes;
+                            dropped_rows += file.row_count;
+                        }
+                        !dropped
+                    });
+                }
+            }
+            persisted_snapshot.parquet_size_bytes -= dropped_bytes;
+            persisted_snapshot.row_count -= dropped_rows;
+            self.add_persisted_snapshot(persisted_snapshot);
+        }
+        self.older_snapshots = page.continuation;
+        if self.older_snapshots.is_none() {
+            self.compacted_paths = HashSet::new();
+            self.removed_tables = HashSet::new();
+            self.retained_tables = None;
         }
     }
 
     pub fn add_persisted_snapshot(&mut self, persisted_snapshot: PersistedSnapshot) {
+        if self.latest_snapshot.as_ref().map_or(true, |latest| {
+            latest.snapshot_sequence_number < persisted_snapshot.snapshot_sequence_number
+        }) {
+            self.latest_snapshot = Some(without_files(&persisted_snapshot));
+        }
         self.parquet_files_row_count += persisted_snapshot.row_count;
         self.parquet_files_size_mb += as_mb(persisted_snapshot.parquet_size_bytes);
         let file_count =
             update_persisted_files_with_snapshot(false, persisted_snapshot, &mut self.files);
         self.parquet_files_count += file_count;
     }
+
+    pub fn remove_files_older_than(
+        &mut self,
+        db_id: DbId,
+        cutoff_time_ns: i64,
+    ) -> Vec<ParquetFile> {
+        let Some(tables) = self.files.get_mut(&db_id) else {
+            return vec![];
+        };
+        let mut removed = vec![];
+        for table_files in tables.values_mut() {
+            let (expired, retained): (Vec<_>, Vec<_>) = table_files
+                .drain(..)
+                .partition(|file| file.max_time < cutoff_time_ns);
+            *table_files = retained;
+            removed.extend(expired);
+        }
+        self.remove_from_metrics(&removed);
+        removed
+    }
+
+    pub fn remove_table_files(&mut self, db_id: DbId, table_id: TableId) -> Vec<ParquetFile> {
+        let removed = self
+            .files
+            .get_mut(&db_id)
+            .and_then(|tables| tables.remove(&table_id))
+            .unwrap_or_default();
+        if self.older_snapshots.is_some() {
+            self.removed_tables.insert((db_id, table_id));
+        }
+        self.remove_from_metrics(&removed);
+        removed
+    }
+
+    pub fn apply_compaction(&mut self, compaction: &Compaction) -> Vec<ParquetFile> {
+        let table_files = self
+            .files
+            .entry(compaction.db_id)
+            .or_default()
+            .entry(compaction.table_id)
+            .or_default();
+        // files are matched by path, since a gen2 file has the id of the newest file it replaced:
+        let (removed, retained): (Vec<_>, Vec<_>) = table_files.drain(..).partition(|file| {
+            compaction
+                .replaced
+                .iter()
+                .any(|replaced| replaced.path == file.path)
+        });
+        *table_files = retained;
+        // the generations loaded on startup are applied again to files that were loaded from the
+        // index of the persisted files, which may already have the gen2 file:
+        let already_applied = table_files
+            .iter()
+            .any(|file| file.path == compaction.file.path);
+        if !already_applied {
+            table_files.push(compaction.file.clone());
+        }
+        if self.older_snapshots.is_some() {
+            self.compacted_paths
+                .extend(compaction.replaced.iter().map(|file| file.path.clone()));
+        }
+        self.remove_from_metrics(&removed);
+        if !already_applied {
+            self.parquet_files_count += 1;
+            self.parquet_files_size_mb += as_mb(compaction.file.size_bytes);
+            self.parquet_files_row_count += compaction.file.row_count;
+        }
+        removed
+    }
+
+    fn remove_from_metrics(&mut self, removed: &[ParquetFile]) {
+        for file in removed {
+            self.parquet_files_count -= 1;
+            self.parquet_files_size_mb -= as_mb(file.size_bytes);
+            self.parquet_files_row_count -= file.row_count;
+        }
+    }
+}
+
+fn without_files(persisted_snapshot: &PersistedSnapshot) -> PersistedSnapshot {
+    PersistedSnapshot {
+        host_id: persisted_snapshot.host_id.clone(),
+        next_file_id: persisted_snapshot.next_file_id,
+        next_db_id: persisted_snapshot.next_db_id,
+        next_table_id: persisted_snapshot.next_table_id,
+        next_column_id: persisted_snapshot.next_column_id,
+        snapshot_sequence_number: persisted_snapshot.snapshot_sequence_number,
+        wal_file_sequence_number: persisted_snapshot.wal_file_sequence_number,
+        catalog_sequence_number: persisted_snapshot.catalog_sequence_number,
+        parquet_size_bytes: 0,
+        row_count: 0,
+        min_time: persisted_snapshot.min_time,
+        max_time: persisted_snapshot.max_time,
+        databases: Default::default(),
+    }
 }
 
 fn as_mb(bytes: u64) -> f64 {
@@ -161,8 +631,10 @@ mod tests {
 
     use influxdb3_catalog::catalog::CatalogSequenceNumber;
     use influxdb3_wal::{SnapshotSequenceNumber, WalFileSequenceNumber};
+    use object_store::memory::InMemory;
     use observability_deps::tracing::info;
     use pretty_assertions::assert_eq;
+    use std::sync::Arc;
 
     use crate::ParquetFileId;
 
@@ -238,6 +710,226 @@ mod tests {
         assert_eq!(150, row_count);
     }
 
+    #[test_log::test(test)]
+    fn test_remove_files_older_than() {
+        let all_persisted_snapshot_files = build_persisted_snapshots();
+        let persisted_file =
+            PersistedFiles::new_from_persisted_snapshots(all_persisted_snapshot_files);
+        let mut newer_file = build_parquet_files(1).pop().unwrap();
+        newer_file.min_time = 1_000;
+        newer_file.max_time = 2_000;
+        persisted_file.add_persisted_snapshot_files(build_snapshot(vec![newer_file], 3, 3, 3));
+
+        // nothing is removed from a database that doesn't exist:
+        assert!(persisted_file
+            .remove_files_older_than(DbId::from(1), 1_000)
+            .is_empty());
+
+        let removed = persisted_file.remove_files_older_than(DbId::from(0), 1_000);
+        assert_eq!(10, removed.len());
+
+        let (file_count, size_in_mb, row_count) = persisted_file.get_metrics();
+        assert_eq!(1, file_count);
+        assert!((size_in_mb - 0.05).abs() < 1e-9);
+        assert_eq!(10, row_count);
+
+        let remaining = persisted_file.get_files(DbId::from(0), TableId::from(0));
+        assert_eq!(1, remaining.len());
+        assert_eq!(2_000, remaining[0].max_time);
+    }
+
+    #[test]
+    fn test_apply_compaction() {
+        let mut files = build_parquet_files(3);
+        for (i, file) in files.iter_mut().enumerate() {
+            file.path = format!("gen1/{i}.parquet");
+        }
+        let persisted_file = PersistedFiles::new_from_persisted_snapshots(vec![build_snapshot(
+            files.clone(),
+            1,
+            1,
+            1,
+        )]);
+        let gen2_file = ParquetFile {
+            id: files[1].id,
+            path: "gen2/0.parquet".to_owned(),
+            row_count: 15,
+            ..files[1].clone()
+        };
+        let removed = persisted_file.apply_compaction(&Compaction {
+            db_id: DbId::from(0),
+            table_id: TableId::from(0),
+            file: gen2_file.clone(),
+            replaced: files[..2].to_vec(),
+        });
+        assert_eq!(files[..2].to_vec(), removed);
+
+        let mut remaining = persisted_file.get_files(DbId::from(0), TableId::from(0));
+        remaining.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(vec![files[2].clone(), gen2_file], remaining);
+        let (file_count, _, row_count) = persisted_file.get_metrics();
+        assert_eq!(2, file_count);
+        assert_eq!(25, row_count);
+    }
+
+    #[test]
+    fn test_get_files_in_time_range() {
+        let persisted_file = PersistedFiles::default();
+        for (min_time, max_time) in [(0, 99), (100, 199), (200, 299)] {
+            let mut file = build_parquet_files(1).pop().unwrap();
+            file.min_time = min_time;
+            file.max_time = max_time;
+            persisted_file.add_file(DbId::from(0), TableId::from(0), file);
+        }
+        let time = || datafusion::prelude::col(TIME_COLUMN_NAME);
+        let ts = |v| datafusion::prelude::lit(ScalarValue::TimestampNanosecond(Some(v), None));
+        let min_times = |filters: &[Expr]| {
+            persisted_file
+                .get_files_in_time_range(
+                    DbId::from(0),
+                    TableId::from(0),
+                    TimeRange::from_filters(filters),
+                )
+                .into_iter()
+                .map(|file| file.min_time)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec![200, 100, 0], min_times(&[]));
+        assert_eq!(vec![200, 100], min_times(&[time().gt_eq(ts(150))]));
+        assert_eq!(vec![200], min_times(&[time().gt(ts(199))]));
+        assert_eq!(
+            vec![100],
+            min_times(&[time().gt(ts(99)).and(time().lt(ts(200)))])
+        );
+        assert_eq!(vec![0], min_times(&[ts(100).gt(time())]));
+        assert_eq!(
+            vec![200, 100],
+            min_times(&[time().between(ts(150), ts(250))])
+        );
+        assert_eq!(vec![100], min_times(&[time().eq(ts(150))]));
+        // comparisons with other columns, or that are not with timestamps, are ignored:
+        assert_eq!(
+            vec![200, 100, 0],
+            min_times(&[
+                datafusion::prelude::col("host").gt(ts(150)),
+                time().not_eq(ts(150)),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load_older_snapshots() {
+        let persister = Persister::new(Arc::new(InMemory::new()), "sample-host-id");
+        let mut files = build_parquet_files(3);
+        for (i, file) in files.iter_mut().enumerate() {
+            file.path = format!("gen1/{i}.parquet");
+            file.min_time = i as i64 * 100;
+            file.max_time = i as i64 * 100 + 99;
+        }
+        for (i, file) in files.iter().enumerate() {
+            let snapshot = build_snapshot(vec![file.clone()], i as u64, i as u64, i as u32);
+            persister.persist_snapshot(&snapshot).await.unwrap();
+        }
+
+        // only the most recent snapshot is loaded to start with:
+        let page = persister.load_snapshots_page(None, 1).await.unwrap();
+        let persisted_file = PersistedFiles::new_from_snapshot_page(page);
+        assert!(!persisted_file.needs_older_snapshots(250));
+        assert!(persisted_file.needs_older_snapshots(150));
+        assert_eq!(
+            0,
+            persisted_file
+                .load_older_snapshots(&persister, 250, 1)
+                .await
+                .unwrap()
+        );
+
+        // the older snapshots are loaded until they have data from as far back as is needed:
+        assert_eq!(
+            1,
+            persisted_file
+                .load_older_snapshots(&persister, 150, 1)
+                .await
+                .unwrap()
+        );
+        assert!(!persisted_file.needs_older_snapshots(150));
+        assert!(!persisted_file.all_snapshots_loaded());
+
+        // files that were compacted are not added back when the snapshots that had them are:
+        let gen2_file = ParquetFile {
+            path: "gen2/0.parquet".to_owned(),
+            min_time: 0,
+            ..files[1].clone()
+        };
+        persisted_file.apply_compaction(&Compaction {
+            db_id: DbId::from(0),
+            table_id: TableId::from(0),
+            file: gen2_file.clone(),
+            replaced: files[..2].to_vec(),
+        });
+        assert_eq!(
+            1,
+            persisted_file
+                .load_older_snapshots(&persister, i64::MIN, 1)
+                .await
+                .unwrap()
+        );
+        assert!(persisted_file.all_snapshots_loaded());
+        assert!(!persisted_file.needs_older_snapshots(i64::MIN));
+
+        let mut paths = persisted_file
+            .get_files(DbId::from(0), TableId::from(0))
+            .into_iter()
+            .map(|file| file.path)
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(vec!["gen1/2.parquet", "gen2/0.parquet"], paths);
+        let (file_count, _, row_count) = persisted_file.get_metrics();
+        assert_eq!(2, file_count);
+        assert_eq!(20, row_count);
+    }
+
+    #[tokio::test]
+    async fn test_retain_tables() {
+        let persister = Persister::new(Arc::new(InMemory::new()), "sample-host-id");
+        let mut files = build_parquet_files(4);
+        for (i, file) in files.iter_mut().enumerate() {
+            file.path = format!("gen1/{i}.parquet");
+        }
+        // each snapshot has a file of table 0 and of table 1:
+        for (i, files) in files.chunks(2).enumerate() {
+            let mut snapshot = build_snapshot(vec![files[0].clone()], i as u64, i as u64, 0);
+            snapshot.add_parquet_file(DbId::from(0), TableId::from(1), files[1].clone());
+            persister.persist_snapshot(&snapshot).await.unwrap();
+        }
+        let page = persister.load_snapshots_page(None, 1).await.unwrap();
+        let persisted_file = PersistedFiles::new_from_snapshot_page(page);
+
+        let removed =
+            persisted_file.retain_tables(HashSet::from([(DbId::from(0), TableId::from(0))]));
+        assert_eq!(vec![files[3].clone()], removed);
+
+        // the files of the tables that were removed are not added back by the older snapshots:
+        persisted_file
+            .load_older_snapshots(&persister, i64::MIN, 1)
+            .await
+            .unwrap();
+        assert!(persisted_file.all_snapshots_loaded());
+        assert!(persisted_file
+            .get_files(DbId::from(0), TableId::from(1))
+            .is_empty());
+        assert_eq!(
+            2,
+            persisted_file
+                .get_files(DbId::from(0), TableId::from(0))
+                .len()
+        );
+        let (file_count, _, row_count) = persisted_file.get_metrics();
+        assert_eq!(2, file_count);
+        assert_eq!(20, row_count);
+    }
+
     fn build_persisted_snapshots() -> Vec<PersistedSnapshot> {
         let mut all_persisted_snapshot_files = Vec::new();
         let parquet_files_1 = build_parquet_files(5);
@@ -279,6 +971,9 @@ mod tests {
                 chunk_time: 10,
                 min_time: 10,
                 max_time: 200,
+                indexes: Default::default(),
+                column_ranges: Default::default(),
+                sort_key: Default::default(),
             })
             .collect();
         parquet_files
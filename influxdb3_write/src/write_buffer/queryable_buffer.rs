@@ -1,11 +1,21 @@
 use crate::chunk::BufferChunk;
+use crate::distinct_cache::DistinctCacheProvider;
 use crate::last_cache::LastCacheProvider;
 use crate::parquet_cache::{CacheRequest, ParquetCacheOracle};
 use crate::paths::ParquetFilePath;
-use crate::persister::Persister;
+use crate::persister::{self, BloomFilters, PersistBackoff, Persister, SnapshotMarker};
+use crate::write_buffer::events::WriteEventListeners;
 use crate::write_buffer::persisted_files::PersistedFiles;
+use crate::write_buffer::replication::LeaderSnapshots;
 use crate::write_buffer::table_buffer::TableBuffer;
-use crate::{ParquetFile, ParquetFileId, PersistedSnapshot};
+use crate::write_buffer::tag_values::TagValueDictionary;
+use crate::{
+    ColumnValueRange, LastCacheReplay, ParquetFile, ParquetFileId, ParquetFileIndexes,
+    PersistedSnapshot,
+};
+use arrow::array::AsArray;
+use arrow::compute::{cast, max_string, min_string};
+use arrow::datatypes::DataType;
 use arrow::record_batch::RecordBatch;
 use async_trait::async_trait;
 use data_types::{ChunkId, ChunkOrder, PartitionKey, TimestampMinMax, TransitionPartitionId};
@@ -13,31 +23,39 @@ use datafusion::catalog::Session;
 use datafusion::common::DataFusionError;
 use datafusion::logical_expr::Expr;
 use datafusion_util::stream_from_batches;
+use futures::stream::{self, StreamExt};
 use hashbrown::HashMap;
-use influxdb3_catalog::catalog::{Catalog, DatabaseSchema};
+use influxdb3_catalog::catalog::{Catalog, DatabaseSchema, TableDefinition};
 use influxdb3_id::{DbId, TableId};
-use influxdb3_wal::{CatalogOp, SnapshotDetails, WalContents, WalFileNotifier, WalOp, WriteBatch};
+use influxdb3_wal::{
+    CatalogOp, DropColumnDefinition, RenameColumnDefinition, SnapshotDetails,
+    SnapshotSequenceNumber, TableChunks, WalContents, WalFileNotifier, WalFileSequenceNumber,
+    WalOp, WriteBatch,
+};
 use iox_query::chunk_statistics::{create_chunk_statistics, NoColumnRanges};
 use iox_query::exec::Executor;
 use iox_query::frontend::reorg::ReorgPlanner;
 use iox_query::QueryChunk;
+use metric::{Attributes, Metric, Registry, U64Gauge};
 use object_store::path::Path;
-use observability_deps::tracing::{error, info};
-use parking_lot::RwLock;
+use observability_deps::tracing::{error, info, warn};
+use parking_lot::{Mutex, RwLock};
 use parquet::format::FileMetaData;
 use schema::sort::SortKey;
-use schema::Schema;
+use schema::{InfluxColumnType, Schema};
 use std::any::Any;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::oneshot;
 use tokio::sync::oneshot::Receiver;
+use tokio::sync::{oneshot, Notify};
 
 #[derive(Debug)]
 pub struct QueryableBuffer {
     pub(crate) executor: Arc<Executor>,
     catalog: Arc<Catalog>,
     last_cache_provider: Arc<LastCacheProvider>,
+    distinct_cache_provider: Arc<DistinctCacheProvider>,
     persister: Arc<Persister>,
     persisted_files: Arc<PersistedFiles>,
     buffer: Arc<RwLock<BufferState>>,
@@ -45,6 +63,66 @@ pub struct QueryableBuffer {
     /// Sends a notification to this watch channel whenever a snapshot info is persisted
     persisted_snapshot_notify_rx: tokio::sync::watch::Receiver<Option<PersistedSnapshot>>,
     persisted_snapshot_notify_tx: tokio::sync::watch::Sender<Option<PersistedSnapshot>>,
+    /// Notified whenever persisted data is cleared out of the buffer
+    buffer_drained: Arc<Notify>,
+    event_listeners: Arc<WriteEventListeners>,
+    /// How the writes replayed from the WAL on startup are written to the last caches, or `None`
+    /// once the replay is finished and new writes are written through to them
+    last_cache_replay: Mutex<Option<LastCacheReplay>>,
+    /// The replayed writes that are held back from the last caches until the replay is finished,
+    /// with [`LastCacheReplay::Parallel`]
+    replayed_last_cache_writes: Mutex<Vec<WriteBatch>>,
+    metrics: Arc<BufferMetrics>,
+    /// If set, the buffer is of a replica, which loads the snapshots that the leader persists
+    /// rather than persisting its own
+    leader_snapshots: Option<Arc<LeaderSnapshots>>,
+}
+
+/// The metrics for the data held in the buffer, for each table
+#[derive(Debug)]
+struct BufferMetrics {
+    rows: Metric<U64Gauge>,
+    bytes: Metric<U64Gauge>,
+}
+
+impl BufferMetrics {
+    fn new(metric_registry: &Registry) -> Self {
+        Self {
+            rows: metric_registry.register_metric::<U64Gauge>(
+                "influxdb3_buffered_rows",
+                "number of rows held in the buffer for the table",
+            ),
+            bytes: metric_registry.register_metric::<U64Gauge>(
+                "influxdb3_buffered_bytes",
+                "estimated size of the data held in the buffer for the table",
+            ),
+        }
+    }
+
+    /// Record the number of rows, and the estimated size, of the data buffered for each table
+    fn record(&self, buffer: &BufferState) {
+        for (db_id, table_map) in &buffer.db_to_table {
+            let Some(db_schema) = buffer.catalog.db_schema_by_id(db_id) else {
+                continue;
+            };
+            for (table_id, table_buffer) in table_map {
+                let Some(table_def) = db_schema.table_definition_by_id(table_id) else {
+                    continue;
+                };
+                let table_buffer = table_buffer.read();
+                let attributes = Attributes::from([
+                    ("db", Cow::Owned(db_schema.name.to_string())),
+                    ("table", Cow::Owned(table_def.table_name.to_string())),
+                ]);
+                self.rows
+                    .recorder(attributes.clone())
+                    .set(table_buffer.row_count() as u64);
+                self.bytes
+                    .recorder(attributes)
+                    .set(table_buffer.estimated_size() as u64);
+            }
+        }
+    }
 }
 
 impl QueryableBuffer {
@@ -53,30 +131,94 @@ impl QueryableBuffer {
         catalog: Arc<Catalog>,
         persister: Arc<Persister>,
         last_cache_provider: Arc<LastCacheProvider>,
+        distinct_cache_provider: Arc<DistinctCacheProvider>,
         persisted_files: Arc<PersistedFiles>,
         parquet_cache: Option<Arc<dyn ParquetCacheOracle>>,
+        event_listeners: Arc<WriteEventListeners>,
+        last_cache_replay: LastCacheReplay,
+        tag_values: Arc<TagValueDictionary>,
+        metric_registry: &Registry,
     ) -> Self {
-        let buffer = Arc::new(RwLock::new(BufferState::new(Arc::clone(&catalog))));
+        let buffer = Arc::new(RwLock::new(BufferState::new(
+            Arc::clone(&catalog),
+            tag_values,
+        )));
         let (persisted_snapshot_notify_tx, persisted_snapshot_notify_rx) =
             tokio::sync::watch::channel(None);
         Self {
             executor,
             catalog,
             last_cache_provider,
+            distinct_cache_provider,
             persister,
             persisted_files,
             buffer,
             parquet_cache,
             persisted_snapshot_notify_rx,
             persisted_snapshot_notify_tx,
+            buffer_drained: Arc::new(Notify::new()),
+            event_listeners,
+            last_cache_replay: Mutex::new(Some(last_cache_replay)),
+            replayed_last_cache_writes: Mutex::new(vec![]),
+            metrics: Arc::new(BufferMetrics::new(metric_registry)),
+            leader_snapshots: None,
+        }
+    }
+
+    /// Make the buffer that of a replica of the leader that `persister` persists the snapshots
+    /// of, which has loaded the snapshots up to `last_snapshot_sequence_number`
+    pub(crate) fn with_leader_snapshots(
+        mut self,
+        last_snapshot_sequence_number: Option<SnapshotSequenceNumber>,
+    ) -> Self {
+        self.leader_snapshots = Some(Arc::new(LeaderSnapshots::new(
+            last_snapshot_sequence_number,
+        )));
+        self
+    }
+
+    /// Called once the WAL has been replayed on startup, after which new writes are written
+    /// through to the last caches. With [`LastCacheReplay::Parallel`], the replayed writes that
+    /// were held back are written to them first.
+    pub fn finish_wal_replay(&self) {
+        let mut last_cache_replay = self.last_cache_replay.lock();
+        if last_cache_replay.take() == Some(LastCacheReplay::Parallel) {
+            let writes = std::mem::take(&mut *self.replayed_last_cache_writes.lock());
+            info!(
+                n_batches = writes.len(),
+                "writing the replayed wal to the last caches"
+            );
+            self.last_cache_provider
+                .write_batches_to_cache_in_parallel(&writes);
         }
     }
 
+    /// Write the contents of a WAL file to the last caches, unless the WAL is being replayed and
+    /// the replayed writes are not written through to them
+    fn write_to_last_cache(&self, write: &WalContents) {
+        match *self.last_cache_replay.lock() {
+            None | Some(LastCacheReplay::WriteThrough) => {
+                self.last_cache_provider.write_wal_contents_to_cache(write)
+            }
+            Some(LastCacheReplay::Parallel) => {
+                let cached_writes = write.ops.iter().filter_map(|op| match op {
+                    WalOp::Write(batch) => self.last_cache_provider.cached_part_of_batch(batch),
+                    WalOp::Catalog(_) => None,
+                });
+                self.replayed_last_cache_writes.lock().extend(cached_writes);
+            }
+            Some(LastCacheReplay::Cold) => (),
+        }
+    }
+
+    /// Get the chunks for a table from the buffer. If `retention_cutoff_ns` is set, chunks that
+    /// only hold data older than the cutoff are left out.
     pub fn get_table_chunks(
         &self,
         db_schema: Arc<DatabaseSchema>,
         table_name: &str,
         filters: &[Expr],
+        retention_cutoff_ns: Option<i64>,
         _projection: Option<&Vec<usize>>,
         _ctx: &dyn Session,
     ) -> Result<Vec<Arc<dyn QueryChunk>>, DataFusionError> {
@@ -86,19 +228,18 @@ impl QueryableBuffer {
 
         let influx_schema = table_def.influx_schema();
 
-        let buffer = self.buffer.read();
-
-        let Some(db_buffer) = buffer.db_to_table.get(&db_schema.id) else {
-            return Ok(vec![]);
-        };
-        let Some(table_buffer) = db_buffer.get(&table_id) else {
+        let Some(table_buffer) = self.table_buffer(db_schema.id, table_id) else {
             return Ok(vec![]);
         };
+        let table_buffer = table_buffer.read();
 
         Ok(table_buffer
             .partitioned_record_batches(Arc::clone(&table_def), filters)
             .map_err(|e| DataFusionError::Execution(format!("error getting batches {}", e)))?
             .into_iter()
+            .filter(|(_, (ts_min_max, _))| {
+                retention_cutoff_ns.map_or(true, |cutoff| ts_min_max.max >= cutoff)
+            })
             .map(|(gen_time, (ts_min_max, batches))| {
                 let row_count = batches.iter().map(|b| b.num_rows()).sum::<usize>();
                 let chunk_stats = create_chunk_statistics(
@@ -123,11 +264,42 @@ impl QueryableBuffer {
             .collect())
     }
 
-    /// Called when the wal has persisted a new file. Buffer the contents in memory and update the last cache so the data is queryable.
+    /// Get the buffer of a table, without holding the lock of the whole buffer for longer than it
+    /// takes to look it up, so that reading or writing it does not block other tables.
+    pub(crate) fn table_buffer(
+        &self,
+        db_id: DbId,
+        table_id: TableId,
+    ) -> Option<Arc<RwLock<TableBuffer>>> {
+        self.buffer
+            .read()
+            .db_to_table
+            .get(&db_id)
+            .and_then(|tables| tables.get(&table_id))
+            .map(Arc::clone)
+    }
+
+    /// Called when the wal has persisted a new file. Buffer the contents in memory and update the
+    /// last and distinct value caches so the data is queryable.
     fn buffer_contents(&self, write: WalContents) {
-        self.last_cache_provider.write_wal_contents_to_cache(&write);
-        let mut buffer = self.buffer.write();
-        buffer.buffer_ops(write.ops, &self.last_cache_provider);
+        self.write_to_last_cache(&write);
+        self.distinct_cache_provider
+            .write_wal_contents_to_cache(&write);
+        // the lock of the whole buffer is only held to apply the catalog ops and to add the
+        // buffers of new tables, the rows are buffered holding only the lock of their table:
+        let (table_writes, tag_values) = {
+            let mut buffer = self.buffer.write();
+            let table_writes = buffer.apply_ops(
+                write.ops,
+                &self.last_cache_provider,
+                &self.distinct_cache_provider,
+            );
+            (table_writes, Arc::clone(&buffer.tag_values))
+        };
+        for table_write in table_writes {
+            table_write.buffer(&tag_values);
+        }
+        self.metrics.record(&self.buffer.read());
     }
 
     /// Called when the wal has written a new file and is attempting to snapshot. Kicks off persistence of
@@ -141,6 +313,10 @@ impl QueryableBuffer {
             ?snapshot_details,
             "Buffering contents and persisting snapshotted data"
         );
+        // a distinct value that is only written in this file would otherwise be missing from the
+        // cache until it is written again:
+        self.distinct_cache_provider
+            .write_wal_contents_to_cache(&write);
         let persist_jobs = {
             let mut buffer = self.buffer.write();
 
@@ -148,16 +324,29 @@ impl QueryableBuffer {
             let catalog = Arc::clone(&buffer.catalog);
             for (database_id, table_map) in buffer.db_to_table.iter_mut() {
                 let db_schema = catalog.db_schema_by_id(database_id).expect("db exists");
-                for (table_id, table_buffer) in table_map.iter_mut() {
-                    let table_def = db_schema
-                        .table_definition_by_id(table_id)
-                        .expect("table exists");
+                for (table_id, table_buffer) in table_map.iter() {
+                    // a table that is hard deleted is removed from the catalog before the
+                    // deletion is buffered, which removes its data:
+                    let Some(table_def) = db_schema.table_definition_by_id(table_id) else {
+                        continue;
+                    };
+                    let table_name = Arc::clone(&table_def.table_name);
+                    let mut table_buffer = table_buffer.write();
                     let snapshot_chunks =
-                        table_buffer.snapshot(table_def, snapshot_details.end_time_marker);
+                        match table_buffer.snapshot(table_def, snapshot_details.end_time_marker) {
+                            Ok(snapshot_chunks) => snapshot_chunks,
+                            Err(e) => {
+                                error!(
+                                    %e,
+                                    %table_name,
+                                    "error snapshotting table buffer, its data is held in the \
+                                    buffer until the next snapshot"
+                                );
+                                continue;
+                            }
+                        };
 
                     for chunk in snapshot_chunks {
-                        let table_name =
-                            db_schema.table_id_to_name(table_id).expect("table exists");
                         let persist_job = PersistJob {
                             database_id: *database_id,
                             table_id: *table_id,
@@ -185,7 +374,12 @@ impl QueryableBuffer {
 
             // we must buffer the ops after the snapshotting as this data should not be persisted
             // with this set of wal files
-            buffer.buffer_ops(write.ops, &self.last_cache_provider);
+            buffer.buffer_ops(
+                write.ops,
+                &self.last_cache_provider,
+                &self.distinct_cache_provider,
+            );
+            self.metrics.record(&buffer);
 
             persisting_chunks
         };
@@ -199,32 +393,45 @@ impl QueryableBuffer {
         let buffer = Arc::clone(&self.buffer);
         let catalog = Arc::clone(&self.catalog);
         let notify_snapshot_tx = self.persisted_snapshot_notify_tx.clone();
+        let buffer_drained = Arc::clone(&self.buffer_drained);
         let parquet_cache = self.parquet_cache.clone();
+        let event_listeners = Arc::clone(&self.event_listeners);
+        let last_cache_provider = Arc::clone(&self.last_cache_provider);
+        let metrics = Arc::clone(&self.metrics);
 
         tokio::spawn(async move {
-            // persist the catalog if it has been updated
+            // persist the changes to the catalog if it has been updated, as a delta, or in full
+            // once enough deltas have been persisted:
+            let mut backoff = PersistBackoff::default();
             loop {
                 if !catalog.is_updated() {
                     break;
                 }
+                let inner_catalog = catalog.clone_inner();
+                let delta = inner_catalog.unpersisted_delta();
+                let checkpoint = inner_catalog.deltas_since_checkpoint()
+                    >= persister.catalog_checkpoint_interval();
                 info!(
+                    checkpoint,
                     "persisting catalog for wal file {}",
                     wal_file_number.as_u64()
                 );
-                let inner_catalog = catalog.clone_inner();
-                let sequence_number = inner_catalog.sequence_number();
 
-                match persister
-                    .persist_catalog(&Catalog::from_inner(inner_catalog))
-                    .await
-                {
+                let result = if checkpoint {
+                    persister
+                        .persist_catalog(&Catalog::from_inner(inner_catalog))
+                        .await
+                } else {
+                    persister.persist_catalog_delta(&delta).await
+                };
+                match result {
                     Ok(_) => {
-                        catalog.set_updated_false_if_sequence_matches(sequence_number);
+                        catalog.set_persisted(&delta, checkpoint);
                         break;
                     }
                     Err(e) => {
-                        error!(%e, "Error persisting catalog, sleeping and retrying...");
-                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        error!(%e, "Error persisting catalog, backing off and retrying...");
+                        backoff.wait().await;
                     }
                 }
             }
@@ -234,6 +441,20 @@ impl QueryableBuffer {
                 persist_jobs.len(),
                 wal_file_number.as_u64(),
             );
+            // the marker lists the files that are about to be persisted, so that they can be
+            // deleted on restart if the snapshot is interrupted before its file is persisted:
+            let marker = SnapshotMarker {
+                snapshot_sequence_number: snapshot_details.snapshot_sequence_number,
+                parquet_files: persist_jobs
+                    .iter()
+                    .map(|persist_job| persist_job.path.to_string())
+                    .collect(),
+            };
+            let mut backoff = PersistBackoff::default();
+            while let Err(e) = persister.persist_snapshot_marker(&marker).await {
+                error!(%e, "Error persisting snapshot marker, backing off and retrying...");
+                backoff.wait().await;
+            }
             // persist the individual files, building the snapshot as we go
             let mut persisted_snapshot = PersistedSnapshot::new(
                 persister.host_identifier_prefix().to_string(),
@@ -241,42 +462,71 @@ impl QueryableBuffer {
                 wal_file_number,
                 catalog.sequence_number(),
             );
-            let mut cache_notifiers = vec![];
-            for persist_job in persist_jobs {
-                let path = persist_job.path.to_string();
-                let database_id = persist_job.database_id;
-                let table_id = persist_job.table_id;
-                let chunk_time = persist_job.chunk_time;
-                let min_time = persist_job.timestamp_min_max.min;
-                let max_time = persist_job.timestamp_min_max.max;
-
-                let (size_bytes, meta, cache_notifier) = sort_dedupe_persist(
-                    persist_job,
-                    Arc::clone(&persister),
-                    Arc::clone(&executor),
-                    parquet_cache.clone(),
-                )
+            // the files are persisted concurrently, but are added to the snapshot in order:
+            let persisted = stream::iter(persist_jobs)
+                .map(|persist_job| {
+                    // the ids are assigned in the order of the jobs, rather than the order that
+                    // they finish in, as they order the files when they are queried:
+                    let id = ParquetFileId::new();
+                    let persister = Arc::clone(&persister);
+                    let executor = Arc::clone(&executor);
+                    let parquet_cache = parquet_cache.clone();
+                    async move {
+                        let path = persist_job.path.to_string();
+                        let database_id = persist_job.database_id;
+                        let table_id = persist_job.table_id;
+                        let chunk_time = persist_job.chunk_time;
+                        let min_time = persist_job.timestamp_min_max.min;
+                        let max_time = persist_job.timestamp_min_max.max;
+                        let column_ranges =
+                            tag_column_ranges(&persist_job.schema, &persist_job.batch);
+                        let sort_key = persist_job
+                            .sort_key
+                            .to_columns()
+                            .map(|column| column.to_string())
+                            .collect();
+
+                        let (size_bytes, meta, cache_notifier) =
+                            sort_dedupe_persist(persist_job, persister, executor, parquet_cache)
+                                .await;
+                        let parquet_file = ParquetFile {
+                            id,
+                            path,
+                            size_bytes,
+                            row_count: meta.num_rows as u64,
+                            chunk_time,
+                            min_time,
+                            max_time,
+                            indexes: ParquetFileIndexes::from_meta(&meta),
+                            column_ranges,
+                            sort_key,
+                        };
+                        (database_id, table_id, parquet_file, cache_notifier)
+                    }
+                })
+                .buffered(persister.persist_concurrency())
+                .collect::<Vec<_>>()
                 .await;
+            let mut cache_notifiers = vec![];
+            for (database_id, table_id, parquet_file, cache_notifier) in persisted {
                 cache_notifiers.push(cache_notifier);
-                persisted_snapshot.add_parquet_file(
-                    database_id,
-                    table_id,
-                    ParquetFile {
-                        id: ParquetFileId::new(),
-                        path,
-                        size_bytes,
-                        row_count: meta.num_rows as u64,
-                        chunk_time,
-                        min_time,
-                        max_time,
-                    },
-                )
+                persisted_snapshot.add_parquet_file(database_id, table_id, parquet_file);
             }
 
             // persist the snapshot file
+            let mut backoff = PersistBackoff::default();
             loop {
                 match persister.persist_snapshot(&persisted_snapshot).await {
                     Ok(_) => {
+                        // a marker that is left behind is deleted on restart, as the snapshot
+                        // finished:
+                        if let Err(e) = persister
+                            .delete_snapshot_marker(snapshot_details.snapshot_sequence_number)
+                            .await
+                        {
+                            warn!(%e, "Error deleting snapshot marker");
+                        }
+                        event_listeners.snapshot_persisted(&persisted_snapshot);
                         let persisted_snapshot = Some(persisted_snapshot.clone());
                         notify_snapshot_tx
                             .send(persisted_snapshot)
@@ -284,12 +534,21 @@ impl QueryableBuffer {
                         break;
                     }
                     Err(e) => {
-                        error!(%e, "Error persisting snapshot, sleeping and retrying...");
-                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        error!(%e, "Error persisting snapshot, backing off and retrying...");
+                        backoff.wait().await;
                     }
                 }
             }
 
+            // persist the contents of the last caches, so they are warm when the server restarts.
+            // They are only a cache, so this is not retried if it fails:
+            let last_cache_contents = last_cache_provider.contents();
+            if !last_cache_contents.caches.is_empty() {
+                if let Err(e) = persister.persist_last_caches(&last_cache_contents).await {
+                    error!(%e, "Error persisting last cache contents");
+                }
+            }
+
             // clear out the write buffer and add all the persisted files to the persisted files
             // on a background task to ensure that the cache has been populated before we clear
             // the buffer
@@ -298,12 +557,15 @@ impl QueryableBuffer {
                 for notifier in cache_notifiers.into_iter().flatten() {
                     let _ = notifier.await;
                 }
-                let mut buffer = buffer.write();
-                for (_, table_map) in buffer.db_to_table.iter_mut() {
-                    for (_, table_buffer) in table_map.iter_mut() {
-                        table_buffer.clear_snapshots();
+                let buffer = buffer.read();
+                for table_map in buffer.db_to_table.values() {
+                    for table_buffer in table_map.values() {
+                        table_buffer.write().clear_snapshots();
                     }
                 }
+                buffer.tag_values.remove_unused();
+                metrics.record(&buffer);
+                buffer_drained.notify_waiters();
 
                 persisted_files.add_persisted_snapshot_files(persisted_snapshot);
             });
@@ -314,6 +576,157 @@ impl QueryableBuffer {
         receiver
     }
 
+    /// Called when a replica has applied a WAL file of the leader that triggered a snapshot. The
+    /// data that the leader snapshot is held in the buffer until the leader has persisted the
+    /// snapshot, which is then loaded in its place.
+    async fn buffer_contents_and_load_leader_snapshot(
+        &self,
+        write: WalContents,
+        snapshot_details: SnapshotDetails,
+        leader_snapshots: Arc<LeaderSnapshots>,
+    ) -> Receiver<SnapshotDetails> {
+        self.distinct_cache_provider
+            .write_wal_contents_to_cache(&write);
+        {
+            let mut buffer = self.buffer.write();
+            let catalog = Arc::clone(&buffer.catalog);
+            for (database_id, table_map) in buffer.db_to_table.iter() {
+                let Some(db_schema) = catalog.db_schema_by_id(database_id) else {
+                    continue;
+                };
+                for (table_id, table_buffer) in table_map.iter() {
+                    let Some(table_def) = db_schema.table_definition_by_id(table_id) else {
+                        continue;
+                    };
+                    // the leader persists the chunks, so they are only held until it has:
+                    let table_name = Arc::clone(&table_def.table_name);
+                    if let Err(e) = table_buffer
+                        .write()
+                        .snapshot(table_def, snapshot_details.end_time_marker)
+                    {
+                        error!(%e, %table_name, "error snapshotting table buffer");
+                    }
+                }
+            }
+            buffer.buffer_ops(
+                write.ops,
+                &self.last_cache_provider,
+                &self.distinct_cache_provider,
+            );
+            self.metrics.record(&buffer);
+        }
+
+        let (sender, receiver) = oneshot::channel();
+
+        let persister = Arc::clone(&self.persister);
+        let persisted_files = Arc::clone(&self.persisted_files);
+        let buffer = Arc::clone(&self.buffer);
+        let notify_snapshot_tx = self.persisted_snapshot_notify_tx.clone();
+        let buffer_drained = Arc::clone(&self.buffer_drained);
+        let metrics = Arc::clone(&self.metrics);
+
+        tokio::spawn(async move {
+            let snapshots = leader_snapshots
+                .load_through(&persister, snapshot_details.snapshot_sequence_number)
+                .await;
+            // the files are added before the buffer is cleared, so that the data is never missing
+            // from queries:
+            for snapshot in snapshots {
+                info!(
+                    snapshot_sequence_number = %snapshot.snapshot_sequence_number,
+                    "loaded snapshot of the leader"
+                );
+                persisted_files.add_persisted_snapshot_files(snapshot.clone());
+                let _ = notify_snapshot_tx.send(Some(snapshot));
+            }
+            {
+                let buffer = buffer.read();
+                for table_map in buffer.db_to_table.values() {
+                    for table_buffer in table_map.values() {
+                        table_buffer.write().clear_snapshots();
+                    }
+                }
+                buffer.tag_values.remove_unused();
+                metrics.record(&buffer);
+                buffer_drained.notify_waiters();
+            }
+
+            let _ = sender.send(snapshot_details);
+        });
+
+        receiver
+    }
+
+    /// Load the snapshots that the leader took of the WAL files before `wal_file_number`, which
+    /// it removed before they were applied, and remove the data that they persisted from the
+    /// buffer, returning the sequence number of the last snapshot that was loaded
+    async fn load_missed_leader_snapshots(
+        &self,
+        wal_file_number: WalFileSequenceNumber,
+        leader_snapshots: &LeaderSnapshots,
+    ) -> Option<SnapshotSequenceNumber> {
+        let snapshots = leader_snapshots
+            .load_before(&self.persister, wal_file_number)
+            .await;
+        let last_loaded = snapshots
+            .last()
+            .map(|snapshot| snapshot.snapshot_sequence_number);
+        // the leader persisted every chunk before the end time marker of its snapshots, which is
+        // not recorded in them, but is after the newest data that they persisted:
+        let older_than_chunk_time = snapshots
+            .iter()
+            .filter(|snapshot| !snapshot.databases.is_empty())
+            .map(|snapshot| snapshot.max_time.saturating_add(1))
+            .max();
+        // the files are added before the buffer is cleared, so that the data is never missing
+        // from queries:
+        for snapshot in snapshots {
+            info!(
+                snapshot_sequence_number = %snapshot.snapshot_sequence_number,
+                "loaded snapshot of the leader for missed wal files"
+            );
+            self.persisted_files
+                .add_persisted_snapshot_files(snapshot.clone());
+            let _ = self.persisted_snapshot_notify_tx.send(Some(snapshot));
+        }
+        if let Some(older_than_chunk_time) = older_than_chunk_time {
+            let buffer = self.buffer.read();
+            for table_map in buffer.db_to_table.values() {
+                for table_buffer in table_map.values() {
+                    table_buffer
+                        .write()
+                        .remove_older_than(older_than_chunk_time);
+                }
+            }
+            buffer.tag_values.remove_unused();
+            self.metrics.record(&buffer);
+            self.buffer_drained.notify_waiters();
+        }
+
+        last_loaded
+    }
+
+    /// Returns an estimate of the memory used by all the data held in the buffer, including data
+    /// that is in the process of being persisted.
+    pub fn buffer_size_bytes(&self) -> usize {
+        self.buffer.read().estimated_size()
+    }
+
+    /// Wait until the buffer holds less than `limit` bytes, which is checked again each time
+    /// persisted data is cleared out of it
+    pub(crate) async fn wait_for_size_below(&self, limit: usize) {
+        loop {
+            let drained = self.buffer_drained.notified();
+            tokio::pin!(drained);
+            // register for the notification before checking, so that one isn't missed:
+            drained.as_mut().enable();
+            if self.buffer_size_bytes() < limit {
+                return;
+            }
+            drained.await;
+        }
+    }
+
     pub fn persisted_parquet_files(&self, db_id: DbId, table_id: TableId) -> Vec<ParquetFile> {
         self.persisted_files.get_files(db_id, table_id)
     }
@@ -336,7 +749,28 @@ impl WalFileNotifier for QueryableBuffer {
         write: WalContents,
         snapshot_details: SnapshotDetails,
     ) -> Receiver<SnapshotDetails> {
-        self.buffer_contents_and_persist_snapshotted_data(write, snapshot_details)
+        match &self.leader_snapshots {
+            Some(leader_snapshots) => {
+                self.buffer_contents_and_load_leader_snapshot(
+                    write,
+                    snapshot_details,
+                    Arc::clone(leader_snapshots),
+                )
+                .await
+            }
+            None => {
+                self.buffer_contents_and_persist_snapshotted_data(write, snapshot_details)
+                    .await
+            }
+        }
+    }
+
+    async fn notify_missed(
+        &self,
+        wal_file_number: WalFileSequenceNumber,
+    ) -> Option<SnapshotSequenceNumber> {
+        let leader_snapshots = self.leader_snapshots.as_ref()?;
+        self.load_missed_leader_snapshots(wal_file_number, leader_snapshots)
             .await
     }
 
@@ -349,25 +783,51 @@ impl WalFileNotifier for QueryableBuffer {
 pub struct BufferState {
     pub db_to_table: HashMap<DbId, TableIdToBufferMap>,
     catalog: Arc<Catalog>,
+    /// The tag values of the buffered rows, which they share with the writes they came from
+    tag_values: Arc<TagValueDictionary>,
 }
 
-type TableIdToBufferMap = HashMap<TableId, TableBuffer>;
+/// Each table buffer has its own lock, so that writes to a table do not block queries of, or
+/// writes to, other tables. The lock of the whole buffer is only written to add or remove tables.
+type TableIdToBufferMap = HashMap<TableId, Arc<RwLock<TableBuffer>>>;
 
 impl BufferState {
-    pub fn new(catalog: Arc<Catalog>) -> Self {
+    pub fn new(catalog: Arc<Catalog>, tag_values: Arc<TagValueDictionary>) -> Self {
         Self {
             db_to_table: HashMap::new(),
             catalog,
+            tag_values,
+        }
+    }
+
+    pub fn buffer_ops(
+        &mut self,
+        ops: Vec<WalOp>,
+        last_cache_provider: &LastCacheProvider,
+        distinct_cache_provider: &DistinctCacheProvider,
+    ) {
+        for table_write in self.apply_ops(ops, last_cache_provider, distinct_cache_provider) {
+            table_write.buffer(&self.tag_values);
         }
     }
 
-    pub fn buffer_ops(&mut self, ops: Vec<WalOp>, last_cache_provider: &LastCacheProvider) {
+    /// Apply the catalog ops, and add the buffers of any tables that are written to for the first
+    /// time, returning the writes to buffer in each table.
+    fn apply_ops(
+        &mut self,
+        ops: Vec<WalOp>,
+        last_cache_provider: &LastCacheProvider,
+        distinct_cache_provider: &DistinctCacheProvider,
+    ) -> Vec<TableWrite> {
+        let mut table_writes = vec![];
         for op in ops {
             match op {
-                WalOp::Write(write_batch) => self.add_write_batch(write_batch),
+                WalOp::Write(write_batch) => table_writes.extend(self.table_writes(write_batch)),
                 WalOp::Catalog(catalog_batch) => {
+                    // batches from live writes have already been applied, so this only changes the
+                    // catalog when batches are replayed:
                     self.catalog
-                        .apply_catalog_batch(&catalog_batch)
+                        .replay_catalog_batch(&catalog_batch)
                         .expect("catalog batch should apply");
 
                     let db_schema = self
@@ -387,6 +847,16 @@ impl BufferState {
                                     &definition,
                                 );
                             }
+                            CatalogOp::UpdateLastCache(definition) => {
+                                let table_def = db_schema
+                                    .table_definition_by_id(&definition.table_id)
+                                    .expect("table should exist");
+                                last_cache_provider.update_cache_from_definition(
+                                    db_schema.id,
+                                    table_def,
+                                    &definition,
+                                );
+                            }
                             CatalogOp::DeleteLastCache(cache) => {
                                 // we can ignore it if this doesn't exist for any reason
                                 let _ = last_cache_provider.delete_cache(
@@ -395,28 +865,107 @@ impl BufferState {
                                     &cache.name,
                                 );
                             }
+                            CatalogOp::CreateDistinctCache(definition) => {
+                                let table_def = db_schema
+                                    .table_definition_by_id(&definition.table_id)
+                                    .expect("table should exist");
+                                distinct_cache_provider.create_cache_from_definition(
+                                    db_schema.id,
+                                    table_def,
+                                    &definition,
+                                );
+                            }
+                            CatalogOp::DeleteDistinctCache(cache) => {
+                                // we can ignore it if this doesn't exist for any reason
+                                let _ = distinct_cache_provider.delete_cache(
+                                    db_schema.id,
+                                    cache.table_id,
+                                    &cache.name,
+                                );
+                            }
                             CatalogOp::AddFields(_) => (),
                             CatalogOp::CreateTable(_) => (),
                             CatalogOp::CreateDatabase(_) => (),
+                            CatalogOp::SetRetentionPeriod(_) => (),
+                            CatalogOp::SetWriteTimeLimits(_) => (),
+                            CatalogOp::SetSchemaLimits(_) => (),
+                            CatalogOp::SetCardinalityLimits(_) => (),
+                            CatalogOp::SetSchemaMode(_) => (),
+                            CatalogOp::SetFieldCoercion(_) => (),
+                            CatalogOp::SetCompatibilityMode(_) => (),
+                            CatalogOp::SetSeriesKeyPolicy(_) => (),
+                            CatalogOp::SetIdentifierPolicy(_) => (),
+                            CatalogOp::SetWriteRateLimit(_) => (),
+                            CatalogOp::SetGen1Duration(_) => (),
+                            CatalogOp::SetDatabaseMetadata(_) => (),
+                            CatalogOp::SetTableMetadata(_) => (),
+                            CatalogOp::SetColumnDefault(_) => (),
+                            CatalogOp::CreateDownsamplingTask(_) => (),
+                            CatalogOp::DeleteDownsamplingTask(_) => (),
+                            // the buffer and the last caches are keyed by id, and look up names
+                            // in the catalog, so there is nothing to remap:
+                            CatalogOp::RenameDatabase(_) => (),
+                            CatalogOp::RenameTable(_) => (),
+                            // deleted tables keep their data until they are hard deleted:
+                            CatalogOp::MarkTableDeleted(_) => (),
+                            CatalogOp::UndeleteTable(_) => (),
+                            CatalogOp::HardDeleteTable(definition) => {
+                                if let Some(tables) = self.db_to_table.get_mut(&db_schema.id) {
+                                    tables.remove(&definition.table_id);
+                                }
+                                last_cache_provider
+                                    .delete_caches_for_table(db_schema.id, definition.table_id);
+                                distinct_cache_provider
+                                    .delete_caches_for_table(db_schema.id, definition.table_id);
+                            }
+                            // the buffer is keyed by column id, so only the caches need to
+                            // be updated:
+                            CatalogOp::DropColumn(DropColumnDefinition { table_id, .. })
+                            | CatalogOp::RenameColumn(RenameColumnDefinition {
+                                table_id, ..
+                            }) => {
+                                if let Some(table_def) = db_schema.table_definition_by_id(&table_id)
+                                {
+                                    last_cache_provider.recreate_caches_for_table(
+                                        db_schema.id,
+                                        Arc::clone(&table_def),
+                                    );
+                                    distinct_cache_provider
+                                        .recreate_caches_for_table(db_schema.id, table_def);
+                                }
+                            }
                         }
                     }
                 }
             }
         }
+        table_writes
+    }
+
+    /// Returns the sum of the estimated sizes of all table buffers
+    pub fn estimated_size(&self) -> usize {
+        self.db_to_table
+            .values()
+            .flat_map(|tables| tables.values())
+            .map(|table_buffer| table_buffer.read().estimated_size())
+            .sum()
     }
 
-    fn add_write_batch(&mut self, write_batch: WriteBatch) {
+    fn table_writes(&mut self, write_batch: WriteBatch) -> Vec<TableWrite> {
         let db_schema = self
             .catalog
             .db_schema_by_id(&write_batch.database_id)
             .expect("database should exist");
         let database_buffer = self.db_to_table.entry(write_batch.database_id).or_default();
 
+        let mut table_writes = vec![];
         for (table_id, table_chunks) in write_batch.table_chunks {
+            // the table may have been hard deleted since the write, if it is replayed from the
+            // WAL, with a catalog that was persisted after the deletion:
+            let Some(table_def) = db_schema.table_definition_by_id(&table_id) else {
+                continue;
+            };
             let table_buffer = database_buffer.entry(table_id).or_insert_with(|| {
-                let table_def = db_schema
-                    .table_definition_by_id(&table_id)
-                    .expect("table should exist");
                 // TODO: can we have the primary key stored on the table definition (we already have
                 // the series key, so that doesn't seem like too much of a stretch).
                 let sort_key = table_def
@@ -427,11 +976,42 @@ impl BufferState {
                     .collect::<Vec<_>>();
                 let index_columns = table_def.index_column_ids();
 
-                TableBuffer::new(index_columns, SortKey::from(sort_key))
+                Arc::new(RwLock::new(TableBuffer::new(
+                    index_columns,
+                    SortKey::from(sort_key),
+                )))
             });
-            for (chunk_time, chunk) in table_chunks.chunk_time_to_chunk {
-                table_buffer.buffer_chunk(chunk_time, chunk.rows);
-            }
+            table_writes.push(TableWrite {
+                db_id: write_batch.database_id,
+                table_id,
+                table_def,
+                table_buffer: Arc::clone(table_buffer),
+                table_chunks,
+            });
+        }
+        table_writes
+    }
+}
+
+/// The rows written to a table in a write batch
+#[derive(Debug)]
+struct TableWrite {
+    db_id: DbId,
+    table_id: TableId,
+    table_def: Arc<TableDefinition>,
+    table_buffer: Arc<RwLock<TableBuffer>>,
+    table_chunks: TableChunks,
+}
+
+impl TableWrite {
+    /// Buffer the rows, holding only the lock of the table buffer
+    fn buffer(mut self, tag_values: &TagValueDictionary) {
+        for chunk in self.table_chunks.chunk_time_to_chunk.values_mut() {
+            tag_values.intern_rows(self.db_id, self.table_id, &mut chunk.rows);
+        }
+        let mut table_buffer = self.table_buffer.write();
+        for (chunk_time, chunk) in self.table_chunks.chunk_time_to_chunk {
+            table_buffer.buffer_chunk(chunk_time, chunk.rows, &self.table_def.column_defaults);
         }
     }
 }
@@ -449,6 +1029,43 @@ struct PersistJob {
     sort_key: SortKey,
 }
 
+/// The minimum and maximum values of each of the tag columns in a batch that has any values
+pub(crate) fn tag_column_ranges(
+    schema: &Schema,
+    batch: &RecordBatch,
+) -> BTreeMap<String, ColumnValueRange> {
+    schema
+        .iter()
+        .filter(|(column_type, _)| *column_type == InfluxColumnType::Tag)
+        .filter_map(|(_, field)| {
+            let column = batch.column_by_name(field.name())?;
+            let values = cast(column, &DataType::Utf8).ok()?;
+            let values = values.as_string::<i32>();
+            Some((
+                field.name().to_string(),
+                ColumnValueRange {
+                    min: min_string(values)?.to_string(),
+                    max: max_string(values)?.to_string(),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Tag columns get bloom filters, so that queries filtering on a tag value can skip the row groups
+/// that do not have it, which the min and max statistics of the row groups rarely allow. They are
+/// sized for `row_count` distinct values, which is at most the number of rows in the file.
+pub(crate) fn tag_bloom_filters(schema: &Schema, row_count: u64) -> BloomFilters {
+    BloomFilters {
+        columns: schema
+            .iter()
+            .filter(|(column_type, _)| *column_type == InfluxColumnType::Tag)
+            .map(|(_, field)| field.name().to_string())
+            .collect(),
+        distinct_values: row_count,
+    }
+}
+
 async fn sort_dedupe_persist(
     persist_job: PersistJob,
     persister: Arc<Persister>,
@@ -505,13 +1122,19 @@ async fn sort_dedupe_persist(
     // Execute the plan and return compacted record batches
     let data = ctx.collect(physical_plan).await.unwrap();
 
+    let bloom_filters = tag_bloom_filters(
+        &persist_job.schema,
+        data.iter().map(|batch| batch.num_rows() as u64).sum(),
+    );
+
     // keep attempting to persist forever. If we can't reach the object store, we'll stop accepting
     // writes elsewhere in the system, so we need to keep trying to persist.
+    let mut backoff = PersistBackoff::default();
     loop {
         let batch_stream = stream_from_batches(persist_job.schema.as_arrow(), data.clone());
 
         match persister
-            .persist_parquet_file(persist_job.path.clone(), batch_stream)
+            .persist_parquet_file(persist_job.path.clone(), batch_stream, &bloom_filters)
             .await
         {
             Ok((size_bytes, meta)) => {
@@ -519,18 +1142,23 @@ async fn sort_dedupe_persist(
                 if let Some(pq) = parquet_cache {
                     let (cache_request, cache_notify_rx) =
                         CacheRequest::create(Path::from(persist_job.path.to_string()));
-                    pq.register(cache_request);
+                    pq.register(
+                        cache_request
+                            .with_file_info(size_bytes as usize, persist_job.timestamp_min_max.max),
+                    );
                     return (size_bytes, meta, Some(cache_notify_rx));
                 } else {
                     return (size_bytes, meta, None);
                 }
             }
             Err(e) => {
-                error!(
-                    "Error persisting parquet file {:?}, sleeping and retrying...",
-                    e
-                );
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                let e = persister::Error::PersistParquetFile {
+                    path: persist_job.path.to_string(),
+                    attempt: backoff.attempt() + 1,
+                    source: Box::new(e),
+                };
+                error!(%e, "Error persisting parquet file, backing off and retrying...");
+                backoff.wait().await;
             }
         }
     }
@@ -0,0 +1,201 @@
+//! Limits on the rate at which data can be written to each database
+
+use std::{collections::HashMap, time::Duration};
+
+use influxdb3_catalog::catalog::DatabaseSchema;
+use influxdb3_id::DbId;
+use influxdb3_wal::WriteRateLimit;
+use iox_time::Time;
+use parking_lot::Mutex;
+
+/// Enforces the [`WriteRateLimit`] of each database, using a token bucket for each of the lines
+/// and bytes written per second.
+///
+/// Each bucket holds up to one second's worth of tokens, so a database can burst up to its limit
+/// after being idle. A single write that is larger than the limit is accepted once the bucket is
+/// full, and the bucket then has to refill from empty before the next write is accepted.
+#[derive(Debug, Default)]
+pub struct WriteRateLimiter {
+    buckets: Mutex<HashMap<DbId, DatabaseBuckets>>,
+}
+
+#[derive(Debug)]
+struct DatabaseBuckets {
+    limit: WriteRateLimit,
+    lines: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl DatabaseBuckets {
+    fn new(limit: WriteRateLimit, now: Time) -> Self {
+        Self {
+            limit,
+            lines: limit
+                .lines_per_second
+                .map(|rate| TokenBucket::new(rate, now)),
+            bytes: limit
+                .bytes_per_second
+                .map(|rate| TokenBucket::new(rate, now)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last_refill: Time,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, now: Time) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Time) {
+        if let Some(elapsed) = now.checked_duration_since(self.last_refill) {
+            self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+            self.last_refill = now;
+        }
+    }
+
+    /// How long until there are enough tokens in the bucket to take `cost` of them
+    fn wait_for(&self, cost: f64) -> Duration {
+        let needed = cost.min(self.rate) - self.tokens;
+        if needed <= 0.0 || self.rate <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(needed / self.rate)
+        }
+    }
+}
+
+impl WriteRateLimiter {
+    /// Take tokens for a write of `lines` lines and `bytes` bytes to the database. If the write
+    /// would exceed the rate limit of the database, no tokens are taken and the time to wait
+    /// before retrying is returned instead.
+    pub(crate) fn check(
+        &self,
+        db_schema: &DatabaseSchema,
+        lines: usize,
+        bytes: usize,
+        now: Time,
+    ) -> Result<(), Duration> {
+        let limit = db_schema.write_rate_limit;
+        let mut buckets = self.buckets.lock();
+        if limit.is_unlimited() {
+            buckets.remove(&db_schema.id);
+            return Ok(());
+        }
+
+        let db_buckets = buckets
+            .entry(db_schema.id)
+            .or_insert_with(|| DatabaseBuckets::new(limit, now));
+        // start from full buckets when the limit is changed:
+        if db_buckets.limit != limit {
+            *db_buckets = DatabaseBuckets::new(limit, now);
+        }
+
+        let mut retry_after = Duration::ZERO;
+        for (bucket, cost) in [
+            (db_buckets.lines.as_mut(), lines),
+            (db_buckets.bytes.as_mut(), bytes),
+        ] {
+            if let Some(bucket) = bucket {
+                bucket.refill(now);
+                retry_after = retry_after.max(bucket.wait_for(cost as f64));
+            }
+        }
+        if !retry_after.is_zero() {
+            return Err(retry_after);
+        }
+
+        for (bucket, cost) in [
+            (db_buckets.lines.as_mut(), lines),
+            (db_buckets.bytes.as_mut(), bytes),
+        ] {
+            if let Some(bucket) = bucket {
+                bucket.tokens -= cost as f64;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn db_schema(write_rate_limit: WriteRateLimit) -> DatabaseSchema {
+        let mut db_schema = DatabaseSchema::new(DbId::from(0), Arc::from("db"));
+        db_schema.write_rate_limit = write_rate_limit;
+        db_schema
+    }
+
+    #[test]
+    fn lines_per_second_limit() {
+        let limiter = WriteRateLimiter::default();
+        let db_schema = db_schema(WriteRateLimit {
+            lines_per_second: Some(10),
+            bytes_per_second: None,
+        });
+        let start = Time::from_timestamp_nanos(0);
+
+        limiter.check(&db_schema, 6, 100, start).unwrap();
+        // only 4 lines are left in the bucket, so the next write has to wait for two more:
+        let retry_after = limiter.check(&db_schema, 6, 100, start).unwrap_err();
+        assert_eq!(Duration::from_millis(200), retry_after);
+        // the rejected write did not take any tokens:
+        limiter.check(&db_schema, 4, 100, start).unwrap();
+
+        // after half a second, 5 lines have been added back:
+        let later = start + Duration::from_millis(500);
+        limiter.check(&db_schema, 5, 100, later).unwrap();
+        assert!(limiter.check(&db_schema, 1, 100, later).is_err());
+    }
+
+    #[test]
+    fn oversized_write_accepted_when_bucket_full() {
+        let limiter = WriteRateLimiter::default();
+        let db_schema = db_schema(WriteRateLimit {
+            lines_per_second: None,
+            bytes_per_second: Some(1_000),
+        });
+        let start = Time::from_timestamp_nanos(0);
+
+        limiter.check(&db_schema, 1, 3_000, start).unwrap();
+        // the bucket is now 2,000 bytes in debt, so it takes 3 seconds to be full again:
+        let retry_after = limiter.check(&db_schema, 1, 1_000, start).unwrap_err();
+        assert_eq!(Duration::from_secs(3), retry_after);
+        limiter
+            .check(&db_schema, 1, 1_000, start + Duration::from_secs(3))
+            .unwrap();
+    }
+
+    #[test]
+    fn changing_limit_resets_buckets() {
+        let limiter = WriteRateLimiter::default();
+        let start = Time::from_timestamp_nanos(0);
+        let limited = db_schema(WriteRateLimit {
+            lines_per_second: Some(1),
+            bytes_per_second: None,
+        });
+        limiter.check(&limited, 1, 10, start).unwrap();
+        assert!(limiter.check(&limited, 1, 10, start).is_err());
+
+        let unlimited = db_schema(WriteRateLimit::default());
+        limiter.check(&unlimited, 1_000, 10, start).unwrap();
+        let raised = db_schema(WriteRateLimit {
+            lines_per_second: Some(100),
+            bytes_per_second: None,
+        });
+        limiter.check(&raised, 100, 10, start).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+//! Replication of the writes to another host, the leader, to a read-only replica, which follows
+//! the leader's WAL with a [`WalFollower`](influxdb3_wal::follower::WalFollower), and loads the
+//! snapshots that the leader persists, so that it can be queried in place of the leader, e.g., to
+//! scale out queries, or to fail over to.
+//!
+//! The replica holds the writes in the leader's WAL files in its buffer, as the leader does, until
+//! the leader has persisted the snapshot that they are in, which the replica then loads instead of
+//! persisting one of its own. The compactions of the leader are applied when the replica starts,
+//! but not those that it runs later, so the leader must keep the files that it compacts for as
+//! long as a replica may query them.
+
+use crate::persister::{PersistBackoff, Persister};
+use crate::PersistedSnapshot;
+use influxdb3_wal::{SnapshotSequenceNumber, WalFileSequenceNumber};
+use observability_deps::tracing::{error, info};
+use std::time::Duration;
+
+/// The default interval that a replica polls the leader's WAL for new files on
+pub const DEFAULT_REPLICATION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How a write buffer replicates the writes to the leader, with
+/// [`WriteBufferImpl::new_follower`](crate::write_buffer::WriteBufferImpl::new_follower)
+#[derive(Debug, Clone, Copy)]
+pub struct ReplicationConfig {
+    /// How often the leader's WAL is polled for new files, which bounds how far the replica lags
+    /// behind the leader when it keeps up with it
+    pub poll_interval: Duration,
+}
+
+/// The snapshots of the leader that a replica has loaded
+#[derive(Debug)]
+pub(crate) struct LeaderSnapshots {
+    last_loaded: tokio::sync::Mutex<SnapshotSequenceNumber>,
+}
+
+impl LeaderSnapshots {
+    pub(crate) fn new(last_loaded: Option<SnapshotSequenceNumber>) -> Self {
+        Self {
+            last_loaded: tokio::sync::Mutex::new(last_loaded.unwrap_or_default()),
+        }
+    }
+
+    /// Load the snapshots that the leader has persisted since those that were loaded before, up
+    /// to and including `snapshot_sequence_number`, oldest first, waiting for the leader to
+    /// persist it if it has not yet. A replica that missed some of the leader's WAL files gets the
+    /// snapshots that they triggered too.
+    pub(crate) async fn load_through(
+        &self,
+        persister: &Persister,
+        snapshot_sequence_number: SnapshotSequenceNumber,
+    ) -> Vec<PersistedSnapshot> {
+        let mut last_loaded = self.last_loaded.lock().await;
+        if *last_loaded >= snapshot_sequence_number {
+            return vec![];
+        }
+
+        let mut backoff = PersistBackoff::default();
+        loop {
+            match persister.load_snapshots_after(*last_loaded).await {
+                Ok(mut snapshots)
+                    if snapshots.first().is_some_and(|snapshot| {
+                        snapshot.snapshot_sequence_number >= snapshot_sequence_number
+                    }) =>
+                {
+                    // the snapshots are loaded most recent first:
+                    snapshots.reverse();
+                    *last_loaded = snapshots
+                        .last()
+                        .expect("there is at least one snapshot")
+                        .snapshot_sequence_number;
+                    return snapshots;
+                }
+                Ok(_) => info!(
+                    %snapshot_sequence_number,
+                    "waiting for the leader to persist its snapshot"
+                ),
+                Err(e) => {
+                    error!(%e, "Error loading snapshots of the leader, backing off and retrying...")
+                }
+            }
+            backoff.wait().await;
+        }
+    }
+
+    /// Load the snapshots that the leader has persisted since those that were loaded before, which
+    /// were triggered by WAL files before `wal_file_number`, oldest first. These are the snapshots
+    /// of the WAL files that the leader removed before the replica applied them, which it only does
+    /// once they are snapshot, so this does not wait for the leader.
+    pub(crate) async fn load_before(
+        &self,
+        persister: &Persister,
+        wal_file_number: WalFileSequenceNumber,
+    ) -> Vec<PersistedSnapshot> {
+        let mut last_loaded = self.last_loaded.lock().await;
+
+        let mut backoff = PersistBackoff::default();
+        loop {
+            match persister.load_snapshots_after(*last_loaded).await {
+                Ok(snapshots) => {
+                    // the snapshots are loaded most recent first:
+                    let snapshots = snapshots
+                        .into_iter()
+                        .rev()
+                        .filter(|snapshot| snapshot.wal_file_sequence_number < wal_file_number)
+                        .collect::<Vec<_>>();
+                    if let Some(last) = snapshots.last() {
+                        *last_loaded = last.snapshot_sequence_number;
+                    }
+                    return snapshots;
+                }
+                Err(e) => {
+                    error!(%e, "Error loading snapshots of the leader, backing off and retrying...")
+                }
+            }
+            backoff.wait().await;
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Conversion of Arrow [`RecordBatch`]es into [`WriteRow`]s, so that they can be written to the
+//! buffer without first being serialized to line protocol
+
+use arrow::{
+    array::AsArray,
+    compute::cast,
+    datatypes::{DataType, Float64Type, Int64Type, TimeUnit, TimestampNanosecondType, UInt64Type},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use schema::TIME_COLUMN_NAME;
+use thiserror::Error;
+
+use crate::{WriteFieldValue, WriteRow};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("column '{name}' has unsupported type {data_type}")]
+    UnsupportedColumnType { name: String, data_type: DataType },
+
+    #[error("error converting tag column '{name}': {source}")]
+    TagConversion {
+        name: String,
+        #[source]
+        source: ArrowError,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Convert the rows in `batch` into [`WriteRow`]s for the table `table_name`.
+///
+/// Columns are mapped onto the v1 data model as follows:
+/// * dictionary encoded string columns are tags
+/// * a nanosecond timestamp column named `time` provides the timestamp of each row; rows without
+///   a time will be given the ingest time when written
+/// * all other columns are fields, and must be one of `Int64`, `UInt64`, `Float64`, `Utf8`, or
+///   `Boolean`
+///
+/// Null values are omitted from the resulting row, and rows that have no non-null fields are
+/// skipped entirely, as they would not be valid in line protocol.
+pub fn record_batch_to_rows(table_name: &str, batch: &RecordBatch) -> Result<Vec<WriteRow>> {
+    let mut rows: Vec<WriteRow> = (0..batch.num_rows())
+        .map(|_| WriteRow {
+            table_name: table_name.to_string(),
+            tags: vec![],
+            fields: vec![],
+            timestamp: None,
+        })
+        .collect();
+
+    let schema = batch.schema();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        let name = field.name();
+        match field.data_type() {
+            DataType::Dictionary(_, value_type) if value_type.as_ref() == &DataType::Utf8 => {
+                let tags =
+                    cast(column, &DataType::Utf8).map_err(|source| Error::TagConversion {
+                        name: name.to_string(),
+                        source,
+                    })?;
+                for (row, value) in rows.iter_mut().zip(tags.as_string::<i32>().iter()) {
+                    // empty tag values are not allowed in line protocol, so treat them as null:
+                    if let Some(value) = value.filter(|v| !v.is_empty()) {
+                        row.tags.push((name.to_string(), value.to_string()));
+                    }
+                }
+            }
+            DataType::Timestamp(TimeUnit::Nanosecond, _) if name == TIME_COLUMN_NAME => {
+                let times = column.as_primitive::<TimestampNanosecondType>();
+                for (row, time) in rows.iter_mut().zip(times.iter()) {
+                    row.timestamp = time;
+                }
+            }
+            DataType::Int64 => push_fields(
+                &mut rows,
+                name,
+                column
+                    .as_primitive::<Int64Type>()
+                    .iter()
+                    .map(|v| v.map(WriteFieldValue::I64)),
+            ),
+            DataType::UInt64 => push_fields(
+                &mut rows,
+                name,
+                column
+                    .as_primitive::<UInt64Type>()
+                    .iter()
+                    .map(|v| v.map(WriteFieldValue::U64)),
+            ),
+            DataType::Float64 => push_fields(
+                &mut rows,
+                name,
+                column
+                    .as_primitive::<Float64Type>()
+                    .iter()
+                    .map(|v| v.map(WriteFieldValue::F64)),
+            ),
+            DataType::Utf8 => push_fields(
+                &mut rows,
+                name,
+                column
+                    .as_string::<i32>()
+                    .iter()
+                    .map(|v| v.map(|s| WriteFieldValue::String(s.to_string()))),
+            ),
+            DataType::Boolean => push_fields(
+                &mut rows,
+                name,
+                column
+                    .as_boolean()
+                    .iter()
+                    .map(|v| v.map(WriteFieldValue::Boolean)),
+            ),
+            data_type => {
+                return Err(Error::UnsupportedColumnType {
+                    name: name.to_string(),
+                    data_type: data_type.clone(),
+                })
+            }
+        }
+    }
+
+    rows.retain(|row| !row.fields.is_empty());
+
+    Ok(rows)
+}
+
+fn push_fields(
+    rows: &mut [WriteRow],
+    name: &str,
+    values: impl Iterator<Item = Option<WriteFieldValue>>,
+) {
+    for (row, value) in rows.iter_mut().zip(values) {
+        if let Some(value) = value {
+            row.fields.push((name.to_string(), value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{
+            BooleanArray, DictionaryArray, Float64Array, Int64Array, StringArray,
+            TimestampNanosecondArray,
+        },
+        datatypes::{Field, Int32Type, Schema},
+    };
+
+    use super::*;
+
+    #[test]
+    fn convert_record_batch() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "host",
+                DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+                true,
+            ),
+            Field::new("usage", DataType::Float64, true),
+            Field::new("count", DataType::Int64, true),
+            Field::new("status", DataType::Utf8, true),
+            Field::new("up", DataType::Boolean, true),
+            Field::new(
+                "time",
+                DataType::Timestamp(TimeUnit::Nanosecond, None),
+                true,
+            ),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(
+                    vec![Some("a"), None, Some("c")]
+                        .into_iter()
+                        .collect::<DictionaryArray<Int32Type>>(),
+                ),
+                Arc::new(Float64Array::from(vec![Some(0.5), None, None])),
+                Arc::new(Int64Array::from(vec![Some(1), Some(2), None])),
+                Arc::new(StringArray::from(vec![Some("ok"), None, None])),
+                Arc::new(BooleanArray::from(vec![Some(true), None, None])),
+                Arc::new(TimestampNanosecondArray::from(vec![
+                    Some(10),
+                    None,
+                    Some(30),
+                ])),
+            ],
+        )
+        .unwrap();
+
+        let rows = record_batch_to_rows("cpu", &batch).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                WriteRow {
+                    table_name: "cpu".to_string(),
+                    tags: vec![("host".to_string(), "a".to_string())],
+                    fields: vec![
+                        ("usage".to_string(), WriteFieldValue::F64(0.5)),
+                        ("count".to_string(), WriteFieldValue::I64(1)),
+                        (
+                            "status".to_string(),
+                            WriteFieldValue::String("ok".to_string())
+                        ),
+                        ("up".to_string(), WriteFieldValue::Boolean(true)),
+                    ],
+                    timestamp: Some(10),
+                },
+                WriteRow {
+                    table_name: "cpu".to_string(),
+                    tags: vec![],
+                    fields: vec![("count".to_string(), WriteFieldValue::I64(2))],
+                    timestamp: None,
+                },
+                // the third row has no fields, so is skipped
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_column_type() {
+        let schema = Arc::new(Schema::new(vec![Field::new("val", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(arrow::array::Int32Array::from(vec![1]))],
+        )
+        .unwrap();
+        assert!(matches!(
+            record_batch_to_rows("cpu", &batch),
+            Err(Error::UnsupportedColumnType { name, data_type: DataType::Int32 }) if name == "val"
+        ));
+    }
+}
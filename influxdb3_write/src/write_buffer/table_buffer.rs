@@ -1,23 +1,31 @@
 //! The in memory buffer of a table that can be quickly added to and queried
 
+use crate::write_buffer::persisted_files::TimeRange;
 use arrow::array::{
-    Array, ArrayBuilder, ArrayRef, BooleanBuilder, Float64Builder, GenericByteDictionaryBuilder,
-    Int64Builder, StringArray, StringBuilder, StringDictionaryBuilder, TimestampNanosecondBuilder,
-    UInt64Builder,
+    Array, ArrayRef, AsArray, BooleanArray, BooleanBuilder, Float64Builder, Int64Builder,
+    StringBuilder, StringDictionaryBuilder, TimestampNanosecondBuilder, UInt32Array, UInt64Builder,
+};
+use arrow::buffer::{BooleanBuffer, Buffer};
+use arrow::compute::{and, concat, filter_record_batch, take};
+use arrow::datatypes::{
+    DataType, Float64Type, Int32Type, Int64Type, TimestampNanosecondType, UInt64Type,
 };
-use arrow::datatypes::{GenericStringType, Int32Type};
 use arrow::record_batch::RecordBatch;
+use arrow::row::{RowConverter, SortField};
+use arrow::util::bit_util;
 use data_types::TimestampMinMax;
-use datafusion::logical_expr::{BinaryExpr, Expr};
-use hashbrown::HashMap;
-use influxdb3_catalog::catalog::TableDefinition;
+use datafusion::logical_expr::utils::split_conjunction;
+use datafusion::logical_expr::{BinaryExpr, Expr, Operator};
+use datafusion::scalar::ScalarValue;
+use hashbrown::{DefaultHashBuilder, HashMap, HashTable};
+use influxdb3_catalog::catalog::{TableDefinition, TIME_COLUMN_NAME};
 use influxdb3_id::ColumnId;
-use influxdb3_wal::{FieldData, Row};
+use influxdb3_wal::{Field, FieldData, Row};
 use observability_deps::tracing::{debug, error, info};
 use schema::sort::SortKey;
 use schema::{InfluxColumnType, InfluxFieldType, Schema, SchemaBuilder};
-use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, HashSet};
+use std::hash::BuildHasher;
 use std::mem::size_of;
 use std::sync::Arc;
 use thiserror::Error;
@@ -50,19 +58,34 @@ impl TableBuffer {
         }
     }
 
-    pub fn buffer_chunk(&mut self, chunk_time: i64, rows: Vec<Row>) {
+    /// Buffer rows in the chunk for `chunk_time`. Field columns that are missing from a row are
+    /// given their value in `column_defaults`, if they have one, or null otherwise.
+    pub fn buffer_chunk(
+        &mut self,
+        chunk_time: i64,
+        rows: Vec<Row>,
+        column_defaults: &BTreeMap<ColumnId, FieldData>,
+    ) {
         let buffer_chunk = self
             .chunk_time_to_chunks
             .entry(chunk_time)
             .or_insert_with(|| MutableTableChunk {
                 timestamp_min: i64::MAX,
                 timestamp_max: i64::MIN,
+                segments: Default::default(),
+                segment_count: 0,
                 data: Default::default(),
                 row_count: 0,
+                estimated_size: 0,
                 index: self.index.clone(),
+                hash_builder: Default::default(),
+                series_keys: vec![],
+                series_ids: HashTable::new(),
+                last_write_rows: HashTable::new(),
+                live_rows: BTreeMap::new(),
             });
 
-        buffer_chunk.add_rows(rows);
+        buffer_chunk.add_rows(rows, column_defaults);
     }
 
     /// Produce a partitioned set of record batches along with their min/max timestamp
@@ -73,9 +96,16 @@ impl TableBuffer {
         table_def: Arc<TableDefinition>,
         filter: &[Expr],
     ) -> Result<HashMap<i64, (TimestampMinMax, Vec<RecordBatch>)>> {
+        let filter = BufferFilter::new(&table_def, filter);
         let mut batches = HashMap::new();
         let schema = table_def.schema.as_arrow();
         for sc in &self.snapshotting_chunks {
+            if !filter
+                .time_range
+                .overlaps(sc.timestamp_min_max.min, sc.timestamp_min_max.max)
+            {
+                continue;
+            }
             let cols: std::result::Result<Vec<_>, _> = schema
                 .fields()
                 .iter()
@@ -96,12 +126,15 @@ impl TableBuffer {
             v.push(rb);
         }
         for (t, c) in &self.chunk_time_to_chunks {
+            if !filter.time_range.overlaps(c.timestamp_min, c.timestamp_max) {
+                continue;
+            }
             let ts_min_max = TimestampMinMax::new(c.timestamp_min, c.timestamp_max);
             let (ts, v) = batches
                 .entry(*t)
                 .or_insert_with(|| (ts_min_max, Vec::new()));
             *ts = ts.union(&ts_min_max);
-            v.push(c.record_batch(Arc::clone(&table_def), filter)?);
+            v.extend(c.record_batches(Arc::clone(&table_def), &filter)?);
         }
         Ok(batches)
     }
@@ -111,11 +144,18 @@ impl TableBuffer {
         table_def: Arc<TableDefinition>,
         filter: &[Expr],
     ) -> Result<Vec<RecordBatch>> {
+        let filter = BufferFilter::new(&table_def, filter);
         let mut batches =
             Vec::with_capacity(self.snapshotting_chunks.len() + self.chunk_time_to_chunks.len());
         let schema = table_def.schema.as_arrow();
 
         for sc in &self.snapshotting_chunks {
+            if !filter
+                .time_range
+                .overlaps(sc.timestamp_min_max.min, sc.timestamp_min_max.max)
+            {
+                continue;
+            }
             let cols: std::result::Result<Vec<_>, _> = schema
                 .fields()
                 .iter()
@@ -134,7 +174,10 @@ impl TableBuffer {
         }
 
         for c in self.chunk_time_to_chunks.values() {
-            batches.push(c.record_batch(Arc::clone(&table_def), filter)?)
+            if !filter.time_range.overlaps(c.timestamp_min, c.timestamp_max) {
+                continue;
+            }
+            batches.extend(c.record_batches(Arc::clone(&table_def), &filter)?)
         }
 
         Ok(batches)
@@ -160,6 +203,37 @@ impl TableBuffer {
         timestamp_min_max
     }
 
+    /// Returns a cheap estimate of the memory used by this table buffer, based on the size of the
+    /// values that have been buffered and the chunks that are being snapshot.
+    pub fn estimated_size(&self) -> usize {
+        let buffered: usize = self
+            .chunk_time_to_chunks
+            .values()
+            .map(|c| c.estimated_size)
+            .sum();
+        let snapshotting: usize = self
+            .snapshotting_chunks
+            .iter()
+            .map(|sc| sc.record_batch.get_array_memory_size())
+            .sum();
+        buffered + snapshotting
+    }
+
+    /// Returns the number of rows held in this table buffer, including those being snapshot
+    pub fn row_count(&self) -> usize {
+        let buffered: usize = self
+            .chunk_time_to_chunks
+            .values()
+            .map(|c| c.row_count)
+            .sum();
+        let snapshotting: usize = self
+            .snapshotting_chunks
+            .iter()
+            .map(|sc| sc.record_batch.num_rows())
+            .sum();
+        buffered + snapshotting
+    }
+
     /// Returns an estimate of the size of this table buffer based on the data and index sizes.
     #[allow(dead_code)]
     pub fn computed_size(&self) -> usize {
@@ -169,47 +243,59 @@ impl TableBuffer {
             for biulder in c.data.values() {
                 size += size_of::<ColumnId>() + size_of::<String>() + biulder.size();
             }
+            for segments in c.segments.values() {
+                size += segments
+                    .iter()
+                    .map(|a| a.get_array_memory_size())
+                    .sum::<usize>();
+            }
 
             size += c.index.size();
+            size += c.dedupe_size();
         }
 
         size
     }
 
+    /// Snapshot the chunks older than `older_than_chunk_time`, sorted and deduplicated, which
+    /// are held until [`Self::clear_snapshots`] is called. If any of the chunks cannot be
+    /// snapshot, an error is returned and they are all left in the buffer.
     pub fn snapshot(
         &mut self,
         table_def: Arc<TableDefinition>,
         older_than_chunk_time: i64,
-    ) -> Vec<SnapshotChunk> {
+    ) -> Result<Vec<SnapshotChunk>> {
         info!(%older_than_chunk_time, "Snapshotting table buffer");
-        let keys_to_remove = self
+        let snapshot_chunks = self
             .chunk_time_to_chunks
-            .keys()
-            .filter(|k| **k < older_than_chunk_time)
-            .copied()
-            .collect::<Vec<_>>();
-        self.snapshotting_chunks = keys_to_remove
-            .into_iter()
-            .map(|chunk_time| {
-                let chunk = self.chunk_time_to_chunks.remove(&chunk_time).unwrap();
-                let timestamp_min_max = chunk.timestamp_min_max();
-                let (schema, record_batch) = chunk.into_schema_record_batch(Arc::clone(&table_def));
-
-                SnapshotChunk {
-                    chunk_time,
-                    timestamp_min_max,
+            .range(..older_than_chunk_time)
+            .map(|(chunk_time, chunk)| {
+                let (schema, record_batch) = chunk.schema_record_batch(&table_def)?;
+                let record_batch = sort_dedupe_record_batch(record_batch, &self.sort_key)?;
+
+                Ok(SnapshotChunk {
+                    chunk_time: *chunk_time,
+                    timestamp_min_max: chunk.timestamp_min_max(),
                     record_batch,
                     schema,
-                }
+                })
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>>>()?;
 
-        self.snapshotting_chunks.clone()
+        self.chunk_time_to_chunks = self.chunk_time_to_chunks.split_off(&older_than_chunk_time);
+        self.snapshotting_chunks = snapshot_chunks.clone();
+        Ok(snapshot_chunks)
     }
 
     pub fn clear_snapshots(&mut self) {
         self.snapshotting_chunks.clear();
     }
+
+    /// Remove the chunks older than `older_than_chunk_time` without snapshotting them, as they were
+    /// persisted by another host, i.e., the leader of a replica
+    pub fn remove_older_than(&mut self, older_than_chunk_time: i64) {
+        self.chunk_time_to_chunks = self.chunk_time_to_chunks.split_off(&older_than_chunk_time);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -242,152 +328,137 @@ impl std::fmt::Debug for TableBuffer {
     }
 }
 
+/// The number of rows after which the builders of a chunk are finished into arrays. Queries share
+/// the arrays of these sealed segments without copying them, so only the rows buffered since the
+/// last segment was sealed are copied out of the builders for each query.
+const SEGMENT_ROW_COUNT: usize = 8_192;
+
 struct MutableTableChunk {
     timestamp_min: i64,
     timestamp_max: i64,
+    /// The arrays of each column for the sealed segments of the chunk, in the order they were
+    /// sealed, each of `SEGMENT_ROW_COUNT` rows
+    segments: BTreeMap<ColumnId, Vec<ArrayRef>>,
+    segment_count: usize,
+    /// The builders of each column for the rows buffered since the last segment was sealed
     data: BTreeMap<ColumnId, Builder>,
     row_count: usize,
+    /// A running estimate of the size of the values buffered in this chunk
+    estimated_size: usize,
     index: BufferIndex,
+    hash_builder: DefaultHashBuilder,
+    /// The series keys of the rows in the chunk, each with its hash, indexed by series
+    series_keys: Vec<(u64, SeriesKey)>,
+    /// The series of each of the series keys, found by their hash
+    series_ids: HashTable<usize>,
+    /// The most recent write to each series and timestamp in the chunk, found by the hash of the
+    /// series and timestamp. Later writes overwrite the row of the entry.
+    last_write_rows: HashTable<LastWrite>,
+    /// A bitmap of the rows that have not been overwritten by a later write to the same series
+    /// and timestamp, for each segment that has rows that have been, the last of which may be
+    /// that of the rows buffered since the last segment was sealed
+    live_rows: BTreeMap<usize, Vec<u8>>,
 }
 
-impl MutableTableChunk {
-    fn add_rows(&mut self, rows: Vec<Row>) {
-        let new_row_count = rows.len();
+/// The tag and series key values that identify a series within a table
+type SeriesKey = Vec<(ColumnId, Arc<str>)>;
 
-        for (row_index, r) in rows.into_iter().enumerate() {
+/// The row of the most recent write to a series and timestamp
+struct LastWrite {
+    hash: u64,
+    series: usize,
+    time: i64,
+    row: usize,
+}
+
+impl MutableTableChunk {
+    fn add_rows(&mut self, rows: Vec<Row>, column_defaults: &BTreeMap<ColumnId, FieldData>) {
+        for mut r in rows {
+            let row_index = self.row_count;
+            // a later write to the same series and timestamp only replaces the fields that it
+            // has, the others keep the values of the earlier write:
+            if let Some(previous) = self.dedupe_row(row_index, &r) {
+                self.add_fields_of_previous_row(previous, &mut r);
+            }
+            for (column_id, default) in column_defaults {
+                if !r.fields.iter().any(|f| f.id == *column_id) {
+                    r.fields.push(Field::new(*column_id, default.clone()));
+                }
+            }
             let mut value_added = HashSet::with_capacity(r.fields.len());
 
             for f in r.fields {
                 value_added.insert(f.id);
+                self.estimated_size += estimated_field_size(&f.value);
 
                 match f.value {
                     FieldData::Timestamp(v) => {
                         self.timestamp_min = self.timestamp_min.min(v);
                         self.timestamp_max = self.timestamp_max.max(v);
 
-                        let b = self.data.entry(f.id).or_insert_with(|| {
+                        let b = self.builder(f.id, || {
                             debug!("Creating new timestamp builder");
-                            let mut time_builder = TimestampNanosecondBuilder::new();
-                            // append nulls for all previous rows
-                            for _ in 0..(row_index + self.row_count) {
-                                debug!("Appending null for timestamp");
-                                time_builder.append_null();
-                            }
-                            Builder::Time(time_builder)
+                            Builder::Time(TimestampNanosecondBuilder::new())
                         });
-                        if let Builder::Time(b) = b {
-                            b.append_value(v);
-                        } else {
+                        let Builder::Time(b) = b else {
                             panic!("unexpected field type");
-                        }
+                        };
+                        b.append_value(v);
                     }
                     FieldData::Tag(v) => {
-                        if let Entry::Vacant(e) = self.data.entry(f.id) {
-                            let mut tag_builder = StringDictionaryBuilder::new();
-                            // append nulls for all previous rows
-                            for _ in 0..(row_index + self.row_count) {
-                                tag_builder.append_null();
-                            }
-                            e.insert(Builder::Tag(tag_builder));
-                        }
-                        let b = self.data.get_mut(&f.id).expect("tag builder should exist");
-                        if let Builder::Tag(b) = b {
-                            self.index.add_row_if_indexed_column(b.len(), f.id, &v);
-                            b.append(v)
-                                .expect("shouldn't be able to overflow 32 bit dictionary");
-                        } else {
+                        self.index.add_row_if_indexed_column(row_index, f.id, &v);
+                        let b = self.builder(f.id, || Builder::Tag(StringDictionaryBuilder::new()));
+                        let Builder::Tag(b) = b else {
                             panic!("unexpected field type");
-                        }
+                        };
+                        b.append(v)
+                            .expect("shouldn't be able to overflow 32 bit dictionary");
                     }
                     FieldData::Key(v) => {
-                        if let Entry::Vacant(e) = self.data.entry(f.id) {
-                            let key_builder = StringDictionaryBuilder::new();
-                            if self.row_count > 0 {
-                                panic!("series key columns must be passed in the very first write for a table");
-                            }
-                            e.insert(Builder::Key(key_builder));
+                        if !self.data.contains_key(&f.id) && self.row_count > 0 {
+                            panic!("series key columns must be passed in the very first write for a table");
                         }
-                        let b = self.data.get_mut(&f.id).expect("key builder should exist");
+                        self.index.add_row_if_indexed_column(row_index, f.id, &v);
+                        let b = self.builder(f.id, || Builder::Key(StringDictionaryBuilder::new()));
                         let Builder::Key(b) = b else {
                             panic!("unexpected field type");
                         };
-                        self.index.add_row_if_indexed_column(b.len(), f.id, &v);
                         b.append_value(v);
                     }
                     FieldData::String(v) => {
-                        let b = self.data.entry(f.id).or_insert_with(|| {
-                            let mut string_builder = StringBuilder::new();
-                            // append nulls for all previous rows
-                            for _ in 0..(row_index + self.row_count) {
-                                string_builder.append_null();
-                            }
-                            Builder::String(string_builder)
-                        });
-                        if let Builder::String(b) = b {
-                            b.append_value(v);
-                        } else {
+                        let b = self.builder(f.id, || Builder::String(StringBuilder::new()));
+                        let Builder::String(b) = b else {
                             panic!("unexpected field type");
-                        }
+                        };
+                        b.append_value(v);
                     }
                     FieldData::Integer(v) => {
-                        let b = self.data.entry(f.id).or_insert_with(|| {
-                            let mut int_builder = Int64Builder::new();
-                            // append nulls for all previous rows
-                            for _ in 0..(row_index + self.row_count) {
-                                int_builder.append_null();
-                            }
-                            Builder::I64(int_builder)
-                        });
-                        if let Builder::I64(b) = b {
-                            b.append_value(v);
-                        } else {
+                        let b = self.builder(f.id, || Builder::I64(Int64Builder::new()));
+                        let Builder::I64(b) = b else {
                             panic!("unexpected field type");
-                        }
+                        };
+                        b.append_value(v);
                     }
                     FieldData::UInteger(v) => {
-                        let b = self.data.entry(f.id).or_insert_with(|| {
-                            let mut uint_builder = UInt64Builder::new();
-                            // append nulls for all previous rows
-                            for _ in 0..(row_index + self.row_count) {
-                                uint_builder.append_null();
-                            }
-                            Builder::U64(uint_builder)
-                        });
-                        if let Builder::U64(b) = b {
-                            b.append_value(v);
-                        } else {
+                        let b = self.builder(f.id, || Builder::U64(UInt64Builder::new()));
+                        let Builder::U64(b) = b else {
                             panic!("unexpected field type");
-                        }
+                        };
+                        b.append_value(v);
                     }
                     FieldData::Float(v) => {
-                        let b = self.data.entry(f.id).or_insert_with(|| {
-                            let mut float_builder = Float64Builder::new();
-                            // append nulls for all previous rows
-                            for _ in 0..(row_index + self.row_count) {
-                                float_builder.append_null();
-                            }
-                            Builder::F64(float_builder)
-                        });
-                        if let Builder::F64(b) = b {
-                            b.append_value(v);
-                        } else {
+                        let b = self.builder(f.id, || Builder::F64(Float64Builder::new()));
+                        let Builder::F64(b) = b else {
                             panic!("unexpected field type");
-                        }
+                        };
+                        b.append_value(v);
                     }
                     FieldData::Boolean(v) => {
-                        let b = self.data.entry(f.id).or_insert_with(|| {
-                            let mut bool_builder = BooleanBuilder::new();
-                            // append nulls for all previous rows
-                            for _ in 0..(row_index + self.row_count) {
-                                bool_builder.append_null();
-                            }
-                            Builder::Bool(bool_builder)
-                        });
-                        if let Builder::Bool(b) = b {
-                            b.append_value(v);
-                        } else {
+                        let b = self.builder(f.id, || Builder::Bool(BooleanBuilder::new()));
+                        let Builder::Bool(b) = b else {
                             panic!("unexpected field type");
-                        }
+                        };
+                        b.append_value(v);
                     }
                 }
             }
@@ -396,75 +467,296 @@ impl MutableTableChunk {
             for (name, builder) in &mut self.data {
                 if !value_added.contains(name) {
                     debug!("Adding null for column {}", name);
-                    match builder {
-                        Builder::Bool(b) => b.append_null(),
-                        Builder::F64(b) => b.append_null(),
-                        Builder::I64(b) => b.append_null(),
-                        Builder::U64(b) => b.append_null(),
-                        Builder::String(b) => b.append_null(),
-                        Builder::Tag(b) => b.append_null(),
-                        Builder::Key(b) => b.append_null(),
-                        Builder::Time(b) => b.append_null(),
-                    }
+                    builder.append_null();
                 }
             }
+
+            self.row_count += 1;
+            if self.row_count == (self.segment_count + 1) * SEGMENT_ROW_COUNT {
+                self.seal_segment();
+            }
         }
+    }
 
-        self.row_count += new_row_count;
+    /// The builder for a column, which is created with `new_builder` if the column is new to the
+    /// chunk, with nulls for all of the rows buffered before it
+    fn builder(
+        &mut self,
+        column_id: ColumnId,
+        new_builder: impl FnOnce() -> Builder,
+    ) -> &mut Builder {
+        let unsealed_rows = self.row_count - self.segment_count * SEGMENT_ROW_COUNT;
+        let segment_count = self.segment_count;
+        let segments = &mut self.segments;
+        self.data.entry(column_id).or_insert_with(|| {
+            let mut builder = new_builder();
+            let null_segments = (0..segment_count)
+                .map(|_| {
+                    for _ in 0..SEGMENT_ROW_COUNT {
+                        builder.append_null();
+                    }
+                    builder.finish()
+                })
+                .collect();
+            segments.insert(column_id, null_segments);
+            for _ in 0..unsealed_rows {
+                builder.append_null();
+            }
+            builder
+        })
+    }
+
+    /// Finish the builders of the chunk into the arrays of a new segment
+    fn seal_segment(&mut self) {
+        for (column_id, builder) in &mut self.data {
+            self.segments
+                .entry(*column_id)
+                .or_default()
+                .push(builder.finish());
+        }
+        self.segment_count += 1;
+    }
+
+    /// Record the row as the latest write for its series and timestamp, marking any earlier
+    /// write to the same series and timestamp as superseded, so the last write wins. Returns the
+    /// index of the superseded row, if there is one.
+    fn dedupe_row(&mut self, row_index: usize, row: &Row) -> Option<usize> {
+        let series = self.series(row);
+        let hash = self.hash_builder.hash_one((series, row.time));
+        let last_write = self
+            .last_write_rows
+            .find_mut(hash, |w| w.series == series && w.time == row.time);
+        match last_write {
+            Some(last_write) => {
+                let previous = std::mem::replace(&mut last_write.row, row_index);
+                self.mark_superseded(previous);
+                Some(previous)
+            }
+            None => {
+                let last_write = LastWrite {
+                    hash,
+                    series,
+                    time: row.time,
+                    row: row_index,
+                };
+                self.last_write_rows
+                    .insert_unique(hash, last_write, |w| w.hash);
+                self.estimated_size += size_of::<LastWrite>();
+                None
+            }
+        }
+    }
+
+    /// The series of the row, which is added to the chunk if it is new to it. The series key is
+    /// only collected for new series: the hash of a series key is the sum of those of its tags,
+    /// so that it does not depend on their order in the row.
+    fn series(&mut self, row: &Row) -> usize {
+        let tags = || {
+            row.fields.iter().filter_map(|f| match &f.value {
+                FieldData::Tag(v) | FieldData::Key(v) => Some((f.id, v)),
+                _ => None,
+            })
+        };
+        let (hash, tag_count) = tags().fold((0u64, 0), |(hash, count), (id, v)| {
+            let tag_hash = self.hash_builder.hash_one((id, v.as_ref()));
+            (hash.wrapping_add(tag_hash), count + 1)
+        });
+        let series_keys = &self.series_keys;
+        let existing = self.series_ids.find(hash, |series| {
+            let series_key = &series_keys[*series].1;
+            series_key.len() == tag_count
+                && tags().all(|(id, v)| series_key.iter().any(|(k, kv)| *k == id && kv == v))
+        });
+        if let Some(series) = existing {
+            return *series;
+        }
+
+        let mut series_key: SeriesKey = tags().map(|(id, v)| (id, Arc::clone(v))).collect();
+        series_key.sort_unstable_by_key(|(id, _)| *id);
+        self.estimated_size += series_key_size(&series_key);
+        let series = self.series_keys.len();
+        self.series_keys.push((hash, series_key));
+        let series_keys = &self.series_keys;
+        self.series_ids
+            .insert_unique(hash, series, |series| series_keys[*series].0);
+        series
+    }
+
+    /// Mark the `row`th row of the chunk as overwritten by a later write, in the bitmap of the
+    /// live rows of its segment, which is created with all of its rows live if it has none
+    fn mark_superseded(&mut self, row: usize) {
+        let estimated_size = &mut self.estimated_size;
+        let live_rows = self
+            .live_rows
+            .entry(row / SEGMENT_ROW_COUNT)
+            .or_insert_with(|| {
+                *estimated_size += SEGMENT_ROW_COUNT / 8;
+                vec![u8::MAX; SEGMENT_ROW_COUNT / 8]
+            });
+        bit_util::unset_bit(live_rows, row % SEGMENT_ROW_COUNT);
+    }
+
+    /// Add the field values of the `previous` row that `row` does not have to it
+    fn add_fields_of_previous_row(&self, previous: usize, row: &mut Row) {
+        for column_id in self.data.keys() {
+            if row.fields.iter().any(|f| f.id == *column_id) {
+                continue;
+            }
+            if let Some(value) = self.field_value(*column_id, previous) {
+                row.fields.push(Field::new(*column_id, value));
+            }
+        }
+    }
+
+    /// The value of a field column in the `row`th row of the chunk, or `None` if it is null, or
+    /// the column is not a field
+    fn field_value(&self, column_id: ColumnId, row: usize) -> Option<FieldData> {
+        let sealed_rows = self.segment_count * SEGMENT_ROW_COUNT;
+        if row < sealed_rows {
+            let array = &self.segments.get(&column_id)?[row / SEGMENT_ROW_COUNT];
+            return array_field_value(array, row % SEGMENT_ROW_COUNT);
+        }
+        self.data.get(&column_id)?.field_value(row - sealed_rows)
+    }
+
+    /// The size of the index of the latest write to each series and timestamp in the chunk,
+    /// along with the series keys and the bitmaps of the live rows
+    fn dedupe_size(&self) -> usize {
+        let keys: usize = self
+            .series_keys
+            .iter()
+            .map(|(_, series_key)| series_key_size(series_key))
+            .sum();
+        keys + self.last_write_rows.len() * size_of::<LastWrite>()
+            + self.live_rows.len() * SEGMENT_ROW_COUNT / 8
     }
 
     fn timestamp_min_max(&self) -> TimestampMinMax {
         TimestampMinMax::new(self.timestamp_min, self.timestamp_max)
     }
 
-    fn record_batch(
+    /// The record batches of the chunk, one for each sealed segment, which share its arrays, and
+    /// one for the rows buffered since, leaving out superseded rows and those that cannot match
+    /// `filter`
+    fn record_batches(
         &self,
         table_def: Arc<TableDefinition>,
-        filter: &[Expr],
-    ) -> Result<RecordBatch> {
-        let row_ids = self
-            .index
-            .get_rows_from_index_for_filter(Arc::clone(&table_def), filter);
+        filter: &BufferFilter,
+    ) -> Result<Vec<RecordBatch>> {
+        let index_rows = self.index.rows_matching(filter);
         let schema = table_def.schema.as_arrow();
 
-        let mut cols = Vec::with_capacity(schema.fields().len());
-
-        for f in schema.fields() {
-            match row_ids {
-                Some(row_ids) => {
-                    let b = table_def
-                        .column_name_to_id(f.name().as_str())
-                        .and_then(|id| self.data.get(&id))
-                        .ok_or_else(|| Error::FieldNotFound(f.name().to_string()))?
-                        .get_rows(row_ids);
-                    cols.push(b);
-                }
-                None => {
-                    let b = table_def
-                        .column_name_to_id(f.name().as_str())
-                        .and_then(|id| self.data.get(&id))
-                        .ok_or_else(|| Error::FieldNotFound(f.name().to_string()))?
-                        .as_arrow();
-                    cols.push(b);
-                }
-            }
+        let column_ids = schema
+            .fields()
+            .iter()
+            .map(|f| {
+                table_def
+                    .column_name_to_id(f.name().as_str())
+                    .filter(|id| self.data.contains_key(id))
+                    .ok_or_else(|| Error::FieldNotFound(f.name().to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut batches = Vec::with_capacity(self.segment_count + 1);
+        for segment in 0..self.segment_count {
+            let cols = column_ids
+                .iter()
+                .map(|id| Arc::clone(&self.segments[id][segment]))
+                .collect();
+            batches.push(RecordBatch::try_new(Arc::clone(&schema), cols)?);
         }
+        if self.row_count > self.segment_count * SEGMENT_ROW_COUNT || batches.is_empty() {
+            let cols = column_ids
+                .iter()
+                .map(|id| self.data[id].as_arrow())
+                .collect();
+            batches.push(RecordBatch::try_new(schema, cols)?);
+        }
+
+        // the times are only compared if some of the rows can be outside of the range:
+        let time_column = if self.timestamp_min < filter.time_range.min
+            || self.timestamp_max > filter.time_range.max
+        {
+            table_def
+                .column_name_to_id(TIME_COLUMN_NAME)
+                .and_then(|time_id| column_ids.iter().position(|id| *id == time_id))
+        } else {
+            None
+        };
+        // the values of tag columns that are not indexed are compared in each batch:
+        let tag_columns = filter
+            .tag_values
+            .iter()
+            .filter(|(id, _)| !self.index.columns.contains_key(id))
+            .filter_map(|(id, value)| Some((column_ids.iter().position(|c| c == id)?, value)))
+            .collect::<Vec<_>>();
 
-        Ok(RecordBatch::try_new(schema, cols)?)
+        if index_rows.is_none()
+            && self.live_rows.is_empty()
+            && time_column.is_none()
+            && tag_columns.is_empty()
+        {
+            return Ok(batches);
+        }
+        // the batches of segments that have no superseded rows, and no rows to filter out, are
+        // returned as they are:
+        batches
+            .into_iter()
+            .enumerate()
+            .map(|(segment, batch)| {
+                let num_rows = batch.num_rows();
+                let mut keep = self.live_rows.get(&segment).map(|live_rows| {
+                    let live_rows = Buffer::from_slice_ref(live_rows);
+                    BooleanArray::new(BooleanBuffer::new(live_rows, 0, num_rows), None)
+                });
+                if let Some(rows) = &index_rows {
+                    let offset = segment * SEGMENT_ROW_COUNT;
+                    let matching = (offset..offset + num_rows)
+                        .map(|r| Some(rows.contains(&r)))
+                        .collect();
+                    keep = Some(and_keep(keep, matching)?);
+                }
+                if let Some(time_column) = time_column {
+                    let in_range = times_in_range(batch.column(time_column), filter.time_range);
+                    keep = Some(and_keep(keep, in_range)?);
+                }
+                for (tag_column, value) in &tag_columns {
+                    let equal = tags_equal(batch.column(*tag_column), value);
+                    keep = Some(and_keep(keep, equal)?);
+                }
+                match keep {
+                    Some(keep) if keep.true_count() < num_rows => {
+                        Ok(filter_record_batch(&batch, &keep)?)
+                    }
+                    _ => Ok(batch),
+                }
+            })
+            .collect()
     }
 
-    fn into_schema_record_batch(self, table_def: Arc<TableDefinition>) -> (Schema, RecordBatch) {
+    /// The schema of the chunk, and all of its rows in a single record batch, including those
+    /// that have been superseded
+    fn schema_record_batch(&self, table_def: &TableDefinition) -> Result<(Schema, RecordBatch)> {
         let mut cols = Vec::with_capacity(self.data.len());
         let mut schema_builder = SchemaBuilder::new();
-        for (col_id, builder) in self.data.into_iter() {
-            let (col_type, col) = builder.into_influxcol_and_arrow();
-            schema_builder.influx_column(
-                table_def
-                    .column_id_to_name(&col_id)
-                    .expect("valid column id")
-                    .as_ref(),
-                col_type,
-            );
+        for (col_id, builder) in &self.data {
+            // the data of a column that was dropped since it was buffered is not persisted:
+            let Some(col_name) = table_def.column_id_to_name(col_id) else {
+                continue;
+            };
+            let col = builder.as_arrow();
+            let col = match self.segments.get(col_id) {
+                Some(segments) if !segments.is_empty() => {
+                    let arrays = segments
+                        .iter()
+                        .chain([&col])
+                        .map(|a| a.as_ref())
+                        .collect::<Vec<_>>();
+                    concat(&arrays)?
+                }
+                _ => col,
+            };
+            schema_builder.influx_column(col_name.as_ref(), builder.influx_column_type());
             cols.push(col);
         }
         let schema = schema_builder
@@ -472,11 +764,102 @@ impl MutableTableChunk {
             .expect("should always be able to build schema");
         let arrow_schema = schema.as_arrow();
 
-        (
-            schema,
-            RecordBatch::try_new(arrow_schema, cols)
-                .expect("should always be able to build record batch"),
-        )
+        Ok((schema, RecordBatch::try_new(arrow_schema, cols)?))
+    }
+}
+
+/// Sort the batch on the sort key and remove duplicate rows, i.e., those with the same values for
+/// every sort key column. Writes are buffered in the order they arrive, so the sort is stable and
+/// the last row for any duplicated key is the one that is kept.
+fn sort_dedupe_record_batch(batch: RecordBatch, sort_key: &SortKey) -> Result<RecordBatch> {
+    let sort_cols = sort_key
+        .iter()
+        .filter_map(|(col, _)| batch.column_by_name(col).cloned())
+        .collect::<Vec<_>>();
+    if sort_cols.is_empty() || batch.num_rows() == 0 {
+        return Ok(batch);
+    }
+
+    let converter = RowConverter::new(
+        sort_cols
+            .iter()
+            .map(|c| SortField::new(c.data_type().clone()))
+            .collect(),
+    )?;
+    let rows = converter.convert_columns(&sort_cols)?;
+
+    let mut indices = (0..batch.num_rows()).collect::<Vec<_>>();
+    indices.sort_by(|a, b| rows.row(*a).cmp(&rows.row(*b)));
+    let deduped: UInt32Array = indices
+        .iter()
+        .enumerate()
+        .filter(|(i, row)| {
+            indices
+                .get(i + 1)
+                .map_or(true, |next| rows.row(*next) != rows.row(**row))
+        })
+        .map(|(_, row)| *row as u32)
+        .collect();
+
+    let cols = batch
+        .columns()
+        .iter()
+        .map(|c| take(c, &deduped, None))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(RecordBatch::try_new(batch.schema(), cols)?)
+}
+
+/// The rows that are in both `keep`, if there is one, and `rows`
+fn and_keep(keep: Option<BooleanArray>, rows: BooleanArray) -> Result<BooleanArray> {
+    match keep {
+        Some(keep) => Ok(and(&keep, &rows)?),
+        None => Ok(rows),
+    }
+}
+
+/// The number of bytes that `series_key` takes up in a chunk, along with its entry in the index
+/// of the series keys
+fn series_key_size(series_key: &SeriesKey) -> usize {
+    size_of::<(u64, SeriesKey)>()
+        + size_of::<usize>()
+        + series_key
+            .iter()
+            .map(|(_, v)| size_of::<(ColumnId, Arc<str>)>() + v.len())
+            .sum::<usize>()
+}
+
+/// The value of a field in the `row`th row of a sealed segment's array, or `None` if it is null,
+/// or the array is not of a field column
+fn array_field_value(array: &ArrayRef, row: usize) -> Option<FieldData> {
+    if array.is_null(row) {
+        return None;
+    }
+    match array.data_type() {
+        DataType::Boolean => Some(FieldData::Boolean(array.as_boolean().value(row))),
+        DataType::Int64 => Some(FieldData::Integer(
+            array.as_primitive::<Int64Type>().value(row),
+        )),
+        DataType::UInt64 => Some(FieldData::UInteger(
+            array.as_primitive::<UInt64Type>().value(row),
+        )),
+        DataType::Float64 => Some(FieldData::Float(
+            array.as_primitive::<Float64Type>().value(row),
+        )),
+        DataType::Utf8 => Some(FieldData::String(
+            array.as_string::<i32>().value(row).to_string(),
+        )),
+        _ => None,
+    }
+}
+
+/// The estimated number of bytes a value takes up once buffered
+fn estimated_field_size(value: &FieldData) -> usize {
+    match value {
+        FieldData::Timestamp(_) | FieldData::Integer(_) | FieldData::Float(_) => size_of::<i64>(),
+        FieldData::UInteger(_) => size_of::<u64>(),
+        FieldData::Boolean(_) => size_of::<bool>(),
+        FieldData::Key(v) | FieldData::Tag(v) => v.len(),
+        FieldData::String(v) => v.len(),
     }
 }
 
@@ -487,14 +870,93 @@ impl std::fmt::Debug for MutableTableChunk {
             .field("timestamp_min", &self.timestamp_min)
             .field("timestamp_max", &self.timestamp_max)
             .field("row_count", &self.row_count)
+            .field("estimated_size", &self.estimated_size)
             .finish()
     }
 }
 
+/// The parts of the filters of a query that the buffer evaluates itself, so that it only returns
+/// the rows that can match them: the range of times they restrict the query to, and the values
+/// that they require tag columns to be equal to. The filters are still all applied to the rows
+/// that are returned.
+#[derive(Debug, Default)]
+struct BufferFilter {
+    time_range: TimeRange,
+    tag_values: Vec<(ColumnId, Arc<str>)>,
+}
+
+impl BufferFilter {
+    fn new(table_def: &TableDefinition, filters: &[Expr]) -> Self {
+        let tag_values = filters
+            .iter()
+            .flat_map(split_conjunction)
+            .filter_map(|expr| {
+                let Expr::BinaryExpr(BinaryExpr {
+                    left,
+                    op: Operator::Eq,
+                    right,
+                }) = expr
+                else {
+                    return None;
+                };
+                // the column can be on either side of the comparison:
+                let (column, value) = match (left.as_ref(), right.as_ref()) {
+                    (Expr::Column(column), Expr::Literal(value))
+                    | (Expr::Literal(value), Expr::Column(column)) => (column, value),
+                    _ => return None,
+                };
+                let (column_id, column_def) = table_def.column_def_and_id(column.name())?;
+                if column_def.data_type != InfluxColumnType::Tag {
+                    return None;
+                }
+                Some((column_id, Arc::from(string_value(value)?)))
+            })
+            .collect();
+        Self {
+            time_range: TimeRange::from_filters(filters),
+            tag_values,
+        }
+    }
+}
+
+/// The string in a literal, which is a dictionary if it has been coerced to the type of a tag
+/// column
+fn string_value(value: &ScalarValue) -> Option<&str> {
+    match value {
+        ScalarValue::Utf8(Some(value)) => Some(value),
+        ScalarValue::Dictionary(_, value) => string_value(value),
+        _ => None,
+    }
+}
+
+/// Which of the times in `array` are in `time_range`
+fn times_in_range(array: &ArrayRef, time_range: TimeRange) -> BooleanArray {
+    array
+        .as_primitive::<TimestampNanosecondType>()
+        .iter()
+        .map(|time| Some(time.is_some_and(|t| t >= time_range.min && t <= time_range.max)))
+        .collect()
+}
+
+/// Which of the values of the tag column in `array` are equal to `value`
+fn tags_equal(array: &ArrayRef, value: &str) -> BooleanArray {
+    let dictionary = array.as_dictionary::<Int32Type>();
+    let values = dictionary.values().as_string::<i32>();
+    let keys = (0..values.len())
+        .filter(|&i| values.is_valid(i) && values.value(i) == value)
+        .map(|i| i as i32)
+        .collect::<HashSet<_>>();
+    dictionary
+        .keys()
+        .iter()
+        .map(|key| Some(key.is_some_and(|key| keys.contains(&key))))
+        .collect()
+}
+
 #[derive(Debug, Clone)]
 struct BufferIndex {
     // column id -> string value -> row indexes
-    columns: HashMap<ColumnId, HashMap<String, Vec<usize>>>,
+    columns: HashMap<ColumnId, HashMap<Arc<str>, Vec<usize>>>,
 }
 
 impl BufferIndex {
@@ -508,38 +970,35 @@ impl BufferIndex {
         Self { columns }
     }
 
-    fn add_row_if_indexed_column(&mut self, row_index: usize, column_id: ColumnId, value: &str) {
+    fn add_row_if_indexed_column(
+        &mut self,
+        row_index: usize,
+        column_id: ColumnId,
+        value: &Arc<str>,
+    ) {
         if let Some(column) = self.columns.get_mut(&column_id) {
             column
-                .entry_ref(value)
+                .entry(Arc::clone(value))
                 .and_modify(|c| c.push(row_index))
                 .or_insert(vec![row_index]);
         }
     }
 
-    fn get_rows_from_index_for_filter(
-        &self,
-        table_def: Arc<TableDefinition>,
-        filter: &[Expr],
-    ) -> Option<&Vec<usize>> {
-        for expr in filter {
-            if let Expr::BinaryExpr(BinaryExpr { left, op, right }) = expr {
-                if *op == datafusion::logical_expr::Operator::Eq {
-                    if let Expr::Column(c) = left.as_ref() {
-                        if let Expr::Literal(datafusion::scalar::ScalarValue::Utf8(Some(v))) =
-                            right.as_ref()
-                        {
-                            return table_def
-                                .column_name_to_id(c.name())
-                                .and_then(|id| self.columns.get(&id))
-                                .and_then(|m| m.get(v.as_str()));
-                        }
-                    }
-                }
-            }
+    /// The rows that have the values `filter` requires of the indexed columns, or `None` if it
+    /// does not require values of any of them
+    fn rows_matching(&self, filter: &BufferFilter) -> Option<HashSet<usize>> {
+        let mut matching: Option<HashSet<usize>> = None;
+        for (column_id, value) in &filter.tag_values {
+            let Some(column) = self.columns.get(column_id) else {
+                continue;
+            };
+            let rows = column.get(value).into_iter().flatten().copied();
+            matching = Some(match matching {
+                None => rows.collect(),
+                Some(matching) => rows.filter(|row| matching.contains(row)).collect(),
+            });
         }
-
-        None
+        matching
     }
 
     #[allow(dead_code)]
@@ -548,9 +1007,9 @@ impl BufferIndex {
         for (_, v) in &self.columns {
             size += size_of::<ColumnId>()
                 + size_of::<String>()
-                + size_of::<HashMap<String, Vec<usize>>>();
+                + size_of::<HashMap<Arc<str>, Vec<usize>>>();
             for (k, v) in v {
-                size += k.len() + size_of::<String>() + size_of::<Vec<usize>>();
+                size += k.len() + size_of::<Arc<str>>() + size_of::<Vec<usize>>();
                 size += v.len() * size_of::<usize>();
             }
         }
@@ -572,6 +1031,33 @@ pub enum Builder {
 }
 
 impl Builder {
+    fn append_null(&mut self) {
+        match self {
+            Self::Bool(b) => b.append_null(),
+            Self::I64(b) => b.append_null(),
+            Self::F64(b) => b.append_null(),
+            Self::U64(b) => b.append_null(),
+            Self::String(b) => b.append_null(),
+            Self::Tag(b) => b.append_null(),
+            Self::Key(b) => b.append_null(),
+            Self::Time(b) => b.append_null(),
+        }
+    }
+
+    /// Finish the values appended so far into an array, leaving the builder empty
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::Bool(b) => Arc::new(b.finish()),
+            Self::I64(b) => Arc::new(b.finish()),
+            Self::F64(b) => Arc::new(b.finish()),
+            Self::U64(b) => Arc::new(b.finish()),
+            Self::String(b) => Arc::new(b.finish()),
+            Self::Tag(b) => Arc::new(b.finish()),
+            Self::Key(b) => Arc::new(b.finish()),
+            Self::Time(b) => Arc::new(b.finish()),
+        }
+    }
+
     fn as_arrow(&self) -> ArrayRef {
         match self {
             Self::Bool(b) => Arc::new(b.finish_cloned()),
@@ -585,101 +1071,43 @@ impl Builder {
         }
     }
 
-    fn into_influxcol_and_arrow(self) -> (InfluxColumnType, ArrayRef) {
+    fn influx_column_type(&self) -> InfluxColumnType {
         match self {
-            Self::Bool(mut b) => (
-                InfluxColumnType::Field(InfluxFieldType::Boolean),
-                Arc::new(b.finish()),
-            ),
-            Self::I64(mut b) => (
-                InfluxColumnType::Field(InfluxFieldType::Integer),
-                Arc::new(b.finish()),
-            ),
-            Self::F64(mut b) => (
-                InfluxColumnType::Field(InfluxFieldType::Float),
-                Arc::new(b.finish()),
-            ),
-            Self::U64(mut b) => (
-                InfluxColumnType::Field(InfluxFieldType::UInteger),
-                Arc::new(b.finish()),
-            ),
-            Self::String(mut b) => (
-                InfluxColumnType::Field(InfluxFieldType::String),
-                Arc::new(b.finish()),
-            ),
-            Self::Tag(mut b) => (InfluxColumnType::Tag, Arc::new(b.finish())),
-            Self::Key(mut b) => (InfluxColumnType::Tag, Arc::new(b.finish())),
-            Self::Time(mut b) => (InfluxColumnType::Timestamp, Arc::new(b.finish())),
+            Self::Bool(_) => InfluxColumnType::Field(InfluxFieldType::Boolean),
+            Self::I64(_) => InfluxColumnType::Field(InfluxFieldType::Integer),
+            Self::F64(_) => InfluxColumnType::Field(InfluxFieldType::Float),
+            Self::U64(_) => InfluxColumnType::Field(InfluxFieldType::UInteger),
+            Self::String(_) => InfluxColumnType::Field(InfluxFieldType::String),
+            Self::Tag(_) | Self::Key(_) => InfluxColumnType::Tag,
+            Self::Time(_) => InfluxColumnType::Timestamp,
         }
     }
 
-    fn get_rows(&self, rows: &[usize]) -> ArrayRef {
+    /// The value of a field in the `row`th row of the builder, or `None` if it is null, or the
+    /// builder is not of a field column
+    fn field_value(&self, row: usize) -> Option<FieldData> {
+        let is_valid =
+            |validity: Option<&[u8]>| validity.map_or(true, |v| bit_util::get_bit(v, row));
         match self {
-            Self::Bool(b) => {
-                let b = b.finish_cloned();
-                let mut builder = BooleanBuilder::with_capacity(rows.len());
-                for row in rows {
-                    builder.append_value(b.value(*row));
-                }
-                Arc::new(builder.finish())
-            }
+            Self::Bool(b) => is_valid(b.validity_slice())
+                .then(|| FieldData::Boolean(bit_util::get_bit(b.values_slice(), row))),
             Self::I64(b) => {
-                let b = b.finish_cloned();
-                let mut builder = Int64Builder::with_capacity(rows.len());
-                for row in rows {
-                    builder.append_value(b.value(*row));
-                }
-                Arc::new(builder.finish())
+                is_valid(b.validity_slice()).then(|| FieldData::Integer(b.values_slice()[row]))
             }
             Self::F64(b) => {
-                let b = b.finish_cloned();
-                let mut builder = Float64Builder::with_capacity(rows.len());
-                for row in rows {
-                    builder.append_value(b.value(*row));
-                }
-                Arc::new(builder.finish())
+                is_valid(b.validity_slice()).then(|| FieldData::Float(b.values_slice()[row]))
             }
             Self::U64(b) => {
-                let b = b.finish_cloned();
-                let mut builder = UInt64Builder::with_capacity(rows.len());
-                for row in rows {
-                    builder.append_value(b.value(*row));
-                }
-                Arc::new(builder.finish())
-            }
-            Self::String(b) => {
-                let b = b.finish_cloned();
-                let mut builder = StringBuilder::new();
-                for row in rows {
-                    builder.append_value(b.value(*row));
-                }
-                Arc::new(builder.finish())
-            }
-            Self::Tag(b) | Self::Key(b) => {
-                let b = b.finish_cloned();
-                let bv = b.values();
-                let bva: &StringArray = bv.as_any().downcast_ref::<StringArray>().unwrap();
-
-                let mut builder: GenericByteDictionaryBuilder<Int32Type, GenericStringType<i32>> =
-                    StringDictionaryBuilder::new();
-                for row in rows {
-                    let val = b.key(*row).unwrap();
-                    let tag_val = bva.value(val);
-
-                    builder
-                        .append(tag_val)
-                        .expect("shouldn't be able to overflow 32 bit dictionary");
-                }
-                Arc::new(builder.finish())
-            }
-            Self::Time(b) => {
-                let b = b.finish_cloned();
-                let mut builder = TimestampNanosecondBuilder::with_capacity(rows.len());
-                for row in rows {
-                    builder.append_value(b.value(*row));
-                }
-                Arc::new(builder.finish())
+                is_valid(b.validity_slice()).then(|| FieldData::UInteger(b.values_slice()[row]))
             }
+            Self::String(b) => is_valid(b.validity_slice()).then(|| {
+                let offsets = b.offsets_slice();
+                let value = &b.values_slice()[offsets[row] as usize..offsets[row + 1] as usize];
+                FieldData::String(
+                    String::from_utf8(value.to_vec()).expect("string builder holds utf8"),
+                )
+            }),
+            Self::Tag(_) | Self::Key(_) | Self::Time(_) => None,
         }
     }
 
@@ -717,7 +1145,6 @@ mod tests {
     use arrow_util::{assert_batches_eq, assert_batches_sorted_eq};
     use datafusion::common::Column;
     use influxdb3_id::TableId;
-    use influxdb3_wal::Field;
     use schema::InfluxFieldType;
 
     #[test]
@@ -753,7 +1180,7 @@ mod tests {
                     fields: vec![
                         Field {
                             id: ColumnId::from(0),
-                            value: FieldData::Tag("a".to_string()),
+                            value: FieldData::Tag("a".into()),
                         },
                         Field {
                             id: ColumnId::from(1),
@@ -770,7 +1197,7 @@ mod tests {
                     fields: vec![
                         Field {
                             id: ColumnId::from(0),
-                            value: FieldData::Tag("b".to_string()),
+                            value: FieldData::Tag("b".into()),
                         },
                         Field {
                             id: ColumnId::from(1),
@@ -784,7 +1211,7 @@ mod tests {
                 },
             ];
 
-            table_buffer.buffer_chunk(offset, rows);
+            table_buffer.buffer_chunk(offset, rows, &BTreeMap::new());
         }
 
         let partitioned_batches = table_buffer
@@ -852,7 +1279,7 @@ mod tests {
                 fields: vec![
                     Field {
                         id: ColumnId::from(0),
-                        value: FieldData::Tag("a".to_string()),
+                        value: FieldData::Tag("a".into()),
                     },
                     Field {
                         id: ColumnId::from(1),
@@ -869,7 +1296,7 @@ mod tests {
                 fields: vec![
                     Field {
                         id: ColumnId::from(0),
-                        value: FieldData::Tag("b".to_string()),
+                        value: FieldData::Tag("b".into()),
                     },
                     Field {
                         id: ColumnId::from(1),
@@ -886,7 +1313,7 @@ mod tests {
                 fields: vec![
                     Field {
                         id: ColumnId::from(0),
-                        value: FieldData::Tag("a".to_string()),
+                        value: FieldData::Tag("a".into()),
                     },
                     Field {
                         id: ColumnId::from(1),
@@ -900,7 +1327,7 @@ mod tests {
             },
         ];
 
-        table_buffer.buffer_chunk(0, rows);
+        table_buffer.buffer_chunk(0, rows, &BTreeMap::new());
 
         let filter = &[Expr::BinaryExpr(BinaryExpr {
             left: Box::new(Expr::Column(Column {
@@ -917,9 +1344,9 @@ mod tests {
             .get(&0)
             .unwrap()
             .index
-            .get_rows_from_index_for_filter(Arc::clone(&table_def), filter)
+            .rows_matching(&BufferFilter::new(&table_def, filter))
             .unwrap();
-        assert_eq!(a_rows, &[0, 2]);
+        assert_eq!(a_rows, HashSet::from([0, 2]));
 
         let a = table_buffer
             .record_batches(Arc::clone(&table_def), filter)
@@ -950,9 +1377,9 @@ mod tests {
             .get(&0)
             .unwrap()
             .index
-            .get_rows_from_index_for_filter(Arc::clone(&table_def), filter)
+            .rows_matching(&BufferFilter::new(&table_def, filter))
             .unwrap();
-        assert_eq!(b_rows, &[1]);
+        assert_eq!(b_rows, HashSet::from([1]));
 
         let b = table_buffer
             .record_batches(Arc::clone(&table_def), filter)
@@ -967,6 +1394,110 @@ mod tests {
         assert_batches_eq!(&expected_b, &b);
     }
 
+    #[test]
+    fn filters_are_evaluated_in_the_buffer() {
+        let table_def = Arc::new(
+            TableDefinition::new(
+                TableId::new(),
+                "test_table".into(),
+                vec![
+                    (ColumnId::from(0), "tag".into(), InfluxColumnType::Tag),
+                    (
+                        ColumnId::from(1),
+                        "value".into(),
+                        InfluxColumnType::Field(InfluxFieldType::Integer),
+                    ),
+                    (
+                        ColumnId::from(2),
+                        "time".into(),
+                        InfluxColumnType::Timestamp,
+                    ),
+                ],
+                None,
+            )
+            .unwrap(),
+        );
+        // the tag column is not indexed, so its values are compared in the batches:
+        let mut table_buffer = TableBuffer::new(vec![], SortKey::empty());
+        let rows = (0..4)
+            .map(|i| Row {
+                time: i,
+                fields: vec![
+                    Field {
+                        id: ColumnId::from(0),
+                        value: FieldData::Tag(if i % 2 == 0 { "a" } else { "b" }.into()),
+                    },
+                    Field {
+                        id: ColumnId::from(1),
+                        value: FieldData::Integer(i),
+                    },
+                    Field {
+                        id: ColumnId::from(2),
+                        value: FieldData::Timestamp(i),
+                    },
+                ],
+            })
+            .collect();
+        table_buffer.buffer_chunk(0, rows, &BTreeMap::new());
+
+        let tag = || {
+            Box::new(Expr::Column(Column {
+                relation: None,
+                name: "tag".to_string(),
+            }))
+        };
+        let time = || {
+            Box::new(Expr::Column(Column {
+                relation: None,
+                name: "time".to_string(),
+            }))
+        };
+        let timestamp = |t: i64| {
+            Box::new(Expr::Literal(ScalarValue::TimestampNanosecond(
+                Some(t),
+                None,
+            )))
+        };
+        // the literal is a dictionary once it is coerced to the type of the tag column:
+        let filter = &[
+            Expr::BinaryExpr(BinaryExpr {
+                left: Box::new(Expr::Literal(ScalarValue::Dictionary(
+                    Box::new(arrow::datatypes::DataType::Int32),
+                    Box::new(ScalarValue::Utf8(Some("a".to_string()))),
+                ))),
+                op: Operator::Eq,
+                right: tag(),
+            }),
+            Expr::BinaryExpr(BinaryExpr {
+                left: time(),
+                op: Operator::GtEq,
+                right: timestamp(1),
+            }),
+        ];
+        let batches = table_buffer
+            .record_batches(Arc::clone(&table_def), filter)
+            .unwrap();
+        let expected = [
+            "+-----+--------------------------------+-------+",
+            "| tag | time                           | value |",
+            "+-----+--------------------------------+-------+",
+            "| a   | 1970-01-01T00:00:00.000000002Z | 2     |",
+            "+-----+--------------------------------+-------+",
+        ];
+        assert_batches_eq!(&expected, &batches);
+
+        // chunks outside of the time range are left out entirely:
+        let filter = &[Expr::BinaryExpr(BinaryExpr {
+            left: time(),
+            op: Operator::Gt,
+            right: timestamp(3),
+        })];
+        assert!(table_buffer
+            .partitioned_record_batches(Arc::clone(&table_def), filter)
+            .unwrap()
+            .is_empty());
+    }
+
     #[test]
     fn computed_size_of_buffer() {
         let mut table_buffer = TableBuffer::new(vec![ColumnId::from(0)], SortKey::empty());
@@ -977,7 +1508,7 @@ mod tests {
                 fields: vec![
                     Field {
                         id: ColumnId::from(0),
-                        value: FieldData::Tag("a".to_string()),
+                        value: FieldData::Tag("a".into()),
                     },
                     Field {
                         id: ColumnId::from(1),
@@ -994,7 +1525,7 @@ mod tests {
                 fields: vec![
                     Field {
                         id: ColumnId::from(0),
-                        value: FieldData::Tag("b".to_string()),
+                        value: FieldData::Tag("b".into()),
                     },
                     Field {
                         id: ColumnId::from(1),
@@ -1011,7 +1542,7 @@ mod tests {
                 fields: vec![
                     Field {
                         id: ColumnId::from(0),
-                        value: FieldData::Tag("this is a long tag value to store".to_string()),
+                        value: FieldData::Tag("this is a long tag value to store".into()),
                     },
                     Field {
                         id: ColumnId::from(1),
@@ -1025,10 +1556,367 @@ mod tests {
             },
         ];
 
-        table_buffer.buffer_chunk(0, rows);
+        table_buffer.buffer_chunk(0, rows, &BTreeMap::new());
 
         let size = table_buffer.computed_size();
-        assert_eq!(size, 18119);
+        assert_eq!(size, 18418);
+    }
+
+    #[test]
+    fn estimated_size_of_buffer() {
+        let mut table_buffer = TableBuffer::new(vec![ColumnId::from(0)], SortKey::empty());
+        assert_eq!(0, table_buffer.estimated_size());
+
+        let rows = vec![Row {
+            time: 1,
+            fields: vec![
+                Field {
+                    id: ColumnId::from(0),
+                    value: FieldData::Tag("abc".into()),
+                },
+                Field {
+                    id: ColumnId::from(1),
+                    value: FieldData::Integer(1),
+                },
+                Field {
+                    id: ColumnId::from(2),
+                    value: FieldData::Timestamp(1),
+                },
+            ],
+        }];
+        // the values take up 19 bytes, the series key another 67, and the entry for the row in
+        // the index of the latest write to each series and timestamp another 32:
+        table_buffer.buffer_chunk(0, rows.clone(), &BTreeMap::new());
+        assert_eq!(118, table_buffer.estimated_size());
+        table_buffer.buffer_chunk(60, rows, &BTreeMap::new());
+        assert_eq!(236, table_buffer.estimated_size());
+    }
+
+    #[test]
+    fn duplicate_series_and_timestamp_keeps_last_write() {
+        let table_def = Arc::new(
+            TableDefinition::new(
+                TableId::new(),
+                "test_table".into(),
+                vec![
+                    (ColumnId::from(0), "tag".into(), InfluxColumnType::Tag),
+                    (
+                        ColumnId::from(1),
+                        "val".into(),
+                        InfluxColumnType::Field(InfluxFieldType::String),
+                    ),
+                    (
+                        ColumnId::from(2),
+                        "time".into(),
+                        InfluxColumnType::Timestamp,
+                    ),
+                ],
+                None,
+            )
+            .unwrap(),
+        );
+        let mut table_buffer = TableBuffer::new(
+            vec![ColumnId::from(0)],
+            SortKey::from(vec!["tag".to_string(), "time".to_string()]),
+        );
+        let row = |tag: &str, val: &str, time: i64| Row {
+            time,
+            fields: vec![
+                Field {
+                    id: ColumnId::from(0),
+                    value: FieldData::Tag(tag.into()),
+                },
+                Field {
+                    id: ColumnId::from(1),
+                    value: FieldData::String(val.to_string()),
+                },
+                Field {
+                    id: ColumnId::from(2),
+                    value: FieldData::Timestamp(time),
+                },
+            ],
+        };
+
+        table_buffer.buffer_chunk(
+            0,
+            vec![
+                row("b", "first", 2),
+                row("a", "first", 1),
+                row("b", "other", 1),
+                row("a", "second", 1),
+            ],
+            &BTreeMap::new(),
+        );
+        table_buffer.buffer_chunk(0, vec![row("a", "third", 1)], &BTreeMap::new());
+
+        let batches = table_buffer
+            .record_batches(Arc::clone(&table_def), &[])
+            .unwrap();
+        assert_batches_sorted_eq!(
+            [
+                "+-----+--------------------------------+-------+",
+                "| tag | time                           | val   |",
+                "+-----+--------------------------------+-------+",
+                "| a   | 1970-01-01T00:00:00.000000001Z | third |",
+                "| b   | 1970-01-01T00:00:00.000000001Z | other |",
+                "| b   | 1970-01-01T00:00:00.000000002Z | first |",
+                "+-----+--------------------------------+-------+",
+            ],
+            &batches
+        );
+
+        // the index lookup should also skip superseded rows:
+        let filter = &[Expr::BinaryExpr(BinaryExpr {
+            left: Box::new(Expr::Column(Column {
+                relation: None,
+                name: "tag".to_string(),
+            })),
+            op: datafusion::logical_expr::Operator::Eq,
+            right: Box::new(Expr::Literal(datafusion::scalar::ScalarValue::Utf8(Some(
+                "a".to_string(),
+            )))),
+        })];
+        let batches = table_buffer
+            .record_batches(Arc::clone(&table_def), filter)
+            .unwrap();
+        assert_batches_eq!(
+            [
+                "+-----+--------------------------------+-------+",
+                "| tag | time                           | val   |",
+                "+-----+--------------------------------+-------+",
+                "| a   | 1970-01-01T00:00:00.000000001Z | third |",
+                "+-----+--------------------------------+-------+",
+            ],
+            &batches
+        );
+
+        // snapshotted chunks are sorted on the sort key with duplicates removed:
+        let snapshot_chunks = table_buffer.snapshot(Arc::clone(&table_def), 60).unwrap();
+        assert_eq!(1, snapshot_chunks.len());
+        let batches = table_buffer
+            .record_batches(Arc::clone(&table_def), &[])
+            .unwrap();
+        assert_batches_eq!(
+            [
+                "+-----+--------------------------------+-------+",
+                "| tag | time                           | val   |",
+                "+-----+--------------------------------+-------+",
+                "| a   | 1970-01-01T00:00:00.000000001Z | third |",
+                "| b   | 1970-01-01T00:00:00.000000001Z | other |",
+                "| b   | 1970-01-01T00:00:00.000000002Z | first |",
+                "+-----+--------------------------------+-------+",
+            ],
+            &batches
+        );
+    }
+
+    #[test]
+    fn duplicate_series_and_timestamp_merges_fields() {
+        let table_def = Arc::new(
+            TableDefinition::new(
+                TableId::new(),
+                "test_table".into(),
+                vec![
+                    (ColumnId::from(0), "tag".into(), InfluxColumnType::Tag),
+                    (
+                        ColumnId::from(1),
+                        "a".into(),
+                        InfluxColumnType::Field(InfluxFieldType::Integer),
+                    ),
+                    (
+                        ColumnId::from(2),
+                        "b".into(),
+                        InfluxColumnType::Field(InfluxFieldType::String),
+                    ),
+                    (
+                        ColumnId::from(3),
+                        "time".into(),
+                        InfluxColumnType::Timestamp,
+                    ),
+                ],
+                None,
+            )
+            .unwrap(),
+        );
+        let mut table_buffer = TableBuffer::new(
+            vec![ColumnId::from(0)],
+            SortKey::from(vec!["tag".to_string(), "time".to_string()]),
+        );
+        let row = |time: i64, a: Option<i64>, b: Option<&str>| {
+            let mut fields = vec![
+                Field::new(ColumnId::from(0), FieldData::Tag("t".into())),
+                Field::new(ColumnId::from(3), FieldData::Timestamp(time)),
+            ];
+            fields.extend(a.map(|a| Field::new(ColumnId::from(1), FieldData::Integer(a))));
+            fields.extend(b.map(|b| Field::new(ColumnId::from(2), FieldData::String(b.into()))));
+            Row { time, fields }
+        };
+
+        // the row at time 0 is sealed in a segment before it is written again, and the last row
+        // is still in the builders:
+        let last = SEGMENT_ROW_COUNT as i64;
+        let rows = (0..=last).map(|t| row(t, Some(t), Some("first"))).collect();
+        table_buffer.buffer_chunk(0, rows, &BTreeMap::new());
+        table_buffer.buffer_chunk(
+            0,
+            vec![row(0, None, Some("second")), row(last, Some(-1), None)],
+            &BTreeMap::new(),
+        );
+
+        // the snapshot is sorted on time, so the rows that were written twice are first and last:
+        table_buffer.snapshot(Arc::clone(&table_def), 60).unwrap();
+        let batches = table_buffer
+            .record_batches(Arc::clone(&table_def), &[])
+            .unwrap();
+        let batch = &batches[0];
+        assert_eq!(SEGMENT_ROW_COUNT + 1, batch.num_rows());
+        assert_batches_eq!(
+            [
+                "+----+--------+-----+--------------------------------+",
+                "| a  | b      | tag | time                           |",
+                "+----+--------+-----+--------------------------------+",
+                "| 0  | second | t   | 1970-01-01T00:00:00Z           |",
+                "| -1 | first  | t   | 1970-01-01T00:00:00.000008192Z |",
+                "+----+--------+-----+--------------------------------+",
+            ],
+            &[batch.slice(0, 1), batch.slice(batch.num_rows() - 1, 1)]
+        );
+    }
+
+    #[test]
+    fn sealed_segments_are_shared_by_queries() {
+        let table_def = Arc::new(
+            TableDefinition::new(
+                TableId::new(),
+                "test_table".into(),
+                vec![
+                    (ColumnId::from(0), "tag".into(), InfluxColumnType::Tag),
+                    (
+                        ColumnId::from(1),
+                        "val".into(),
+                        InfluxColumnType::Field(InfluxFieldType::Integer),
+                    ),
+                    (
+                        ColumnId::from(2),
+                        "time".into(),
+                        InfluxColumnType::Timestamp,
+                    ),
+                    (
+                        ColumnId::from(3),
+                        "extra".into(),
+                        InfluxColumnType::Field(InfluxFieldType::Boolean),
+                    ),
+                ],
+                None,
+            )
+            .unwrap(),
+        );
+        let mut table_buffer = TableBuffer::new(
+            vec![ColumnId::from(0)],
+            SortKey::from(vec!["tag".to_string(), "time".to_string()]),
+        );
+        let row = |tag: &str, val: i64, time: i64| Row {
+            time,
+            fields: vec![
+                Field {
+                    id: ColumnId::from(0),
+                    value: FieldData::Tag(tag.into()),
+                },
+                Field {
+                    id: ColumnId::from(1),
+                    value: FieldData::Integer(val),
+                },
+                Field {
+                    id: ColumnId::from(2),
+                    value: FieldData::Timestamp(time),
+                },
+            ],
+        };
+
+        // fill a segment and then some:
+        let rows = (0..SEGMENT_ROW_COUNT as i64 + 1)
+            .map(|i| row(if i % 2 == 0 { "a" } else { "b" }, i, i))
+            .collect();
+        table_buffer.buffer_chunk(0, rows, &BTreeMap::new());
+
+        // the arrays of the sealed segment are not copied for queries:
+        let chunk = &table_buffer.chunk_time_to_chunks[&0];
+        let segment = &chunk.segments[&ColumnId::from(1)][0];
+        let batches = chunk
+            .record_batches(Arc::clone(&table_def), &BufferFilter::default())
+            .unwrap();
+        assert!(Arc::ptr_eq(
+            segment,
+            batches[0].column_by_name("val").unwrap()
+        ));
+
+        // nor are they when a row buffered since the segment was sealed is superseded:
+        table_buffer.buffer_chunk(0, vec![row("a", 8192, 8192)], &BTreeMap::new());
+        let chunk = &table_buffer.chunk_time_to_chunks[&0];
+        let segment = &chunk.segments[&ColumnId::from(1)][0];
+        let batches = chunk
+            .record_batches(Arc::clone(&table_def), &BufferFilter::default())
+            .unwrap();
+        assert!(Arc::ptr_eq(
+            segment,
+            batches[0].column_by_name("val").unwrap()
+        ));
+        assert_eq!(1, batches[1].num_rows());
+
+        // with a column that is only in the last row, which also supersedes the first row:
+        let mut last_row = row("a", -1, 0);
+        last_row.fields.push(Field {
+            id: ColumnId::from(3),
+            value: FieldData::Boolean(true),
+        });
+        table_buffer.buffer_chunk(0, vec![last_row], &BTreeMap::new());
+
+        let batches = table_buffer
+            .record_batches(Arc::clone(&table_def), &[])
+            .unwrap();
+        assert_eq!(2, batches.len());
+        assert_eq!(SEGMENT_ROW_COUNT - 1, batches[0].num_rows());
+        assert_eq!(2, batches[1].num_rows());
+        let extra = batches[0].column_by_name("extra").unwrap();
+        assert_eq!(extra.len(), extra.null_count());
+        assert_batches_eq!(
+            [
+                "+-------+-----+--------------------------------+------+",
+                "| extra | tag | time                           | val  |",
+                "+-------+-----+--------------------------------+------+",
+                "|       | a   | 1970-01-01T00:00:00.000008192Z | 8192 |",
+                "| true  | a   | 1970-01-01T00:00:00Z           | -1   |",
+                "+-------+-----+--------------------------------+------+",
+            ],
+            &batches[1..]
+        );
+
+        // the index finds rows in both the sealed segment and the builders:
+        let filter = &[Expr::BinaryExpr(BinaryExpr {
+            left: Box::new(Expr::Column(Column {
+                relation: None,
+                name: "tag".to_string(),
+            })),
+            op: datafusion::logical_expr::Operator::Eq,
+            right: Box::new(Expr::Literal(datafusion::scalar::ScalarValue::Utf8(Some(
+                "b".to_string(),
+            )))),
+        })];
+        let batches = table_buffer
+            .record_batches(Arc::clone(&table_def), filter)
+            .unwrap();
+        assert_eq!(
+            SEGMENT_ROW_COUNT / 2,
+            batches.iter().map(|b| b.num_rows()).sum::<usize>()
+        );
+
+        // the segments are combined with the builders when the chunk is snapshot:
+        let snapshot_chunks = table_buffer.snapshot(Arc::clone(&table_def), 60).unwrap();
+        assert_eq!(1, snapshot_chunks.len());
+        assert_eq!(
+            SEGMENT_ROW_COUNT + 1,
+            snapshot_chunks[0].record_batch.num_rows()
+        );
     }
 
     #[test]
@@ -0,0 +1,417 @@
+//! The mapping of writes of tabular data, in CSV or JSON lines, to the rows of a table, with a
+//! [`ColumnMapping`], which are then validated as any other rows are.
+
+use crate::{ColumnMapping, WriteFieldValue, WriteLineError, WriteLineErrorCategory, WriteRow};
+use schema::InfluxFieldType;
+use std::borrow::Cow;
+
+/// A record of a write of tabular data that was mapped to a row
+#[derive(Debug)]
+pub(crate) struct TabularRow {
+    /// The index of the line that the record starts on
+    pub(crate) line_idx: usize,
+    /// The record, which is reported with any error in its row
+    pub(crate) raw_line: String,
+    pub(crate) row: WriteRow,
+}
+
+/// The value of a column of a record
+struct ColumnValue<'a> {
+    text: Cow<'a, str>,
+    /// Whether the value is known to be a string, i.e., it is a JSON string, so that the type of
+    /// a field is not inferred from its text
+    string: bool,
+}
+
+/// Map the records of a CSV, which has a header row that names its columns, to rows
+pub(crate) fn csv_rows(
+    body: &str,
+    mapping: &ColumnMapping,
+) -> Vec<Result<TabularRow, WriteLineError>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .from_reader(body.as_bytes());
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => {
+            return vec![Err(parse_error(
+                body.lines().next().unwrap_or_default(),
+                0,
+                e.to_string(),
+            ))]
+        }
+    };
+
+    // records are reported as they are in the body, which they are sliced from by their byte
+    // offsets. Their line indexes are counted from those offsets too, as a record can span
+    // several lines, and the line numbers of the reader skip the blank lines between records:
+    let mut line_index = LineIndex::new(body);
+    let mut rows = vec![];
+    let mut record = csv::StringRecord::new();
+    loop {
+        let before = reader.position().byte();
+        match reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => {
+                let start = record.position().map_or(before, |position| position.byte());
+                let (line_idx, raw_line) = line_index.record(start, reader.position().byte());
+                rows.push(csv_row(&headers, mapping, &record, line_idx, raw_line));
+            }
+            Err(e) => {
+                let start = e.position().map_or(before, |position| position.byte());
+                let (line_idx, raw_line) = line_index.record(start, reader.position().byte());
+                rows.push(Err(parse_error(raw_line, line_idx, e.to_string())));
+                // the reader moves on to the next record after an error in one, unless it cannot
+                // read any further:
+                if reader.position().byte() == before {
+                    break;
+                }
+            }
+        }
+    }
+    rows
+}
+
+/// Map a record of a CSV to a row
+fn csv_row(
+    headers: &csv::StringRecord,
+    mapping: &ColumnMapping,
+    record: &csv::StringRecord,
+    line_idx: usize,
+    raw_line: &str,
+) -> Result<TabularRow, WriteLineError> {
+    if record.len() > headers.len() {
+        return Err(parse_error(
+            raw_line,
+            line_idx,
+            format!(
+                "the record has {} values, but there are only {} columns",
+                record.len(),
+                headers.len()
+            ),
+        ));
+    }
+    let columns = headers.iter().zip(record.iter()).map(|(column, value)| {
+        let value = ColumnValue {
+            text: Cow::Borrowed(value),
+            string: false,
+        };
+        (column, value)
+    });
+    match map_row(mapping, columns) {
+        Ok(row) => Ok(TabularRow {
+            line_idx,
+            raw_line: raw_line.to_string(),
+            row,
+        }),
+        Err(message) => Err(parse_error(raw_line, line_idx, message)),
+    }
+}
+
+/// Finds the lines of the records of a CSV from their byte offsets, which must be given in
+/// ascending order, so that the lines of the body are only counted once
+struct LineIndex<'a> {
+    body: &'a str,
+    offset: usize,
+    line_idx: usize,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(body: &'a str) -> Self {
+        Self {
+            body,
+            offset: 0,
+            line_idx: 0,
+        }
+    }
+
+    /// The index of the line that the record between the byte offsets `start` and `end` starts
+    /// on, and its text, without the line terminator
+    fn record(&mut self, start: u64, end: u64) -> (usize, &'a str) {
+        let len = self.body.len();
+        let start = usize::try_from(start).map_or(len, |start| start.clamp(self.offset, len));
+        let end = usize::try_from(end).map_or(len, |end| end.clamp(start, len));
+        let text = self.body.get(start..end).unwrap_or_default();
+        // the offset of a record can be that of blank lines before it:
+        let trimmed = text.trim_start_matches(['\r', '\n']);
+        let start = start + (text.len() - trimmed.len());
+        self.line_idx += self.body.as_bytes()[self.offset..start]
+            .iter()
+            .filter(|&&byte| byte == b'\n')
+            .count();
+        self.offset = start;
+        let raw_line = match trimmed.trim_end_matches(['\r', '\n']) {
+            // the reader can be left where the record starts by an error in it:
+            "" => self.body[start..].lines().next().unwrap_or_default(),
+            raw_line => raw_line,
+        };
+        (self.line_idx, raw_line)
+    }
+}
+
+/// Map the objects on the lines of JSON lines to rows. Blank lines are skipped.
+pub(crate) fn json_lines_rows(
+    body: &str,
+    mapping: &ColumnMapping,
+) -> Vec<Result<TabularRow, WriteLineError>> {
+    body.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(line_idx, line)| {
+            let object = match serde_json::from_str::<serde_json::Value>(line) {
+                Ok(serde_json::Value::Object(object)) => object,
+                Ok(_) => {
+                    return Err(parse_error(
+                        line,
+                        line_idx,
+                        "the line is not a JSON object".to_string(),
+                    ))
+                }
+                Err(e) => return Err(parse_error(line, line_idx, e.to_string())),
+            };
+            let mut columns = Vec::with_capacity(object.len());
+            for (column, value) in &object {
+                let value = match value {
+                    serde_json::Value::Null => continue,
+                    serde_json::Value::Bool(value) => ColumnValue {
+                        text: Cow::Owned(value.to_string()),
+                        string: false,
+                    },
+                    serde_json::Value::Number(value) => ColumnValue {
+                        text: Cow::Owned(value.to_string()),
+                        string: false,
+                    },
+                    serde_json::Value::String(value) => ColumnValue {
+                        text: Cow::Borrowed(value.as_str()),
+                        string: true,
+                    },
+                    serde_json::Value::Array(_) | serde_json::Value::Object(_) => {
+                        return Err(parse_error(
+                            line,
+                            line_idx,
+                            format!("column {column} has a nested value, which cannot be written"),
+                        ))
+                    }
+                };
+                columns.push((column.as_str(), value));
+            }
+            match map_row(mapping, columns.into_iter()) {
+                Ok(row) => Ok(TabularRow {
+                    line_idx,
+                    raw_line: line.to_string(),
+                    row,
+                }),
+                Err(message) => Err(parse_error(line, line_idx, message)),
+            }
+        })
+        .collect()
+}
+
+/// Map the values of the columns of a record to a row
+fn map_row<'a>(
+    mapping: &ColumnMapping,
+    columns: impl Iterator<Item = (&'a str, ColumnValue<'a>)>,
+) -> Result<WriteRow, String> {
+    let mut row = WriteRow {
+        table_name: mapping.table_name.clone(),
+        tags: vec![],
+        fields: vec![],
+        timestamp: None,
+    };
+    for (column, value) in columns {
+        if value.text.is_empty() || mapping.ignore.iter().any(|ignored| ignored == column) {
+            continue;
+        }
+        if mapping.time_column.as_deref() == Some(column) {
+            let timestamp = value.text.parse().map_err(|_| {
+                format!(
+                    "time column {column} has the value {}, which is not an integer timestamp",
+                    value.text
+                )
+            })?;
+            row.timestamp = Some(timestamp);
+        } else if mapping.tags.iter().any(|tag| tag == column) {
+            row.tags.push((column.to_string(), value.text.into_owned()));
+        } else {
+            let field_value = field_value(column, &value, mapping.field_types.get(column))?;
+            row.fields.push((column.to_string(), field_value));
+        }
+    }
+    Ok(row)
+}
+
+/// The value of a field, of `field_type` if it is given, or else of the type inferred from it
+fn field_value(
+    column: &str,
+    value: &ColumnValue<'_>,
+    field_type: Option<&InfluxFieldType>,
+) -> Result<WriteFieldValue, String> {
+    let text = value.text.as_ref();
+    let invalid = |type_name: &str| format!("field {column} has the value {text}, not {type_name}");
+    match field_type {
+        Some(InfluxFieldType::Float) => text
+            .parse()
+            .map(WriteFieldValue::F64)
+            .map_err(|_| invalid("a float")),
+        Some(InfluxFieldType::Integer) => text
+            .parse()
+            .map(WriteFieldValue::I64)
+            .map_err(|_| invalid("an integer")),
+        Some(InfluxFieldType::UInteger) => text
+            .parse()
+            .map(WriteFieldValue::U64)
+            .map_err(|_| invalid("an unsigned integer")),
+        Some(InfluxFieldType::Boolean) => parse_bool(text)
+            .map(WriteFieldValue::Boolean)
+            .ok_or_else(|| invalid("a boolean")),
+        Some(InfluxFieldType::String) => Ok(WriteFieldValue::String(text.to_string())),
+        None if value.string => Ok(WriteFieldValue::String(text.to_string())),
+        None => match (text.parse::<f64>(), parse_bool(text)) {
+            (Ok(number), _) if number.is_finite() => Ok(WriteFieldValue::F64(number)),
+            (_, Some(boolean)) => Ok(WriteFieldValue::Boolean(boolean)),
+            _ => Ok(WriteFieldValue::String(text.to_string())),
+        },
+    }
+}
+
+fn parse_bool(text: &str) -> Option<bool> {
+    if text.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if text.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn parse_error(line: &str, line_idx: usize, error_message: String) -> WriteLineError {
+    WriteLineError {
+        original_line: line.to_string(),
+        line_number: line_idx + 1,
+        error_category: WriteLineErrorCategory::Parse,
+        error_message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping() -> ColumnMapping {
+        ColumnMapping {
+            table_name: "sensors".to_string(),
+            tags: vec!["site".to_string()],
+            time_column: Some("ts".to_string()),
+            field_types: [("count".to_string(), InfluxFieldType::Integer)].into(),
+            ignore: vec!["note".to_string()],
+        }
+    }
+
+    #[test]
+    fn csv_records_are_mapped_to_rows() {
+        let body = "site,ts,temp,count,on,note\n\
+            north,10,21.5,3,true,ignored\n\
+            south,,,4,,\n\
+            east,20,1.0,lots,false,\n";
+        let rows = csv_rows(body, &mapping());
+        assert_eq!(3, rows.len());
+
+        let row = rows[0].as_ref().unwrap();
+        assert_eq!(1, row.line_idx);
+        assert_eq!(
+            WriteRow {
+                table_name: "sensors".to_string(),
+                tags: vec![("site".to_string(), "north".to_string())],
+                fields: vec![
+                    ("temp".to_string(), WriteFieldValue::F64(21.5)),
+                    ("count".to_string(), WriteFieldValue::I64(3)),
+                    ("on".to_string(), WriteFieldValue::Boolean(true)),
+                ],
+                timestamp: Some(10),
+            },
+            row.row
+        );
+        // empty values are left out, and the row has the ingest time:
+        let row = rows[1].as_ref().unwrap();
+        assert_eq!(None, row.row.timestamp);
+        assert_eq!(
+            vec![("count".to_string(), WriteFieldValue::I64(4))],
+            row.row.fields
+        );
+
+        let error = rows[2].as_ref().unwrap_err();
+        assert_eq!(4, error.line_number);
+        assert_eq!(WriteLineErrorCategory::Parse, error.error_category);
+        assert_eq!(
+            "field count has the value lots, not an integer",
+            error.error_message
+        );
+    }
+
+    #[test]
+    fn csv_records_are_reported_as_written() {
+        let body = "site,ts,temp,count,on,note\r\n\
+            north,10,1.5,1,true,\"two\nlines\"\r\n\
+            \r\n\
+            \"south\",20,2.5,two,false,\r\n";
+        let rows = csv_rows(body, &mapping());
+        assert_eq!(2, rows.len());
+
+        let row = rows[0].as_ref().unwrap();
+        assert_eq!(1, row.line_idx);
+        assert_eq!("north,10,1.5,1,true,\"two\nlines\"", row.raw_line);
+
+        // the record starts after the multi-line value and the blank line:
+        let error = rows[1].as_ref().unwrap_err();
+        assert_eq!(5, error.line_number);
+        assert_eq!("\"south\",20,2.5,two,false,", error.original_line);
+    }
+
+    #[test]
+    fn json_lines_are_mapped_to_rows() {
+        let body = r#"{"site": "north", "ts": 10, "temp": 21.5, "label": "1", "note": "x"}
+
+{"site": "south", "temp": null, "count": 2}
+{"site": "east", "temp": [1, 2]}
+[1, 2]"#;
+        let rows = json_lines_rows(body, &mapping());
+        assert_eq!(4, rows.len());
+
+        let row = rows[0].as_ref().unwrap();
+        assert_eq!(Some(10), row.row.timestamp);
+        assert_eq!(
+            vec![("site".to_string(), "north".to_string())],
+            row.row.tags
+        );
+        // JSON strings are strings, even if they look like numbers:
+        let mut fields = row.row.fields.clone();
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            vec![
+                (
+                    "label".to_string(),
+                    WriteFieldValue::String("1".to_string())
+                ),
+                ("temp".to_string(), WriteFieldValue::F64(21.5)),
+            ],
+            fields
+        );
+
+        let row = rows[1].as_ref().unwrap();
+        assert_eq!(2, row.line_idx);
+        assert_eq!(
+            vec![("count".to_string(), WriteFieldValue::I64(2))],
+            row.row.fields
+        );
+
+        assert_eq!(
+            "column temp has a nested value, which cannot be written",
+            rows[2].as_ref().unwrap_err().error_message
+        );
+        assert_eq!(
+            "the line is not a JSON object",
+            rows[3].as_ref().unwrap_err().error_message
+        );
+    }
+}
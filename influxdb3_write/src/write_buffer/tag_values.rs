@@ -0,0 +1,110 @@
+//! Interning of the tag values written to the buffer
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use influxdb3_id::{DbId, TableId};
+use influxdb3_wal::{FieldData, Row};
+use parking_lot::Mutex;
+
+/// The distinct tag values written to each table, interned so that the validated writes, the
+/// WAL write batches, and the rows in the buffer that have the same value all share a single
+/// allocation of it.
+///
+/// Values that nothing but the dictionary holds on to any more are removed by
+/// [`TagValueDictionary::remove_unused`], which is done whenever the buffer is snapshot.
+#[derive(Debug, Default)]
+pub struct TagValueDictionary {
+    tables: Mutex<HashMap<(DbId, TableId), HashSet<Arc<str>>>>,
+}
+
+impl TagValueDictionary {
+    /// Get the interned copy of a value of a tag in the table, adding it if it is new
+    pub fn intern(&self, db_id: DbId, table_id: TableId, value: &str) -> Arc<str> {
+        let mut tables = self.tables.lock();
+        intern(tables.entry((db_id, table_id)).or_default(), value)
+    }
+
+    /// Replace the tag and series key values of rows in the table with their interned copies,
+    /// e.g., for rows that were replayed from the WAL
+    pub(crate) fn intern_rows(&self, db_id: DbId, table_id: TableId, rows: &mut [Row]) {
+        let mut tables = self.tables.lock();
+        let values = tables.entry((db_id, table_id)).or_default();
+        for field in rows.iter_mut().flat_map(|row| row.fields.iter_mut()) {
+            if let FieldData::Tag(v) | FieldData::Key(v) = &mut field.value {
+                *v = intern(values, v);
+            }
+        }
+    }
+
+    /// Remove the values that are no longer in any write or buffered row
+    pub fn remove_unused(&self) {
+        let mut tables = self.tables.lock();
+        for values in tables.values_mut() {
+            values.retain(|v| Arc::strong_count(v) > 1);
+        }
+        tables.retain(|_, values| !values.is_empty());
+    }
+
+    /// The number of distinct values in the dictionary, across all tables
+    pub fn len(&self) -> usize {
+        self.tables.lock().values().map(|values| values.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn intern(values: &mut HashSet<Arc<str>>, value: &str) -> Arc<str> {
+    if let Some(interned) = values.get(value) {
+        return Arc::clone(interned);
+    }
+    let interned: Arc<str> = Arc::from(value);
+    values.insert(Arc::clone(&interned));
+    interned
+}
+
+#[cfg(test)]
+mod tests {
+    use influxdb3_id::ColumnId;
+    use influxdb3_wal::Field;
+
+    use super::*;
+
+    #[test]
+    fn values_are_shared_until_unused() {
+        let dictionary = TagValueDictionary::default();
+        let (db_id, table_id) = (DbId::from(0), TableId::from(0));
+
+        let a = dictionary.intern(db_id, table_id, "a");
+        assert!(Arc::ptr_eq(&a, &dictionary.intern(db_id, table_id, "a")));
+        // values are interned separately for each table:
+        let other = dictionary.intern(db_id, TableId::from(1), "a");
+        assert!(!Arc::ptr_eq(&a, &other));
+
+        let mut row = Row {
+            time: 0,
+            fields: vec![
+                Field::new(ColumnId::from(0), FieldData::Tag(Arc::from("a"))),
+                Field::new(ColumnId::from(1), FieldData::Key(Arc::from("b"))),
+            ],
+        };
+        dictionary.intern_rows(db_id, table_id, std::slice::from_mut(&mut row));
+        let FieldData::Tag(v) = &row.fields[0].value else {
+            panic!("unexpected field type");
+        };
+        assert!(Arc::ptr_eq(&a, v));
+        assert_eq!(3, dictionary.len());
+
+        // only the values that are still held on to are kept:
+        drop(other);
+        dictionary.remove_unused();
+        assert_eq!(2, dictionary.len());
+        drop((a, row));
+        dictionary.remove_unused();
+        assert!(dictionary.is_empty());
+    }
+}
@@ -0,0 +1,48 @@
+//! Transformation of the rows written to a database before they are validated against its schema
+//! and buffered, e.g., to rename tags, drop or derive fields, or route rows to another table
+
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+
+use parking_lot::RwLock;
+
+use crate::WriteRow;
+
+/// Transforms the rows written to a database. Transforms are applied to writes that use the v1
+/// data model, after the line protocol has been parsed, but before the rows are validated
+/// against the schema of the database, so any schema changes made by a transform, such as a
+/// derived field or a renamed tag, are added to the catalog like those of any other write.
+///
+/// Writes of v3 line protocol are not transformed, since the series key of a v3 table is fixed
+/// when the table is created, and a renamed tag or a row routed to another table would not
+/// match it.
+///
+/// Transforms are called inline on the write path, so they should return quickly.
+pub trait WriteTransform: Debug + Send + Sync + 'static {
+    /// Transform a row, returning the row to write in its place, or `None` to drop it. The
+    /// table name of the row can be changed to write it to a different table.
+    fn transform(&self, row: WriteRow) -> Option<WriteRow>;
+}
+
+/// The [`WriteTransform`] configured for each database
+#[derive(Debug, Default)]
+pub(crate) struct WriteTransforms {
+    transforms: RwLock<HashMap<String, Arc<dyn WriteTransform>>>,
+}
+
+impl WriteTransforms {
+    pub(crate) fn set(&self, db_name: &str, transform: Option<Arc<dyn WriteTransform>>) {
+        let mut transforms = self.transforms.write();
+        match transform {
+            Some(transform) => {
+                transforms.insert(db_name.to_string(), transform);
+            }
+            None => {
+                transforms.remove(db_name);
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, db_name: &str) -> Option<Arc<dyn WriteTransform>> {
+        self.transforms.read().get(db_name).cloned()
+    }
+}
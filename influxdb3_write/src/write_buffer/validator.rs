@@ -1,22 +1,39 @@
 use std::{borrow::Cow, sync::Arc};
 
-use crate::{write_buffer::Result, Precision, WriteLineError};
+use crate::{
+    write_buffer::Result, ColumnMapping, Precision, WriteFieldValue, WriteLineError,
+    WriteLineErrorCategory, WriteRow,
+};
 use data_types::{NamespaceName, Timestamp};
 use indexmap::IndexMap;
 use influxdb3_catalog::catalog::{
     influx_column_type_from_field_value, Catalog, DatabaseSchema, TableDefinition,
 };
 
-use influxdb3_id::{ColumnId, TableId};
+use influxdb3_id::{ColumnId, DbId, TableId};
 use influxdb3_wal::{
-    CatalogBatch, CatalogOp, Field, FieldAdditions, FieldData, FieldDefinition, Gen1Duration, Row,
-    TableChunks, WriteBatch,
+    CatalogBatch, CatalogOp, CompatibilityMode, Field, FieldAdditions, FieldCoercion, FieldData,
+    FieldDefinition, Gen1Duration, IdentifierPolicy, Row, SchemaMode, SeriesKeyPolicy, TableChunks,
+    WriteBatch,
+};
+use influxdb_line_protocol::{
+    parse_lines,
+    v3::{self, SeriesValue},
+    FieldValue, ParsedLine,
 };
-use influxdb_line_protocol::{parse_lines, v3, ParsedLine};
 use iox_time::Time;
-use schema::{InfluxColumnType, TIME_COLUMN_NAME};
-
-use super::Error;
+use schema::{InfluxColumnType, InfluxFieldType, TIME_COLUMN_NAME};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use super::{
+    cardinality::{CardinalityTracker, NewSeries},
+    compat,
+    rate_limit::WriteRateLimiter,
+    tabular::{self, TabularRow},
+    tag_values::TagValueDictionary,
+    transform::WriteTransform,
+    Error,
+};
 
 /// Type state for the [`WriteValidator`] after it has been initialized
 /// with the catalog.
@@ -24,6 +41,135 @@ pub(crate) struct WithCatalog {
     catalog: Arc<Catalog>,
     db_schema: Arc<DatabaseSchema>,
     time_now_ns: i64,
+    cardinality: Option<Arc<CardinalityTracker>>,
+    rate_limiter: Option<(Arc<WriteRateLimiter>, Time)>,
+    transform: Option<Arc<dyn WriteTransform>>,
+    tag_values: Option<Arc<TagValueDictionary>>,
+    dry_run: bool,
+}
+
+impl WithCatalog {
+    /// Apply the schema changes made by the validated lines to the catalog, or, for a dry run,
+    /// only check that they could be applied
+    fn apply_catalog_batch(&self, catalog_batch: &CatalogBatch) -> Result<()> {
+        if self.dry_run {
+            self.catalog.validate_catalog_batch(catalog_batch)?;
+        } else {
+            self.catalog.apply_catalog_batch(catalog_batch)?;
+        }
+        Ok(())
+    }
+
+    /// Charge a write of `lines` lines and `bytes` bytes to the write rate limit of the database,
+    /// returning [`Error::RateLimited`] if it exceeds the limit
+    fn check_rate_limit(&self, lines: usize, bytes: usize) -> Result<()> {
+        let Some((rate_limiter, now)) = &self.rate_limiter else {
+            return Ok(());
+        };
+        rate_limiter
+            .check(&self.db_schema, lines, bytes, *now)
+            .map_err(|retry_after| Error::RateLimited {
+                db_name: self.db_schema.name.to_string(),
+                retry_after,
+            })
+    }
+
+    /// Validate a line that uses the v1 data model, after applying the transform to it if there
+    /// is one, and normalizing its names according to the identifier policy of the database.
+    /// Returns `Ok(None)` if the transform dropped the line.
+    #[allow(clippy::too_many_arguments)]
+    fn validate_v1_line(
+        &self,
+        db_schema: &mut Cow<'_, DatabaseSchema>,
+        line_number: usize,
+        line: &impl V1Line,
+        raw_line: &str,
+        ingest_time: Time,
+        precision: Precision,
+        new_series: &mut NewSeries,
+        ids: &mut IdAllocator,
+    ) -> Result<Option<(QualifiedLine, Option<CatalogOp>)>, WriteLineError> {
+        let identifier_policy = db_schema.identifier_policy;
+        if self.transform.is_none() && identifier_policy.is_preserve() {
+            return validate_and_qualify_v1_line(
+                db_schema,
+                line_number,
+                line,
+                raw_line,
+                ingest_time,
+                precision,
+                self.cardinality.as_deref(),
+                new_series,
+                ids,
+                self.tag_values.as_deref(),
+            )
+            .map(Some);
+        }
+        let mut row = line.to_row();
+        if let Some(transform) = self.transform.as_deref() {
+            let Some(transformed) = transform.transform(row) else {
+                return Ok(None);
+            };
+            row = transformed;
+        }
+        normalize_row_names(identifier_policy, &mut row);
+        validate_and_qualify_v1_line(
+            db_schema,
+            line_number,
+            &row,
+            raw_line,
+            ingest_time,
+            precision,
+            self.cardinality.as_deref(),
+            new_series,
+            ids,
+            self.tag_values.as_deref(),
+        )
+        .map(Some)
+    }
+}
+
+/// Hands out the ids of the tables and columns that validated lines add to the catalog
+#[derive(Debug)]
+enum IdAllocator {
+    /// Take the ids from the global counters
+    Catalog,
+    /// Hand out placeholder ids that follow on from the next ids of the global counters, without
+    /// advancing them, as the tables and columns of a dry run never reach the catalog
+    DryRun { next_table: u32, next_column: u32 },
+}
+
+impl IdAllocator {
+    fn new(dry_run: bool) -> Self {
+        if dry_run {
+            Self::DryRun {
+                next_table: TableId::next_id().as_u32(),
+                next_column: ColumnId::next_id().as_u32(),
+            }
+        } else {
+            Self::Catalog
+        }
+    }
+
+    fn table_id(&mut self) -> TableId {
+        match self {
+            Self::Catalog => TableId::new(),
+            Self::DryRun { next_table, .. } => {
+                *next_table += 1;
+                TableId::from(*next_table - 1)
+            }
+        }
+    }
+
+    fn column_id(&mut self) -> ColumnId {
+        match self {
+            Self::Catalog => ColumnId::new(),
+            Self::DryRun { next_column, .. } => {
+                *next_column += 1;
+                ColumnId::from(*next_column - 1)
+            }
+        }
+    }
 }
 
 /// Type state for the [`WriteValidator`] after it has parsed v1 or v3
@@ -33,6 +179,7 @@ pub(crate) struct LinesParsed {
     lines: Vec<QualifiedLine>,
     catalog_batch: Option<CatalogBatch>,
     errors: Vec<WriteLineError>,
+    new_series: NewSeries,
 }
 
 /// A state machine for validating v1 or v3 line protocol and updating
@@ -55,10 +202,77 @@ impl WriteValidator<WithCatalog> {
                 catalog,
                 db_schema,
                 time_now_ns,
+                cardinality: None,
+                rate_limiter: None,
+                transform: None,
+                tag_values: None,
+                dry_run: false,
             },
         })
     }
 
+    /// Initialize a [`WriteValidator`] for a dry run, which validates lines in the same way, but
+    /// leaves the catalog unchanged, including not creating the database if it does not exist
+    pub(crate) fn initialize_dry_run(
+        db_name: NamespaceName<'static>,
+        catalog: Arc<Catalog>,
+        time_now_ns: i64,
+    ) -> Result<WriteValidator<WithCatalog>> {
+        let db_schema = catalog.db_schema(db_name.as_str()).unwrap_or_else(|| {
+            Arc::new(DatabaseSchema::new(
+                DbId::next_id(),
+                Arc::from(db_name.as_str()),
+            ))
+        });
+        Ok(WriteValidator {
+            state: WithCatalog {
+                catalog,
+                db_schema,
+                time_now_ns,
+                cardinality: None,
+                rate_limiter: None,
+                transform: None,
+                tag_values: None,
+                dry_run: true,
+            },
+        })
+    }
+
+    /// Enforce the cardinality limits of the database when validating lines, counting the series
+    /// of its tables, which lines that use the v3 data model identify by their series key, with
+    /// the given [`CardinalityTracker`]
+    pub(crate) fn with_cardinality_tracker(mut self, tracker: Arc<CardinalityTracker>) -> Self {
+        self.state.cardinality = Some(tracker);
+        self
+    }
+
+    /// Charge the lines that are parsed, and the bytes they were parsed from, to the write rate
+    /// limit of the database with the given [`WriteRateLimiter`], as of `now`, before the catalog
+    /// is updated
+    pub(crate) fn with_rate_limiter(
+        mut self,
+        rate_limiter: Arc<WriteRateLimiter>,
+        now: Time,
+    ) -> Self {
+        self.state.rate_limiter = Some((rate_limiter, now));
+        self
+    }
+
+    /// Apply the given [`WriteTransform`] to each line that uses the v1 data model before it is
+    /// validated. Lines that use the v3 data model are not transformed.
+    pub(crate) fn with_transform(mut self, transform: Option<Arc<dyn WriteTransform>>) -> Self {
+        self.state.transform = transform;
+        self
+    }
+
+    /// Intern the tag values of lines, or the series key values of lines that use the v3 data
+    /// model, in the given [`TagValueDictionary`], so that they share an allocation with the
+    /// values already buffered
+    pub(crate) fn with_tag_values(mut self, tag_values: Arc<TagValueDictionary>) -> Self {
+        self.state.tag_values = Some(tag_values);
+        self
+    }
+
     /// Parse the incoming lines of line protocol using the v3 parser and update
     /// the [`DatabaseSchema`] if:
     ///
@@ -81,12 +295,15 @@ impl WriteValidator<WithCatalog> {
         let mut lines = vec![];
         let mut catalog_updates = vec![];
         let mut schema = Cow::Borrowed(self.state.db_schema.as_ref());
+        let mut new_series = NewSeries::default();
+        let mut ids = IdAllocator::new(self.state.dry_run);
 
         for (line_idx, maybe_line) in v3::parse_lines(lp).enumerate() {
             let (qualified_line, catalog_op) = match maybe_line
                 .map_err(|e| WriteLineError {
                     original_line: lp_lines.next().unwrap().to_string(),
                     line_number: line_idx + 1,
+                    error_category: WriteLineErrorCategory::Parse,
                     error_message: e.to_string(),
                 })
                 .and_then(|line| {
@@ -97,6 +314,10 @@ impl WriteValidator<WithCatalog> {
                         lp_lines.next().unwrap(),
                         ingest_time,
                         precision,
+                        self.state.cardinality.as_deref(),
+                        &mut new_series,
+                        self.state.tag_values.as_deref(),
+                        &mut ids,
                     )
                 }) {
                 Ok((qualified_line, catalog_ops)) => (qualified_line, catalog_ops),
@@ -104,7 +325,7 @@ impl WriteValidator<WithCatalog> {
                     if accept_partial {
                         errors.push(error);
                     } else {
-                        return Err(Error::ParseError(error));
+                        return Err(rejected_line_error(error));
                     }
                     continue;
                 }
@@ -117,6 +338,8 @@ impl WriteValidator<WithCatalog> {
             lines.push(qualified_line);
         }
 
+        self.state
+            .check_rate_limit(lines.len() + errors.len(), lp.len())?;
         let catalog_batch = if catalog_updates.is_empty() {
             None
         } else {
@@ -126,7 +349,7 @@ impl WriteValidator<WithCatalog> {
                 time_ns: self.state.time_now_ns,
                 ops: catalog_updates,
             };
-            self.state.catalog.apply_catalog_batch(&catalog_batch)?;
+            self.state.apply_catalog_batch(&catalog_batch)?;
             Some(catalog_batch)
         };
 
@@ -135,6 +358,7 @@ impl WriteValidator<WithCatalog> {
                 catalog: self.state,
                 lines,
                 catalog_batch,
+                new_series,
                 errors,
             },
         })
@@ -157,35 +381,70 @@ impl WriteValidator<WithCatalog> {
         ingest_time: Time,
         precision: Precision,
     ) -> Result<WriteValidator<LinesParsed>> {
+        self.v1_parse_lines_from(lp, 0, accept_partial, ingest_time, precision)
+            .map(|(validator, _)| validator)
+    }
+
+    /// Parse and validate the lines of `lp` in the same way as
+    /// [`WriteValidator::v1_parse_lines_and_update_schema`], numbering them from
+    /// `first_line_idx`. Returns the number of lines that were parsed along with the validator.
+    fn v1_parse_lines_from(
+        self,
+        lp: &str,
+        first_line_idx: usize,
+        accept_partial: bool,
+        ingest_time: Time,
+        precision: Precision,
+    ) -> Result<(WriteValidator<LinesParsed>, usize)> {
+        let lp = match self.state.db_schema.compatibility_mode {
+            CompatibilityMode::Off => Cow::Borrowed(lp),
+            CompatibilityMode::InfluxDb1 => compat::normalize_lp(lp),
+        };
+        let lp = lp.as_ref();
         let mut errors = vec![];
-        let mut lp_lines = lp.lines();
+        // blank lines and comments do not produce a parsed line, so they are skipped to keep the
+        // raw lines in step with the parsed ones:
+        let mut lp_lines = lp.lines().filter(|line| {
+            let line = line.trim_start();
+            !line.is_empty() && !line.starts_with('#')
+        });
         let mut lines = vec![];
         let mut catalog_updates = vec![];
         let mut schema = Cow::Borrowed(self.state.db_schema.as_ref());
+        let mut new_series = NewSeries::default();
+        let mut ids = IdAllocator::new(self.state.dry_run);
+        let mut n_lines = 0;
 
         for (line_idx, maybe_line) in parse_lines(lp).enumerate() {
+            let line_idx = first_line_idx + line_idx;
+            n_lines += 1;
             let (qualified_line, catalog_op) = match maybe_line
                 .map_err(|e| WriteLineError {
                     // This unwrap is fine because we're moving line by line
                     // alongside the output from parse_lines
                     original_line: lp_lines.next().unwrap().to_string(),
                     line_number: line_idx + 1,
+                    error_category: WriteLineErrorCategory::Parse,
                     error_message: e.to_string(),
                 })
                 .and_then(|l| {
-                    validate_and_qualify_v1_line(
+                    self.state.validate_v1_line(
                         &mut schema,
                         line_idx,
-                        l,
+                        &l,
                         lp_lines.next().unwrap(),
                         ingest_time,
                         precision,
+                        &mut new_series,
+                        &mut ids,
                     )
                 }) {
-                Ok((qualified_line, catalog_op)) => (qualified_line, catalog_op),
+                Ok(Some((qualified_line, catalog_op))) => (qualified_line, catalog_op),
+                // the line was dropped by the transform:
+                Ok(None) => continue,
                 Err(e) => {
                     if !accept_partial {
-                        return Err(Error::ParseError(e));
+                        return Err(rejected_line_error(e));
                     } else {
                         errors.push(e);
                     }
@@ -200,6 +459,8 @@ impl WriteValidator<WithCatalog> {
             lines.push(qualified_line);
         }
 
+        self.state
+            .check_rate_limit(lines.len() + errors.len(), lp.len())?;
         // All lines are parsed and validated, so all steps after this
         // are infallible, therefore, update the catalog if changes were
         // made to the schema:
@@ -212,7 +473,203 @@ impl WriteValidator<WithCatalog> {
                 database_name: Arc::clone(&self.state.db_schema.name),
                 ops: catalog_updates,
             };
-            self.state.catalog.apply_catalog_batch(&catalog_batch)?;
+            self.state.apply_catalog_batch(&catalog_batch)?;
+            Some(catalog_batch)
+        };
+
+        Ok((
+            WriteValidator {
+                state: LinesParsed {
+                    catalog: self.state,
+                    lines,
+                    errors,
+                    catalog_batch,
+                    new_series,
+                },
+            },
+            n_lines,
+        ))
+    }
+
+    /// Validate structured rows using the v1 data model and update the [`DatabaseSchema`], in
+    /// the same way as [`WriteValidator::v1_parse_lines_and_update_schema`], but without any line
+    /// protocol parsing.
+    pub(crate) fn v1_validate_rows_and_update_schema(
+        self,
+        rows: &[WriteRow],
+        accept_partial: bool,
+        ingest_time: Time,
+        precision: Precision,
+    ) -> Result<WriteValidator<LinesParsed>> {
+        let mut errors = vec![];
+        let mut lines = vec![];
+        let mut catalog_updates = vec![];
+        let mut schema = Cow::Borrowed(self.state.db_schema.as_ref());
+        let mut new_series = NewSeries::default();
+        let mut ids = IdAllocator::new(self.state.dry_run);
+
+        for (row_idx, row) in rows.iter().enumerate() {
+            let (qualified_line, catalog_op) = match self.state.validate_v1_line(
+                &mut schema,
+                row_idx,
+                row,
+                "",
+                ingest_time,
+                precision,
+                &mut new_series,
+                &mut ids,
+            ) {
+                Ok(Some((qualified_line, catalog_op))) => (qualified_line, catalog_op),
+                Ok(None) => continue,
+                Err(e) => {
+                    if !accept_partial {
+                        return Err(rejected_line_error(e));
+                    } else {
+                        errors.push(e);
+                    }
+                    continue;
+                }
+            };
+            if let Some(op) = catalog_op {
+                catalog_updates.push(op);
+            }
+            lines.push(qualified_line);
+        }
+
+        // rows are not line protocol, so only count towards the lines per second limit:
+        self.state.check_rate_limit(lines.len() + errors.len(), 0)?;
+        let catalog_batch = if catalog_updates.is_empty() {
+            None
+        } else {
+            let catalog_batch = CatalogBatch {
+                database_id: self.state.db_schema.id,
+                time_ns: self.state.time_now_ns,
+                database_name: Arc::clone(&self.state.db_schema.name),
+                ops: catalog_updates,
+            };
+            self.state.apply_catalog_batch(&catalog_batch)?;
+            Some(catalog_batch)
+        };
+
+        Ok(WriteValidator {
+            state: LinesParsed {
+                catalog: self.state,
+                lines,
+                errors,
+                catalog_batch,
+                new_series,
+            },
+        })
+    }
+
+    /// Parse a CSV, with a header row, into rows of the table of `mapping`, and validate them
+    /// using the v1 data model, updating the [`DatabaseSchema`] in the same way as
+    /// [`WriteValidator::v1_parse_lines_and_update_schema`]. Records that cannot be mapped to a
+    /// row are rejected as lines that cannot be parsed.
+    pub(crate) fn parse_csv_and_update_schema(
+        self,
+        csv: &str,
+        mapping: &ColumnMapping,
+        accept_partial: bool,
+        ingest_time: Time,
+        precision: Precision,
+    ) -> Result<WriteValidator<LinesParsed>> {
+        let rows = tabular::csv_rows(csv, mapping);
+        self.validate_tabular_rows_and_update_schema(
+            rows,
+            csv.len(),
+            accept_partial,
+            ingest_time,
+            precision,
+        )
+    }
+
+    /// Like [`WriteValidator::parse_csv_and_update_schema`], but for JSON lines, with an object
+    /// on each line
+    pub(crate) fn parse_json_lines_and_update_schema(
+        self,
+        json_lines: &str,
+        mapping: &ColumnMapping,
+        accept_partial: bool,
+        ingest_time: Time,
+        precision: Precision,
+    ) -> Result<WriteValidator<LinesParsed>> {
+        let rows = tabular::json_lines_rows(json_lines, mapping);
+        self.validate_tabular_rows_and_update_schema(
+            rows,
+            json_lines.len(),
+            accept_partial,
+            ingest_time,
+            precision,
+        )
+    }
+
+    /// Validate the rows parsed from a tabular body of `bytes` bytes
+    fn validate_tabular_rows_and_update_schema(
+        self,
+        rows: Vec<Result<TabularRow, WriteLineError>>,
+        bytes: usize,
+        accept_partial: bool,
+        ingest_time: Time,
+        precision: Precision,
+    ) -> Result<WriteValidator<LinesParsed>> {
+        let mut errors = vec![];
+        let mut lines = vec![];
+        let mut catalog_updates = vec![];
+        let mut schema = Cow::Borrowed(self.state.db_schema.as_ref());
+        let mut new_series = NewSeries::default();
+        let mut ids = IdAllocator::new(self.state.dry_run);
+
+        for row in rows {
+            let result = row.and_then(|row| {
+                self.state
+                    .validate_v1_line(
+                        &mut schema,
+                        row.line_idx,
+                        &row.row,
+                        &row.raw_line,
+                        ingest_time,
+                        precision,
+                        &mut new_series,
+                        &mut ids,
+                    )
+                    // the errors of a row report the record that it was mapped from:
+                    .map_err(|e| WriteLineError {
+                        original_line: row.raw_line,
+                        line_number: row.line_idx + 1,
+                        ..e
+                    })
+            });
+            let (qualified_line, catalog_op) = match result {
+                Ok(Some((qualified_line, catalog_op))) => (qualified_line, catalog_op),
+                Ok(None) => continue,
+                Err(e) => {
+                    if !accept_partial {
+                        return Err(rejected_line_error(e));
+                    } else {
+                        errors.push(e);
+                    }
+                    continue;
+                }
+            };
+            if let Some(op) = catalog_op {
+                catalog_updates.push(op);
+            }
+            lines.push(qualified_line);
+        }
+
+        self.state
+            .check_rate_limit(lines.len() + errors.len(), bytes)?;
+        let catalog_batch = if catalog_updates.is_empty() {
+            None
+        } else {
+            let catalog_batch = CatalogBatch {
+                database_id: self.state.db_schema.id,
+                time_ns: self.state.time_now_ns,
+                database_name: Arc::clone(&self.state.db_schema.name),
+                ops: catalog_updates,
+            };
+            self.state.apply_catalog_batch(&catalog_batch)?;
             Some(catalog_batch)
         };
 
@@ -222,9 +679,114 @@ impl WriteValidator<WithCatalog> {
                 lines,
                 errors,
                 catalog_batch,
+                new_series,
             },
         })
     }
+
+    /// Like [`WriteValidator::v1_parse_lines_and_update_schema`], but the line protocol is read
+    /// from `lp`, until at least `max_batch_bytes` have been read, so that a large write can be
+    /// validated and buffered in batches, rather than being held in memory in its entirety. A
+    /// batch only ends at the end of a line, and not at a newline in a string field value, so
+    /// that its lines are parsed in the same way as if the write was parsed as a whole.
+    ///
+    /// `line_idx` is the index of the next line of the write, and is advanced past the lines that
+    /// are parsed, so that errors refer to lines by their position in the whole write. The
+    /// returned flag is `false` once `lp` has been read to the end.
+    pub(crate) async fn v1_parse_lines_batch_and_update_schema<R>(
+        self,
+        lp: &mut R,
+        line_idx: &mut usize,
+        max_batch_bytes: usize,
+        accept_partial: bool,
+        ingest_time: Time,
+        precision: Precision,
+    ) -> Result<(WriteValidator<LinesParsed>, bool)>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        let mut batch = String::new();
+        let mut line_start = 0;
+        let mut more = true;
+        while batch.len() < max_batch_bytes || in_string_field_value(&batch[line_start..]) {
+            if lp
+                .read_line(&mut batch)
+                .await
+                .map_err(Error::ReadLineProtocol)?
+                == 0
+            {
+                more = false;
+                break;
+            }
+            if !in_string_field_value(&batch[line_start..]) {
+                line_start = batch.len();
+            }
+        }
+
+        let (validator, n_lines) =
+            self.v1_parse_lines_from(&batch, *line_idx, accept_partial, ingest_time, precision)?;
+        *line_idx += n_lines;
+        Ok((validator, more))
+    }
+}
+
+/// The value of a member of the series key of a v3 line
+fn series_value_str<'a>(value: &'a SeriesValue<'_>) -> &'a str {
+    match value {
+        SeriesValue::String(s) => s.as_str(),
+    }
+}
+
+/// Whether a line of line protocol, read up to a newline, ends inside of a string field value,
+/// in which case the newline is part of the value, and the line continues after it
+fn in_string_field_value(line: &str) -> bool {
+    let line = line.trim_start();
+    if line.starts_with('#') {
+        return false;
+    }
+    let mut in_fields = false;
+    let mut in_string = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' if in_fields => in_string = !in_string,
+            ' ' if !in_string => {
+                if in_fields {
+                    // the timestamp follows the field set:
+                    return false;
+                }
+                in_fields = true;
+            }
+            _ => (),
+        }
+    }
+    in_string
+}
+
+/// Normalize the table, tag, and field names of a row according to an [`IdentifierPolicy`]
+fn normalize_row_names(identifier_policy: IdentifierPolicy, row: &mut WriteRow) {
+    if identifier_policy.is_preserve() {
+        return;
+    }
+    let normalize = |name: &mut String| {
+        let normalized = match identifier_policy.normalize(name) {
+            Cow::Owned(normalized) => Some(normalized),
+            Cow::Borrowed(_) => None,
+        };
+        if let Some(normalized) = normalized {
+            *name = normalized;
+        }
+    };
+    normalize(&mut row.table_name);
+    for (name, _) in row.tags.iter_mut() {
+        normalize(name);
+    }
+    for (name, _) in row.fields.iter_mut() {
+        normalize(name);
+    }
 }
 
 /// Type alias for storing new columns added by a write
@@ -238,7 +800,12 @@ type ColumnTracker = Vec<(ColumnId, Arc<str>, InfluxColumnType)>;
 /// and therefore new tag columns will never be added after the first write.
 ///
 /// This errors if the write is being performed against a v1 table, i.e., one that does not have
-/// a series key.
+/// a series key. Lines whose series key differs from that of the table are rejected, unless the
+/// [`SeriesKeyPolicy`] of the database is to fill in the members that they leave out.
+///
+/// The series key of a line identifies its series when checking the cardinality limits of the
+/// table, and its values are interned like the tag values of the v1 data model.
+#[allow(clippy::too_many_arguments)]
 fn validate_and_qualify_v3_line(
     db_schema: &mut Cow<'_, DatabaseSchema>,
     line_number: usize,
@@ -246,9 +813,27 @@ fn validate_and_qualify_v3_line(
     raw_line: &str,
     ingest_time: Time,
     precision: Precision,
+    cardinality: Option<&CardinalityTracker>,
+    new_series: &mut NewSeries,
+    tag_values: Option<&TagValueDictionary>,
+    ids: &mut IdAllocator,
 ) -> Result<(QualifiedLine, Option<CatalogOp>), WriteLineError> {
+    let db_id = db_schema.id;
+    let key_value = |table_id: TableId, value: &SeriesValue<'_>| {
+        FieldData::Key(match tag_values {
+            Some(tag_values) => tag_values.intern(db_id, table_id, series_value_str(value)),
+            None => Arc::from(series_value_str(value)),
+        })
+    };
     let mut catalog_op = None;
-    let table_name = line.series.measurement.as_str();
+    let identifier_policy = db_schema.identifier_policy;
+    let table_name = identifier_policy.normalize(line.series.measurement.as_str());
+    let table_name = table_name.as_ref();
+    let series_key = line.series.series_key.as_ref().map(|sk| {
+        sk.iter()
+            .map(|(key, val)| (identifier_policy.normalize(key.as_str()), val))
+            .collect::<Vec<_>>()
+    });
     let mut fields = Vec::with_capacity(line.column_count());
     let mut index_count = 0;
     let mut field_count = 0;
@@ -258,21 +843,36 @@ fn validate_and_qualify_v3_line(
             return Err(WriteLineError {
                 original_line: raw_line.to_string(),
                 line_number,
+                error_category: WriteLineErrorCategory::SchemaConflict,
                 error_message: "received v3 write protocol for a table that uses the v1 data model"
                     .to_string(),
             });
         }
         // TODO: may be faster to compare using table def/column IDs than comparing with schema:
-        match (
-            table_def.influx_schema().series_key(),
-            &line.series.series_key,
-        ) {
+        match (table_def.influx_schema().series_key(), &series_key) {
+            (Some(s), Some(l)) if db_schema.series_key_policy == SeriesKeyPolicy::Fill => {
+                if let Some((key, _)) = l.iter().find(|(key, _)| !s.contains(&key.as_ref())) {
+                    return Err(WriteLineError {
+                        original_line: raw_line.to_string(),
+                        line_number,
+                        error_category: WriteLineErrorCategory::SchemaConflict,
+                        error_message: format!(
+                            "write to table {table_name} had the tag {key}, which is not a member \
+                            of its series key [{key_members}]",
+                            table_name = table_def.table_name,
+                            key_members = s.join(", "),
+                        ),
+                    });
+                }
+            }
+            (Some(_), None) if db_schema.series_key_policy == SeriesKeyPolicy::Fill => (),
             (Some(s), Some(l)) => {
-                let l = l.iter().map(|sk| sk.0.as_str()).collect::<Vec<&str>>();
+                let l = l.iter().map(|sk| sk.0.as_ref()).collect::<Vec<&str>>();
                 if s != l {
                     return Err(WriteLineError {
                         original_line: raw_line.to_string(),
                         line_number,
+                        error_category: WriteLineErrorCategory::SchemaConflict,
                         error_message: format!(
                             "write to table {table_name} had the incorrect series key, \
                             expected: [{expected}], received: [{received}]",
@@ -288,6 +888,7 @@ fn validate_and_qualify_v3_line(
                     return Err(WriteLineError {
                         original_line: raw_line.to_string(),
                         line_number,
+                        error_category: WriteLineErrorCategory::SchemaConflict,
                         error_message: format!(
                             "write to table {table_name} was missing a series key, the series key \
                             contains [{key_members}]",
@@ -303,27 +904,38 @@ fn validate_and_qualify_v3_line(
         let mut columns = ColumnTracker::with_capacity(line.column_count() + 1);
 
         // qualify the series key members:
-        if let Some(sk) = &line.series.series_key {
+        if let Some(sk) = &series_key {
             for (key, val) in sk.iter() {
                 let col_id =
                     table_def
-                        .column_name_to_id(key.as_str())
+                        .column_name_to_id(key.as_ref())
                         .ok_or_else(|| WriteLineError {
                             original_line: raw_line.to_string(),
                             line_number,
+                            error_category: WriteLineErrorCategory::SchemaConflict,
                             error_message: format!(
                                 "write contained invalid series key column ({key})\
                             that does not exist in the catalog table definition"
                             ),
                         })?;
-                fields.push(Field::new(col_id, val));
+                fields.push(Field::new(col_id, key_value(table_id, *val)));
+                index_count += 1;
+            }
+        }
+        // fill in the series key members that the line left out, which the policy of the
+        // database has allowed if there are any. Series key columns are not nullable, so these
+        // are filled with an empty value:
+        for col_id in table_def.series_key.iter().flatten() {
+            if !fields.iter().any(|field| field.id == *col_id) {
+                fields.push(Field::new(*col_id, FieldData::Key(Arc::from(""))));
                 index_count += 1;
             }
         }
 
         // qualify the fields:
         for (field_name, field_val) in line.field_set.iter() {
-            if let Some((col_id, col_def)) = table_def.column_def_and_id(field_name.as_str()) {
+            let field_name = identifier_policy.normalize(field_name.as_str());
+            if let Some((col_id, col_def)) = table_def.column_def_and_id(field_name.as_ref()) {
                 let field_col_type = influx_column_type_from_field_value(field_val);
                 let existing_col_type = col_def.data_type;
                 if field_col_type != existing_col_type {
@@ -331,6 +943,7 @@ fn validate_and_qualify_v3_line(
                     return Err(WriteLineError {
                         original_line: raw_line.to_string(),
                         line_number: line_number + 1,
+                        error_category: WriteLineErrorCategory::TypeConflict,
                         error_message: format!(
                         "invalid field value in line protocol for field '{field_name}' on line \
                         {line_number}: expected type {expected}, but got {got}",
@@ -341,10 +954,10 @@ fn validate_and_qualify_v3_line(
                 }
                 fields.push(Field::new(col_id, field_val));
             } else {
-                let col_id = ColumnId::new();
+                let col_id = ids.column_id();
                 columns.push((
                     col_id,
-                    Arc::from(field_name.as_str()),
+                    Arc::from(field_name.as_ref()),
                     influx_column_type_from_field_value(field_val),
                 ));
                 fields.push(Field::new(col_id, field_val));
@@ -356,7 +969,7 @@ fn validate_and_qualify_v3_line(
         let time_col_id = table_def
             .column_name_to_id(TIME_COLUMN_NAME)
             .unwrap_or_else(|| {
-                let col_id = ColumnId::new();
+                let col_id = ids.column_id();
                 columns.push((
                     col_id,
                     Arc::from(TIME_COLUMN_NAME),
@@ -364,11 +977,39 @@ fn validate_and_qualify_v3_line(
                 ));
                 col_id
             });
-        let timestamp_ns = line
-            .timestamp
-            .map(|ts| apply_precision_to_timestamp(precision, ts))
-            .unwrap_or(ingest_time.timestamp_nanos());
+        let timestamp_ns = line_timestamp_ns(db_schema, line.timestamp, ingest_time, precision)
+            .map_err(|error_message| WriteLineError {
+                original_line: raw_line.to_string(),
+                line_number: line_number + 1,
+                error_category: WriteLineErrorCategory::TimestampOutOfRange,
+                error_message,
+            })?;
         fields.push(Field::new(time_col_id, FieldData::Timestamp(timestamp_ns)));
+        check_new_columns(
+            db_schema,
+            table_name,
+            table_def.columns.len(),
+            &columns,
+            &raw_line,
+            line_number,
+        )?;
+
+        if let Some(cardinality) = cardinality {
+            let series = series_key.iter().flatten();
+            cardinality
+                .check_series(
+                    db_schema,
+                    table_id,
+                    series.map(|(key, val)| (key.as_ref(), series_value_str(val))),
+                    new_series,
+                )
+                .map_err(|error_message| WriteLineError {
+                    original_line: raw_line.to_string(),
+                    line_number: line_number + 1,
+                    error_category: WriteLineErrorCategory::CardinalityLimit,
+                    error_message,
+                })?;
+        }
 
         // if we have new columns defined, add them to the db_schema table so that subsequent lines
         // won't try to add the same definitions. Collect these additions into a catalog op, which
@@ -402,6 +1043,7 @@ fn validate_and_qualify_v3_line(
                 .map_err(|e| WriteLineError {
                     original_line: raw_line.to_string(),
                     line_number: line_number + 1,
+                    error_category: WriteLineErrorCategory::SchemaLimit,
                     error_message: e.to_string(),
                 })?;
             db_schema.insert_table(table_id, Arc::new(new_table_def));
@@ -416,40 +1058,55 @@ fn validate_and_qualify_v3_line(
             field_count,
         }
     } else {
-        let table_id = TableId::new();
+        if let Some(cardinality) = cardinality {
+            cardinality
+                .check_new_table(db_schema)
+                .map_err(|error_message| WriteLineError {
+                    original_line: raw_line.to_string(),
+                    line_number: line_number + 1,
+                    error_category: WriteLineErrorCategory::CardinalityLimit,
+                    error_message,
+                })?;
+        }
+        let table_id = ids.table_id();
         let mut columns = Vec::new();
         let mut key = Vec::new();
-        if let Some(series_key) = &line.series.series_key {
+        if let Some(series_key) = &series_key {
             for (sk, sv) in series_key.iter() {
-                let col_id = ColumnId::new();
+                let col_id = ids.column_id();
                 key.push(col_id);
-                columns.push((col_id, Arc::from(sk.as_str()), InfluxColumnType::Tag));
-                fields.push(Field::new(col_id, sv));
+                columns.push((col_id, Arc::from(sk.as_ref()), InfluxColumnType::Tag));
+                fields.push(Field::new(col_id, key_value(table_id, *sv)));
                 index_count += 1;
             }
         }
         for (field_name, field_val) in line.field_set.iter() {
-            let col_id = ColumnId::new();
+            let field_name = identifier_policy.normalize(field_name.as_str());
+            let col_id = ids.column_id();
             columns.push((
                 col_id,
-                Arc::from(field_name.as_str()),
+                Arc::from(field_name.as_ref()),
                 influx_column_type_from_field_value(field_val),
             ));
             fields.push(Field::new(col_id, field_val));
             field_count += 1;
         }
         // Always add time last on new table:
-        let time_col_id = ColumnId::new();
+        let time_col_id = ids.column_id();
         columns.push((
             time_col_id,
             Arc::from(TIME_COLUMN_NAME),
             InfluxColumnType::Timestamp,
         ));
-        let timestamp_ns = line
-            .timestamp
-            .map(|ts| apply_precision_to_timestamp(precision, ts))
-            .unwrap_or(ingest_time.timestamp_nanos());
+        let timestamp_ns = line_timestamp_ns(db_schema, line.timestamp, ingest_time, precision)
+            .map_err(|error_message| WriteLineError {
+                original_line: raw_line.to_string(),
+                line_number: line_number + 1,
+                error_category: WriteLineErrorCategory::TimestampOutOfRange,
+                error_message,
+            })?;
         fields.push(Field::new(time_col_id, FieldData::Timestamp(timestamp_ns)));
+        check_new_columns(db_schema, table_name, 0, &columns, &raw_line, line_number)?;
 
         let table_name = table_name.into();
 
@@ -467,8 +1124,25 @@ fn validate_and_qualify_v3_line(
         .map_err(|e| WriteLineError {
             original_line: raw_line.to_string(),
             line_number: line_number + 1,
+            error_category: WriteLineErrorCategory::SchemaLimit,
             error_message: e.to_string(),
         })?;
+        if let Some(cardinality) = cardinality {
+            let series = series_key.iter().flatten();
+            cardinality
+                .check_series(
+                    db_schema,
+                    table_id,
+                    series.map(|(key, val)| (key.as_ref(), series_value_str(val))),
+                    new_series,
+                )
+                .map_err(|error_message| WriteLineError {
+                    original_line: raw_line.to_string(),
+                    line_number: line_number + 1,
+                    error_category: WriteLineErrorCategory::CardinalityLimit,
+                    error_message,
+                })?;
+        }
 
         let table_definition_op = CatalogOp::CreateTable(influxdb3_wal::TableDefinition {
             table_id,
@@ -499,6 +1173,114 @@ fn validate_and_qualify_v3_line(
     Ok((qualified, catalog_op))
 }
 
+/// A line of data for the v1 data model, which is either parsed from line protocol, or was
+/// provided as a structured [`WriteRow`]
+trait V1Line: std::fmt::Display {
+    fn table_name(&self) -> &str;
+
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)>;
+
+    fn fields(&self) -> impl Iterator<Item = (&str, FieldValue<'_>)>;
+
+    fn timestamp(&self) -> Option<i64>;
+
+    fn column_count(&self) -> usize;
+
+    /// Convert the line into an owned [`WriteRow`]
+    fn to_row(&self) -> WriteRow;
+}
+
+impl V1Line for ParsedLine<'_> {
+    fn table_name(&self) -> &str {
+        self.series.measurement.as_str()
+    }
+
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.series
+            .tag_set
+            .iter()
+            .flatten()
+            .map(|(key, val)| (key.as_str(), val.as_str()))
+    }
+
+    fn fields(&self) -> impl Iterator<Item = (&str, FieldValue<'_>)> {
+        self.field_set
+            .iter()
+            .map(|(name, val)| (name.as_str(), val.clone()))
+    }
+
+    fn timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    fn column_count(&self) -> usize {
+        ParsedLine::column_count(self)
+    }
+
+    fn to_row(&self) -> WriteRow {
+        WriteRow {
+            table_name: self.table_name().to_string(),
+            tags: self
+                .tags()
+                .map(|(key, val)| (key.to_string(), val.to_string()))
+                .collect(),
+            fields: self
+                .field_set
+                .iter()
+                .map(|(name, val)| {
+                    let val = match val {
+                        FieldValue::I64(v) => WriteFieldValue::I64(*v),
+                        FieldValue::U64(v) => WriteFieldValue::U64(*v),
+                        FieldValue::F64(v) => WriteFieldValue::F64(*v),
+                        FieldValue::String(v) => WriteFieldValue::String(v.to_string()),
+                        FieldValue::Boolean(v) => WriteFieldValue::Boolean(*v),
+                    };
+                    (name.to_string(), val)
+                })
+                .collect(),
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+impl V1Line for WriteRow {
+    fn table_name(&self) -> &str {
+        self.table_name.as_str()
+    }
+
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags
+            .iter()
+            .map(|(key, val)| (key.as_str(), val.as_str()))
+    }
+
+    fn fields(&self) -> impl Iterator<Item = (&str, FieldValue<'_>)> {
+        self.fields.iter().map(|(name, val)| {
+            let val = match val {
+                WriteFieldValue::I64(v) => FieldValue::I64(*v),
+                WriteFieldValue::U64(v) => FieldValue::U64(*v),
+                WriteFieldValue::F64(v) => FieldValue::F64(*v),
+                WriteFieldValue::String(v) => FieldValue::String(v.as_str().into()),
+                WriteFieldValue::Boolean(v) => FieldValue::Boolean(*v),
+            };
+            (name.as_str(), val)
+        })
+    }
+
+    fn timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    fn column_count(&self) -> usize {
+        // include the time column:
+        self.tags.len() + self.fields.len() + 1
+    }
+
+    fn to_row(&self) -> WriteRow {
+        self.clone()
+    }
+}
+
 /// Validate a line of line protocol against the given schema definition
 ///
 /// This is for scenarios where a write comes in for a table that exists, but may have
@@ -506,16 +1288,26 @@ fn validate_and_qualify_v3_line(
 ///
 /// An error will also be produced if the write, which is for the v1 data model, is targetting
 /// a v3 table.
+#[allow(clippy::too_many_arguments)]
 fn validate_and_qualify_v1_line(
     db_schema: &mut Cow<'_, DatabaseSchema>,
     line_number: usize,
-    line: ParsedLine,
+    line: &impl V1Line,
     _raw_line: &str,
     ingest_time: Time,
     precision: Precision,
+    cardinality: Option<&CardinalityTracker>,
+    new_series: &mut NewSeries,
+    ids: &mut IdAllocator,
+    tag_values: Option<&TagValueDictionary>,
 ) -> Result<(QualifiedLine, Option<CatalogOp>), WriteLineError> {
+    let db_id = db_schema.id;
+    let tag_value = |table_id: TableId, value: &str| match tag_values {
+        Some(tag_values) => tag_values.intern(db_id, table_id, value),
+        None => Arc::from(value),
+    };
     let mut catalog_op = None;
-    let table_name = line.series.measurement.as_str();
+    let table_name = line.table_name();
     let mut fields = Vec::with_capacity(line.column_count());
     let mut index_count = 0;
     let mut field_count = 0;
@@ -524,34 +1316,42 @@ fn validate_and_qualify_v1_line(
             return Err(WriteLineError {
                 original_line: line.to_string(),
                 line_number,
+                error_category: WriteLineErrorCategory::SchemaConflict,
                 error_message: "received v1 write protocol for a table that uses the v3 data model"
                     .to_string(),
             });
         }
         // This table already exists, so update with any new columns if present:
         let mut columns = ColumnTracker::with_capacity(line.column_count() + 1);
-        if let Some(tag_set) = &line.series.tag_set {
-            for (tag_key, tag_val) in tag_set {
-                if let Some(col_id) = table_def.column_name_to_id(tag_key.as_str()) {
-                    fields.push(Field::new(col_id, FieldData::Tag(tag_val.to_string())));
-                } else {
-                    let col_id = ColumnId::new();
-                    columns.push((col_id, Arc::from(tag_key.as_str()), InfluxColumnType::Tag));
-                    fields.push(Field::new(col_id, FieldData::Tag(tag_val.to_string())));
-                }
-                index_count += 1;
+        for (tag_key, tag_val) in line.tags() {
+            let tag_val = FieldData::Tag(tag_value(table_def.table_id, tag_val));
+            if let Some(col_id) = table_def.column_name_to_id(tag_key) {
+                fields.push(Field::new(col_id, tag_val));
+            } else {
+                let col_id = ids.column_id();
+                columns.push((col_id, Arc::from(tag_key), InfluxColumnType::Tag));
+                fields.push(Field::new(col_id, tag_val));
             }
+            index_count += 1;
         }
-        for (field_name, field_val) in line.field_set.iter() {
+        for (field_name, field_val) in line.fields() {
             // This field already exists, so check the incoming type matches existing type:
-            if let Some((col_id, col_def)) = table_def.column_def_and_id(field_name.as_str()) {
-                let field_col_type = influx_column_type_from_field_value(field_val);
+            if let Some((col_id, col_def)) = table_def.column_def_and_id(field_name) {
+                let field_col_type = influx_column_type_from_field_value(&field_val);
                 let existing_col_type = col_def.data_type;
                 if field_col_type != existing_col_type {
+                    if let Some(coerced) =
+                        coerce_field_value(db_schema.field_coercion, &field_val, existing_col_type)
+                    {
+                        fields.push(Field::new(col_id, coerced));
+                        field_count += 1;
+                        continue;
+                    }
                     let field_name = field_name.to_string();
                     return Err(WriteLineError {
                         original_line: line.to_string(),
                         line_number: line_number + 1,
+                        error_category: WriteLineErrorCategory::TypeConflict,
                         error_message: format!(
                             "invalid field value in line protocol for field '{field_name}' on line \
                             {line_number}: expected type {expected}, but got {got}",
@@ -562,11 +1362,11 @@ fn validate_and_qualify_v1_line(
                 }
                 fields.push(Field::new(col_id, field_val));
             } else {
-                let col_id = ColumnId::new();
+                let col_id = ids.column_id();
                 columns.push((
                     col_id,
-                    Arc::from(field_name.as_str()),
-                    influx_column_type_from_field_value(field_val),
+                    Arc::from(field_name),
+                    influx_column_type_from_field_value(&field_val),
                 ));
                 fields.push(Field::new(col_id, field_val));
             }
@@ -576,7 +1376,7 @@ fn validate_and_qualify_v1_line(
         let time_col_id = table_def
             .column_name_to_id(TIME_COLUMN_NAME)
             .unwrap_or_else(|| {
-                let col_id = ColumnId::new();
+                let col_id = ids.column_id();
                 columns.push((
                     col_id,
                     Arc::from(TIME_COLUMN_NAME),
@@ -584,11 +1384,34 @@ fn validate_and_qualify_v1_line(
                 ));
                 col_id
             });
-        let timestamp_ns = line
-            .timestamp
-            .map(|ts| apply_precision_to_timestamp(precision, ts))
-            .unwrap_or(ingest_time.timestamp_nanos());
+        let timestamp = line.timestamp();
+        let timestamp_ns = line_timestamp_ns(db_schema, timestamp, ingest_time, precision)
+            .map_err(|error_message| WriteLineError {
+                original_line: line.to_string(),
+                line_number: line_number + 1,
+                error_category: WriteLineErrorCategory::TimestampOutOfRange,
+                error_message,
+            })?;
         fields.push(Field::new(time_col_id, FieldData::Timestamp(timestamp_ns)));
+        check_new_columns(
+            db_schema,
+            table_name,
+            table_def.columns.len(),
+            &columns,
+            line,
+            line_number,
+        )?;
+
+        if let Some(cardinality) = cardinality {
+            cardinality
+                .check_series(db_schema, table_def.table_id, line.tags(), new_series)
+                .map_err(|error_message| WriteLineError {
+                    original_line: line.to_string(),
+                    line_number: line_number + 1,
+                    error_category: WriteLineErrorCategory::CardinalityLimit,
+                    error_message,
+                })?;
+        }
 
         // if we have new columns defined, add them to the db_schema table so that subsequent lines
         // won't try to add the same definitions. Collect these additions into a catalog op, which
@@ -618,6 +1441,7 @@ fn validate_and_qualify_v1_line(
                 .map_err(|e| WriteLineError {
                     original_line: line.to_string(),
                     line_number: line_number + 1,
+                    error_category: WriteLineErrorCategory::SchemaLimit,
                     error_message: e.to_string(),
                 })?;
             db_schema.insert_table(table_id, Arc::new(new_table_def));
@@ -640,39 +1464,55 @@ fn validate_and_qualify_v1_line(
             field_count,
         }
     } else {
-        let table_id = TableId::new();
+        if let Some(cardinality) = cardinality {
+            cardinality
+                .check_new_table(db_schema)
+                .map_err(|error_message| WriteLineError {
+                    original_line: line.to_string(),
+                    line_number: line_number + 1,
+                    error_category: WriteLineErrorCategory::CardinalityLimit,
+                    error_message,
+                })?;
+        }
+        let table_id = ids.table_id();
         // This is a new table, so build up its columns:
         let mut columns = Vec::new();
-        if let Some(tag_set) = &line.series.tag_set {
-            for (tag_key, tag_val) in tag_set {
-                let col_id = ColumnId::new();
-                fields.push(Field::new(col_id, FieldData::Tag(tag_val.to_string())));
-                columns.push((col_id, Arc::from(tag_key.as_str()), InfluxColumnType::Tag));
-                index_count += 1;
-            }
+        for (tag_key, tag_val) in line.tags() {
+            let col_id = ids.column_id();
+            fields.push(Field::new(
+                col_id,
+                FieldData::Tag(tag_value(table_id, tag_val)),
+            ));
+            columns.push((col_id, Arc::from(tag_key), InfluxColumnType::Tag));
+            index_count += 1;
         }
-        for (field_name, field_val) in &line.field_set {
-            let col_id = ColumnId::new();
+        for (field_name, field_val) in line.fields() {
+            let col_id = ids.column_id();
             columns.push((
                 col_id,
-                Arc::from(field_name.as_str()),
-                influx_column_type_from_field_value(field_val),
+                Arc::from(field_name),
+                influx_column_type_from_field_value(&field_val),
             ));
             fields.push(Field::new(col_id, field_val));
             field_count += 1;
         }
         // Always add time last on new table:
-        let time_col_id = ColumnId::new();
+        let time_col_id = ids.column_id();
         columns.push((
             time_col_id,
             Arc::from(TIME_COLUMN_NAME),
             InfluxColumnType::Timestamp,
         ));
-        let timestamp_ns = line
-            .timestamp
-            .map(|ts| apply_precision_to_timestamp(precision, ts))
-            .unwrap_or(ingest_time.timestamp_nanos());
+        let timestamp = line.timestamp();
+        let timestamp_ns = line_timestamp_ns(db_schema, timestamp, ingest_time, precision)
+            .map_err(|error_message| WriteLineError {
+                original_line: line.to_string(),
+                line_number: line_number + 1,
+                error_category: WriteLineErrorCategory::TimestampOutOfRange,
+                error_message,
+            })?;
         fields.push(Field::new(time_col_id, FieldData::Timestamp(timestamp_ns)));
+        check_new_columns(db_schema, table_name, 0, &columns, line, line_number)?;
 
         let table_name = table_name.into();
         let mut field_definitions = Vec::with_capacity(columns.len());
@@ -689,7 +1529,23 @@ fn validate_and_qualify_v1_line(
             key: None,
         }));
 
-        let table = TableDefinition::new(table_id, Arc::clone(&table_name), columns, None).unwrap();
+        let table = TableDefinition::new(table_id, Arc::clone(&table_name), columns, None)
+            .map_err(|e| WriteLineError {
+                original_line: line.to_string(),
+                line_number: line_number + 1,
+                error_category: WriteLineErrorCategory::SchemaLimit,
+                error_message: e.to_string(),
+            })?;
+        if let Some(cardinality) = cardinality {
+            cardinality
+                .check_series(db_schema, table_id, line.tags(), new_series)
+                .map_err(|error_message| WriteLineError {
+                    original_line: line.to_string(),
+                    line_number: line_number + 1,
+                    error_category: WriteLineErrorCategory::CardinalityLimit,
+                    error_message,
+                })?;
+        }
 
         let db_schema = db_schema.to_mut();
         assert!(
@@ -726,6 +1582,8 @@ pub(crate) struct ValidatedLines {
     pub(crate) valid_data: WriteBatch,
     /// If any catalog updates were made, they will be included here
     pub(crate) catalog_updates: Option<CatalogBatch>,
+    /// The series that the lines add to their tables, to be recorded once they are buffered
+    pub(crate) new_series: NewSeries,
 }
 
 impl WriteValidator<LinesParsed> {
@@ -733,9 +1591,19 @@ impl WriteValidator<LinesParsed> {
     /// be buffered and written to the WAL, if configured.
     ///
     /// This involves splitting out the writes into different batches for each chunk, which will
-    /// map to the `Gen1Duration`. This function should be infallible, because
-    /// the schema for incoming writes has been fully validated.
-    pub(crate) fn convert_lines_to_buffer(self, gen1_duration: Gen1Duration) -> ValidatedLines {
+    /// map to the `Gen1Duration` of the database, or `default_gen1_duration` if it does not have
+    /// one. This function should be infallible, because the schema for incoming writes has been
+    /// fully validated.
+    pub(crate) fn convert_lines_to_buffer(
+        self,
+        default_gen1_duration: Gen1Duration,
+    ) -> ValidatedLines {
+        let gen1_duration = self
+            .state
+            .catalog
+            .db_schema
+            .gen1_duration
+            .unwrap_or(default_gen1_duration);
         let mut table_chunks = IndexMap::new();
         let line_count = self.state.lines.len();
         let mut field_count = 0;
@@ -761,6 +1629,7 @@ impl WriteValidator<LinesParsed> {
             errors: self.state.errors,
             valid_data: write_batch,
             catalog_updates: self.state.catalog_batch,
+            new_series: self.state.new_series,
         }
     }
 }
@@ -783,7 +1652,192 @@ struct QualifiedLine {
     field_count: usize,
 }
 
-fn apply_precision_to_timestamp(precision: Precision, ts: i64) -> i64 {
+/// The largest integer magnitude up to which every integer can be represented exactly as a float
+const MAX_EXACT_FLOAT_INTEGER: i64 = 1 << f64::MANTISSA_DIGITS;
+
+/// Convert a field value to the type of its existing column, if allowed by the `field_coercion`
+/// policy, returning `None` if it cannot be converted without changing its value
+fn coerce_field_value(
+    field_coercion: FieldCoercion,
+    value: &FieldValue<'_>,
+    column_type: InfluxColumnType,
+) -> Option<FieldData> {
+    let InfluxColumnType::Field(field_type) = column_type else {
+        return None;
+    };
+    match (field_coercion, value, field_type) {
+        (FieldCoercion::Off, _, _) => None,
+        // only integers that a float can represent exactly are converted:
+        (_, FieldValue::I64(v), InfluxFieldType::Float)
+            if (-MAX_EXACT_FLOAT_INTEGER..=MAX_EXACT_FLOAT_INTEGER).contains(v) =>
+        {
+            Some(FieldData::Float(*v as f64))
+        }
+        (_, FieldValue::U64(v), InfluxFieldType::Integer) => {
+            i64::try_from(*v).ok().map(FieldData::Integer)
+        }
+        (FieldCoercion::Stringify, value, InfluxFieldType::String) => {
+            Some(FieldData::String(match value {
+                FieldValue::I64(v) => v.to_string(),
+                FieldValue::U64(v) => v.to_string(),
+                FieldValue::F64(v) => v.to_string(),
+                FieldValue::String(v) => v.to_string(),
+                FieldValue::Boolean(v) => v.to_string(),
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// Check that adding the `new_columns` to a table that has `existing_column_count` columns is
+/// allowed by the schema mode of the database, and is within its schema limits. A table with no
+/// existing columns is one that the line would create, since every table has a time column.
+fn check_new_columns(
+    db_schema: &DatabaseSchema,
+    table_name: &str,
+    existing_column_count: usize,
+    new_columns: &[(ColumnId, Arc<str>, InfluxColumnType)],
+    line: &dyn std::fmt::Display,
+    line_number: usize,
+) -> Result<(), WriteLineError> {
+    let limits = &db_schema.schema_limits;
+    let error = |error_category, error_message| WriteLineError {
+        original_line: line.to_string(),
+        line_number: line_number + 1,
+        error_category,
+        error_message,
+    };
+    match db_schema.schema_mode {
+        SchemaMode::Auto => (),
+        SchemaMode::Strict if existing_column_count == 0 => {
+            return Err(error(
+                WriteLineErrorCategory::SchemaLocked,
+                format!(
+                    "table {table_name} does not exist, and new tables cannot be created in \
+                    database {db_name}, which has the strict schema mode",
+                    db_name = db_schema.name,
+                ),
+            ));
+        }
+        SchemaMode::Strict | SchemaMode::TagLocked => {
+            let locked = new_columns.iter().find(|(_, _, column_type)| {
+                db_schema.schema_mode == SchemaMode::Strict || *column_type == InfluxColumnType::Tag
+            });
+            if let Some((_, name, _)) = locked {
+                return Err(error(
+                    WriteLineErrorCategory::SchemaLocked,
+                    format!(
+                        "column '{name}' does not exist in table {table_name}, and cannot be \
+                        added in database {db_name}, which has the {mode} schema mode",
+                        db_name = db_schema.name,
+                        mode = db_schema.schema_mode,
+                    ),
+                ));
+            }
+        }
+    }
+    let table_def = db_schema.table_definition(table_name);
+    for (_, name, _) in new_columns {
+        // persisted files may still have a column by the name, which would be read as part of
+        // the new column:
+        if table_def
+            .as_ref()
+            .is_some_and(|table_def| table_def.is_retired_column_name(name))
+        {
+            return Err(error(
+                WriteLineErrorCategory::SchemaConflict,
+                format!(
+                    "column '{name}' was dropped or renamed in table {table_name}, so its name \
+                    cannot be used for a new column"
+                ),
+            ));
+        }
+        if let Some(max_length) = limits.max_column_name_length {
+            if name.len() > max_length {
+                return Err(error(
+                    WriteLineErrorCategory::InvalidColumnName,
+                    format!("column name '{name}' is longer than the limit of {max_length} bytes"),
+                ));
+            }
+        }
+        if !limits.column_name_policy.allows(name) {
+            return Err(error(
+                WriteLineErrorCategory::InvalidColumnName,
+                format!(
+                    "column name '{name}' is not allowed by the {policy} column name policy of \
+                    database {db_name}",
+                    policy = limits.column_name_policy,
+                    db_name = db_schema.name,
+                ),
+            ));
+        }
+    }
+    if let Some(max_columns) = limits.max_columns_per_table {
+        let column_count = existing_column_count + new_columns.len();
+        if column_count > max_columns {
+            return Err(error(
+                WriteLineErrorCategory::SchemaLimit,
+                format!(
+                    "table {table_name} would have {column_count} columns, which exceeds the \
+                    limit of {max_columns} columns per table"
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The error returned for a rejected line when partial writes are not accepted
+fn rejected_line_error(e: WriteLineError) -> Error {
+    match e.error_category {
+        WriteLineErrorCategory::CardinalityLimit => Error::CardinalityLimitExceeded(e),
+        _ => Error::ParseError(e),
+    }
+}
+
+/// Get the timestamp of a line in nanoseconds, using the ingest time if the line does not have one,
+/// and check that it falls within the write limits of the database. An error message is returned
+/// if the timestamp cannot be accepted.
+fn line_timestamp_ns(
+    db_schema: &DatabaseSchema,
+    timestamp: Option<i64>,
+    ingest_time: Time,
+    precision: Precision,
+) -> Result<i64, String> {
+    let now_ns = ingest_time.timestamp_nanos();
+    let Some(ts) = timestamp else {
+        return Ok(now_ns);
+    };
+    let ts_ns = apply_precision_to_timestamp(precision, ts).ok_or_else(|| {
+        format!(
+            "timestamp {ts} is out of range when converted from {precision:?} precision to \
+            nanoseconds"
+        )
+    })?;
+    if let Some(earliest) = db_schema.earliest_write_time_ns(now_ns) {
+        if ts_ns < earliest {
+            return Err(format!(
+                "timestamp {ts_ns} is before the earliest time of {earliest} accepted by the \
+                past write limit of database {db_name}",
+                db_name = db_schema.name
+            ));
+        }
+    }
+    if let Some(latest) = db_schema.latest_write_time_ns(now_ns) {
+        if ts_ns > latest {
+            return Err(format!(
+                "timestamp {ts_ns} is after the latest time of {latest} accepted by the future \
+                write limit of database {db_name}",
+                db_name = db_schema.name
+            ));
+        }
+    }
+    Ok(ts_ns)
+}
+
+/// Convert the timestamp `ts`, given in `precision`, to nanoseconds, or return `None` if the
+/// result is outside the range of timestamps that can be represented.
+fn apply_precision_to_timestamp(precision: Precision, ts: i64) -> Option<i64> {
     let multiplier = match precision {
         Precision::Auto => match crate::guess_precision(ts) {
             Precision::Second => 1_000_000_000,
@@ -799,19 +1853,22 @@ fn apply_precision_to_timestamp(precision: Precision, ts: i64) -> i64 {
         Precision::Nanosecond => 1,
     };
 
-    ts * multiplier
+    ts.checked_mul(multiplier)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{collections::HashSet, sync::Arc};
 
-    use super::WriteValidator;
-    use crate::{write_buffer::Error, Precision};
+    use super::{IdAllocator, WriteValidator};
+    use crate::{
+        write_buffer::{tag_values::TagValueDictionary, Error},
+        ColumnMapping, Precision, WriteFieldValue, WriteLineErrorCategory, WriteRow,
+    };
     use data_types::NamespaceName;
-    use influxdb3_catalog::catalog::Catalog;
-    use influxdb3_id::TableId;
-    use influxdb3_wal::Gen1Duration;
+    use influxdb3_catalog::catalog::{Catalog, CatalogLimits, Error as CatalogError};
+    use influxdb3_id::{ColumnId, TableId};
+    use influxdb3_wal::{create, CatalogOp, FieldCoercion, Gen1Duration};
     use iox_time::Time;
 
     #[test]
@@ -878,4 +1935,410 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn write_validator_v1_error_categories() -> Result<(), Error> {
+        let host_id = Arc::from("sample-host-id");
+        let instance_id = Arc::from("sample-instance-id");
+        let namespace = NamespaceName::new("test").unwrap();
+        let catalog = Arc::new(Catalog::new(host_id, instance_id));
+        let result = WriteValidator::initialize(namespace, Arc::clone(&catalog), 0)?
+            .v1_parse_lines_and_update_schema(
+                "cpu,tag1=foo val1=1 1\n\
+                cpu,tag1=foo val1= 2\n\
+                cpu,tag1=foo val1=\"bar\" 3\n\
+                cpu,tag1=foo val1=4 9223372036854775807",
+                true,
+                Time::from_timestamp_nanos(0),
+                Precision::Second,
+            )?
+            .convert_lines_to_buffer(Gen1Duration::new_5m());
+
+        assert_eq!(result.line_count, 1);
+        let categories = result
+            .errors
+            .iter()
+            .map(|e| (e.line_number, e.error_category))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            categories,
+            vec![
+                (2, WriteLineErrorCategory::Parse),
+                (3, WriteLineErrorCategory::TypeConflict),
+                (4, WriteLineErrorCategory::TimestampOutOfRange),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_validator_catalog_limits() -> Result<(), Error> {
+        let catalog = Arc::new(Catalog::new(
+            Arc::from("sample-host-id"),
+            Arc::from("sample-instance-id"),
+        ));
+        catalog.set_limits(CatalogLimits {
+            max_columns: 3,
+            ..Default::default()
+        });
+        let write = |lp: &'static str| {
+            WriteValidator::initialize(
+                NamespaceName::new("test").unwrap(),
+                Arc::clone(&catalog),
+                0,
+            )?
+            .v1_parse_lines_and_update_schema(
+                lp,
+                true,
+                Time::from_timestamp_nanos(0),
+                Precision::Auto,
+            )
+            .map(|_| ())
+        };
+
+        write("cpu,tag1=foo val1=1 1")?;
+        assert!(matches!(
+            write("cpu,tag1=foo val1=1,val2=2 2"),
+            Err(Error::CatalogUpdateError(
+                CatalogError::TooManyColumnsInCatalog(3)
+            ))
+        ));
+        assert_eq!(3, catalog.usage().columns);
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_validator_v1_field_coercion() -> Result<(), Error> {
+        let lp = "m f=1.5,s=\"a\",u=1u,i=1i 1\n\
+            m f=2i 2\n\
+            m i=3u 3\n\
+            m i=18446744073709551615u 4\n\
+            m u=5i 5\n\
+            m f=9007199254740993i 6\n\
+            m s=7i 7\n\
+            m f=true 8";
+        for (field_coercion, expected_rejected) in [
+            (FieldCoercion::Off, vec![2, 3, 4, 5, 6, 7, 8]),
+            (FieldCoercion::WidenNumeric, vec![4, 5, 6, 7, 8]),
+            (FieldCoercion::Stringify, vec![4, 5, 6, 8]),
+        ] {
+            let catalog = Arc::new(Catalog::new(
+                Arc::from("sample-host-id"),
+                Arc::from("sample-instance-id"),
+            ));
+            let db_id = catalog.db_or_create("test")?.id;
+            catalog.apply_catalog_batch(
+                create::catalog_batch_op(
+                    db_id,
+                    "test",
+                    0,
+                    [create::set_field_coercion_op(db_id, "test", field_coercion)],
+                )
+                .as_catalog()
+                .unwrap(),
+            )?;
+            let result =
+                WriteValidator::initialize(NamespaceName::new("test").unwrap(), catalog, 0)?
+                    .v1_parse_lines_and_update_schema(
+                        lp,
+                        true,
+                        Time::from_timestamp_nanos(0),
+                        Precision::Nanosecond,
+                    )?
+                    .convert_lines_to_buffer(Gen1Duration::new_5m());
+
+            let rejected = result
+                .errors
+                .iter()
+                .map(|e| {
+                    assert_eq!(WriteLineErrorCategory::TypeConflict, e.error_category);
+                    e.line_number
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                expected_rejected, rejected,
+                "field coercion: {field_coercion}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_validator_v1_stream() -> Result<(), Error> {
+        let host_id = Arc::from("sample-host-id");
+        let instance_id = Arc::from("sample-instance-id");
+        let namespace = NamespaceName::new("test").unwrap();
+        let catalog = Arc::new(Catalog::new(host_id, instance_id));
+        let lp = "\
+            cpu,tag1=foo val1=\"bar\" 1234\n\
+            \n\
+            # a comment\n\
+            cpu,tag1=foo val1=1 1235\n\
+            mem,tag1=foo val2=false 1236";
+        let mut line_idx = 0;
+        let (validator, more) =
+            WriteValidator::initialize(namespace.clone(), Arc::clone(&catalog), 0)?
+                .v1_parse_lines_batch_and_update_schema(
+                    &mut lp.as_bytes(),
+                    &mut line_idx,
+                    usize::MAX,
+                    true,
+                    Time::from_timestamp_nanos(0),
+                    Precision::Auto,
+                )
+                .await?;
+        let result = validator.convert_lines_to_buffer(Gen1Duration::new_5m());
+
+        // blank lines and comments are skipped, and the line with a field type conflict is
+        // reported as an error:
+        assert!(!more);
+        assert_eq!(line_idx, 3);
+        assert_eq!(result.line_count, 2);
+        assert_eq!(result.field_count, 2);
+        assert_eq!(result.index_count, 2);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].line_number, 2);
+        assert!(result.errors[0].original_line.contains("1235"));
+        assert_eq!(result.valid_data.table_chunks.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_validator_v1_stream_batches_end_at_lines() -> Result<(), Error> {
+        let host_id = Arc::from("sample-host-id");
+        let instance_id = Arc::from("sample-instance-id");
+        let namespace = NamespaceName::new("test").unwrap();
+        let catalog = Arc::new(Catalog::new(host_id, instance_id));
+
+        // validate a batch of at least one byte, returning the number of lines in it:
+        async fn batch(
+            namespace: &NamespaceName<'static>,
+            catalog: &Arc<Catalog>,
+            reader: &mut &[u8],
+            line_idx: &mut usize,
+        ) -> Result<(usize, bool), Error> {
+            let (validator, more) =
+                WriteValidator::initialize(namespace.clone(), Arc::clone(catalog), 0)?
+                    .v1_parse_lines_batch_and_update_schema(
+                        reader,
+                        line_idx,
+                        1,
+                        false,
+                        Time::from_timestamp_nanos(0),
+                        Precision::Auto,
+                    )
+                    .await?;
+            let line_count = validator
+                .convert_lines_to_buffer(Gen1Duration::new_5m())
+                .line_count;
+            Ok((line_count, more))
+        }
+
+        // the newline in the string field value does not end the first batch:
+        let lp = "\
+            cpu,tag1=foo val1=\"first\nline\" 1234\n\
+            cpu,tag1=foo val1=\"second\" 1235\n";
+        let mut reader = lp.as_bytes();
+        let mut line_idx = 0;
+        assert_eq!(
+            (1, true),
+            batch(&namespace, &catalog, &mut reader, &mut line_idx).await?
+        );
+        assert_eq!(
+            (1, true),
+            batch(&namespace, &catalog, &mut reader, &mut line_idx).await?
+        );
+        assert_eq!(
+            (0, false),
+            batch(&namespace, &catalog, &mut reader, &mut line_idx).await?
+        );
+        assert_eq!(line_idx, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn string_field_values_continue_lines() {
+        for (line, expected) in [
+            ("cpu val=1 1\n", false),
+            ("cpu val=\"a\n", true),
+            ("cpu val=\"a\\\"\n", true),
+            ("cpu val=\"a\\\\\"\n", false),
+            ("cpu,tag=\"a val=1\n", false),
+            ("cpu\\ \"a val=\"b\" 1\n", false),
+            ("# cpu val=\"a\n", false),
+        ] {
+            assert_eq!(
+                super::in_string_field_value(line),
+                expected,
+                "line: {line:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn write_validator_v1_rows() -> Result<(), Error> {
+        let host_id = Arc::from("sample-host-id");
+        let instance_id = Arc::from("sample-instance-id");
+        let namespace = NamespaceName::new("test").unwrap();
+        let catalog = Arc::new(Catalog::new(host_id, instance_id));
+        let row = |val: WriteFieldValue| WriteRow {
+            table_name: "cpu".to_string(),
+            tags: vec![("tag1".to_string(), "foo".to_string())],
+            fields: vec![("val1".to_string(), val)],
+            timestamp: Some(1234),
+        };
+        let result = WriteValidator::initialize(namespace.clone(), Arc::clone(&catalog), 0)?
+            .v1_validate_rows_and_update_schema(
+                &[
+                    row(WriteFieldValue::String("bar".to_string())),
+                    row(WriteFieldValue::I64(1)),
+                ],
+                true,
+                Time::from_timestamp_nanos(0),
+                Precision::Nanosecond,
+            )?
+            .convert_lines_to_buffer(Gen1Duration::new_5m());
+
+        assert_eq!(result.line_count, 1);
+        assert_eq!(result.field_count, 1);
+        assert_eq!(result.index_count, 1);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].original_line, "cpu,tag1=foo val1=1i 1234");
+        assert!(result.catalog_updates.is_some());
+
+        // the table created from rows can be written to with line protocol:
+        let result = WriteValidator::initialize(namespace, catalog, 0)?
+            .v1_parse_lines_and_update_schema(
+                "cpu,tag1=foo val1=\"baz\" 1235",
+                false,
+                Time::from_timestamp_nanos(0),
+                Precision::Auto,
+            )?
+            .convert_lines_to_buffer(Gen1Duration::new_5m());
+        assert_eq!(result.line_count, 1);
+        assert!(result.errors.is_empty());
+        assert!(result.catalog_updates.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_validator_dry_run_ids() -> Result<(), Error> {
+        // a dry run hands out placeholder ids that follow on from the ones it starts with:
+        let mut ids = IdAllocator::DryRun {
+            next_table: 5,
+            next_column: 7,
+        };
+        assert_eq!(TableId::from(5), ids.table_id());
+        assert_eq!(TableId::from(6), ids.table_id());
+        assert_eq!(ColumnId::from(7), ids.column_id());
+
+        let host_id = Arc::from("sample-host-id");
+        let instance_id = Arc::from("sample-instance-id");
+        let namespace = NamespaceName::new("test").unwrap();
+        let catalog = Arc::new(Catalog::new(host_id, instance_id));
+        let next_table_id = TableId::next_id();
+        let next_column_id = ColumnId::next_id();
+        let result = WriteValidator::initialize_dry_run(namespace, Arc::clone(&catalog), 0)?
+            .v1_parse_lines_and_update_schema(
+                "cpu,host=a usage=1 1\nmem,host=a free=1 1",
+                false,
+                Time::from_timestamp_nanos(0),
+                Precision::Nanosecond,
+            )?
+            .convert_lines_to_buffer(Gen1Duration::new_5m());
+
+        let ops = result.catalog_updates.unwrap().ops;
+        let tables = ops
+            .iter()
+            .map(|op| match op {
+                CatalogOp::CreateTable(table) => table,
+                op => panic!("unexpected catalog op: {op:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(2, tables.len());
+        assert_ne!(tables[0].table_id, tables[1].table_id);
+        let column_ids = tables
+            .iter()
+            .flat_map(|table| table.field_definitions.iter().map(|field| field.id))
+            .collect::<HashSet<_>>();
+        assert_eq!(6, column_ids.len());
+        // the ids are placeholders that are not taken from the counters, which other tests may
+        // advance, so they are only known not to precede them:
+        assert!(tables.iter().all(|table| table.table_id >= next_table_id));
+        assert!(column_ids.iter().all(|id| *id >= next_column_id));
+        assert!(catalog.db_schema("test").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_validator_v3_interns_series_key_values() -> Result<(), Error> {
+        let host_id = Arc::from("sample-host-id");
+        let instance_id = Arc::from("sample-instance-id");
+        let namespace = NamespaceName::new("test").unwrap();
+        let catalog = Arc::new(Catalog::new(host_id, instance_id));
+        let tag_values = Arc::new(TagValueDictionary::default());
+        let result = WriteValidator::initialize(namespace, Arc::clone(&catalog), 0)?
+            .with_tag_values(Arc::clone(&tag_values))
+            .v3_parse_lines_and_update_schema(
+                "cpu,host/a usage=1i 1\ncpu,host/a usage=2i 2\ncpu,host/b usage=3i 3",
+                false,
+                Time::from_timestamp_nanos(0),
+                Precision::Nanosecond,
+            )?
+            .convert_lines_to_buffer(Gen1Duration::new_5m());
+
+        assert_eq!(result.line_count, 3);
+        assert_eq!(2, tag_values.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_validator_csv() -> Result<(), Error> {
+        let host_id = Arc::from("sample-host-id");
+        let instance_id = Arc::from("sample-instance-id");
+        let namespace = NamespaceName::new("test").unwrap();
+        let catalog = Arc::new(Catalog::new(host_id, instance_id));
+        let mapping = ColumnMapping {
+            table_name: "sensors".to_string(),
+            tags: vec!["site".to_string()],
+            time_column: Some("ts".to_string()),
+            ..Default::default()
+        };
+        let result = WriteValidator::initialize(namespace, Arc::clone(&catalog), 0)?
+            .parse_csv_and_update_schema(
+                "site,ts,temp\nnorth,1,21.5\nsouth,2,warm\n",
+                &mapping,
+                true,
+                Time::from_timestamp_nanos(0),
+                Precision::Nanosecond,
+            )?
+            .convert_lines_to_buffer(Gen1Duration::new_5m());
+
+        assert_eq!(result.line_count, 1);
+        assert!(result.catalog_updates.is_some());
+        // the second record infers a string, which conflicts with the float of the first:
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].line_number, 3);
+        assert_eq!(result.errors[0].original_line, "south,2,warm");
+        assert_eq!(
+            result.errors[0].error_category,
+            WriteLineErrorCategory::TypeConflict
+        );
+        let table_def = catalog
+            .db_schema("test")
+            .unwrap()
+            .table_definition("sensors")
+            .unwrap();
+        assert_eq!(3, table_def.num_columns());
+
+        Ok(())
+    }
 }
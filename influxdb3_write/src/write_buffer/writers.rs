@@ -0,0 +1,177 @@
+//! The parquet files persisted by other writers, i.e., other hosts that write to the same object
+//! store under their own host identifier prefix, which are queried along with the files of this
+//! host, so that a query node can serve a unified view of the data ingested by several writers.
+//!
+//! Each writer's catalog, snapshots, and compaction generations are loaded on startup, and then
+//! refreshed periodically, so the data of a writer is queryable once it has been persisted, and
+//! the writer's catalog persisted since. The tables of the writers are matched to those of this
+//! host by their database and table names, as their ids differ from host to host, so a table
+//! must exist in the catalog of this host to be queried. The writes that a writer has not
+//! persisted yet are not queried.
+
+use crate::persister::{self, Persister};
+use crate::write_buffer::persisted_files::{PersistedFiles, TimeRange};
+use crate::ParquetFile;
+use influxdb3_catalog::catalog::Catalog;
+use observability_deps::tracing::info;
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+/// The files persisted by the other writers that this host queries along with its own
+#[derive(Debug, Default)]
+pub struct WriterFiles {
+    writers: Vec<Writer>,
+}
+
+#[derive(Debug)]
+struct Writer {
+    persister: Persister,
+    /// The catalog of the writer as of the last refresh, which its database and table names are
+    /// resolved to ids with, or `None` if it has not persisted one yet
+    catalog: RwLock<Option<Arc<Catalog>>>,
+    persisted_files: PersistedFiles,
+    /// The generation after the last compaction generation that has been applied, which is held
+    /// while the writer is refreshed, so that it is only refreshed once at a time
+    next_generation: tokio::sync::Mutex<u64>,
+}
+
+impl WriterFiles {
+    /// Load the catalog, and the most recent `n_snapshots` snapshots, of each of the writers with
+    /// the given host identifier prefixes, using the object store and encryption keys of
+    /// `persister`
+    pub(crate) async fn load(
+        persister: &Persister,
+        host_identifier_prefixes: &[String],
+        n_snapshots: usize,
+    ) -> persister::Result<Self> {
+        let mut writers = Vec::with_capacity(host_identifier_prefixes.len());
+        for host_identifier_prefix in host_identifier_prefixes {
+            let mut writer_persister =
+                Persister::new(persister.object_store(), host_identifier_prefix.as_str());
+            if let Some(key_provider) = persister.key_provider() {
+                writer_persister = writer_persister.with_key_provider(key_provider);
+            }
+            let catalog = writer_persister.load_catalog().await?;
+            let page = writer_persister
+                .load_snapshots_page(None, n_snapshots)
+                .await?;
+            info!(
+                %host_identifier_prefix,
+                n_snapshots = page.snapshots.len(),
+                "loaded the persisted files of writer"
+            );
+            let writer = Writer {
+                catalog: RwLock::new(catalog.map(|catalog| Arc::new(Catalog::from_inner(catalog)))),
+                persisted_files: PersistedFiles::new_from_snapshot_page(page),
+                persister: writer_persister,
+                next_generation: Default::default(),
+            };
+            writer
+                .apply_compaction_generations(&mut *writer.next_generation.lock().await)
+                .await?;
+            writers.push(writer);
+        }
+        Ok(Self { writers })
+    }
+
+    /// Reload the catalog of each writer, and load the snapshots and compaction generations that
+    /// it has persisted since they were last loaded. Returns the number of snapshots that were
+    /// loaded.
+    pub(crate) async fn refresh(&self) -> persister::Result<usize> {
+        let mut loaded = 0;
+        for writer in &self.writers {
+            loaded += writer.refresh().await?;
+        }
+        Ok(loaded)
+    }
+
+    /// Load the older snapshots of each writer that have data from as far back as `min_time`,
+    /// if they have not been loaded yet, `page_size` at a time
+    pub(crate) async fn load_older_snapshots(
+        &self,
+        min_time: i64,
+        page_size: usize,
+    ) -> persister::Result<usize> {
+        let mut loaded = 0;
+        for writer in &self.writers {
+            if writer.persisted_files.needs_older_snapshots(min_time) {
+                loaded += writer
+                    .persisted_files
+                    .load_older_snapshots(&writer.persister, min_time, page_size)
+                    .await?;
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// The files of the table with the given names that have data in `time_range`, from all of
+    /// the writers that have it, ordered by writer, and then by when they were persisted
+    pub fn get_files_in_time_range(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        time_range: TimeRange,
+    ) -> Vec<ParquetFile> {
+        let mut files = vec![];
+        for writer in &self.writers {
+            let Some(catalog) = writer.catalog.read().clone() else {
+                continue;
+            };
+            let Some(db_schema) = catalog.db_schema(db_name) else {
+                continue;
+            };
+            let Some(table_id) = db_schema.table_name_to_id(table_name) else {
+                continue;
+            };
+            let mut writer_files =
+                writer
+                    .persisted_files
+                    .get_files_in_time_range(db_schema.id, table_id, time_range);
+            writer_files.sort_by_key(|file| file.id);
+            files.append(&mut writer_files);
+        }
+        files
+    }
+}
+
+impl Writer {
+    async fn refresh(&self) -> persister::Result<usize> {
+        let mut next_generation = self.next_generation.lock().await;
+        if let Some(catalog) = self.persister.load_catalog().await? {
+            *self.catalog.write() = Some(Arc::new(Catalog::from_inner(catalog)));
+        }
+
+        let mut snapshots = self
+            .persister
+            .load_snapshots_after(
+                self.persisted_files
+                    .latest_snapshot_sequence_number()
+                    .unwrap_or_default(),
+            )
+            .await?;
+        let loaded = snapshots.len();
+        // the snapshots are loaded most recent first:
+        snapshots.reverse();
+        for snapshot in snapshots {
+            self.persisted_files.add_persisted_snapshot_files(snapshot);
+        }
+        self.apply_compaction_generations(&mut next_generation)
+            .await?;
+        Ok(loaded)
+    }
+
+    /// Apply the compaction generations that the writer has persisted since those applied before
+    async fn apply_compaction_generations(
+        &self,
+        next_generation: &mut u64,
+    ) -> persister::Result<()> {
+        for generation in self.persister.load_compaction_generations().await? {
+            if generation.generation >= *next_generation {
+                self.persisted_files
+                    .apply_compaction_generation(&generation);
+                *next_generation = generation.generation + 1;
+            }
+        }
+        Ok(())
+    }
+}